@@ -0,0 +1,1338 @@
+//! Software compositing for the RENDER extension
+//!
+//! A small pixman-like core shared by every backend. The pictures taking
+//! part in a Composite are read from their drawables with get_image,
+//! combined pixel by pixel with a Porter-Duff or PDF blend operator, and the
+//! destination is written back with put_image. Plain copies and solid fills
+//! go to the backend's own copy_area and fill_rectangle instead.
+//!
+//! Pixels are premultiplied ARGB (0xAARRGGBB).
+
+use super::raster::clamp;
+use super::{Backend, BackendDrawable, BackendGC, BackendResult};
+use crate::protocol::Rectangle;
+use std::sync::Arc;
+
+/// Storage format of a picture
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PictFormat {
+    /// 32-bit ARGB
+    Argb32,
+    /// 24-bit RGB, always opaque
+    Rgb24,
+    /// 8-bit alpha
+    A8,
+    /// 1-bit alpha
+    A1,
+}
+
+impl PictFormat {
+    /// The format of a drawable of `depth`
+    pub fn for_depth(depth: u8) -> Self {
+        match depth {
+            32 => PictFormat::Argb32,
+            8 => PictFormat::A8,
+            1 => PictFormat::A1,
+            _ => PictFormat::Rgb24,
+        }
+    }
+
+    pub fn depth(self) -> u8 {
+        match self {
+            PictFormat::Argb32 => 32,
+            PictFormat::Rgb24 => 24,
+            PictFormat::A8 => 8,
+            PictFormat::A1 => 1,
+        }
+    }
+}
+
+/// What a picture shows outside its drawable
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Repeat {
+    /// Transparent
+    #[default]
+    None,
+    /// The drawable tiled
+    Normal,
+    /// The nearest edge pixel
+    Pad,
+    /// The drawable tiled, mirrored every other time
+    Reflect,
+}
+
+impl Repeat {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(Repeat::None),
+            1 => Some(Repeat::Normal),
+            2 => Some(Repeat::Pad),
+            3 => Some(Repeat::Reflect),
+            _ => None,
+        }
+    }
+
+    /// The pixel index that `v` reads in a dimension of `size`, or None
+    /// where the picture is transparent
+    fn wrap(self, v: i32, size: i32) -> Option<i32> {
+        if size <= 0 {
+            return None;
+        }
+        match self {
+            Repeat::None => (0..size).contains(&v).then_some(v),
+            Repeat::Normal => Some(v.rem_euclid(size)),
+            Repeat::Pad => Some(v.clamp(0, size - 1)),
+            Repeat::Reflect => {
+                let v = v.rem_euclid(2 * size);
+                Some(if v >= size { 2 * size - 1 - v } else { v })
+            }
+        }
+    }
+}
+
+/// Where a picture's pixels come from
+#[derive(Debug, Clone)]
+pub enum PictureSource {
+    /// A premultiplied color covering the whole plane
+    Solid(u32),
+    /// A linear, radial or conical gradient
+    Gradient(Arc<Gradient>),
+    /// Premultiplied pixels held by the server, such as a glyph, row by row
+    Pixels {
+        width: u16,
+        height: u16,
+        pixels: Arc<Vec<u32>>,
+    },
+    /// A window or pixmap
+    Drawable {
+        drawable: BackendDrawable,
+        format: PictFormat,
+        width: u16,
+        height: u16,
+    },
+}
+
+/// Geometry of a gradient, in picture coordinates
+#[derive(Debug, Clone, PartialEq)]
+pub enum GradientShape {
+    /// Colors run from `p1` (0) to `p2` (1), constant across the line
+    Linear { p1: (f64, f64), p2: (f64, f64) },
+    /// Colors run from the inner circle (0) to the outer one (1); circles
+    /// are (center x, center y, radius)
+    Radial {
+        inner: (f64, f64, f64),
+        outer: (f64, f64, f64),
+    },
+    /// Colors run once around `center`, starting at `angle` degrees
+    Conical { center: (f64, f64), angle: f64 },
+}
+
+/// A gradient picture: its geometry and color stops
+#[derive(Debug, Clone, PartialEq)]
+pub struct Gradient {
+    pub shape: GradientShape,
+    /// Offsets in increasing order with non-premultiplied (a, r, g, b)
+    /// colors in 0..=1
+    pub stops: Vec<(f64, [f64; 4])>,
+}
+
+impl Gradient {
+    /// The gradient parameter at picture point (`x`, `y`), or None where
+    /// the gradient is undefined
+    fn parameter(&self, x: f64, y: f64, repeat: Repeat) -> Option<f64> {
+        match self.shape {
+            GradientShape::Linear { p1, p2 } => {
+                let (dx, dy) = (p2.0 - p1.0, p2.1 - p1.1);
+                let length = dx * dx + dy * dy;
+                (length > 0.0).then(|| ((x - p1.0) * dx + (y - p1.1) * dy) / length)
+            }
+            GradientShape::Radial { inner, outer } => {
+                // The largest t whose circle, interpolated between the two,
+                // passes through the point with a non-negative radius
+                let (cdx, cdy, dr) = (outer.0 - inner.0, outer.1 - inner.1, outer.2 - inner.2);
+                let (pdx, pdy) = (x - inner.0, y - inner.1);
+                let a = cdx * cdx + cdy * cdy - dr * dr;
+                let b = pdx * cdx + pdy * cdy + inner.2 * dr;
+                let c = pdx * pdx + pdy * pdy - inner.2 * inner.2;
+                let roots = if a.abs() < 1e-9 {
+                    if b == 0.0 {
+                        return None;
+                    }
+                    [c / (2.0 * b); 2]
+                } else {
+                    let discriminant = b * b - a * c;
+                    if discriminant < 0.0 {
+                        return None;
+                    }
+                    let root = discriminant.sqrt();
+                    let (t1, t2) = ((b + root) / a, (b - root) / a);
+                    [t1.max(t2), t1.min(t2)]
+                };
+                roots.into_iter().find(|t| {
+                    inner.2 + t * dr >= 0.0 && (repeat != Repeat::None || (0.0..=1.0).contains(t))
+                })
+            }
+            GradientShape::Conical { center, angle } => {
+                let turn = std::f64::consts::TAU;
+                let t = ((y - center.1).atan2(x - center.0) + angle.to_radians()).rem_euclid(turn);
+                Some(1.0 - t / turn)
+            }
+        }
+    }
+
+    /// The premultiplied color at picture point (`x`, `y`)
+    fn color_at(&self, x: f64, y: f64, repeat: Repeat) -> u32 {
+        let (Some(t), Some(first), Some(last)) = (
+            self.parameter(x, y, repeat),
+            self.stops.first(),
+            self.stops.last(),
+        ) else {
+            return 0;
+        };
+        let t = match repeat {
+            Repeat::None if !(0.0..=1.0).contains(&t) => return 0,
+            Repeat::None | Repeat::Pad => t.clamp(0.0, 1.0),
+            Repeat::Normal => t - t.floor(),
+            Repeat::Reflect => {
+                let t = t.rem_euclid(2.0);
+                if t > 1.0 {
+                    2.0 - t
+                } else {
+                    t
+                }
+            }
+        };
+        let color = if t <= first.0 {
+            first.1
+        } else if t >= last.0 {
+            last.1
+        } else {
+            let next = self.stops.iter().position(|s| s.0 > t).unwrap_or(0).max(1);
+            let ((o1, c1), (o2, c2)) = (self.stops[next - 1], self.stops[next]);
+            let f = if o2 > o1 { (t - o1) / (o2 - o1) } else { 0.0 };
+            std::array::from_fn(|i| c1[i] + (c2[i] - c1[i]) * f)
+        };
+        let alpha = color[0];
+        pack([
+            alpha as f32,
+            (color[1] * alpha) as f32,
+            (color[2] * alpha) as f32,
+            (color[3] * alpha) as f32,
+        ])
+    }
+}
+
+/// A picture taking part in a composite, with its attributes resolved
+#[derive(Debug, Clone)]
+pub struct CompositePicture {
+    pub source: PictureSource,
+    pub repeat: Repeat,
+    /// Whether a mask applies each color channel to its own channel
+    pub component_alpha: bool,
+    /// Clip rectangles in picture coordinates, or None if unclipped
+    pub clip: Option<Vec<Rectangle>>,
+    /// Picture supplying the alpha channel, with its origin
+    pub alpha_map: Option<(Box<CompositePicture>, i16, i16)>,
+    /// Maps destination-aligned coordinates into the picture
+    pub transform: Option<Transform>,
+    pub filter: Filter,
+}
+
+impl CompositePicture {
+    pub fn new(source: PictureSource) -> Self {
+        CompositePicture {
+            source,
+            repeat: Repeat::None,
+            component_alpha: false,
+            clip: None,
+            alpha_map: None,
+            transform: None,
+            filter: Filter::Nearest,
+        }
+    }
+
+    /// Whether pixels are read one for one, untransformed
+    fn is_direct(&self) -> bool {
+        self.transform.is_none() && !matches!(self.filter, Filter::Convolution { .. })
+    }
+}
+
+/// A projective transform: a 3x3 matrix, row by row
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transform(pub [[f64; 3]; 3]);
+
+impl Transform {
+    /// Transform the point (`x`, `y`), or None where it goes to infinity
+    pub fn apply(&self, x: f64, y: f64) -> Option<(f64, f64)> {
+        let m = &self.0;
+        let w = m[2][0] * x + m[2][1] * y + m[2][2];
+        if w.abs() < 1e-12 {
+            return None;
+        }
+        Some((
+            (m[0][0] * x + m[0][1] * y + m[0][2]) / w,
+            (m[1][0] * x + m[1][1] * y + m[1][2]) / w,
+        ))
+    }
+}
+
+/// How a picture's pixels are resampled
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum Filter {
+    /// The pixel a point falls in
+    #[default]
+    Nearest,
+    /// The four pixels around a point, weighted by distance
+    Bilinear,
+    /// A kernel of `width` x `height` weights, row by row, centered on the
+    /// point
+    Convolution {
+        width: usize,
+        height: usize,
+        kernel: Vec<f64>,
+    },
+}
+
+/// How a Porter-Duff operator weighs partially covered pixels
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overlap {
+    /// Source and destination coverage are uncorrelated
+    Plain,
+    /// Coverage overlaps as little as possible
+    Disjoint,
+    /// Coverage overlaps as much as possible
+    Conjoint,
+}
+
+/// A separable blend function B(sa, s, da, d)
+type BlendFn = fn(f32, f32, f32, f32) -> f32;
+
+/// A RENDER compositing operator
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operator {
+    /// Clear to Xor (index 0-11) in plain, disjoint or conjoint form
+    PorterDuff(u8, Overlap),
+    Add,
+    Saturate,
+    Multiply,
+    Screen,
+    Overlay,
+    Darken,
+    Lighten,
+    ColorDodge,
+    ColorBurn,
+    HardLight,
+    SoftLight,
+    Difference,
+    Exclusion,
+    HslHue,
+    HslSaturation,
+    HslColor,
+    HslLuminosity,
+}
+
+impl Operator {
+    pub const CLEAR: Operator = Operator::PorterDuff(0, Overlap::Plain);
+    pub const SRC: Operator = Operator::PorterDuff(1, Overlap::Plain);
+    pub const DST: Operator = Operator::PorterDuff(2, Overlap::Plain);
+    pub const OVER: Operator = Operator::PorterDuff(3, Overlap::Plain);
+
+    /// Decode a RENDER PictOp
+    pub fn from_u8(op: u8) -> Option<Self> {
+        Some(match op {
+            0..=11 => Operator::PorterDuff(op, Overlap::Plain),
+            12 => Operator::Add,
+            13 => Operator::Saturate,
+            0x10..=0x1b => Operator::PorterDuff(op - 0x10, Overlap::Disjoint),
+            0x20..=0x2b => Operator::PorterDuff(op - 0x20, Overlap::Conjoint),
+            0x30 => Operator::Multiply,
+            0x31 => Operator::Screen,
+            0x32 => Operator::Overlay,
+            0x33 => Operator::Darken,
+            0x34 => Operator::Lighten,
+            0x35 => Operator::ColorDodge,
+            0x36 => Operator::ColorBurn,
+            0x37 => Operator::HardLight,
+            0x38 => Operator::SoftLight,
+            0x39 => Operator::Difference,
+            0x3a => Operator::Exclusion,
+            0x3b => Operator::HslHue,
+            0x3c => Operator::HslSaturation,
+            0x3d => Operator::HslColor,
+            0x3e => Operator::HslLuminosity,
+            _ => return None,
+        })
+    }
+
+    /// Encode as a RENDER PictOp
+    pub fn to_u8(self) -> u8 {
+        match self {
+            Operator::PorterDuff(index, Overlap::Plain) => index,
+            Operator::PorterDuff(index, Overlap::Disjoint) => 0x10 + index,
+            Operator::PorterDuff(index, Overlap::Conjoint) => 0x20 + index,
+            Operator::Add => 12,
+            Operator::Saturate => 13,
+            Operator::Multiply => 0x30,
+            Operator::Screen => 0x31,
+            Operator::Overlay => 0x32,
+            Operator::Darken => 0x33,
+            Operator::Lighten => 0x34,
+            Operator::ColorDodge => 0x35,
+            Operator::ColorBurn => 0x36,
+            Operator::HardLight => 0x37,
+            Operator::SoftLight => 0x38,
+            Operator::Difference => 0x39,
+            Operator::Exclusion => 0x3a,
+            Operator::HslHue => 0x3b,
+            Operator::HslSaturation => 0x3c,
+            Operator::HslColor => 0x3d,
+            Operator::HslLuminosity => 0x3e,
+        }
+    }
+
+    /// The blend function of a separable blend mode
+    fn separable(self) -> Option<BlendFn> {
+        let blend: BlendFn = match self {
+            Operator::Multiply => |_, s, _, d| s * d,
+            Operator::Screen => |sa, s, da, d| s * da + d * sa - s * d,
+            Operator::Overlay => |sa, s, da, d| hard_light(da, d, sa, s),
+            Operator::Darken => |sa, s, da, d| (s * da).min(d * sa),
+            Operator::Lighten => |sa, s, da, d| (s * da).max(d * sa),
+            Operator::ColorDodge => color_dodge,
+            Operator::ColorBurn => color_burn,
+            Operator::HardLight => hard_light,
+            Operator::SoftLight => soft_light,
+            Operator::Difference => |sa, s, da, d| (s * da - d * sa).abs(),
+            Operator::Exclusion => |sa, s, da, d| s * da + d * sa - 2.0 * s * d,
+            _ => return None,
+        };
+        Some(blend)
+    }
+}
+
+// Porter-Duff factor selectors: the source is weighted by its "in" or
+// "out" part (or both, i.e. 1), and likewise the destination
+const A_OUT: u8 = 1;
+const A_IN: u8 = 2;
+const B_OUT: u8 = 4;
+const B_IN: u8 = 8;
+const A: u8 = A_OUT | A_IN;
+const B: u8 = B_OUT | B_IN;
+
+/// Factors of Clear, Src, Dst, Over, OverReverse, In, InReverse, Out,
+/// OutReverse, Atop, AtopReverse and Xor
+const PORTER_DUFF: [u8; 12] = [
+    0,
+    A,
+    B,
+    A | B_OUT,
+    B | A_OUT,
+    A_IN,
+    B_IN,
+    A_OUT,
+    B_OUT,
+    A_IN | B_OUT,
+    B_IN | A_OUT,
+    A_OUT | B_OUT,
+];
+
+/// Weight of a picture with alpha `a` against the other picture's `b`;
+/// `parts` is A_OUT, A_IN or both
+fn factor(parts: u8, overlap: Overlap, a: f32, b: f32) -> f32 {
+    match (parts, overlap) {
+        (0, _) => 0.0,
+        (A_OUT, Overlap::Plain) => 1.0 - b,
+        (A_IN, Overlap::Plain) => b,
+        (A_OUT, Overlap::Disjoint) if a == 0.0 => 1.0,
+        (A_OUT, Overlap::Disjoint) => ((1.0 - b) / a).clamp(0.0, 1.0),
+        (A_IN, Overlap::Disjoint) if a == 0.0 => 0.0,
+        (A_IN, Overlap::Disjoint) => (1.0 - (1.0 - b) / a).clamp(0.0, 1.0),
+        (A_OUT, Overlap::Conjoint) if a == 0.0 => 0.0,
+        (A_OUT, Overlap::Conjoint) => (1.0 - b / a).clamp(0.0, 1.0),
+        (A_IN, Overlap::Conjoint) if a == 0.0 => 1.0,
+        (A_IN, Overlap::Conjoint) => (b / a).clamp(0.0, 1.0),
+        _ => 1.0,
+    }
+}
+
+fn hard_light(sa: f32, s: f32, da: f32, d: f32) -> f32 {
+    if 2.0 * s < sa {
+        2.0 * s * d
+    } else {
+        sa * da - 2.0 * (da - d) * (sa - s)
+    }
+}
+
+fn color_dodge(sa: f32, s: f32, da: f32, d: f32) -> f32 {
+    if d == 0.0 {
+        0.0
+    } else if d * sa >= sa * da - s * da || sa == s {
+        sa * da
+    } else {
+        sa * sa * d / (sa - s)
+    }
+}
+
+fn color_burn(sa: f32, s: f32, da: f32, d: f32) -> f32 {
+    if d >= da {
+        sa * da
+    } else if sa * (da - d) >= s * da || s == 0.0 {
+        0.0
+    } else {
+        sa * (da - sa * (da - d) / s)
+    }
+}
+
+fn soft_light(sa: f32, s: f32, da: f32, d: f32) -> f32 {
+    if da == 0.0 {
+        d * sa
+    } else if 2.0 * s < sa {
+        d * sa - d * (da - d) * (sa - 2.0 * s) / da
+    } else if 4.0 * d <= da {
+        d * sa + (2.0 * s - sa) * d * ((16.0 * d / da - 12.0) * d / da + 3.0)
+    } else {
+        d * sa + ((d * da).sqrt() - d) * (2.0 * s - sa)
+    }
+}
+
+fn lum(c: [f32; 3]) -> f32 {
+    0.3 * c[0] + 0.59 * c[1] + 0.11 * c[2]
+}
+
+fn sat(c: [f32; 3]) -> f32 {
+    c[0].max(c[1]).max(c[2]) - c[0].min(c[1]).min(c[2])
+}
+
+/// `c` with its luminosity moved to `l`, kept within 0..=`a`
+fn set_lum(c: [f32; 3], a: f32, l: f32) -> [f32; 3] {
+    let delta = l - lum(c);
+    let c = c.map(|v| v + delta);
+    let l = lum(c);
+    let (min, max) = (c[0].min(c[1]).min(c[2]), c[0].max(c[1]).max(c[2]));
+    let c = if min < 0.0 {
+        if l - min == 0.0 {
+            [l; 3]
+        } else {
+            c.map(|v| l + (v - l) * l / (l - min))
+        }
+    } else {
+        c
+    };
+    if max > a {
+        if max - l == 0.0 {
+            [a; 3]
+        } else {
+            c.map(|v| l + (v - l) * (a - l) / (max - l))
+        }
+    } else {
+        c
+    }
+}
+
+/// `c` with its saturation changed to `s`
+fn set_sat(c: [f32; 3], s: f32) -> [f32; 3] {
+    let mut order = [0, 1, 2];
+    order.sort_by(|&i, &j| c[i].total_cmp(&c[j]));
+    let [min, mid, max] = order;
+    let mut out = [0.0; 3];
+    if c[max] > c[min] {
+        out[mid] = (c[mid] - c[min]) * s / (c[max] - c[min]);
+        out[max] = s;
+    }
+    out
+}
+
+/// Combine a source pixel into a destination pixel
+///
+/// Channels are premultiplied (a, r, g, b) in 0..=1. `src` is already
+/// multiplied by the mask; `src_alpha` is the source alpha each channel
+/// sees, which only differs between channels with a component-alpha mask.
+pub fn combine(op: Operator, src: [f32; 4], src_alpha: [f32; 4], dst: [f32; 4]) -> [f32; 4] {
+    let da = dst[0];
+    let result: [f32; 4] = match op {
+        Operator::PorterDuff(index, overlap) => {
+            let parts = PORTER_DUFF[index as usize % PORTER_DUFF.len()];
+            std::array::from_fn(|c| {
+                let sa = src_alpha[c];
+                let fa = factor(parts & A, overlap, sa, da);
+                let fb = factor((parts & B) >> 2, overlap, da, sa);
+                src[c] * fa + dst[c] * fb
+            })
+        }
+        Operator::Add => std::array::from_fn(|c| src[c] + dst[c]),
+        Operator::Saturate => std::array::from_fn(|c| {
+            let sa = src_alpha[c];
+            let fa = if sa == 0.0 {
+                1.0
+            } else {
+                ((1.0 - da) / sa).min(1.0)
+            };
+            src[c] * fa + dst[c]
+        }),
+        Operator::HslHue
+        | Operator::HslSaturation
+        | Operator::HslColor
+        | Operator::HslLuminosity => {
+            let sa = src[0];
+            let s = [src[1], src[2], src[3]];
+            let d = [dst[1], dst[2], dst[3]];
+            let (sc, dc) = (s.map(|v| v * da), d.map(|v| v * sa));
+            let blended = match op {
+                Operator::HslHue => set_lum(set_sat(sc, sat(dc)), sa * da, lum(dc)),
+                Operator::HslSaturation => set_lum(set_sat(dc, sat(sc)), sa * da, lum(dc)),
+                Operator::HslColor => set_lum(sc, sa * da, lum(dc)),
+                _ => set_lum(dc, sa * da, lum(sc)),
+            };
+            let mut out = [sa + da - sa * da; 4];
+            for c in 0..3 {
+                out[c + 1] = (1.0 - sa) * d[c] + (1.0 - da) * s[c] + blended[c];
+            }
+            out
+        }
+        _ => {
+            let blend = op.separable().unwrap_or(|_, _, _, d| d);
+            let mut out = [src[0] + da - src[0] * da; 4];
+            for c in 1..4 {
+                let sa = src_alpha[c];
+                out[c] = (1.0 - sa) * dst[c] + (1.0 - da) * src[c] + blend(sa, src[c], da, dst[c]);
+            }
+            out
+        }
+    };
+    result.map(|v| v.clamp(0.0, 1.0))
+}
+
+/// Split a pixel into (a, r, g, b) in 0..=1
+pub fn unpack(pixel: u32) -> [f32; 4] {
+    [24, 16, 8, 0].map(|shift| ((pixel >> shift) & 0xff) as f32 / 255.0)
+}
+
+/// Join (a, r, g, b) in 0..=1 into a pixel
+pub fn pack(channels: [f32; 4]) -> u32 {
+    channels
+        .iter()
+        .fold(0, |pixel, v| pixel << 8 | (v * 255.0 + 0.5) as u32)
+}
+
+/// Pixels read from part of a drawable, as premultiplied ARGB
+struct Image {
+    area: Rectangle,
+    pixels: Vec<u32>,
+}
+
+impl Image {
+    /// The pixel at drawable (`x`, `y`), transparent outside the area read
+    fn get(&self, x: i32, y: i32) -> u32 {
+        let (col, row) = (x - self.area.x as i32, y - self.area.y as i32);
+        if col < 0 || row < 0 || col >= self.area.width as i32 || row >= self.area.height as i32 {
+            return 0;
+        }
+        self.pixels[row as usize * self.area.width as usize + col as usize]
+    }
+
+    fn read<B: Backend + ?Sized>(
+        backend: &mut B,
+        drawable: BackendDrawable,
+        format: PictFormat,
+        area: Rectangle,
+    ) -> BackendResult<Self> {
+        let (depth, _, data) =
+            backend.get_image(drawable, area.x, area.y, area.width, area.height, !0, 2)?;
+        let pixels = decode(
+            format,
+            depth,
+            &data,
+            area.width as usize,
+            area.height as usize,
+        );
+        if pixels.len() < area.width as usize * area.height as usize {
+            return Err("Short image read during composite".into());
+        }
+        Ok(Image { area, pixels })
+    }
+
+    fn write<B: Backend + ?Sized>(
+        &self,
+        backend: &mut B,
+        drawable: BackendDrawable,
+        format: PictFormat,
+    ) -> BackendResult<()> {
+        let width = self.area.width as usize;
+        let data = match format {
+            PictFormat::Argb32 => self.pixels.iter().flat_map(|p| p.to_le_bytes()).collect(),
+            PictFormat::Rgb24 => self
+                .pixels
+                .iter()
+                .flat_map(|p| (p | 0xff00_0000).to_le_bytes())
+                .collect(),
+            PictFormat::A8 => {
+                let stride = width.div_ceil(4) * 4;
+                let mut data = vec![0u8; stride * self.area.height as usize];
+                for (i, p) in self.pixels.iter().enumerate() {
+                    data[i / width * stride + i % width] = (p >> 24) as u8;
+                }
+                data
+            }
+            PictFormat::A1 => {
+                let stride = width.div_ceil(32) * 4;
+                let mut data = vec![0u8; stride * self.area.height as usize];
+                for (i, p) in self.pixels.iter().enumerate() {
+                    if p >> 24 >= 0x80 {
+                        let (x, y) = (i % width, i / width);
+                        data[y * stride + x / 8] |= 1 << (x % 8);
+                    }
+                }
+                data
+            }
+        };
+        backend.put_image(
+            drawable,
+            &BackendGC::default(),
+            self.area.width,
+            self.area.height,
+            self.area.x,
+            self.area.y,
+            format.depth(),
+            2,
+            &data,
+        )
+    }
+}
+
+/// Decode ZPixmap data of `depth` into premultiplied pixels of `format`
+///
+/// Backends that keep every drawable at full depth hand back 32-bit pixels;
+/// alpha-only formats then keep their alpha in the low byte.
+pub fn decode(format: PictFormat, depth: u8, data: &[u8], width: usize, height: usize) -> Vec<u32> {
+    let raw: Vec<u32> = match depth {
+        1 => {
+            let stride = width.div_ceil(32) * 4;
+            (0..width * height)
+                .map(|i| {
+                    let (x, y) = (i % width, i / width);
+                    let set = data
+                        .get(y * stride + x / 8)
+                        .is_some_and(|b| b >> (x % 8) & 1 != 0);
+                    if set {
+                        0xffff_ffff
+                    } else {
+                        0
+                    }
+                })
+                .collect()
+        }
+        8 => {
+            let stride = width.div_ceil(4) * 4;
+            (0..width * height)
+                .map(|i| {
+                    let v = data.get(i / width * stride + i % width).copied();
+                    v.unwrap_or(0) as u32 * 0x0101_0101
+                })
+                .collect()
+        }
+        _ => data
+            .chunks_exact(4)
+            .map(|p| u32::from_le_bytes([p[0], p[1], p[2], p[3]]))
+            .collect(),
+    };
+    let full_depth = depth >= 24;
+    raw.into_iter()
+        .map(|p| match format {
+            PictFormat::Argb32 if depth == 32 => p,
+            PictFormat::Argb32 | PictFormat::Rgb24 => p | 0xff00_0000,
+            PictFormat::A8 | PictFormat::A1 if full_depth => (p & 0xff) << 24,
+            PictFormat::A8 | PictFormat::A1 => p & 0xff00_0000,
+        })
+        .collect()
+}
+
+fn contains(rects: &[Rectangle], x: i32, y: i32) -> bool {
+    rects.iter().any(|r| {
+        let (rx, ry) = (r.x as i32, r.y as i32);
+        x >= rx && y >= ry && x < rx + r.width as i32 && y < ry + r.height as i32
+    })
+}
+
+/// Reads a picture with its repeat mode, clip and alpha map applied
+struct Sampler<'a> {
+    picture: &'a CompositePicture,
+    image: Option<Image>,
+    alpha: Option<(Box<Sampler<'a>>, i32, i32)>,
+}
+
+impl<'a> Sampler<'a> {
+    /// Read what the picture shows in `region` (x1, y1, x2, y2)
+    fn load<B: Backend + ?Sized>(
+        backend: &mut B,
+        picture: &'a CompositePicture,
+        region: (i32, i32, i32, i32),
+    ) -> BackendResult<Self> {
+        let image = match picture.source {
+            PictureSource::Solid(_) | PictureSource::Gradient(_) | PictureSource::Pixels { .. } => {
+                None
+            }
+            PictureSource::Drawable {
+                drawable,
+                format,
+                width,
+                height,
+            } => {
+                // Filters read around each point
+                let (pad_x, pad_y) = match &picture.filter {
+                    Filter::Nearest => (0, 0),
+                    Filter::Bilinear => (1, 1),
+                    Filter::Convolution { width, height, .. } => {
+                        (*width as i32 / 2 + 1, *height as i32 / 2 + 1)
+                    }
+                };
+                let region = (
+                    region.0 - pad_x,
+                    region.1 - pad_y,
+                    region.2 + pad_x,
+                    region.3 + pad_y,
+                );
+                // A repeating or transformed picture may need any of its pixels
+                let inside = region.0 >= 0
+                    && region.1 >= 0
+                    && region.2 <= width as i32
+                    && region.3 <= height as i32;
+                let needed =
+                    if picture.transform.is_none() && (picture.repeat == Repeat::None || inside) {
+                        region
+                    } else {
+                        (0, 0, width as i32, height as i32)
+                    };
+                match clamp(needed, (width, height)) {
+                    Some(area) => Some(Image::read(backend, drawable, format, area)?),
+                    None => None,
+                }
+            }
+        };
+        let alpha = match &picture.alpha_map {
+            Some((map, x, y)) => {
+                let (x, y) = (*x as i32, *y as i32);
+                let region = (region.0 - x, region.1 - y, region.2 - x, region.3 - y);
+                Some((Box::new(Sampler::load(backend, map, region)?), x, y))
+            }
+            None => None,
+        };
+        Ok(Sampler {
+            picture,
+            image,
+            alpha,
+        })
+    }
+
+    /// The pixel at picture (`x`, `y`), or None where the clip excludes it
+    fn sample(&self, x: i32, y: i32) -> Option<u32> {
+        if let Some(clip) = &self.picture.clip {
+            if !contains(clip, x, y) {
+                return None;
+            }
+        }
+        let pixel = if self.picture.is_direct() {
+            self.fetch(x, y)
+        } else {
+            // Transforms apply to pixel centers
+            let (cx, cy) = (x as f64 + 0.5, y as f64 + 0.5);
+            let point = match &self.picture.transform {
+                Some(transform) => transform.apply(cx, cy),
+                None => Some((cx, cy)),
+            };
+            point.map_or(0, |(u, v)| self.filtered(u, v))
+        };
+        Some(match &self.alpha {
+            Some((map, ax, ay)) => {
+                let alpha = map.sample(x - ax, y - ay).unwrap_or(0);
+                (pixel & 0x00ff_ffff) | (alpha & 0xff00_0000)
+            }
+            None => pixel,
+        })
+    }
+
+    /// Pixel (`x`, `y`) with the repeat mode applied
+    fn fetch(&self, x: i32, y: i32) -> u32 {
+        let repeat = self.picture.repeat;
+        match &self.picture.source {
+            PictureSource::Solid(color) => *color,
+            // Gradients are sampled at pixel centers
+            PictureSource::Gradient(gradient) => {
+                gradient.color_at(x as f64 + 0.5, y as f64 + 0.5, repeat)
+            }
+            PictureSource::Pixels {
+                width,
+                height,
+                pixels,
+            } => match (
+                repeat.wrap(x, *width as i32),
+                repeat.wrap(y, *height as i32),
+            ) {
+                (Some(x), Some(y)) => pixels[y as usize * *width as usize + x as usize],
+                _ => 0,
+            },
+            PictureSource::Drawable { width, height, .. } => {
+                match (
+                    repeat.wrap(x, *width as i32),
+                    repeat.wrap(y, *height as i32),
+                ) {
+                    (Some(x), Some(y)) => self.image.as_ref().map_or(0, |image| image.get(x, y)),
+                    _ => 0,
+                }
+            }
+        }
+    }
+
+    /// The picture's color at point (`u`, `v`), through its filter
+    fn filtered(&self, u: f64, v: f64) -> u32 {
+        match &self.picture.source {
+            PictureSource::Solid(color) => return *color,
+            PictureSource::Gradient(gradient) => {
+                return gradient.color_at(u, v, self.picture.repeat)
+            }
+            _ => {}
+        }
+        match &self.picture.filter {
+            Filter::Nearest => self.fetch(u.floor() as i32, v.floor() as i32),
+            Filter::Bilinear => {
+                let (u, v) = (u - 0.5, v - 0.5);
+                let (x, y) = (u.floor(), v.floor());
+                let (fx, fy) = (u - x, v - y);
+                let (x, y) = (x as i32, y as i32);
+                weighted([
+                    (self.fetch(x, y), (1.0 - fx) * (1.0 - fy)),
+                    (self.fetch(x + 1, y), fx * (1.0 - fy)),
+                    (self.fetch(x, y + 1), (1.0 - fx) * fy),
+                    (self.fetch(x + 1, y + 1), fx * fy),
+                ])
+            }
+            Filter::Convolution {
+                width,
+                height,
+                kernel,
+            } => {
+                // The kernel's center lands on the pixel the point falls in
+                let x1 = (u - (*width as f64 - 1.0) / 2.0).floor() as i32;
+                let y1 = (v - (*height as f64 - 1.0) / 2.0).floor() as i32;
+                weighted((0..*height).flat_map(|j| {
+                    (0..*width).map(move |i| {
+                        let weight = kernel.get(j * width + i).copied().unwrap_or(0.0);
+                        (self.fetch(x1 + i as i32, y1 + j as i32), weight)
+                    })
+                }))
+            }
+        }
+    }
+}
+
+/// Sum of premultiplied pixels scaled by their weights
+fn weighted(samples: impl IntoIterator<Item = (u32, f64)>) -> u32 {
+    let mut sum = [0.0f32; 4];
+    for (pixel, weight) in samples {
+        if weight != 0.0 {
+            let channels = unpack(pixel);
+            for c in 0..4 {
+                sum[c] += channels[c] * weight as f32;
+            }
+        }
+    }
+    pack(sum.map(|v| v.clamp(0.0, 1.0)))
+}
+
+/// The premultiplied pixels `picture` shows in `area`, row by row
+///
+/// Points its clip excludes are transparent.
+pub fn read_picture<B: Backend + ?Sized>(
+    backend: &mut B,
+    picture: &CompositePicture,
+    area: Rectangle,
+) -> BackendResult<Vec<u32>> {
+    let (x1, y1) = (area.x as i32, area.y as i32);
+    let (x2, y2) = (x1 + area.width as i32, y1 + area.height as i32);
+    let sampler = Sampler::load(backend, picture, (x1, y1, x2, y2))?;
+    Ok((y1..y2)
+        .flat_map(|y| (x1..x2).map(move |x| (x, y)))
+        .map(|(x, y)| sampler.sample(x, y).unwrap_or(0))
+        .collect())
+}
+
+/// Composite `src` through `mask` onto the `area` of `dst`
+///
+/// `src_origin` and `mask_origin` are the picture coordinates that line up
+/// with the top-left corner of `area`.
+#[allow(clippy::too_many_arguments)]
+pub fn composite<B: Backend + ?Sized>(
+    backend: &mut B,
+    op: Operator,
+    src: &CompositePicture,
+    mask: Option<&CompositePicture>,
+    dst: &CompositePicture,
+    src_origin: (i16, i16),
+    mask_origin: (i16, i16),
+    area: Rectangle,
+) -> BackendResult<()> {
+    let PictureSource::Drawable {
+        drawable,
+        format,
+        width,
+        height,
+    } = dst.source
+    else {
+        return Err("Composite destination is not a drawable".into());
+    };
+    if op == Operator::DST {
+        return Ok(());
+    }
+    let (x1, y1) = (area.x as i32, area.y as i32);
+    let mut bounds = (x1, y1, x1 + area.width as i32, y1 + area.height as i32);
+    if let Some(clip) = &dst.clip {
+        let mut extents = (i32::MAX, i32::MAX, i32::MIN, i32::MIN);
+        for r in clip {
+            extents.0 = extents.0.min(r.x as i32);
+            extents.1 = extents.1.min(r.y as i32);
+            extents.2 = extents.2.max(r.x as i32 + r.width as i32);
+            extents.3 = extents.3.max(r.y as i32 + r.height as i32);
+        }
+        bounds = (
+            bounds.0.max(extents.0),
+            bounds.1.max(extents.1),
+            bounds.2.min(extents.2),
+            bounds.3.min(extents.3),
+        );
+    }
+    let Some(area) = clamp(bounds, (width, height)) else {
+        return Ok(());
+    };
+
+    // Offsets from destination to source and mask coordinates
+    let src_offset = (src_origin.0 as i32 - x1, src_origin.1 as i32 - y1);
+    let mask_offset = (mask_origin.0 as i32 - x1, mask_origin.1 as i32 - y1);
+    if mask.is_none() && native(backend, op, src, dst, format, src_offset, area)? {
+        return Ok(());
+    }
+
+    let (ax1, ay1) = (area.x as i32, area.y as i32);
+    let (ax2, ay2) = (ax1 + area.width as i32, ay1 + area.height as i32);
+    let shifted = |(dx, dy): (i32, i32)| (ax1 + dx, ay1 + dy, ax2 + dx, ay2 + dy);
+    let src_sampler = Sampler::load(backend, src, shifted(src_offset))?;
+    let mask_sampler = match mask {
+        Some(mask) => Some(Sampler::load(backend, mask, shifted(mask_offset))?),
+        None => None,
+    };
+    let mut image = Image::read(backend, drawable, format, area)?;
+    // A destination alpha map takes the alpha channel of the result
+    let mut alpha_image = match &dst.alpha_map {
+        Some((map, mx, my)) => match map.source {
+            PictureSource::Drawable {
+                drawable,
+                format,
+                width,
+                height,
+            } => {
+                let region = shifted((-(*mx as i32), -(*my as i32)));
+                match clamp(region, (width, height)) {
+                    Some(area) => Some((Image::read(backend, drawable, format, area)?, *mx, *my)),
+                    None => None,
+                }
+            }
+            _ => None,
+        },
+        None => None,
+    };
+
+    for y in ay1..ay2 {
+        for x in ax1..ax2 {
+            if dst.clip.as_ref().is_some_and(|clip| !contains(clip, x, y)) {
+                continue;
+            }
+            let Some(s) = src_sampler.sample(x + src_offset.0, y + src_offset.1) else {
+                continue;
+            };
+            let s = unpack(s);
+            let (s, sa) = match &mask_sampler {
+                Some(sampler) => {
+                    let Some(m) = sampler.sample(x + mask_offset.0, y + mask_offset.1) else {
+                        continue;
+                    };
+                    let m = unpack(m);
+                    if sampler.picture.component_alpha {
+                        (std::array::from_fn(|c| s[c] * m[c]), m.map(|m| s[0] * m))
+                    } else {
+                        (s.map(|v| v * m[0]), [s[0] * m[0]; 4])
+                    }
+                }
+                None => (s, [s[0]; 4]),
+            };
+
+            let index = (y - ay1) as usize * area.width as usize + (x - ax1) as usize;
+            let mut d = unpack(image.pixels[index]);
+            if let Some((map, mx, my)) = &alpha_image {
+                d[0] = unpack(map.get(x - *mx as i32, y - *my as i32))[0];
+            }
+            let result = combine(op, s, sa, d);
+            match &mut alpha_image {
+                Some((map, mx, my)) => {
+                    let (col, row) = (
+                        x - *mx as i32 - map.area.x as i32,
+                        y - *my as i32 - map.area.y as i32,
+                    );
+                    if col >= 0
+                        && row >= 0
+                        && col < map.area.width as i32
+                        && row < map.area.height as i32
+                    {
+                        let i = row as usize * map.area.width as usize + col as usize;
+                        map.pixels[i] = pack([result[0], 0.0, 0.0, 0.0]);
+                    }
+                    let color = pack(result) & 0x00ff_ffff;
+                    image.pixels[index] = (image.pixels[index] & 0xff00_0000) | color;
+                }
+                None => image.pixels[index] = pack(result),
+            }
+        }
+    }
+
+    image.write(backend, drawable, format)?;
+    if let (Some((map, _, _)), Some((alpha_map, _, _))) = (&alpha_image, &dst.alpha_map) {
+        if let PictureSource::Drawable {
+            drawable, format, ..
+        } = alpha_map.source
+        {
+            map.write(backend, drawable, format)?;
+        }
+    }
+    Ok(())
+}
+
+/// Draw a composite that is a plain copy or solid fill with the backend's
+/// own operations; false if it is neither
+fn native<B: Backend + ?Sized>(
+    backend: &mut B,
+    op: Operator,
+    src: &CompositePicture,
+    dst: &CompositePicture,
+    dst_format: PictFormat,
+    src_offset: (i32, i32),
+    area: Rectangle,
+) -> BackendResult<bool> {
+    let PictureSource::Drawable { drawable, .. } = dst.source else {
+        return Ok(false);
+    };
+    if !src.is_direct()
+        || src.alpha_map.is_some()
+        || dst.alpha_map.is_some()
+        || !matches!(dst_format, PictFormat::Argb32 | PictFormat::Rgb24)
+    {
+        return Ok(false);
+    }
+    let gc = BackendGC {
+        clip_rectangles: dst.clip.clone(),
+        ..BackendGC::default()
+    };
+    match src.source {
+        PictureSource::Gradient(_) | PictureSource::Pixels { .. } => return Ok(false),
+        PictureSource::Solid(color) => {
+            let color = match op {
+                Operator::CLEAR => 0,
+                Operator::SRC => color,
+                Operator::OVER if color >> 24 == 0xff => color,
+                _ => return Ok(false),
+            };
+            let foreground = if dst_format == PictFormat::Rgb24 {
+                color & 0x00ff_ffff
+            } else {
+                color
+            };
+            let gc = BackendGC { foreground, ..gc };
+            backend.fill_rectangle(drawable, &gc, area.x, area.y, area.width, area.height)?;
+        }
+        PictureSource::Drawable {
+            drawable: src_drawable,
+            format,
+            width,
+            height,
+        } => {
+            // Copies need matching formats and a source entirely inside its
+            // drawable, so that repeat and transparency do not come into it
+            let opaque = format == PictFormat::Rgb24;
+            let copies =
+                format == dst_format && (op == Operator::SRC || op == Operator::OVER && opaque);
+            let (sx, sy) = (area.x as i32 + src_offset.0, area.y as i32 + src_offset.1);
+            let inside = sx >= 0
+                && sy >= 0
+                && sx + area.width as i32 <= width as i32
+                && sy + area.height as i32 <= height as i32;
+            if !copies || !inside || src.clip.is_some() {
+                return Ok(false);
+            }
+            backend.copy_area(
+                src_drawable,
+                drawable,
+                &gc,
+                sx as i16,
+                sy as i16,
+                area.width,
+                area.height,
+                area.x,
+                area.y,
+            )?;
+        }
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(op: u8, src: u32, dst: u32) -> u32 {
+        let op = Operator::from_u8(op).unwrap();
+        let s = unpack(src);
+        pack(combine(op, s, [s[0]; 4], unpack(dst)))
+    }
+
+    #[test]
+    fn test_operators() {
+        let half_red = 0x8080_0000;
+        let blue = 0xff00_00ff;
+        assert_eq!(apply(0, half_red, blue), 0);
+        assert_eq!(apply(1, half_red, blue), half_red);
+        assert_eq!(apply(3, half_red, blue), 0xff80_007f); // Over
+        assert_eq!(apply(5, half_red, blue), half_red); // In
+        assert_eq!(apply(8, half_red, blue), 0x7f00_007f); // OutReverse
+        assert_eq!(apply(12, 0xc0c0_0000, 0xc000_00c0), 0xffc0_00c0); // Add saturates
+                                                                      // Disjoint Over of two half-covered pixels covers everything
+        assert_eq!(apply(0x13, half_red, 0x8000_0080) >> 24, 0xff);
+        // Conjoint Over hides the destination behind the same coverage
+        assert_eq!(apply(0x23, half_red, 0x8000_0080), half_red);
+        assert_eq!(apply(0x30, 0xff80_8080, 0xff40_ff00), 0xff20_8000); // Multiply
+        assert_eq!(apply(0x39, 0xffff_ffff, 0xff40_ff00), 0xffbf_00ff); // Difference
+        assert!(Operator::from_u8(14).is_none());
+        for op in 0..=0x3e {
+            if let Some(operator) = Operator::from_u8(op) {
+                assert_eq!(operator.to_u8(), op);
+            }
+        }
+    }
+
+    #[test]
+    fn test_component_alpha() {
+        let white = unpack(0xffff_ffff);
+        let mask = unpack(0xffff_0000);
+        let src = std::array::from_fn(|c| white[c] * mask[c]);
+        let alpha = mask.map(|m| white[0] * m);
+        let result = combine(Operator::OVER, src, alpha, unpack(0xff00_00ff));
+        assert_eq!(pack(result), 0xffff_00ff);
+    }
+
+    #[test]
+    fn test_gradients() {
+        let stops = vec![(0.0, [1.0, 0.0, 0.0, 0.0]), (1.0, [1.0, 1.0, 1.0, 1.0])];
+        let linear = Gradient {
+            shape: GradientShape::Linear {
+                p1: (0.0, 0.0),
+                p2: (10.0, 0.0),
+            },
+            stops: stops.clone(),
+        };
+        assert_eq!(linear.color_at(5.0, 3.0, Repeat::None), 0xff80_8080);
+        assert_eq!(linear.color_at(15.0, 0.0, Repeat::None), 0);
+        assert_eq!(linear.color_at(15.0, 0.0, Repeat::Pad), 0xffff_ffff);
+        assert_eq!(linear.color_at(15.0, 0.0, Repeat::Normal), 0xff80_8080);
+        assert_eq!(linear.color_at(12.0, 0.0, Repeat::Reflect), 0xffcc_cccc);
+
+        let radial = Gradient {
+            shape: GradientShape::Radial {
+                inner: (0.0, 0.0, 0.0),
+                outer: (0.0, 0.0, 10.0),
+            },
+            stops: stops.clone(),
+        };
+        assert_eq!(radial.color_at(0.0, 5.0, Repeat::None), 0xff80_8080);
+
+        // A quarter turn clockwise (y grows downwards) from the start angle
+        let conical = Gradient {
+            shape: GradientShape::Conical {
+                center: (0.0, 0.0),
+                angle: 0.0,
+            },
+            stops,
+        };
+        assert_eq!(conical.color_at(0.0, 5.0, Repeat::None), 0xffbf_bfbf);
+
+        // Stop colors are premultiplied after interpolation
+        let translucent = Gradient {
+            shape: GradientShape::Linear {
+                p1: (0.0, 0.0),
+                p2: (1.0, 0.0),
+            },
+            stops: vec![(0.0, [0.5, 1.0, 0.0, 0.0])],
+        };
+        assert_eq!(translucent.color_at(0.5, 0.0, Repeat::Pad), 0x8080_0000);
+    }
+
+    #[test]
+    fn test_repeat_and_decode() {
+        assert_eq!(Repeat::None.wrap(-1, 4), None);
+        assert_eq!(Repeat::Normal.wrap(-1, 4), Some(3));
+        assert_eq!(Repeat::Pad.wrap(9, 4), Some(3));
+        assert_eq!(Repeat::Reflect.wrap(5, 4), Some(2));
+        assert_eq!(Repeat::Reflect.wrap(-1, 4), Some(0));
+
+        let a1 = decode(PictFormat::A1, 1, &[0b101, 0, 0, 0], 3, 1);
+        assert_eq!(a1, vec![0xff00_0000, 0, 0xff00_0000]);
+        let a8 = decode(PictFormat::A8, 8, &[0x40, 0, 0, 0], 1, 1);
+        assert_eq!(a8, vec![0x4000_0000]);
+        let rgb = decode(PictFormat::Rgb24, 24, &[1, 2, 3, 0], 1, 1);
+        assert_eq!(rgb, vec![0xff03_0201]);
+    }
+
+    #[test]
+    fn test_transform_and_filters() {
+        // Two pixels: opaque black, opaque white
+        let picture = |filter, repeat| {
+            let mut picture = CompositePicture::new(PictureSource::Pixels {
+                width: 2,
+                height: 1,
+                pixels: Arc::new(vec![0xff00_0000, 0xffff_ffff]),
+            });
+            picture.filter = filter;
+            picture.repeat = repeat;
+            picture
+        };
+        let sampler = |picture| Sampler {
+            picture,
+            image: None,
+            alpha: None,
+        };
+
+        // Halfway between the pixel centers, bilinear gives mid gray
+        let mut bilinear = picture(Filter::Bilinear, Repeat::Pad);
+        bilinear.transform = Some(Transform([
+            [1.0, 0.0, 0.5],
+            [0.0, 1.0, 0.0],
+            [0.0, 0.0, 1.0],
+        ]));
+        assert_eq!(sampler(&bilinear).sample(0, 0), Some(0xff80_8080));
+        // Scaling by two reads pixel 1 from destination pixel 3
+        let mut nearest = picture(Filter::Nearest, Repeat::None);
+        nearest.transform = Some(Transform([
+            [0.5, 0.0, 0.0],
+            [0.0, 0.5, 0.0],
+            [0.0, 0.0, 1.0],
+        ]));
+        assert_eq!(sampler(&nearest).sample(3, 0), Some(0xffff_ffff));
+        assert_eq!(sampler(&nearest).sample(4, 0), Some(0));
+
+        // A box blur over the edge sees transparent without repeat and the
+        // edge pixel with Pad
+        let blur = Filter::Convolution {
+            width: 3,
+            height: 1,
+            kernel: vec![1.0 / 3.0; 3],
+        };
+        let none = picture(blur.clone(), Repeat::None);
+        assert_eq!(sampler(&none).sample(0, 0), Some(0xaa55_5555));
+        let pad = picture(blur, Repeat::Pad);
+        assert_eq!(sampler(&pad).sample(0, 0), Some(0xff55_5555));
+    }
+}
@@ -0,0 +1,310 @@
+//! Keyboard layouts shared by backends
+//!
+//! Backends that cannot ask the host for its layout build their mapping
+//! from these tables: keysyms for unshifted keys, with the shifted level
+//! derived as on a US keyboard.
+
+use super::KeyboardMapping;
+
+/// Keycodes for each of Shift, Lock, Control and Mod1-Mod5 (0 = unused)
+pub type ModifierKeys = [[u8; 2]; 8];
+
+/// A two-level mapping (unshifted, shifted) for keycodes `min..=max`
+pub fn two_level_mapping(
+    min_keycode: u8,
+    max_keycode: u8,
+    mut keysyms: impl FnMut(u8) -> (u32, u32),
+    modifiers: &ModifierKeys,
+) -> KeyboardMapping {
+    let mut mapping = KeyboardMapping {
+        min_keycode,
+        keysyms_per_keycode: 2,
+        keycodes_per_modifier: 2,
+        modifiers: modifiers.concat(),
+        ..Default::default()
+    };
+    for keycode in min_keycode..=max_keycode {
+        let (normal, shifted) = keysyms(keycode);
+        mapping.keysyms.extend([normal, shifted]);
+    }
+    mapping
+}
+
+/// The keysym a character types: Latin-1 characters are their own keysym,
+/// others are 0x01000000 + their code point
+pub fn char_keysym(c: char) -> u32 {
+    match c as u32 {
+        0x08 => 0xff08, // BackSpace
+        0x09 => 0xff09, // Tab
+        0x0d => 0xff0d, // Return
+        0x1b => 0xff1b, // Escape
+        0x7f => 0xffff, // Delete
+        code @ (0x20..=0x7e | 0xa0..=0xff) => code,
+        code => 0x0100_0000 | code,
+    }
+}
+
+/// The shifted keysym of a key on a US keyboard
+pub fn us_shifted_keysym(keysym: u32) -> u32 {
+    match keysym {
+        // Lowercase letters -> uppercase
+        0x61..=0x7a => keysym - 0x20, // 'a'-'z' -> 'A'-'Z'
+        // Number row shifted symbols
+        0x31 => 0x21, // '1' -> '!'
+        0x32 => 0x40, // '2' -> '@'
+        0x33 => 0x23, // '3' -> '#'
+        0x34 => 0x24, // '4' -> '$'
+        0x35 => 0x25, // '5' -> '%'
+        0x36 => 0x5e, // '6' -> '^'
+        0x37 => 0x26, // '7' -> '&'
+        0x38 => 0x2a, // '8' -> '*'
+        0x39 => 0x28, // '9' -> '('
+        0x30 => 0x29, // '0' -> ')'
+        0x2d => 0x5f, // '-' -> '_'
+        0x3d => 0x2b, // '=' -> '+'
+        0x5b => 0x7b, // '[' -> '{'
+        0x5d => 0x7d, // ']' -> '}'
+        0x5c => 0x7c, // '\' -> '|'
+        0x3b => 0x3a, // ';' -> ':'
+        0x27 => 0x22, // ''' -> '"'
+        0x60 => 0x7e, // '`' -> '~'
+        0x2c => 0x3c, // ',' -> '<'
+        0x2e => 0x3e, // '.' -> '>'
+        0x2f => 0x3f, // '/' -> '?'
+        // For other keys, shifted is the same
+        _ => keysym,
+    }
+}
+
+/// Modifier keys of the macOS keycodes (after the +8 offset)
+const MACOS_MODIFIERS: ModifierKeys = [
+    [64, 68], // Shift: kVK_Shift, kVK_RightShift
+    [65, 0],  // Lock: kVK_CapsLock
+    [67, 70], // Control: kVK_Control, kVK_RightControl
+    [66, 69], // Mod1: kVK_Option, kVK_RightOption
+    [0, 0],
+    [0, 0],
+    [63, 62], // Mod4: kVK_Command, kVK_RightCommand
+    [0, 0],
+];
+
+/// The mapping of macOS virtual key codes (offset by 8), US layout
+pub fn macos_mapping() -> KeyboardMapping {
+    two_level_mapping(
+        8,
+        255,
+        |keycode| {
+            let keysym = macos_keycode_to_keysym(keycode);
+            (keysym, us_shifted_keysym(keysym))
+        },
+        &MACOS_MODIFIERS,
+    )
+}
+
+/// Map macOS keycode (after +8 offset) to X11 keysym
+/// macOS keycodes are not sequential - this provides the correct mapping
+pub fn macos_keycode_to_keysym(keycode: u8) -> u32 {
+    // macOS keycode + 8 = our keycode, so subtract 8 to get macOS keycode
+    let mac_keycode = keycode.saturating_sub(8);
+
+    // macOS virtual key codes to X11 keysyms
+    // Reference: Carbon/HIToolbox/Events.h (kVK_* constants)
+    match mac_keycode {
+        // Letters (QWERTY layout)
+        0 => 0x61,  // kVK_ANSI_A -> 'a'
+        1 => 0x73,  // kVK_ANSI_S -> 's'
+        2 => 0x64,  // kVK_ANSI_D -> 'd'
+        3 => 0x66,  // kVK_ANSI_F -> 'f'
+        4 => 0x68,  // kVK_ANSI_H -> 'h'
+        5 => 0x67,  // kVK_ANSI_G -> 'g'
+        6 => 0x7a,  // kVK_ANSI_Z -> 'z'
+        7 => 0x78,  // kVK_ANSI_X -> 'x'
+        8 => 0x63,  // kVK_ANSI_C -> 'c'
+        9 => 0x76,  // kVK_ANSI_V -> 'v'
+        11 => 0x62, // kVK_ANSI_B -> 'b'
+        12 => 0x71, // kVK_ANSI_Q -> 'q'
+        13 => 0x77, // kVK_ANSI_W -> 'w'
+        14 => 0x65, // kVK_ANSI_E -> 'e'
+        15 => 0x72, // kVK_ANSI_R -> 'r'
+        16 => 0x79, // kVK_ANSI_Y -> 'y'
+        17 => 0x74, // kVK_ANSI_T -> 't'
+        18 => 0x31, // kVK_ANSI_1 -> '1'
+        19 => 0x32, // kVK_ANSI_2 -> '2'
+        20 => 0x33, // kVK_ANSI_3 -> '3'
+        21 => 0x34, // kVK_ANSI_4 -> '4'
+        22 => 0x36, // kVK_ANSI_6 -> '6'
+        23 => 0x35, // kVK_ANSI_5 -> '5'
+        24 => 0x3d, // kVK_ANSI_Equal -> '='
+        25 => 0x39, // kVK_ANSI_9 -> '9'
+        26 => 0x37, // kVK_ANSI_7 -> '7'
+        27 => 0x2d, // kVK_ANSI_Minus -> '-'
+        28 => 0x38, // kVK_ANSI_8 -> '8'
+        29 => 0x30, // kVK_ANSI_0 -> '0'
+        30 => 0x5d, // kVK_ANSI_RightBracket -> ']'
+        31 => 0x6f, // kVK_ANSI_O -> 'o'
+        32 => 0x75, // kVK_ANSI_U -> 'u'
+        33 => 0x5b, // kVK_ANSI_LeftBracket -> '['
+        34 => 0x69, // kVK_ANSI_I -> 'i'
+        35 => 0x70, // kVK_ANSI_P -> 'p'
+        37 => 0x6c, // kVK_ANSI_L -> 'l'
+        38 => 0x6a, // kVK_ANSI_J -> 'j'
+        39 => 0x27, // kVK_ANSI_Quote -> '''
+        40 => 0x6b, // kVK_ANSI_K -> 'k'
+        41 => 0x3b, // kVK_ANSI_Semicolon -> ';'
+        42 => 0x5c, // kVK_ANSI_Backslash -> '\'
+        43 => 0x2c, // kVK_ANSI_Comma -> ','
+        44 => 0x2f, // kVK_ANSI_Slash -> '/'
+        45 => 0x6e, // kVK_ANSI_N -> 'n'
+        46 => 0x6d, // kVK_ANSI_M -> 'm'
+        47 => 0x2e, // kVK_ANSI_Period -> '.'
+        50 => 0x60, // kVK_ANSI_Grave -> '`'
+
+        // Special keys
+        36 => 0xff0d, // kVK_Return -> XK_Return
+        48 => 0xff09, // kVK_Tab -> XK_Tab
+        49 => 0x20,   // kVK_Space -> ' '
+        51 => 0xff08, // kVK_Delete (backspace) -> XK_BackSpace
+        53 => 0xff1b, // kVK_Escape -> XK_Escape
+
+        // Arrow keys
+        123 => 0xff51, // kVK_LeftArrow -> XK_Left
+        124 => 0xff53, // kVK_RightArrow -> XK_Right
+        125 => 0xff54, // kVK_DownArrow -> XK_Down
+        126 => 0xff52, // kVK_UpArrow -> XK_Up
+
+        // Function keys
+        122 => 0xffbe, // kVK_F1 -> XK_F1
+        120 => 0xffbf, // kVK_F2 -> XK_F2
+        99 => 0xffc0,  // kVK_F3 -> XK_F3
+        118 => 0xffc1, // kVK_F4 -> XK_F4
+        96 => 0xffc2,  // kVK_F5 -> XK_F5
+        97 => 0xffc3,  // kVK_F6 -> XK_F6
+        98 => 0xffc4,  // kVK_F7 -> XK_F7
+        100 => 0xffc5, // kVK_F8 -> XK_F8
+        101 => 0xffc6, // kVK_F9 -> XK_F9
+        109 => 0xffc7, // kVK_F10 -> XK_F10
+        103 => 0xffc8, // kVK_F11 -> XK_F11
+        111 => 0xffc9, // kVK_F12 -> XK_F12
+
+        // Modifier keys
+        56 => 0xffe1, // kVK_Shift -> XK_Shift_L
+        60 => 0xffe2, // kVK_RightShift -> XK_Shift_R
+        58 => 0xffe9, // kVK_Option -> XK_Alt_L
+        61 => 0xffea, // kVK_RightOption -> XK_Alt_R
+        59 => 0xffe3, // kVK_Control -> XK_Control_L
+        62 => 0xffe4, // kVK_RightControl -> XK_Control_R
+        55 => 0xffeb, // kVK_Command -> XK_Super_L
+        54 => 0xffec, // kVK_RightCommand -> XK_Super_R
+        57 => 0xffe5, // kVK_CapsLock -> XK_Caps_Lock
+
+        // Keypad
+        65 => 0xffae, // kVK_ANSI_KeypadDecimal -> XK_KP_Decimal
+        67 => 0xffaa, // kVK_ANSI_KeypadMultiply -> XK_KP_Multiply
+        69 => 0xffab, // kVK_ANSI_KeypadPlus -> XK_KP_Add
+        71 => 0xff7f, // kVK_ANSI_KeypadClear -> XK_Num_Lock
+        75 => 0xffaf, // kVK_ANSI_KeypadDivide -> XK_KP_Divide
+        76 => 0xff8d, // kVK_ANSI_KeypadEnter -> XK_KP_Enter
+        78 => 0xffad, // kVK_ANSI_KeypadMinus -> XK_KP_Subtract
+        81 => 0xffbd, // kVK_ANSI_KeypadEquals -> XK_KP_Equal
+        82 => 0xffb0, // kVK_ANSI_Keypad0 -> XK_KP_0
+        83 => 0xffb1, // kVK_ANSI_Keypad1 -> XK_KP_1
+        84 => 0xffb2, // kVK_ANSI_Keypad2 -> XK_KP_2
+        85 => 0xffb3, // kVK_ANSI_Keypad3 -> XK_KP_3
+        86 => 0xffb4, // kVK_ANSI_Keypad4 -> XK_KP_4
+        87 => 0xffb5, // kVK_ANSI_Keypad5 -> XK_KP_5
+        88 => 0xffb6, // kVK_ANSI_Keypad6 -> XK_KP_6
+        89 => 0xffb7, // kVK_ANSI_Keypad7 -> XK_KP_7
+        91 => 0xffb8, // kVK_ANSI_Keypad8 -> XK_KP_8
+        92 => 0xffb9, // kVK_ANSI_Keypad9 -> XK_KP_9
+
+        // Navigation keys
+        115 => 0xff50, // kVK_Home -> XK_Home
+        116 => 0xff55, // kVK_PageUp -> XK_Page_Up
+        117 => 0xffff, // kVK_ForwardDelete -> XK_Delete
+        119 => 0xff57, // kVK_End -> XK_End
+        121 => 0xff56, // kVK_PageDown -> XK_Page_Down
+
+        // Unknown key
+        _ => 0, // NoSymbol
+    }
+}
+
+/// Modifier keys of Windows virtual-key codes
+pub const WINDOWS_MODIFIERS: ModifierKeys = [
+    [0x10, 0],    // Shift: VK_SHIFT
+    [0x14, 0],    // Lock: VK_CAPITAL
+    [0x11, 0],    // Control: VK_CONTROL
+    [0x12, 0],    // Mod1: VK_MENU
+    [0x90, 0],    // Mod2: VK_NUMLOCK
+    [0, 0],       // Mod3
+    [0x5b, 0x5c], // Mod4: VK_LWIN, VK_RWIN
+    [0, 0],       // Mod5
+];
+
+/// The keysym of a Windows virtual-key code for keys that do not type a
+/// character; None for character keys, whose keysyms depend on the layout
+pub fn windows_vk_keysym(vk: u8) -> Option<u32> {
+    let keysym = match vk {
+        0x08 => 0xff08,                             // VK_BACK -> XK_BackSpace
+        0x09 => 0xff09,                             // VK_TAB -> XK_Tab
+        0x0c => 0xff0b,                             // VK_CLEAR -> XK_Clear
+        0x0d => 0xff0d,                             // VK_RETURN -> XK_Return
+        0x10 => 0xffe1,                             // VK_SHIFT -> XK_Shift_L
+        0x11 => 0xffe3,                             // VK_CONTROL -> XK_Control_L
+        0x12 => 0xffe9,                             // VK_MENU -> XK_Alt_L
+        0x13 => 0xff13,                             // VK_PAUSE -> XK_Pause
+        0x14 => 0xffe5,                             // VK_CAPITAL -> XK_Caps_Lock
+        0x1b => 0xff1b,                             // VK_ESCAPE -> XK_Escape
+        0x20 => 0x20,                               // VK_SPACE -> XK_space
+        0x21 => 0xff55,                             // VK_PRIOR -> XK_Page_Up
+        0x22 => 0xff56,                             // VK_NEXT -> XK_Page_Down
+        0x23 => 0xff57,                             // VK_END -> XK_End
+        0x24 => 0xff50,                             // VK_HOME -> XK_Home
+        0x25 => 0xff51,                             // VK_LEFT -> XK_Left
+        0x26 => 0xff52,                             // VK_UP -> XK_Up
+        0x27 => 0xff53,                             // VK_RIGHT -> XK_Right
+        0x28 => 0xff54,                             // VK_DOWN -> XK_Down
+        0x2c => 0xff61,                             // VK_SNAPSHOT -> XK_Print
+        0x2d => 0xff63,                             // VK_INSERT -> XK_Insert
+        0x2e => 0xffff,                             // VK_DELETE -> XK_Delete
+        0x5b => 0xffeb,                             // VK_LWIN -> XK_Super_L
+        0x5c => 0xffec,                             // VK_RWIN -> XK_Super_R
+        0x5d => 0xff67,                             // VK_APPS -> XK_Menu
+        0x60..=0x69 => 0xffb0 + (vk - 0x60) as u32, // VK_NUMPAD0-9 -> XK_KP_0-9
+        0x6a => 0xffaa,                             // VK_MULTIPLY -> XK_KP_Multiply
+        0x6b => 0xffab,                             // VK_ADD -> XK_KP_Add
+        0x6c => 0xffac,                             // VK_SEPARATOR -> XK_KP_Separator
+        0x6d => 0xffad,                             // VK_SUBTRACT -> XK_KP_Subtract
+        0x6e => 0xffae,                             // VK_DECIMAL -> XK_KP_Decimal
+        0x6f => 0xffaf,                             // VK_DIVIDE -> XK_KP_Divide
+        0x70..=0x87 => 0xffbe + (vk - 0x70) as u32, // VK_F1-F24 -> XK_F1-F24
+        0x90 => 0xff7f,                             // VK_NUMLOCK -> XK_Num_Lock
+        0x91 => 0xff14,                             // VK_SCROLL -> XK_Scroll_Lock
+        0xa0 => 0xffe1,                             // VK_LSHIFT -> XK_Shift_L
+        0xa1 => 0xffe2,                             // VK_RSHIFT -> XK_Shift_R
+        0xa2 => 0xffe3,                             // VK_LCONTROL -> XK_Control_L
+        0xa3 => 0xffe4,                             // VK_RCONTROL -> XK_Control_R
+        0xa4 => 0xffe9,                             // VK_LMENU -> XK_Alt_L
+        0xa5 => 0xffea,                             // VK_RMENU -> XK_Alt_R
+        _ => return None,
+    };
+    Some(keysym)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_macos_mapping() {
+        let mapping = macos_mapping();
+        // kVK_ANSI_A is macOS keycode 0
+        assert_eq!(mapping.keysyms(8), &[0x61, 0x41]);
+        assert_eq!(mapping.keysyms(8 + 18), &[0x31, 0x21]);
+        assert!(mapping.keysyms(7).is_empty());
+        assert_eq!(&mapping.modifiers[0..2], &[64, 68]);
+
+        assert_eq!(char_keysym('\u{e9}'), 0xe9);
+        assert_eq!(char_keysym('\u{20ac}'), 0x0100_20ac);
+    }
+}
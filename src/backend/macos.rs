@@ -185,6 +185,14 @@ extern "C" {
 
     fn macos_backend_flush(handle: BackendHandle) -> i32;
 
+    fn macos_backend_set_clip(
+        handle: BackendHandle,
+        is_window: i32,
+        drawable_id: i32,
+        rects: *const i32,
+        count: i32,
+    ) -> i32;
+
     fn macos_backend_draw_text(
         handle: BackendHandle,
         is_window: i32,
@@ -226,12 +234,27 @@ extern "C" {
 
     // Cursor operations
     fn macos_backend_create_cursor(handle: BackendHandle, cursor_type: i32) -> i32;
+    fn macos_backend_create_image_cursor(
+        handle: BackendHandle,
+        width: i32,
+        height: i32,
+        x_hot: i32,
+        y_hot: i32,
+        data: *const u8,
+    ) -> i32;
+    fn macos_backend_create_animated_cursor(
+        handle: BackendHandle,
+        frame_ids: *const i32,
+        delays: *const u32,
+        count: i32,
+    ) -> i32;
     fn macos_backend_free_cursor(handle: BackendHandle, cursor_id: i32) -> i32;
     fn macos_backend_set_window_cursor(
         handle: BackendHandle,
         window_id: i32,
         cursor_id: i32,
     ) -> i32;
+    fn macos_backend_warp_pointer(handle: BackendHandle, x: i32, y: i32) -> i32;
 }
 
 /// Window data stored per-window
@@ -268,9 +291,13 @@ pub struct MacOSBackend {
 
     /// Pixmap handle mapping (pixmap ID -> Swift pixmap ID)
     pixmaps: HashMap<usize, i32>,
+    /// Pixmap sizes, for software raster operations
+    pixmap_sizes: HashMap<usize, (u16, u16)>,
 
     /// Cursor handle mapping (cursor ID -> Swift cursor ID)
     cursors: HashMap<usize, i32>,
+    /// Swift cursors of the frames of animated cursors, freed with them
+    cursor_frames: HashMap<usize, Vec<i32>>,
 
     /// Next resource ID to allocate
     next_resource_id: usize,
@@ -294,7 +321,9 @@ impl MacOSBackend {
             screen_height_mm: 285,
             windows: HashMap::new(),
             pixmaps: HashMap::new(),
+            pixmap_sizes: HashMap::new(),
             cursors: HashMap::new(),
+            cursor_frames: HashMap::new(),
             next_resource_id: 1,
             event_queue: Vec::new(),
             debug: false,
@@ -332,6 +361,51 @@ impl MacOSBackend {
         (r, g, b)
     }
 
+    /// Set the drawable's clip to the GC's clip rectangles (count -1 removes it)
+    unsafe fn apply_clip(
+        &self,
+        is_window: i32,
+        drawable_id: i32,
+        x_offset: i16,
+        y_offset: i16,
+        gc: &BackendGC,
+    ) {
+        match gc.clip_in_drawable() {
+            Some(rects) => {
+                let flat: Vec<i32> = rects
+                    .iter()
+                    .flat_map(|r| {
+                        [
+                            (r.x + x_offset) as i32,
+                            (r.y + y_offset) as i32,
+                            r.width as i32,
+                            r.height as i32,
+                        ]
+                    })
+                    .collect();
+                macos_backend_set_clip(
+                    self.handle,
+                    is_window,
+                    drawable_id,
+                    flat.as_ptr(),
+                    rects.len() as i32,
+                );
+            }
+            None => {
+                macos_backend_set_clip(self.handle, is_window, drawable_id, std::ptr::null(), -1);
+            }
+        }
+    }
+
+    /// Size of a drawable in pixels
+    fn drawable_size(&self, drawable: BackendDrawable) -> BackendResult<(u16, u16)> {
+        let size = match drawable {
+            BackendDrawable::Window(w) => self.windows.get(&w.0).map(|d| (d.width, d.height)),
+            BackendDrawable::Pixmap(p) => self.pixmap_sizes.get(&p).copied(),
+        };
+        size.ok_or_else(|| format!("Invalid drawable: {:?}", drawable).into())
+    }
+
     /// Get drawable ID from BackendDrawable
     /// Returns (is_window, swift_id, x_offset, y_offset)
     /// For child windows, returns parent's swift_id and child's position as offset
@@ -674,8 +748,34 @@ impl Backend for MacOSBackend {
         width: u16,
         height: u16,
     ) -> BackendResult<()> {
+        if gc.line_style != LineStyle::Solid {
+            let (x2, y2) = (
+                x.saturating_add(width as i16),
+                y.saturating_add(height as i16),
+            );
+            let outline = [
+                Point { x, y },
+                Point { x: x2, y },
+                Point { x: x2, y: y2 },
+                Point { x, y: y2 },
+                Point { x, y },
+            ];
+            return self.draw_dashed_lines(drawable, gc, &outline);
+        }
+
+        if raster::needs_software_fill(gc) {
+            let size = self.drawable_size(drawable)?;
+            let (x, y) = (x as i32, y as i32);
+            let corners = [(x, y), (x + width as i32, y + height as i32)];
+            let bounds = raster::bounds_of(&corners, gc.line_width as i32 + 1);
+            return raster::draw_with_function(self, drawable, size, gc, bounds, |b, d, g| {
+                b.draw_rectangle(d, g, x as i16, y as i16, width, height)
+            });
+        }
+
         unsafe {
             let (is_window, drawable_id, x_offset, y_offset) = self.get_drawable_id(drawable)?;
+            self.apply_clip(is_window, drawable_id, x_offset, y_offset, gc);
 
             let (r, g, b) = Self::color_to_rgb(gc.foreground);
             let line_width = if gc.line_width == 0 {
@@ -711,8 +811,22 @@ impl Backend for MacOSBackend {
         width: u16,
         height: u16,
     ) -> BackendResult<()> {
+        if raster::needs_software_fill(gc) {
+            let size = self.drawable_size(drawable)?;
+            let bounds = (
+                x as i32,
+                y as i32,
+                x as i32 + width as i32,
+                y as i32 + height as i32,
+            );
+            return raster::draw_with_function(self, drawable, size, gc, bounds, |b, d, g| {
+                b.fill_rectangle(d, g, x, y, width, height)
+            });
+        }
+
         unsafe {
             let (is_window, drawable_id, x_offset, y_offset) = self.get_drawable_id(drawable)?;
+            self.apply_clip(is_window, drawable_id, x_offset, y_offset, gc);
 
             let (r, g, b) = Self::color_to_rgb(gc.foreground);
 
@@ -743,8 +857,23 @@ impl Backend for MacOSBackend {
         x2: i16,
         y2: i16,
     ) -> BackendResult<()> {
+        if gc.line_style != LineStyle::Solid {
+            let line = [Point { x: x1, y: y1 }, Point { x: x2, y: y2 }];
+            return self.draw_dashed_lines(drawable, gc, &line);
+        }
+
+        if raster::needs_software_fill(gc) {
+            let size = self.drawable_size(drawable)?;
+            let ends = [(x1 as i32, y1 as i32), (x2 as i32, y2 as i32)];
+            let bounds = raster::bounds_of(&ends, gc.line_width as i32 + 1);
+            return raster::draw_with_function(self, drawable, size, gc, bounds, |b, d, g| {
+                b.draw_line(d, g, x1, y1, x2, y2)
+            });
+        }
+
         unsafe {
             let (is_window, drawable_id, x_offset, y_offset) = self.get_drawable_id(drawable)?;
+            self.apply_clip(is_window, drawable_id, x_offset, y_offset, gc);
 
             let (r, g, b) = Self::color_to_rgb(gc.foreground);
             let line_width = if gc.line_width == 0 {
@@ -791,9 +920,25 @@ impl Backend for MacOSBackend {
         x: i16,
         y: i16,
         text: &str,
+        bounds: Rectangle,
     ) -> BackendResult<()> {
+        if raster::needs_software_fill(gc) {
+            // Native glyphs may overhang the measured cells a little
+            let size = self.drawable_size(drawable)?;
+            let area = (
+                bounds.x as i32 - 2,
+                bounds.y as i32 - 2,
+                bounds.x as i32 + bounds.width as i32 + 2,
+                bounds.y as i32 + bounds.height as i32 + 2,
+            );
+            return raster::draw_with_function(self, drawable, size, gc, area, |b, d, g| {
+                b.draw_text(d, g, x, y, text, bounds)
+            });
+        }
+
         unsafe {
             let (is_window, drawable_id, x_offset, y_offset) = self.get_drawable_id(drawable)?;
+            self.apply_clip(is_window, drawable_id, x_offset, y_offset, gc);
             let (r, g, b) = Self::color_to_rgb(gc.foreground);
             let text_cstr = CString::new(text).unwrap_or_else(|_| CString::new("").unwrap());
 
@@ -819,8 +964,25 @@ impl Backend for MacOSBackend {
         gc: &BackendGC,
         arcs: &[crate::protocol::Arc],
     ) -> BackendResult<()> {
+        if gc.line_style != LineStyle::Solid {
+            for arc in arcs {
+                self.draw_dashed_lines(drawable, gc, &dash::arc_points(arc))?;
+            }
+            return Ok(());
+        }
+
+        if raster::needs_software_fill(gc) {
+            let size = self.drawable_size(drawable)?;
+            let corners = raster::arc_corners(arcs);
+            let bounds = raster::bounds_of(&corners, gc.line_width as i32 + 1);
+            return raster::draw_with_function(self, drawable, size, gc, bounds, |b, d, g| {
+                b.draw_arcs(d, g, arcs)
+            });
+        }
+
         unsafe {
             let (is_window, drawable_id, x_offset, y_offset) = self.get_drawable_id(drawable)?;
+            self.apply_clip(is_window, drawable_id, x_offset, y_offset, gc);
             let (r, g, b) = Self::color_to_rgb(gc.foreground);
             let line_width = if gc.line_width == 0 {
                 1.0
@@ -856,8 +1018,17 @@ impl Backend for MacOSBackend {
         gc: &BackendGC,
         arcs: &[crate::protocol::Arc],
     ) -> BackendResult<()> {
+        if raster::needs_software_fill(gc) {
+            let size = self.drawable_size(drawable)?;
+            let bounds = raster::bounds_of(&raster::arc_corners(arcs), 1);
+            return raster::draw_with_function(self, drawable, size, gc, bounds, |b, d, g| {
+                b.fill_arcs(d, g, arcs)
+            });
+        }
+
         unsafe {
             let (is_window, drawable_id, x_offset, y_offset) = self.get_drawable_id(drawable)?;
+            self.apply_clip(is_window, drawable_id, x_offset, y_offset, gc);
             let (r, g, b) = Self::color_to_rgb(gc.foreground);
 
             for arc in arcs {
@@ -887,8 +1058,18 @@ impl Backend for MacOSBackend {
         gc: &BackendGC,
         points: &[crate::protocol::Point],
     ) -> BackendResult<()> {
+        if raster::needs_software_fill(gc) {
+            let size = self.drawable_size(drawable)?;
+            let coords: Vec<(i32, i32)> = points.iter().map(|p| (p.x as i32, p.y as i32)).collect();
+            let bounds = raster::bounds_of(&coords, 1);
+            return raster::draw_with_function(self, drawable, size, gc, bounds, |b, d, g| {
+                b.fill_polygon(d, g, points)
+            });
+        }
+
         unsafe {
             let (is_window, drawable_id, x_offset, y_offset) = self.get_drawable_id(drawable)?;
+            self.apply_clip(is_window, drawable_id, x_offset, y_offset, gc);
             let (r, g, b) = Self::color_to_rgb(gc.foreground);
 
             // Convert points to flat array of i32, applying child window offset
@@ -916,7 +1097,7 @@ impl Backend for MacOSBackend {
         &mut self,
         src: BackendDrawable,
         dst: BackendDrawable,
-        _gc: &BackendGC,
+        gc: &BackendGC,
         src_x: i16,
         src_y: i16,
         width: u16,
@@ -924,11 +1105,25 @@ impl Backend for MacOSBackend {
         dst_x: i16,
         dst_y: i16,
     ) -> BackendResult<()> {
+        if raster::needs_emulation(gc) {
+            let (src_size, dst_size) = (self.drawable_size(src)?, self.drawable_size(dst)?);
+            return raster::copy_with_function(
+                self, src, src_size, dst, dst_size, gc, src_x, src_y, width, height, dst_x, dst_y,
+            );
+        }
+
         unsafe {
             let (src_is_window, src_drawable_id, src_x_offset, src_y_offset) =
                 self.get_drawable_id(src)?;
             let (dst_is_window, dst_drawable_id, dst_x_offset, dst_y_offset) =
                 self.get_drawable_id(dst)?;
+            self.apply_clip(
+                dst_is_window,
+                dst_drawable_id,
+                dst_x_offset,
+                dst_y_offset,
+                gc,
+            );
 
             let result = macos_backend_copy_area(
                 self.handle,
@@ -963,6 +1158,7 @@ impl Backend for MacOSBackend {
             let id = self.next_resource_id;
             self.next_resource_id += 1;
             self.pixmaps.insert(id, swift_id);
+            self.pixmap_sizes.insert(id, (width, height));
 
             Ok(id)
         }
@@ -970,6 +1166,7 @@ impl Backend for MacOSBackend {
 
     fn free_pixmap(&mut self, pixmap: usize) -> BackendResult<()> {
         unsafe {
+            self.pixmap_sizes.remove(&pixmap);
             if let Some(swift_id) = self.pixmaps.remove(&pixmap) {
                 macos_backend_free_pixmap(self.handle, swift_id);
             }
@@ -980,7 +1177,7 @@ impl Backend for MacOSBackend {
     fn put_image(
         &mut self,
         drawable: BackendDrawable,
-        _gc: &BackendGC,
+        gc: &BackendGC,
         width: u16,
         height: u16,
         dst_x: i16,
@@ -989,8 +1186,26 @@ impl Backend for MacOSBackend {
         format: u8,
         data: &[u8],
     ) -> BackendResult<()> {
+        // Depth 24 and 32 ZPixmaps carry 32 bits per pixel
+        if raster::needs_emulation(gc) && format == 2 && depth >= 24 {
+            let size = self.drawable_size(drawable)?;
+            let pixels = raster::decode_pixels(depth, data, width as usize, height as usize);
+            let count = (width as usize * height as usize).min(pixels.len());
+            return raster::put_with_function(
+                self,
+                drawable,
+                size,
+                gc,
+                dst_x,
+                dst_y,
+                width,
+                &pixels[..count],
+            );
+        }
+
         unsafe {
             let (is_window, drawable_id, x_offset, y_offset) = self.get_drawable_id(drawable)?;
+            self.apply_clip(is_window, drawable_id, x_offset, y_offset, gc);
 
             let result = macos_backend_put_image(
                 self.handle,
@@ -1110,8 +1325,65 @@ impl Backend for MacOSBackend {
         }
     }
 
+    fn create_image_cursor(&mut self, frames: &[CursorFrame]) -> BackendResult<BackendCursor> {
+        if frames.is_empty() {
+            return Err("Cursor has no frames".into());
+        }
+        let free_all = |handle, ids: &[i32]| {
+            for &id in ids {
+                unsafe {
+                    macos_backend_free_cursor(handle, id);
+                }
+            }
+        };
+        let mut frame_ids = Vec::with_capacity(frames.len());
+        for frame in frames {
+            let data: Vec<u8> = frame.pixels.iter().flat_map(|p| p.to_le_bytes()).collect();
+            let swift_cursor_id = unsafe {
+                macos_backend_create_image_cursor(
+                    self.handle,
+                    frame.width as i32,
+                    frame.height as i32,
+                    frame.x_hot as i32,
+                    frame.y_hot as i32,
+                    data.as_ptr(),
+                )
+            };
+            if swift_cursor_id <= 0 {
+                free_all(self.handle, &frame_ids);
+                return Err("Failed to create cursor".into());
+            }
+            frame_ids.push(swift_cursor_id);
+        }
+
+        let id = self.next_resource_id;
+        self.next_resource_id += 1;
+        if let [swift_cursor_id] = frame_ids[..] {
+            self.cursors.insert(id, swift_cursor_id);
+            return Ok(BackendCursor(id));
+        }
+        // The Swift side steps through the frames on a timer
+        let delays: Vec<u32> = frames.iter().map(|frame| frame.delay).collect();
+        let swift_cursor_id = unsafe {
+            macos_backend_create_animated_cursor(
+                self.handle,
+                frame_ids.as_ptr(),
+                delays.as_ptr(),
+                frame_ids.len() as i32,
+            )
+        };
+        if swift_cursor_id <= 0 {
+            free_all(self.handle, &frame_ids);
+            return Err("Failed to create animated cursor".into());
+        }
+        self.cursors.insert(id, swift_cursor_id);
+        self.cursor_frames.insert(id, frame_ids);
+        Ok(BackendCursor(id))
+    }
+
     fn free_cursor(&mut self, cursor: BackendCursor) -> BackendResult<()> {
-        if let Some(swift_cursor_id) = self.cursors.remove(&cursor.0) {
+        let frames = self.cursor_frames.remove(&cursor.0).unwrap_or_default();
+        for swift_cursor_id in self.cursors.remove(&cursor.0).into_iter().chain(frames) {
             unsafe {
                 macos_backend_free_cursor(self.handle, swift_cursor_id);
             }
@@ -1142,6 +1414,13 @@ impl Backend for MacOSBackend {
         }
     }
 
+    fn warp_pointer(&mut self, x: i16, y: i16) -> BackendResult<()> {
+        unsafe {
+            macos_backend_warp_pointer(self.handle, x as i32, y as i32);
+        }
+        Ok(())
+    }
+
     fn flush(&mut self) -> BackendResult<()> {
         unsafe {
             macos_backend_flush(self.handle);
@@ -1263,6 +1542,11 @@ impl Backend for MacOSBackend {
             },
         ])
     }
+
+    fn keyboard_mapping(&mut self) -> BackendResult<KeyboardMapping> {
+        // The Swift side reports macOS virtual key codes + 8
+        Ok(keymap::macos_mapping())
+    }
 }
 
 impl MacOSBackend {
@@ -9,6 +9,17 @@
 mod r#trait;
 pub use r#trait::*;
 
+// Software line dashing and raster operations shared by backends
+pub mod dash;
+pub mod raster;
+
+// Software RENDER compositing and shape rasterization shared by backends
+pub mod composite;
+pub mod trapezoids;
+
+// Keyboard layout tables for backends without a host keymap
+pub mod keymap;
+
 // Null backend (always available for testing)
 pub mod null;
 
@@ -152,6 +152,7 @@ impl Backend for NullBackend {
         _x: i16,
         _y: i16,
         _text: &str,
+        _bounds: Rectangle,
     ) -> BackendResult<()> {
         Ok(())
     }
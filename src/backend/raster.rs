@@ -0,0 +1,511 @@
+//! Software raster operations
+//!
+//! Combines pixels with the 16 GC functions and a plane mask, and emulates
+//! them, along with tiled and stippled fills, for backends whose drawing APIs
+//! can only copy a solid color. An operation is drawn into a scratch pixmap
+//! to find the pixels it covers; those are combined with the destination in
+//! software and written back.
+//!
+//! Pixels are exchanged as ZPixmap data of the drawable's depth: 32 bits
+//! (0x00RRGGBB, little endian) from depth 24 up, bytes at depth 8 and
+//! LSB-first bits at depth 1.
+
+use super::{Backend, BackendDrawable, BackendGC, BackendResult, GCPattern};
+use crate::protocol::{Arc, FillStyle, GCFunction, Rectangle};
+
+/// Bits of a pixel that hold color
+const PIXEL_BITS: u32 = 0x00ff_ffff;
+
+/// Combine a source pixel into a destination pixel
+///
+/// Only the bits set in `plane_mask` are modified.
+pub fn combine(function: GCFunction, plane_mask: u32, src: u32, dst: u32) -> u32 {
+    let result = match function {
+        GCFunction::Clear => 0,
+        GCFunction::And => src & dst,
+        GCFunction::AndReverse => src & !dst,
+        GCFunction::Copy => src,
+        GCFunction::AndInverted => !src & dst,
+        GCFunction::NoOp => dst,
+        GCFunction::Xor => src ^ dst,
+        GCFunction::Or => src | dst,
+        GCFunction::Nor => !(src | dst),
+        GCFunction::Equiv => !src ^ dst,
+        GCFunction::Invert => !dst,
+        GCFunction::OrReverse => src | !dst,
+        GCFunction::CopyInverted => !src,
+        GCFunction::OrInverted => !src | dst,
+        GCFunction::Nand => !(src & dst),
+        GCFunction::Set => !0,
+    };
+    ((result & plane_mask) | (dst & !plane_mask)) & PIXEL_BITS
+}
+
+/// Whether drawing with `gc` does anything other than copy the source
+pub fn needs_emulation(gc: &BackendGC) -> bool {
+    gc.function != GCFunction::Copy || gc.plane_mask & PIXEL_BITS != PIXEL_BITS
+}
+
+/// Whether filling or drawing lines with `gc` needs `draw_with_function`,
+/// either for its function and plane mask or for a patterned fill style
+pub fn needs_software_fill(gc: &BackendGC) -> bool {
+    needs_emulation(gc) || gc.fill_pattern().is_some()
+}
+
+/// Draw an operation with `gc`'s function, plane mask and fill style
+///
+/// `draw` renders the operation into the drawable it is given with a plain,
+/// solid Copy GC. `bounds` (x1, y1, x2, y2) must cover every pixel it touches;
+/// a generous estimate only costs time. `size` is the drawable's size.
+pub fn draw_with_function<B, F>(
+    backend: &mut B,
+    drawable: BackendDrawable,
+    size: (u16, u16),
+    gc: &BackendGC,
+    bounds: (i32, i32, i32, i32),
+    draw: F,
+) -> BackendResult<()>
+where
+    B: Backend + ?Sized,
+    F: FnOnce(&mut B, BackendDrawable, &BackendGC) -> BackendResult<()>,
+{
+    let Some(area) = clamp(bounds, size) else {
+        return Ok(());
+    };
+    let scratch = backend.create_pixmap(
+        (area.x as u16).saturating_add(area.width),
+        (area.y as u16).saturating_add(area.height),
+        24,
+    )?;
+    let scratch = BackendDrawable::Pixmap(scratch);
+
+    let result = (|| {
+        // Coverage: the operation drawn on black, clipped like the real
+        // thing; green marks pixels drawn with the foreground and red the
+        // odd dashes of DoubleDash lines, drawn with the background
+        let mut clear_gc = copy_gc(gc);
+        clear_gc.clip_rectangles = None;
+        clear_gc.foreground = 0;
+        backend.fill_rectangle(scratch, &clear_gc, area.x, area.y, area.width, area.height)?;
+        let mut mask_gc = copy_gc(gc);
+        mask_gc.foreground = 0x00_ff00;
+        mask_gc.background = 0xff_0000;
+        draw(backend, scratch, &mask_gc)?;
+
+        let (_, coverage) = read_pixels(backend, scratch, area)?;
+        let (depth, mut pixels) = read_pixels(backend, drawable, area)?;
+        let pattern = match gc.fill_pattern() {
+            Some(pattern) => Some((pattern, read_pattern(backend, pattern)?)),
+            None => None,
+        };
+        for (i, (pixel, covered)) in pixels.iter_mut().zip(coverage).enumerate() {
+            // Anti-aliased edges count once they are mostly covered
+            let odd_dash = if (covered >> 8) & 0xff >= 0x80 {
+                false
+            } else if (covered >> 16) & 0xff >= 0x80 {
+                true
+            } else {
+                continue;
+            };
+            let x = area.x as i32 + (i % area.width as usize) as i32;
+            let y = area.y as i32 + (i / area.width as usize) as i32;
+            let pattern = pattern
+                .as_ref()
+                .map(|(info, data)| (*info, data.as_slice()));
+            if let Some(source) = fill_source(gc, pattern, x, y, odd_dash) {
+                *pixel = combine(gc.function, gc.plane_mask, source, *pixel);
+            }
+        }
+        write_pixels(backend, drawable, gc, area, depth, &pixels)
+    })();
+
+    if let BackendDrawable::Pixmap(id) = scratch {
+        backend.free_pixmap(id)?;
+    }
+    result
+}
+
+/// Combine a block of source pixels (`width` per row) into the drawable at
+/// `x`, `y` with `gc`'s function, plane mask and clip
+#[allow(clippy::too_many_arguments)]
+pub fn put_with_function<B: Backend + ?Sized>(
+    backend: &mut B,
+    drawable: BackendDrawable,
+    size: (u16, u16),
+    gc: &BackendGC,
+    x: i16,
+    y: i16,
+    width: u16,
+    source: &[u32],
+) -> BackendResult<()> {
+    if width == 0 {
+        return Ok(());
+    }
+    let height = (source.len() / width as usize) as i32;
+    let bounds = (
+        x as i32,
+        y as i32,
+        x as i32 + width as i32,
+        y as i32 + height,
+    );
+    let Some(area) = clamp(bounds, size) else {
+        return Ok(());
+    };
+
+    let (depth, mut pixels) = read_pixels(backend, drawable, area)?;
+    let (skip_x, skip_y) = ((area.x - x) as usize, (area.y - y) as usize);
+    for row in 0..area.height as usize {
+        for col in 0..area.width as usize {
+            let src = source[(row + skip_y) * width as usize + col + skip_x];
+            let pixel = &mut pixels[row * area.width as usize + col];
+            *pixel = combine(gc.function, gc.plane_mask, src, *pixel);
+        }
+    }
+    write_pixels(backend, drawable, gc, area, depth, &pixels)
+}
+
+/// Copy an area between drawables with `gc`'s function, plane mask and clip
+#[allow(clippy::too_many_arguments)]
+pub fn copy_with_function<B: Backend + ?Sized>(
+    backend: &mut B,
+    src: BackendDrawable,
+    src_size: (u16, u16),
+    dst: BackendDrawable,
+    dst_size: (u16, u16),
+    gc: &BackendGC,
+    src_x: i16,
+    src_y: i16,
+    width: u16,
+    height: u16,
+    dst_x: i16,
+    dst_y: i16,
+) -> BackendResult<()> {
+    // Only the part of the source that exists can be read
+    let bounds = (
+        src_x as i32,
+        src_y as i32,
+        src_x as i32 + width as i32,
+        src_y as i32 + height as i32,
+    );
+    let Some(area) = clamp(bounds, src_size) else {
+        return Ok(());
+    };
+    let (_, source) = read_pixels(backend, src, area)?;
+    put_with_function(
+        backend,
+        dst,
+        dst_size,
+        gc,
+        dst_x + (area.x - src_x),
+        dst_y + (area.y - src_y),
+        area.width,
+        &source,
+    )
+}
+
+/// Bounding box (x1, y1, x2, y2) of `points`, grown by `pad` on every side
+pub fn bounds_of(points: &[(i32, i32)], pad: i32) -> (i32, i32, i32, i32) {
+    let mut bounds = (i32::MAX, i32::MAX, i32::MIN, i32::MIN);
+    for &(x, y) in points {
+        bounds.0 = bounds.0.min(x - pad);
+        bounds.1 = bounds.1.min(y - pad);
+        bounds.2 = bounds.2.max(x + pad + 1);
+        bounds.3 = bounds.3.max(y + pad + 1);
+    }
+    bounds
+}
+
+/// Corners of the bounding boxes of `arcs`, for `bounds_of`
+pub fn arc_corners(arcs: &[Arc]) -> Vec<(i32, i32)> {
+    arcs.iter()
+        .flat_map(|a| {
+            let (x, y) = (a.x as i32, a.y as i32);
+            [(x, y), (x + a.width as i32, y + a.height as i32)]
+        })
+        .collect()
+}
+
+/// Decode `width` x `height` ZPixmap data of `depth` into pixels
+pub fn decode_pixels(depth: u8, data: &[u8], width: usize, height: usize) -> Vec<u32> {
+    match depth {
+        1 => {
+            let stride = width.div_ceil(32) * 4;
+            (0..width * height)
+                .map(|i| {
+                    let (x, y) = (i % width, i / width);
+                    data.get(y * stride + x / 8)
+                        .map_or(0, |b| (*b as u32 >> (x % 8)) & 1)
+                })
+                .collect()
+        }
+        8 => {
+            let stride = width.div_ceil(4) * 4;
+            (0..width * height)
+                .map(|i| {
+                    data.get(i / width * stride + i % width)
+                        .map_or(0, |&v| v as u32)
+                })
+                .collect()
+        }
+        _ => data
+            .chunks_exact(4)
+            .map(|p| u32::from_le_bytes([p[0], p[1], p[2], 0]))
+            .collect(),
+    }
+}
+
+/// Encode pixels, `width` per row, as ZPixmap data of `depth`
+///
+/// Pixels from depth 24 up are written opaque.
+pub fn encode_pixels(depth: u8, pixels: &[u32], width: usize) -> Vec<u8> {
+    let height = pixels.len().checked_div(width).unwrap_or(0);
+    match depth {
+        1 => {
+            let stride = width.div_ceil(32) * 4;
+            let mut data = vec![0u8; stride * height];
+            for (i, p) in pixels.iter().enumerate() {
+                if p & 1 != 0 {
+                    data[i / width * stride + i % width / 8] |= 1 << (i % width % 8);
+                }
+            }
+            data
+        }
+        8 => {
+            let stride = width.div_ceil(4) * 4;
+            let mut data = vec![0u8; stride * height];
+            for (i, p) in pixels.iter().enumerate() {
+                data[i / width * stride + i % width] = *p as u8;
+            }
+            data
+        }
+        _ => pixels
+            .iter()
+            .flat_map(|p| (p | 0xff00_0000).to_le_bytes())
+            .collect(),
+    }
+}
+
+/// A copy of `gc` that draws plainly
+fn copy_gc(gc: &BackendGC) -> BackendGC {
+    let mut copy = gc.clone();
+    copy.function = GCFunction::Copy;
+    copy.plane_mask = !0;
+    copy.fill_style = FillStyle::Solid;
+    copy
+}
+
+/// Source pixel a tile or stipple gives drawable pixel (`x`, `y`), or None
+/// where a stipple leaves the destination untouched
+fn pattern_source(gc: &BackendGC, info: &GCPattern, data: &[u32], x: i32, y: i32) -> Option<u32> {
+    let px = (x - gc.ts_x_origin as i32).rem_euclid(info.width as i32) as usize;
+    let py = (y - gc.ts_y_origin as i32).rem_euclid(info.height as i32) as usize;
+    let value = data[py * info.width as usize + px];
+    match gc.fill_style {
+        FillStyle::Tiled => Some(value),
+        FillStyle::OpaqueStippled if value == 0 => Some(gc.background),
+        _ if value == 0 => None,
+        _ => Some(gc.foreground),
+    }
+}
+
+/// Source pixel a fill gives drawable pixel (`x`, `y`), or None where it
+/// leaves the destination untouched
+///
+/// Odd dashes of DoubleDash lines take the background where even dashes take
+/// the foreground: solid fills use it instead of the foreground, and
+/// stippled fills paint it through the stipple.
+fn fill_source(
+    gc: &BackendGC,
+    pattern: Option<(&GCPattern, &[u32])>,
+    x: i32,
+    y: i32,
+    odd_dash: bool,
+) -> Option<u32> {
+    match pattern {
+        None if odd_dash => Some(gc.background),
+        None => Some(gc.foreground),
+        Some((info, data)) => {
+            let source = pattern_source(gc, info, data, x, y)?;
+            match gc.fill_style {
+                FillStyle::Stippled if odd_dash => Some(gc.background),
+                _ => Some(source),
+            }
+        }
+    }
+}
+
+/// Read a tile or stipple, row by row; stipple pixels are 0 or 1
+///
+/// Backends may keep depth-1 pixmaps as LSB-first bitmaps or at full depth.
+fn read_pattern<B: Backend + ?Sized>(
+    backend: &mut B,
+    pattern: &GCPattern,
+) -> BackendResult<Vec<u32>> {
+    let (width, height) = (pattern.width as usize, pattern.height as usize);
+    if width == 0 || height == 0 {
+        return Err("Empty tile or stipple".into());
+    }
+    let (depth, _, data) = backend.get_image(
+        BackendDrawable::Pixmap(pattern.pixmap),
+        0,
+        0,
+        pattern.width,
+        pattern.height,
+        !0,
+        2,
+    )?;
+    let mut pixels = decode_pixels(depth, &data, width, height);
+    if pixels.len() < width * height {
+        return Err("Short image read during raster operation".into());
+    }
+    if pattern.depth == 1 {
+        for pixel in &mut pixels {
+            *pixel = (*pixel != 0) as u32;
+        }
+    }
+    Ok(pixels)
+}
+
+/// Intersect (x1, y1, x2, y2) with a drawable of `size`
+pub(super) fn clamp(bounds: (i32, i32, i32, i32), size: (u16, u16)) -> Option<Rectangle> {
+    let (x1, y1) = (bounds.0.max(0), bounds.1.max(0));
+    let x2 = bounds.2.min(size.0 as i32);
+    let y2 = bounds.3.min(size.1 as i32);
+    (x2 > x1 && y2 > y1).then(|| Rectangle {
+        x: x1 as i16,
+        y: y1 as i16,
+        width: (x2 - x1) as u16,
+        height: (y2 - y1) as u16,
+    })
+}
+
+/// Read an area of a drawable, with the depth it is stored at
+fn read_pixels<B: Backend + ?Sized>(
+    backend: &mut B,
+    drawable: BackendDrawable,
+    area: Rectangle,
+) -> BackendResult<(u8, Vec<u32>)> {
+    let (depth, _, data) =
+        backend.get_image(drawable, area.x, area.y, area.width, area.height, !0, 2)?;
+    let (width, height) = (area.width as usize, area.height as usize);
+    let pixels = decode_pixels(depth, &data, width, height);
+    if pixels.len() < width * height {
+        return Err("Short image read during raster operation".into());
+    }
+    Ok((depth, pixels))
+}
+
+/// Write an area back at the depth `read_pixels` gave
+fn write_pixels<B: Backend + ?Sized>(
+    backend: &mut B,
+    drawable: BackendDrawable,
+    gc: &BackendGC,
+    area: Rectangle,
+    depth: u8,
+    pixels: &[u32],
+) -> BackendResult<()> {
+    let data = encode_pixels(depth, pixels, area.width as usize);
+    backend.put_image(
+        drawable,
+        &copy_gc(gc),
+        area.width,
+        area.height,
+        area.x,
+        area.y,
+        depth,
+        2,
+        &data,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_combine_functions() {
+        let (src, dst) = (0x00ff_00f0, 0x0000_ff0f);
+        assert_eq!(combine(GCFunction::Xor, !0, src, dst), 0x00ff_ffff);
+        assert_eq!(combine(GCFunction::Invert, !0, src, dst), 0x00ff_00f0);
+        assert_eq!(combine(GCFunction::AndInverted, !0, src, dst), 0x0000_ff0f);
+        assert_eq!(combine(GCFunction::Set, !0, src, dst), PIXEL_BITS);
+        // Planes outside the mask keep the destination's bits
+        assert_eq!(
+            combine(GCFunction::Clear, 0x0000_00ff, src, dst),
+            0x0000_ff00
+        );
+    }
+
+    #[test]
+    fn test_pattern_source() {
+        let info = GCPattern {
+            pixmap: 0,
+            width: 2,
+            height: 2,
+            depth: 1,
+        };
+        let bits = [1, 0, 0, 1];
+        let mut gc = BackendGC {
+            foreground: 0x00ff_0000,
+            background: 0x0000_00ff,
+            fill_style: FillStyle::Stippled,
+            ts_x_origin: 1,
+            ..Default::default()
+        };
+        // The origin shifts the pattern; clear bits are skipped
+        assert_eq!(pattern_source(&gc, &info, &bits, 1, 0), Some(0x00ff_0000));
+        assert_eq!(pattern_source(&gc, &info, &bits, 0, 0), None);
+        gc.fill_style = FillStyle::OpaqueStippled;
+        assert_eq!(pattern_source(&gc, &info, &bits, 0, 0), Some(0x0000_00ff));
+        // Tiles repeat in both directions, including before the origin
+        gc.fill_style = FillStyle::Tiled;
+        let tile = [10, 20, 30, 40];
+        assert_eq!(pattern_source(&gc, &info, &tile, -1, 3), Some(30));
+    }
+
+    #[test]
+    fn test_odd_dash_source() {
+        let info = GCPattern {
+            pixmap: 0,
+            width: 2,
+            height: 1,
+            depth: 1,
+        };
+        let bits = [1, 0];
+        let mut gc = BackendGC {
+            foreground: 0x00ff_0000,
+            background: 0x0000_00ff,
+            ..Default::default()
+        };
+        // Solid odd dashes take the background
+        assert_eq!(fill_source(&gc, None, 0, 0, false), Some(0x00ff_0000));
+        assert_eq!(fill_source(&gc, None, 0, 0, true), Some(0x0000_00ff));
+        // Stippled ones paint it through the stipple
+        gc.fill_style = FillStyle::Stippled;
+        let stipple = Some((&info, &bits[..]));
+        assert_eq!(fill_source(&gc, stipple, 0, 0, true), Some(0x0000_00ff));
+        assert_eq!(fill_source(&gc, stipple, 1, 0, true), None);
+        // Opaque stipples ignore the dash
+        gc.fill_style = FillStyle::OpaqueStippled;
+        assert_eq!(fill_source(&gc, stipple, 0, 0, true), Some(0x00ff_0000));
+    }
+
+    #[test]
+    fn test_depth_1_pixels() {
+        // Rows are padded to 32 bits, pixels LSB-first
+        let data = [0b0000_0101, 0, 0, 0, 0b0000_0010, 0, 0, 0];
+        let pixels = decode_pixels(1, &data, 3, 2);
+        assert_eq!(pixels, [1, 0, 1, 0, 1, 0]);
+        assert_eq!(encode_pixels(1, &pixels, 3), data);
+
+        // Combined values keep only the low bit
+        let inverted: Vec<u32> = pixels
+            .iter()
+            .map(|&p| combine(GCFunction::Invert, !0, 0, p))
+            .collect();
+        assert_eq!(
+            decode_pixels(1, &encode_pixels(1, &inverted, 3), 3, 2),
+            [0, 1, 0, 1, 0, 1]
+        );
+    }
+}
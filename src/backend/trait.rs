@@ -3,6 +3,8 @@
 //! This module defines the trait that all display backends must implement.
 //! Backends translate X11 operations to native window system operations.
 
+use super::composite::{CompositePicture, Operator};
+use super::dash::Dasher;
 use crate::protocol::*;
 use std::error::Error;
 
@@ -22,6 +24,20 @@ impl BackendCursor {
     pub const NONE: BackendCursor = BackendCursor(0);
 }
 
+/// One image of a cursor, in ARGB
+#[derive(Debug, Clone, PartialEq)]
+pub struct CursorFrame {
+    pub width: u16,
+    pub height: u16,
+    /// Hotspot, relative to the image's top-left corner
+    pub x_hot: u16,
+    pub y_hot: u16,
+    /// Premultiplied ARGB pixels, row by row
+    pub pixels: Vec<u32>,
+    /// How long the frame is shown in an animated cursor, in milliseconds
+    pub delay: u32,
+}
+
 /// Standard X11 cursor shapes (from cursor font glyph indices)
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u16)]
@@ -106,21 +122,96 @@ pub enum StandardCursor {
 }
 
 impl StandardCursor {
+    /// Every shape, in glyph order
+    const ALL: [StandardCursor; 77] = [
+        StandardCursor::XCursor,
+        StandardCursor::Arrow,
+        StandardCursor::BasedArrowDown,
+        StandardCursor::BasedArrowUp,
+        StandardCursor::Boat,
+        StandardCursor::Bogosity,
+        StandardCursor::BottomLeftCorner,
+        StandardCursor::BottomRightCorner,
+        StandardCursor::BottomSide,
+        StandardCursor::BottomTee,
+        StandardCursor::BoxSpiral,
+        StandardCursor::CenterPtr,
+        StandardCursor::Circle,
+        StandardCursor::Clock,
+        StandardCursor::CoffeeMug,
+        StandardCursor::Cross,
+        StandardCursor::CrossReverse,
+        StandardCursor::Crosshair,
+        StandardCursor::DiamondCross,
+        StandardCursor::Dot,
+        StandardCursor::Dotbox,
+        StandardCursor::DoubleArrow,
+        StandardCursor::DraftLarge,
+        StandardCursor::DraftSmall,
+        StandardCursor::DrapedBox,
+        StandardCursor::Exchange,
+        StandardCursor::Fleur,
+        StandardCursor::Gobbler,
+        StandardCursor::Gumby,
+        StandardCursor::Hand1,
+        StandardCursor::Hand2,
+        StandardCursor::Heart,
+        StandardCursor::Icon,
+        StandardCursor::IronCross,
+        StandardCursor::LeftPtr,
+        StandardCursor::LeftSide,
+        StandardCursor::LeftTee,
+        StandardCursor::Leftbutton,
+        StandardCursor::LlAngle,
+        StandardCursor::LrAngle,
+        StandardCursor::Man,
+        StandardCursor::Middlebutton,
+        StandardCursor::Mouse,
+        StandardCursor::Pencil,
+        StandardCursor::Pirate,
+        StandardCursor::Plus,
+        StandardCursor::QuestionArrow,
+        StandardCursor::RightPtr,
+        StandardCursor::RightSide,
+        StandardCursor::RightTee,
+        StandardCursor::Rightbutton,
+        StandardCursor::RtlLogo,
+        StandardCursor::Sailboat,
+        StandardCursor::SbDownArrow,
+        StandardCursor::SbHDoubleArrow,
+        StandardCursor::SbLeftArrow,
+        StandardCursor::SbRightArrow,
+        StandardCursor::SbUpArrow,
+        StandardCursor::SbVDoubleArrow,
+        StandardCursor::Shuttle,
+        StandardCursor::Sizing,
+        StandardCursor::Spider,
+        StandardCursor::Spraycan,
+        StandardCursor::Star,
+        StandardCursor::Target,
+        StandardCursor::Tcross,
+        StandardCursor::TopLeftArrow,
+        StandardCursor::TopLeftCorner,
+        StandardCursor::TopRightCorner,
+        StandardCursor::TopSide,
+        StandardCursor::TopTee,
+        StandardCursor::Trek,
+        StandardCursor::UlAngle,
+        StandardCursor::Umbrella,
+        StandardCursor::UrAngle,
+        StandardCursor::Watch,
+        StandardCursor::Xterm,
+    ];
+
     /// Convert a glyph index from the cursor font to a StandardCursor
+    ///
+    /// Shapes are the even glyphs; each odd glyph is the mask of the one
+    /// before it.
     pub fn from_glyph(glyph: u16) -> Option<StandardCursor> {
-        match glyph {
-            0 => Some(StandardCursor::XCursor),
-            2 => Some(StandardCursor::Arrow),
-            34 => Some(StandardCursor::Crosshair),
-            52 => Some(StandardCursor::Fleur),
-            58 => Some(StandardCursor::Hand1),
-            60 => Some(StandardCursor::Hand2),
-            68 => Some(StandardCursor::LeftPtr),
-            150 => Some(StandardCursor::Watch),
-            152 => Some(StandardCursor::Xterm),
-            // Add more as needed
-            _ => None,
+        if !glyph.is_multiple_of(2) {
+            return None;
         }
+        Self::ALL.get(glyph as usize / 2).copied()
     }
 }
 
@@ -135,6 +226,8 @@ pub enum BackendDrawable {
 #[derive(Debug, Clone)]
 pub struct BackendGC {
     pub function: GCFunction,
+    /// Planes drawing may modify; other bits keep the destination's value
+    pub plane_mask: u32,
     pub foreground: u32,
     pub background: u32,
     pub line_width: u16,
@@ -143,12 +236,77 @@ pub struct BackendGC {
     pub join_style: JoinStyle,
     pub fill_style: FillStyle,
     pub fill_rule: FillRule,
+    pub arc_mode: ArcMode,
+    /// Dash lengths for OnOffDash/DoubleDash lines (on, off, on, ...)
+    pub dashes: Vec<u8>,
+    pub dash_offset: u16,
+    pub subwindow_mode: SubwindowMode,
+    /// Whether CopyArea/CopyPlane report unavailable source areas
+    pub graphics_exposures: bool,
+    /// Clip origin, added to every clip rectangle
+    pub clip_x_origin: i16,
+    pub clip_y_origin: i16,
+    /// Clip rectangles relative to the clip origin, or None to draw unclipped.
+    /// Clip-mask pixmaps are turned into rectangles by the server.
+    pub clip_rectangles: Option<Vec<Rectangle>>,
+    /// Tile for FillTiled
+    pub tile: Option<GCPattern>,
+    /// Depth-1 stipple for FillStippled and FillOpaqueStippled
+    pub stipple: Option<GCPattern>,
+    /// Origin of the tile or stipple
+    pub ts_x_origin: i16,
+    pub ts_y_origin: i16,
+}
+
+/// What a window's border is painted with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WindowBorder {
+    Pixel(u32),
+    /// A tile aligned with the window's origin
+    Pixmap(GCPattern),
+}
+
+/// A pixmap used as a GC tile or stipple
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GCPattern {
+    /// Backend pixmap holding the pattern
+    pub pixmap: usize,
+    pub width: u16,
+    pub height: u16,
+    pub depth: u8,
+}
+
+impl BackendGC {
+    /// The pattern drawing with this GC uses, or None for solid fills
+    pub fn fill_pattern(&self) -> Option<&GCPattern> {
+        match self.fill_style {
+            FillStyle::Solid => None,
+            FillStyle::Tiled => self.tile.as_ref(),
+            FillStyle::Stippled | FillStyle::OpaqueStippled => self.stipple.as_ref(),
+        }
+    }
+
+    /// Clip rectangles in drawable coordinates, or None if unclipped
+    pub fn clip_in_drawable(&self) -> Option<Vec<Rectangle>> {
+        self.clip_rectangles.as_ref().map(|rects| {
+            rects
+                .iter()
+                .map(|r| Rectangle {
+                    x: r.x.saturating_add(self.clip_x_origin),
+                    y: r.y.saturating_add(self.clip_y_origin),
+                    width: r.width,
+                    height: r.height,
+                })
+                .collect()
+        })
+    }
 }
 
 impl Default for BackendGC {
     fn default() -> Self {
         BackendGC {
             function: GCFunction::Copy,
+            plane_mask: !0,
             foreground: 0,
             background: 0xffffff,
             line_width: 0,
@@ -157,6 +315,18 @@ impl Default for BackendGC {
             join_style: JoinStyle::Miter,
             fill_style: FillStyle::Solid,
             fill_rule: FillRule::EvenOdd,
+            arc_mode: ArcMode::PieSlice,
+            dashes: vec![4, 4],
+            dash_offset: 0,
+            subwindow_mode: SubwindowMode::ClipByChildren,
+            graphics_exposures: true,
+            clip_x_origin: 0,
+            clip_y_origin: 0,
+            clip_rectangles: None,
+            tile: None,
+            stipple: None,
+            ts_x_origin: 0,
+            ts_y_origin: 0,
         }
     }
 }
@@ -186,6 +356,28 @@ pub struct WindowConfig {
     pub stack_mode: Option<StackMode>,
 }
 
+/// One of a window's SHAPE shapes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ShapeKind {
+    /// Outline of the window, border included
+    Bounding = 0,
+    /// Area inside the border where contents show
+    Clip = 1,
+    /// Area that receives pointer input
+    Input = 2,
+}
+
+impl ShapeKind {
+    pub fn from_u8(kind: u8) -> Option<Self> {
+        match kind {
+            0 => Some(ShapeKind::Bounding),
+            1 => Some(ShapeKind::Clip),
+            2 => Some(ShapeKind::Input),
+            _ => None,
+        }
+    }
+}
+
 /// Screen information
 #[derive(Debug, Clone)]
 pub struct ScreenInfo {
@@ -255,22 +447,58 @@ pub struct BackendFontInfo {
 
 impl BackendFontInfo {
     /// Generate XLFD name from font properties
+    ///
+    /// Scalable fonts (pixel size 0) get zeros in every size field.
     pub fn generate_xlfd(&self) -> String {
+        let resolution = if self.pixel_size == 0 { 0 } else { 75 };
         format!(
-            "-*-{}-{}-{}-normal--{}-{}-75-75-{}-{}-{}-{}",
-            self.family.to_lowercase().replace(' ', "-"),
+            "-misc-{}-{}-{}-normal--{}-{}-{}-{}-{}-{}-{}-{}",
+            self.family.to_lowercase().replace('-', " "),
             self.weight.to_lowercase(),
             self.slant.to_lowercase(),
             self.pixel_size,
             self.point_size,
+            resolution,
+            resolution,
             if self.char_width > 0 { "m" } else { "p" },
-            self.char_width,
+            if self.pixel_size == 0 {
+                0
+            } else {
+                self.char_width
+            },
             self.registry.to_lowercase(),
             self.encoding.to_lowercase()
         )
     }
 }
 
+/// A keyboard layout: the keysyms of each keycode, and the modifier map
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KeyboardMapping {
+    /// Keycode of the first entry in `keysyms`
+    pub min_keycode: u8,
+    pub keysyms_per_keycode: u8,
+    /// `keysyms_per_keycode` keysyms per keycode from `min_keycode` on;
+    /// 0 is NoSymbol
+    pub keysyms: Vec<u32>,
+    pub keycodes_per_modifier: u8,
+    /// `keycodes_per_modifier` keycodes for each of Shift, Lock, Control
+    /// and Mod1-Mod5; 0 for unused slots
+    pub modifiers: Vec<u8>,
+}
+
+impl KeyboardMapping {
+    /// The keysyms of a keycode; empty if the mapping does not cover it
+    pub fn keysyms(&self, keycode: u8) -> &[u32] {
+        let per = self.keysyms_per_keycode as usize;
+        let Some(index) = keycode.checked_sub(self.min_keycode) else {
+            return &[];
+        };
+        let start = index as usize * per;
+        self.keysyms.get(start..start + per).unwrap_or(&[])
+    }
+}
+
 /// Backend events
 #[derive(Debug, Clone)]
 pub enum BackendEvent {
@@ -358,6 +586,8 @@ pub enum BackendEvent {
         y: i16,
         time: u32,
     },
+    /// The host keyboard layout changed; see `Backend::keyboard_mapping`
+    MappingChanged,
 }
 
 /// The main backend trait
@@ -405,6 +635,55 @@ pub trait Backend: Send {
     /// Set window title
     fn set_window_title(&mut self, window: BackendWindow, title: &str) -> BackendResult<()>;
 
+    /// Move a window under a new parent (None = the backend's top level)
+    fn reparent_window(
+        &mut self,
+        window: BackendWindow,
+        parent: Option<BackendWindow>,
+        x: i16,
+        y: i16,
+    ) -> BackendResult<()> {
+        // Default implementation: no-op (the server tracks the logical parent)
+        let _ = (window, parent, x, y);
+        Ok(())
+    }
+
+    /// Set one of a window's shapes, relative to its origin inside the
+    /// border (None = unshaped)
+    ///
+    /// Backends without shaped windows ignore this; the server still uses
+    /// the shapes for its own hit-testing.
+    fn set_window_shape(
+        &mut self,
+        window: BackendWindow,
+        kind: ShapeKind,
+        rectangles: Option<&[Rectangle]>,
+    ) -> BackendResult<()> {
+        let _ = (window, kind, rectangles);
+        Ok(())
+    }
+
+    /// Set what a window's border is painted with
+    ///
+    /// Backends whose windows have no border area ignore this.
+    fn set_window_border(
+        &mut self,
+        window: BackendWindow,
+        border: WindowBorder,
+    ) -> BackendResult<()> {
+        let _ = (window, border);
+        Ok(())
+    }
+
+    /// Backend window standing in for the X root window, if any
+    ///
+    /// Rooted backends present the whole virtual screen as one native window
+    /// and report input on it; the server then routes that input through its
+    /// own window tree. Rootless backends return None.
+    fn root_window(&self) -> Option<BackendWindow> {
+        None
+    }
+
     // Drawing operations
 
     /// Clear an area of a window
@@ -485,6 +764,9 @@ pub trait Backend: Send {
         gc: &BackendGC,
         points: &[Point],
     ) -> BackendResult<()> {
+        if gc.line_style != LineStyle::Solid {
+            return self.draw_dashed_lines(drawable, gc, points);
+        }
         // Default implementation: draw segments
         for i in 0..points.len().saturating_sub(1) {
             self.draw_line(
@@ -499,6 +781,46 @@ pub trait Backend: Send {
         Ok(())
     }
 
+    /// Draw connected lines with the GC's dash pattern as individual solid
+    /// lines, for backends without native X11 dashes
+    fn draw_dashed_lines(
+        &mut self,
+        drawable: BackendDrawable,
+        gc: &BackendGC,
+        points: &[Point],
+    ) -> BackendResult<()> {
+        let spans = Dasher::new(&gc.dashes, gc.dash_offset).polyline(points);
+        let mut on_gc = gc.clone();
+        on_gc.line_style = LineStyle::Solid;
+        let mut off_gc = on_gc.clone();
+        off_gc.foreground = gc.background;
+
+        // Off dashes first so the caps of on dashes end up on top
+        if gc.line_style == LineStyle::DoubleDash {
+            for span in spans.iter().filter(|s| !s.on) {
+                self.draw_line(
+                    drawable,
+                    &off_gc,
+                    span.from.x,
+                    span.from.y,
+                    span.to.x,
+                    span.to.y,
+                )?;
+            }
+        }
+        for span in spans.iter().filter(|s| s.on) {
+            self.draw_line(
+                drawable,
+                &on_gc,
+                span.from.x,
+                span.from.y,
+                span.to.x,
+                span.to.y,
+            )?;
+        }
+        Ok(())
+    }
+
     /// Draw line segments (each segment is independent: x1, y1, x2, y2)
     fn draw_segments(
         &mut self,
@@ -521,7 +843,9 @@ pub trait Backend: Send {
         points: &[Point],
     ) -> BackendResult<()>;
 
-    /// Draw text
+    /// Draw text in the backend's own font
+    ///
+    /// `bounds` is the box the server measured the string to fill.
     fn draw_text(
         &mut self,
         drawable: BackendDrawable,
@@ -529,6 +853,7 @@ pub trait Backend: Send {
         x: i16,
         y: i16,
         text: &str,
+        bounds: Rectangle,
     ) -> BackendResult<()>;
 
     /// Draw arcs (elliptical arcs)
@@ -555,95 +880,24 @@ pub trait Backend: Send {
         points: &[Point],
     ) -> BackendResult<()>;
 
-    /// Fill trapezoids (RENDER extension)
+    /// Composite `src` through an optional `mask` onto `area` of `dst`
+    /// (RENDER extension)
     ///
-    /// Trapezoids are defined in fixed-point 16.16 format.
-    /// The color is in ARGB format (0xAARRGGBB).
-    fn fill_trapezoids(
+    /// The default implementation uses the shared software compositor,
+    /// which hands plain copies and solid fills to copy_area and
+    /// fill_rectangle.
+    #[allow(clippy::too_many_arguments)]
+    fn composite(
         &mut self,
-        drawable: BackendDrawable,
-        color: u32,
-        trapezoids: &[RenderTrapezoid],
+        op: Operator,
+        src: &CompositePicture,
+        mask: Option<&CompositePicture>,
+        dst: &CompositePicture,
+        src_origin: (i16, i16),
+        mask_origin: (i16, i16),
+        area: Rectangle,
     ) -> BackendResult<()> {
-        log::debug!(
-            "fill_trapezoids: drawable={:?} color=0x{:08x} count={}",
-            drawable,
-            color,
-            trapezoids.len()
-        );
-        // Default implementation: rasterize trapezoids as polygons
-        for trap in trapezoids {
-            // Convert fixed-point to integer (shift right 16 bits)
-            let top = (trap.top >> 16) as i16;
-            let bottom = (trap.bottom >> 16) as i16;
-
-            // Calculate left edge X at top and bottom
-            let left_x_top = interpolate_x(
-                trap.left_x1,
-                trap.left_y1,
-                trap.left_x2,
-                trap.left_y2,
-                trap.top,
-            );
-            let left_x_bottom = interpolate_x(
-                trap.left_x1,
-                trap.left_y1,
-                trap.left_x2,
-                trap.left_y2,
-                trap.bottom,
-            );
-
-            // Calculate right edge X at top and bottom
-            let right_x_top = interpolate_x(
-                trap.right_x1,
-                trap.right_y1,
-                trap.right_x2,
-                trap.right_y2,
-                trap.top,
-            );
-            let right_x_bottom = interpolate_x(
-                trap.right_x1,
-                trap.right_y1,
-                trap.right_x2,
-                trap.right_y2,
-                trap.bottom,
-            );
-
-            // Convert to integer coordinates
-            let x1 = (left_x_top >> 16) as i16;
-            let x2 = (right_x_top >> 16) as i16;
-            let x3 = (right_x_bottom >> 16) as i16;
-            let x4 = (left_x_bottom >> 16) as i16;
-
-            // Create a polygon from the trapezoid
-            let points = vec![
-                Point { x: x1, y: top },
-                Point { x: x2, y: top },
-                Point { x: x3, y: bottom },
-                Point { x: x4, y: bottom },
-            ];
-
-            log::debug!(
-                "fill_trapezoids: polygon ({},{}) ({},{}) ({},{}) ({},{})",
-                x1,
-                top,
-                x2,
-                top,
-                x3,
-                bottom,
-                x4,
-                bottom
-            );
-
-            // Create a temporary GC with the color
-            let gc = BackendGC {
-                foreground: color,
-                ..Default::default()
-            };
-
-            self.fill_polygon(drawable, &gc, &points)?;
-        }
-        Ok(())
+        super::composite::composite(self, op, src, mask, dst, src_origin, mask_origin, area)
     }
 
     /// Copy area from one drawable to another
@@ -737,6 +991,27 @@ pub trait Backend: Send {
         Ok(BackendCursor::NONE)
     }
 
+    /// Create a cursor from ARGB images, animated when there are several
+    ///
+    /// Backends without image cursors use the standard arrow instead.
+    fn create_image_cursor(&mut self, frames: &[CursorFrame]) -> BackendResult<BackendCursor> {
+        let _ = frames;
+        self.create_standard_cursor(StandardCursor::LeftPtr)
+    }
+
+    /// Change the colors of a cursor created from a standard shape
+    ///
+    /// Backends whose standard cursors have fixed colors ignore this.
+    fn recolor_cursor(
+        &mut self,
+        cursor: BackendCursor,
+        foreground: [u16; 3],
+        background: [u16; 3],
+    ) -> BackendResult<()> {
+        let _ = (cursor, foreground, background);
+        Ok(())
+    }
+
     /// Free a cursor
     fn free_cursor(&mut self, _cursor: BackendCursor) -> BackendResult<()> {
         // Default implementation: no-op (most backends use system cursors)
@@ -756,6 +1031,13 @@ pub trait Backend: Send {
         Ok(())
     }
 
+    /// Move the host pointer to a position in screen coordinates
+    fn warp_pointer(&mut self, x: i16, y: i16) -> BackendResult<()> {
+        // Default implementation: no-op
+        let _ = (x, y);
+        Ok(())
+    }
+
     // Event handling
 
     /// Poll for events from the backend
@@ -785,17 +1067,20 @@ pub trait Backend: Send {
         // Backends can override to query real font metrics from the system
         Ok(None)
     }
-}
 
-/// Helper function to interpolate X coordinate along a line at a given Y
-/// All values are in fixed-point 16.16 format
-pub fn interpolate_x(x1: i32, y1: i32, x2: i32, y2: i32, y: i32) -> i32 {
-    if y1 == y2 {
-        return x1;
+    // Keyboard
+
+    /// The host keyboard layout, for the keycodes this backend reports
+    ///
+    /// Backends send `BackendEvent::MappingChanged` when it changes
+    fn keyboard_mapping(&mut self) -> BackendResult<KeyboardMapping> {
+        // Default implementation: no keysyms, no modifiers
+        Ok(KeyboardMapping {
+            min_keycode: 8,
+            keysyms_per_keycode: 1,
+            keysyms: vec![0; 248],
+            keycodes_per_modifier: 1,
+            modifiers: vec![0; 8],
+        })
     }
-    // Linear interpolation: x = x1 + (x2 - x1) * (y - y1) / (y2 - y1)
-    let dy = y2 - y1;
-    let dx = x2 - x1;
-    let t = y - y1;
-    x1 + ((dx as i64 * t as i64) / dy as i64) as i32
 }
@@ -0,0 +1,258 @@
+//! Coverage rasterization of RENDER trapezoids and triangles
+//!
+//! Shapes are rasterized into alpha masks the compositor draws through.
+//! Edges keep their 16.16 fixed-point precision: an A8 mask holds how much
+//! of each pixel the shapes cover, measured on a grid of sub-scanlines with
+//! exact horizontal spans, while an A1 mask holds the pixels whose centers
+//! they cover. Coverage of overlapping shapes adds up.
+
+use super::composite::PictFormat;
+use super::RenderTrapezoid;
+use crate::protocol::Rectangle;
+
+/// Sub-scanlines per pixel row of an A8 mask
+const SUB_SCANLINES: usize = 15;
+
+/// A 16.16 fixed-point value as floating point
+fn fixed(v: i32) -> f64 {
+    v as f64 / 65536.0
+}
+
+/// X of the line through (`x1`, `y1`) and (`x2`, `y2`) at `y`
+fn line_x(x1: i32, y1: i32, x2: i32, y2: i32, y: f64) -> f64 {
+    if y1 == y2 {
+        return fixed(x1);
+    }
+    let t = (y - fixed(y1)) / (fixed(y2) - fixed(y1));
+    fixed(x1) + (fixed(x2) - fixed(x1)) * t
+}
+
+impl RenderTrapezoid {
+    /// The left and right edges' X at `y`
+    fn span(&self, y: f64) -> (f64, f64) {
+        (
+            line_x(self.left_x1, self.left_y1, self.left_x2, self.left_y2, y),
+            line_x(
+                self.right_x1,
+                self.right_y1,
+                self.right_x2,
+                self.right_y2,
+                y,
+            ),
+        )
+    }
+
+    /// Pixel bounds (x1, y1, x2, y2) the trapezoid may touch
+    fn bounds(&self) -> (i32, i32, i32, i32) {
+        let (top, bottom) = (fixed(self.top), fixed(self.bottom));
+        let (left_top, right_top) = self.span(top);
+        let (left_bottom, right_bottom) = self.span(bottom);
+        let clamp = |v: f64| v.clamp(i32::MIN as f64, i32::MAX as f64);
+        (
+            clamp(left_top.min(left_bottom).floor()) as i32,
+            clamp(top.floor()) as i32,
+            clamp(right_top.max(right_bottom).ceil()) as i32,
+            clamp(bottom.ceil()) as i32,
+        )
+    }
+
+    /// A trapezoid from its top and bottom spans (RENDER AddTraps)
+    pub fn from_spans(top: (i32, i32, i32), bottom: (i32, i32, i32)) -> Self {
+        let (top_left, top_right, top_y) = top;
+        let (bottom_left, bottom_right, bottom_y) = bottom;
+        RenderTrapezoid {
+            top: top_y,
+            bottom: bottom_y,
+            left_x1: top_left,
+            left_y1: top_y,
+            left_x2: bottom_left,
+            left_y2: bottom_y,
+            right_x1: top_right,
+            right_y1: top_y,
+            right_x2: bottom_right,
+            right_y2: bottom_y,
+        }
+    }
+
+    /// The trapezoid moved by whole pixels
+    pub fn translate(&self, dx: i32, dy: i32) -> Self {
+        let (dx, dy) = (dx.wrapping_shl(16), dy.wrapping_shl(16));
+        RenderTrapezoid {
+            top: self.top.wrapping_add(dy),
+            bottom: self.bottom.wrapping_add(dy),
+            left_x1: self.left_x1.wrapping_add(dx),
+            left_y1: self.left_y1.wrapping_add(dy),
+            left_x2: self.left_x2.wrapping_add(dx),
+            left_y2: self.left_y2.wrapping_add(dy),
+            right_x1: self.right_x1.wrapping_add(dx),
+            right_y1: self.right_y1.wrapping_add(dy),
+            right_x2: self.right_x2.wrapping_add(dx),
+            right_y2: self.right_y2.wrapping_add(dy),
+        }
+    }
+}
+
+/// Split a triangle of fixed-point points into trapezoids
+pub fn triangle_trapezoids(points: [(i32, i32); 3]) -> Vec<RenderTrapezoid> {
+    let mut points = points;
+    points.sort_by_key(|&(_, y)| y);
+    let [a, b, c] = points;
+    if a.1 == c.1 {
+        return Vec::new();
+    }
+    // The long edge runs from the top to the bottom vertex; the two short
+    // ones lie on the side of the middle vertex
+    let long_x = line_x(a.0, a.1, c.0, c.1, fixed(b.1));
+    let short_left = fixed(b.0) < long_x;
+    let trapezoid = |top: i32, bottom: i32, short: ((i32, i32), (i32, i32))| {
+        let (long, short) = (((a.0, a.1), (c.0, c.1)), short);
+        let (left, right) = if short_left {
+            (short, long)
+        } else {
+            (long, short)
+        };
+        RenderTrapezoid {
+            top,
+            bottom,
+            left_x1: left.0 .0,
+            left_y1: left.0 .1,
+            left_x2: left.1 .0,
+            left_y2: left.1 .1,
+            right_x1: right.0 .0,
+            right_y1: right.0 .1,
+            right_x2: right.1 .0,
+            right_y2: right.1 .1,
+        }
+    };
+    let mut trapezoids = Vec::new();
+    if a.1 < b.1 {
+        trapezoids.push(trapezoid(a.1, b.1, (a, b)));
+    }
+    if b.1 < c.1 {
+        trapezoids.push(trapezoid(b.1, c.1, (b, c)));
+    }
+    trapezoids
+}
+
+/// Rasterize trapezoids into an A1 or A8 mask, within `clip`
+///
+/// Returns the mask's area and its pixels (alpha in the top byte), or None
+/// if nothing inside `clip` is covered.
+pub fn rasterize(
+    trapezoids: &[RenderTrapezoid],
+    format: PictFormat,
+    clip: Rectangle,
+) -> Option<(Rectangle, Vec<u32>)> {
+    let mut extents = (i32::MAX, i32::MAX, i32::MIN, i32::MIN);
+    for trapezoid in trapezoids.iter().filter(|t| t.top < t.bottom) {
+        let bounds = trapezoid.bounds();
+        extents.0 = extents.0.min(bounds.0);
+        extents.1 = extents.1.min(bounds.1);
+        extents.2 = extents.2.max(bounds.2);
+        extents.3 = extents.3.max(bounds.3);
+    }
+    let x1 = extents.0.max(clip.x as i32);
+    let y1 = extents.1.max(clip.y as i32);
+    let x2 = extents.2.min(clip.x as i32 + clip.width as i32);
+    let y2 = extents.3.min(clip.y as i32 + clip.height as i32);
+    if x2 <= x1 || y2 <= y1 {
+        return None;
+    }
+    let (width, height) = ((x2 - x1) as usize, (y2 - y1) as usize);
+
+    // A1 samples each pixel's center; A8 a grid of sub-scanlines
+    let (samples, coverage_of): (usize, fn(f64, f64, f64) -> f64) = match format {
+        PictFormat::A1 => (1, |left, right, x| {
+            let center = x + 0.5;
+            if left <= center && center < right {
+                1.0
+            } else {
+                0.0
+            }
+        }),
+        _ => (SUB_SCANLINES, |left, right, x| {
+            (right.min(x + 1.0) - left.max(x)).max(0.0)
+        }),
+    };
+    let mut coverage = vec![0.0f64; width * height];
+    for (row, line) in coverage.chunks_exact_mut(width).enumerate() {
+        let py = (y1 + row as i32) as f64;
+        for sample in 0..samples {
+            let y = py + (sample as f64 + 0.5) / samples as f64;
+            for trapezoid in trapezoids {
+                if y < fixed(trapezoid.top) || y >= fixed(trapezoid.bottom) {
+                    continue;
+                }
+                let (left, right) = trapezoid.span(y);
+                if left >= right {
+                    continue;
+                }
+                let first = (left.floor() as i32).max(x1);
+                let last = (right.ceil() as i32).min(x2);
+                for x in first..last {
+                    line[(x - x1) as usize] += coverage_of(left, right, x as f64);
+                }
+            }
+        }
+        for value in line.iter_mut() {
+            *value /= samples as f64;
+        }
+    }
+
+    let pixels = coverage
+        .into_iter()
+        .map(|value| {
+            let alpha = (value.min(1.0) * 255.0).round() as u32;
+            if format == PictFormat::A1 && alpha != 0 {
+                0xff00_0000
+            } else {
+                alpha << 24
+            }
+        })
+        .collect();
+    let area = Rectangle {
+        x: x1 as i16,
+        y: y1 as i16,
+        width: width as u16,
+        height: height as u16,
+    };
+    Some((area, pixels))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_coverage() {
+        let clip = Rectangle {
+            x: 0,
+            y: 0,
+            width: 100,
+            height: 100,
+        };
+        // A square from (1.5, 0) to (3.5, 2): the edge pixels are half covered
+        let square =
+            RenderTrapezoid::from_spans((0x18000, 0x38000, 0), (0x18000, 0x38000, 0x20000));
+        let (area, pixels) = rasterize(&[square], PictFormat::A8, clip).unwrap();
+        assert_eq!((area.x, area.y, area.width, area.height), (1, 0, 3, 2));
+        assert_eq!(pixels[..3], [0x8000_0000, 0xff00_0000, 0x8000_0000]);
+        // A1 takes the pixels whose centers are inside
+        let (_, pixels) = rasterize(&[square], PictFormat::A1, clip).unwrap();
+        assert_eq!(pixels[..3], [0xff00_0000, 0xff00_0000, 0]);
+
+        // A right triangle with legs of 4 covers half of its bounding box
+        let triangle = triangle_trapezoids([(0, 0), (0x40000, 0x40000), (0, 0x40000)]);
+        assert_eq!(triangle.len(), 1);
+        let (area, pixels) = rasterize(&triangle, PictFormat::A8, clip).unwrap();
+        assert_eq!((area.width, area.height), (4, 4));
+        let total: u32 = pixels.iter().map(|p| p >> 24).sum();
+        assert!((total as i32 - 8 * 255).abs() < 30);
+        // Overlapping shapes add up, and everything outside the clip is cut
+        let (_, pixels) = rasterize(&[square, square], PictFormat::A8, clip).unwrap();
+        assert_eq!(pixels[0], 0xff00_0000);
+        let clip = Rectangle { width: 2, ..clip };
+        let (area, _) = rasterize(&[square], PictFormat::A8, clip).unwrap();
+        assert_eq!((area.x, area.width), (1, 1));
+    }
+}
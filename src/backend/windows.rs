@@ -6,7 +6,7 @@
 
 use super::*;
 use crate::protocol::*;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::ffi::OsStr;
 use std::iter::once;
 use std::mem;
@@ -17,7 +17,8 @@ use windows_sys::Win32::Graphics::Gdi::*;
 use windows_sys::Win32::System::LibraryLoader::GetModuleHandleW;
 use windows_sys::Win32::UI::Controls::WM_MOUSELEAVE;
 use windows_sys::Win32::UI::Input::KeyboardAndMouse::{
-    TrackMouseEvent, TME_LEAVE, TRACKMOUSEEVENT,
+    GetKeyboardLayout, MapVirtualKeyExW, ToUnicodeEx, TrackMouseEvent, HKL, MAPVK_VK_TO_VSC,
+    TME_LEAVE, TRACKMOUSEEVENT,
 };
 use windows_sys::Win32::UI::WindowsAndMessaging::*;
 
@@ -59,6 +60,14 @@ struct WindowData {
     cursor: HCURSOR,
 }
 
+/// An animated cursor: its frames with their delays, and the thread timer
+/// stepping through them
+struct CursorAnimation {
+    frames: Vec<(HCURSOR, u32)>,
+    current: usize,
+    timer: usize,
+}
+
 pub struct WindowsBackend {
     /// Whether the backend has been initialized
     initialized: bool,
@@ -79,9 +88,15 @@ pub struct WindowsBackend {
 
     /// Pixmap handle mapping (X11 pixmap ID -> (HDC, HBITMAP))
     pixmaps: HashMap<usize, (HDC, HBITMAP)>,
+    /// Pixmap sizes, for software raster operations
+    pixmap_sizes: HashMap<usize, (u16, u16)>,
 
     /// Cursor handle mapping (Backend cursor ID -> HCURSOR)
     cursors: HashMap<usize, HCURSOR>,
+    /// Cursors created from images, which must be destroyed when freed
+    image_cursors: HashSet<usize>,
+    /// Animated cursors, whose entry in `cursors` is the frame shown
+    cursor_animations: HashMap<usize, CursorAnimation>,
 
     /// Next resource ID to allocate
     next_resource_id: usize,
@@ -89,6 +104,9 @@ pub struct WindowsBackend {
     /// Event queue
     event_queue: Vec<BackendEvent>,
 
+    /// Keyboard layout the last keyboard mapping was read from
+    keyboard_layout: HKL,
+
     /// Debug mode flag
     debug: bool,
 }
@@ -105,13 +123,96 @@ impl WindowsBackend {
             screen_height_mm: 285,
             windows: HashMap::new(),
             pixmaps: HashMap::new(),
+            pixmap_sizes: HashMap::new(),
             cursors: HashMap::new(),
+            image_cursors: HashSet::new(),
+            cursor_animations: HashMap::new(),
             next_resource_id: 1,
             event_queue: Vec::new(),
+            keyboard_layout: 0,
             debug: false,
         }
     }
 
+    /// Create a cursor showing one frame of an image cursor
+    fn frame_cursor(frame: &CursorFrame) -> BackendResult<HCURSOR> {
+        // Icon color bitmaps take straight, not premultiplied, alpha
+        let pixels: Vec<u32> = frame
+            .pixels
+            .iter()
+            .map(|&pixel| {
+                let alpha = pixel >> 24;
+                if alpha == 0 || alpha == 0xff {
+                    return pixel;
+                }
+                let channel =
+                    |shift: u32| ((pixel >> shift & 0xff) * 255 / alpha).min(255) << shift;
+                alpha << 24 | channel(16) | channel(8) | channel(0)
+            })
+            .collect();
+        // The alpha channel decides what shows, so the AND mask is all clear;
+        // monochrome rows are padded to 16 bits
+        let mask = vec![0u8; (frame.width as usize).div_ceil(16) * 2 * frame.height as usize];
+        unsafe {
+            let color = CreateBitmap(
+                frame.width as i32,
+                frame.height as i32,
+                1,
+                32,
+                pixels.as_ptr() as *const _,
+            );
+            let mask = CreateBitmap(
+                frame.width as i32,
+                frame.height as i32,
+                1,
+                1,
+                mask.as_ptr() as *const _,
+            );
+            let info = ICONINFO {
+                fIcon: 0,
+                xHotspot: frame.x_hot as u32,
+                yHotspot: frame.y_hot as u32,
+                hbmMask: mask,
+                hbmColor: color,
+            };
+            let hcursor = CreateIconIndirect(&info);
+            DeleteObject(color as isize);
+            DeleteObject(mask as isize);
+            if hcursor == 0 {
+                return Err("Failed to create cursor".into());
+            }
+            Ok(hcursor)
+        }
+    }
+
+    /// Show the next frame of the animated cursor driven by `timer`, in
+    /// every window using it, and wait that frame's delay
+    fn advance_cursor_animation(&mut self, timer: usize) {
+        let Some((&id, animation)) = self
+            .cursor_animations
+            .iter_mut()
+            .find(|(_, animation)| animation.timer == timer)
+        else {
+            return;
+        };
+        let previous = animation.frames[animation.current].0;
+        animation.current = (animation.current + 1) % animation.frames.len();
+        let (hcursor, delay) = animation.frames[animation.current];
+        unsafe {
+            // Reusing the ID of a thread timer resets its interval
+            SetTimer(0, timer, delay.max(1), None);
+            self.cursors.insert(id, hcursor);
+            for data in self.windows.values_mut() {
+                if data.cursor == previous {
+                    data.cursor = hcursor;
+                    if data.mouse_inside {
+                        SetCursor(hcursor);
+                    }
+                }
+            }
+        }
+    }
+
     /// Map StandardCursor to Windows system cursor ID
     fn standard_cursor_to_idc(cursor: StandardCursor) -> *const u16 {
         match cursor {
@@ -223,6 +324,15 @@ impl WindowsBackend {
             .ok_or_else(|| format!("Invalid window ID: {}", window.0))
     }
 
+    /// Size of a drawable in pixels
+    fn drawable_size(&self, drawable: BackendDrawable) -> BackendResult<(u16, u16)> {
+        let size = match drawable {
+            BackendDrawable::Window(w) => self.windows.get(&w.0).map(|d| (d.width, d.height)),
+            BackendDrawable::Pixmap(p) => self.pixmap_sizes.get(&p).copied(),
+        };
+        size.ok_or_else(|| format!("Invalid drawable: {:?}", drawable).into())
+    }
+
     /// Get device context for drawable (returns backing store DC for windows)
     fn get_dc(&self, drawable: BackendDrawable) -> Result<HDC, String> {
         match drawable {
@@ -238,15 +348,38 @@ impl WindowsBackend {
         }
     }
 
+    /// Get device context for drawable with the GC's clip region selected
+    unsafe fn get_gc_dc(&self, drawable: BackendDrawable, gc: &BackendGC) -> Result<HDC, String> {
+        let hdc = self.get_dc(drawable)?;
+        match gc.clip_in_drawable() {
+            Some(rects) => {
+                let region = CreateRectRgn(0, 0, 0, 0);
+                for rect in rects {
+                    let part = CreateRectRgn(
+                        rect.x as i32,
+                        rect.y as i32,
+                        rect.x as i32 + rect.width as i32,
+                        rect.y as i32 + rect.height as i32,
+                    );
+                    CombineRgn(region, region, part, RGN_OR);
+                    DeleteObject(part as isize);
+                }
+                // SelectClipRgn copies the region
+                SelectClipRgn(hdc, region);
+                DeleteObject(region as isize);
+            }
+            None => {
+                SelectClipRgn(hdc, 0);
+            }
+        }
+        Ok(hdc)
+    }
+
     /// Create GDI pen from GC
     unsafe fn create_pen(&self, gc: &BackendGC) -> HPEN {
+        // Dashed lines are split into solid dashes before they get here
         let (r, g, b) = color_to_rgb(gc.foreground);
-        let style = match gc.line_style {
-            LineStyle::Solid => PS_SOLID,
-            LineStyle::OnOffDash => PS_DASH,
-            LineStyle::DoubleDash => PS_DASH,
-        };
-        CreatePen(style as i32, gc.line_width as i32, rgb(r, g, b))
+        CreatePen(PS_SOLID as i32, gc.line_width as i32, rgb(r, g, b))
     }
 
     /// Create GDI brush from GC
@@ -552,6 +685,7 @@ impl Backend for WindowsBackend {
         unsafe {
             let data = self.get_window_data(window)?;
             let hdc = data.mem_dc;
+            SelectClipRgn(hdc, 0);
 
             let rect = RECT {
                 left: x as i32,
@@ -576,8 +710,33 @@ impl Backend for WindowsBackend {
         width: u16,
         height: u16,
     ) -> BackendResult<()> {
+        if gc.line_style != LineStyle::Solid {
+            let (x2, y2) = (
+                x.saturating_add(width as i16),
+                y.saturating_add(height as i16),
+            );
+            let outline = [
+                Point { x, y },
+                Point { x: x2, y },
+                Point { x: x2, y: y2 },
+                Point { x, y: y2 },
+                Point { x, y },
+            ];
+            return self.draw_dashed_lines(drawable, gc, &outline);
+        }
+
+        if raster::needs_software_fill(gc) {
+            let size = self.drawable_size(drawable)?;
+            let (x, y) = (x as i32, y as i32);
+            let corners = [(x, y), (x + width as i32, y + height as i32)];
+            let bounds = raster::bounds_of(&corners, gc.line_width as i32 + 1);
+            return raster::draw_with_function(self, drawable, size, gc, bounds, |b, d, g| {
+                b.draw_rectangle(d, g, x as i16, y as i16, width, height)
+            });
+        }
+
         unsafe {
-            let hdc = self.get_dc(drawable)?;
+            let hdc = self.get_gc_dc(drawable, gc)?;
 
             let pen = self.create_pen(gc);
             let old_pen = SelectObject(hdc, pen as isize);
@@ -610,8 +769,21 @@ impl Backend for WindowsBackend {
         width: u16,
         height: u16,
     ) -> BackendResult<()> {
+        if raster::needs_software_fill(gc) {
+            let size = self.drawable_size(drawable)?;
+            let bounds = (
+                x as i32,
+                y as i32,
+                x as i32 + width as i32,
+                y as i32 + height as i32,
+            );
+            return raster::draw_with_function(self, drawable, size, gc, bounds, |b, d, g| {
+                b.fill_rectangle(d, g, x, y, width, height)
+            });
+        }
+
         unsafe {
-            let hdc = self.get_dc(drawable)?;
+            let hdc = self.get_gc_dc(drawable, gc)?;
 
             let (r, g, b) = color_to_rgb(gc.foreground);
             let color = rgb(r, g, b);
@@ -650,8 +822,22 @@ impl Backend for WindowsBackend {
         x2: i16,
         y2: i16,
     ) -> BackendResult<()> {
+        if gc.line_style != LineStyle::Solid {
+            let line = [Point { x: x1, y: y1 }, Point { x: x2, y: y2 }];
+            return self.draw_dashed_lines(drawable, gc, &line);
+        }
+
+        if raster::needs_software_fill(gc) {
+            let size = self.drawable_size(drawable)?;
+            let ends = [(x1 as i32, y1 as i32), (x2 as i32, y2 as i32)];
+            let bounds = raster::bounds_of(&ends, gc.line_width as i32 + 1);
+            return raster::draw_with_function(self, drawable, size, gc, bounds, |b, d, g| {
+                b.draw_line(d, g, x1, y1, x2, y2)
+            });
+        }
+
         unsafe {
-            let hdc = self.get_dc(drawable)?;
+            let hdc = self.get_gc_dc(drawable, gc)?;
 
             let pen = self.create_pen(gc);
             let old_pen = SelectObject(hdc, pen as isize);
@@ -672,8 +858,17 @@ impl Backend for WindowsBackend {
         gc: &BackendGC,
         points: &[Point],
     ) -> BackendResult<()> {
+        if raster::needs_software_fill(gc) {
+            let size = self.drawable_size(drawable)?;
+            let coords: Vec<(i32, i32)> = points.iter().map(|p| (p.x as i32, p.y as i32)).collect();
+            let bounds = raster::bounds_of(&coords, 1);
+            return raster::draw_with_function(self, drawable, size, gc, bounds, |b, d, g| {
+                b.draw_points(d, g, points)
+            });
+        }
+
         unsafe {
-            let hdc = self.get_dc(drawable)?;
+            let hdc = self.get_gc_dc(drawable, gc)?;
             let (r, g, b) = color_to_rgb(gc.foreground);
             let color = rgb(r, g, b);
 
@@ -692,14 +887,31 @@ impl Backend for WindowsBackend {
         x: i16,
         y: i16,
         text: &str,
+        bounds: Rectangle,
     ) -> BackendResult<()> {
+        if raster::needs_software_fill(gc) {
+            // Native glyphs may overhang the measured cells a little
+            let size = self.drawable_size(drawable)?;
+            let area = (
+                bounds.x as i32 - 2,
+                bounds.y as i32 - 2,
+                bounds.x as i32 + bounds.width as i32 + 2,
+                bounds.y as i32 + bounds.height as i32 + 2,
+            );
+            return raster::draw_with_function(self, drawable, size, gc, area, |b, d, g| {
+                b.draw_text(d, g, x, y, text, bounds)
+            });
+        }
+
         unsafe {
-            let hdc = self.get_dc(drawable)?;
+            let hdc = self.get_gc_dc(drawable, gc)?;
             let text_wide = to_wide_string(text);
 
             let (r, g, b) = color_to_rgb(gc.foreground);
             SetTextColor(hdc, rgb(r, g, b));
             SetBkMode(hdc, TRANSPARENT as i32);
+            // X11 text origins are on the baseline
+            SetTextAlign(hdc, TA_BASELINE | TA_LEFT);
 
             TextOutW(
                 hdc,
@@ -719,8 +931,24 @@ impl Backend for WindowsBackend {
         gc: &BackendGC,
         arcs: &[crate::protocol::Arc],
     ) -> BackendResult<()> {
+        if gc.line_style != LineStyle::Solid {
+            for arc in arcs {
+                self.draw_dashed_lines(drawable, gc, &dash::arc_points(arc))?;
+            }
+            return Ok(());
+        }
+
+        if raster::needs_software_fill(gc) {
+            let size = self.drawable_size(drawable)?;
+            let corners = raster::arc_corners(arcs);
+            let bounds = raster::bounds_of(&corners, gc.line_width as i32 + 1);
+            return raster::draw_with_function(self, drawable, size, gc, bounds, |b, d, g| {
+                b.draw_arcs(d, g, arcs)
+            });
+        }
+
         unsafe {
-            let hdc = self.get_dc(drawable)?;
+            let hdc = self.get_gc_dc(drawable, gc)?;
             let pen = self.create_pen(gc);
             let old_pen = SelectObject(hdc, pen);
 
@@ -766,8 +994,16 @@ impl Backend for WindowsBackend {
         gc: &BackendGC,
         arcs: &[crate::protocol::Arc],
     ) -> BackendResult<()> {
+        if raster::needs_software_fill(gc) {
+            let size = self.drawable_size(drawable)?;
+            let bounds = raster::bounds_of(&raster::arc_corners(arcs), 1);
+            return raster::draw_with_function(self, drawable, size, gc, bounds, |b, d, g| {
+                b.fill_arcs(d, g, arcs)
+            });
+        }
+
         unsafe {
-            let hdc = self.get_dc(drawable)?;
+            let hdc = self.get_gc_dc(drawable, gc)?;
             let brush = self.create_brush(gc);
             let old_brush = SelectObject(hdc, brush);
 
@@ -809,8 +1045,17 @@ impl Backend for WindowsBackend {
         gc: &BackendGC,
         points: &[crate::protocol::Point],
     ) -> BackendResult<()> {
+        if raster::needs_software_fill(gc) {
+            let size = self.drawable_size(drawable)?;
+            let coords: Vec<(i32, i32)> = points.iter().map(|p| (p.x as i32, p.y as i32)).collect();
+            let bounds = raster::bounds_of(&coords, 1);
+            return raster::draw_with_function(self, drawable, size, gc, bounds, |b, d, g| {
+                b.fill_polygon(d, g, points)
+            });
+        }
+
         unsafe {
-            let hdc = self.get_dc(drawable)?;
+            let hdc = self.get_gc_dc(drawable, gc)?;
             let brush = self.create_brush(gc);
             let old_brush = SelectObject(hdc, brush);
 
@@ -836,7 +1081,7 @@ impl Backend for WindowsBackend {
         &mut self,
         src: BackendDrawable,
         dst: BackendDrawable,
-        _gc: &BackendGC,
+        gc: &BackendGC,
         src_x: i16,
         src_y: i16,
         width: u16,
@@ -844,9 +1089,16 @@ impl Backend for WindowsBackend {
         dst_x: i16,
         dst_y: i16,
     ) -> BackendResult<()> {
+        if raster::needs_emulation(gc) {
+            let (src_size, dst_size) = (self.drawable_size(src)?, self.drawable_size(dst)?);
+            return raster::copy_with_function(
+                self, src, src_size, dst, dst_size, gc, src_x, src_y, width, height, dst_x, dst_y,
+            );
+        }
+
         unsafe {
             let src_hdc = self.get_dc(src)?;
-            let dst_hdc = self.get_dc(dst)?;
+            let dst_hdc = self.get_gc_dc(dst, gc)?;
 
             BitBlt(
                 dst_hdc,
@@ -885,6 +1137,7 @@ impl Backend for WindowsBackend {
             let id = self.next_resource_id;
             self.next_resource_id += 1;
             self.pixmaps.insert(id, (mem_dc, bitmap));
+            self.pixmap_sizes.insert(id, (width, height));
 
             Ok(id)
         }
@@ -892,6 +1145,7 @@ impl Backend for WindowsBackend {
 
     fn free_pixmap(&mut self, pixmap: usize) -> BackendResult<()> {
         unsafe {
+            self.pixmap_sizes.remove(&pixmap);
             if let Some((hdc, hbitmap)) = self.pixmaps.remove(&pixmap) {
                 DeleteObject(hbitmap as isize);
                 DeleteDC(hdc);
@@ -903,7 +1157,7 @@ impl Backend for WindowsBackend {
     fn put_image(
         &mut self,
         drawable: BackendDrawable,
-        _gc: &BackendGC,
+        gc: &BackendGC,
         width: u16,
         height: u16,
         dst_x: i16,
@@ -912,8 +1166,25 @@ impl Backend for WindowsBackend {
         format: u8,
         data: &[u8],
     ) -> BackendResult<()> {
+        // Depth 24 and 32 ZPixmaps carry 32 bits per pixel
+        if raster::needs_emulation(gc) && format == 2 && depth >= 24 {
+            let size = self.drawable_size(drawable)?;
+            let pixels = raster::decode_pixels(depth, data, width as usize, height as usize);
+            let count = (width as usize * height as usize).min(pixels.len());
+            return raster::put_with_function(
+                self,
+                drawable,
+                size,
+                gc,
+                dst_x,
+                dst_y,
+                width,
+                &pixels[..count],
+            );
+        }
+
         unsafe {
-            let hdc = self.get_dc(drawable)?;
+            let hdc = self.get_gc_dc(drawable, gc)?;
 
             // X11 image formats:
             // 0 = Bitmap (1 bit per pixel)
@@ -1092,6 +1363,12 @@ impl Backend for WindowsBackend {
         unsafe {
             let mut msg: MSG = mem::zeroed();
 
+            // The user switched input languages since the mapping was read
+            if self.keyboard_layout != 0 && GetKeyboardLayout(0) != self.keyboard_layout {
+                self.keyboard_layout = GetKeyboardLayout(0);
+                self.event_queue.push(BackendEvent::MappingChanged);
+            }
+
             // Process all available messages without blocking
             while PeekMessageW(&mut msg, 0, 0, 0, PM_REMOVE) != 0 {
                 TranslateMessage(&msg);
@@ -1286,6 +1563,9 @@ impl Backend for WindowsBackend {
                             }
                         }
                     }
+                    WM_TIMER if msg.hwnd == 0 => {
+                        self.advance_cursor_animation(msg.wParam);
+                    }
                     WM_SETCURSOR => {
                         // Set the window's cursor when mouse is in client area
                         if (msg.lParam & 0xffff) as u32 == HTCLIENT {
@@ -1369,9 +1649,61 @@ impl Backend for WindowsBackend {
         }
     }
 
+    fn create_image_cursor(&mut self, frames: &[CursorFrame]) -> BackendResult<BackendCursor> {
+        if frames.is_empty() {
+            return Err("Cursor has no frames".into());
+        }
+        let mut cursors = Vec::with_capacity(frames.len());
+        for frame in frames {
+            match Self::frame_cursor(frame) {
+                Ok(hcursor) => cursors.push((hcursor, frame.delay)),
+                Err(e) => {
+                    for (hcursor, _) in cursors {
+                        unsafe {
+                            DestroyCursor(hcursor);
+                        }
+                    }
+                    return Err(e);
+                }
+            }
+        }
+
+        let id = self.next_resource_id;
+        self.next_resource_id += 1;
+        self.cursors.insert(id, cursors[0].0);
+        self.image_cursors.insert(id);
+        if cursors.len() > 1 {
+            // A thread timer steps through the frames; its WM_TIMER messages
+            // come through the event loop
+            let timer = unsafe { SetTimer(0, 0, cursors[0].1.max(1), None) };
+            self.cursor_animations.insert(
+                id,
+                CursorAnimation {
+                    frames: cursors,
+                    current: 0,
+                    timer,
+                },
+            );
+        }
+        Ok(BackendCursor(id))
+    }
+
     fn free_cursor(&mut self, cursor: BackendCursor) -> BackendResult<()> {
         // System cursors don't need to be freed, just remove from our map
-        self.cursors.remove(&cursor.0);
+        let hcursor = self.cursors.remove(&cursor.0);
+        if let Some(animation) = self.cursor_animations.remove(&cursor.0) {
+            self.image_cursors.remove(&cursor.0);
+            unsafe {
+                KillTimer(0, animation.timer);
+                for (hcursor, _) in animation.frames {
+                    DestroyCursor(hcursor);
+                }
+            }
+        } else if let (true, Some(hcursor)) = (self.image_cursors.remove(&cursor.0), hcursor) {
+            unsafe {
+                DestroyCursor(hcursor);
+            }
+        }
         Ok(())
     }
 
@@ -1402,6 +1734,15 @@ impl Backend for WindowsBackend {
         }
     }
 
+    fn warp_pointer(&mut self, x: i16, y: i16) -> BackendResult<()> {
+        unsafe {
+            if SetCursorPos(x as i32, y as i32) == 0 {
+                return Err("SetCursorPos failed".into());
+            }
+        }
+        Ok(())
+    }
+
     fn flush(&mut self) -> BackendResult<()> {
         unsafe {
             // Flush GDI queue to ensure all drawing operations are complete
@@ -1634,6 +1975,9 @@ impl Backend for WindowsBackend {
                             });
                         }
                     }
+                    WM_TIMER if msg.hwnd == 0 => {
+                        self.advance_cursor_animation(msg.wParam);
+                    }
                     WM_SETCURSOR => {
                         // Set the window's cursor when mouse is in client area
                         if (msg.lParam & 0xffff) as u32 == HTCLIENT {
@@ -1799,4 +2143,46 @@ impl Backend for WindowsBackend {
         log::debug!("Windows backend: enumerated {} fonts", fonts.len());
         Ok(fonts)
     }
+
+    fn keyboard_mapping(&mut self) -> BackendResult<KeyboardMapping> {
+        // Keycodes are virtual-key codes; character keys get the keysyms the
+        // current layout types with and without Shift
+        const VK_SHIFT_KEY: usize = 0x10;
+        unsafe {
+            let layout = GetKeyboardLayout(0);
+            self.keyboard_layout = layout;
+            let type_char = |vk: u8, shift: bool| -> u32 {
+                let mut state = [0u8; 256];
+                if shift {
+                    state[VK_SHIFT_KEY] = 0x80;
+                }
+                let scan = MapVirtualKeyExW(vk as u32, MAPVK_VK_TO_VSC, layout);
+                let mut buf = [0u16; 4];
+                // Flag 4: leave the keyboard (dead key) state untouched
+                let n = ToUnicodeEx(
+                    vk as u32,
+                    scan,
+                    state.as_ptr(),
+                    buf.as_mut_ptr(),
+                    buf.len() as i32,
+                    4,
+                    layout,
+                );
+                if n == 1 {
+                    char::from_u32(buf[0] as u32).map_or(0, keymap::char_keysym)
+                } else {
+                    0
+                }
+            };
+            Ok(keymap::two_level_mapping(
+                8,
+                255,
+                |vk| match keymap::windows_vk_keysym(vk) {
+                    Some(keysym) => (keysym, keysym),
+                    None => (type_char(vk, false), type_char(vk, true)),
+                },
+                &keymap::WINDOWS_MODIFIERS,
+            ))
+        }
+    }
 }
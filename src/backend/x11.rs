@@ -6,23 +6,31 @@
 //! - Protocol debugging and logging
 //! - Testing the protocol implementation
 
+mod connection;
+mod display;
+mod render;
+mod xauth;
+
+use super::composite::{self, CompositePicture, Operator, PictFormat};
 use super::*;
 use crate::protocol::*;
+use connection::X11Connection;
+use display::{DisplayName, Stream};
+use render::UpstreamRender;
 use std::collections::HashMap;
 use std::io::{Read, Write};
-use std::os::unix::net::UnixStream;
-use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
 
 pub struct X11Backend {
     display: String,
-    connection: Option<UnixStream>,
+    connection: Option<X11Connection>,
     setup_info: Option<SetupSuccess>,
     byte_order: ByteOrder,
 
     // Resource ID mapping (our IDs -> real server IDs)
     window_map: Arc<Mutex<HashMap<usize, u32>>>,
     pixmap_map: Arc<Mutex<HashMap<usize, u32>>>,
+    pixmap_depths: HashMap<usize, u8>,
     gc_map: Arc<Mutex<HashMap<usize, u32>>>,
     cursor_map: Arc<Mutex<HashMap<usize, u32>>>,
 
@@ -39,9 +47,41 @@ pub struct X11Backend {
     // Cursor font for creating standard cursors
     cursor_font_id: Option<u32>,
 
+    // Upstream RENDER extension, for composites and image cursors; None
+    // until queried, Some(None) if unavailable
+    render: Option<Option<UpstreamRender>>,
+
+    // Upstream SHAPE major opcode; None until queried, Some(None) if
+    // unavailable
+    shape_opcode: Option<Option<u8>>,
+
+    // Rooted mode: size of the virtual root, and the upstream window hosting it
+    rooted: Option<(u16, u16)>,
+    desktop_window: Option<BackendWindow>,
+
+    // Screen of the upstream display to use (DISPLAY's .S suffix)
+    screen: usize,
+
     debug: bool,
 }
 
+/// Events selected on the desktop window in rooted mode. All input for the
+/// virtual screen is reported against this window and routed by the server.
+const DESKTOP_EVENT_MASK: u32 = 0x0000_0001 // KeyPress
+    | 0x0000_0002 // KeyRelease
+    | 0x0000_0004 // ButtonPress
+    | 0x0000_0008 // ButtonRelease
+    | 0x0000_0010 // EnterWindow
+    | 0x0000_0020 // LeaveWindow
+    | 0x0000_0040 // PointerMotion
+    | 0x0000_8000 // Exposure
+    | 0x0002_0000 // StructureNotify
+    | 0x0020_0000; // FocusChange
+
+/// Events client windows may select upstream in rooted mode. Input is left
+/// unselected so the upstream server propagates it to the desktop window.
+const ROOTED_CLIENT_EVENT_MASK: u32 = 0x0000_8000; // Exposure
+
 impl X11Backend {
     pub fn new(target_display: &str) -> Self {
         Self {
@@ -51,6 +91,7 @@ impl X11Backend {
             byte_order: ByteOrder::LSBFirst,
             window_map: Arc::new(Mutex::new(HashMap::new())),
             pixmap_map: Arc::new(Mutex::new(HashMap::new())),
+            pixmap_depths: HashMap::new(),
             gc_map: Arc::new(Mutex::new(HashMap::new())),
             cursor_map: Arc::new(Mutex::new(HashMap::new())),
             next_resource_id: 1,
@@ -60,6 +101,11 @@ impl X11Backend {
             resource_id_mask: 0,
             default_font_id: None,
             cursor_font_id: None,
+            render: None,
+            shape_opcode: None,
+            rooted: None,
+            desktop_window: None,
+            screen: 0,
             debug: true,
         }
     }
@@ -69,6 +115,13 @@ impl X11Backend {
         self
     }
 
+    /// Run in rooted mode: present a single `width`x`height` upstream window
+    /// acting as the root, with all client windows nested inside it
+    pub fn with_rooted(mut self, width: u16, height: u16) -> Self {
+        self.rooted = Some((width, height));
+        self
+    }
+
     /// Allocate a resource ID on the connected X server
     fn allocate_server_resource_id(&mut self) -> u32 {
         let id = self.resource_id_base | (self.next_resource_id as u32 & self.resource_id_mask);
@@ -76,13 +129,12 @@ impl X11Backend {
         id
     }
 
-    /// Send a request to the X server
+    /// Queue a request for the X server; it is written on the next flush
     fn send_request(&mut self, data: &[u8]) -> BackendResult<()> {
         if let Some(ref mut conn) = self.connection {
-            conn.write_all(data)
-                .map_err(|e| format!("Failed to send X11 request: {}", e))?;
+            let sequence = conn.send_request(data)?;
             if self.debug {
-                log::debug!("Sent {} bytes to X server", data.len());
+                log::debug!("Queued {} byte request #{}", data.len(), sequence);
             }
             Ok(())
         } else {
@@ -90,50 +142,43 @@ impl X11Backend {
         }
     }
 
-    /// Send a request and read the reply from the X server
+    /// Send a request and wait for its reply from the X server
+    ///
+    /// Requests queued before it are flushed along with it; events that
+    /// arrive in the meantime stay queued for `poll_events`.
     fn send_request_with_reply(&mut self, data: &[u8]) -> BackendResult<Vec<u8>> {
-        // Send the request
-        self.send_request(data)?;
-        self.flush()?;
-
-        // Read the reply header (32 bytes)
         let conn = self
             .connection
             .as_mut()
             .ok_or("Not connected to X server")?;
-        let mut header = [0u8; 32];
-        conn.read_exact(&mut header)
-            .map_err(|e| format!("Failed to read reply header: {}", e))?;
+        let sequence = conn.send_request_with_reply(data)?;
+        let reply = conn.wait_for_reply(sequence)?;
 
-        let reply_type = header[0];
-        if reply_type == 0 {
-            // Error response
-            let error_code = header[1];
-            return Err(format!("X11 error: code={}", error_code).into());
-        }
-        if reply_type != 1 {
-            return Err(format!("Unexpected reply type: {}", reply_type).into());
+        if self.debug {
+            log::debug!(
+                "Received {} byte reply to request #{} from X server",
+                reply.len(),
+                sequence
+            );
         }
 
-        // Get the additional data length (in 4-byte units)
-        let additional_length =
-            u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize;
-        let additional_bytes = additional_length * 4;
-
-        // Read additional data if any
-        let mut reply_data = header.to_vec();
-        if additional_bytes > 0 {
-            let mut additional = vec![0u8; additional_bytes];
-            conn.read_exact(&mut additional)
-                .map_err(|e| format!("Failed to read reply data: {}", e))?;
-            reply_data.extend_from_slice(&additional);
-        }
+        Ok(reply)
+    }
 
-        if self.debug {
-            log::debug!("Received {} byte reply from X server", reply_data.len());
+    /// Log errors the X server reported for requests without replies
+    fn log_async_errors(&mut self) {
+        let Some(conn) = self.connection.as_mut() else {
+            return;
+        };
+        for error in conn.take_errors() {
+            log::warn!(
+                "X server error: code={} major={} minor={} value=0x{:x}",
+                error[1],
+                error[10],
+                u16::from_le_bytes([error[8], error[9]]),
+                u32::from_le_bytes([error[4], error[5], error[6], error[7]])
+            );
         }
-
-        Ok(reply_data)
     }
 
     /// Create a GC on the server
@@ -149,6 +194,7 @@ impl X11Backend {
         // Value mask and value list
         // X11 GC attribute bits:
         // 0x00000001 = function
+        // 0x00000002 = plane-mask
         // 0x00000004 = foreground
         // 0x00000008 = background
         // 0x00000010 = line-width (CARD16, but stored as 4 bytes)
@@ -164,6 +210,10 @@ impl X11Backend {
         value_mask |= 0x00000001;
         value_list.extend_from_slice(&(gc.function as u32).to_le_bytes());
 
+        // Plane mask (bit 1)
+        value_mask |= 0x00000002;
+        value_list.extend_from_slice(&gc.plane_mask.to_le_bytes());
+
         // Foreground (bit 2)
         value_mask |= 0x00000004;
         value_list.extend_from_slice(&gc.foreground.to_le_bytes());
@@ -196,6 +246,21 @@ impl X11Backend {
         value_mask |= 0x00000200;
         value_list.extend_from_slice(&(gc.fill_rule as u32).to_le_bytes());
 
+        // Tile, stipple and their origin (bits 10-13)
+        self.push_gc_pattern_values(gc, &mut value_mask, &mut value_list);
+
+        // Subwindow mode (bit 15)
+        value_mask |= 0x00008000;
+        value_list.extend_from_slice(&(gc.subwindow_mode as u32).to_le_bytes());
+
+        // Dash offset (bit 20); the dash list follows in SetDashes
+        value_mask |= 0x00100000;
+        value_list.extend_from_slice(&(gc.dash_offset as u32).to_le_bytes());
+
+        // Arc mode (bit 22)
+        value_mask |= 0x00400000;
+        value_list.extend_from_slice(&(gc.arc_mode as u32).to_le_bytes());
+
         req.extend_from_slice(&value_mask.to_le_bytes());
         req.extend_from_slice(&value_list);
 
@@ -209,6 +274,8 @@ impl X11Backend {
         }
 
         self.send_request(&req)?;
+        self.set_server_gc_clip(gc_id, gc)?;
+        self.set_server_gc_dashes(gc_id, gc)?;
 
         if self.debug {
             log::debug!(
@@ -224,6 +291,26 @@ impl X11Backend {
         Ok(())
     }
 
+    /// Append the tile, stipple and tile/stipple origin of a GC to a value
+    /// list; patterns are mapped to their server pixmaps
+    fn push_gc_pattern_values(
+        &self,
+        gc: &BackendGC,
+        value_mask: &mut u32,
+        value_list: &mut Vec<u8>,
+    ) {
+        let pixmap_map = self.pixmap_map.lock().unwrap();
+        for (bit, pattern) in [(0x00000400, &gc.tile), (0x00000800, &gc.stipple)] {
+            if let Some(&server_pixmap) = pattern.and_then(|p| pixmap_map.get(&p.pixmap)) {
+                *value_mask |= bit;
+                value_list.extend_from_slice(&server_pixmap.to_le_bytes());
+            }
+        }
+        *value_mask |= 0x00001000 | 0x00002000;
+        value_list.extend_from_slice(&(gc.ts_x_origin as i32).to_le_bytes());
+        value_list.extend_from_slice(&(gc.ts_y_origin as i32).to_le_bytes());
+    }
+
     /// Change a GC on the server
     fn change_server_gc(&mut self, gc_id: u32, gc: &BackendGC) -> BackendResult<()> {
         // Build ChangeGC request (opcode 56)
@@ -236,6 +323,7 @@ impl X11Backend {
         // Value mask and value list
         // X11 GC attribute bits:
         // 0x00000001 = function
+        // 0x00000002 = plane-mask
         // 0x00000004 = foreground
         // 0x00000008 = background
         // 0x00000010 = line-width (CARD16, but stored as 4 bytes)
@@ -251,6 +339,10 @@ impl X11Backend {
         value_mask |= 0x00000001;
         value_list.extend_from_slice(&(gc.function as u32).to_le_bytes());
 
+        // Plane mask (bit 1)
+        value_mask |= 0x00000002;
+        value_list.extend_from_slice(&gc.plane_mask.to_le_bytes());
+
         // Foreground (bit 2)
         value_mask |= 0x00000004;
         value_list.extend_from_slice(&gc.foreground.to_le_bytes());
@@ -283,6 +375,25 @@ impl X11Backend {
         value_mask |= 0x00000200;
         value_list.extend_from_slice(&(gc.fill_rule as u32).to_le_bytes());
 
+        // Tile, stipple and their origin (bits 10-13)
+        self.push_gc_pattern_values(gc, &mut value_mask, &mut value_list);
+
+        // Subwindow mode (bit 15)
+        value_mask |= 0x00008000;
+        value_list.extend_from_slice(&(gc.subwindow_mode as u32).to_le_bytes());
+
+        // Clip mask (bit 19) - cleared here, set by SetClipRectangles below
+        value_mask |= 0x00080000;
+        value_list.extend_from_slice(&0u32.to_le_bytes());
+
+        // Dash offset (bit 20); the dash list follows in SetDashes
+        value_mask |= 0x00100000;
+        value_list.extend_from_slice(&(gc.dash_offset as u32).to_le_bytes());
+
+        // Arc mode (bit 22)
+        value_mask |= 0x00400000;
+        value_list.extend_from_slice(&(gc.arc_mode as u32).to_le_bytes());
+
         req.extend_from_slice(&value_mask.to_le_bytes());
         req.extend_from_slice(&value_list);
 
@@ -296,6 +407,8 @@ impl X11Backend {
         }
 
         self.send_request(&req)?;
+        self.set_server_gc_clip(gc_id, gc)?;
+        self.set_server_gc_dashes(gc_id, gc)?;
 
         if self.debug {
             log::debug!(
@@ -311,6 +424,50 @@ impl X11Backend {
         Ok(())
     }
 
+    /// Send a GC's dash list to a server GC when its lines are dashed
+    fn set_server_gc_dashes(&mut self, gc_id: u32, gc: &BackendGC) -> BackendResult<()> {
+        if gc.line_style == LineStyle::Solid || gc.dashes.is_empty() {
+            return Ok(());
+        }
+
+        // SetDashes (opcode 58)
+        let mut req = Vec::with_capacity(12 + gc.dashes.len() + 3);
+        req.push(58);
+        req.push(0);
+        req.extend_from_slice(&((3 + gc.dashes.len().div_ceil(4)) as u16).to_le_bytes());
+        req.extend_from_slice(&gc_id.to_le_bytes());
+        req.extend_from_slice(&gc.dash_offset.to_le_bytes());
+        req.extend_from_slice(&(gc.dashes.len() as u16).to_le_bytes());
+        req.extend_from_slice(&gc.dashes);
+        while req.len() % 4 != 0 {
+            req.push(0);
+        }
+        self.send_request(&req)
+    }
+
+    /// Apply a GC's clip origin and rectangles to a server GC
+    fn set_server_gc_clip(&mut self, gc_id: u32, gc: &BackendGC) -> BackendResult<()> {
+        let Some(ref rects) = gc.clip_rectangles else {
+            return Ok(());
+        };
+
+        // SetClipRectangles (opcode 59), ordering Unsorted
+        let mut req = Vec::with_capacity(12 + rects.len() * 8);
+        req.push(59);
+        req.push(0);
+        req.extend_from_slice(&((3 + rects.len() * 2) as u16).to_le_bytes());
+        req.extend_from_slice(&gc_id.to_le_bytes());
+        req.extend_from_slice(&gc.clip_x_origin.to_le_bytes());
+        req.extend_from_slice(&gc.clip_y_origin.to_le_bytes());
+        for rect in rects {
+            req.extend_from_slice(&rect.x.to_le_bytes());
+            req.extend_from_slice(&rect.y.to_le_bytes());
+            req.extend_from_slice(&rect.width.to_le_bytes());
+            req.extend_from_slice(&rect.height.to_le_bytes());
+        }
+        self.send_request(&req)
+    }
+
     /// Open a default font for text rendering
     fn open_default_font(&mut self) -> BackendResult<()> {
         let font_id = self.allocate_server_resource_id();
@@ -343,6 +500,132 @@ impl X11Backend {
         Ok(())
     }
 
+    /// Create the upstream window that hosts the virtual screen in rooted mode
+    ///
+    /// The window is tiled with the classic root weave so areas not covered
+    /// by client windows are painted by the upstream server itself.
+    fn create_desktop_window(&mut self, width: u16, height: u16) -> BackendResult<()> {
+        let (upstream_root, visual_id, depth, black, white, bpp) = {
+            let setup = self.setup_info.as_ref().ok_or("Not initialized")?;
+            let screen = &setup.roots[self.screen];
+            let bpp = setup
+                .pixmap_formats
+                .iter()
+                .find(|f| f.depth == screen.root_depth)
+                .map(|f| f.bits_per_pixel)
+                .unwrap_or(32);
+            (
+                screen.root.id().get(),
+                screen.root_visual.get(),
+                screen.root_depth,
+                screen.black_pixel,
+                screen.white_pixel,
+                bpp,
+            )
+        };
+
+        // Root weave: a 2x2 checkerboard, only built for 32bpp ZPixmap layouts
+        let weave_pixmap = if bpp == 32 {
+            let pid = self.allocate_server_resource_id();
+            let mut req = vec![0u8; 16];
+            req[0] = 53; // Opcode: CreatePixmap
+            req[1] = depth;
+            req[2..4].copy_from_slice(&4u16.to_le_bytes());
+            req[4..8].copy_from_slice(&pid.to_le_bytes());
+            req[8..12].copy_from_slice(&upstream_root.to_le_bytes());
+            req[12..14].copy_from_slice(&2u16.to_le_bytes());
+            req[14..16].copy_from_slice(&2u16.to_le_bytes());
+            self.send_request(&req)?;
+
+            let gc_id = self.allocate_server_resource_id();
+            self.create_server_gc(pid, gc_id, &BackendGC::default())?;
+
+            // PutImage (opcode 72), ZPixmap, 2x2 pixels
+            let mut req = Vec::new();
+            req.push(72);
+            req.push(2); // ZPixmap
+            req.extend_from_slice(&10u16.to_le_bytes()); // 6 words header + 4 words data
+            req.extend_from_slice(&pid.to_le_bytes());
+            req.extend_from_slice(&gc_id.to_le_bytes());
+            req.extend_from_slice(&2u16.to_le_bytes()); // width
+            req.extend_from_slice(&2u16.to_le_bytes()); // height
+            req.extend_from_slice(&0i16.to_le_bytes()); // dst_x
+            req.extend_from_slice(&0i16.to_le_bytes()); // dst_y
+            req.push(0); // left_pad
+            req.push(depth);
+            req.extend_from_slice(&[0, 0]);
+            for pixel in [white, black, black, white] {
+                req.extend_from_slice(&pixel.to_le_bytes());
+            }
+            self.send_request(&req)?;
+
+            // FreeGC (opcode 60)
+            let mut req = vec![60, 0];
+            req.extend_from_slice(&2u16.to_le_bytes());
+            req.extend_from_slice(&gc_id.to_le_bytes());
+            self.send_request(&req)?;
+
+            Some(pid)
+        } else {
+            None
+        };
+
+        // CreateWindow (opcode 1) directly on the upstream root
+        let server_wid = self.allocate_server_resource_id();
+        let mut req = Vec::new();
+        req.push(1); // Opcode: CreateWindow
+        req.push(depth);
+        req.extend_from_slice(&10u16.to_le_bytes()); // 8 words + 2 values
+        req.extend_from_slice(&server_wid.to_le_bytes());
+        req.extend_from_slice(&upstream_root.to_le_bytes());
+        req.extend_from_slice(&0i16.to_le_bytes()); // x
+        req.extend_from_slice(&0i16.to_le_bytes()); // y
+        req.extend_from_slice(&width.to_le_bytes());
+        req.extend_from_slice(&height.to_le_bytes());
+        req.extend_from_slice(&0u16.to_le_bytes()); // border_width
+        req.extend_from_slice(&1u16.to_le_bytes()); // class: InputOutput
+        req.extend_from_slice(&visual_id.to_le_bytes());
+        match weave_pixmap {
+            Some(pid) => {
+                req.extend_from_slice(&0x0000_0801u32.to_le_bytes()); // CWBackPixmap | CWEventMask
+                req.extend_from_slice(&pid.to_le_bytes());
+            }
+            None => {
+                req.extend_from_slice(&0x0000_0802u32.to_le_bytes()); // CWBackPixel | CWEventMask
+                req.extend_from_slice(&black.to_le_bytes());
+            }
+        }
+        req.extend_from_slice(&DESKTOP_EVENT_MASK.to_le_bytes());
+        self.send_request(&req)?;
+
+        // The window keeps its own reference to the background pixmap
+        if let Some(pid) = weave_pixmap {
+            let mut req = vec![54, 0]; // Opcode: FreePixmap
+            req.extend_from_slice(&2u16.to_le_bytes());
+            req.extend_from_slice(&pid.to_le_bytes());
+            self.send_request(&req)?;
+        }
+
+        let our_id = self.next_resource_id;
+        self.next_resource_id += 1;
+        self.window_map.lock().unwrap().insert(our_id, server_wid);
+        let desktop = BackendWindow(our_id);
+        self.desktop_window = Some(desktop);
+
+        self.set_window_title(desktop, &format!("X11Anywhere ({}x{})", width, height))?;
+        self.map_window(desktop)?;
+        self.flush()?;
+
+        log::info!(
+            "Rooted mode: desktop window 0x{:x} ({}x{})",
+            server_wid,
+            width,
+            height
+        );
+
+        Ok(())
+    }
+
     /// Create a GC on the server with optional font
     fn create_server_gc_with_font(
         &mut self,
@@ -363,6 +646,14 @@ impl X11Backend {
         let mut value_mask = 0u32;
         let mut value_list = Vec::new();
 
+        // Function (bit 0)
+        value_mask |= 0x00000001;
+        value_list.extend_from_slice(&(gc.function as u32).to_le_bytes());
+
+        // Plane mask (bit 1)
+        value_mask |= 0x00000002;
+        value_list.extend_from_slice(&gc.plane_mask.to_le_bytes());
+
         // Foreground (bit 2)
         value_mask |= 0x00000004;
         value_list.extend_from_slice(&gc.foreground.to_le_bytes());
@@ -377,6 +668,10 @@ impl X11Backend {
             value_list.extend_from_slice(&fid.to_le_bytes());
         }
 
+        // Subwindow mode (bit 15)
+        value_mask |= 0x00008000;
+        value_list.extend_from_slice(&(gc.subwindow_mode as u32).to_le_bytes());
+
         req.extend_from_slice(&value_mask.to_le_bytes());
         req.extend_from_slice(&value_list);
 
@@ -390,6 +685,7 @@ impl X11Backend {
         }
 
         self.send_request(&req)?;
+        self.set_server_gc_clip(gc_id, gc)?;
 
         if self.debug {
             log::debug!(
@@ -403,176 +699,88 @@ impl X11Backend {
         Ok(())
     }
 
-    fn connect_to_display(&mut self) -> BackendResult<()> {
-        // Parse display number (format: :display.screen or :display)
-        let display_str = self.display.trim_start_matches(':');
-        // Split off the screen number if present (e.g., "0.0" -> "0")
-        let display_num_str = display_str.split('.').next().unwrap_or(display_str);
-        let display_num: usize = display_num_str
-            .parse()
-            .map_err(|_| format!("Invalid display number: {}", self.display))?;
-
-        // Connect via abstract socket on Linux
-        #[cfg(target_os = "linux")]
-        let stream = {
-            use nix::sys::socket::*;
-            use std::os::fd::{AsRawFd, FromRawFd};
-
-            // Create socket
-            let socket_fd = socket(
-                AddressFamily::Unix,
-                SockType::Stream,
-                SockFlag::SOCK_CLOEXEC,
-                None,
-            )
-            .map_err(|e| format!("Failed to create socket: {}", e))?;
-
-            // Create abstract socket address
-            let abstract_name = format!("/tmp/.X11-unix/X{}", display_num);
-            let addr = UnixAddr::new_abstract(abstract_name.as_bytes())
-                .map_err(|e| format!("Failed to create abstract address: {}", e))?;
-
-            // Connect
-            if let Err(e) = connect(socket_fd.as_raw_fd(), &addr) {
-                let _ = unsafe { UnixStream::from_raw_fd(socket_fd.as_raw_fd()) }; // Clean up
-                return Err(format!("Failed to connect to display {}: {}", self.display, e).into());
+    /// Parse the DISPLAY string and connect over the first transport that works
+    fn connect_to_display(&mut self) -> BackendResult<(DisplayName, Stream)> {
+        let display = DisplayName::parse(&self.display)?;
+        self.screen = display.screen as usize;
+
+        let mut failures = Vec::new();
+        for transport in display.transports() {
+            match transport.connect() {
+                Ok(stream) => {
+                    if self.debug {
+                        log::debug!(
+                            "X11 backend connected to display {} via {:?}",
+                            self.display,
+                            transport
+                        );
+                    }
+                    return Ok((display, stream));
+                }
+                Err(e) => failures.push(e.to_string()),
             }
-
-            // Convert to UnixStream
-            let stream = unsafe { UnixStream::from_raw_fd(socket_fd.as_raw_fd()) };
-            std::mem::forget(socket_fd); // Don't close the fd
-            stream
-        };
-
-        #[cfg(not(target_os = "linux"))]
-        let stream = {
-            let path = format!("/tmp/.X11-unix/X{}", display_num);
-            UnixStream::connect(&path)
-                .map_err(|e| format!("Failed to connect to display {}: {}", self.display, e))?
-        };
-
-        self.connection = Some(stream);
-
-        if self.debug {
-            log::debug!("X11 backend connected to display {}", self.display);
         }
 
-        Ok(())
+        Err(format!(
+            "Failed to connect to display {}: {}",
+            self.display,
+            failures.join("; ")
+        )
+        .into())
     }
 
-    fn read_xauthority(&self, display_num: usize) -> BackendResult<(String, Vec<u8>)> {
-        // Get .Xauthority path from env or use default
-        let xauth_path = std::env::var("XAUTHORITY")
-            .ok()
-            .map(PathBuf::from)
-            .or_else(|| {
-                std::env::var("HOME")
-                    .ok()
-                    .map(|h| PathBuf::from(h).join(".Xauthority"))
-            })
-            .ok_or("Could not find .Xauthority file")?;
+    /// Look up the authorization for a display in the Xauthority file
+    fn read_xauthority(
+        &self,
+        display: &DisplayName,
+        stream: &Stream,
+    ) -> BackendResult<(String, Vec<u8>)> {
+        let xauth_path = xauth::xauthority_path().ok_or("Could not find .Xauthority file")?;
 
         if self.debug {
             log::debug!("Reading auth from: {:?}", xauth_path);
         }
 
-        let mut file = std::fs::File::open(&xauth_path)
-            .map_err(|e| format!("Failed to open .Xauthority: {}", e))?;
-
-        let mut data = Vec::new();
-        file.read_to_end(&mut data)
-            .map_err(|e| format!("Failed to read .Xauthority: {}", e))?;
-
-        let mut offset = 0;
-        let display_str = format!("{}", display_num);
-
-        // Parse .Xauthority entries
-        while offset < data.len() {
-            if offset + 2 > data.len() {
-                break;
-            }
-            let family = u16::from_be_bytes([data[offset], data[offset + 1]]);
-            offset += 2;
-
-            // Read address
-            if offset + 2 > data.len() {
-                break;
-            }
-            let addr_len = u16::from_be_bytes([data[offset], data[offset + 1]]) as usize;
-            offset += 2;
-            if offset + addr_len > data.len() {
-                break;
-            }
-            let _address = &data[offset..offset + addr_len];
-            offset += addr_len;
-
-            // Read display number
-            if offset + 2 > data.len() {
-                break;
-            }
-            let num_len = u16::from_be_bytes([data[offset], data[offset + 1]]) as usize;
-            offset += 2;
-            if offset + num_len > data.len() {
-                break;
-            }
-            let number = String::from_utf8_lossy(&data[offset..offset + num_len]).to_string();
-            offset += num_len;
-
-            // Read auth name
-            if offset + 2 > data.len() {
-                break;
-            }
-            let name_len = u16::from_be_bytes([data[offset], data[offset + 1]]) as usize;
-            offset += 2;
-            if offset + name_len > data.len() {
-                break;
-            }
-            let name = String::from_utf8_lossy(&data[offset..offset + name_len]).to_string();
-            offset += name_len;
+        let data =
+            std::fs::read(&xauth_path).map_err(|e| format!("Failed to read .Xauthority: {}", e))?;
+        let entries = xauth::parse_entries(&data);
 
-            // Read auth data
-            if offset + 2 > data.len() {
-                break;
-            }
-            let data_len = u16::from_be_bytes([data[offset], data[offset + 1]]) as usize;
-            offset += 2;
-            if offset + data_len > data.len() {
-                break;
+        let hostname = xauth::local_hostname();
+        match xauth::find_entry(&entries, display, stream.peer_ip(), &hostname) {
+            Some(entry) => {
+                if self.debug {
+                    log::debug!(
+                        "Found auth: {} ({} bytes, family {})",
+                        entry.name,
+                        entry.data.len(),
+                        entry.family
+                    );
+                }
+                Ok((entry.name.clone(), entry.data.clone()))
             }
-            let auth_data = data[offset..offset + data_len].to_vec();
-            offset += data_len;
-
-            // Check if this entry matches our display
-            // Family 0x0100 = FamilyLocal, 0x0000 = FamilyInternet
-            if (family == 0x0100 || family == 0x0000) && number == display_str {
+            None => {
+                // No auth found - return empty
                 if self.debug {
-                    log::debug!("Found auth: {} ({} bytes)", name, auth_data.len());
+                    log::debug!("No auth entry found for display {}", self.display);
                 }
-                return Ok((name, auth_data));
+                Ok((String::new(), Vec::new()))
             }
         }
-
-        // No auth found - return empty
-        if self.debug {
-            log::debug!("No auth entry found for display {}", display_num);
-        }
-        Ok((String::new(), Vec::new()))
     }
 
-    fn perform_handshake(&mut self) -> BackendResult<()> {
-        // Get display number for auth lookup
-        let display_num: usize = self.display.trim_start_matches(':').parse().unwrap_or(0);
-
-        // Try to get auth from .Xauthority (before borrowing connection)
-        let (auth_name, auth_data) = self.read_xauthority(display_num).unwrap_or_else(|e| {
+    fn perform_handshake(
+        &mut self,
+        display: &DisplayName,
+        stream: &mut Stream,
+    ) -> BackendResult<()> {
+        // Try to get auth from .Xauthority
+        let (auth_name, auth_data) = self.read_xauthority(display, stream).unwrap_or_else(|e| {
             if self.debug {
                 log::debug!("Could not read auth: {}", e);
             }
             (String::new(), Vec::new())
         });
 
-        let stream = self.connection.as_mut().ok_or("Not connected")?;
-
         // Send setup request
         let mut setup_bytes = Vec::new();
         setup_bytes.push(b'l'); // LSB first
@@ -658,6 +866,16 @@ impl X11Backend {
             log::debug!("  Screens: {}", setup.roots.len());
         }
 
+        if self.screen >= setup.roots.len() {
+            return Err(format!(
+                "Display {} has no screen {} ({} screens)",
+                self.display,
+                self.screen,
+                setup.roots.len()
+            )
+            .into());
+        }
+
         self.resource_id_base = setup.resource_id_base;
         self.resource_id_mask = setup.resource_id_mask;
         self.setup_info = Some(setup);
@@ -863,8 +1081,9 @@ impl X11Backend {
 
 impl Backend for X11Backend {
     fn init(&mut self) -> BackendResult<()> {
-        self.connect_to_display()?;
-        self.perform_handshake()?;
+        let (display, mut stream) = self.connect_to_display()?;
+        self.perform_handshake(&display, &mut stream)?;
+        self.connection = Some(X11Connection::new(stream));
         // Open a default font for text rendering
         // This may fail on systems without the "fixed" font, but we continue anyway
         if let Err(e) = self.open_default_font() {
@@ -872,19 +1091,41 @@ impl Backend for X11Backend {
                 log::debug!("Could not open default font: {}", e);
             }
         }
+        if let Some((width, height)) = self.rooted {
+            self.create_desktop_window(width, height)?;
+        }
         Ok(())
     }
 
     fn get_screen_info(&self) -> BackendResult<ScreenInfo> {
         let setup = self.setup_info.as_ref().ok_or("Not initialized")?;
 
-        let screen = &setup.roots[0];
+        let screen = &setup.roots[self.screen];
+
+        // In rooted mode the virtual root is the desktop window; keep the
+        // upstream screen's physical resolution for the millimeter sizes
+        let (width, height, width_mm, height_mm) = match self.rooted {
+            Some((w, h)) => (
+                w,
+                h,
+                (screen.width_in_millimeters as u32 * w as u32
+                    / screen.width_in_pixels.max(1) as u32) as u16,
+                (screen.height_in_millimeters as u32 * h as u32
+                    / screen.height_in_pixels.max(1) as u32) as u16,
+            ),
+            None => (
+                screen.width_in_pixels,
+                screen.height_in_pixels,
+                screen.width_in_millimeters,
+                screen.height_in_millimeters,
+            ),
+        };
 
         Ok(ScreenInfo {
-            width: screen.width_in_pixels,
-            height: screen.height_in_pixels,
-            width_mm: screen.width_in_millimeters,
-            height_mm: screen.height_in_millimeters,
+            width,
+            height,
+            width_mm,
+            height_mm,
             root_visual: screen.root_visual,
             root_depth: screen.root_depth,
             white_pixel: screen.white_pixel,
@@ -895,7 +1136,7 @@ impl Backend for X11Backend {
     fn get_visuals(&self) -> BackendResult<Vec<VisualInfo>> {
         let setup = self.setup_info.as_ref().ok_or("Not initialized")?;
 
-        let screen = &setup.roots[0];
+        let screen = &setup.roots[self.screen];
         let mut visuals = Vec::new();
 
         for depth in &screen.allowed_depths {
@@ -929,18 +1170,33 @@ impl Backend for X11Backend {
                 .unwrap()
                 .get(&parent.0)
                 .unwrap_or(&server_wid)
+        } else if let Some(desktop) = self.desktop_window {
+            // Rooted mode: top-level windows live inside the desktop window
+            *self
+                .window_map
+                .lock()
+                .unwrap()
+                .get(&desktop.0)
+                .ok_or("Desktop window not found")?
         } else {
             // Use root window from setup
             if let Some(ref setup) = self.setup_info {
-                setup.roots[0].root.id().get()
+                setup.roots[self.screen].root.id().get()
             } else {
                 return Err("Not initialized".into());
             }
         };
 
+        // In rooted mode input is routed by the server from the desktop window
+        let event_mask = if self.rooted.is_some() {
+            params.event_mask & ROOTED_CLIENT_EVENT_MASK
+        } else {
+            params.event_mask
+        };
+
         // Get visual ID
         let visual_id = if let Some(ref setup) = self.setup_info {
-            setup.roots[0].root_visual.get()
+            setup.roots[self.screen].root_visual.get()
         } else {
             return Err("Not initialized".into());
         };
@@ -969,9 +1225,9 @@ impl Backend for X11Backend {
             value_list.extend_from_slice(&bg.to_le_bytes());
         }
 
-        if params.event_mask != 0 {
+        if event_mask != 0 {
             value_mask |= 0x00000800; // CWEventMask
-            value_list.extend_from_slice(&params.event_mask.to_le_bytes());
+            value_list.extend_from_slice(&event_mask.to_le_bytes());
         }
 
         req.extend_from_slice(&value_mask.to_le_bytes());
@@ -1003,7 +1259,29 @@ impl Backend for X11Backend {
         Ok(BackendWindow(our_id))
     }
 
-    fn destroy_window(&mut self, _window: BackendWindow) -> BackendResult<()> {
+    fn destroy_window(&mut self, window: BackendWindow) -> BackendResult<()> {
+        let server_wid = match self.window_map.lock().unwrap().remove(&window.0) {
+            Some(id) => id,
+            None => return Ok(()),
+        };
+
+        // Build DestroyWindow request (opcode 4)
+        let mut req = Vec::new();
+        req.push(4); // Opcode: DestroyWindow
+        req.push(0); // Padding
+        req.extend_from_slice(&2u16.to_le_bytes()); // Length: 2 words
+        req.extend_from_slice(&server_wid.to_le_bytes());
+
+        self.send_request(&req)?;
+
+        if self.debug {
+            log::debug!(
+                "Destroyed window: our_id={}, server_id=0x{:x}",
+                window.0,
+                server_wid
+            );
+        }
+
         Ok(())
     }
 
@@ -1036,15 +1314,94 @@ impl Backend for X11Backend {
         Ok(())
     }
 
-    fn unmap_window(&mut self, _window: BackendWindow) -> BackendResult<()> {
+    fn unmap_window(&mut self, window: BackendWindow) -> BackendResult<()> {
+        let server_wid = *self
+            .window_map
+            .lock()
+            .unwrap()
+            .get(&window.0)
+            .ok_or("Window not found")?;
+
+        // Build UnmapWindow request (opcode 10)
+        let mut req = Vec::new();
+        req.push(10); // Opcode: UnmapWindow
+        req.push(0); // Padding
+        req.extend_from_slice(&2u16.to_le_bytes()); // Length: 2 words
+        req.extend_from_slice(&server_wid.to_le_bytes());
+
+        self.send_request(&req)?;
+
+        if self.debug {
+            log::debug!("Unmapped window 0x{:x}", server_wid);
+        }
+
         Ok(())
     }
 
     fn configure_window(
         &mut self,
-        _window: BackendWindow,
-        _config: WindowConfig,
+        window: BackendWindow,
+        config: WindowConfig,
     ) -> BackendResult<()> {
+        let server_wid = *self
+            .window_map
+            .lock()
+            .unwrap()
+            .get(&window.0)
+            .ok_or("Window not found")?;
+
+        // Values must appear in mask bit order: x, y, width, height,
+        // border-width, sibling, stack-mode (each padded to 4 bytes)
+        let mut value_mask = 0u16;
+        let mut value_list = Vec::new();
+        if let Some(x) = config.x {
+            value_mask |= 0x0001;
+            value_list.extend_from_slice(&(x as i32).to_le_bytes());
+        }
+        if let Some(y) = config.y {
+            value_mask |= 0x0002;
+            value_list.extend_from_slice(&(y as i32).to_le_bytes());
+        }
+        if let Some(width) = config.width {
+            value_mask |= 0x0004;
+            value_list.extend_from_slice(&(width.max(1) as u32).to_le_bytes());
+        }
+        if let Some(height) = config.height {
+            value_mask |= 0x0008;
+            value_list.extend_from_slice(&(height.max(1) as u32).to_le_bytes());
+        }
+        if let Some(border_width) = config.border_width {
+            value_mask |= 0x0010;
+            value_list.extend_from_slice(&(border_width as u32).to_le_bytes());
+        }
+        if let Some(stack_mode) = config.stack_mode {
+            value_mask |= 0x0040;
+            value_list.extend_from_slice(&(stack_mode as u32).to_le_bytes());
+        }
+        if value_mask == 0 {
+            return Ok(());
+        }
+
+        // Build ConfigureWindow request (opcode 12)
+        let mut req = Vec::new();
+        req.push(12); // Opcode: ConfigureWindow
+        req.push(0); // Unused
+        req.extend_from_slice(&((3 + value_list.len() / 4) as u16).to_le_bytes());
+        req.extend_from_slice(&server_wid.to_le_bytes());
+        req.extend_from_slice(&value_mask.to_le_bytes());
+        req.extend_from_slice(&[0u8; 2]); // Padding
+        req.extend_from_slice(&value_list);
+
+        self.send_request(&req)?;
+
+        if self.debug {
+            log::debug!(
+                "Configured window 0x{:x}: mask=0x{:x}",
+                server_wid,
+                value_mask
+            );
+        }
+
         Ok(())
     }
 
@@ -1147,14 +1504,83 @@ impl Backend for X11Backend {
         Ok(())
     }
 
+    fn reparent_window(
+        &mut self,
+        window: BackendWindow,
+        parent: Option<BackendWindow>,
+        x: i16,
+        y: i16,
+    ) -> BackendResult<()> {
+        let (server_wid, server_parent) = {
+            let window_map = self.window_map.lock().unwrap();
+            let server_wid = *window_map.get(&window.0).ok_or("Window not found")?;
+            let server_parent = match parent.or(self.desktop_window) {
+                Some(p) => *window_map.get(&p.0).ok_or("Parent window not found")?,
+                None => match self.setup_info {
+                    Some(ref setup) => setup.roots[self.screen].root.id().get(),
+                    None => return Err("Not initialized".into()),
+                },
+            };
+            (server_wid, server_parent)
+        };
+
+        // Build ReparentWindow request (opcode 7)
+        let mut req = Vec::new();
+        req.push(7); // Opcode: ReparentWindow
+        req.push(0); // Padding
+        req.extend_from_slice(&4u16.to_le_bytes()); // Length: 4 words
+        req.extend_from_slice(&server_wid.to_le_bytes());
+        req.extend_from_slice(&server_parent.to_le_bytes());
+        req.extend_from_slice(&x.to_le_bytes());
+        req.extend_from_slice(&y.to_le_bytes());
+
+        self.send_request(&req)?;
+
+        if self.debug {
+            log::debug!(
+                "Reparented window 0x{:x} into 0x{:x} at ({},{})",
+                server_wid,
+                server_parent,
+                x,
+                y
+            );
+        }
+
+        Ok(())
+    }
+
+    fn root_window(&self) -> Option<BackendWindow> {
+        self.desktop_window
+    }
+
     fn clear_area(
         &mut self,
-        _window: BackendWindow,
-        _x: i16,
-        _y: i16,
-        _width: u16,
-        _height: u16,
+        window: BackendWindow,
+        x: i16,
+        y: i16,
+        width: u16,
+        height: u16,
     ) -> BackendResult<()> {
+        let server_wid = *self
+            .window_map
+            .lock()
+            .unwrap()
+            .get(&window.0)
+            .ok_or("Window not found")?;
+
+        // Build ClearArea request (opcode 61), exposures = False
+        let mut req = Vec::new();
+        req.push(61); // Opcode: ClearArea
+        req.push(0); // exposures
+        req.extend_from_slice(&4u16.to_le_bytes()); // Length: 4 words
+        req.extend_from_slice(&server_wid.to_le_bytes());
+        req.extend_from_slice(&x.to_le_bytes());
+        req.extend_from_slice(&y.to_le_bytes());
+        req.extend_from_slice(&width.to_le_bytes());
+        req.extend_from_slice(&height.to_le_bytes());
+
+        self.send_request(&req)?;
+
         Ok(())
     }
 
@@ -1286,6 +1712,64 @@ impl Backend for X11Backend {
         Ok(())
     }
 
+    fn fill_rectangles(
+        &mut self,
+        drawable: BackendDrawable,
+        gc: &BackendGC,
+        rectangles: &[Rectangle],
+    ) -> BackendResult<()> {
+        if rectangles.is_empty() {
+            return Ok(());
+        }
+
+        // Get server drawable ID
+        let server_drawable = match drawable {
+            BackendDrawable::Window(w) => *self
+                .window_map
+                .lock()
+                .unwrap()
+                .get(&w.0)
+                .ok_or("Window not found")?,
+            BackendDrawable::Pixmap(p) => *self
+                .pixmap_map
+                .lock()
+                .unwrap()
+                .get(&p)
+                .ok_or("Pixmap not found")?,
+        };
+
+        let gc_id = self.allocate_server_resource_id();
+        self.create_server_gc(server_drawable, gc_id, gc)?;
+
+        // One PolyFillRectangle request (opcode 70) per chunk, keeping each
+        // within the core request size limit
+        for chunk in rectangles.chunks(2000) {
+            let mut req = Vec::with_capacity(12 + chunk.len() * 8);
+            req.push(70); // Opcode: PolyFillRectangle
+            req.push(0); // Padding
+            req.extend_from_slice(&((3 + chunk.len() * 2) as u16).to_le_bytes());
+            req.extend_from_slice(&server_drawable.to_le_bytes());
+            req.extend_from_slice(&gc_id.to_le_bytes());
+            for rect in chunk {
+                req.extend_from_slice(&rect.x.to_le_bytes());
+                req.extend_from_slice(&rect.y.to_le_bytes());
+                req.extend_from_slice(&rect.width.to_le_bytes());
+                req.extend_from_slice(&rect.height.to_le_bytes());
+            }
+            self.send_request(&req)?;
+        }
+
+        if self.debug {
+            log::debug!(
+                "Filled rectangles: drawable=0x{:x}, {} rectangles",
+                server_drawable,
+                rectangles.len()
+            );
+        }
+
+        Ok(())
+    }
+
     fn draw_line(
         &mut self,
         drawable: BackendDrawable,
@@ -1342,6 +1826,61 @@ impl Backend for X11Backend {
         Ok(())
     }
 
+    fn draw_lines(
+        &mut self,
+        drawable: BackendDrawable,
+        gc: &BackendGC,
+        points: &[Point],
+    ) -> BackendResult<()> {
+        if points.len() < 2 {
+            return Ok(());
+        }
+
+        // Get server drawable ID
+        let server_drawable = match drawable {
+            BackendDrawable::Window(w) => *self
+                .window_map
+                .lock()
+                .unwrap()
+                .get(&w.0)
+                .ok_or("Window not found")?,
+            BackendDrawable::Pixmap(p) => *self
+                .pixmap_map
+                .lock()
+                .unwrap()
+                .get(&p)
+                .ok_or("Pixmap not found")?,
+        };
+
+        let gc_id = self.allocate_server_resource_id();
+        self.create_server_gc(server_drawable, gc_id, gc)?;
+
+        // Build PolyLine request (opcode 65) so joins and the dash pattern
+        // carry across points
+        let mut req = Vec::with_capacity(12 + points.len() * 4);
+        req.push(65); // Opcode: PolyLine
+        req.push(0); // Coordinate mode: Origin
+        req.extend_from_slice(&((3 + points.len()) as u16).to_le_bytes());
+        req.extend_from_slice(&server_drawable.to_le_bytes());
+        req.extend_from_slice(&gc_id.to_le_bytes());
+        for point in points {
+            req.extend_from_slice(&point.x.to_le_bytes());
+            req.extend_from_slice(&point.y.to_le_bytes());
+        }
+
+        self.send_request(&req)?;
+
+        if self.debug {
+            log::debug!(
+                "Drew polyline: drawable=0x{:x}, {} points",
+                server_drawable,
+                points.len()
+            );
+        }
+
+        Ok(())
+    }
+
     fn draw_points(
         &mut self,
         drawable: BackendDrawable,
@@ -1405,6 +1944,7 @@ impl Backend for X11Backend {
         x: i16,
         y: i16,
         text: &str,
+        _bounds: Rectangle,
     ) -> BackendResult<()> {
         // Check if we have a font
         let font_id = match self.default_font_id {
@@ -1739,7 +2279,7 @@ impl Backend for X11Backend {
 
         // Get root window as the drawable reference
         let root_drawable = if let Some(ref setup) = self.setup_info {
-            setup.roots[0].root.id().get()
+            setup.roots[self.screen].root.id().get()
         } else {
             return Err("Not initialized".into());
         };
@@ -1759,6 +2299,7 @@ impl Backend for X11Backend {
 
         // Store the mapping
         self.pixmap_map.lock().unwrap().insert(our_id, server_pid);
+        self.pixmap_depths.insert(our_id, depth);
 
         if self.debug {
             log::debug!(
@@ -1782,6 +2323,7 @@ impl Backend for X11Backend {
             .unwrap()
             .remove(&pixmap)
             .ok_or("Pixmap not found")?;
+        self.pixmap_depths.remove(&pixmap);
 
         // Build FreePixmap request (opcode 54)
         let mut req = vec![0u8; 8];
@@ -1961,35 +2503,12 @@ impl Backend for X11Backend {
     }
 
     fn poll_events(&mut self) -> BackendResult<Vec<BackendEvent>> {
-        // Collect raw event buffers first to avoid borrow issues
-        let mut raw_events: Vec<[u8; 32]> = Vec::new();
-
-        if let Some(ref mut conn) = self.connection {
-            // Set non-blocking temporarily
-            conn.set_nonblocking(true)
-                .map_err(|e| format!("Failed to set non-blocking: {}", e))?;
-
-            loop {
-                let mut buf = [0u8; 32];
-                match conn.read_exact(&mut buf) {
-                    Ok(()) => {
-                        raw_events.push(buf);
-                    }
-                    Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
-                        break; // No more events
-                    }
-                    Err(_) => {
-                        break; // Error, stop reading
-                    }
-                }
-            }
-
-            // Restore blocking mode
-            conn.set_nonblocking(false)
-                .map_err(|e| format!("Failed to restore blocking: {}", e))?;
-        }
+        let raw_events = match self.connection.as_mut() {
+            Some(conn) => conn.poll_events()?,
+            None => return Ok(Vec::new()),
+        };
+        self.log_async_errors();
 
-        // Now parse the events (no longer holding mutable borrow)
         let events = raw_events
             .iter()
             .filter_map(|buf| self.parse_x11_event(buf))
@@ -1999,23 +2518,23 @@ impl Backend for X11Backend {
     }
 
     fn flush(&mut self) -> BackendResult<()> {
-        if let Some(stream) = &mut self.connection {
-            stream.flush().map_err(|e| format!("Flush failed: {}", e))?;
+        if let Some(conn) = &mut self.connection {
+            conn.flush()?;
         }
         Ok(())
     }
 
     fn wait_for_event(&mut self) -> BackendResult<BackendEvent> {
-        if let Some(ref mut conn) = self.connection {
-            let mut buf = [0u8; 32];
-            conn.read_exact(&mut buf)
-                .map_err(|e| format!("Failed to read event: {}", e))?;
-
+        loop {
+            let buf = self
+                .connection
+                .as_mut()
+                .ok_or("Not connected to X server")?
+                .wait_for_event()?;
             if let Some(event) = self.parse_x11_event(&buf) {
                 return Ok(event);
             }
         }
-        Err("No event available".into())
     }
 
     fn list_system_fonts(&mut self) -> BackendResult<Vec<BackendFontInfo>> {
@@ -2225,6 +2744,44 @@ impl Backend for X11Backend {
         }))
     }
 
+    fn keyboard_mapping(&mut self) -> BackendResult<KeyboardMapping> {
+        let setup = self.setup_info.as_ref().ok_or("Not initialized")?;
+        let (min_keycode, max_keycode) = (setup.min_keycode, setup.max_keycode);
+
+        // GetKeyboardMapping (opcode 101): first_keycode, count
+        let count = max_keycode - min_keycode + 1;
+        let req = [101, 0, 2, 0, min_keycode, count, 0, 0];
+        let reply = self.send_request_with_reply(&req)?;
+        if reply.len() < 32 {
+            return Err("GetKeyboardMapping reply too short".into());
+        }
+        let keysyms_per_keycode = reply[1];
+        let keysyms = reply[32..]
+            .chunks_exact(4)
+            .map(|k| u32::from_le_bytes([k[0], k[1], k[2], k[3]]))
+            .collect();
+
+        // GetModifierMapping (opcode 119)
+        let reply = self.send_request_with_reply(&[119, 0, 1, 0])?;
+        if reply.len() < 32 {
+            return Err("GetModifierMapping reply too short".into());
+        }
+        let keycodes_per_modifier = reply[1];
+        let modifiers = reply[32..]
+            .iter()
+            .copied()
+            .take(8 * keycodes_per_modifier as usize)
+            .collect();
+
+        Ok(KeyboardMapping {
+            min_keycode,
+            keysyms_per_keycode,
+            keysyms,
+            keycodes_per_modifier,
+            modifiers,
+        })
+    }
+
     fn create_standard_cursor(
         &mut self,
         cursor_shape: StandardCursor,
@@ -2293,6 +2850,89 @@ impl Backend for X11Backend {
         Ok(BackendCursor(our_cursor_id))
     }
 
+    fn create_image_cursor(&mut self, frames: &[CursorFrame]) -> BackendResult<BackendCursor> {
+        if self.connection.is_none() {
+            return Ok(BackendCursor::NONE);
+        }
+        let render = self.query_render()?;
+        let Some((render, format)) =
+            render.and_then(|render| Some((render.opcode, render.format(PictFormat::Argb32)?)))
+        else {
+            return self.create_standard_cursor(StandardCursor::LeftPtr);
+        };
+
+        let mut server_cursors = Vec::with_capacity(frames.len());
+        for frame in frames {
+            server_cursors.push(self.create_render_cursor(render, format, frame)?);
+        }
+        let server_cursor_id = if let [cursor] = server_cursors[..] {
+            cursor
+        } else {
+            // RenderCreateAnimCursor: cid(4) + (cursor(4) + delay(4)) per frame
+            let cursor_id = self.allocate_server_resource_id();
+            let mut req = vec![render, 31];
+            req.extend_from_slice(&(2 + 2 * frames.len() as u16).to_le_bytes());
+            req.extend_from_slice(&cursor_id.to_le_bytes());
+            for (cursor, frame) in server_cursors.iter().zip(frames) {
+                req.extend_from_slice(&cursor.to_le_bytes());
+                req.extend_from_slice(&frame.delay.to_le_bytes());
+            }
+            self.send_request(&req)?;
+            // The animation keeps its own references to the frames
+            for cursor in server_cursors {
+                let mut req = vec![95, 0];
+                req.extend_from_slice(&2u16.to_le_bytes());
+                req.extend_from_slice(&cursor.to_le_bytes());
+                self.send_request(&req)?;
+            }
+            cursor_id
+        };
+        self.flush()?;
+
+        let our_cursor_id = self.next_cursor_id;
+        self.next_cursor_id += 1;
+        self.cursor_map
+            .lock()
+            .unwrap()
+            .insert(our_cursor_id, server_cursor_id);
+
+        if self.debug {
+            log::debug!(
+                "Created image cursor: our_id={}, server_id=0x{:x}, {} frames",
+                our_cursor_id,
+                server_cursor_id,
+                frames.len()
+            );
+        }
+
+        Ok(BackendCursor(our_cursor_id))
+    }
+
+    fn recolor_cursor(
+        &mut self,
+        cursor: BackendCursor,
+        foreground: [u16; 3],
+        background: [u16; 3],
+    ) -> BackendResult<()> {
+        if self.connection.is_none() {
+            return Ok(());
+        }
+        let Some(server_cursor_id) = self.cursor_map.lock().unwrap().get(&cursor.0).copied() else {
+            return Err("Invalid cursor handle".into());
+        };
+
+        // RecolorCursor (opcode 96): cursor(4) + foreground and background
+        // red, green, blue(2 each)
+        let mut req = vec![96, 0];
+        req.extend_from_slice(&5u16.to_le_bytes());
+        req.extend_from_slice(&server_cursor_id.to_le_bytes());
+        for channel in foreground.iter().chain(&background) {
+            req.extend_from_slice(&channel.to_le_bytes());
+        }
+        self.send_request(&req)?;
+        self.flush()
+    }
+
     fn free_cursor(&mut self, cursor: BackendCursor) -> BackendResult<()> {
         if cursor == BackendCursor::NONE {
             return Ok(());
@@ -2327,6 +2967,102 @@ impl Backend for X11Backend {
         Ok(())
     }
 
+    fn set_window_shape(
+        &mut self,
+        window: BackendWindow,
+        kind: ShapeKind,
+        rectangles: Option<&[Rectangle]>,
+    ) -> BackendResult<()> {
+        if self.connection.is_none() {
+            return Ok(());
+        }
+        let Some(shape) = self.query_shape_opcode()? else {
+            return Ok(());
+        };
+        let server_window_id = {
+            let window_map = self.window_map.lock().unwrap();
+            match window_map.get(&window.0) {
+                Some(&id) => id,
+                None => return Err("Invalid window handle".into()),
+            }
+        };
+
+        let mut req = Vec::new();
+        match rectangles {
+            Some(rectangles) => {
+                // ShapeRectangles: operation Set, ordering UnSorted
+                let length = 4 + 2 * rectangles.len();
+                let Ok(length) = u16::try_from(length) else {
+                    log::warn!("Shape of {} rectangles is too large", rectangles.len());
+                    return Ok(());
+                };
+                req.extend_from_slice(&[shape, 1]);
+                req.extend_from_slice(&length.to_le_bytes());
+                req.extend_from_slice(&[0, kind as u8, 0, 0]);
+                req.extend_from_slice(&server_window_id.to_le_bytes());
+                req.extend_from_slice(&[0; 4]); // offset
+                for rect in rectangles {
+                    req.extend_from_slice(&rect.x.to_le_bytes());
+                    req.extend_from_slice(&rect.y.to_le_bytes());
+                    req.extend_from_slice(&rect.width.to_le_bytes());
+                    req.extend_from_slice(&rect.height.to_le_bytes());
+                }
+            }
+            None => {
+                // ShapeMask with no bitmap: operation Set removes the shape
+                req.extend_from_slice(&[shape, 2]);
+                req.extend_from_slice(&5u16.to_le_bytes());
+                req.extend_from_slice(&[0, kind as u8, 0, 0]);
+                req.extend_from_slice(&server_window_id.to_le_bytes());
+                req.extend_from_slice(&[0; 4]); // offset
+                req.extend_from_slice(&0u32.to_le_bytes()); // None
+            }
+        }
+        self.send_request(&req)?;
+        self.flush()
+    }
+
+    fn set_window_border(
+        &mut self,
+        window: BackendWindow,
+        border: WindowBorder,
+    ) -> BackendResult<()> {
+        if self.connection.is_none() {
+            return Ok(());
+        }
+        let server_window_id = {
+            let window_map = self.window_map.lock().unwrap();
+            match window_map.get(&window.0) {
+                Some(&id) => id,
+                None => return Err("Invalid window handle".into()),
+            }
+        };
+        let (value_mask, value) = match border {
+            WindowBorder::Pixel(pixel) => (0x00000008u32, pixel), // CWBorderPixel
+            WindowBorder::Pixmap(tile) => {
+                // Server windows are all depth 24, and a border tile must match
+                let server_pixmap = self.pixmap_map.lock().unwrap().get(&tile.pixmap).copied();
+                match server_pixmap {
+                    Some(pixmap) if tile.depth == 24 => (0x00000004, pixmap), // CWBorderPixmap
+                    _ => {
+                        log::debug!("Border tile of depth {} not supported", tile.depth);
+                        return Ok(());
+                    }
+                }
+            }
+        };
+
+        // ChangeWindowAttributes (opcode 2)
+        let mut req = Vec::new();
+        req.push(2);
+        req.push(0);
+        req.extend_from_slice(&4u16.to_le_bytes());
+        req.extend_from_slice(&server_window_id.to_le_bytes());
+        req.extend_from_slice(&value_mask.to_le_bytes());
+        req.extend_from_slice(&value.to_le_bytes());
+        self.send_request(&req)
+    }
+
     fn set_window_cursor(
         &mut self,
         window: BackendWindow,
@@ -2379,6 +3115,61 @@ impl Backend for X11Backend {
 
         Ok(())
     }
+
+    fn composite(
+        &mut self,
+        op: Operator,
+        src: &CompositePicture,
+        mask: Option<&CompositePicture>,
+        dst: &CompositePicture,
+        src_origin: (i16, i16),
+        mask_origin: (i16, i16),
+        area: Rectangle,
+    ) -> BackendResult<()> {
+        // The upstream RENDER extension draws what it can express; the rest
+        // goes to the software compositor
+        if self.connection.is_some() {
+            if let Some(render) = self.query_render()? {
+                if self.upstream_composite(
+                    render,
+                    op,
+                    src,
+                    mask,
+                    dst,
+                    (src_origin, mask_origin),
+                    area,
+                )? {
+                    return Ok(());
+                }
+            }
+        }
+        composite::composite(self, op, src, mask, dst, src_origin, mask_origin, area)
+    }
+
+    fn warp_pointer(&mut self, x: i16, y: i16) -> BackendResult<()> {
+        if self.connection.is_none() {
+            return Ok(());
+        }
+        let root = self.setup_info.as_ref().ok_or("Not initialized")?.roots[self.screen]
+            .root
+            .id()
+            .get();
+
+        // WarpPointer (opcode 41): src-window(4), dst-window(4), src-x(2),
+        // src-y(2), src-width(2), src-height(2), dst-x(2), dst-y(2)
+        let mut req = Vec::with_capacity(24);
+        req.push(41); // Opcode: WarpPointer
+        req.push(0); // Unused
+        req.extend_from_slice(&6u16.to_le_bytes()); // Length: 6 words (24 bytes)
+        req.extend_from_slice(&0u32.to_le_bytes()); // src-window: None
+        req.extend_from_slice(&root.to_le_bytes());
+        req.extend_from_slice(&[0; 8]); // src rectangle: unused without src-window
+        req.extend_from_slice(&x.to_le_bytes());
+        req.extend_from_slice(&y.to_le_bytes());
+
+        self.send_request(&req)?;
+        self.flush()
+    }
 }
 
 impl X11Backend {
@@ -2406,6 +3197,71 @@ impl X11Backend {
         Ok(())
     }
 
+    /// Major opcode of an upstream extension, if the server has it
+    fn query_extension_opcode(&mut self, name: &[u8]) -> BackendResult<Option<u8>> {
+        // QueryExtension (opcode 98)
+        let mut req = vec![98, 0];
+        req.extend_from_slice(&(2 + name.len().div_ceil(4) as u16).to_le_bytes());
+        req.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        req.extend_from_slice(&[0, 0]);
+        req.extend_from_slice(name);
+        req.resize(8 + name.len().div_ceil(4) * 4, 0);
+        let reply = self.send_request_with_reply(&req)?;
+        if reply.len() < 10 || reply[8] == 0 {
+            return Ok(None);
+        }
+        Ok(Some(reply[9]))
+    }
+
+    /// Upstream SHAPE major opcode, queried once
+    fn query_shape_opcode(&mut self) -> BackendResult<Option<u8>> {
+        if let Some(shape) = self.shape_opcode {
+            return Ok(shape);
+        }
+        let shape = self.query_extension_opcode(b"SHAPE")?;
+        if shape.is_none() {
+            log::info!("Upstream server has no SHAPE extension; windows stay rectangular");
+        }
+        self.shape_opcode = Some(shape);
+        Ok(shape)
+    }
+
+    /// Upload a cursor image and create an upstream RENDER cursor from it
+    fn create_render_cursor(
+        &mut self,
+        render: u8,
+        format: u32,
+        frame: &CursorFrame,
+    ) -> BackendResult<u32> {
+        let pixmap = self.upload_argb(frame.width, frame.height, &frame.pixels)?;
+        let picture = self.allocate_server_resource_id();
+        let cursor = self.allocate_server_resource_id();
+
+        // RenderCreatePicture, RenderCreateCursor, then release everything
+        // but the cursor
+        let mut req = vec![render, 4];
+        req.extend_from_slice(&5u16.to_le_bytes());
+        req.extend_from_slice(&picture.to_le_bytes());
+        req.extend_from_slice(&pixmap.to_le_bytes());
+        req.extend_from_slice(&format.to_le_bytes());
+        req.extend_from_slice(&0u32.to_le_bytes());
+        self.send_request(&req)?;
+        let mut req = vec![render, 27];
+        req.extend_from_slice(&4u16.to_le_bytes());
+        req.extend_from_slice(&cursor.to_le_bytes());
+        req.extend_from_slice(&picture.to_le_bytes());
+        req.extend_from_slice(&frame.x_hot.to_le_bytes());
+        req.extend_from_slice(&frame.y_hot.to_le_bytes());
+        self.send_request(&req)?;
+        for (opcode, minor, id) in [(render, 7, picture), (54, 0, pixmap)] {
+            let mut req = vec![opcode, minor];
+            req.extend_from_slice(&2u16.to_le_bytes());
+            req.extend_from_slice(&id.to_le_bytes());
+            self.send_request(&req)?;
+        }
+        Ok(cursor)
+    }
+
     /// Ensure the cursor font is opened on the upstream X server
     fn ensure_cursor_font_opened(&mut self) -> BackendResult<()> {
         if self.cursor_font_id.is_some() {
@@ -2513,9 +3369,10 @@ impl X11Backend {
 
     /// Get a cloned connection to the real X server for direct passthrough
     /// This is useful for bidirectional proxying
-    pub fn clone_connection(&self) -> BackendResult<UnixStream> {
+    pub fn clone_connection(&self) -> BackendResult<Stream> {
         match &self.connection {
-            Some(stream) => stream
+            Some(conn) => conn
+                .stream()
                 .try_clone()
                 .map_err(|e| format!("Failed to clone connection: {}", e).into()),
             None => Err("Backend not initialized".into()),
@@ -2537,10 +3394,38 @@ impl X11Backend {
     fn parse_x11_event(&self, buf: &[u8; 32]) -> Option<BackendEvent> {
         let event_code = buf[0] & 0x7F; // Mask off the "sent-event" bit
 
-        // Extract window ID (usually at bytes 4-7 for most events)
-        let window_id = u32::from_le_bytes([buf[4], buf[5], buf[6], buf[7]]);
+        // MappingNotify (34) is sent to every client and names no window;
+        // Modifier (0) and Keyboard (1) changes alter the keyboard mapping
+        if event_code == 34 {
+            return (buf[4] <= 1).then_some(BackendEvent::MappingChanged);
+        }
+
+        // The window an event is reported against depends on its layout:
+        // input and crossing events carry it at bytes 12-15, structure
+        // events (DestroyNotify..ConfigureNotify) at bytes 8-11, and
+        // focus and expose events at bytes 4-7
+        let window_offset = match event_code {
+            2..=8 => 12,
+            17..=22 => 8,
+            _ => 4,
+        };
+        let window_id = u32::from_le_bytes([
+            buf[window_offset],
+            buf[window_offset + 1],
+            buf[window_offset + 2],
+            buf[window_offset + 3],
+        ]);
         let window = self.server_wid_to_backend(window_id)?;
 
+        // In rooted mode the pointer moving between the desktop and the
+        // client windows nested in it is not a crossing of the virtual screen
+        if (event_code == 7 || event_code == 8)
+            && Some(window) == self.desktop_window
+            && buf[1] == 2
+        {
+            return None;
+        }
+
         match event_code {
             2 => {
                 // KeyPress
@@ -2680,10 +3565,10 @@ impl X11Backend {
             }
             22 => {
                 // ConfigureNotify
-                let x = i16::from_le_bytes([buf[8], buf[9]]);
-                let y = i16::from_le_bytes([buf[10], buf[11]]);
-                let width = u16::from_le_bytes([buf[12], buf[13]]);
-                let height = u16::from_le_bytes([buf[14], buf[15]]);
+                let x = i16::from_le_bytes([buf[16], buf[17]]);
+                let y = i16::from_le_bytes([buf[18], buf[19]]);
+                let width = u16::from_le_bytes([buf[20], buf[21]]);
+                let height = u16::from_le_bytes([buf[22], buf[23]]);
                 Some(BackendEvent::Configure {
                     window,
                     x,
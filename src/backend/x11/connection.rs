@@ -0,0 +1,380 @@
+//! Pipelined connection to the upstream X server
+//!
+//! Modeled after xcb: requests are appended to an output buffer and written
+//! in batches, every request is assigned a 64-bit sequence number, and
+//! everything the server sends back is demultiplexed by type. Replies are
+//! stored by sequence until someone asks for them, errors for requests that
+//! expect a reply are handed to the waiter, and all other errors and events
+//! are queued. Nothing is read out of order, so events that arrive while
+//! waiting for a reply are never lost. Replies nobody waits for are dropped
+//! once a later request has been waited for.
+
+use super::display::Stream;
+use crate::backend::BackendResult;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::{Read, Write};
+use std::os::fd::AsRawFd;
+
+/// Write the output buffer once it grows past this size
+const FLUSH_THRESHOLD: usize = 64 * 1024;
+
+/// Size of a single read from the socket
+const READ_CHUNK: usize = 64 * 1024;
+
+/// Packet types in the first byte of everything the server sends
+const PACKET_ERROR: u8 = 0;
+const PACKET_REPLY: u8 = 1;
+const PACKET_GENERIC_EVENT: u8 = 35;
+
+pub struct X11Connection {
+    stream: Stream,
+
+    /// Requests not yet written to the socket
+    out_buf: Vec<u8>,
+
+    /// Bytes read from the socket that don't form a complete packet yet
+    in_buf: Vec<u8>,
+
+    /// Sequence number of the last request queued
+    last_request: u64,
+
+    /// Sequence number of the last request the server has answered or
+    /// generated an event after
+    last_read: u64,
+
+    /// Requests whose reply (or error) someone will wait for
+    awaiting_reply: HashSet<u64>,
+
+    /// Replies received, by request sequence; some requests, such as
+    /// ListFontsWithInfo, send several
+    replies: HashMap<u64, Vec<Vec<u8>>>,
+
+    /// Sequence number of the last request waited for
+    last_waited: u64,
+
+    /// Errors for requests that expect a reply, by request sequence
+    reply_errors: HashMap<u64, [u8; 32]>,
+
+    /// Errors for requests without replies, in arrival order
+    errors: VecDeque<[u8; 32]>,
+
+    /// Events, in arrival order
+    events: VecDeque<[u8; 32]>,
+}
+
+impl X11Connection {
+    /// Wrap a stream on which the connection setup has already completed
+    pub fn new(stream: Stream) -> Self {
+        Self {
+            stream,
+            out_buf: Vec::with_capacity(FLUSH_THRESHOLD),
+            in_buf: Vec::new(),
+            last_request: 0,
+            last_read: 0,
+            awaiting_reply: HashSet::new(),
+            replies: HashMap::new(),
+            last_waited: 0,
+            reply_errors: HashMap::new(),
+            errors: VecDeque::new(),
+            events: VecDeque::new(),
+        }
+    }
+
+    /// The underlying stream
+    pub fn stream(&self) -> &Stream {
+        &self.stream
+    }
+
+    /// Queue a request that has no reply, returning its sequence number
+    pub fn send_request(&mut self, data: &[u8]) -> BackendResult<u64> {
+        if self.out_buf.len() + data.len() > FLUSH_THRESHOLD {
+            self.flush()?;
+        }
+        self.out_buf.extend_from_slice(data);
+        self.last_request += 1;
+        Ok(self.last_request)
+    }
+
+    /// Queue a request that has a reply, returning its sequence number for
+    /// `wait_for_reply`
+    pub fn send_request_with_reply(&mut self, data: &[u8]) -> BackendResult<u64> {
+        let sequence = self.send_request(data)?;
+        self.awaiting_reply.insert(sequence);
+        Ok(sequence)
+    }
+
+    /// Write all queued requests to the socket
+    pub fn flush(&mut self) -> BackendResult<()> {
+        if self.out_buf.is_empty() {
+            return Ok(());
+        }
+        self.stream
+            .write_all(&self.out_buf)
+            .map_err(|e| format!("Failed to send X11 requests: {}", e))?;
+        self.stream
+            .flush()
+            .map_err(|e| format!("Flush failed: {}", e))?;
+        self.out_buf.clear();
+        Ok(())
+    }
+
+    /// Block until the reply to `sequence` arrives
+    ///
+    /// Events and unrelated errors read in the meantime are queued.
+    pub fn wait_for_reply(&mut self, sequence: u64) -> BackendResult<Vec<u8>> {
+        let mut replies = self.wait_for_replies(sequence, |_| true)?;
+        Ok(replies.swap_remove(0))
+    }
+
+    /// Block until all replies to a request that sends several have
+    /// arrived; `is_last` recognizes the final one
+    pub fn wait_for_replies(
+        &mut self,
+        sequence: u64,
+        is_last: impl Fn(&[u8]) -> bool,
+    ) -> BackendResult<Vec<Vec<u8>>> {
+        self.flush()?;
+        let result = loop {
+            let replies = self.replies.get(&sequence);
+            let complete = replies
+                .and_then(|replies| replies.last())
+                .is_some_and(|reply| is_last(reply));
+            // The server answers in order: once it has moved past the
+            // request, nothing more is coming for it
+            if complete || (replies.is_some() && self.last_read > sequence) {
+                break Ok(self.replies.remove(&sequence).unwrap_or_default());
+            }
+            if let Some(error) = self.reply_errors.remove(&sequence) {
+                break Err(format!(
+                    "X11 error: code={} (major opcode {}, value 0x{:x})",
+                    error[1],
+                    error[10],
+                    u32::from_le_bytes([error[4], error[5], error[6], error[7]])
+                )
+                .into());
+            }
+            if self.last_read > sequence {
+                break Err(format!("No reply for request {}", sequence).into());
+            }
+            self.read_packets(true)?;
+        };
+        self.forget_through(sequence);
+        result
+    }
+
+    /// Drop what is stored for requests up to `sequence`; requests are
+    /// waited for in order, so nobody will ask for them any more
+    fn forget_through(&mut self, sequence: u64) {
+        self.last_waited = self.last_waited.max(sequence);
+        let last = self.last_waited;
+        self.awaiting_reply.retain(|s| *s > last);
+        self.replies.retain(|s, _| *s > last);
+        self.reply_errors.retain(|s, _| *s > last);
+    }
+
+    /// Flush, read whatever the server has sent without blocking, and return
+    /// all queued events
+    pub fn poll_events(&mut self) -> BackendResult<Vec<[u8; 32]>> {
+        self.flush()?;
+        self.read_packets(false)?;
+        Ok(self.events.drain(..).collect())
+    }
+
+    /// Block until at least one event is available and return it
+    pub fn wait_for_event(&mut self) -> BackendResult<[u8; 32]> {
+        self.flush()?;
+        loop {
+            if let Some(event) = self.events.pop_front() {
+                return Ok(event);
+            }
+            self.read_packets(true)?;
+        }
+    }
+
+    /// Take errors reported for requests that have no reply
+    pub fn take_errors(&mut self) -> Vec<[u8; 32]> {
+        self.errors.drain(..).collect()
+    }
+
+    /// Read from the socket and dispatch all complete packets
+    ///
+    /// When `block` is set, waits until at least some data arrives.
+    fn read_packets(&mut self, block: bool) -> BackendResult<()> {
+        let mut chunk = vec![0u8; READ_CHUNK];
+        let mut received = false;
+        if block {
+            let n = self
+                .stream
+                .read(&mut chunk)
+                .map_err(|e| format!("Failed to read from X server: {}", e))?;
+            if n == 0 {
+                return Err("X server closed the connection".into());
+            }
+            self.in_buf.extend_from_slice(&chunk[..n]);
+            received = true;
+        }
+
+        // Drain whatever else is available without toggling the socket mode
+        loop {
+            use nix::sys::socket::{recv, MsgFlags};
+            match recv(self.stream.as_raw_fd(), &mut chunk, MsgFlags::MSG_DONTWAIT) {
+                Ok(0) if !received => return Err("X server closed the connection".into()),
+                Ok(0) => break,
+                Ok(n) => {
+                    self.in_buf.extend_from_slice(&chunk[..n]);
+                    received = true;
+                }
+                Err(nix::errno::Errno::EAGAIN) => break,
+                Err(nix::errno::Errno::EINTR) => continue,
+                Err(e) => return Err(format!("Failed to read from X server: {}", e).into()),
+            }
+        }
+
+        self.dispatch_packets();
+        Ok(())
+    }
+
+    /// Split the input buffer into packets and route each one
+    fn dispatch_packets(&mut self) {
+        let mut offset = 0;
+        while self.in_buf.len() - offset >= 32 {
+            let header = &self.in_buf[offset..offset + 32];
+            // Only replies and generic events (not sent through SendEvent)
+            // carry data past the 32-byte header
+            let length = match header[0] {
+                PACKET_REPLY | PACKET_GENERIC_EVENT => {
+                    32 + u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize
+                        * 4
+                }
+                _ => 32,
+            };
+            if self.in_buf.len() - offset < length {
+                break;
+            }
+            let packet = self.in_buf[offset..offset + length].to_vec();
+            offset += length;
+            self.dispatch_packet(packet);
+        }
+        self.in_buf.drain(..offset);
+    }
+
+    fn dispatch_packet(&mut self, packet: Vec<u8>) {
+        let kind = packet[0] & 0x7F;
+
+        // KeymapNotify is the only packet without a sequence number
+        if kind != 11 {
+            let sequence = self.widen_sequence(u16::from_le_bytes([packet[2], packet[3]]));
+            self.last_read = self.last_read.max(sequence);
+
+            match kind {
+                PACKET_ERROR => {
+                    let mut error = [0u8; 32];
+                    error.copy_from_slice(&packet[..32]);
+                    if self.awaiting_reply.contains(&sequence) {
+                        self.reply_errors.insert(sequence, error);
+                    } else {
+                        self.errors.push_back(error);
+                    }
+                    return;
+                }
+                PACKET_REPLY => {
+                    if self.awaiting_reply.contains(&sequence) {
+                        self.replies.entry(sequence).or_default().push(packet);
+                    } else {
+                        log::debug!("Dropping unexpected reply for request {}", sequence);
+                    }
+                    return;
+                }
+                _ => {}
+            }
+        }
+
+        // Events; generic events keep only their fixed 32-byte part
+        let mut event = [0u8; 32];
+        event.copy_from_slice(&packet[..32]);
+        self.events.push_back(event);
+    }
+
+    /// Recover the full sequence number from the 16 bits on the wire. The
+    /// server never answers a request we haven't sent, so the packet belongs
+    /// to the most recent request with matching low bits.
+    fn widen_sequence(&self, sequence: u16) -> u64 {
+        let behind = (self.last_request as u16).wrapping_sub(sequence) as u64;
+        self.last_request.saturating_sub(behind)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixStream;
+
+    fn packet(kind: u8, sequence: u16, extra_words: u32) -> Vec<u8> {
+        let mut buf = vec![0u8; 32 + extra_words as usize * 4];
+        buf[0] = kind;
+        buf[2..4].copy_from_slice(&sequence.to_le_bytes());
+        if kind == PACKET_REPLY {
+            buf[4..8].copy_from_slice(&extra_words.to_le_bytes());
+        }
+        buf
+    }
+
+    #[test]
+    fn test_demultiplex_by_sequence() {
+        let (client, mut server) = UnixStream::pair().unwrap();
+        let mut conn = X11Connection::new(Stream::Unix(client));
+
+        conn.send_request(&[0u8; 4]).unwrap();
+        let first = conn.send_request_with_reply(&[0u8; 4]).unwrap();
+        let second = conn.send_request_with_reply(&[0u8; 4]).unwrap();
+        assert_eq!((first, second), (2, 3));
+
+        // Error for the request without reply, an event, then the replies
+        // answered in order: an error for #2 and a reply with data for #3
+        server.write_all(&packet(PACKET_ERROR, 1, 0)).unwrap();
+        server.write_all(&packet(12, 1, 0)).unwrap();
+        server.write_all(&packet(PACKET_ERROR, 2, 0)).unwrap();
+        server.write_all(&packet(PACKET_REPLY, 3, 2)).unwrap();
+
+        assert_eq!(conn.wait_for_reply(second).unwrap().len(), 40);
+        assert!(conn.wait_for_reply(first).is_err());
+        assert_eq!(conn.poll_events().unwrap().len(), 1);
+        assert_eq!(conn.take_errors().len(), 1);
+    }
+
+    #[test]
+    fn test_multiple_replies_and_eviction() {
+        let (client, mut server) = UnixStream::pair().unwrap();
+        let mut conn = X11Connection::new(Stream::Unix(client));
+
+        let skipped = conn.send_request_with_reply(&[0u8; 4]).unwrap();
+        conn.send_request(&[0u8; 4]).unwrap();
+        let listed = conn.send_request_with_reply(&[0u8; 4]).unwrap();
+
+        // A reply nobody waits for, one for a request without reply, then
+        // two replies to the same request; the second is the last one
+        server.write_all(&packet(PACKET_REPLY, 1, 0)).unwrap();
+        server.write_all(&packet(PACKET_REPLY, 2, 0)).unwrap();
+        let mut more = packet(PACKET_REPLY, 3, 1);
+        more[1] = 1;
+        server.write_all(&more).unwrap();
+        server.write_all(&packet(PACKET_REPLY, 3, 0)).unwrap();
+
+        let replies = conn
+            .wait_for_replies(listed, |reply| reply[1] == 0)
+            .unwrap();
+        assert_eq!(replies.iter().map(Vec::len).collect::<Vec<_>>(), [36, 32]);
+        // Everything up to the request waited for is gone
+        assert!(conn.replies.is_empty() && conn.awaiting_reply.is_empty());
+        assert!(conn.wait_for_reply(skipped).is_err());
+    }
+
+    #[test]
+    fn test_widen_sequence_wraps() {
+        let (client, _server) = UnixStream::pair().unwrap();
+        let mut conn = X11Connection::new(Stream::Unix(client));
+        conn.last_request = 0x1_0005;
+        assert_eq!(conn.widen_sequence(0x0003), 0x1_0003);
+        assert_eq!(conn.widen_sequence(0xFFFF), 0xFFFF);
+    }
+}
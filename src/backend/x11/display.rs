@@ -0,0 +1,299 @@
+//! DISPLAY parsing and transport selection
+//!
+//! Understands the same display names as Xlib/xcb:
+//! - `:N[.S]` and `unix:N[.S]` - local socket (abstract first on Linux)
+//! - `host:N[.S]` and `tcp/host:N[.S]` - TCP to port 6000 + N
+//! - `[::1]:N` - TCP over IPv6
+//! - `/path/to/socket[:N[.S]]` - a specific Unix socket (e.g. XQuartz launchd)
+//! - `@name[:N[.S]]` - a specific Linux abstract socket
+
+use crate::backend::BackendResult;
+use std::io::{Read, Write};
+use std::net::{IpAddr, TcpStream, ToSocketAddrs};
+use std::os::fd::{AsRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+
+/// First TCP port used by X servers
+const X_TCP_PORT: u16 = 6000;
+
+/// A parsed DISPLAY string
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DisplayName {
+    /// Host to connect to; empty for the local machine
+    pub host: String,
+    /// Explicit protocol (`tcp`, `inet`, `inet6`, `unix`), if given
+    pub protocol: Option<String>,
+    /// Explicit socket path (`/path` or `@abstract`), if given
+    pub socket: Option<String>,
+    pub display: u16,
+    pub screen: u16,
+}
+
+/// A way of reaching the display, tried in order
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Transport {
+    /// Unix domain socket at a filesystem path
+    Unix(String),
+    /// Linux abstract Unix domain socket (name without the leading NUL)
+    Abstract(String),
+    /// TCP connection to host and port
+    Tcp(String, u16),
+}
+
+/// An open connection to the X server over any transport
+#[derive(Debug)]
+pub enum Stream {
+    Unix(UnixStream),
+    Tcp(TcpStream),
+}
+
+impl DisplayName {
+    /// Parse a DISPLAY string
+    pub fn parse(name: &str) -> Result<Self, String> {
+        let invalid = || format!("Invalid display name: {:?}", name);
+
+        // Explicit socket: everything up to an optional trailing :N[.S]
+        if name.starts_with('/') || name.starts_with('@') {
+            let (socket, display, screen) = match name.rsplit_once(':') {
+                Some((socket, number)) => {
+                    let (display, screen) = Self::parse_number(number).ok_or_else(invalid)?;
+                    (socket, display, screen)
+                }
+                None => {
+                    // .../XN names the display itself
+                    let digits: String = name
+                        .chars()
+                        .rev()
+                        .take_while(|c| c.is_ascii_digit())
+                        .collect::<Vec<_>>()
+                        .into_iter()
+                        .rev()
+                        .collect();
+                    (name, digits.parse().unwrap_or(0), 0)
+                }
+            };
+            return Ok(DisplayName {
+                host: String::new(),
+                protocol: None,
+                socket: Some(socket.to_string()),
+                display,
+                screen,
+            });
+        }
+
+        let (protocol, rest) = match name.split_once('/') {
+            Some((protocol, rest)) => (Some(protocol.to_string()), rest),
+            None => (None, name),
+        };
+        let (host, number) = rest.rsplit_once(':').ok_or_else(invalid)?;
+        let (display, screen) = Self::parse_number(number).ok_or_else(invalid)?;
+
+        // IPv6 literals may be bracketed
+        let host = host
+            .strip_prefix('[')
+            .and_then(|h| h.strip_suffix(']'))
+            .unwrap_or(host);
+
+        Ok(DisplayName {
+            host: host.to_string(),
+            protocol,
+            socket: None,
+            display,
+            screen,
+        })
+    }
+
+    /// Parse the `N[.S]` part of a display name
+    fn parse_number(number: &str) -> Option<(u16, u16)> {
+        let (display, screen) = match number.split_once('.') {
+            Some((display, screen)) => (display, Some(screen)),
+            None => (number, None),
+        };
+        let display = display.parse().ok()?;
+        let screen = match screen {
+            Some(screen) => screen.parse().ok()?,
+            None => 0,
+        };
+        Some((display, screen))
+    }
+
+    /// Whether this display is reached through a local socket
+    pub fn is_local(&self) -> bool {
+        match self.protocol.as_deref() {
+            Some("unix") => true,
+            Some(_) => false,
+            None => self.socket.is_some() || self.host.is_empty() || self.host == "unix",
+        }
+    }
+
+    /// Transports to try, in order of preference
+    pub fn transports(&self) -> Vec<Transport> {
+        if let Some(ref socket) = self.socket {
+            return match socket.strip_prefix('@') {
+                Some(name) => vec![Transport::Abstract(name.to_string())],
+                None => vec![Transport::Unix(socket.clone())],
+            };
+        }
+
+        if self.is_local() {
+            let path = format!("/tmp/.X11-unix/X{}", self.display);
+            let mut transports = Vec::new();
+            if cfg!(target_os = "linux") {
+                transports.push(Transport::Abstract(path.clone()));
+            }
+            transports.push(Transport::Unix(path));
+            // Like Xlib, fall back to TCP on localhost for a bare :N
+            if self.protocol.is_none() && self.host.is_empty() {
+                transports.push(Transport::Tcp(
+                    "localhost".to_string(),
+                    X_TCP_PORT + self.display,
+                ));
+            }
+            return transports;
+        }
+
+        vec![Transport::Tcp(self.host.clone(), X_TCP_PORT + self.display)]
+    }
+}
+
+impl Transport {
+    /// Open a connection over this transport
+    pub fn connect(&self) -> BackendResult<Stream> {
+        match self {
+            Transport::Unix(path) => Ok(Stream::Unix(
+                UnixStream::connect(path).map_err(|e| format!("{}: {}", path, e))?,
+            )),
+            Transport::Abstract(name) => Self::connect_abstract(name),
+            Transport::Tcp(host, port) => {
+                let stream = TcpStream::connect((host.as_str(), *port))
+                    .map_err(|e| format!("{}:{}: {}", host, port, e))?;
+                // Requests are batched already; don't delay them further
+                stream.set_nodelay(true).ok();
+                Ok(Stream::Tcp(stream))
+            }
+        }
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn connect_abstract(name: &str) -> BackendResult<Stream> {
+        use nix::sys::socket::*;
+        use std::os::fd::{FromRawFd, IntoRawFd};
+
+        let socket_fd = socket(
+            AddressFamily::Unix,
+            SockType::Stream,
+            SockFlag::SOCK_CLOEXEC,
+            None,
+        )
+        .map_err(|e| format!("Failed to create socket: {}", e))?;
+
+        let addr = UnixAddr::new_abstract(name.as_bytes())
+            .map_err(|e| format!("Failed to create abstract address: {}", e))?;
+        connect(socket_fd.as_raw_fd(), &addr).map_err(|e| format!("@{}: {}", name, e))?;
+
+        // Hand ownership of the fd to the stream
+        let stream = unsafe { UnixStream::from_raw_fd(socket_fd.into_raw_fd()) };
+        Ok(Stream::Unix(stream))
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    fn connect_abstract(name: &str) -> BackendResult<Stream> {
+        Err(format!(
+            "@{}: abstract sockets are not supported on this platform",
+            name
+        )
+        .into())
+    }
+}
+
+impl Stream {
+    pub fn try_clone(&self) -> std::io::Result<Stream> {
+        match self {
+            Stream::Unix(s) => s.try_clone().map(Stream::Unix),
+            Stream::Tcp(s) => s.try_clone().map(Stream::Tcp),
+        }
+    }
+
+    /// The address Xauthority entries are matched against: None for local
+    /// sockets, the server's IP address for TCP
+    pub fn peer_ip(&self) -> Option<IpAddr> {
+        match self {
+            Stream::Unix(_) => None,
+            Stream::Tcp(s) => s.peer_addr().ok().map(|a| a.ip()),
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Stream::Unix(s) => s.read(buf),
+            Stream::Tcp(s) => s.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Stream::Unix(s) => s.write(buf),
+            Stream::Tcp(s) => s.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Stream::Unix(s) => s.flush(),
+            Stream::Tcp(s) => s.flush(),
+        }
+    }
+}
+
+impl AsRawFd for Stream {
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            Stream::Unix(s) => s.as_raw_fd(),
+            Stream::Tcp(s) => s.as_raw_fd(),
+        }
+    }
+}
+
+/// Resolve a host name to the addresses Xauthority entries may use
+pub fn resolve_host(host: &str) -> Vec<IpAddr> {
+    (host, 0)
+        .to_socket_addrs()
+        .map(|addrs| addrs.map(|a| a.ip()).collect())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_display_names() {
+        let local = DisplayName::parse(":1.2").unwrap();
+        assert_eq!((local.display, local.screen), (1, 2));
+        assert!(local.is_local());
+
+        let tcp = DisplayName::parse("tcp/example.org:3").unwrap();
+        assert_eq!(
+            tcp.transports(),
+            vec![Transport::Tcp("example.org".into(), 6003)]
+        );
+
+        let host = DisplayName::parse("10.0.0.1:0").unwrap();
+        assert!(!host.is_local());
+        assert_eq!(DisplayName::parse("[::1]:2").unwrap().host, "::1");
+
+        let abstract_socket = DisplayName::parse("@/tmp/.X11-unix/X5").unwrap();
+        assert_eq!(abstract_socket.display, 5);
+        assert_eq!(
+            abstract_socket.transports(),
+            vec![Transport::Abstract("/tmp/.X11-unix/X5".into())]
+        );
+
+        assert!(DisplayName::parse("nonsense").is_err());
+        assert!(DisplayName::parse(":x").is_err());
+    }
+}
@@ -0,0 +1,503 @@
+//! Compositing on the upstream RENDER extension
+//!
+//! The pictures taking part in a Composite are recreated upstream for the
+//! request and freed after it: drawables get a picture of the matching
+//! upstream format, solid fills and gradients are created as they are, and
+//! pixels held by the server, such as glyph and trapezoid masks, are
+//! uploaded to a scratch pixmap. Composites with a picture the upstream
+//! server cannot express go to the software compositor.
+
+use super::X11Backend;
+use crate::backend::composite::{
+    CompositePicture, Filter, Gradient, GradientShape, Operator, PictFormat, PictureSource, Repeat,
+};
+use crate::backend::{Backend, BackendDrawable, BackendResult};
+use crate::protocol::Rectangle;
+
+/// The upstream RENDER extension
+#[derive(Debug, Clone, Copy)]
+pub(super) struct UpstreamRender {
+    pub opcode: u8,
+    minor_version: u32,
+    /// Picture formats for ARGB32, RGB24, A8 and A1
+    formats: [Option<u32>; 4],
+}
+
+impl UpstreamRender {
+    /// The upstream picture format storing `format`
+    pub fn format(&self, format: PictFormat) -> Option<u32> {
+        self.formats[match format {
+            PictFormat::Argb32 => 0,
+            PictFormat::Rgb24 => 1,
+            PictFormat::A8 => 2,
+            PictFormat::A1 => 3,
+        }]
+    }
+}
+
+/// A 16.16 fixed-point number
+fn fixed(v: f64) -> i32 {
+    (v * 65536.0).round() as i32
+}
+
+/// A RENDER request of a 4-byte first field, usually a picture, and the
+/// rest of its fields, padded and with its length filled in
+fn render_request(opcode: u8, minor: u8, first: u32, body: &[u8]) -> Vec<u8> {
+    let mut req = vec![opcode, minor, 0, 0];
+    req.extend_from_slice(&first.to_le_bytes());
+    req.extend_from_slice(body);
+    req.resize(req.len().div_ceil(4) * 4, 0);
+    let length = (req.len() / 4) as u16;
+    req[2..4].copy_from_slice(&length.to_le_bytes());
+    req
+}
+
+/// CreateLinearGradient, CreateRadialGradient or CreateConicalGradient
+fn gradient_request(opcode: u8, picture: u32, gradient: &Gradient) -> Vec<u8> {
+    let (minor, geometry) = match gradient.shape {
+        GradientShape::Linear { p1, p2 } => (34, vec![p1.0, p1.1, p2.0, p2.1]),
+        GradientShape::Radial { inner, outer } => (
+            35,
+            vec![inner.0, inner.1, outer.0, outer.1, inner.2, outer.2],
+        ),
+        GradientShape::Conical { center, angle } => (36, vec![center.0, center.1, angle]),
+    };
+    let mut body = Vec::new();
+    for v in geometry {
+        body.extend_from_slice(&fixed(v).to_le_bytes());
+    }
+    body.extend_from_slice(&(gradient.stops.len() as u32).to_le_bytes());
+    for (offset, _) in &gradient.stops {
+        body.extend_from_slice(&fixed(*offset).to_le_bytes());
+    }
+    for (_, [a, r, g, b]) in &gradient.stops {
+        for channel in [r, g, b, a] {
+            body.extend_from_slice(&((channel * 65535.0).round() as u16).to_le_bytes());
+        }
+    }
+    render_request(opcode, minor, picture, &body)
+}
+
+impl X11Backend {
+    /// Largest request the upstream server accepts, in bytes
+    fn max_request_bytes(&self) -> usize {
+        self.setup_info
+            .as_ref()
+            .map_or(4096, |setup| setup.maximum_request_length as usize)
+            * 4
+    }
+
+    /// Find the upstream RENDER extension and its picture formats, once
+    pub(super) fn query_render(&mut self) -> BackendResult<Option<UpstreamRender>> {
+        if let Some(render) = self.render {
+            return Ok(render);
+        }
+
+        let Some(opcode) = self.query_extension_opcode(b"RENDER")? else {
+            log::info!(
+                "Upstream server has no RENDER extension; compositing is done in software \
+                 and image cursors are unavailable"
+            );
+            self.render = Some(None);
+            return Ok(None);
+        };
+
+        // RenderQueryVersion: client version 0.11
+        let mut req = vec![opcode, 0];
+        req.extend_from_slice(&3u16.to_le_bytes());
+        req.extend_from_slice(&0u32.to_le_bytes());
+        req.extend_from_slice(&11u32.to_le_bytes());
+        let reply = self.send_request_with_reply(&req)?;
+        let minor_version = reply
+            .get(12..16)
+            .map_or(0, |v| u32::from_le_bytes([v[0], v[1], v[2], v[3]]));
+
+        // RenderQueryPictFormats: formats of 28 bytes follow the header
+        let mut req = vec![opcode, 1];
+        req.extend_from_slice(&1u16.to_le_bytes());
+        let reply = self.send_request_with_reply(&req)?;
+        let count = reply
+            .get(8..12)
+            .map_or(0, |n| u32::from_le_bytes([n[0], n[1], n[2], n[3]]) as usize);
+        let formats = reply
+            .get(32..)
+            .unwrap_or_default()
+            .chunks_exact(28)
+            .take(count);
+        // Direct formats with 8 bits per color channel at the ARGB shifts:
+        // red 16, green 8 and blue 0, and alpha at 24 or absent
+        let mut found = [None; 4];
+        for f in formats {
+            let field = |i: usize| u16::from_le_bytes([f[i], f[i + 1]]);
+            let id = u32::from_le_bytes([f[0], f[1], f[2], f[3]]);
+            let rgb = (
+                field(8),
+                field(10),
+                field(12),
+                field(14),
+                field(16),
+                field(18),
+            );
+            let alpha = (field(20), field(22));
+            let slot = match (f[5], rgb, alpha) {
+                (32, (16, 0xff, 8, 0xff, 0, 0xff), (24, 0xff)) => 0,
+                (24, (16, 0xff, 8, 0xff, 0, 0xff), (_, 0)) => 1,
+                (8, (_, 0, _, 0, _, 0), (0, 0xff)) => 2,
+                (1, (_, 0, _, 0, _, 0), (0, 1)) => 3,
+                _ => continue,
+            };
+            if f[4] == 1 && found[slot].is_none() {
+                found[slot] = Some(id);
+            }
+        }
+        let render = UpstreamRender {
+            opcode,
+            minor_version,
+            formats: found,
+        };
+        if render.format(PictFormat::Argb32).is_none() {
+            log::info!(
+                "Upstream server has no ARGB32 picture format; image cursors are unavailable"
+            );
+        }
+        self.render = Some(Some(render));
+        Ok(Some(render))
+    }
+
+    /// Upload premultiplied ARGB pixels, row by row, into a new depth 32
+    /// pixmap
+    pub(super) fn upload_argb(
+        &mut self,
+        width: u16,
+        height: u16,
+        pixels: &[u32],
+    ) -> BackendResult<u32> {
+        let root = self.setup_info.as_ref().ok_or("Not initialized")?.roots[self.screen]
+            .root
+            .id()
+            .get();
+        let pixmap = self.allocate_server_resource_id();
+        let gc = self.allocate_server_resource_id();
+
+        // CreatePixmap (opcode 53) of depth 32, and a GC for it (opcode 55)
+        let mut req = vec![53, 32];
+        req.extend_from_slice(&4u16.to_le_bytes());
+        req.extend_from_slice(&pixmap.to_le_bytes());
+        req.extend_from_slice(&root.to_le_bytes());
+        req.extend_from_slice(&width.to_le_bytes());
+        req.extend_from_slice(&height.to_le_bytes());
+        self.send_request(&req)?;
+        let mut req = vec![55, 0];
+        req.extend_from_slice(&4u16.to_le_bytes());
+        req.extend_from_slice(&gc.to_le_bytes());
+        req.extend_from_slice(&pixmap.to_le_bytes());
+        req.extend_from_slice(&0u32.to_le_bytes());
+        self.send_request(&req)?;
+
+        // PutImage (opcode 72) as depth 32 ZPixmaps of as many rows as fit
+        // in a request
+        let row_bytes = width as usize * 4;
+        let rows = (self.max_request_bytes() - 24) / row_bytes.max(1);
+        if rows == 0 {
+            return Err("Image rows too wide for a request".into());
+        }
+        for (band, chunk) in pixels.chunks(width as usize * rows).enumerate() {
+            let mut req = vec![72, 2];
+            req.extend_from_slice(&((6 + chunk.len()) as u16).to_le_bytes());
+            req.extend_from_slice(&pixmap.to_le_bytes());
+            req.extend_from_slice(&gc.to_le_bytes());
+            req.extend_from_slice(&width.to_le_bytes());
+            req.extend_from_slice(&((chunk.len() / width as usize) as u16).to_le_bytes());
+            req.extend_from_slice(&0i16.to_le_bytes());
+            req.extend_from_slice(&((band * rows) as i16).to_le_bytes());
+            req.extend_from_slice(&[0, 32, 0, 0]);
+            for pixel in chunk {
+                req.extend_from_slice(&pixel.to_le_bytes());
+            }
+            self.send_request(&req)?;
+        }
+
+        // FreeGC (opcode 60)
+        let mut req = vec![60, 0];
+        req.extend_from_slice(&2u16.to_le_bytes());
+        req.extend_from_slice(&gc.to_le_bytes());
+        self.send_request(&req)?;
+        Ok(pixmap)
+    }
+
+    /// Composite on the upstream server; false if a picture can't be
+    /// expressed there
+    #[allow(clippy::too_many_arguments)]
+    pub(super) fn upstream_composite(
+        &mut self,
+        render: UpstreamRender,
+        op: Operator,
+        src: &CompositePicture,
+        mask: Option<&CompositePicture>,
+        dst: &CompositePicture,
+        (src_origin, mask_origin): ((i16, i16), (i16, i16)),
+        area: Rectangle,
+    ) -> BackendResult<bool> {
+        // Blend modes came with RENDER 0.11
+        if op.to_u8() >= 0x30 && render.minor_version < 11
+            || !matches!(dst.source, PictureSource::Drawable { .. })
+            || ![Some(src), mask, Some(dst)]
+                .into_iter()
+                .flatten()
+                .all(|picture| self.expressible(&render, picture))
+        {
+            return Ok(false);
+        }
+
+        let mut created = Vec::new();
+        let src_id = self.upstream_picture(&render, src, &mut created)?;
+        let mask_id = match mask {
+            Some(mask) => self.upstream_picture(&render, mask, &mut created)?,
+            None => 0,
+        };
+        let dst_id = self.upstream_picture(&render, dst, &mut created)?;
+
+        // RenderComposite: op(1) + pad(3), the pictures, then the area
+        let mut body = Vec::new();
+        for id in [src_id, mask_id, dst_id] {
+            body.extend_from_slice(&id.to_le_bytes());
+        }
+        for v in [
+            src_origin.0,
+            src_origin.1,
+            mask_origin.0,
+            mask_origin.1,
+            area.x,
+            area.y,
+        ] {
+            body.extend_from_slice(&v.to_le_bytes());
+        }
+        body.extend_from_slice(&area.width.to_le_bytes());
+        body.extend_from_slice(&area.height.to_le_bytes());
+        let op = op.to_u8() as u32;
+        self.send_request(&render_request(render.opcode, 8, op, &body))?;
+
+        // Release what was created, the pictures before their pixmaps
+        for (opcode, minor, id) in created.into_iter().rev() {
+            let mut req = vec![opcode, minor];
+            req.extend_from_slice(&2u16.to_le_bytes());
+            req.extend_from_slice(&id.to_le_bytes());
+            self.send_request(&req)?;
+        }
+        self.flush()?;
+        Ok(true)
+    }
+
+    /// Depth of the upstream drawable behind a backend drawable
+    fn drawable_depth(&self, drawable: BackendDrawable) -> Option<u8> {
+        match drawable {
+            BackendDrawable::Window(_) => {
+                Some(self.setup_info.as_ref()?.roots[self.screen].root_depth)
+            }
+            BackendDrawable::Pixmap(p) => self.pixmap_depths.get(&p).copied(),
+        }
+    }
+
+    /// Whether the upstream server can show `picture` as the compositor would
+    fn expressible(&self, render: &UpstreamRender, picture: &CompositePicture) -> bool {
+        let source = match &picture.source {
+            PictureSource::Solid(_) => true,
+            // Gradients came with RENDER 0.10
+            PictureSource::Gradient(_) => render.minor_version >= 10,
+            PictureSource::Pixels { width, height, .. } => {
+                *width > 0
+                    && *height > 0
+                    && *width as usize * 4 + 24 <= self.max_request_bytes()
+                    && render.format(PictFormat::Argb32).is_some()
+            }
+            PictureSource::Drawable {
+                drawable, format, ..
+            } => {
+                render.format(*format).is_some()
+                    && self.drawable_depth(*drawable) == Some(format.depth())
+            }
+        };
+        let clip_fits = picture
+            .clip
+            .as_ref()
+            .is_none_or(|clip| 12 + clip.len() * 8 <= self.max_request_bytes());
+        let filter_fits = match &picture.filter {
+            Filter::Convolution { kernel, .. } => 28 + kernel.len() * 4 <= self.max_request_bytes(),
+            _ => true,
+        };
+        source
+            && clip_fits
+            && filter_fits
+            && picture
+                .alpha_map
+                .as_ref()
+                .is_none_or(|(map, _, _)| self.expressible(render, map))
+    }
+
+    /// Create an upstream picture showing `picture`, adding what must be
+    /// freed after the composite to `created` as (opcode, minor, ID)
+    fn upstream_picture(
+        &mut self,
+        render: &UpstreamRender,
+        picture: &CompositePicture,
+        created: &mut Vec<(u8, u8, u32)>,
+    ) -> BackendResult<u32> {
+        let opcode = render.opcode;
+        // Picture attributes as (value-mask bit, value), in value-list order
+        let mut values = Vec::new();
+        if picture.repeat != Repeat::None {
+            values.push((1 << 0, picture.repeat as u32));
+        }
+        if let Some((map, x, y)) = &picture.alpha_map {
+            let map = self.upstream_picture(render, map, created)?;
+            values.push((1 << 1, map));
+            values.push((1 << 2, *x as i32 as u32));
+            values.push((1 << 3, *y as i32 as u32));
+        }
+        if picture.component_alpha {
+            values.push((1 << 12, 1));
+        }
+
+        let id = self.allocate_server_resource_id();
+        match &picture.source {
+            PictureSource::Solid(color) => {
+                // CreateSolidFill, its premultiplied color widened to 16 bits
+                let mut body = Vec::new();
+                for shift in [16, 8, 0, 24] {
+                    let channel = (color >> shift & 0xff) as u16 * 257;
+                    body.extend_from_slice(&channel.to_le_bytes());
+                }
+                self.send_request(&render_request(opcode, 33, id, &body))?;
+            }
+            PictureSource::Gradient(gradient) => {
+                self.send_request(&gradient_request(opcode, id, gradient))?;
+            }
+            PictureSource::Pixels {
+                width,
+                height,
+                pixels,
+            } => {
+                let pixmap = self.upload_argb(*width, *height, pixels)?;
+                created.push((54, 0, pixmap));
+                let format = render
+                    .format(PictFormat::Argb32)
+                    .ok_or("No ARGB32 format")?;
+                self.create_upstream_picture(opcode, id, pixmap, format)?;
+            }
+            PictureSource::Drawable {
+                drawable, format, ..
+            } => {
+                let server_drawable = match drawable {
+                    BackendDrawable::Window(w) => {
+                        self.window_map.lock().unwrap().get(&w.0).copied()
+                    }
+                    BackendDrawable::Pixmap(p) => self.pixmap_map.lock().unwrap().get(p).copied(),
+                }
+                .ok_or("Drawable not found")?;
+                let format = render.format(*format).ok_or("No picture format")?;
+                self.create_upstream_picture(opcode, id, server_drawable, format)?;
+            }
+        }
+        created.push((opcode, 7, id));
+
+        // ChangePicture
+        if !values.is_empty() {
+            let mask = values.iter().fold(0u32, |mask, (bit, _)| mask | bit);
+            let mut body = mask.to_le_bytes().to_vec();
+            for (_, value) in &values {
+                body.extend_from_slice(&value.to_le_bytes());
+            }
+            self.send_request(&render_request(opcode, 5, id, &body))?;
+        }
+        // SetPictureClipRectangles, the origin already in the rectangles
+        if let Some(clip) = &picture.clip {
+            let mut body = vec![0; 4];
+            for r in clip {
+                body.extend_from_slice(&r.x.to_le_bytes());
+                body.extend_from_slice(&r.y.to_le_bytes());
+                body.extend_from_slice(&r.width.to_le_bytes());
+                body.extend_from_slice(&r.height.to_le_bytes());
+            }
+            self.send_request(&render_request(opcode, 6, id, &body))?;
+        }
+        // SetPictureTransform
+        if let Some(transform) = &picture.transform {
+            let mut body = Vec::new();
+            for v in transform.0.iter().flatten() {
+                body.extend_from_slice(&fixed(*v).to_le_bytes());
+            }
+            self.send_request(&render_request(opcode, 28, id, &body))?;
+        }
+        // SetPictureFilter: name length, pad, the padded name, then values
+        let filter: Option<(&[u8], Vec<f64>)> = match &picture.filter {
+            Filter::Nearest => None,
+            Filter::Bilinear => Some((b"bilinear", Vec::new())),
+            Filter::Convolution {
+                width,
+                height,
+                kernel,
+            } => {
+                let mut params = vec![*width as f64, *height as f64];
+                params.extend_from_slice(kernel);
+                Some((b"convolution", params))
+            }
+        };
+        if let Some((name, params)) = filter {
+            let mut body = (name.len() as u16).to_le_bytes().to_vec();
+            body.extend_from_slice(&[0, 0]);
+            body.extend_from_slice(name);
+            body.resize(4 + name.len().div_ceil(4) * 4, 0);
+            for v in params {
+                body.extend_from_slice(&fixed(v).to_le_bytes());
+            }
+            self.send_request(&render_request(opcode, 30, id, &body))?;
+        }
+        Ok(id)
+    }
+
+    /// CreatePicture on an upstream drawable, without attributes
+    fn create_upstream_picture(
+        &mut self,
+        opcode: u8,
+        picture: u32,
+        drawable: u32,
+        format: u32,
+    ) -> BackendResult<()> {
+        let mut body = Vec::new();
+        for v in [drawable, format, 0] {
+            body.extend_from_slice(&v.to_le_bytes());
+        }
+        self.send_request(&render_request(opcode, 4, picture, &body))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_request_encoding() {
+        // Padded to whole words, with the length in words
+        let req = render_request(139, 30, 7, &[8, 0, 0, 0, b'b', b'i']);
+        assert_eq!(req.len(), 16);
+        assert_eq!(u16::from_le_bytes([req[2], req[3]]), 4);
+        assert_eq!(&req[4..8], &7u32.to_le_bytes());
+
+        // A linear gradient: picture, two points, stop count, then the
+        // offsets and their colors
+        let gradient = Gradient {
+            shape: GradientShape::Linear {
+                p1: (0.0, 0.0),
+                p2: (1.5, 0.0),
+            },
+            stops: vec![(0.0, [1.0, 1.0, 0.0, 0.0]), (1.0, [0.5, 0.0, 0.0, 1.0])],
+        };
+        let req = gradient_request(139, 1, &Arc::new(gradient));
+        assert_eq!(req[1], 34);
+        assert_eq!(req.len(), 8 + 16 + 4 + 2 * 4 + 2 * 8);
+        assert_eq!(&req[16..20], &0x18000i32.to_le_bytes());
+        // The second stop is half-transparent blue: red, green, blue, alpha
+        assert_eq!(&req[44..52], &[0, 0, 0, 0, 0xff, 0xff, 0x00, 0x80]);
+    }
+}
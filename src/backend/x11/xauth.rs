@@ -0,0 +1,180 @@
+//! Xauthority lookup
+//!
+//! Picks the authorization entry for a display the way Xlib does: the first
+//! entry whose family and address match how we reached the server and whose
+//! display number matches (or is empty).
+
+use super::display::{resolve_host, DisplayName};
+use std::net::IpAddr;
+use std::path::PathBuf;
+
+/// Address families used in Xauthority entries
+const FAMILY_INTERNET: u16 = 0;
+const FAMILY_INTERNET6: u16 = 6;
+const FAMILY_LOCAL: u16 = 256;
+const FAMILY_WILD: u16 = 65535;
+
+/// The only authorization protocols we can answer
+const SUPPORTED_AUTH: &[&str] = &["MIT-MAGIC-COOKIE-1"];
+
+/// One entry of an Xauthority file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct XauthEntry {
+    pub family: u16,
+    pub address: Vec<u8>,
+    pub number: String,
+    pub name: String,
+    pub data: Vec<u8>,
+}
+
+/// Location of the Xauthority file: $XAUTHORITY, else ~/.Xauthority
+pub fn xauthority_path() -> Option<PathBuf> {
+    std::env::var_os("XAUTHORITY")
+        .filter(|p| !p.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".Xauthority")))
+}
+
+/// Parse the contents of an Xauthority file; a truncated trailing entry is ignored
+pub fn parse_entries(data: &[u8]) -> Vec<XauthEntry> {
+    fn read_u16(data: &[u8], offset: &mut usize) -> Option<u16> {
+        let bytes = data.get(*offset..*offset + 2)?;
+        *offset += 2;
+        Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+    fn read_counted<'a>(data: &'a [u8], offset: &mut usize) -> Option<&'a [u8]> {
+        let len = read_u16(data, offset)? as usize;
+        let bytes = data.get(*offset..*offset + len)?;
+        *offset += len;
+        Some(bytes)
+    }
+
+    let mut entries = Vec::new();
+    let mut offset = 0;
+    while offset < data.len() {
+        let entry = (|| {
+            Some(XauthEntry {
+                family: read_u16(data, &mut offset)?,
+                address: read_counted(data, &mut offset)?.to_vec(),
+                number: String::from_utf8_lossy(read_counted(data, &mut offset)?).to_string(),
+                name: String::from_utf8_lossy(read_counted(data, &mut offset)?).to_string(),
+                data: read_counted(data, &mut offset)?.to_vec(),
+            })
+        })();
+        match entry {
+            Some(entry) => entries.push(entry),
+            None => break,
+        }
+    }
+    entries
+}
+
+/// Find the entry to authenticate with
+///
+/// `peer` is the server's address for TCP connections and None for local
+/// sockets, which use FamilyLocal entries keyed by our host name.
+pub fn find_entry<'a>(
+    entries: &'a [XauthEntry],
+    display: &DisplayName,
+    peer: Option<IpAddr>,
+    hostname: &str,
+) -> Option<&'a XauthEntry> {
+    // Connections to the loopback address authenticate like local ones
+    let local = match peer {
+        None => true,
+        Some(ip) => ip.is_loopback(),
+    };
+
+    // For TCP, the address may be recorded under any of the host's addresses
+    let mut addresses: Vec<IpAddr> = peer.into_iter().collect();
+    if !local {
+        addresses.extend(resolve_host(&display.host));
+    }
+
+    let number = display.display.to_string();
+    entries.iter().find(|entry| {
+        if !entry.number.is_empty() && entry.number != number {
+            return false;
+        }
+        if !SUPPORTED_AUTH.contains(&entry.name.as_str()) {
+            return false;
+        }
+        match entry.family {
+            FAMILY_WILD => true,
+            FAMILY_LOCAL => local && entry.address == hostname.as_bytes(),
+            FAMILY_INTERNET => addresses.iter().any(|a| match a {
+                IpAddr::V4(v4) => entry.address == v4.octets(),
+                IpAddr::V6(v6) => v6
+                    .to_ipv4_mapped()
+                    .is_some_and(|v4| entry.address == v4.octets()),
+            }),
+            FAMILY_INTERNET6 => addresses
+                .iter()
+                .any(|a| matches!(a, IpAddr::V6(v6) if entry.address == v6.octets())),
+            _ => false,
+        }
+    })
+}
+
+/// Our host name, as recorded in FamilyLocal entries
+pub fn local_hostname() -> String {
+    nix::unistd::gethostname()
+        .ok()
+        .and_then(|h| h.into_string().ok())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(entry: &XauthEntry) -> Vec<u8> {
+        let mut out = entry.family.to_be_bytes().to_vec();
+        for field in [
+            &entry.address[..],
+            entry.number.as_bytes(),
+            entry.name.as_bytes(),
+            &entry.data[..],
+        ] {
+            out.extend_from_slice(&(field.len() as u16).to_be_bytes());
+            out.extend_from_slice(field);
+        }
+        out
+    }
+
+    fn entry(family: u16, address: &[u8], number: &str) -> XauthEntry {
+        XauthEntry {
+            family,
+            address: address.to_vec(),
+            number: number.to_string(),
+            name: "MIT-MAGIC-COOKIE-1".to_string(),
+            data: vec![family as u8; 16],
+        }
+    }
+
+    #[test]
+    fn test_match_by_family_and_address() {
+        let entries = [
+            entry(FAMILY_LOCAL, b"otherhost", "0"),
+            entry(FAMILY_LOCAL, b"myhost", "1"),
+            entry(FAMILY_LOCAL, b"myhost", "0"),
+            entry(FAMILY_INTERNET, &[10, 0, 0, 7], "0"),
+        ];
+        let data: Vec<u8> = entries.iter().flat_map(encode).collect();
+        let parsed = parse_entries(&data);
+        assert_eq!(parsed, entries);
+
+        let local = DisplayName::parse(":0").unwrap();
+        let found = find_entry(&parsed, &local, None, "myhost").unwrap();
+        assert_eq!(found, &entries[2]);
+
+        let remote = DisplayName::parse("10.0.0.7:0").unwrap();
+        let peer = Some("10.0.0.7".parse().unwrap());
+        let found = find_entry(&parsed, &remote, peer, "myhost").unwrap();
+        assert_eq!(found.family, FAMILY_INTERNET);
+
+        let unknown = Some("10.0.0.8".parse().unwrap());
+        let other = DisplayName::parse("10.0.0.8:0").unwrap();
+        assert!(find_entry(&parsed, &other, unknown, "myhost").is_none());
+    }
+}
@@ -0,0 +1,262 @@
+//! BDF (Glyph Bitmap Distribution Format) parsing
+
+use super::{BitmapFont, CharInfo, Glyph, PropertyValue};
+use std::error::Error;
+use std::io::BufRead;
+
+/// Parse a BDF font
+pub fn parse(data: &[u8]) -> Result<BitmapFont, Box<dyn Error + Send + Sync>> {
+    let text = String::from_utf8_lossy(data);
+    if !text.starts_with("STARTFONT") {
+        return Err("Not a BDF font".into());
+    }
+    let mut lines = text.lines();
+    let mut font = BitmapFont::default();
+    let mut bounding_box = (0i16, 0i16, 0i16, 0i16);
+    let (mut ascent, mut descent, mut default_char) = (None, None, None);
+
+    while let Some(line) = lines.next() {
+        let (keyword, rest) = split_keyword(line);
+        match keyword {
+            "FONT" => font.name = rest.to_string(),
+            "FONTBOUNDINGBOX" => {
+                let v = metrics(rest, line)?;
+                if v.len() >= 4 {
+                    bounding_box = (v[0], v[1], v[2], v[3]);
+                }
+            }
+            "STARTPROPERTIES" => {
+                for line in lines.by_ref() {
+                    let (key, value) = split_keyword(line);
+                    if key == "ENDPROPERTIES" {
+                        break;
+                    }
+                    let value = parse_property(value);
+                    match (key, &value) {
+                        ("FONT_ASCENT", PropertyValue::Int(v)) => ascent = Some(metric(*v, line)?),
+                        ("FONT_DESCENT", PropertyValue::Int(v)) => {
+                            descent = Some(metric(*v, line)?)
+                        }
+                        ("DEFAULT_CHAR", PropertyValue::Int(v)) => default_char = Some(*v),
+                        _ => {}
+                    }
+                    font.properties.push((key.to_string(), value));
+                }
+            }
+            "STARTCHAR" => {
+                if let Some((code, glyph)) = parse_char(&mut lines)? {
+                    font.glyphs.insert(code, glyph);
+                }
+            }
+            "ENDFONT" => break,
+            _ => {}
+        }
+    }
+
+    let (_, height, _, y_offset) = bounding_box;
+    let out_of_range = || "FONTBOUNDINGBOX out of range";
+    font.font_ascent = match ascent {
+        Some(ascent) => ascent,
+        None => height.checked_add(y_offset).ok_or_else(out_of_range)?,
+    };
+    font.font_descent = match descent {
+        Some(descent) => descent,
+        None => y_offset.checked_neg().ok_or_else(out_of_range)?,
+    };
+    // A DEFAULT_CHAR outside the font means "none"
+    font.default_char = default_char
+        .and_then(|c| u16::try_from(c).ok())
+        .unwrap_or(0xffff);
+    Ok(font)
+}
+
+/// Read just the FONT name from the start of a BDF font
+pub fn read_name(reader: impl BufRead) -> Result<Option<String>, Box<dyn Error + Send + Sync>> {
+    let mut lines = reader.lines();
+    let first = lines.next().transpose()?.unwrap_or_default();
+    if !first.starts_with("STARTFONT") {
+        return Err("Not a BDF font".into());
+    }
+    for line in lines {
+        let line = line?;
+        match split_keyword(&line) {
+            ("FONT", name) => return Ok(Some(name.to_string())),
+            ("STARTCHAR" | "ENDFONT", _) => break,
+            _ => {}
+        }
+    }
+    Ok(None)
+}
+
+/// Parse the rest of a character after STARTCHAR; None for unencoded or
+/// out-of-range characters
+fn parse_char<'a>(
+    lines: &mut impl Iterator<Item = &'a str>,
+) -> Result<Option<(u16, Glyph)>, Box<dyn Error + Send + Sync>> {
+    let mut code: Option<u16> = None;
+    let mut width = 0i16;
+    let mut bbx = (0i16, 0i16, 0i16, 0i16);
+    let mut bitmap = Vec::new();
+
+    while let Some(line) = lines.next() {
+        let (keyword, rest) = split_keyword(line);
+        match keyword {
+            "ENCODING" => {
+                // "-1 n" marks a glyph outside the standard encoding
+                code = numbers(rest).first().and_then(|&c| u16::try_from(c).ok());
+            }
+            "DWIDTH" => width = metrics(rest, line)?.first().copied().unwrap_or(0),
+            "BBX" => {
+                let v = metrics(rest, line)?;
+                if v.len() < 4 {
+                    return Err(format!("Bad BBX line: {}", line).into());
+                }
+                bbx = (v[0], v[1], v[2], v[3]);
+            }
+            "BITMAP" => {
+                for row in lines.by_ref() {
+                    let row = row.trim();
+                    if row == "ENDCHAR" {
+                        break;
+                    }
+                    let stride = (bbx.0.max(0) as usize).div_ceil(8);
+                    let bytes: Vec<u8> = (0..stride)
+                        .map(|i| {
+                            row.get(i * 2..i * 2 + 2)
+                                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                                .unwrap_or(0)
+                        })
+                        .collect();
+                    bitmap.extend(bytes);
+                }
+                break;
+            }
+            "ENDCHAR" => break,
+            _ => {}
+        }
+    }
+
+    let Some(code) = code else {
+        return Ok(None);
+    };
+    let (w, h, x_offset, y_offset) = bbx;
+    let out_of_range = || format!("BBX of character {} out of range", code);
+    let info = CharInfo {
+        left_side_bearing: x_offset,
+        right_side_bearing: x_offset.checked_add(w).ok_or_else(out_of_range)?,
+        character_width: width,
+        ascent: h.checked_add(y_offset).ok_or_else(out_of_range)?,
+        descent: y_offset.checked_neg().ok_or_else(out_of_range)?,
+        attributes: 0,
+    };
+    Ok(Some((
+        code,
+        Glyph {
+            info,
+            bitmap: bitmap.into(),
+        },
+    )))
+}
+
+/// Split a line into its keyword and the rest
+fn split_keyword(line: &str) -> (&str, &str) {
+    let line = line.trim();
+    match line.split_once(char::is_whitespace) {
+        Some((keyword, rest)) => (keyword, rest.trim()),
+        None => (line, ""),
+    }
+}
+
+/// A metric, which must fit the 16 bits fonts are described with
+fn metric(value: i32, line: &str) -> Result<i16, Box<dyn Error + Send + Sync>> {
+    i16::try_from(value).map_err(|_| format!("Metric out of range: {}", line.trim()).into())
+}
+
+fn metrics(text: &str, line: &str) -> Result<Vec<i16>, Box<dyn Error + Send + Sync>> {
+    numbers(text).into_iter().map(|v| metric(v, line)).collect()
+}
+
+fn numbers(text: &str) -> Vec<i32> {
+    text.split_whitespace()
+        .filter_map(|n| n.parse().ok())
+        .collect()
+}
+
+/// A property value: a quoted string (with "" for a literal quote) or an
+/// integer
+fn parse_property(value: &str) -> PropertyValue {
+    if let Some(quoted) = value.strip_prefix('"') {
+        let inner = quoted.strip_suffix('"').unwrap_or(quoted);
+        PropertyValue::Atom(inner.replace("\"\"", "\""))
+    } else {
+        value
+            .parse()
+            .map(PropertyValue::Int)
+            .unwrap_or_else(|_| PropertyValue::Atom(value.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &str = "STARTFONT 2.1
+FONT -test-sample-medium-r-normal--4-40-75-75-c-40-iso8859-1
+FONTBOUNDINGBOX 4 4 0 -1
+STARTPROPERTIES 2
+FAMILY_NAME \"Sample\"
+DEFAULT_CHAR 65
+ENDPROPERTIES
+CHARS 2
+STARTCHAR A
+ENCODING 65
+DWIDTH 4 0
+BBX 3 3 0 0
+BITMAP
+40
+A0
+E0
+ENDCHAR
+STARTCHAR unencoded
+ENCODING -1 300
+DWIDTH 4 0
+BBX 1 1 0 0
+BITMAP
+80
+ENDCHAR
+ENDFONT
+";
+
+    #[test]
+    fn test_parse_bdf() {
+        let font = parse(SAMPLE.as_bytes()).unwrap();
+        assert_eq!((font.font_ascent, font.font_descent), (3, 1));
+        assert_eq!(font.default_char, 65);
+        assert_eq!(font.glyphs.len(), 1);
+        assert_eq!(
+            font.properties[0],
+            (
+                "FAMILY_NAME".to_string(),
+                PropertyValue::Atom("Sample".into())
+            )
+        );
+
+        let glyph = &font.glyphs[&65];
+        assert_eq!(glyph.info.right_side_bearing, 3);
+        assert_eq!((glyph.info.ascent, glyph.info.descent), (3, 0));
+        assert!(glyph.pixel(1, 0) && !glyph.pixel(0, 0) && glyph.pixel(2, 2));
+        // Missing characters fall back to the default character
+        assert!(font.glyph(66).is_some());
+
+        // Metrics beyond 16 bits, or adding up beyond them, are rejected
+        for bad in [
+            "BBX 3 3 0 0",
+            "BBX 70000 3 0 0",
+            "BBX 3 3 32767 0",
+            "BBX 3 3 0 -32768",
+        ] {
+            let font = SAMPLE.replacen("BBX 3 3 0 0", bad, 1);
+            assert_eq!(parse(font.as_bytes()).is_err(), bad != "BBX 3 3 0 0");
+        }
+    }
+}
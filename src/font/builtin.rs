@@ -0,0 +1,117 @@
+//! The misc-fixed fonts compiled into the server (see fonts/misc)
+
+/// Built-in fonts: XLFD name and gzip-compressed BDF data
+pub const FONTS: &[(&str, &[u8])] = &[
+    (
+        "-Misc-Fixed-Medium-R-Normal--10-100-75-75-C-60-ISO10646-1",
+        include_bytes!("../../fonts/misc/6x10.bdf.gz"),
+    ),
+    (
+        "-Misc-Fixed-Medium-R-SemiCondensed--12-110-75-75-C-60-ISO10646-1",
+        include_bytes!("../../fonts/misc/6x12.bdf.gz"),
+    ),
+    (
+        "-Misc-Fixed-Medium-R-SemiCondensed--13-120-75-75-C-60-ISO10646-1",
+        include_bytes!("../../fonts/misc/6x13.bdf.gz"),
+    ),
+    (
+        "-Misc-Fixed-Bold-R-SemiCondensed--13-120-75-75-C-60-ISO10646-1",
+        include_bytes!("../../fonts/misc/6x13B.bdf.gz"),
+    ),
+    (
+        "-Misc-Fixed-Medium-R-Normal--13-120-75-75-C-70-ISO10646-1",
+        include_bytes!("../../fonts/misc/7x13.bdf.gz"),
+    ),
+    (
+        "-Misc-Fixed-Bold-R-Normal--13-120-75-75-C-70-ISO10646-1",
+        include_bytes!("../../fonts/misc/7x13B.bdf.gz"),
+    ),
+    (
+        "-Misc-Fixed-Medium-R-Normal--14-130-75-75-C-70-ISO10646-1",
+        include_bytes!("../../fonts/misc/7x14.bdf.gz"),
+    ),
+    (
+        "-Misc-Fixed-Medium-R-Normal--13-120-75-75-C-80-ISO10646-1",
+        include_bytes!("../../fonts/misc/8x13.bdf.gz"),
+    ),
+    (
+        "-Misc-Fixed-Bold-R-Normal--13-120-75-75-C-80-ISO10646-1",
+        include_bytes!("../../fonts/misc/8x13B.bdf.gz"),
+    ),
+    (
+        "-Misc-Fixed-Medium-R-Normal--15-140-75-75-C-90-ISO10646-1",
+        include_bytes!("../../fonts/misc/9x15.bdf.gz"),
+    ),
+    (
+        "-Misc-Fixed-Bold-R-Normal--15-140-75-75-C-90-ISO10646-1",
+        include_bytes!("../../fonts/misc/9x15B.bdf.gz"),
+    ),
+    (
+        "-Misc-Fixed-Medium-R-Normal--18-120-100-100-C-90-ISO10646-1",
+        include_bytes!("../../fonts/misc/9x18.bdf.gz"),
+    ),
+    (
+        "-Misc-Fixed-Medium-R-Normal--20-200-75-75-C-100-ISO10646-1",
+        include_bytes!("../../fonts/misc/10x20.bdf.gz"),
+    ),
+];
+
+/// Aliases for the built-in fonts, as in misc-misc's fonts.alias
+pub const ALIASES: &[(&str, &str)] = &[
+    (
+        "fixed",
+        "-misc-fixed-medium-r-semicondensed--13-120-75-75-c-60-iso8859-1",
+    ),
+    (
+        "6x10",
+        "-misc-fixed-medium-r-normal--10-100-75-75-c-60-iso8859-1",
+    ),
+    (
+        "6x12",
+        "-misc-fixed-medium-r-semicondensed--12-110-75-75-c-60-iso8859-1",
+    ),
+    (
+        "6x13",
+        "-misc-fixed-medium-r-semicondensed--13-120-75-75-c-60-iso8859-1",
+    ),
+    (
+        "6x13bold",
+        "-misc-fixed-bold-r-semicondensed--13-120-75-75-c-60-iso8859-1",
+    ),
+    (
+        "7x13",
+        "-misc-fixed-medium-r-normal--13-120-75-75-c-70-iso8859-1",
+    ),
+    (
+        "7x13bold",
+        "-misc-fixed-bold-r-normal--13-120-75-75-c-70-iso8859-1",
+    ),
+    (
+        "7x14",
+        "-misc-fixed-medium-r-normal--14-130-75-75-c-70-iso8859-1",
+    ),
+    (
+        "8x13",
+        "-misc-fixed-medium-r-normal--13-120-75-75-c-80-iso8859-1",
+    ),
+    (
+        "8x13bold",
+        "-misc-fixed-bold-r-normal--13-120-75-75-c-80-iso8859-1",
+    ),
+    (
+        "9x15",
+        "-misc-fixed-medium-r-normal--15-140-75-75-c-90-iso8859-1",
+    ),
+    (
+        "9x15bold",
+        "-misc-fixed-bold-r-normal--15-140-75-75-c-90-iso8859-1",
+    ),
+    (
+        "9x18",
+        "-misc-fixed-medium-r-normal--18-120-100-100-c-90-iso8859-1",
+    ),
+    (
+        "10x20",
+        "-misc-fixed-medium-r-normal--20-200-75-75-c-100-iso8859-1",
+    ),
+];
@@ -0,0 +1,613 @@
+//! The font path and the names it provides
+//!
+//! Each font path element is a directory. Its `fonts.dir` (and
+//! `fonts.scale`) map file names to font names, and `fonts.alias` adds
+//! alternative names. Directories without a `fonts.dir` are scanned by
+//! reading the name of every font file in them; the names are kept until
+//! the directory changes. The built-in misc-fixed fonts follow the path, so
+//! `fixed` always resolves.
+//!
+//! TrueType and OpenType fonts are listed under scalable names, with zeros
+//! in the size fields, and rasterized at whatever size a name asks for.
+
+use super::xlfd::{self, Scale};
+use super::{builtin, parse_font, read_font_name, truetype, BitmapFont};
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Weak};
+use std::time::SystemTime;
+
+/// Directories searched for fonts when none is set
+pub const DEFAULT_FONT_PATH: &[&str] = &[
+    "/usr/share/fonts/X11/misc",
+    "/usr/share/fonts/X11/75dpi",
+    "/usr/share/fonts/X11/100dpi",
+    "/usr/share/X11/fonts/misc",
+    "/opt/X11/share/fonts/misc",
+    "/usr/share/fonts/X11/TTF",
+    "/usr/share/fonts/X11/OTF",
+    "/usr/share/fonts/truetype/dejavu",
+    "/Library/Fonts",
+    "/System/Library/Fonts",
+    "C:\\Windows\\Fonts",
+];
+
+/// How deep alias chains are followed
+const MAX_ALIAS_DEPTH: usize = 8;
+
+/// Whether a file name looks like a font the server can load
+fn is_font_file(path: &Path) -> bool {
+    let name = path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or("")
+        .to_ascii_lowercase();
+    let name = name.strip_suffix(".gz").unwrap_or(&name);
+    name.ends_with(".pcf") || name.ends_with(".bdf") || is_scalable(path)
+}
+
+/// Whether a file name looks like a TrueType or OpenType font
+fn is_scalable(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| ["ttf", "otf", "ttc"].contains(&e.to_ascii_lowercase().as_str()))
+}
+
+/// Split a `fonts.scale` file field into a face index and file name; faces
+/// after the first in a collection are written `:index:file`
+fn face_file(field: &str) -> (u32, &str) {
+    if let Some((index, file)) = field
+        .strip_prefix(':')
+        .and_then(|rest| rest.split_once(':'))
+    {
+        if let Ok(index) = index.parse() {
+            return (index, file);
+        }
+    }
+    (0, field)
+}
+
+/// The directory a font path element names; a `:unscaled` suffix is
+/// accepted and ignored
+fn element_directory(element: &str) -> &Path {
+    Path::new(element.strip_suffix(":unscaled").unwrap_or(element))
+}
+
+/// Match a font name against an XLFD pattern, ignoring case: `*` matches
+/// any run of characters (hyphens included) and `?` any single character
+pub fn pattern_matches(pattern: &str, name: &str) -> bool {
+    let pattern = pattern.as_bytes();
+    let name = name.as_bytes();
+    let (mut p, mut n) = (0, 0);
+    // Where to resume after the last `*`: (pattern index, name index)
+    let mut backtrack = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                backtrack = Some((p + 1, n));
+                p += 1;
+            }
+            Some(&c) if c == b'?' || c.eq_ignore_ascii_case(&name[n]) => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                Some((bp, bn)) => {
+                    backtrack = Some((bp, bn + 1));
+                    p = bp;
+                    n = bn + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+fn is_pattern(name: &str) -> bool {
+    name.contains(['*', '?'])
+}
+
+/// Where a catalog font is loaded from
+#[derive(Debug, Clone)]
+enum FontSource {
+    Builtin(&'static [u8]),
+    File(PathBuf),
+    /// A face of a TrueType or OpenType file
+    Scalable {
+        path: PathBuf,
+        index: u32,
+    },
+}
+
+#[derive(Debug, Clone)]
+struct CatalogEntry {
+    /// Lower-case font name; scalable fonts have zeros in the size fields,
+    /// or the requested size once resolved
+    name: String,
+    source: FontSource,
+    /// Serve only codes 0-255 (an ISO8859-1 view of an ISO10646-1 font)
+    latin1: bool,
+}
+
+impl CatalogEntry {
+    fn is_scalable(&self) -> bool {
+        matches!(self.source, FontSource::Scalable { .. })
+    }
+}
+
+/// The fonts the server can open, by name
+#[derive(Debug, Default)]
+pub struct FontCatalog {
+    /// Font path elements, as set
+    path: Vec<String>,
+    /// Fonts on the path, in path order, then the built-in fonts
+    entries: Vec<CatalogEntry>,
+    /// Lower-case alias -> lower-case font name or pattern
+    aliases: Vec<(String, String)>,
+    /// Fonts currently in use, by lower-case name
+    loaded: HashMap<String, Weak<BitmapFont>>,
+    /// Names scanned from directories without `fonts.dir`, with the
+    /// directory's modification time when they were read
+    scanned: HashMap<PathBuf, (SystemTime, Vec<(String, String)>)>,
+}
+
+impl FontCatalog {
+    /// A catalog holding only the built-in misc-fixed fonts
+    pub fn new() -> Self {
+        let mut catalog = FontCatalog::default();
+        catalog.add_builtins();
+        catalog
+    }
+
+    /// The existing directories of `DEFAULT_FONT_PATH`
+    pub fn default_path() -> Vec<String> {
+        DEFAULT_FONT_PATH
+            .iter()
+            .filter(|dir| Path::new(dir).is_dir())
+            .map(|dir| dir.to_string())
+            .collect()
+    }
+
+    /// The font path
+    pub fn path(&self) -> &[String] {
+        &self.path
+    }
+
+    /// Replace the font path; an empty path restores the default
+    ///
+    /// Fails with the index of the first element that is not a readable
+    /// directory, leaving the path unchanged. The directories are read
+    /// right away; the server sets its default path before serving clients.
+    pub fn set_path(&mut self, path: &[String]) -> Result<(), usize> {
+        if path.is_empty() {
+            self.path = Self::default_path();
+        } else {
+            if let Some(bad) = path
+                .iter()
+                .position(|element| std::fs::read_dir(element_directory(element)).is_err())
+            {
+                return Err(bad);
+            }
+            self.path = path.to_vec();
+        }
+        self.read_path();
+        Ok(())
+    }
+
+    /// Read the font path directories
+    fn read_path(&mut self) {
+        self.entries.clear();
+        self.aliases.clear();
+        self.loaded.clear();
+        for element in self.path.clone() {
+            let dir = element_directory(&element);
+            match self.add_directory(dir) {
+                Ok(count) => log::info!("Found {} fonts in {}", count, dir.display()),
+                Err(e) => log::warn!("Cannot read font directory {}: {}", dir.display(), e),
+            }
+        }
+        self.add_builtins();
+    }
+
+    /// Register the built-in fonts and their aliases
+    fn add_builtins(&mut self) {
+        for (name, data) in builtin::FONTS {
+            let name = name.to_ascii_lowercase();
+            // Each is also offered as ISO8859-1, its first 256 characters
+            if let Some(base) = name.strip_suffix("-iso10646-1") {
+                self.entries.push(CatalogEntry {
+                    name: format!("{}-iso8859-1", base),
+                    source: FontSource::Builtin(data),
+                    latin1: true,
+                });
+            }
+            self.entries.push(CatalogEntry {
+                name,
+                source: FontSource::Builtin(data),
+                latin1: false,
+            });
+        }
+        for (alias, target) in builtin::ALIASES {
+            self.aliases.push((alias.to_string(), target.to_string()));
+        }
+    }
+
+    /// Add the fonts and aliases of one directory; returns how many fonts
+    /// were found
+    fn add_directory(&mut self, dir: &Path) -> std::io::Result<usize> {
+        let mut files = Vec::new();
+        let mut listed = false;
+        for index in ["fonts.dir", "fonts.scale"] {
+            if let Ok(text) = std::fs::read_to_string(dir.join(index)) {
+                listed = true;
+                files.extend(parse_fonts_dir(&text));
+            }
+        }
+        if !listed {
+            files = self.scan_directory(dir)?;
+        }
+
+        let mut count = 0;
+        let mut seen = HashSet::new();
+        for (file, name) in &files {
+            let (index, file) = face_file(file);
+            let path = dir.join(file);
+            if !is_font_file(&path) {
+                log::debug!("Skipping unsupported font file {}", path.display());
+                continue;
+            }
+            let source = if is_scalable(&path) {
+                // Rasterized fonts cover Unicode or its first 256 characters
+                if !name.ends_with("-iso10646-1") && !name.ends_with("-iso8859-1") {
+                    log::debug!("Skipping scalable font {} with unsupported encoding", name);
+                    continue;
+                }
+                FontSource::Scalable { path, index }
+            } else {
+                FontSource::File(path)
+            };
+            if !seen.insert(name.clone()) {
+                continue;
+            }
+            self.entries.push(CatalogEntry {
+                name: name.clone(),
+                source,
+                latin1: false,
+            });
+            count += 1;
+        }
+
+        if let Ok(text) = std::fs::read_to_string(dir.join("fonts.alias")) {
+            let (aliases, file_names_aliases) = parse_fonts_alias(&text);
+            self.aliases.extend(aliases);
+            if file_names_aliases {
+                for (file, name) in &files {
+                    let file = face_file(file).1.to_ascii_lowercase();
+                    let file = file.strip_suffix(".gz").unwrap_or(&file);
+                    let stem = file.rsplit_once('.').map_or(file, |(stem, _)| stem);
+                    self.aliases.push((stem.to_string(), name.clone()));
+                }
+            }
+        }
+        Ok(count)
+    }
+
+    /// The font names in a directory without `fonts.dir`, scanned again
+    /// only if the directory changed
+    fn scan_directory(&mut self, dir: &Path) -> std::io::Result<Vec<(String, String)>> {
+        let modified = std::fs::metadata(dir)?.modified()?;
+        if let Some((time, files)) = self.scanned.get(dir) {
+            if *time == modified {
+                return Ok(files.clone());
+            }
+        }
+        let files = scan_font_files(dir)?;
+        self.scanned
+            .insert(dir.to_path_buf(), (modified, files.clone()));
+        Ok(files)
+    }
+
+    /// Names and aliases matching an XLFD pattern, without duplicates
+    ///
+    /// Scalable fonts are listed at the size the pattern asks for, if any.
+    pub fn list(&mut self, pattern: &str, max_names: usize) -> Vec<String> {
+        let mut seen = HashSet::new();
+        let mut names = Vec::new();
+        let entries = self.entries.iter().map(|e| (&e.name, e.is_scalable()));
+        let aliases = self.aliases.iter().map(|(alias, _)| (alias, false));
+        for (name, scalable) in entries.chain(aliases) {
+            if names.len() >= max_names {
+                break;
+            }
+            let name = if pattern_matches(pattern, name) {
+                name.clone()
+            } else if let Some((instance, _)) = scalable
+                .then(|| xlfd::match_scalable(pattern, name))
+                .flatten()
+            {
+                instance
+            } else {
+                continue;
+            };
+            if seen.insert(name.clone()) {
+                names.push(name);
+            }
+        }
+        names
+    }
+
+    /// The catalog entry a name, alias or pattern refers to
+    ///
+    /// Scalable fonts come back named after the instance to open.
+    fn resolve(&self, name: &str) -> Option<CatalogEntry> {
+        let mut name = name.to_ascii_lowercase();
+        for _ in 0..MAX_ALIAS_DEPTH {
+            if let Some(entry) = self.entries.iter().find(|e| e.name == name) {
+                let mut entry = entry.clone();
+                if entry.is_scalable() {
+                    entry.name = xlfd::instantiate(&entry.name, &Scale::default())?;
+                }
+                return Some(entry);
+            }
+            if let Some((_, target)) = self.aliases.iter().find(|(alias, _)| *alias == name) {
+                name = target.clone();
+                continue;
+            }
+            // A pattern opens the first font it matches
+            if let Some(candidate) = self
+                .entries
+                .iter()
+                .map(|e| &e.name)
+                .chain(self.aliases.iter().map(|(alias, _)| alias))
+                .find(|candidate| is_pattern(&name) && pattern_matches(&name, candidate))
+            {
+                name = candidate.clone();
+                continue;
+            }
+            // Otherwise, a scalable font at the size the name asks for
+            return self
+                .entries
+                .iter()
+                .filter(|e| e.is_scalable())
+                .find_map(|e| {
+                    let (instance, _) = xlfd::match_scalable(&name, &e.name)?;
+                    Some(CatalogEntry {
+                        name: instance,
+                        ..e.clone()
+                    })
+                });
+        }
+        None
+    }
+
+    /// Load a font by name, alias or pattern (case-insensitive); None if
+    /// nothing matches
+    pub fn open(
+        &mut self,
+        name: &str,
+    ) -> Result<Option<Arc<BitmapFont>>, Box<dyn Error + Send + Sync>> {
+        let Some(entry) = self.resolve(name) else {
+            return Ok(None);
+        };
+        if let Some(font) = self.loaded.get(&entry.name).and_then(Weak::upgrade) {
+            return Ok(Some(font));
+        }
+
+        let mut font = match &entry.source {
+            FontSource::Builtin(data) => parse_font(data)?,
+            FontSource::File(path) => parse_font(&std::fs::read(path)?)?,
+            FontSource::Scalable { path, index } => {
+                let scale = xlfd::requested_scale(&entry.name).unwrap_or_default();
+                truetype::rasterize(std::fs::read(path)?, *index, &scale, &entry.name)?
+            }
+        };
+        if entry.latin1 {
+            font = font.latin1_subset(&entry.name);
+        }
+        log::debug!(
+            "Loaded font {} ({} glyphs, ascent {}, descent {})",
+            entry.name,
+            font.glyphs.len(),
+            font.font_ascent,
+            font.font_descent
+        );
+        let font = Arc::new(font);
+        self.loaded.retain(|_, font| font.strong_count() > 0);
+        self.loaded.insert(entry.name, Arc::downgrade(&font));
+        Ok(Some(font))
+    }
+}
+
+/// (file, lower-case font name) pairs from a `fonts.dir` or `fonts.scale`
+///
+/// The first line holds the number of entries.
+fn parse_fonts_dir(text: &str) -> Vec<(String, String)> {
+    text.lines()
+        .skip(1)
+        .filter_map(|line| {
+            let (file, name) = line.trim().split_once(char::is_whitespace)?;
+            Some((file.to_string(), name.trim().to_ascii_lowercase()))
+        })
+        .collect()
+}
+
+/// (file, lower-case font name) pairs for a directory without `fonts.dir`,
+/// read from the fonts' headers, in `fonts.scale` form
+fn scan_font_files(dir: &Path) -> std::io::Result<Vec<(String, String)>> {
+    let mut paths: Vec<PathBuf> = std::fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| is_font_file(path))
+        .collect();
+    paths.sort();
+
+    let mut files = Vec::new();
+    for path in paths {
+        let file = path.file_name().unwrap_or_default().to_string_lossy();
+        if is_scalable(&path) {
+            let faces = match truetype::read_face_names(&path) {
+                Ok(faces) => faces,
+                Err(e) => {
+                    log::debug!("Skipping font {}: {}", path.display(), e);
+                    continue;
+                }
+            };
+            for (index, name) in faces {
+                let file = match index {
+                    0 => file.to_string(),
+                    index => format!(":{}:{}", index, file),
+                };
+                let name = name.to_ascii_lowercase();
+                let latin1 = name.replace("-iso10646-1", "-iso8859-1");
+                files.push((file.clone(), name));
+                files.push((file, latin1));
+            }
+            continue;
+        }
+        match read_font_name(&path) {
+            Ok(Some(name)) if !name.is_empty() => {
+                files.push((file.into_owned(), name.to_ascii_lowercase()));
+            }
+            Ok(_) => log::debug!("Font {} has no name, skipping", path.display()),
+            Err(e) => log::debug!("Skipping font {}: {}", path.display(), e),
+        }
+    }
+    Ok(files)
+}
+
+/// Lower-case (alias, target) pairs from a `fonts.alias`, and whether it
+/// asks for FILE_NAMES_ALIASES
+///
+/// Either field may be quoted; lines starting with `!` are comments.
+fn parse_fonts_alias(text: &str) -> (Vec<(String, String)>, bool) {
+    let mut aliases = Vec::new();
+    let mut file_names_aliases = false;
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('!') {
+            continue;
+        }
+        if line == "FILE_NAMES_ALIASES" {
+            file_names_aliases = true;
+            continue;
+        }
+        let (alias, rest) = alias_field(line);
+        let (target, _) = alias_field(rest);
+        if !alias.is_empty() && !target.is_empty() {
+            aliases.push((alias.to_ascii_lowercase(), target.to_ascii_lowercase()));
+        }
+    }
+    (aliases, file_names_aliases)
+}
+
+/// Split off one field, which may be quoted (with `\` escapes)
+fn alias_field(text: &str) -> (String, &str) {
+    let text = text.trim_start();
+    let Some(quoted) = text.strip_prefix('"') else {
+        let end = text.find(char::is_whitespace).unwrap_or(text.len());
+        return (text[..end].to_string(), &text[end..]);
+    };
+    let mut field = String::new();
+    let mut chars = quoted.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return (field, &quoted[i + 1..]),
+            '\\' => field.extend(chars.next().map(|(_, c)| c)),
+            c => field.push(c),
+        }
+    }
+    (field, "")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::font::PropertyValue;
+
+    #[test]
+    fn test_builtin_fixed() {
+        let mut catalog = FontCatalog::new();
+        let font = catalog.open("fixed").unwrap().expect("fixed is built in");
+        assert_eq!(
+            font.name,
+            "-misc-fixed-medium-r-semicondensed--13-120-75-75-c-60-iso8859-1"
+        );
+        assert_eq!((font.font_ascent, font.font_descent), (11, 2));
+        assert_eq!(font.char_range(), (0, 0, 0, 255));
+
+        let a = font.glyph(b'A' as u16).unwrap();
+        assert_eq!(a.info.character_width, 6);
+        assert_eq!((a.width(), a.height()), (6, 13));
+        assert!((0..a.height()).any(|y| (0..a.width()).any(|x| a.pixel(x, y))));
+        assert!(font
+            .properties
+            .contains(&("PIXEL_SIZE".to_string(), PropertyValue::Int(13))));
+
+        // The full font is a matrix font
+        let unicode = catalog
+            .open("-Misc-Fixed-Medium-R-SemiCondensed--13-120-75-75-C-60-ISO10646-1")
+            .unwrap()
+            .unwrap();
+        assert!(unicode.is_matrix());
+
+        // Patterns open the first match
+        let bold = catalog.open("-*-fixed-bold-r-*--15-*").unwrap().unwrap();
+        assert!(bold.name.contains("-bold-"));
+    }
+
+    #[test]
+    fn test_pattern_matches() {
+        let name = "-misc-fixed-medium-r-normal--13-120-75-75-c-70-iso8859-1";
+        assert!(pattern_matches("*", name));
+        assert!(pattern_matches("-MISC-Fixed-*-ISO8859-1", name));
+        assert!(pattern_matches("-*-fixed-medium-r-*--13-*", name));
+        assert!(pattern_matches("-misc-fixed-medium-?-normal--13-*", name));
+        assert!(!pattern_matches("-*-helvetica-*", name));
+        assert!(!pattern_matches("fixed", name));
+        assert!(pattern_matches("fixed", "fixed"));
+    }
+
+    #[test]
+    fn test_font_path() {
+        let dir = std::env::temp_dir().join(format!("x11anywhere-fonts-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let font = builtin::FONTS[0];
+        std::fs::write(dir.join("small.bdf.gz"), font.1).unwrap();
+        std::fs::write(
+            dir.join("fonts.dir"),
+            format!("1\nsmall.bdf.gz {}\n", font.0.to_uppercase()),
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("fonts.alias"),
+            "! comment\nFILE_NAMES_ALIASES\n\"my font\"  -misc-fixed-*-iso10646-1\n",
+        )
+        .unwrap();
+
+        let mut catalog = FontCatalog::new();
+        let element = dir.to_string_lossy().into_owned();
+        assert_eq!(
+            catalog.set_path(&[element.clone(), "/nonexistent/fonts".into()]),
+            Err(1)
+        );
+        catalog.set_path(std::slice::from_ref(&element)).unwrap();
+        assert_eq!(catalog.path(), std::slice::from_ref(&element));
+
+        assert_eq!(catalog.list(font.0, 10), vec![font.0.to_ascii_lowercase()]);
+        assert!(catalog.list("small", 10).contains(&"small".to_string()));
+        let by_alias = catalog.open("My Font").unwrap().unwrap();
+        assert!(by_alias.is_matrix());
+        assert!(catalog.open("fixed").unwrap().is_some());
+
+        // Without fonts.dir the font names are read from the files, once
+        std::fs::remove_file(dir.join("fonts.dir")).unwrap();
+        catalog.set_path(std::slice::from_ref(&element)).unwrap();
+        assert_eq!(catalog.list(font.0, 10), vec![font.0.to_ascii_lowercase()]);
+        assert_eq!(catalog.scanned.len(), 1);
+        catalog.set_path(std::slice::from_ref(&element)).unwrap();
+        assert_eq!(catalog.list(font.0, 10), vec![font.0.to_ascii_lowercase()]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,254 @@
+//! Server-side fonts
+//!
+//! Core-protocol fonts are loaded, measured and rasterized by the server
+//! itself, so text lays out the same on every backend. Fonts come from BDF
+//! and PCF files (optionally gzip-compressed) and TrueType/OpenType files
+//! found on the font path, and from the misc-fixed family compiled into the
+//! binary.
+
+mod bdf;
+mod builtin;
+mod catalog;
+mod pcf;
+mod truetype;
+mod xlfd;
+
+pub use catalog::FontCatalog;
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+use std::sync::{Arc, OnceLock};
+
+/// Per-character metrics, as in the protocol's CHARINFO
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CharInfo {
+    pub left_side_bearing: i16,
+    pub right_side_bearing: i16,
+    pub character_width: i16,
+    pub ascent: i16,
+    pub descent: i16,
+    pub attributes: u16,
+}
+
+/// A character's metrics and image
+#[derive(Debug, Clone, Default)]
+pub struct Glyph {
+    pub info: CharInfo,
+    /// Ink bitmap covering the bearings horizontally and ascent + descent
+    /// vertically; rows are MSB-first and padded to whole bytes. Scalable
+    /// fonts fill it in when the glyph is first looked up.
+    pub bitmap: OnceLock<Vec<u8>>,
+}
+
+impl Glyph {
+    /// Width of the bitmap in pixels
+    pub fn width(&self) -> usize {
+        let (left, right) = (self.info.left_side_bearing, self.info.right_side_bearing);
+        (right as i32 - left as i32).max(0) as usize
+    }
+
+    /// Height of the bitmap in pixels
+    pub fn height(&self) -> usize {
+        (self.info.ascent as i32 + self.info.descent as i32).max(0) as usize
+    }
+
+    /// Whether the pixel at (`x`, `y`) of the bitmap is set
+    pub fn pixel(&self, x: usize, y: usize) -> bool {
+        let stride = self.width().div_ceil(8);
+        self.bitmap
+            .get()
+            .and_then(|bitmap| bitmap.get(y * stride + x / 8))
+            .is_some_and(|b| b & (0x80 >> (x % 8)) != 0)
+    }
+}
+
+/// A font property value; string values are sent as atoms
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PropertyValue {
+    Int(i32),
+    Atom(String),
+}
+
+/// A font loaded by the server
+#[derive(Debug, Clone, Default)]
+pub struct BitmapFont {
+    /// Name the font was found under
+    pub name: String,
+    pub properties: Vec<(String, PropertyValue)>,
+    pub font_ascent: i16,
+    pub font_descent: i16,
+    /// Drawn for characters the font lacks, if it exists
+    pub default_char: u16,
+    /// Glyphs by character code (byte1 << 8 | byte2 for matrix fonts)
+    pub glyphs: BTreeMap<u16, Glyph>,
+    /// Outlines the glyph bitmaps of a scalable font are rendered from
+    outlines: Option<Arc<truetype::Outlines>>,
+}
+
+impl BitmapFont {
+    /// The glyph drawn for `code`: its own, the default character's, or
+    /// None if neither exists
+    pub fn glyph(&self, code: u16) -> Option<&Glyph> {
+        let (&code, glyph) = self
+            .glyphs
+            .get_key_value(&code)
+            .or_else(|| self.glyphs.get_key_value(&self.default_char))?;
+        if let Some(outlines) = &self.outlines {
+            glyph
+                .bitmap
+                .get_or_init(|| outlines.render(code, &glyph.info));
+        }
+        Some(glyph)
+    }
+
+    /// Whether this is a matrix (two-byte) font
+    pub fn is_matrix(&self) -> bool {
+        self.glyphs.keys().next_back().is_some_and(|&c| c > 0xff)
+    }
+
+    /// (min_byte1, max_byte1, min_char_or_byte2, max_char_or_byte2)
+    pub fn char_range(&self) -> (u8, u8, u16, u16) {
+        let (Some(&first), Some(&last)) =
+            (self.glyphs.keys().next(), self.glyphs.keys().next_back())
+        else {
+            return (0, 0, 0, 0);
+        };
+        if !self.is_matrix() {
+            return (0, 0, first, last);
+        }
+        let min_byte2 = self.glyphs.keys().map(|c| c & 0xff).min().unwrap_or(0);
+        let max_byte2 = self.glyphs.keys().map(|c| c & 0xff).max().unwrap_or(0);
+        ((first >> 8) as u8, (last >> 8) as u8, min_byte2, max_byte2)
+    }
+
+    /// Metrics for every code in `char_range`, row by row; missing
+    /// characters are all zero
+    pub fn char_infos(&self) -> Vec<CharInfo> {
+        let (min_byte1, max_byte1, min_byte2, max_byte2) = self.char_range();
+        let mut infos = Vec::new();
+        for byte1 in min_byte1 as u16..=max_byte1 as u16 {
+            for byte2 in min_byte2..=max_byte2 {
+                let code = (byte1 << 8) | byte2;
+                infos.push(self.glyphs.get(&code).map(|g| g.info).unwrap_or_default());
+            }
+        }
+        infos
+    }
+
+    /// Whether every code in `char_range` has a glyph
+    pub fn all_chars_exist(&self) -> bool {
+        let (min_byte1, max_byte1, min_byte2, max_byte2) = self.char_range();
+        let rows = (max_byte1 - min_byte1) as usize + 1;
+        let columns = (max_byte2 - min_byte2) as usize + 1;
+        !self.glyphs.is_empty() && self.glyphs.len() == rows * columns
+    }
+
+    /// Smallest and largest value of each metric over all glyphs
+    pub fn bounds(&self) -> (CharInfo, CharInfo) {
+        let mut glyphs = self.glyphs.values().map(|g| g.info);
+        let Some(first) = glyphs.next() else {
+            return (CharInfo::default(), CharInfo::default());
+        };
+        glyphs.fold((first, first), |(min, max), info| {
+            (
+                CharInfo {
+                    left_side_bearing: min.left_side_bearing.min(info.left_side_bearing),
+                    right_side_bearing: min.right_side_bearing.min(info.right_side_bearing),
+                    character_width: min.character_width.min(info.character_width),
+                    ascent: min.ascent.min(info.ascent),
+                    descent: min.descent.min(info.descent),
+                    attributes: min.attributes & info.attributes,
+                },
+                CharInfo {
+                    left_side_bearing: max.left_side_bearing.max(info.left_side_bearing),
+                    right_side_bearing: max.right_side_bearing.max(info.right_side_bearing),
+                    character_width: max.character_width.max(info.character_width),
+                    ascent: max.ascent.max(info.ascent),
+                    descent: max.descent.max(info.descent),
+                    attributes: max.attributes | info.attributes,
+                },
+            )
+        })
+    }
+
+    /// A copy limited to codes 0-255, named `name`
+    ///
+    /// ISO 10646 fonts serve as ISO 8859-1 fonts this way, since the two
+    /// agree on their first 256 characters.
+    pub fn latin1_subset(&self, name: &str) -> BitmapFont {
+        let mut font = self.clone();
+        font.name = name.to_string();
+        font.glyphs.retain(|&code, _| code <= 0xff);
+        for (key, value) in &mut font.properties {
+            match key.as_str() {
+                "CHARSET_REGISTRY" => *value = PropertyValue::Atom("ISO8859".into()),
+                "CHARSET_ENCODING" => *value = PropertyValue::Atom("1".into()),
+                "FONT" => *value = PropertyValue::Atom(name.to_uppercase()),
+                _ => {}
+            }
+        }
+        font
+    }
+
+    /// Fill in FONT, FONT_ASCENT and FONT_DESCENT if the file left them out
+    fn add_standard_properties(&mut self) {
+        let has =
+            |props: &[(String, PropertyValue)], key: &str| props.iter().any(|(k, _)| k == key);
+        if !has(&self.properties, "FONT") && !self.name.is_empty() {
+            self.properties
+                .push(("FONT".into(), PropertyValue::Atom(self.name.clone())));
+        }
+        if !has(&self.properties, "FONT_ASCENT") {
+            self.properties.push((
+                "FONT_ASCENT".into(),
+                PropertyValue::Int(self.font_ascent as i32),
+            ));
+        }
+        if !has(&self.properties, "FONT_DESCENT") {
+            self.properties.push((
+                "FONT_DESCENT".into(),
+                PropertyValue::Int(self.font_descent as i32),
+            ));
+        }
+    }
+}
+
+/// Read the name of a BDF or PCF font file, which may be gzip-compressed,
+/// without loading its glyphs
+pub fn read_font_name(path: &Path) -> Result<Option<String>, Box<dyn Error + Send + Sync>> {
+    let mut file = BufReader::new(File::open(path)?);
+    let mut reader: Box<dyn BufRead> = if file.fill_buf()?.starts_with(&[0x1f, 0x8b]) {
+        Box::new(BufReader::new(flate2::read::GzDecoder::new(file)))
+    } else {
+        Box::new(file)
+    };
+    let head = reader.fill_buf()?;
+    if head.starts_with(pcf::MAGIC) {
+        pcf::read_name(&mut reader)
+    } else if head.starts_with(b"STARTFONT") {
+        bdf::read_name(reader)
+    } else {
+        Err("Not a BDF or PCF font".into())
+    }
+}
+
+/// Parse a BDF or PCF font, which may be gzip-compressed
+pub fn parse_font(data: &[u8]) -> Result<BitmapFont, Box<dyn Error + Send + Sync>> {
+    if data.starts_with(&[0x1f, 0x8b]) {
+        let mut decompressed = Vec::new();
+        flate2::read::GzDecoder::new(data).read_to_end(&mut decompressed)?;
+        return parse_font(&decompressed);
+    }
+    let mut font = if data.starts_with(pcf::MAGIC) {
+        pcf::parse(data)?
+    } else if data.starts_with(b"STARTFONT") {
+        bdf::parse(data)?
+    } else {
+        return Err("Not a BDF or PCF font".into());
+    };
+    font.add_standard_properties();
+    Ok(font)
+}
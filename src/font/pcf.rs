@@ -0,0 +1,405 @@
+//! PCF (Portable Compiled Format) parsing
+
+use super::{BitmapFont, CharInfo, Glyph, PropertyValue};
+use std::error::Error;
+use std::io::Read;
+
+/// File signature: "\1fcp"
+pub const MAGIC: &[u8] = b"\x01fcp";
+
+const PROPERTIES: u32 = 1 << 0;
+const ACCELERATORS: u32 = 1 << 1;
+const METRICS: u32 = 1 << 2;
+const BITMAPS: u32 = 1 << 3;
+const BDF_ENCODINGS: u32 = 1 << 5;
+const BDF_ACCELERATORS: u32 = 1 << 8;
+
+/// Format bits of a table
+const GLYPH_PAD_MASK: u32 = 3;
+const BYTE_MASK: u32 = 1 << 2;
+const BIT_MASK: u32 = 1 << 3;
+const SCAN_UNIT_SHIFT: u32 = 4;
+const COMPRESSED_METRICS: u32 = 0x100;
+
+type Result<T> = std::result::Result<T, Box<dyn Error + Send + Sync>>;
+
+/// Reads one table, in the byte order its format word specifies
+struct Table<'a> {
+    data: &'a [u8],
+    pos: usize,
+    format: u32,
+}
+
+impl<'a> Table<'a> {
+    /// Find a table by type in the table of contents
+    fn find(file: &'a [u8], kind: u32) -> Result<Option<Self>> {
+        let count = le_u32(file, 4)? as usize;
+        for i in 0..count {
+            let entry = 8 + i * 16;
+            if le_u32(file, entry)? != kind {
+                continue;
+            }
+            let size = le_u32(file, entry + 8)? as usize;
+            let offset = le_u32(file, entry + 12)? as usize;
+            let data = file
+                .get(offset..offset.saturating_add(size))
+                .ok_or("PCF table out of bounds")?;
+            // The format word itself is always little endian
+            let format = le_u32(data, 0)?;
+            return Ok(Some(Table {
+                data,
+                pos: 4,
+                format,
+            }));
+        }
+        Ok(None)
+    }
+
+    fn big_endian(&self) -> bool {
+        self.format & BYTE_MASK != 0
+    }
+
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8]> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos + n)
+            .ok_or("Truncated PCF table")?;
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn i16(&mut self) -> Result<i16> {
+        let b = self.bytes(2)?;
+        Ok(if self.big_endian() {
+            i16::from_be_bytes([b[0], b[1]])
+        } else {
+            i16::from_le_bytes([b[0], b[1]])
+        })
+    }
+
+    fn i32(&mut self) -> Result<i32> {
+        let b = self.bytes(4)?;
+        let b = [b[0], b[1], b[2], b[3]];
+        Ok(if self.big_endian() {
+            i32::from_be_bytes(b)
+        } else {
+            i32::from_le_bytes(b)
+        })
+    }
+
+    /// One metrics entry, compressed or not
+    fn metrics(&mut self, compressed: bool) -> Result<CharInfo> {
+        if compressed {
+            let mut next = || -> Result<i16> { Ok(self.u8()? as i16 - 0x80) };
+            Ok(CharInfo {
+                left_side_bearing: next()?,
+                right_side_bearing: next()?,
+                character_width: next()?,
+                ascent: next()?,
+                descent: next()?,
+                attributes: 0,
+            })
+        } else {
+            Ok(CharInfo {
+                left_side_bearing: self.i16()?,
+                right_side_bearing: self.i16()?,
+                character_width: self.i16()?,
+                ascent: self.i16()?,
+                descent: self.i16()?,
+                attributes: self.i16()? as u16,
+            })
+        }
+    }
+}
+
+fn le_u32(data: &[u8], offset: usize) -> Result<u32> {
+    let b = data.get(offset..offset + 4).ok_or("Truncated PCF header")?;
+    Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+/// Parse a PCF font
+pub fn parse(file: &[u8]) -> Result<BitmapFont> {
+    if !file.starts_with(MAGIC) {
+        return Err("Not a PCF font".into());
+    }
+    let properties = parse_properties(file)?;
+    let mut font = BitmapFont {
+        name: font_property(&properties).unwrap_or_default(),
+        properties,
+        ..Default::default()
+    };
+
+    // Font ascent and descent live in the accelerators
+    let mut accel = match Table::find(file, BDF_ACCELERATORS)? {
+        Some(table) => table,
+        None => Table::find(file, ACCELERATORS)?.ok_or("PCF font has no accelerators")?,
+    };
+    accel.bytes(8)?;
+    font.font_ascent = accel.i32()? as i16;
+    font.font_descent = accel.i32()? as i16;
+
+    // Metrics, in glyph index order
+    let mut table = Table::find(file, METRICS)?.ok_or("PCF font has no metrics")?;
+    let compressed = table.format & COMPRESSED_METRICS != 0;
+    let count = if compressed {
+        table.i16()? as u16 as usize
+    } else {
+        table.i32()?.max(0) as usize
+    };
+    let metrics = (0..count)
+        .map(|_| table.metrics(compressed))
+        .collect::<Result<Vec<_>>>()?;
+
+    let bitmaps = parse_bitmaps(file, &metrics)?;
+
+    // Encodings map character codes to glyph indices
+    let mut table = Table::find(file, BDF_ENCODINGS)?.ok_or("PCF font has no encodings")?;
+    let min_byte2 = table.i16()? as u16;
+    let max_byte2 = table.i16()? as u16;
+    let min_byte1 = table.i16()? as u16;
+    let max_byte1 = table.i16()? as u16;
+    font.default_char = table.i16()? as u16;
+    for byte1 in min_byte1..=max_byte1 {
+        for byte2 in min_byte2..=max_byte2 {
+            let index = table.i16()? as u16;
+            if index == 0xffff {
+                continue;
+            }
+            let (Some(&info), Some(bitmap)) =
+                (metrics.get(index as usize), bitmaps.get(index as usize))
+            else {
+                continue;
+            };
+            font.glyphs.insert(
+                (byte1 << 8) | byte2,
+                Glyph {
+                    info,
+                    bitmap: bitmap.clone().into(),
+                },
+            );
+        }
+    }
+    Ok(font)
+}
+
+/// Read just the FONT property of a PCF font, up to the end of its
+/// properties table (usually the first)
+pub fn read_name(reader: &mut dyn Read) -> Result<Option<String>> {
+    let mut file = vec![0u8; 8];
+    reader.read_exact(&mut file)?;
+    if !file.starts_with(MAGIC) {
+        return Err("Not a PCF font".into());
+    }
+    let count = le_u32(&file, 4)? as usize;
+    if count > 64 {
+        return Err("Too many PCF tables".into());
+    }
+    file.resize(8 + count * 16, 0);
+    reader.read_exact(&mut file[8..])?;
+    let Some(entry) = (0..count)
+        .map(|i| 8 + i * 16)
+        .find(|&entry| le_u32(&file, entry).ok() == Some(PROPERTIES))
+    else {
+        return Ok(None);
+    };
+    // Table of contents entries: type, format, size, offset
+    let end = le_u32(&file, entry + 12)? as u64 + le_u32(&file, entry + 8)? as u64;
+    let missing = end.saturating_sub(file.len() as u64);
+    reader.take(missing).read_to_end(&mut file)?;
+    Ok(font_property(&parse_properties(&file)?))
+}
+
+/// The FONT property, the font's name
+fn font_property(properties: &[(String, PropertyValue)]) -> Option<String> {
+    properties.iter().find_map(|(key, value)| match value {
+        PropertyValue::Atom(name) if key == "FONT" => Some(name.clone()),
+        _ => None,
+    })
+}
+
+fn parse_properties(file: &[u8]) -> Result<Vec<(String, PropertyValue)>> {
+    let Some(mut table) = Table::find(file, PROPERTIES)? else {
+        return Ok(Vec::new());
+    };
+    let count = table.i32()?.max(0) as usize;
+    let mut raw = Vec::with_capacity(count);
+    for _ in 0..count {
+        let name = table.i32()?;
+        let is_string = table.u8()? != 0;
+        let value = table.i32()?;
+        raw.push((name, is_string, value));
+    }
+    // Padded to a 4-byte boundary
+    table.bytes((4 - count % 4) % 4)?;
+    let strings_size = table.i32()?.max(0) as usize;
+    let strings = table.bytes(strings_size)?;
+    let string_at = |offset: i32| -> String {
+        let start = (offset.max(0) as usize).min(strings.len());
+        let end = strings[start..]
+            .iter()
+            .position(|&b| b == 0)
+            .map_or(strings.len(), |p| start + p);
+        String::from_utf8_lossy(&strings[start..end]).into_owned()
+    };
+
+    Ok(raw
+        .into_iter()
+        .map(|(name, is_string, value)| {
+            let value = if is_string {
+                PropertyValue::Atom(string_at(value))
+            } else {
+                PropertyValue::Int(value)
+            };
+            (string_at(name), value)
+        })
+        .collect())
+}
+
+/// Glyph bitmaps, converted to MSB-first rows padded to whole bytes
+fn parse_bitmaps(file: &[u8], metrics: &[CharInfo]) -> Result<Vec<Vec<u8>>> {
+    let mut table = Table::find(file, BITMAPS)?.ok_or("PCF font has no bitmaps")?;
+    let format = table.format;
+    let count = table.i32()?.max(0) as usize;
+    let offsets = (0..count)
+        .map(|_| table.i32().map(|o| o.max(0) as usize))
+        .collect::<Result<Vec<_>>>()?;
+    let mut sizes = [0usize; 4];
+    for size in &mut sizes {
+        *size = table.i32()?.max(0) as usize;
+    }
+    let pad_index = (format & GLYPH_PAD_MASK) as usize;
+    let mut data = table.bytes(sizes[pad_index])?.to_vec();
+
+    // Normalize to MSB-first bits in left-to-right byte order
+    let msb_bits = format & BIT_MASK != 0;
+    let msb_bytes = format & BYTE_MASK != 0;
+    if !msb_bits {
+        for byte in &mut data {
+            *byte = byte.reverse_bits();
+        }
+    }
+    let scan_unit = 1usize << ((format >> SCAN_UNIT_SHIFT) & 3);
+    if msb_bits != msb_bytes && scan_unit > 1 {
+        for unit in data.chunks_exact_mut(scan_unit) {
+            unit.reverse();
+        }
+    }
+
+    let pad = 1usize << pad_index;
+    Ok(metrics
+        .iter()
+        .zip(offsets)
+        .map(|(info, offset)| {
+            let width = (info.right_side_bearing - info.left_side_bearing).max(0) as usize;
+            let height = (info.ascent + info.descent).max(0) as usize;
+            let src_stride = width.div_ceil(pad * 8) * pad;
+            let dst_stride = width.div_ceil(8);
+            let mut bitmap = vec![0u8; dst_stride * height];
+            for row in 0..height {
+                let src = offset + row * src_stride;
+                if let Some(bytes) = data.get(src..src + dst_stride) {
+                    bitmap[row * dst_stride..(row + 1) * dst_stride].copy_from_slice(bytes);
+                }
+            }
+            bitmap
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A big-endian table: format word (always little endian), then body
+    fn table(format: u32, body: &[u8]) -> Vec<u8> {
+        let mut data = format.to_le_bytes().to_vec();
+        data.extend_from_slice(body);
+        data
+    }
+
+    /// Build a PCF file from (type, table) pairs
+    fn pcf(tables: &[(u32, Vec<u8>)]) -> Vec<u8> {
+        let mut file = MAGIC.to_vec();
+        file.extend_from_slice(&(tables.len() as u32).to_le_bytes());
+        let mut offset = 8 + 16 * tables.len();
+        for (kind, data) in tables {
+            for word in [*kind, 0, data.len() as u32, offset as u32] {
+                file.extend_from_slice(&word.to_le_bytes());
+            }
+            offset += data.len();
+        }
+        for (_, data) in tables {
+            file.extend_from_slice(data);
+        }
+        file
+    }
+
+    #[test]
+    fn test_parse_pcf() {
+        // Big endian, MSB first bits, glyphs padded to 4 bytes
+        let format = BYTE_MASK | BIT_MASK | 2;
+
+        let strings = b"FONT\0-test-pcf-medium-r-normal--2-20-75-75-c-30-iso8859-1\0";
+        let mut props = Vec::new();
+        props.extend_from_slice(&1i32.to_be_bytes());
+        props.extend_from_slice(&0i32.to_be_bytes()); // name offset
+        props.push(1); // string
+        props.extend_from_slice(&5i32.to_be_bytes()); // value offset
+        props.extend_from_slice(&[0; 3]); // pad to 4 properties
+        props.extend_from_slice(&(strings.len() as i32).to_be_bytes());
+        props.extend_from_slice(strings);
+
+        let mut accel = vec![0u8; 8];
+        accel.extend_from_slice(&2i32.to_be_bytes()); // font ascent
+        accel.extend_from_slice(&1i32.to_be_bytes()); // font descent
+
+        // One glyph: 3 wide, 2 high, above the baseline
+        let mut metrics = 1i32.to_be_bytes().to_vec();
+        for v in [0i16, 3, 3, 2, 0, 0] {
+            metrics.extend_from_slice(&v.to_be_bytes());
+        }
+
+        let mut bitmaps = 1i32.to_be_bytes().to_vec();
+        bitmaps.extend_from_slice(&0i32.to_be_bytes()); // offset of glyph 0
+        for size in [2i32, 4, 8, 8] {
+            bitmaps.extend_from_slice(&size.to_be_bytes());
+        }
+        bitmaps.extend_from_slice(&[0xa0, 0, 0, 0, 0x40, 0, 0, 0]);
+
+        let mut encodings = Vec::new();
+        for v in [0x41i16, 0x42, 0, 0, 0x41] {
+            encodings.extend_from_slice(&v.to_be_bytes());
+        }
+        encodings.extend_from_slice(&0i16.to_be_bytes()); // 'A' -> glyph 0
+        encodings.extend_from_slice(&(-1i16).to_be_bytes()); // 'B' missing
+
+        let file = pcf(&[
+            (PROPERTIES, table(format, &props)),
+            (ACCELERATORS, table(format, &accel)),
+            (METRICS, table(format, &metrics)),
+            (BITMAPS, table(format, &bitmaps)),
+            (BDF_ENCODINGS, table(format, &encodings)),
+        ]);
+
+        let font = parse(&file).unwrap();
+        assert_eq!(
+            font.name,
+            "-test-pcf-medium-r-normal--2-20-75-75-c-30-iso8859-1"
+        );
+        assert_eq!((font.font_ascent, font.font_descent), (2, 1));
+        assert_eq!(font.default_char, 0x41);
+        assert_eq!(font.glyphs.len(), 1);
+
+        let glyph = &font.glyphs[&0x41];
+        assert_eq!(glyph.info.character_width, 3);
+        assert_eq!(glyph.bitmap.get(), Some(&vec![0xa0, 0x40]));
+
+        // Directory scans read only as far as the properties
+        let mut header = &file[..8 + 16 * 5 + props.len() + 4];
+        assert_eq!(read_name(&mut header).unwrap(), Some(font.name));
+    }
+}
@@ -0,0 +1,545 @@
+//! TrueType and OpenType fonts
+//!
+//! Outlines are rasterized to one-bit glyphs, sampling pixel centres with
+//! the non-zero winding rule. Opening a size measures every glyph; bitmaps
+//! are only rendered for the glyphs that get drawn.
+
+use super::xlfd::{self, Scale};
+use super::{BitmapFont, CharInfo, Glyph, PropertyValue};
+use crate::backend::BackendFontInfo;
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use std::sync::{Arc, OnceLock};
+use ttf_parser::{Face, GlyphId, OutlineBuilder};
+
+/// Line segments per Bézier curve
+const CURVE_STEPS: usize = 8;
+
+/// Furthest a glyph's ink may reach from its origin, in pixels
+const MAX_EXTENT: f32 = 4096.0;
+
+/// Scalable ISO10646-1 XLFD names of the faces in a font file, with their
+/// index in it
+pub fn face_names(data: &[u8]) -> Vec<(u32, String)> {
+    let count = ttf_parser::fonts_in_collection(data).unwrap_or(1);
+    (0..count)
+        .filter_map(|index| {
+            let face = Face::parse(data, index).ok()?;
+            let family = face
+                .names()
+                .into_iter()
+                .filter(|name| name.name_id == ttf_parser::name_id::FAMILY)
+                .find_map(|name| name.to_string())?;
+            let info = BackendFontInfo {
+                xlfd_name: String::new(),
+                family,
+                weight: if face.is_bold() || face.weight().to_number() >= 600 {
+                    "bold".into()
+                } else {
+                    "medium".into()
+                },
+                slant: if face.is_italic() {
+                    "i".into()
+                } else if face.is_oblique() {
+                    "o".into()
+                } else {
+                    "r".into()
+                },
+                pixel_size: 0,
+                point_size: 0,
+                // Any width marks the font monospaced
+                char_width: face.is_monospaced() as u16,
+                ascent: 0,
+                descent: 0,
+                registry: "iso10646".into(),
+                encoding: "1".into(),
+            };
+            Some((index, info.generate_xlfd()))
+        })
+        .collect()
+}
+
+/// Tables `face_names` looks at
+const NAME_TABLES: [&[u8; 4]; 6] = [b"head", b"hhea", b"maxp", b"name", b"OS/2", b"post"];
+
+/// `face_names` of a font file, reading only the tables it needs
+///
+/// The rest of the file is left zeroed, so scanning a directory of large
+/// fonts reads a few kilobytes of each.
+pub fn read_face_names(path: &Path) -> std::io::Result<Vec<(u32, String)>> {
+    let mut file = File::open(path)?;
+    let mut data = vec![0u8; file.metadata()?.len() as usize];
+    let be_u32 = |data: &[u8], at: usize| {
+        data.get(at..at + 4)
+            .map_or(0, |b| u32::from_be_bytes([b[0], b[1], b[2], b[3]])) as usize
+    };
+    let mut read_at = |data: &mut Vec<u8>, offset: usize, len: usize| -> std::io::Result<()> {
+        let end = offset.saturating_add(len).min(data.len());
+        if offset < end {
+            file.seek(SeekFrom::Start(offset as u64))?;
+            file.read_exact(&mut data[offset..end])?;
+        }
+        Ok(())
+    };
+
+    // A collection header lists the offsets of its faces' table directories
+    read_at(&mut data, 0, 12)?;
+    let faces: Vec<usize> = if data.starts_with(b"ttcf") {
+        let count = be_u32(&data, 8).min(256);
+        read_at(&mut data, 12, count * 4)?;
+        (0..count).map(|i| be_u32(&data, 12 + i * 4)).collect()
+    } else {
+        vec![0]
+    };
+    for face in faces {
+        read_at(&mut data, face, 12)?;
+        let tables = be_u32(&data, face + 2) & 0xffff;
+        read_at(&mut data, face + 12, tables * 16)?;
+        for record in (0..tables).map(|i| face + 12 + i * 16) {
+            let tag = data.get(record..record + 4).unwrap_or_default();
+            if NAME_TABLES.iter().any(|name| name.as_slice() == tag) {
+                let (offset, len) = (be_u32(&data, record + 8), be_u32(&data, record + 12));
+                read_at(&mut data, offset, len)?;
+            }
+        }
+    }
+    Ok(face_names(&data))
+}
+
+/// Open face `index` of a font file at `scale`, as the font `name`
+///
+/// ISO8859-1 fonts get the first 256 characters, others the whole Basic
+/// Multilingual Plane, limited to the code points the cmap maps.
+pub fn rasterize(
+    data: Vec<u8>,
+    index: u32,
+    scale: &Scale,
+    name: &str,
+) -> Result<BitmapFont, Box<dyn Error + Send + Sync>> {
+    let face = Face::parse(&data, index)?;
+    let units = face.units_per_em() as f32;
+    let matrix = scale.matrix.map(|v| v / units);
+    if !matrix.iter().all(|v| v.is_finite()) {
+        return Err(format!("Bad scale for {}", name).into());
+    }
+    let last_code = if name.ends_with("-iso8859-1") {
+        0xff
+    } else {
+        0xffff
+    };
+
+    let mut font = BitmapFont {
+        name: name.to_string(),
+        font_ascent: (face.ascender() as f32 * matrix[3]).ceil() as i16,
+        font_descent: (-face.descender() as f32 * matrix[3]).ceil() as i16,
+        default_char: 0xffff,
+        ..Default::default()
+    };
+    let mut codes = BTreeSet::new();
+    for subtable in face.tables().cmap.iter().flat_map(|cmap| cmap.subtables) {
+        if subtable.is_unicode() {
+            subtable.codepoints(|code| {
+                if code <= last_code {
+                    codes.insert(code);
+                }
+            });
+        }
+    }
+    for code in codes {
+        let Some(glyph_id) = char::from_u32(code).and_then(|c| face.glyph_index(c)) else {
+            continue;
+        };
+        let info = glyph_info(&face, glyph_id, &trace(&face, glyph_id, &matrix), &matrix);
+        font.glyphs.insert(
+            code as u16,
+            Glyph {
+                info,
+                bitmap: OnceLock::new(),
+            },
+        );
+    }
+    if font.glyphs.contains_key(&0x20) {
+        font.default_char = 0x20;
+    }
+    font.properties = properties(name, scale);
+    font.add_standard_properties();
+    font.outlines = Some(Arc::new(Outlines {
+        data,
+        index,
+        matrix,
+    }));
+    Ok(font)
+}
+
+/// A face at one size, from which glyph bitmaps are rendered on first use
+pub struct Outlines {
+    data: Vec<u8>,
+    index: u32,
+    /// Maps font units to pixels
+    matrix: [f32; 4],
+}
+
+impl std::fmt::Debug for Outlines {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Outlines")
+            .field("index", &self.index)
+            .field("matrix", &self.matrix)
+            .finish()
+    }
+}
+
+impl Outlines {
+    /// The bitmap of the glyph for `code`, measured as `info`
+    pub fn render(&self, code: u16, info: &CharInfo) -> Vec<u8> {
+        let Ok(face) = Face::parse(&self.data, self.index) else {
+            return Vec::new();
+        };
+        let Some(glyph_id) = char::from_u32(code as u32).and_then(|c| face.glyph_index(c)) else {
+            return Vec::new();
+        };
+        render_bitmap(&trace(&face, glyph_id, &self.matrix), info)
+    }
+}
+
+/// Properties describing an instance, taken from its name
+fn properties(name: &str, scale: &Scale) -> Vec<(String, PropertyValue)> {
+    let mut properties = Vec::new();
+    let fields = xlfd::fields(name).unwrap_or_default();
+    let atoms = [
+        ("FOUNDRY", 0),
+        ("FAMILY_NAME", 1),
+        ("WEIGHT_NAME", 2),
+        ("SLANT", 3),
+        ("SETWIDTH_NAME", 4),
+        ("ADD_STYLE_NAME", 5),
+        ("SPACING", 10),
+        ("CHARSET_REGISTRY", 12),
+        ("CHARSET_ENCODING", 13),
+    ];
+    for (key, field) in atoms {
+        if let Some(value) = fields.get(field) {
+            properties.push((key.to_string(), PropertyValue::Atom(value.to_uppercase())));
+        }
+    }
+    for (key, value) in [
+        ("PIXEL_SIZE", scale.pixel_size),
+        ("POINT_SIZE", scale.point_size),
+        ("RESOLUTION_X", scale.resolution_x),
+        ("RESOLUTION_Y", scale.resolution_y),
+    ] {
+        properties.push((key.to_string(), PropertyValue::Int(value as i32)));
+    }
+    properties
+}
+
+/// Collects a glyph outline as polygons in pixel space
+struct Outline {
+    matrix: [f32; 4],
+    contours: Vec<Vec<(f32, f32)>>,
+    current: (f32, f32),
+}
+
+impl Outline {
+    fn point(&self, x: f32, y: f32) -> (f32, f32) {
+        let [a, b, c, d] = self.matrix;
+        (a * x + c * y, b * x + d * y)
+    }
+
+    fn push(&mut self, x: f32, y: f32) {
+        self.current = (x, y);
+        let point = self.point(x, y);
+        if let Some(contour) = self.contours.last_mut() {
+            contour.push(point);
+        }
+    }
+}
+
+impl OutlineBuilder for Outline {
+    fn move_to(&mut self, x: f32, y: f32) {
+        self.contours.push(Vec::new());
+        self.push(x, y);
+    }
+
+    fn line_to(&mut self, x: f32, y: f32) {
+        self.push(x, y);
+    }
+
+    fn quad_to(&mut self, x1: f32, y1: f32, x: f32, y: f32) {
+        let (x0, y0) = self.current;
+        for step in 1..=CURVE_STEPS {
+            let t = step as f32 / CURVE_STEPS as f32;
+            let u = 1.0 - t;
+            self.push(
+                u * u * x0 + 2.0 * u * t * x1 + t * t * x,
+                u * u * y0 + 2.0 * u * t * y1 + t * t * y,
+            );
+        }
+    }
+
+    fn curve_to(&mut self, x1: f32, y1: f32, x2: f32, y2: f32, x: f32, y: f32) {
+        let (x0, y0) = self.current;
+        for step in 1..=CURVE_STEPS {
+            let t = step as f32 / CURVE_STEPS as f32;
+            let u = 1.0 - t;
+            let (w0, w1, w2, w3) = (u * u * u, 3.0 * u * u * t, 3.0 * u * t * t, t * t * t);
+            self.push(
+                w0 * x0 + w1 * x1 + w2 * x2 + w3 * x,
+                w0 * y0 + w1 * y1 + w2 * y2 + w3 * y,
+            );
+        }
+    }
+
+    fn close(&mut self) {}
+}
+
+/// A glyph's outline in pixel space; `matrix` maps font units to pixels
+fn trace(face: &Face, glyph_id: GlyphId, matrix: &[f32; 4]) -> Outline {
+    let mut outline = Outline {
+        matrix: *matrix,
+        contours: Vec::new(),
+        current: (0.0, 0.0),
+    };
+    face.outline_glyph(glyph_id, &mut outline);
+    outline
+}
+
+/// A glyph's metrics, from the pixels its outline covers
+///
+/// Bounds are clamped to `MAX_EXTENT`, so broken outlines can't ask for
+/// huge bitmaps.
+fn glyph_info(face: &Face, glyph_id: GlyphId, outline: &Outline, matrix: &[f32; 4]) -> CharInfo {
+    let advance = face.glyph_hor_advance(glyph_id).unwrap_or(0) as f32;
+    let character_width = (advance * matrix[0]).round() as i16;
+
+    let points = outline.contours.iter().flatten();
+    let (mut min_x, mut min_y) = (f32::MAX, f32::MAX);
+    let (mut max_x, mut max_y) = (f32::MIN, f32::MIN);
+    for &(x, y) in points {
+        min_x = min_x.min(x);
+        min_y = min_y.min(y);
+        max_x = max_x.max(x);
+        max_y = max_y.max(y);
+    }
+    if min_x > max_x {
+        // No outline, as for a space
+        return CharInfo {
+            character_width,
+            ..Default::default()
+        };
+    }
+
+    let clamp = |v: f32| v.clamp(-MAX_EXTENT, MAX_EXTENT) as i16;
+    let left = clamp(min_x.floor());
+    let right = clamp(max_x.ceil()).max(left + 1);
+    let top = clamp(max_y.ceil());
+    let bottom = clamp(min_y.floor()).min(top - 1);
+    CharInfo {
+        left_side_bearing: left,
+        right_side_bearing: right,
+        character_width,
+        ascent: top,
+        descent: -bottom,
+        attributes: 0,
+    }
+}
+
+/// Rasterize an outline into the box `info` gives
+fn render_bitmap(outline: &Outline, info: &CharInfo) -> Vec<u8> {
+    let left = info.left_side_bearing as i32;
+    let top = info.ascent as i32;
+    let width = (info.right_side_bearing as i32 - left).max(0) as usize;
+    let height = (top + info.descent as i32).max(0) as usize;
+    let stride = width.div_ceil(8);
+    let mut bitmap = vec![0u8; stride * height];
+
+    let mut crossings: Vec<(f32, i32)> = Vec::new();
+    for row in 0..height {
+        // Sample at pixel centres
+        let y = top as f32 - row as f32 - 0.5;
+        crossings.clear();
+        for contour in &outline.contours {
+            for (i, &(x0, y0)) in contour.iter().enumerate() {
+                let (x1, y1) = contour[(i + 1) % contour.len()];
+                if (y0 <= y) != (y1 <= y) {
+                    let x = x0 + (y - y0) / (y1 - y0) * (x1 - x0);
+                    crossings.push((x, if y1 > y0 { 1 } else { -1 }));
+                }
+            }
+        }
+        crossings.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        let mut winding = 0;
+        for pair in crossings.windows(2) {
+            winding += pair[0].1;
+            if winding == 0 {
+                continue;
+            }
+            // Pixels whose centres lie between the two crossings
+            let start = ((pair[0].0 - 0.5 - left as f32).ceil().max(0.0) as usize).min(width);
+            let end = ((pair[1].0 - 0.5 - left as f32).ceil().max(0.0) as usize).min(width);
+            for col in start..end {
+                bitmap[row * stride + col / 8] |= 0x80 >> (col % 8);
+            }
+        }
+    }
+    bitmap
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A minimal TrueType font with one glyph, a 500x700 unit box, mapped
+    /// to 'I'
+    fn box_font() -> Vec<u8> {
+        fn table(tag: &[u8; 4], data: Vec<u8>) -> ([u8; 4], Vec<u8>) {
+            (*tag, data)
+        }
+        let be16 = |v: i16| v.to_be_bytes().to_vec();
+        let cat = |parts: &[Vec<u8>]| parts.concat();
+
+        let head = cat(&[
+            vec![0, 1, 0, 0],
+            vec![0; 4],
+            vec![0; 4],
+            vec![0x5f, 0x0f, 0x3c, 0xf5],
+            be16(0),
+            be16(1000), // units per em
+            vec![0; 16],
+            be16(0),
+            be16(0),
+            be16(500),
+            be16(700),
+            be16(0),
+            be16(8),
+            be16(2),
+            be16(0), // short loca offsets
+            be16(0),
+        ]);
+        let hhea = cat(&[
+            vec![0, 1, 0, 0],
+            be16(800),  // ascender
+            be16(-200), // descender
+            be16(0),
+            be16(600),
+            vec![0; 20],
+            be16(0),
+            be16(2), // number of hmetrics
+        ]);
+        let maxp = cat(&[vec![0, 0, 0x50, 0], be16(2)]);
+        let hmtx = cat(&[be16(600), be16(0), be16(600), be16(50)]);
+        // Glyph 1: one contour, four on-curve points
+        let glyf = cat(&[
+            be16(1),
+            be16(50),
+            be16(0),
+            be16(550),
+            be16(700),
+            be16(3), // end point of contour 0
+            be16(0), // no instructions
+            vec![0x01; 4],
+            be16(50),
+            be16(0),
+            be16(500),
+            be16(0),
+            be16(0),
+            be16(700),
+            be16(0),
+            be16(-700),
+        ]);
+        let loca = cat(&[be16(0), be16(0), be16(glyf.len() as i16 / 2)]);
+        let cmap = cat(&[
+            be16(0),
+            be16(1),
+            be16(3),
+            be16(1),
+            12u32.to_be_bytes().to_vec(),
+            // Format 4 with segments 'I' and the final 0xFFFF
+            be16(4),
+            be16(32),
+            be16(0),
+            be16(4),
+            be16(4),
+            be16(1),
+            be16(0),
+            be16(0x49),
+            be16(-1),
+            be16(0),
+            be16(0x49),
+            be16(-1),
+            be16(1 - 0x49),
+            be16(1),
+            be16(0),
+            be16(0),
+        ]);
+        let mut tables = [
+            table(b"cmap", cmap),
+            table(b"glyf", glyf),
+            table(b"head", head),
+            table(b"hhea", hhea),
+            table(b"hmtx", hmtx),
+            table(b"loca", loca),
+            table(b"maxp", maxp),
+        ];
+        tables.sort_by_key(|(tag, _)| *tag);
+
+        let mut file = cat(&[vec![0, 1, 0, 0], be16(tables.len() as i16), vec![0; 6]]);
+        let mut offset = 12 + 16 * tables.len();
+        let mut body = Vec::new();
+        for (tag, data) in &tables {
+            file.extend_from_slice(tag);
+            file.extend_from_slice(&[0; 4]);
+            file.extend_from_slice(&(offset as u32).to_be_bytes());
+            file.extend_from_slice(&(data.len() as u32).to_be_bytes());
+            let padded = data.len().div_ceil(4) * 4;
+            body.extend_from_slice(data);
+            body.resize(body.len() + padded - data.len(), 0);
+            offset += padded;
+        }
+        file.extend(body);
+        file
+    }
+
+    #[test]
+    fn test_rasterize() {
+        let scale = Scale {
+            matrix: [10.0, 0.0, 0.0, 10.0],
+            is_matrix: false,
+            pixel_size: 10,
+            point_size: 96,
+            resolution_x: 75,
+            resolution_y: 75,
+        };
+        let font = rasterize(
+            box_font(),
+            0,
+            &scale,
+            "-misc-box-medium-r-normal--10-96-75-75-p-0-iso8859-1",
+        )
+        .unwrap();
+        assert_eq!((font.font_ascent, font.font_descent), (8, 2));
+        assert_eq!(font.glyphs.len(), 1);
+
+        // The box covers x 0.5-5.5 and y 0-7 at 10 pixels per em: pixel
+        // centres 0.5 to 4.5
+        let glyph = font.glyph(b'I' as u16).unwrap();
+        assert_eq!(
+            glyph.info,
+            CharInfo {
+                left_side_bearing: 0,
+                right_side_bearing: 6,
+                character_width: 6,
+                ascent: 7,
+                descent: 0,
+                attributes: 0,
+            }
+        );
+        assert!((0..7).all(|y| (0..5).all(|x| glyph.pixel(x, y)) && !glyph.pixel(5, y)));
+        assert!(font
+            .properties
+            .contains(&("PIXEL_SIZE".to_string(), PropertyValue::Int(10))));
+    }
+}
@@ -0,0 +1,235 @@
+//! XLFD (X Logical Font Description) names
+//!
+//! A scalable font is listed with zeros in its size fields, e.g.
+//! `-misc-dejavu sans-medium-r-normal--0-0-0-0-p-0-iso10646-1`. A request
+//! naming a size, such as `-*-dejavu sans-medium-r-*-*-12-*`, instantiates
+//! it at that size.
+
+use super::catalog::pattern_matches;
+
+/// Field indices, counted after the leading hyphen
+const PIXEL_SIZE: usize = 6;
+const POINT_SIZE: usize = 7;
+const RESOLUTION_X: usize = 8;
+const RESOLUTION_Y: usize = 9;
+const AVERAGE_WIDTH: usize = 11;
+const FIELD_COUNT: usize = 14;
+
+/// Resolution assumed when a name leaves it open
+const DEFAULT_RESOLUTION: u16 = 75;
+
+/// Size of a scalable font opened without one: 12 points
+const DEFAULT_POINT_SIZE: u16 = 120;
+
+/// Largest size, in pixels, a scalable font is rendered at; larger sizes
+/// and matrices are scaled down to it
+const MAX_PIXEL_SIZE: f32 = 1024.0;
+
+/// The size a scalable font is rendered at
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Scale {
+    /// Pixel matrix [a b c d]: a font-space point (x, y), in ems, lands at
+    /// (a*x + c*y, b*x + d*y) pixels, y pointing up
+    pub matrix: [f32; 4],
+    /// Whether the name gave a full matrix rather than a plain size
+    pub is_matrix: bool,
+    pub pixel_size: u16,
+    /// In decipoints
+    pub point_size: u16,
+    pub resolution_x: u16,
+    pub resolution_y: u16,
+}
+
+impl Default for Scale {
+    fn default() -> Self {
+        Scale::from_pixel_size(
+            point_to_pixel(DEFAULT_POINT_SIZE as f32, DEFAULT_RESOLUTION),
+            DEFAULT_RESOLUTION,
+            DEFAULT_RESOLUTION,
+        )
+    }
+}
+
+impl Scale {
+    fn from_pixel_size(pixels: f32, resolution_x: u16, resolution_y: u16) -> Self {
+        Scale {
+            matrix: [pixels, 0.0, 0.0, pixels],
+            is_matrix: false,
+            pixel_size: pixels.round() as u16,
+            point_size: pixel_to_point(pixels, resolution_y),
+            resolution_x,
+            resolution_y,
+        }
+    }
+}
+
+fn point_to_pixel(decipoints: f32, resolution: u16) -> f32 {
+    decipoints / 722.7 * resolution as f32
+}
+
+fn pixel_to_point(pixels: f32, resolution: u16) -> u16 {
+    (pixels * 722.7 / resolution as f32).round() as u16
+}
+
+/// The fields of an XLFD name or pattern, after the leading hyphen
+///
+/// Patterns may have fewer fields, with a `*` standing for the rest.
+pub fn fields(name: &str) -> Option<Vec<&str>> {
+    let fields: Vec<&str> = name.strip_prefix('-')?.split('-').collect();
+    (fields.len() <= FIELD_COUNT).then_some(fields)
+}
+
+/// A size field: a number, or a `[a b c d]` matrix with `~` for minus
+fn size_field(field: &str) -> Option<[f32; 4]> {
+    if let Some(inner) = field.strip_prefix('[').and_then(|f| f.strip_suffix(']')) {
+        let values: Vec<f32> = inner
+            .split_whitespace()
+            .map(|v| v.replace('~', "-").parse().ok())
+            .collect::<Option<_>>()?;
+        let matrix = <[f32; 4]>::try_from(values).ok()?;
+        return matrix.iter().all(|v| v.is_finite()).then_some(matrix);
+    }
+    let size: f32 = field.parse().ok()?;
+    (size.is_finite() && size > 0.0).then_some([size, 0.0, 0.0, size])
+}
+
+fn format_size(value: f32) -> String {
+    let value = (value * 100.0).round() / 100.0;
+    value.to_string().replace('-', "~")
+}
+
+/// The size a name or pattern asks for, if it gives a pixel or point size
+///
+/// Sizes that come to nothing are refused and huge ones are reduced to
+/// `MAX_PIXEL_SIZE`.
+pub fn requested_scale(name: &str) -> Option<Scale> {
+    let mut scale = unbounded_scale(name)?;
+    let largest = scale.matrix.iter().fold(0.0f32, |m, v| m.max(v.abs()));
+    if !(largest.is_finite() && scale.matrix[3].hypot(scale.matrix[2]) > 0.0) {
+        return None;
+    }
+    if largest > MAX_PIXEL_SIZE {
+        let matrix = scale.matrix.map(|v| v * (MAX_PIXEL_SIZE / largest));
+        scale = Scale {
+            matrix,
+            is_matrix: scale.is_matrix,
+            ..Scale::from_pixel_size(
+                matrix[3].hypot(matrix[2]),
+                scale.resolution_x,
+                scale.resolution_y,
+            )
+        };
+    }
+    Some(scale)
+}
+
+fn unbounded_scale(name: &str) -> Option<Scale> {
+    let fields = fields(name)?;
+    let resolution = |index: usize| {
+        fields
+            .get(index)
+            .and_then(|f| f.parse().ok())
+            .filter(|&r| r > 0)
+            .unwrap_or(DEFAULT_RESOLUTION)
+    };
+    let (resolution_x, resolution_y) = (resolution(RESOLUTION_X), resolution(RESOLUTION_Y));
+
+    if let Some(pixel) = fields.get(PIXEL_SIZE).and_then(|f| size_field(f)) {
+        if fields[PIXEL_SIZE].starts_with('[') {
+            let pixel_size = pixel[3].hypot(pixel[2]);
+            return Some(Scale {
+                matrix: pixel,
+                is_matrix: true,
+                pixel_size: pixel_size.round() as u16,
+                point_size: pixel_to_point(pixel_size, resolution_y),
+                resolution_x,
+                resolution_y,
+            });
+        }
+        return Some(Scale::from_pixel_size(pixel[0], resolution_x, resolution_y));
+    }
+    let point = fields.get(POINT_SIZE).and_then(|f| size_field(f))?;
+    let mut scale = Scale::from_pixel_size(
+        point_to_pixel(point[3], resolution_y),
+        resolution_x,
+        resolution_y,
+    );
+    if fields[POINT_SIZE].starts_with('[') {
+        scale.is_matrix = true;
+        scale.matrix = point.map(|v| point_to_pixel(v, resolution_y));
+    }
+    Some(scale)
+}
+
+/// A scalable font's name with the size fields filled in
+pub fn instantiate(template: &str, scale: &Scale) -> Option<String> {
+    let mut fields: Vec<String> = fields(template)?.iter().map(|f| f.to_string()).collect();
+    if fields.len() != FIELD_COUNT {
+        return None;
+    }
+    fields[PIXEL_SIZE] = if scale.is_matrix {
+        let [a, b, c, d] = scale.matrix.map(format_size);
+        format!("[{} {} {} {}]", a, b, c, d)
+    } else {
+        scale.pixel_size.to_string()
+    };
+    fields[POINT_SIZE] = scale.point_size.to_string();
+    fields[RESOLUTION_X] = scale.resolution_x.to_string();
+    fields[RESOLUTION_Y] = scale.resolution_y.to_string();
+    fields[AVERAGE_WIDTH] = "0".to_string();
+    Some(format!("-{}", fields.join("-")))
+}
+
+/// The instance of a scalable font that a name or pattern selects, if any
+pub fn match_scalable(pattern: &str, template: &str) -> Option<(String, Scale)> {
+    let scale = requested_scale(pattern)?;
+    let name = instantiate(template, &scale)?;
+    pattern_matches(pattern, &name).then_some((name, scale))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEMPLATE: &str = "-misc-dejavu sans-medium-r-normal--0-0-0-0-p-0-iso10646-1";
+
+    #[test]
+    fn test_match_scalable() {
+        let (name, scale) = match_scalable("-*-dejavu sans-medium-r-*-*-12-*", TEMPLATE).unwrap();
+        assert_eq!(
+            name,
+            "-misc-dejavu sans-medium-r-normal--12-116-75-75-p-0-iso10646-1"
+        );
+        assert_eq!(scale.matrix, [12.0, 0.0, 0.0, 12.0]);
+
+        // Point size at a given resolution
+        let (_, scale) = match_scalable(
+            "-*-dejavu sans-*-*-*-*-*-140-100-100-*-*-iso10646-1",
+            TEMPLATE,
+        )
+        .unwrap();
+        assert_eq!((scale.pixel_size, scale.point_size), (19, 140));
+
+        // Matrices, with ~ for minus
+        let (name, scale) = match_scalable(
+            "-misc-dejavu sans-medium-r-normal--[12 0 ~2 12]-*-*-*-*-*-iso10646-1",
+            TEMPLATE,
+        )
+        .unwrap();
+        assert!(name.contains("--[12 0 ~2 12]-"));
+        assert_eq!(scale.matrix, [12.0, 0.0, -2.0, 12.0]);
+
+        // Sizes are finite, positive and bounded
+        for size in ["inf", "0", "~5", "[1e40 0 0 1e40]", "[1 0 0 0]"] {
+            let pattern = format!("-*-dejavu sans-medium-r-normal--{}-*", size);
+            assert!(match_scalable(&pattern, TEMPLATE).is_none(), "{}", size);
+        }
+        let scale = requested_scale("-*-dejavu sans-*-*-*-*-65000-*").unwrap();
+        assert_eq!(scale.matrix, [1024.0, 0.0, 0.0, 1024.0]);
+        let scale = requested_scale("-*-dejavu sans-*-*-*-*-[1e30 0 ~1e30 1e30]-*").unwrap();
+        assert_eq!(scale.matrix, [1024.0, 0.0, -1024.0, 1024.0]);
+
+        assert!(match_scalable("-*-helvetica-*-*-*-*-12-*", TEMPLATE).is_none());
+        assert!(match_scalable("-*-dejavu sans-*", TEMPLATE).is_none());
+    }
+}
@@ -8,6 +8,7 @@ use std::process;
 // Internal modules
 mod backend;
 mod connection;
+mod font;
 mod protocol;
 mod resources;
 mod security;
@@ -30,12 +31,14 @@ fn print_usage() {
     println!("  -tcp                  Listen on TCP (port 6000 + display)");
     println!("  -unix                 Listen on Unix socket (default on Unix)");
     println!("  -security <level>     Security level: permissive, default, strict");
+    println!("  -rooted <WxH>         Run as a single WxH desktop window (x11 backend)");
     println!("  -list-backends        List available backends");
     println!("  -h, --help            Show this help message");
     println!();
     println!("Examples:");
     println!("  x11anywhere -display 1 -backend x11");
     println!("  x11anywhere -display 2 -backend wayland -tcp");
+    println!("  x11anywhere -display 3 -backend x11 -rooted 1024x768");
     println!();
 }
 
@@ -73,6 +76,7 @@ struct Config {
     listen_tcp: bool,
     listen_unix: bool,
     security: SecurityPolicy,
+    rooted: Option<(u16, u16)>,
 }
 
 impl Default for Config {
@@ -89,6 +93,7 @@ impl Default for Config {
             #[cfg(not(unix))]
             listen_unix: false,
             security: SecurityPolicy::default(),
+            rooted: None,
         }
     }
 }
@@ -142,6 +147,18 @@ fn parse_args() -> Result<Config, String> {
                     _ => return Err(format!("Invalid security level: {}", args[i])),
                 };
             }
+            "-rooted" => {
+                i += 1;
+                if i >= args.len() {
+                    return Err("Missing value for -rooted".to_string());
+                }
+                let size = args[i]
+                    .split_once('x')
+                    .and_then(|(w, h)| Some((w.parse().ok()?, h.parse().ok()?)))
+                    .filter(|&(w, h): &(u16, u16)| w > 0 && h > 0)
+                    .ok_or_else(|| format!("Invalid size for -rooted: {}", args[i]))?;
+                config.rooted = Some(size);
+            }
             arg => {
                 return Err(format!("Unknown option: {}", arg));
             }
@@ -255,6 +272,12 @@ fn main() {
         config.security.window_isolation,
         config.security.allow_global_selections
     );
+    if let Some((width, height)) = config.rooted {
+        log::info!("Rooted mode: {}x{}", width, height);
+        if backend_type != "x11" {
+            log::warn!("Rooted mode is only supported by the x11 backend, ignoring");
+        }
+    }
 
     // Initialize backend based on platform
     let backend: Box<dyn backend::Backend> = {
@@ -282,7 +305,11 @@ fn main() {
                 "Initializing X11 backend, connecting to display {}",
                 target_display
             );
-            Box::new(backend::x11::X11Backend::new(&target_display))
+            let x11_backend = backend::x11::X11Backend::new(&target_display);
+            match config.rooted {
+                Some((width, height)) => Box::new(x11_backend.with_rooted(width, height)),
+                None => Box::new(x11_backend),
+            }
         }
 
         #[cfg(not(any(
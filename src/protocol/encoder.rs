@@ -3,6 +3,37 @@
 //! This module encodes replies and events to the wire protocol.
 
 use super::*;
+use crate::font::CharInfo;
+
+/// Font metrics and properties sent in QueryFont and ListFontsWithInfo
+/// replies
+#[derive(Debug, Clone, Default)]
+pub struct FontReplyInfo {
+    pub min_bounds: CharInfo,
+    pub max_bounds: CharInfo,
+    pub min_char_or_byte2: u16,
+    pub max_char_or_byte2: u16,
+    pub default_char: u16,
+    pub min_byte1: u8,
+    pub max_byte1: u8,
+    pub all_chars_exist: bool,
+    pub font_ascent: i16,
+    pub font_descent: i16,
+    /// (name atom, value) pairs; string values are atoms too
+    pub properties: Vec<(u32, u32)>,
+}
+
+/// Metrics of a string, as in a QueryTextExtents reply
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TextExtents {
+    pub font_ascent: i16,
+    pub font_descent: i16,
+    pub overall_ascent: i16,
+    pub overall_descent: i16,
+    pub overall_width: i32,
+    pub overall_left: i32,
+    pub overall_right: i32,
+}
 
 /// Reply encoder
 pub struct ProtocolEncoder {
@@ -311,61 +342,81 @@ impl ProtocolEncoder {
     pub fn encode_query_font_reply(
         &self,
         sequence: u16,
-        font_ascent: i16,
-        font_descent: i16,
-        char_width: i16,
-        _char_height: i16,
+        info: &FontReplyInfo,
+        char_infos: &[CharInfo],
     ) -> Vec<u8> {
-        // X11 QueryFont reply structure (60 bytes for n=0 properties, m=0 char infos):
-        // bytes 0-7:   reply header (type, unused, sequence, length)
-        // bytes 8-19:  min_bounds CHARINFO (12 bytes)
-        // bytes 20-23: unused (4 bytes)
-        // bytes 24-35: max_bounds CHARINFO (12 bytes)
-        // bytes 36-39: unused (4 bytes)
-        // bytes 40-59: font info (20 bytes)
-        // reply_length = (60 - 32) / 4 = 7
+        // 60-byte fixed part, then properties (8 bytes each) and CHARINFOs
+        // (12 bytes each)
         let mut buffer = vec![0u8; 60];
+        buffer[0] = 1; // Reply
+        buffer[2..4].copy_from_slice(&self.write_u16(sequence));
+        let reply_length = 7 + 2 * info.properties.len() + 3 * char_infos.len();
+        buffer[4..8].copy_from_slice(&self.write_u32(reply_length as u32));
+        self.write_font_info(&mut buffer, info);
+        buffer[56..60].copy_from_slice(&self.write_u32(char_infos.len() as u32)); // n_char_infos
 
-        // CHARINFO for min_bounds (all zeros for simplicity)
-        let min_bounds = [0u8; 12];
+        self.write_font_properties(&mut buffer, info);
+        for char_info in char_infos {
+            buffer.extend_from_slice(&self.write_char_info(char_info));
+        }
 
-        // CHARINFO for max_bounds
-        let mut max_bounds = [0u8; 12];
-        max_bounds[0..2].copy_from_slice(&self.write_i16(0)); // left_side_bearing
-        max_bounds[2..4].copy_from_slice(&self.write_i16(char_width)); // right_side_bearing
-        max_bounds[4..6].copy_from_slice(&self.write_i16(char_width)); // character_width
-        max_bounds[6..8].copy_from_slice(&self.write_i16(font_ascent)); // ascent
-        max_bounds[8..10].copy_from_slice(&self.write_i16(font_descent)); // descent
-        max_bounds[10..12].copy_from_slice(&self.write_u16(0)); // attributes
+        buffer
+    }
 
-        // Reply header
+    /// Encode QueryTextExtents reply
+    pub fn encode_query_text_extents_reply(&self, sequence: u16, extents: &TextExtents) -> Vec<u8> {
+        let mut buffer = vec![0u8; 32];
         buffer[0] = 1; // Reply
-                       // buffer[1] = unused (already 0)
+        buffer[1] = 0; // draw_direction: LeftToRight
         buffer[2..4].copy_from_slice(&self.write_u16(sequence));
-        buffer[4..8].copy_from_slice(&self.write_u32(7)); // reply_length = 7 for n=0, m=0
-
-        // min_bounds at bytes 8-19
-        buffer[8..20].copy_from_slice(&min_bounds);
-        // bytes 20-23 unused (already 0)
-
-        // max_bounds at bytes 24-35
-        buffer[24..36].copy_from_slice(&max_bounds);
-        // bytes 36-39 unused (already 0)
+        buffer[4..8].copy_from_slice(&self.write_u32(0)); // length
+        buffer[8..10].copy_from_slice(&self.write_i16(extents.font_ascent));
+        buffer[10..12].copy_from_slice(&self.write_i16(extents.font_descent));
+        buffer[12..14].copy_from_slice(&self.write_i16(extents.overall_ascent));
+        buffer[14..16].copy_from_slice(&self.write_i16(extents.overall_descent));
+        buffer[16..20].copy_from_slice(&self.write_u32(extents.overall_width as u32));
+        buffer[20..24].copy_from_slice(&self.write_u32(extents.overall_left as u32));
+        buffer[24..28].copy_from_slice(&self.write_u32(extents.overall_right as u32));
+        buffer
+    }
 
-        // Font info at bytes 40-59
-        buffer[40..42].copy_from_slice(&self.write_u16(0)); // min_char_or_byte2
-        buffer[42..44].copy_from_slice(&self.write_u16(255)); // max_char_or_byte2
-        buffer[44..46].copy_from_slice(&self.write_u16(0)); // default_char
-        buffer[46..48].copy_from_slice(&self.write_u16(0)); // n_font_props
+    /// Fill in the bounds and font info shared by QueryFont and
+    /// ListFontsWithInfo replies (bytes 8-55)
+    fn write_font_info(&self, buffer: &mut [u8], info: &FontReplyInfo) {
+        buffer[8..20].copy_from_slice(&self.write_char_info(&info.min_bounds));
+        // bytes 20-23 unused
+        buffer[24..36].copy_from_slice(&self.write_char_info(&info.max_bounds));
+        // bytes 36-39 unused
+        buffer[40..42].copy_from_slice(&self.write_u16(info.min_char_or_byte2));
+        buffer[42..44].copy_from_slice(&self.write_u16(info.max_char_or_byte2));
+        buffer[44..46].copy_from_slice(&self.write_u16(info.default_char));
+        buffer[46..48].copy_from_slice(&self.write_u16(info.properties.len() as u16));
         buffer[48] = 0; // draw_direction (LeftToRight)
-        buffer[49] = 0; // min_byte1
-        buffer[50] = 0; // max_byte1
-        buffer[51] = 1; // all_chars_exist
-        buffer[52..54].copy_from_slice(&self.write_i16(font_ascent)); // font_ascent
-        buffer[54..56].copy_from_slice(&self.write_i16(font_descent)); // font_descent
-        buffer[56..60].copy_from_slice(&self.write_u32(0)); // n_char_infos (m)
+        buffer[49] = info.min_byte1;
+        buffer[50] = info.max_byte1;
+        buffer[51] = info.all_chars_exist as u8;
+        buffer[52..54].copy_from_slice(&self.write_i16(info.font_ascent));
+        buffer[54..56].copy_from_slice(&self.write_i16(info.font_descent));
+    }
 
-        buffer
+    /// Append the FONTPROP list
+    fn write_font_properties(&self, buffer: &mut Vec<u8>, info: &FontReplyInfo) {
+        for &(name, value) in &info.properties {
+            buffer.extend_from_slice(&self.write_u32(name));
+            buffer.extend_from_slice(&self.write_u32(value));
+        }
+    }
+
+    /// Encode a CHARINFO
+    fn write_char_info(&self, info: &CharInfo) -> [u8; 12] {
+        let mut out = [0u8; 12];
+        out[0..2].copy_from_slice(&self.write_i16(info.left_side_bearing));
+        out[2..4].copy_from_slice(&self.write_i16(info.right_side_bearing));
+        out[4..6].copy_from_slice(&self.write_i16(info.character_width));
+        out[6..8].copy_from_slice(&self.write_i16(info.ascent));
+        out[8..10].copy_from_slice(&self.write_i16(info.descent));
+        out[10..12].copy_from_slice(&self.write_u16(info.attributes));
+        out
     }
 
     /// Encode a simple success reply (no data)
@@ -408,68 +459,35 @@ impl ProtocolEncoder {
         buffer
     }
 
+    /// Encode GetFontPath reply, laid out like a ListFonts reply
+    pub fn encode_get_font_path_reply(&self, sequence: u16, path: &[String]) -> Vec<u8> {
+        self.encode_list_fonts_reply(sequence, path)
+    }
+
     /// Encode ListFontsWithInfo reply for a single font
     /// The X11 protocol sends one reply per matching font, each with font metrics
     pub fn encode_list_fonts_with_info_reply(
         &self,
         sequence: u16,
         font_name: &str,
+        info: &FontReplyInfo,
         replies_hint: u32,
     ) -> Vec<u8> {
         let name_bytes = font_name.as_bytes();
-        let name_len = name_bytes.len().min(255) as u8;
+        let name_len = name_bytes.len().min(255);
 
-        // Calculate reply length: 7 (base 60 bytes - 32 header = 28 / 4) + name padding
-        let name_padded_len = (name_len as usize + 3) & !3; // Round up to 4
-        let reply_length = 7 + (name_padded_len / 4);
-
-        let mut buffer = vec![0u8; 60 + name_padded_len];
-
-        // Header
+        let mut buffer = vec![0u8; 60];
         buffer[0] = 1; // Reply
-        buffer[1] = name_len; // name-len in byte 1
+        buffer[1] = name_len as u8; // name-len in byte 1
         buffer[2..4].copy_from_slice(&self.write_u16(sequence));
-        buffer[4..8].copy_from_slice(&self.write_u32(reply_length as u32));
-
-        // min-bounds CHARINFO (12 bytes) at offset 8
-        // left-side-bearing, right-side-bearing, character-width, ascent, descent, attributes
-        buffer[8..10].copy_from_slice(&self.write_i16(0)); // left-side-bearing
-        buffer[10..12].copy_from_slice(&self.write_i16(8)); // right-side-bearing
-        buffer[12..14].copy_from_slice(&self.write_i16(8)); // character-width
-        buffer[14..16].copy_from_slice(&self.write_i16(10)); // ascent
-        buffer[16..18].copy_from_slice(&self.write_i16(2)); // descent
-        buffer[18..20].copy_from_slice(&self.write_u16(0)); // attributes
-
-        // unused 4 bytes at offset 20
-        // (already zeroed)
-
-        // max-bounds CHARINFO (12 bytes) at offset 24
-        buffer[24..26].copy_from_slice(&self.write_i16(0)); // left-side-bearing
-        buffer[26..28].copy_from_slice(&self.write_i16(8)); // right-side-bearing
-        buffer[28..30].copy_from_slice(&self.write_i16(8)); // character-width
-        buffer[30..32].copy_from_slice(&self.write_i16(10)); // ascent
-        buffer[32..34].copy_from_slice(&self.write_i16(2)); // descent
-        buffer[34..36].copy_from_slice(&self.write_u16(0)); // attributes
-
-        // unused 4 bytes at offset 36
-        // (already zeroed)
-
-        // Font metrics at offset 40
-        buffer[40..42].copy_from_slice(&self.write_u16(0)); // min-char-or-byte2
-        buffer[42..44].copy_from_slice(&self.write_u16(255)); // max-char-or-byte2
-        buffer[44..46].copy_from_slice(&self.write_u16(0)); // default-char
-        buffer[46..48].copy_from_slice(&self.write_u16(0)); // n-properties
-        buffer[48] = 0; // draw-direction (LeftToRight)
-        buffer[49] = 0; // min-byte1
-        buffer[50] = 0; // max-byte1
-        buffer[51] = 0; // all-chars-exist
-        buffer[52..54].copy_from_slice(&self.write_i16(10)); // font-ascent
-        buffer[54..56].copy_from_slice(&self.write_i16(2)); // font-descent
+        self.write_font_info(&mut buffer, info);
         buffer[56..60].copy_from_slice(&self.write_u32(replies_hint)); // replies-hint
 
-        // Font name at offset 60
-        buffer[60..60 + name_len as usize].copy_from_slice(&name_bytes[..name_len as usize]);
-        // Rest is padding (already zeroed)
+        self.write_font_properties(&mut buffer, info);
+        buffer.extend_from_slice(&name_bytes[..name_len]);
+        buffer.resize((buffer.len() + 3) & !3, 0);
+        let reply_length = (buffer.len() - 32) / 4;
+        buffer[4..8].copy_from_slice(&self.write_u32(reply_length as u32));
 
         buffer
     }
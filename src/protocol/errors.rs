@@ -24,6 +24,12 @@ pub enum ErrorCode {
     Name = 15,
     Length = 16,
     Implementation = 17,
+    /// MIT-SHM BadShmSeg, at the extension's first error
+    ShmSeg = 130,
+    /// XFIXES BadRegion, at the extension's first error
+    Region = 140,
+    /// RENDER BadPicture, the extension's second error
+    Picture = 143,
 }
 
 impl ErrorCode {
@@ -46,6 +52,9 @@ impl ErrorCode {
             15 => Some(ErrorCode::Name),
             16 => Some(ErrorCode::Length),
             17 => Some(ErrorCode::Implementation),
+            130 => Some(ErrorCode::ShmSeg),
+            140 => Some(ErrorCode::Region),
+            143 => Some(ErrorCode::Picture),
             _ => None,
         }
     }
@@ -69,6 +78,9 @@ impl ErrorCode {
             ErrorCode::Name => "Name: font or color name doesn't exist",
             ErrorCode::Length => "Length: request length incorrect",
             ErrorCode::Implementation => "Implementation: server implementation error",
+            ErrorCode::ShmSeg => "ShmSeg: invalid shared memory segment",
+            ErrorCode::Region => "Region: invalid Region parameter",
+            ErrorCode::Picture => "Picture: invalid Picture parameter",
         }
     }
 }
@@ -169,6 +181,14 @@ impl X11Error {
         X11Error::new(ErrorCode::Atom, sequence, atom.get(), 0, major_opcode)
     }
 
+    pub fn bad_font(sequence: u16, font: u32, major_opcode: u8) -> Self {
+        X11Error::new(ErrorCode::Font, sequence, font, 0, major_opcode)
+    }
+
+    pub fn bad_cursor(sequence: u16, cursor: u32, major_opcode: u8) -> Self {
+        X11Error::new(ErrorCode::Cursor, sequence, cursor, 0, major_opcode)
+    }
+
     pub fn bad_drawable(sequence: u16, drawable: Drawable, major_opcode: u8) -> Self {
         X11Error::new(
             ErrorCode::Drawable,
@@ -213,6 +233,18 @@ impl X11Error {
         X11Error::new(ErrorCode::Length, sequence, 0, 0, major_opcode)
     }
 
+    pub fn bad_shm_seg(sequence: u16, shmseg: u32, major_opcode: u8) -> Self {
+        X11Error::new(ErrorCode::ShmSeg, sequence, shmseg, 0, major_opcode)
+    }
+
+    pub fn bad_region(sequence: u16, region: u32, major_opcode: u8) -> Self {
+        X11Error::new(ErrorCode::Region, sequence, region, 0, major_opcode)
+    }
+
+    pub fn bad_picture(sequence: u16, picture: u32, major_opcode: u8) -> Self {
+        X11Error::new(ErrorCode::Picture, sequence, picture, 0, major_opcode)
+    }
+
     pub fn implementation_error(sequence: u16, major_opcode: u8) -> Self {
         X11Error::new(ErrorCode::Implementation, sequence, 0, 0, major_opcode)
     }
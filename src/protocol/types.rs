@@ -462,6 +462,31 @@ pub enum GCFunction {
     Set = 15,
 }
 
+impl GCFunction {
+    pub fn from_u32(value: u32) -> Option<Self> {
+        use GCFunction::*;
+        const ALL: [GCFunction; 16] = [
+            Clear,
+            And,
+            AndReverse,
+            Copy,
+            AndInverted,
+            NoOp,
+            Xor,
+            Or,
+            Nor,
+            Equiv,
+            Invert,
+            OrReverse,
+            CopyInverted,
+            OrInverted,
+            Nand,
+            Set,
+        ];
+        ALL.get(value as usize).copied()
+    }
+}
+
 /// Line style
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum LineStyle {
@@ -510,6 +535,63 @@ pub enum ArcMode {
     PieSlice = 1,
 }
 
+/// Subwindow mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubwindowMode {
+    ClipByChildren = 0,
+    IncludeInferiors = 1,
+}
+
+/// Clip rectangle ordering (SetClipRectangles)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClipOrdering {
+    Unsorted = 0,
+    YSorted = 1,
+    YXSorted = 2,
+    YXBanded = 3,
+}
+
+impl ClipOrdering {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(ClipOrdering::Unsorted),
+            1 => Some(ClipOrdering::YSorted),
+            2 => Some(ClipOrdering::YXSorted),
+            3 => Some(ClipOrdering::YXBanded),
+            _ => None,
+        }
+    }
+
+    /// Whether `rects` really are in this order
+    pub fn verify(self, rects: &[Rectangle]) -> bool {
+        let mut prev: Option<&Rectangle> = None;
+        for rect in rects {
+            if let Some(prev) = prev {
+                let ok = match self {
+                    ClipOrdering::Unsorted => true,
+                    ClipOrdering::YSorted => rect.y >= prev.y,
+                    ClipOrdering::YXSorted => {
+                        rect.y > prev.y || (rect.y == prev.y && rect.x >= prev.x)
+                    }
+                    // Rectangles in a band share y and height, bands don't overlap
+                    ClipOrdering::YXBanded => {
+                        if rect.y == prev.y {
+                            rect.height == prev.height && rect.x >= prev.x
+                        } else {
+                            rect.y as i32 >= prev.y as i32 + prev.height as i32
+                        }
+                    }
+                };
+                if !ok {
+                    return false;
+                }
+            }
+            prev = Some(rect);
+        }
+        true
+    }
+}
+
 /// Image format
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ImageFormat {
@@ -70,6 +70,11 @@ impl ResourceTracker {
         client_id
     }
 
+    /// IDs of the connected clients
+    pub fn client_ids(&self) -> impl Iterator<Item = u32> + '_ {
+        self.windows.keys().copied()
+    }
+
     /// Unregister a client (called on disconnect)
     pub fn unregister_client(&mut self, client_id: u32) -> Vec<CleanupRequest> {
         let mut cleanup_requests = Vec::new();
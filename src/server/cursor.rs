@@ -0,0 +1,428 @@
+//! Cursors built from images
+//!
+//! The server renders cursor images itself, from pictures through the
+//! software compositor or from bitmaps and glyphs in two colors, and hands
+//! them to the backend as ARGB frames. The frames are kept so animated
+//! cursors can be assembled from existing cursors, and two-color images so
+//! they can be recolored. Glyphs of a cursor font only the backend knows
+//! become its standard cursors.
+
+use super::Server;
+use crate::backend::composite::{self, PictureSource};
+use crate::backend::{CursorFrame, StandardCursor};
+use crate::font::Glyph;
+use crate::protocol::*;
+use std::error::Error;
+
+/// A two-color cursor image
+#[derive(Debug, Clone)]
+pub(super) struct BitmapCursor {
+    width: u16,
+    height: u16,
+    x_hot: u16,
+    y_hot: u16,
+    /// Pixels shown in the foreground color rather than the background,
+    /// row by row
+    source: Vec<bool>,
+    /// Pixels shown at all
+    mask: Vec<bool>,
+}
+
+impl BitmapCursor {
+    /// The image in (red, green, blue) `foreground` and `background`
+    fn frame(&self, foreground: [u16; 3], background: [u16; 3]) -> CursorFrame {
+        let pixel = |[red, green, blue]: [u16; 3]| {
+            0xff00_0000 | (red as u32 >> 8) << 16 | (green as u32 >> 8) << 8 | blue as u32 >> 8
+        };
+        let (foreground, background) = (pixel(foreground), pixel(background));
+        let pixels = self
+            .source
+            .iter()
+            .zip(&self.mask)
+            .map(|(&source, &mask)| match (mask, source) {
+                (false, _) => 0,
+                (true, true) => foreground,
+                (true, false) => background,
+            })
+            .collect();
+        CursorFrame {
+            width: self.width,
+            height: self.height,
+            x_hot: self.x_hot,
+            y_hot: self.y_hot,
+            pixels,
+            delay: 0,
+        }
+    }
+
+    /// A glyph cursor: the image covers the mask glyph, or the source glyph
+    /// if there is none, with the hotspot at the glyph origin
+    fn from_glyphs(source: &Glyph, mask: Option<&Glyph>) -> Self {
+        let bounds = mask.unwrap_or(source);
+        let (width, height) = (bounds.width(), bounds.height());
+        let x_hot = -bounds.info.left_side_bearing as i32;
+        let y_hot = bounds.info.ascent as i32;
+        // Set the bits of a glyph drawn at the hotspot
+        let draw = |glyph: &Glyph| {
+            let mut bits = vec![false; width * height];
+            for y in 0..glyph.height() {
+                for x in 0..glyph.width() {
+                    let bx = x_hot + glyph.info.left_side_bearing as i32 + x as i32;
+                    let by = y_hot - glyph.info.ascent as i32 + y as i32;
+                    if glyph.pixel(x, y)
+                        && (0..width as i32).contains(&bx)
+                        && (0..height as i32).contains(&by)
+                    {
+                        bits[by as usize * width + bx as usize] = true;
+                    }
+                }
+            }
+            bits
+        };
+        BitmapCursor {
+            width: width as u16,
+            height: height as u16,
+            x_hot: x_hot.clamp(0, u16::MAX as i32) as u16,
+            y_hot: y_hot.clamp(0, u16::MAX as i32) as u16,
+            source: draw(source),
+            // Without a mask glyph the whole image shows
+            mask: mask.map_or_else(|| vec![true; width * height], draw),
+        }
+    }
+}
+
+/// What a core cursor was made from
+#[derive(Debug, Clone)]
+pub(super) enum CoreCursor {
+    Bitmap(BitmapCursor),
+    /// A glyph of a cursor font the backend draws
+    Standard(StandardCursor),
+}
+
+impl Server {
+    /// Create a cursor from depth-1 `source` and `mask` (0 for none)
+    /// pixmaps, with the hotspot at (`x`, `y`) (CreateCursor)
+    #[allow(clippy::too_many_arguments)]
+    pub fn create_bitmap_cursor(
+        &mut self,
+        cursor_id: u32,
+        source: u32,
+        mask: u32,
+        foreground: [u16; 3],
+        background: [u16; 3],
+        x: u16,
+        y: u16,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let (width, height, source) = self.pixmap_bits(source)?;
+        let mask = match mask {
+            0 => vec![true; source.len()],
+            mask => {
+                let (mask_width, mask_height, mask) = self.pixmap_bits(mask)?;
+                if (mask_width, mask_height) != (width, height) {
+                    return Err(X11Error::bad_match(0, 0).into());
+                }
+                mask
+            }
+        };
+        if x >= width || y >= height {
+            return Err(X11Error::bad_match(0, 0).into());
+        }
+        let bitmap = BitmapCursor {
+            width,
+            height,
+            x_hot: x,
+            y_hot: y,
+            source,
+            mask,
+        };
+        self.create_image_cursor(cursor_id, vec![bitmap.frame(foreground, background)])?;
+        self.core_cursors
+            .insert(cursor_id, CoreCursor::Bitmap(bitmap));
+        Ok(())
+    }
+
+    /// Create a cursor from a glyph of `source_font`, shown through a glyph
+    /// of `mask_font` (0 for none) (CreateGlyphCursor)
+    #[allow(clippy::too_many_arguments)]
+    pub fn create_glyph_cursor(
+        &mut self,
+        cursor_id: u32,
+        source_font: u32,
+        mask_font: u32,
+        source_char: u16,
+        mask_char: u16,
+        foreground: [u16; 3],
+        background: [u16; 3],
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let source = self.font_by_id(source_font)?;
+        let mask = match mask_font {
+            0 => None,
+            font => Some(self.font_by_id(font)?),
+        };
+
+        let Some(font) = &source.font else {
+            // The backend's cursor font, as its standard shapes
+            let shape = StandardCursor::from_glyph(source_char).unwrap_or_else(|| {
+                log::debug!(
+                    "No standard cursor for glyph {}, using left_ptr",
+                    source_char
+                );
+                StandardCursor::LeftPtr
+            });
+            self.create_cursor_from_standard(cursor_id, shape)?;
+            self.backend
+                .recolor_cursor(self.cursors[&cursor_id], foreground, background)?;
+            self.core_cursors
+                .insert(cursor_id, CoreCursor::Standard(shape));
+            return Ok(());
+        };
+        let source_glyph = font
+            .glyph(source_char)
+            .ok_or_else(|| X11Error::bad_value(0, source_char as u32, 0))?;
+        let mask_glyph = match &mask {
+            Some(mask) => {
+                let glyph = mask
+                    .font
+                    .as_ref()
+                    .and_then(|font| font.glyph(mask_char))
+                    .ok_or_else(|| X11Error::bad_value(0, mask_char as u32, 0))?;
+                Some(glyph)
+            }
+            None => None,
+        };
+        let bitmap = BitmapCursor::from_glyphs(source_glyph, mask_glyph);
+        self.create_image_cursor(cursor_id, vec![bitmap.frame(foreground, background)])?;
+        self.core_cursors
+            .insert(cursor_id, CoreCursor::Bitmap(bitmap));
+        Ok(())
+    }
+
+    /// Change the colors of a core cursor (RecolorCursor)
+    ///
+    /// Windows showing the cursor change with it; RENDER cursors keep their
+    /// own colors.
+    pub fn recolor_cursor(
+        &mut self,
+        cursor_id: u32,
+        foreground: [u16; 3],
+        background: [u16; 3],
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let Some(&backend_cursor) = self.cursors.get(&cursor_id) else {
+            return Err(X11Error::bad_cursor(0, cursor_id, 0).into());
+        };
+        match self.core_cursors.get(&cursor_id) {
+            Some(CoreCursor::Bitmap(bitmap)) => {
+                let frame = bitmap.frame(foreground, background);
+                let recolored = self
+                    .backend
+                    .create_image_cursor(std::slice::from_ref(&frame))?;
+                self.backend.free_cursor(backend_cursor)?;
+                self.cursors.insert(cursor_id, recolored);
+                self.cursor_frames.insert(cursor_id, vec![frame]);
+                let windows: Vec<_> = self
+                    .window_info
+                    .iter()
+                    .filter(|(_, info)| info.cursor == cursor_id)
+                    .filter_map(|(window, _)| self.windows.get(window).copied())
+                    .collect();
+                for window in windows {
+                    self.backend.set_window_cursor(window, recolored)?;
+                }
+            }
+            Some(CoreCursor::Standard(_)) => {
+                self.backend
+                    .recolor_cursor(backend_cursor, foreground, background)?;
+            }
+            None => {}
+        }
+        Ok(())
+    }
+
+    /// Create a cursor from the image of a picture, with its hotspot at
+    /// (`x`, `y`) (RENDER CreateCursor)
+    pub fn create_render_cursor(
+        &mut self,
+        cursor_id: u32,
+        picture_id: u32,
+        x: u16,
+        y: u16,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let picture = self.composite_picture(picture_id)?;
+        let PictureSource::Drawable { width, height, .. } = picture.source else {
+            return Err(X11Error::bad_match(0, 0).into());
+        };
+        if x >= width || y >= height {
+            return Err(X11Error::bad_match(0, 0).into());
+        }
+        let area = Rectangle {
+            x: 0,
+            y: 0,
+            width,
+            height,
+        };
+        let pixels = composite::read_picture(&mut *self.backend, &picture, area)?;
+        let frame = CursorFrame {
+            width,
+            height,
+            x_hot: x,
+            y_hot: y,
+            pixels,
+            delay: 0,
+        };
+        self.create_image_cursor(cursor_id, vec![frame])
+    }
+
+    /// Create an animated cursor from existing cursors, each shown for its
+    /// delay in milliseconds (RENDER CreateAnimCursor)
+    pub fn create_anim_cursor(
+        &mut self,
+        cursor_id: u32,
+        elements: &[(u32, u32)],
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut frames = Vec::with_capacity(elements.len());
+        for &(cursor, delay) in elements {
+            let Some(images) = self.cursor_frames.get(&cursor) else {
+                return Err(X11Error::bad_cursor(0, cursor, 0).into());
+            };
+            // Frames can't be animations themselves
+            let [image] = &images[..] else {
+                return Err(X11Error::bad_match(0, 0).into());
+            };
+            frames.push(CursorFrame {
+                delay,
+                ..image.clone()
+            });
+        }
+        if frames.is_empty() {
+            return Err(X11Error::bad_value(0, 0, 0).into());
+        }
+        self.create_image_cursor(cursor_id, frames)
+    }
+
+    /// Create a backend cursor from frames and store it
+    fn create_image_cursor(
+        &mut self,
+        cursor_id: u32,
+        frames: Vec<CursorFrame>,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let backend_cursor = self.backend.create_image_cursor(&frames)?;
+        log::debug!(
+            "Created image cursor 0x{:x} ({} frames) -> backend {:?}",
+            cursor_id,
+            frames.len(),
+            backend_cursor
+        );
+        self.cursors.insert(cursor_id, backend_cursor);
+        self.cursor_frames.insert(cursor_id, frames);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::null::NullBackend;
+
+    #[test]
+    fn test_render_cursors() {
+        let mut server = Server::new(Box::new(NullBackend::new())).unwrap();
+        server.create_pixmap(0x0020_0010, 16, 16, 32).unwrap();
+        server.create_picture(0x0020_0001, 0x0020_0010, 1);
+        server
+            .create_render_cursor(0x0020_0002, 0x0020_0001, 3, 4)
+            .unwrap();
+        let frame = &server.cursor_frames[&0x0020_0002][0];
+        assert_eq!(
+            (frame.width, frame.height, frame.x_hot, frame.y_hot),
+            (16, 16, 3, 4)
+        );
+        assert_eq!(frame.pixels.len(), 256);
+        // The hotspot must be inside the image, and the source a drawable
+        assert!(server
+            .create_render_cursor(0x0020_0003, 0x0020_0001, 16, 0)
+            .is_err());
+        server.create_solid_fill(0x0020_0004, 0, 0, 0, 0xffff);
+        assert!(server
+            .create_render_cursor(0x0020_0003, 0x0020_0004, 0, 0)
+            .is_err());
+
+        server
+            .create_anim_cursor(0x0020_0005, &[(0x0020_0002, 50), (0x0020_0002, 100)])
+            .unwrap();
+        let delays: Vec<u32> = server.cursor_frames[&0x0020_0005]
+            .iter()
+            .map(|f| f.delay)
+            .collect();
+        assert_eq!(delays, [50, 100]);
+        // Animations don't nest, and every frame must exist
+        assert!(server
+            .create_anim_cursor(0x0020_0006, &[(0x0020_0005, 10)])
+            .is_err());
+        assert!(server
+            .create_anim_cursor(0x0020_0006, &[(0x0020_0099, 10)])
+            .is_err());
+        server.free_cursor(0x0020_0002).unwrap();
+        assert!(!server.cursor_frames.contains_key(&0x0020_0002));
+    }
+
+    #[test]
+    fn test_core_cursors() {
+        use crate::font::CharInfo;
+
+        let mut server = Server::new(Box::new(NullBackend::new())).unwrap();
+        server.create_pixmap(0x0020_0010, 8, 8, 1).unwrap();
+        server.create_pixmap(0x0020_0011, 4, 4, 1).unwrap();
+        server.create_pixmap(0x0020_0012, 8, 8, 24).unwrap();
+        let (white, red) = ([0xffff; 3], [0xffff, 0, 0]);
+        server
+            .create_bitmap_cursor(0x0020_0001, 0x0020_0010, 0, [0; 3], white, 1, 1)
+            .unwrap();
+        // Without a mask every pixel shows; the blank source is background
+        assert_eq!(server.cursor_frames[&0x0020_0001][0].pixels[0], 0xffff_ffff);
+        server.recolor_cursor(0x0020_0001, [0; 3], red).unwrap();
+        assert_eq!(server.cursor_frames[&0x0020_0001][0].pixels[0], 0xffff_0000);
+        // Masks match the source's size, and both are bitmaps
+        assert!(server
+            .create_bitmap_cursor(0x0020_0002, 0x0020_0010, 0x0020_0011, [0; 3], white, 0, 0)
+            .is_err());
+        assert!(server
+            .create_bitmap_cursor(0x0020_0002, 0x0020_0012, 0, [0; 3], white, 0, 0)
+            .is_err());
+        assert!(server.recolor_cursor(0x0020_0099, [0; 3], white).is_err());
+
+        // A 3x2 glyph whose origin is one pixel left of its ink, masked by a
+        // 4x3 glyph whose ink starts at the origin
+        let glyph =
+            |left_side_bearing, right_side_bearing, ascent, descent, bitmap: Vec<u8>| Glyph {
+                info: CharInfo {
+                    left_side_bearing,
+                    right_side_bearing,
+                    character_width: 4,
+                    ascent,
+                    descent,
+                    attributes: 0,
+                },
+                bitmap: bitmap.into(),
+            };
+        let source = glyph(1, 4, 2, 0, vec![0xe0, 0xe0]);
+        let mask = glyph(0, 4, 2, 1, vec![0xf0; 3]);
+        let cursor = BitmapCursor::from_glyphs(&source, Some(&mask));
+        assert_eq!(
+            (cursor.width, cursor.height, cursor.x_hot, cursor.y_hot),
+            (4, 3, 0, 2)
+        );
+        assert_eq!(&cursor.source[..4], [false, true, true, true]);
+        assert!(cursor.mask.iter().all(|&m| m));
+        let unmasked = BitmapCursor::from_glyphs(&source, None);
+        assert_eq!((unmasked.width, unmasked.height), (3, 2));
+
+        // Every cursor font shape is its own glyph
+        for glyph in (0..154).step_by(2) {
+            assert_eq!(
+                StandardCursor::from_glyph(glyph).map(|c| c as u16),
+                Some(glyph)
+            );
+        }
+        assert_eq!(StandardCursor::from_glyph(69), None);
+    }
+}
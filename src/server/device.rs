@@ -0,0 +1,329 @@
+//! Input device state
+//!
+//! Pressed keys and buttons are tracked from the backend's input events;
+//! modifiers and the keyboard group come from the XKB state. Every event
+//! carries the state from just before it, the same state QueryPointer and
+//! QueryKeymap report. XTEST's fake input is tracked and routed the same
+//! way.
+
+use super::keyboard::MIN_KEYCODE;
+use super::Server;
+use crate::backend::{BackendEvent, BackendWindow};
+use crate::protocol::*;
+use std::error::Error;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Core state bit of a pointer button (Button1-Button5)
+fn button_mask(button: u8) -> u16 {
+    if (1..=5).contains(&button) {
+        0x80 << button
+    } else {
+        0
+    }
+}
+
+/// Pressed keycodes and pointer buttons
+#[derive(Debug, Default)]
+pub struct DeviceState {
+    /// One bit per keycode, as in QueryKeymap
+    keys: [u8; 32],
+    /// Button1-Button5 in core state bits
+    buttons: u16,
+}
+
+impl DeviceState {
+    fn key_down(&self, keycode: u8) -> bool {
+        self.keys[keycode as usize / 8] & (1 << (keycode % 8)) != 0
+    }
+
+    fn set_key(&mut self, keycode: u8, pressed: bool) {
+        let (byte, bit) = (keycode as usize / 8, 1 << (keycode % 8));
+        if pressed {
+            self.keys[byte] |= bit;
+        } else {
+            self.keys[byte] &= !bit;
+        }
+    }
+}
+
+impl Server {
+    /// Modifiers, buttons and group as the state field of core events
+    pub fn pointer_state(&mut self) -> u16 {
+        let state = self.xkb_core_state();
+        state | self.device_state.buttons
+    }
+
+    /// Pointer buttons held down, in core state bits
+    pub fn button_state(&self) -> u16 {
+        self.device_state.buttons
+    }
+
+    /// The QueryKeymap bit vector of pressed keys
+    pub fn pressed_keys(&self) -> [u8; 32] {
+        self.device_state.keys
+    }
+
+    /// Inject an XTEST event of a core event type (KeyPress to
+    /// MotionNotify); a MotionNotify `detail` of 1 moves relative to the
+    /// pointer
+    pub fn fake_input(
+        &mut self,
+        event_type: u8,
+        detail: u8,
+        root_x: i16,
+        root_y: i16,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let (x, y, _) = self.get_pointer_position();
+        let window = self.root_backend_window.unwrap_or(BackendWindow(0));
+        let time = current_time();
+        let state = 0;
+        let event = match event_type {
+            2 | 3 if detail < MIN_KEYCODE => {
+                return Err(X11Error::bad_value(0, detail as u32, 0).into());
+            }
+            // Releasing a key or button that is up does nothing
+            3 if !self.device_state.key_down(detail) => return Ok(()),
+            2 => BackendEvent::KeyPress {
+                window,
+                keycode: detail,
+                state,
+                time,
+                x,
+                y,
+            },
+            3 => BackendEvent::KeyRelease {
+                window,
+                keycode: detail,
+                state,
+                time,
+                x,
+                y,
+            },
+            4 | 5 if detail == 0 => {
+                return Err(X11Error::bad_value(0, detail as u32, 0).into());
+            }
+            5 if self.device_state.buttons & button_mask(detail) == 0 => return Ok(()),
+            4 => BackendEvent::ButtonPress {
+                window,
+                button: detail,
+                state,
+                time,
+                x,
+                y,
+            },
+            5 => BackendEvent::ButtonRelease {
+                window,
+                button: detail,
+                state,
+                time,
+                x,
+                y,
+            },
+            6 => {
+                let (x, y) = if detail != 0 {
+                    (x.saturating_add(root_x), y.saturating_add(root_y))
+                } else {
+                    (root_x, root_y)
+                };
+                let screen = self.get_screen_info();
+                BackendEvent::MotionNotify {
+                    window,
+                    state,
+                    time,
+                    x: x.clamp(0, screen.width as i16 - 1),
+                    y: y.clamp(0, screen.height as i16 - 1),
+                }
+            }
+            _ => return Err(X11Error::bad_value(0, event_type as u32, 0).into()),
+        };
+        // Outside rooted mode the host pointer is the server's pointer, so
+        // fake motion moves it too
+        if let BackendEvent::MotionNotify { x, y, .. } = event {
+            if self.root_backend_window.is_none() {
+                self.backend.warp_pointer(x, y)?;
+            }
+        }
+        let mut event = event;
+        self.track_input(&mut event);
+        self.route_root_event(event);
+        Ok(())
+    }
+
+    /// Whether a window's cursor, inherited from its ancestors, is `cursor`;
+    /// 1 (CurrentCursor) stands for the cursor shown under the pointer
+    pub fn compare_cursor(
+        &self,
+        window: Window,
+        cursor: u32,
+    ) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        if !self.window_info.contains_key(&window) {
+            return Err(X11Error::bad_window(0, window, 0).into());
+        }
+        let cursor = if cursor == 1 {
+            self.window_cursor(self.last_pointer_window)
+        } else {
+            cursor
+        };
+        Ok(self.window_cursor(window) == cursor)
+    }
+
+    /// The cursor a window shows: its own, or the nearest ancestor's
+    fn window_cursor(&self, mut window: Window) -> u32 {
+        while let Some(info) = self.window_info.get(&window) {
+            if info.cursor != 0 {
+                return info.cursor;
+            }
+            window = info.parent;
+        }
+        0
+    }
+
+    /// Give an input event the state before it, then apply it
+    pub(super) fn track_input(&mut self, event: &mut BackendEvent) {
+        let current = self.pointer_state();
+        match event {
+            BackendEvent::KeyPress {
+                keycode,
+                state,
+                time,
+                ..
+            } => {
+                *state = current;
+                self.device_state.set_key(*keycode, true);
+                self.xkb_key_event(*keycode, true, *time);
+            }
+            BackendEvent::KeyRelease {
+                keycode,
+                state,
+                time,
+                ..
+            } => {
+                *state = current;
+                self.device_state.set_key(*keycode, false);
+                self.xkb_key_event(*keycode, false, *time);
+            }
+            BackendEvent::ButtonPress { button, state, .. } => {
+                *state = current;
+                self.device_state.buttons |= button_mask(*button);
+            }
+            BackendEvent::ButtonRelease { button, state, .. } => {
+                *state = current;
+                self.device_state.buttons &= !button_mask(*button);
+            }
+            BackendEvent::MotionNotify { state, .. } => *state = current,
+            _ => {}
+        }
+    }
+}
+
+/// Milliseconds since the epoch, wrapped, as event timestamps
+pub(super) fn current_time() -> u32 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::null::NullBackend;
+    use crate::backend::BackendWindow;
+
+    #[test]
+    fn test_track_input() {
+        let mut server = Server::new(Box::new(NullBackend::new())).unwrap();
+        server
+            .change_keyboard_mapping(50, 1, &[0xffe1]) // Shift_L
+            .unwrap();
+        server
+            .set_modifier_mapping(1, &[50, 0, 0, 0, 0, 0, 0, 0])
+            .unwrap();
+
+        let window = BackendWindow(1);
+        let mut shift = BackendEvent::KeyPress {
+            window,
+            keycode: 50,
+            state: 0xffff,
+            time: 0,
+            x: 0,
+            y: 0,
+        };
+        server.track_input(&mut shift);
+        assert!(matches!(shift, BackendEvent::KeyPress { state: 0, .. }));
+        assert_eq!(server.pressed_keys()[6], 0x04);
+        // Shift cannot be remapped while it is held
+        let busy = server.set_modifier_mapping(1, &[62, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(busy.unwrap(), 1);
+
+        let mut press = BackendEvent::ButtonPress {
+            window,
+            button: 1,
+            state: 0,
+            time: 0,
+            x: 0,
+            y: 0,
+        };
+        server.track_input(&mut press);
+        assert!(matches!(
+            press,
+            BackendEvent::ButtonPress { state: 0x0001, .. }
+        ));
+
+        // A release's state still has the button
+        let mut release = BackendEvent::ButtonRelease {
+            window,
+            button: 1,
+            state: 0,
+            time: 0,
+            x: 0,
+            y: 0,
+        };
+        server.track_input(&mut release);
+        assert!(matches!(
+            release,
+            BackendEvent::ButtonRelease { state: 0x0101, .. }
+        ));
+        assert_eq!(server.pointer_state(), 0x0001);
+    }
+
+    #[test]
+    fn test_fake_input() {
+        let mut server = Server::new(Box::new(NullBackend::new())).unwrap();
+        let client = server.register_client();
+        let window = Window::new(0x0020_0001);
+        server
+            .create_window(
+                window,
+                server.root_window(),
+                20,
+                20,
+                100,
+                100,
+                0,
+                WindowClass::InputOutput,
+                VisualID::new(0x21),
+                &crate::server::WindowPaintValues::default(),
+                0,
+                false,
+            )
+            .unwrap();
+        server.map_window(window, client).unwrap();
+        server.select_window_events(window, client, event_mask::KEY_PRESS);
+        server.take_client_events(client);
+
+        // Focus follows the pointer moved into the window
+        server.fake_input(6, 0, 30, 40).unwrap();
+        server.fake_input(2, 38, 0, 0).unwrap();
+        server.fake_input(3, 38, 0, 0).unwrap();
+        let events = server.take_client_events(client);
+        assert_eq!(events.len(), 1);
+        assert_eq!((events[0][0], events[0][1]), (2, 38));
+        assert_eq!(events[0][12..16], window.id().get().to_le_bytes());
+        assert_eq!(events[0][24..28], [10, 0, 20, 0]); // event x, y
+
+        // Releasing a button that is up is ignored; keycodes start at 8
+        server.fake_input(5, 1, 0, 0).unwrap();
+        assert!(server.fake_input(2, 7, 0, 0).is_err());
+        assert_eq!(server.pointer_state(), 0);
+    }
+}
@@ -2,19 +2,24 @@
 //!
 //! This module handles requests for X11 extensions like COMPOSITE, XFIXES, DAMAGE, etc.
 
-use super::Server;
-use crate::backend::RenderTrapezoid;
+use super::listener::ClientStream;
+use super::shape::ShapeExtents;
+use super::{Region, Server};
+use crate::backend::composite::GradientShape;
+use crate::backend::{trapezoids, RenderTrapezoid, ShapeKind};
+use crate::protocol::{GContext, Rectangle, Window, X11Error};
 use std::io::Write;
 
 use std::sync::{Arc, Mutex};
 
 /// Handle extension request based on major opcode
-pub fn handle_extension_request<S: Write>(
+pub fn handle_extension_request<S: ClientStream>(
     stream: &mut S,
     header: &[u8],
     data: &[u8],
     major_opcode: u8,
     server: &Arc<Mutex<Server>>,
+    client_id: u32,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let minor_opcode = header[1];
     let sequence = u16::from_le_bytes([header[2], header[3]]);
@@ -27,13 +32,17 @@ pub fn handle_extension_request<S: Write>(
     );
 
     match major_opcode {
-        129 => handle_shape_request(stream, minor_opcode, sequence, data),
-        130 => handle_shm_request(stream, minor_opcode, sequence, data),
+        129 => handle_shape_request(stream, minor_opcode, sequence, data, server, client_id),
+        #[cfg(unix)]
+        130 => handle_shm_request(stream, minor_opcode, sequence, data, server, client_id),
+        132 => handle_xtest_request(stream, minor_opcode, sequence, data, server, client_id),
         133 => handle_big_requests(stream, minor_opcode, sequence, data),
         134 => handle_sync_request(stream, minor_opcode, sequence, data),
-        135 => handle_xkb_request(stream, minor_opcode, sequence, data),
-        138 => handle_xfixes_request(stream, minor_opcode, sequence, data),
-        139 => handle_render_request(stream, minor_opcode, sequence, data, server),
+        135 => {
+            super::xkb::handle_xkb_request(stream, minor_opcode, sequence, data, server, client_id)
+        }
+        138 => handle_xfixes_request(stream, minor_opcode, sequence, data, server),
+        139 => handle_render_request(stream, minor_opcode, sequence, data, server, client_id),
         140 => handle_randr_request(stream, minor_opcode, sequence, data),
         142 => handle_composite_request(stream, minor_opcode, sequence, data),
         143 => handle_damage_request(stream, minor_opcode, sequence, data),
@@ -49,8 +58,30 @@ fn handle_shape_request<S: Write>(
     stream: &mut S,
     minor_opcode: u8,
     sequence: u16,
-    _data: &[u8],
+    data: &[u8],
+    server: &Arc<Mutex<Server>>,
+    client_id: u32,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let read_u32 = |offset: usize| {
+        u32::from_le_bytes([
+            data[offset],
+            data[offset + 1],
+            data[offset + 2],
+            data[offset + 3],
+        ])
+    };
+    let read_i16 = |offset: usize| i16::from_le_bytes([data[offset], data[offset + 1]]);
+    let kind = |kind: u8| ShapeKind::from_u8(kind).ok_or(X11Error::bad_value(0, kind as u32, 0));
+    let min_len = match minor_opcode {
+        1..=3 => 16,
+        4 => 12,
+        5 | 7 => 4,
+        6 | 8 => 8,
+        _ => 0,
+    };
+    if data.len() < min_len {
+        return Err(X11Error::bad_length(0, 0).into());
+    }
     match minor_opcode {
         0 => {
             // ShapeQueryVersion
@@ -58,6 +89,137 @@ fn handle_shape_request<S: Write>(
             let reply = encode_shape_query_version_reply(sequence);
             stream.write_all(&reply)?;
         }
+        1 => {
+            // ShapeRectangles: op(1) + kind(1) + ordering(1) + pad(1) +
+            // window(4) + x_offset(2) + y_offset(2) + rectangles
+            let (op, dest_kind, ordering) = (data[0], kind(data[1])?, data[2]);
+            if ordering > 3 {
+                return Err(X11Error::bad_value(0, ordering as u32, 0).into());
+            }
+            let window = Window::new(read_u32(4));
+            let rects = read_rectangles(&data[12..]);
+            log::debug!(
+                "SHAPE: Rectangles window=0x{:x} op={} kind={:?} {} rects",
+                window.id().get(),
+                op,
+                dest_kind,
+                rects.len()
+            );
+            let mut region = Region::from_rectangles(&rects);
+            region.translate(read_i16(8) as i32, read_i16(10) as i32)?;
+            let mut server = server.lock().unwrap();
+            server.shape_window(window, dest_kind, op, Some(region))?;
+        }
+        2 => {
+            // ShapeMask: op(1) + kind(1) + pad(2) + window(4) + x_offset(2) +
+            // y_offset(2) + source_bitmap(4)
+            let (op, dest_kind) = (data[0], kind(data[1])?);
+            let window = Window::new(read_u32(4));
+            let pixmap = read_u32(12);
+            log::debug!(
+                "SHAPE: Mask window=0x{:x} op={} kind={:?} bitmap=0x{:x}",
+                window.id().get(),
+                op,
+                dest_kind,
+                pixmap
+            );
+            let mut server = server.lock().unwrap();
+            let region = if pixmap == 0 {
+                None
+            } else {
+                let (width, height, bits) = server.pixmap_bits(pixmap)?;
+                let mut region = Region::from_bitmap(width, height, &bits);
+                region.translate(read_i16(8) as i32, read_i16(10) as i32)?;
+                Some(region)
+            };
+            server.shape_window(window, dest_kind, op, region)?;
+        }
+        3 => {
+            // ShapeCombine: op(1) + dest_kind(1) + source_kind(1) + pad(1) +
+            // dest_window(4) + x_offset(2) + y_offset(2) + source_window(4)
+            let (op, dest_kind, source_kind) = (data[0], kind(data[1])?, kind(data[2])?);
+            let window = Window::new(read_u32(4));
+            let source = Window::new(read_u32(12));
+            log::debug!(
+                "SHAPE: Combine window=0x{:x} op={} kind={:?} from 0x{:x} {:?}",
+                window.id().get(),
+                op,
+                dest_kind,
+                source.id().get(),
+                source_kind
+            );
+            let mut server = server.lock().unwrap();
+            let mut region = server.window_shape(source, source_kind)?;
+            region.translate(read_i16(8) as i32, read_i16(10) as i32)?;
+            server.shape_window(window, dest_kind, op, Some(region))?;
+        }
+        4 => {
+            // ShapeOffset: kind(1) + pad(3) + window(4) + x_offset(2) +
+            // y_offset(2)
+            let dest_kind = kind(data[0])?;
+            let window = Window::new(read_u32(4));
+            let (x, y) = (read_i16(8), read_i16(10));
+            log::debug!(
+                "SHAPE: Offset window=0x{:x} kind={:?} by ({},{})",
+                window.id().get(),
+                dest_kind,
+                x,
+                y
+            );
+            let mut server = server.lock().unwrap();
+            server.offset_shape(window, dest_kind, x, y)?;
+        }
+        5 => {
+            // ShapeQueryExtents: window(4)
+            let window = Window::new(read_u32(0));
+            log::debug!("SHAPE: QueryExtents window=0x{:x}", window.id().get());
+            let extents = server.lock().unwrap().shape_extents(window)?;
+            let reply = encode_shape_query_extents_reply(sequence, &extents);
+            stream.write_all(&reply)?;
+        }
+        6 => {
+            // ShapeSelectInput: window(4) + enable(1) + pad(3)
+            let window = Window::new(read_u32(0));
+            let enable = data[4] != 0;
+            log::debug!(
+                "SHAPE: SelectInput window=0x{:x} enable={}",
+                window.id().get(),
+                enable
+            );
+            let mut server = server.lock().unwrap();
+            server.select_shape_input(window, client_id, enable)?;
+        }
+        7 => {
+            // ShapeInputSelected: window(4)
+            let window = Window::new(read_u32(0));
+            log::debug!("SHAPE: InputSelected window=0x{:x}", window.id().get());
+            let enabled = server
+                .lock()
+                .unwrap()
+                .shape_input_selected(window, client_id)?;
+            let mut reply = vec![0u8; 32];
+            reply[0] = 1; // Reply
+            reply[1] = enabled as u8;
+            reply[2..4].copy_from_slice(&write_u16_le(sequence));
+            stream.write_all(&reply)?;
+        }
+        8 => {
+            // ShapeGetRectangles: window(4) + kind(1) + pad(3)
+            let window = Window::new(read_u32(0));
+            let source_kind = kind(data[4])?;
+            log::debug!(
+                "SHAPE: GetRectangles window=0x{:x} kind={:?}",
+                window.id().get(),
+                source_kind
+            );
+            let rects = server
+                .lock()
+                .unwrap()
+                .window_shape(window, source_kind)?
+                .rectangles();
+            let reply = encode_shape_get_rectangles_reply(sequence, &rects);
+            stream.write_all(&reply)?;
+        }
         _ => {
             log::debug!("SHAPE: Unhandled minor opcode {}", minor_opcode);
         }
@@ -66,12 +228,36 @@ fn handle_shape_request<S: Write>(
 }
 
 /// Handle MIT-SHM extension requests
-fn handle_shm_request<S: Write>(
+#[cfg(unix)]
+fn handle_shm_request<S: ClientStream>(
     stream: &mut S,
     minor_opcode: u8,
     sequence: u16,
-    _data: &[u8],
+    data: &[u8],
+    server: &Arc<Mutex<Server>>,
+    client_id: u32,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let read_u32 = |offset: usize| {
+        u32::from_le_bytes([
+            data[offset],
+            data[offset + 1],
+            data[offset + 2],
+            data[offset + 3],
+        ])
+    };
+    let read_u16 = |offset: usize| u16::from_le_bytes([data[offset], data[offset + 1]]);
+    let min_len = match minor_opcode {
+        1 | 7 => 12,
+        2 => 4,
+        3 => 36,
+        4 => 28,
+        5 => 24,
+        6 => 8,
+        _ => 0,
+    };
+    if data.len() < min_len {
+        return Err(X11Error::bad_length(0, 0).into());
+    }
     match minor_opcode {
         0 => {
             // ShmQueryVersion
@@ -79,6 +265,135 @@ fn handle_shm_request<S: Write>(
             let reply = encode_shm_query_version_reply(sequence);
             stream.write_all(&reply)?;
         }
+        1 => {
+            // ShmAttach: shmseg(4) + shmid(4) + read_only(1) + pad(3)
+            let (shmseg, shmid, read_only) = (read_u32(0), read_u32(4), data[8] != 0);
+            log::debug!(
+                "MIT-SHM: Attach shmseg=0x{:x} shmid={} read_only={}",
+                shmseg,
+                shmid,
+                read_only
+            );
+            let mut server = server.lock().unwrap();
+            server.shm_attach(client_id, shmseg, shmid, read_only)?;
+        }
+        2 => {
+            // ShmDetach: shmseg(4)
+            log::debug!("MIT-SHM: Detach shmseg=0x{:x}", read_u32(0));
+            let mut server = server.lock().unwrap();
+            server.shm_detach(client_id, read_u32(0))?;
+        }
+        3 => {
+            // ShmPutImage: drawable(4) + gc(4) + total_width(2) +
+            // total_height(2) + src_x(2) + src_y(2) + src_width(2) +
+            // src_height(2) + dst_x(2) + dst_y(2) + depth(1) + format(1) +
+            // send_event(1) + pad(1) + shmseg(4) + offset(4)
+            let drawable = read_u32(0);
+            let total = (read_u16(8), read_u16(10));
+            let src = (read_u16(12), read_u16(14), read_u16(16), read_u16(18));
+            let dst = (read_u16(20) as i16, read_u16(22) as i16);
+            let (depth, format, send_event) = (data[24], data[25], data[26] != 0);
+            let (shmseg, offset) = (read_u32(28), read_u32(32));
+            log::debug!(
+                "MIT-SHM: PutImage drawable=0x{:x} {}x{} of {}x{} at ({},{}) depth={} shmseg=0x{:x}",
+                drawable,
+                src.2,
+                src.3,
+                total.0,
+                total.1,
+                dst.0,
+                dst.1,
+                depth,
+                shmseg
+            );
+            let first_event = {
+                let mut server = server.lock().unwrap();
+                server.shm_put_image(
+                    drawable,
+                    read_u32(4),
+                    total,
+                    src,
+                    dst,
+                    depth,
+                    format,
+                    shmseg,
+                    offset,
+                )?;
+                server.query_extension("MIT-SHM").map(|e| e.first_event)
+            };
+            if let (true, Some(first_event)) = (send_event, first_event) {
+                let event =
+                    encode_shm_completion_event(first_event, sequence, drawable, shmseg, offset);
+                stream.write_all(&event)?;
+            }
+        }
+        4 => {
+            // ShmGetImage: drawable(4) + x(2) + y(2) + width(2) + height(2) +
+            // plane_mask(4) + format(1) + pad(3) + shmseg(4) + offset(4)
+            let drawable = read_u32(0);
+            let (x, y) = (read_u16(4) as i16, read_u16(6) as i16);
+            let (width, height) = (read_u16(8), read_u16(10));
+            let (shmseg, offset) = (read_u32(20), read_u32(24));
+            log::debug!(
+                "MIT-SHM: GetImage drawable=0x{:x} ({},{}) {}x{} shmseg=0x{:x}",
+                drawable,
+                x,
+                y,
+                width,
+                height,
+                shmseg
+            );
+            let (depth, visual, size) = {
+                let mut server = server.lock().unwrap();
+                server.shm_get_image(
+                    drawable,
+                    x,
+                    y,
+                    width,
+                    height,
+                    read_u32(12),
+                    data[16],
+                    shmseg,
+                    offset,
+                )?
+            };
+            let reply = encode_shm_get_image_reply(sequence, depth, visual, size);
+            stream.write_all(&reply)?;
+        }
+        5 => {
+            // ShmCreatePixmap: pixmaps can't live in client memory, which
+            // QueryVersion says by reporting no shared pixmaps
+            log::debug!("MIT-SHM: CreatePixmap pid=0x{:x} refused", read_u32(0));
+            return Err(X11Error::implementation_error(0, 0).into());
+        }
+        6 => {
+            // ShmAttachFd: shmseg(4) + read_only(1) + pad(3), with the
+            // descriptor passed alongside
+            let (shmseg, read_only) = (read_u32(0), data[4] != 0);
+            log::debug!(
+                "MIT-SHM: AttachFd shmseg=0x{:x} read_only={}",
+                shmseg,
+                read_only
+            );
+            let fd = stream.take_fd().ok_or_else(|| X11Error::bad_match(0, 0))?;
+            let mut server = server.lock().unwrap();
+            server.shm_attach_fd(client_id, shmseg, fd, read_only)?;
+        }
+        7 => {
+            // ShmCreateSegment: shmseg(4) + size(4) + read_only(1) + pad(3)
+            let (shmseg, size, read_only) = (read_u32(0), read_u32(4), data[8] != 0);
+            log::debug!(
+                "MIT-SHM: CreateSegment shmseg=0x{:x} size={} read_only={}",
+                shmseg,
+                size,
+                read_only
+            );
+            let fd = {
+                let mut server = server.lock().unwrap();
+                server.shm_create_segment(client_id, shmseg, size, read_only)?
+            };
+            stream.write_with_fd(&encode_shm_create_segment_reply(sequence), &fd)?;
+        }
         _ => {
             log::debug!("MIT-SHM: Unhandled minor opcode {}", minor_opcode);
         }
@@ -311,7 +626,29 @@ fn handle_xfixes_request<S: Write>(
     minor_opcode: u8,
     sequence: u16,
     data: &[u8],
+    server: &Arc<Mutex<Server>>,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let read_u32 = |offset: usize| {
+        u32::from_le_bytes([
+            data[offset],
+            data[offset + 1],
+            data[offset + 2],
+            data[offset + 3],
+        ])
+    };
+    let read_i16 = |offset: usize| i16::from_le_bytes([data[offset], data[offset + 1]]);
+    // Fixed part of each region request
+    let min_len = match minor_opcode {
+        5 | 10 | 11 | 19 => 4,
+        6..=9 | 12 | 17 | 18 => 8,
+        13..=15 | 20 | 22 => 12,
+        21 | 28 => 16,
+        16 => 20,
+        _ => 0,
+    };
+    if data.len() < min_len {
+        return Err(X11Error::bad_length(0, 0).into());
+    }
     match minor_opcode {
         0 => {
             // XFixesQueryVersion
@@ -372,244 +709,232 @@ fn handle_xfixes_request<S: Write>(
             stream.write_all(&reply)?;
         }
         5 => {
-            // XFixesCreateRegion
-            if data.len() >= 4 {
-                let region = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
-                // Rectangles follow at data[4..]
-                let num_rects = (data.len() - 4) / 8;
-                log::debug!(
-                    "XFIXES: CreateRegion region=0x{:x} num_rects={}",
-                    region,
-                    num_rects
-                );
-            }
-            // No reply
+            // XFixesCreateRegion: region(4) + rectangles
+            let region = read_u32(0);
+            let rects = read_rectangles(&data[4..]);
+            log::debug!(
+                "XFIXES: CreateRegion region=0x{:x} num_rects={}",
+                region,
+                rects.len()
+            );
+            let mut server = server.lock().unwrap();
+            server.create_region(region, Region::from_rectangles(&rects))?;
         }
         6 => {
-            // XFixesCreateRegionFromBitmap
-            if data.len() >= 8 {
-                let region = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
-                let bitmap = u32::from_le_bytes([data[4], data[5], data[6], data[7]]);
-                log::debug!(
-                    "XFIXES: CreateRegionFromBitmap region=0x{:x} bitmap=0x{:x}",
-                    region,
-                    bitmap
-                );
-            }
-            // No reply
+            // XFixesCreateRegionFromBitmap: region(4) + bitmap(4)
+            let (region, bitmap) = (read_u32(0), read_u32(4));
+            log::debug!(
+                "XFIXES: CreateRegionFromBitmap region=0x{:x} bitmap=0x{:x}",
+                region,
+                bitmap
+            );
+            let mut server = server.lock().unwrap();
+            let source = server.bitmap_region(bitmap)?;
+            server.create_region(region, source)?;
         }
         7 => {
-            // XFixesCreateRegionFromWindow
-            if data.len() >= 9 {
-                let region = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
-                let window = u32::from_le_bytes([data[4], data[5], data[6], data[7]]);
-                let kind = data[8];
-                log::debug!(
-                    "XFIXES: CreateRegionFromWindow region=0x{:x} window=0x{:x} kind={}",
-                    region,
-                    window,
-                    kind
-                );
-            }
-            // No reply
+            // XFixesCreateRegionFromWindow: region(4) + window(4) + kind(1)
+            let (region, window, kind) = (read_u32(0), read_u32(4), data[8]);
+            log::debug!(
+                "XFIXES: CreateRegionFromWindow region=0x{:x} window=0x{:x} kind={}",
+                region,
+                window,
+                kind
+            );
+            let kind = match kind {
+                0 => ShapeKind::Bounding,
+                1 => ShapeKind::Clip,
+                _ => return Err(X11Error::bad_value(0, kind as u32, 0).into()),
+            };
+            let mut server = server.lock().unwrap();
+            let source = server.window_shape(Window::new(window), kind)?;
+            server.create_region(region, source)?;
         }
         8 => {
-            // XFixesCreateRegionFromGC
-            if data.len() >= 8 {
-                let region = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
-                let gc = u32::from_le_bytes([data[4], data[5], data[6], data[7]]);
-                log::debug!(
-                    "XFIXES: CreateRegionFromGC region=0x{:x} gc=0x{:x}",
-                    region,
-                    gc
-                );
-            }
-            // No reply
+            // XFixesCreateRegionFromGC: region(4) + gc(4)
+            let (region, gc) = (read_u32(0), read_u32(4));
+            log::debug!(
+                "XFIXES: CreateRegionFromGC region=0x{:x} gc=0x{:x}",
+                region,
+                gc
+            );
+            let mut server = server.lock().unwrap();
+            let source = server.gc_clip_region(GContext::new(gc))?;
+            server.create_region(region, source)?;
         }
         9 => {
-            // XFixesCreateRegionFromPicture
-            if data.len() >= 8 {
-                let region = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
-                let picture = u32::from_le_bytes([data[4], data[5], data[6], data[7]]);
-                log::debug!(
-                    "XFIXES: CreateRegionFromPicture region=0x{:x} picture=0x{:x}",
-                    region,
-                    picture
-                );
-            }
-            // No reply
+            // XFixesCreateRegionFromPicture: region(4) + picture(4)
+            let (region, picture) = (read_u32(0), read_u32(4));
+            log::debug!(
+                "XFIXES: CreateRegionFromPicture region=0x{:x} picture=0x{:x}",
+                region,
+                picture
+            );
+            let mut server = server.lock().unwrap();
+            let source = server.picture_clip_region(picture)?;
+            server.create_region(region, source)?;
         }
         10 => {
-            // XFixesDestroyRegion
-            if data.len() >= 4 {
-                let region = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
-                log::debug!("XFIXES: DestroyRegion region=0x{:x}", region);
-            }
-            // No reply
+            // XFixesDestroyRegion: region(4)
+            let region = read_u32(0);
+            log::debug!("XFIXES: DestroyRegion region=0x{:x}", region);
+            server.lock().unwrap().destroy_region(region)?;
         }
         11 => {
-            // XFixesSetRegion
-            if data.len() >= 4 {
-                let region = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
-                let num_rects = (data.len() - 4) / 8;
-                log::debug!(
-                    "XFIXES: SetRegion region=0x{:x} num_rects={}",
-                    region,
-                    num_rects
-                );
-            }
-            // No reply
+            // XFixesSetRegion: region(4) + rectangles
+            let region = read_u32(0);
+            let rects = read_rectangles(&data[4..]);
+            log::debug!(
+                "XFIXES: SetRegion region=0x{:x} num_rects={}",
+                region,
+                rects.len()
+            );
+            let mut server = server.lock().unwrap();
+            server.set_region(region, Region::from_rectangles(&rects))?;
         }
         12 => {
-            // XFixesCopyRegion
-            if data.len() >= 8 {
-                let src = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
-                let dst = u32::from_le_bytes([data[4], data[5], data[6], data[7]]);
-                log::debug!("XFIXES: CopyRegion src=0x{:x} dst=0x{:x}", src, dst);
-            }
-            // No reply
-        }
-        13 => {
-            // XFixesUnionRegion
-            if data.len() >= 12 {
-                let src1 = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
-                let src2 = u32::from_le_bytes([data[4], data[5], data[6], data[7]]);
-                let dst = u32::from_le_bytes([data[8], data[9], data[10], data[11]]);
-                log::debug!(
-                    "XFIXES: UnionRegion src1=0x{:x} src2=0x{:x} dst=0x{:x}",
-                    src1,
-                    src2,
-                    dst
-                );
-            }
-            // No reply
-        }
-        14 => {
-            // XFixesIntersectRegion
-            if data.len() >= 12 {
-                let src1 = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
-                let src2 = u32::from_le_bytes([data[4], data[5], data[6], data[7]]);
-                let dst = u32::from_le_bytes([data[8], data[9], data[10], data[11]]);
-                log::debug!(
-                    "XFIXES: IntersectRegion src1=0x{:x} src2=0x{:x} dst=0x{:x}",
-                    src1,
-                    src2,
-                    dst
-                );
-            }
-            // No reply
-        }
-        15 => {
-            // XFixesSubtractRegion
-            if data.len() >= 12 {
-                let src1 = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
-                let src2 = u32::from_le_bytes([data[4], data[5], data[6], data[7]]);
-                let dst = u32::from_le_bytes([data[8], data[9], data[10], data[11]]);
-                log::debug!(
-                    "XFIXES: SubtractRegion src1=0x{:x} src2=0x{:x} dst=0x{:x}",
-                    src1,
-                    src2,
-                    dst
-                );
-            }
-            // No reply
+            // XFixesCopyRegion: source(4) + destination(4)
+            let (src, dst) = (read_u32(0), read_u32(4));
+            log::debug!("XFIXES: CopyRegion src=0x{:x} dst=0x{:x}", src, dst);
+            let mut server = server.lock().unwrap();
+            let source = server.region(src)?.clone();
+            server.set_region(dst, source)?;
+        }
+        13..=15 => {
+            // XFixesUnionRegion, XFixesIntersectRegion, XFixesSubtractRegion:
+            // source1(4) + source2(4) + destination(4)
+            let (src1, src2, dst) = (read_u32(0), read_u32(4), read_u32(8));
+            log::debug!(
+                "XFIXES: {} src1=0x{:x} src2=0x{:x} dst=0x{:x}",
+                ["UnionRegion", "IntersectRegion", "SubtractRegion"][minor_opcode as usize - 13],
+                src1,
+                src2,
+                dst
+            );
+            let mut server = server.lock().unwrap();
+            let (a, b) = (server.region(src1)?, server.region(src2)?);
+            let result = match minor_opcode {
+                13 => a.union(b),
+                14 => a.intersect(b),
+                _ => a.subtract(b),
+            };
+            server.set_region(dst, result)?;
         }
         16 => {
-            // XFixesInvertRegion
-            if data.len() >= 20 {
-                let src = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
-                // bounds: x, y, width, height at 4..12
-                let dst = u32::from_le_bytes([data[16], data[17], data[18], data[19]]);
-                log::debug!("XFIXES: InvertRegion src=0x{:x} dst=0x{:x}", src, dst);
-            }
-            // No reply
+            // XFixesInvertRegion: source(4) + bounds(8) + destination(4)
+            let (src, dst) = (read_u32(0), read_u32(12));
+            let bounds = read_rectangles(&data[4..12])[0];
+            log::debug!("XFIXES: InvertRegion src=0x{:x} dst=0x{:x}", src, dst);
+            let mut server = server.lock().unwrap();
+            let result = server.region(src)?.invert(bounds);
+            server.set_region(dst, result)?;
         }
         17 => {
-            // XFixesTranslateRegion
-            if data.len() >= 8 {
-                let region = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
-                let dx = i16::from_le_bytes([data[4], data[5]]);
-                let dy = i16::from_le_bytes([data[6], data[7]]);
-                log::debug!(
-                    "XFIXES: TranslateRegion region=0x{:x} dx={} dy={}",
-                    region,
-                    dx,
-                    dy
-                );
-            }
-            // No reply
+            // XFixesTranslateRegion: region(4) + dx(2) + dy(2)
+            let region = read_u32(0);
+            let (dx, dy) = (read_i16(4), read_i16(6));
+            log::debug!(
+                "XFIXES: TranslateRegion region=0x{:x} dx={} dy={}",
+                region,
+                dx,
+                dy
+            );
+            let mut server = server.lock().unwrap();
+            let mut result = server.region(region)?.clone();
+            result.translate(dx as i32, dy as i32)?;
+            server.set_region(region, result)?;
         }
         18 => {
-            // XFixesRegionExtents
-            if data.len() >= 8 {
-                let src = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
-                let dst = u32::from_le_bytes([data[4], data[5], data[6], data[7]]);
-                log::debug!("XFIXES: RegionExtents src=0x{:x} dst=0x{:x}", src, dst);
-            }
-            // No reply
+            // XFixesRegionExtents: source(4) + destination(4)
+            let (src, dst) = (read_u32(0), read_u32(4));
+            log::debug!("XFIXES: RegionExtents src=0x{:x} dst=0x{:x}", src, dst);
+            let mut server = server.lock().unwrap();
+            let source = server.region(src)?;
+            let result = if source.is_empty() {
+                Region::default()
+            } else {
+                Region::rectangle(source.extents())
+            };
+            server.set_region(dst, result)?;
         }
         19 => {
-            // XFixesFetchRegion
-            if data.len() >= 4 {
-                let region = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
-                log::debug!("XFIXES: FetchRegion region=0x{:x}", region);
-                // Return empty region (bounding box 0,0,0,0, no rectangles)
-                let reply = encode_xfixes_fetch_region_reply(sequence);
-                stream.write_all(&reply)?;
-            }
+            // XFixesFetchRegion: region(4)
+            let region = read_u32(0);
+            log::debug!("XFIXES: FetchRegion region=0x{:x}", region);
+            let (extents, rects) = {
+                let server = server.lock().unwrap();
+                let region = server.region(region)?;
+                (region.extents(), region.rectangles())
+            };
+            let reply = encode_xfixes_fetch_region_reply(sequence, extents, &rects);
+            stream.write_all(&reply)?;
         }
         20 => {
-            // XFixesSetGCClipRegion
-            if data.len() >= 12 {
-                let gc = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
-                let x_origin = i16::from_le_bytes([data[4], data[5]]);
-                let y_origin = i16::from_le_bytes([data[6], data[7]]);
-                let region = u32::from_le_bytes([data[8], data[9], data[10], data[11]]);
-                log::debug!(
-                    "XFIXES: SetGCClipRegion gc=0x{:x} origin=({},{}) region=0x{:x}",
-                    gc,
-                    x_origin,
-                    y_origin,
-                    region
-                );
-            }
-            // No reply
+            // XFixesSetGCClipRegion: gc(4) + x_origin(2) + y_origin(2) +
+            // region(4)
+            let gc = read_u32(0);
+            let (x_origin, y_origin) = (read_i16(4), read_i16(6));
+            let region = read_u32(8);
+            log::debug!(
+                "XFIXES: SetGCClipRegion gc=0x{:x} origin=({},{}) region=0x{:x}",
+                gc,
+                x_origin,
+                y_origin,
+                region
+            );
+            let mut server = server.lock().unwrap();
+            server.set_gc_clip_region(
+                GContext::new(gc),
+                x_origin,
+                y_origin,
+                (region != 0).then_some(region),
+            )?;
         }
         21 => {
-            // XFixesSetWindowShapeRegion
-            if data.len() >= 16 {
-                let dst = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
-                let kind = data[4];
-                let x_offset = i16::from_le_bytes([data[8], data[9]]);
-                let y_offset = i16::from_le_bytes([data[10], data[11]]);
-                let region = u32::from_le_bytes([data[12], data[13], data[14], data[15]]);
-                log::debug!(
-                    "XFIXES: SetWindowShapeRegion window=0x{:x} kind={} offset=({},{}) region=0x{:x}",
-                    dst,
-                    kind,
-                    x_offset,
-                    y_offset,
-                    region
-                );
-            }
-            // No reply
+            // XFixesSetWindowShapeRegion: window(4) + kind(1) + pad(3) +
+            // x_offset(2) + y_offset(2) + region(4)
+            let dst = read_u32(0);
+            let kind = data[4];
+            let (x_offset, y_offset) = (read_i16(8), read_i16(10));
+            let region = read_u32(12);
+            log::debug!(
+                "XFIXES: SetWindowShapeRegion window=0x{:x} kind={} offset=({},{}) region=0x{:x}",
+                dst,
+                kind,
+                x_offset,
+                y_offset,
+                region
+            );
+            let kind = ShapeKind::from_u8(kind).ok_or(X11Error::bad_value(0, kind as u32, 0))?;
+            let mut server = server.lock().unwrap();
+            server.set_window_shape_region(
+                Window::new(dst),
+                kind,
+                x_offset,
+                y_offset,
+                (region != 0).then_some(region),
+            )?;
         }
         22 => {
-            // XFixesSetPictureClipRegion
-            if data.len() >= 12 {
-                let picture = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
-                let x_origin = i16::from_le_bytes([data[4], data[5]]);
-                let y_origin = i16::from_le_bytes([data[6], data[7]]);
-                let region = u32::from_le_bytes([data[8], data[9], data[10], data[11]]);
-                log::debug!(
-                    "XFIXES: SetPictureClipRegion picture=0x{:x} origin=({},{}) region=0x{:x}",
-                    picture,
-                    x_origin,
-                    y_origin,
-                    region
-                );
-            }
-            // No reply
+            // XFixesSetPictureClipRegion: picture(4) + x_origin(2) +
+            // y_origin(2) + region(4)
+            let picture = read_u32(0);
+            let (x_origin, y_origin) = (read_i16(4), read_i16(6));
+            let region = read_u32(8);
+            log::debug!(
+                "XFIXES: SetPictureClipRegion picture=0x{:x} origin=({},{}) region=0x{:x}",
+                picture,
+                x_origin,
+                y_origin,
+                region
+            );
+            let mut server = server.lock().unwrap();
+            server.set_picture_clip_region(
+                picture,
+                x_origin,
+                y_origin,
+                (region != 0).then_some(region),
+            )?;
         }
         23 => {
             // XFixesSetCursorName
@@ -666,25 +991,24 @@ fn handle_xfixes_request<S: Write>(
             // No reply
         }
         28 => {
-            // XFixesExpandRegion
-            if data.len() >= 16 {
-                let src = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
-                let dst = u32::from_le_bytes([data[4], data[5], data[6], data[7]]);
-                let left = u16::from_le_bytes([data[8], data[9]]);
-                let right = u16::from_le_bytes([data[10], data[11]]);
-                let top = u16::from_le_bytes([data[12], data[13]]);
-                let bottom = u16::from_le_bytes([data[14], data[15]]);
-                log::debug!(
-                    "XFIXES: ExpandRegion src=0x{:x} dst=0x{:x} l={} r={} t={} b={}",
-                    src,
-                    dst,
-                    left,
-                    right,
-                    top,
-                    bottom
-                );
-            }
-            // No reply
+            // XFixesExpandRegion: source(4) + destination(4) + left(2) +
+            // right(2) + top(2) + bottom(2)
+            let (src, dst) = (read_u32(0), read_u32(4));
+            let read_u16 = |offset: usize| u16::from_le_bytes([data[offset], data[offset + 1]]);
+            let (left, right, top, bottom) =
+                (read_u16(8), read_u16(10), read_u16(12), read_u16(14));
+            log::debug!(
+                "XFIXES: ExpandRegion src=0x{:x} dst=0x{:x} l={} r={} t={} b={}",
+                src,
+                dst,
+                left,
+                right,
+                top,
+                bottom
+            );
+            let mut server = server.lock().unwrap();
+            let result = server.region(src)?.expand(left, right, top, bottom);
+            server.set_region(dst, result)?;
         }
         29 => {
             // XFixesHideCursor
@@ -746,6 +1070,7 @@ fn handle_render_request<S: Write>(
     sequence: u16,
     data: &[u8],
     server: &Arc<Mutex<Server>>,
+    client_id: u32,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     match minor_opcode {
         0 => {
@@ -763,27 +1088,69 @@ fn handle_render_request<S: Write>(
         4 => {
             // RenderCreatePicture
             // Format: picture(4) + drawable(4) + format(4) + value_mask(4) + values...
-            if data.len() >= 16 {
-                let picture_id = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
-                let drawable = u32::from_le_bytes([data[4], data[5], data[6], data[7]]);
-                let format = u32::from_le_bytes([data[8], data[9], data[10], data[11]]);
-                log::debug!(
-                    "RENDER: CreatePicture picture=0x{:x} drawable=0x{:x} format={}",
-                    picture_id,
-                    drawable,
-                    format
-                );
-                let mut server = server.lock().unwrap();
-                server.create_picture(picture_id, drawable, format);
-            }
+            if data.len() < 16 {
+                return Err(X11Error::bad_length(0, 0).into());
+            }
+            let picture_id = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+            let drawable = u32::from_le_bytes([data[4], data[5], data[6], data[7]]);
+            let format = u32::from_le_bytes([data[8], data[9], data[10], data[11]]);
+            let value_mask = u32::from_le_bytes([data[12], data[13], data[14], data[15]]);
+            log::debug!(
+                "RENDER: CreatePicture picture=0x{:x} drawable=0x{:x} format={} mask=0x{:x}",
+                picture_id,
+                drawable,
+                format,
+                value_mask
+            );
+            let mut server = server.lock().unwrap();
+            server.create_picture(picture_id, drawable, format);
+            let values = read_u32_values(&data[16..]);
+            render_result(
+                "CreatePicture",
+                server.change_picture(picture_id, value_mask, &values),
+            )?;
         }
         5 => {
-            // RenderChangePicture - no reply needed
-            log::debug!("RENDER: ChangePicture");
+            // RenderChangePicture: picture(4) + value_mask(4) + values...
+            if data.len() < 8 {
+                return Err(X11Error::bad_length(0, 0).into());
+            }
+            let picture_id = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+            let value_mask = u32::from_le_bytes([data[4], data[5], data[6], data[7]]);
+            log::debug!(
+                "RENDER: ChangePicture picture=0x{:x} mask=0x{:x}",
+                picture_id,
+                value_mask
+            );
+            let values = read_u32_values(&data[8..]);
+            let mut server = server.lock().unwrap();
+            render_result(
+                "ChangePicture",
+                server.change_picture(picture_id, value_mask, &values),
+            )?;
         }
         6 => {
-            // RenderSetPictureClipRectangles - no reply needed
-            log::debug!("RENDER: SetPictureClipRectangles");
+            // RenderSetPictureClipRectangles: picture(4) + xorigin(2) +
+            // yorigin(2) + rectangles
+            if data.len() < 8 {
+                return Err(X11Error::bad_length(0, 0).into());
+            }
+            let picture_id = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+            let x_origin = i16::from_le_bytes([data[4], data[5]]);
+            let y_origin = i16::from_le_bytes([data[6], data[7]]);
+            let rectangles = read_rectangles(&data[8..]);
+            log::debug!(
+                "RENDER: SetPictureClipRectangles picture=0x{:x} origin=({},{}) count={}",
+                picture_id,
+                x_origin,
+                y_origin,
+                rectangles.len()
+            );
+            let mut server = server.lock().unwrap();
+            render_result(
+                "SetPictureClipRectangles",
+                server.set_picture_clip_rectangles(picture_id, x_origin, y_origin, rectangles),
+            )?;
         }
         7 => {
             // RenderFreePicture
@@ -795,160 +1162,325 @@ fn handle_render_request<S: Write>(
             }
         }
         8 => {
-            // RenderComposite - no reply needed
-            log::debug!("RENDER: Composite");
-        }
-        10 => {
-            // RenderTrapezoids
-            // Format: op(1) + unused(3) + src(4) + dst(4) + mask_format(4) + src_x(2) + src_y(2) + trapezoids...
-            if data.len() >= 24 {
-                let op = data[0];
-                let src_picture = u32::from_le_bytes([data[4], data[5], data[6], data[7]]);
-                let dst_picture = u32::from_le_bytes([data[8], data[9], data[10], data[11]]);
-                let mask_format = u32::from_le_bytes([data[12], data[13], data[14], data[15]]);
-                let src_x = i16::from_le_bytes([data[16], data[17]]);
-                let src_y = i16::from_le_bytes([data[18], data[19]]);
-
-                // Each trapezoid is 40 bytes (10 * 4-byte fixed-point values)
-                let trap_data = &data[20..];
-                let num_trapezoids = trap_data.len() / 40;
-
-                log::debug!(
-                    "RENDER: Trapezoids op={} src=0x{:x} dst=0x{:x} mask_format={} src=({},{}) count={}",
+            // RenderComposite: op(1) + pad(3) + src(4) + mask(4) + dst(4) +
+            // srcX/Y(2+2) + maskX/Y(2+2) + dstX/Y(2+2) + width(2) + height(2)
+            if data.len() < 32 {
+                return Err(X11Error::bad_length(0, 0).into());
+            }
+            let op = data[0];
+            let src = u32::from_le_bytes([data[4], data[5], data[6], data[7]]);
+            let mask = u32::from_le_bytes([data[8], data[9], data[10], data[11]]);
+            let dst = u32::from_le_bytes([data[12], data[13], data[14], data[15]]);
+            let i16_at = |i: usize| i16::from_le_bytes([data[i], data[i + 1]]);
+            let area = Rectangle {
+                x: i16_at(24),
+                y: i16_at(26),
+                width: u16::from_le_bytes([data[28], data[29]]),
+                height: u16::from_le_bytes([data[30], data[31]]),
+            };
+            log::debug!(
+                "RENDER: Composite op={} src=0x{:x} mask=0x{:x} dst=0x{:x} {}x{} at ({},{})",
+                op,
+                src,
+                mask,
+                dst,
+                area.width,
+                area.height,
+                area.x,
+                area.y
+            );
+            let mut server = server.lock().unwrap();
+            render_result(
+                "Composite",
+                server.render_composite(
                     op,
-                    src_picture,
-                    dst_picture,
-                    mask_format,
-                    src_x,
-                    src_y,
-                    num_trapezoids
-                );
-
-                let mut trapezoids = Vec::with_capacity(num_trapezoids);
-                for i in 0..num_trapezoids {
-                    let offset = i * 40;
-                    let trap = RenderTrapezoid {
-                        top: i32::from_le_bytes([
-                            trap_data[offset],
-                            trap_data[offset + 1],
-                            trap_data[offset + 2],
-                            trap_data[offset + 3],
-                        ]),
-                        bottom: i32::from_le_bytes([
-                            trap_data[offset + 4],
-                            trap_data[offset + 5],
-                            trap_data[offset + 6],
-                            trap_data[offset + 7],
-                        ]),
-                        left_x1: i32::from_le_bytes([
-                            trap_data[offset + 8],
-                            trap_data[offset + 9],
-                            trap_data[offset + 10],
-                            trap_data[offset + 11],
-                        ]),
-                        left_y1: i32::from_le_bytes([
-                            trap_data[offset + 12],
-                            trap_data[offset + 13],
-                            trap_data[offset + 14],
-                            trap_data[offset + 15],
-                        ]),
-                        left_x2: i32::from_le_bytes([
-                            trap_data[offset + 16],
-                            trap_data[offset + 17],
-                            trap_data[offset + 18],
-                            trap_data[offset + 19],
-                        ]),
-                        left_y2: i32::from_le_bytes([
-                            trap_data[offset + 20],
-                            trap_data[offset + 21],
-                            trap_data[offset + 22],
-                            trap_data[offset + 23],
-                        ]),
-                        right_x1: i32::from_le_bytes([
-                            trap_data[offset + 24],
-                            trap_data[offset + 25],
-                            trap_data[offset + 26],
-                            trap_data[offset + 27],
-                        ]),
-                        right_y1: i32::from_le_bytes([
-                            trap_data[offset + 28],
-                            trap_data[offset + 29],
-                            trap_data[offset + 30],
-                            trap_data[offset + 31],
-                        ]),
-                        right_x2: i32::from_le_bytes([
-                            trap_data[offset + 32],
-                            trap_data[offset + 33],
-                            trap_data[offset + 34],
-                            trap_data[offset + 35],
-                        ]),
-                        right_y2: i32::from_le_bytes([
-                            trap_data[offset + 36],
-                            trap_data[offset + 37],
-                            trap_data[offset + 38],
-                            trap_data[offset + 39],
-                        ]),
-                    };
-                    trapezoids.push(trap);
-                }
-
-                let mut server = server.lock().unwrap();
-                if let Err(e) = server.render_trapezoids(
+                    src,
+                    mask,
+                    dst,
+                    (i16_at(16), i16_at(18)),
+                    (i16_at(20), i16_at(22)),
+                    area,
+                ),
+            )?;
+        }
+        10..=13 => {
+            // RenderTrapezoids/Triangles/TriStrip/TriFan: op(1) + pad(3) +
+            // src(4) + dst(4) + mask_format(4) + xsrc(2) + ysrc(2) + then
+            // trapezoids (40 bytes), triangles (24 bytes) or points (8 bytes)
+            if data.len() < 20 {
+                return Err(X11Error::bad_length(0, 0).into());
+            }
+            let op = data[0];
+            let src = u32::from_le_bytes([data[4], data[5], data[6], data[7]]);
+            let dst = u32::from_le_bytes([data[8], data[9], data[10], data[11]]);
+            let mask_format = u32::from_le_bytes([data[12], data[13], data[14], data[15]]);
+            let src_x = i16::from_le_bytes([data[16], data[17]]);
+            let src_y = i16::from_le_bytes([data[18], data[19]]);
+            let values: Vec<i32> = read_u32_values(&data[20..])
+                .into_iter()
+                .map(|v| v as i32)
+                .collect();
+            let points: Vec<(i32, i32)> = values.chunks_exact(2).map(|p| (p[0], p[1])).collect();
+            // Each shape is composited on its own without a mask format
+            let (name, shapes): (&str, Vec<Vec<RenderTrapezoid>>) = match minor_opcode {
+                10 => (
+                    "Trapezoids",
+                    read_trapezoids(&data[20..])
+                        .into_iter()
+                        .map(|t| vec![t])
+                        .collect(),
+                ),
+                11 => (
+                    "Triangles",
+                    points
+                        .chunks_exact(3)
+                        .map(|t| trapezoids::triangle_trapezoids([t[0], t[1], t[2]]))
+                        .collect(),
+                ),
+                12 => (
+                    "TriStrip",
+                    points
+                        .windows(3)
+                        .map(|t| trapezoids::triangle_trapezoids([t[0], t[1], t[2]]))
+                        .collect(),
+                ),
+                _ => (
+                    "TriFan",
+                    points
+                        .iter()
+                        .skip(1)
+                        .zip(points.iter().skip(2))
+                        .map(|(&b, &c)| trapezoids::triangle_trapezoids([points[0], b, c]))
+                        .collect(),
+                ),
+            };
+            log::debug!(
+                "RENDER: {} op={} src=0x{:x} dst=0x{:x} mask_format={} src=({},{}) count={}",
+                name,
+                op,
+                src,
+                dst,
+                mask_format,
+                src_x,
+                src_y,
+                shapes.len()
+            );
+            // The source is aligned to the first vertex; a trapezoid's is
+            // the first point of its left edge
+            let reference = match minor_opcode {
+                10 => values.get(2..4).map(|p| (p[0], p[1])),
+                _ => points.first().copied(),
+            };
+            let Some(reference) = reference else {
+                return Ok(());
+            };
+            let mut server = server.lock().unwrap();
+            render_result(
+                name,
+                server.render_shapes(
                     op,
-                    src_picture,
-                    dst_picture,
+                    src,
+                    dst,
                     mask_format,
-                    src_x,
-                    src_y,
-                    &trapezoids,
-                ) {
-                    log::warn!("RENDER: Trapezoids error: {}", e);
-                }
-            }
-        }
-        11 => {
-            // RenderTriangles - no reply needed
-            log::debug!("RENDER: Triangles");
+                    (src_x, src_y),
+                    reference,
+                    &shapes,
+                ),
+            )?;
         }
         17 => {
-            // RenderCreateGlyphSet - no reply needed
-            log::debug!("RENDER: CreateGlyphSet");
+            // RenderCreateGlyphSet: gsid(4) + format(4)
+            if data.len() < 8 {
+                return Err(X11Error::bad_length(0, 0).into());
+            }
+            let glyph_set = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+            let format = u32::from_le_bytes([data[4], data[5], data[6], data[7]]);
+            log::debug!(
+                "RENDER: CreateGlyphSet gsid=0x{:x} format={}",
+                glyph_set,
+                format
+            );
+            let mut server = server.lock().unwrap();
+            render_result("CreateGlyphSet", server.create_glyph_set(glyph_set, format))?;
         }
         18 => {
-            // RenderReferenceGlyphSet - no reply needed
-            log::debug!("RENDER: ReferenceGlyphSet");
+            // RenderReferenceGlyphSet: gsid(4) + existing(4)
+            if data.len() < 8 {
+                return Err(X11Error::bad_length(0, 0).into());
+            }
+            let glyph_set = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+            let existing = u32::from_le_bytes([data[4], data[5], data[6], data[7]]);
+            log::debug!(
+                "RENDER: ReferenceGlyphSet gsid=0x{:x} existing=0x{:x}",
+                glyph_set,
+                existing
+            );
+            let mut server = server.lock().unwrap();
+            render_result(
+                "ReferenceGlyphSet",
+                server.reference_glyph_set(glyph_set, existing),
+            )?;
         }
         19 => {
-            // RenderFreeGlyphSet - no reply needed
-            log::debug!("RENDER: FreeGlyphSet");
+            // RenderFreeGlyphSet: glyphset(4)
+            if data.len() < 4 {
+                return Err(X11Error::bad_length(0, 0).into());
+            }
+            let glyph_set = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+            log::debug!("RENDER: FreeGlyphSet gsid=0x{:x}", glyph_set);
+            let mut server = server.lock().unwrap();
+            render_result("FreeGlyphSet", server.free_glyph_set(glyph_set))?;
         }
         20 => {
-            // RenderAddGlyphs - no reply needed
-            log::debug!("RENDER: AddGlyphs");
-        }
-        23 => {
-            // RenderCompositeGlyphs8 - no reply needed
-            log::debug!("RENDER: CompositeGlyphs8");
-        }
-        24 => {
-            // RenderCompositeGlyphs16 - no reply needed
-            log::debug!("RENDER: CompositeGlyphs16");
+            // RenderAddGlyphs: glyphset(4) + nglyphs(4) + ids(4 each) +
+            // glyph infos(12 each) + images
+            if data.len() < 8 {
+                return Err(X11Error::bad_length(0, 0).into());
+            }
+            let glyph_set = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+            let count = u32::from_le_bytes([data[4], data[5], data[6], data[7]]) as usize;
+            let images = count
+                .checked_mul(16)
+                .and_then(|len| len.checked_add(8))
+                .filter(|&start| start <= data.len())
+                .ok_or_else(|| X11Error::bad_length(0, 0))?;
+            let ids = read_u32_values(&data[8..8 + count * 4]);
+            let infos: Vec<[i16; 6]> = data[8 + count * 4..images]
+                .chunks_exact(12)
+                .map(|info| {
+                    std::array::from_fn(|i| i16::from_le_bytes([info[i * 2], info[i * 2 + 1]]))
+                })
+                .collect();
+            log::debug!("RENDER: AddGlyphs gsid=0x{:x} count={}", glyph_set, count);
+            let mut server = server.lock().unwrap();
+            render_result(
+                "AddGlyphs",
+                server.add_glyphs(glyph_set, &ids, &infos, &data[images..]),
+            )?;
         }
-        25 => {
-            // RenderCompositeGlyphs32 - no reply needed
-            log::debug!("RENDER: CompositeGlyphs32");
+        22 => {
+            // RenderFreeGlyphs: glyphset(4) + glyphs(4 each)
+            if data.len() < 4 {
+                return Err(X11Error::bad_length(0, 0).into());
+            }
+            let glyph_set = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+            let ids = read_u32_values(&data[4..]);
+            log::debug!(
+                "RENDER: FreeGlyphs gsid=0x{:x} count={}",
+                glyph_set,
+                ids.len()
+            );
+            let mut server = server.lock().unwrap();
+            render_result("FreeGlyphs", server.free_glyphs(glyph_set, &ids))?;
+        }
+        23..=25 => {
+            // RenderCompositeGlyphs8/16/32: op(1) + pad(3) + src(4) + dst(4) +
+            // mask_format(4) + glyphset(4) + xsrc(2) + ysrc(2) + elements
+            if data.len() < 24 {
+                return Err(X11Error::bad_length(0, 0).into());
+            }
+            let op = data[0];
+            let src = u32::from_le_bytes([data[4], data[5], data[6], data[7]]);
+            let dst = u32::from_le_bytes([data[8], data[9], data[10], data[11]]);
+            let mask_format = u32::from_le_bytes([data[12], data[13], data[14], data[15]]);
+            let glyph_set = u32::from_le_bytes([data[16], data[17], data[18], data[19]]);
+            let src_x = i16::from_le_bytes([data[20], data[21]]);
+            let src_y = i16::from_le_bytes([data[22], data[23]]);
+            let id_size = match minor_opcode {
+                23 => 1,
+                24 => 2,
+                _ => 4,
+            };
+            let items = super::glyphs::parse_glyph_items(&data[24..], id_size);
+            log::debug!(
+                "RENDER: CompositeGlyphs{} op={} src=0x{:x} dst=0x{:x} mask_format={} gsid=0x{:x} elements={}",
+                id_size * 8,
+                op,
+                src,
+                dst,
+                mask_format,
+                glyph_set,
+                items.len()
+            );
+            let mut server = server.lock().unwrap();
+            render_result(
+                "CompositeGlyphs",
+                server.composite_glyphs(
+                    op,
+                    src,
+                    dst,
+                    mask_format,
+                    glyph_set,
+                    (src_x, src_y),
+                    &items,
+                ),
+            )?;
         }
         26 => {
-            // RenderFillRectangles - no reply needed
-            log::debug!("RENDER: FillRectangles");
+            // RenderFillRectangles: op(1) + pad(3) + dst(4) + color(8) +
+            // rectangles
+            if data.len() < 16 {
+                return Err(X11Error::bad_length(0, 0).into());
+            }
+            let op = data[0];
+            let dst = u32::from_le_bytes([data[4], data[5], data[6], data[7]]);
+            let color: [u16; 4] =
+                std::array::from_fn(|i| u16::from_le_bytes([data[8 + i * 2], data[9 + i * 2]]));
+            let rectangles = read_rectangles(&data[16..]);
+            log::debug!(
+                "RENDER: FillRectangles op={} dst=0x{:x} color={:?} count={}",
+                op,
+                dst,
+                color,
+                rectangles.len()
+            );
+            let mut server = server.lock().unwrap();
+            render_result(
+                "FillRectangles",
+                server.render_fill_rectangles(op, dst, color, &rectangles),
+            )?;
         }
         27 => {
-            // RenderCreateCursor - no reply needed
-            log::debug!("RENDER: CreateCursor");
+            // RenderCreateCursor: cid(4) + src(4) + x(2) + y(2)
+            if data.len() < 12 {
+                return Err(X11Error::bad_length(0, 0).into());
+            }
+            let cursor = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+            let src = u32::from_le_bytes([data[4], data[5], data[6], data[7]]);
+            let x = u16::from_le_bytes([data[8], data[9]]);
+            let y = u16::from_le_bytes([data[10], data[11]]);
+            log::debug!(
+                "RENDER: CreateCursor cid=0x{:x} src=0x{:x} hotspot=({},{})",
+                cursor,
+                src,
+                x,
+                y
+            );
+            let mut server = server.lock().unwrap();
+            render_result(
+                "CreateCursor",
+                server.create_render_cursor(cursor, src, x, y),
+            )?;
+            server.track_cursor(client_id, cursor);
         }
         28 => {
-            // RenderSetPictureTransform - no reply needed
-            log::debug!("RENDER: SetPictureTransform");
+            // RenderSetPictureTransform: picture(4) + 9 fixed-point entries(4 each)
+            if data.len() < 40 {
+                return Err(X11Error::bad_length(0, 0).into());
+            }
+            let picture = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+            let entries = read_u32_values(&data[4..40]);
+            let matrix: [i32; 9] = std::array::from_fn(|i| entries[i] as i32);
+            log::debug!(
+                "RENDER: SetPictureTransform picture=0x{:x} {:?}",
+                picture,
+                matrix
+            );
+            let mut server = server.lock().unwrap();
+            render_result(
+                "SetPictureTransform",
+                server.set_picture_transform(picture, matrix),
+            )?;
         }
         29 => {
             // RenderQueryFilters
@@ -957,16 +1489,86 @@ fn handle_render_request<S: Write>(
             stream.write_all(&reply)?;
         }
         30 => {
-            // RenderSetPictureFilter - no reply needed
-            log::debug!("RENDER: SetPictureFilter");
+            // RenderSetPictureFilter: picture(4) + name_len(2) + pad(2) +
+            // name (padded to 4) + fixed-point values(4 each)
+            if data.len() < 8 {
+                return Err(X11Error::bad_length(0, 0).into());
+            }
+            let picture = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+            let name_len = u16::from_le_bytes([data[4], data[5]]) as usize;
+            let values = 8 + name_len.div_ceil(4) * 4;
+            if data.len() < values {
+                return Err(X11Error::bad_length(0, 0).into());
+            }
+            let name = String::from_utf8_lossy(&data[8..8 + name_len]);
+            let params: Vec<i32> = read_u32_values(&data[values..])
+                .into_iter()
+                .map(|v| v as i32)
+                .collect();
+            log::debug!(
+                "RENDER: SetPictureFilter picture=0x{:x} filter={} params={:?}",
+                picture,
+                name,
+                params
+            );
+            let mut server = server.lock().unwrap();
+            render_result(
+                "SetPictureFilter",
+                server.set_picture_filter(picture, &name, &params),
+            )?;
         }
         31 => {
-            // RenderCreateAnimCursor - no reply needed
-            log::debug!("RENDER: CreateAnimCursor");
+            // RenderCreateAnimCursor: cid(4) + (cursor(4) + delay(4)) per frame
+            if data.len() < 4 {
+                return Err(X11Error::bad_length(0, 0).into());
+            }
+            let cursor = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+            let elements: Vec<(u32, u32)> = read_u32_values(&data[4..])
+                .chunks_exact(2)
+                .map(|e| (e[0], e[1]))
+                .collect();
+            log::debug!(
+                "RENDER: CreateAnimCursor cid=0x{:x} frames={}",
+                cursor,
+                elements.len()
+            );
+            let mut server = server.lock().unwrap();
+            render_result(
+                "CreateAnimCursor",
+                server.create_anim_cursor(cursor, &elements),
+            )?;
+            server.track_cursor(client_id, cursor);
         }
         32 => {
-            // RenderAddTraps - no reply needed
-            log::debug!("RENDER: AddTraps");
+            // RenderAddTraps: picture(4) + xoff(2) + yoff(2) + traps, each a
+            // top and a bottom span of left(4) + right(4) + y(4)
+            if data.len() < 8 {
+                return Err(X11Error::bad_length(0, 0).into());
+            }
+            let picture = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+            let x_off = i16::from_le_bytes([data[4], data[5]]);
+            let y_off = i16::from_le_bytes([data[6], data[7]]);
+            let traps: Vec<RenderTrapezoid> = read_u32_values(&data[8..])
+                .chunks_exact(6)
+                .map(|t| {
+                    RenderTrapezoid::from_spans(
+                        (t[0] as i32, t[1] as i32, t[2] as i32),
+                        (t[3] as i32, t[4] as i32, t[5] as i32),
+                    )
+                })
+                .collect();
+            log::debug!(
+                "RENDER: AddTraps picture=0x{:x} offset=({},{}) count={}",
+                picture,
+                x_off,
+                y_off,
+                traps.len()
+            );
+            let mut server = server.lock().unwrap();
+            render_result(
+                "AddTraps",
+                server.add_traps(picture, (x_off, y_off), &traps),
+            )?;
         }
         33 => {
             // RenderCreateSolidFill
@@ -989,20 +1591,217 @@ fn handle_render_request<S: Write>(
                 server.create_solid_fill(picture_id, red, green, blue, alpha);
             }
         }
-        34 => {
-            // RenderCreateLinearGradient - no reply needed
-            log::debug!("RENDER: CreateLinearGradient");
+        34..=36 => {
+            // RenderCreateLinearGradient: picture(4) + p1(8) + p2(8)
+            // RenderCreateRadialGradient: picture(4) + inner(8) + outer(8) +
+            //     inner_radius(4) + outer_radius(4)
+            // RenderCreateConicalGradient: picture(4) + center(8) + angle(4)
+            // each followed by nStops(4) + stops(4 each) + colors(8 each)
+            let fixed_count = match minor_opcode {
+                34 => 4,
+                35 => 6,
+                _ => 3,
+            };
+            let header = 4 + fixed_count * 4;
+            if data.len() < header + 4 {
+                return Err(X11Error::bad_length(0, 0).into());
+            }
+            let picture_id = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+            // 16.16 fixed-point values as floating point
+            let fixed: Vec<f64> = data[4..header]
+                .chunks_exact(4)
+                .map(|v| i32::from_le_bytes([v[0], v[1], v[2], v[3]]) as f64 / 65536.0)
+                .collect();
+            let shape = match minor_opcode {
+                34 => GradientShape::Linear {
+                    p1: (fixed[0], fixed[1]),
+                    p2: (fixed[2], fixed[3]),
+                },
+                35 => GradientShape::Radial {
+                    inner: (fixed[0], fixed[1], fixed[4]),
+                    outer: (fixed[2], fixed[3], fixed[5]),
+                },
+                _ => GradientShape::Conical {
+                    center: (fixed[0], fixed[1]),
+                    angle: fixed[2],
+                },
+            };
+            let count = u32::from_le_bytes([
+                data[header],
+                data[header + 1],
+                data[header + 2],
+                data[header + 3],
+            ]) as usize;
+            let stops_start = header + 4;
+            let end = count
+                .checked_mul(12)
+                .map(|len| stops_start + len)
+                .filter(|&end| end <= data.len())
+                .ok_or_else(|| X11Error::bad_length(0, 0))?;
+            let colors_start = stops_start + count * 4;
+            let offsets: Vec<i32> = data[stops_start..colors_start]
+                .chunks_exact(4)
+                .map(|v| i32::from_le_bytes([v[0], v[1], v[2], v[3]]))
+                .collect();
+            let colors: Vec<[u16; 4]> = data[colors_start..end]
+                .chunks_exact(8)
+                .map(|c| std::array::from_fn(|i| u16::from_le_bytes([c[i * 2], c[i * 2 + 1]])))
+                .collect();
+            log::debug!(
+                "RENDER: CreateGradient picture=0x{:x} {:?} stops={}",
+                picture_id,
+                shape,
+                count
+            );
+            let mut server = server.lock().unwrap();
+            render_result(
+                "CreateGradient",
+                server.create_gradient(picture_id, shape, &offsets, &colors),
+            )?;
+        }
+        _ => {
+            log::debug!("RENDER: Unhandled minor opcode {}", minor_opcode);
+        }
+    }
+    Ok(())
+}
+
+/// Pass RENDER protocol errors on to the client; other failures, such as
+/// unknown pictures, are logged
+fn render_result(
+    request: &str,
+    result: Result<(), Box<dyn std::error::Error + Send + Sync>>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    match result {
+        Err(e) if e.is::<X11Error>() => Err(e),
+        Err(e) => {
+            log::warn!("RENDER: {} error: {}", request, e);
+            Ok(())
+        }
+        Ok(()) => Ok(()),
+    }
+}
+
+/// Split a value list into 32-bit values
+fn read_u32_values(data: &[u8]) -> Vec<u32> {
+    data.chunks_exact(4)
+        .map(|v| u32::from_le_bytes([v[0], v[1], v[2], v[3]]))
+        .collect()
+}
+
+/// Split a list of 16.16 fixed-point trapezoids
+fn read_trapezoids(data: &[u8]) -> Vec<RenderTrapezoid> {
+    read_u32_values(data)
+        .chunks_exact(10)
+        .map(|t| RenderTrapezoid {
+            top: t[0] as i32,
+            bottom: t[1] as i32,
+            left_x1: t[2] as i32,
+            left_y1: t[3] as i32,
+            left_x2: t[4] as i32,
+            left_y2: t[5] as i32,
+            right_x1: t[6] as i32,
+            right_y1: t[7] as i32,
+            right_x2: t[8] as i32,
+            right_y2: t[9] as i32,
+        })
+        .collect()
+}
+
+/// Split a list of rectangles: x(2) + y(2) + width(2) + height(2) each
+fn read_rectangles(data: &[u8]) -> Vec<Rectangle> {
+    data.chunks_exact(8)
+        .map(|r| Rectangle {
+            x: i16::from_le_bytes([r[0], r[1]]),
+            y: i16::from_le_bytes([r[2], r[3]]),
+            width: u16::from_le_bytes([r[4], r[5]]),
+            height: u16::from_le_bytes([r[6], r[7]]),
+        })
+        .collect()
+}
+
+/// Handle XTEST extension requests
+fn handle_xtest_request<S: Write>(
+    stream: &mut S,
+    minor_opcode: u8,
+    sequence: u16,
+    data: &[u8],
+    server: &Arc<Mutex<Server>>,
+    client_id: u32,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    match minor_opcode {
+        0 => {
+            // XTestGetVersion
+            log::debug!("XTEST: GetVersion");
+            let reply = encode_xtest_get_version_reply(sequence);
+            stream.write_all(&reply)?;
+        }
+        1 => {
+            // XTestCompareCursor: window(4) + cursor(4)
+            if data.len() < 8 {
+                return Err(X11Error::bad_length(0, 0).into());
+            }
+            let window = Window::new(u32::from_le_bytes([data[0], data[1], data[2], data[3]]));
+            let cursor = u32::from_le_bytes([data[4], data[5], data[6], data[7]]);
+            let same = server.lock().unwrap().compare_cursor(window, cursor)?;
+            log::debug!(
+                "XTEST: CompareCursor window=0x{:x} cursor=0x{:x} same={}",
+                window.id().get(),
+                cursor,
+                same
+            );
+            let reply = encode_xtest_compare_cursor_reply(sequence, same);
+            stream.write_all(&reply)?;
         }
-        35 => {
-            // RenderCreateRadialGradient - no reply needed
-            log::debug!("RENDER: CreateRadialGradient");
+        2 => {
+            // XTestFakeInput: type(1) + detail(1) + pad(2) + time(4) + root(4)
+            // + pad(8) + rootX(2) + rootY(2) + pad(7) + deviceid(1)
+            if data.len() < 24 {
+                return Err(X11Error::bad_length(0, 0).into());
+            }
+            let event_type = data[0];
+            let detail = data[1];
+            let delay = u32::from_le_bytes([data[4], data[5], data[6], data[7]]);
+            let root_x = i16::from_le_bytes([data[20], data[21]]);
+            let root_y = i16::from_le_bytes([data[22], data[23]]);
+            log::debug!(
+                "XTEST: FakeInput type={} detail={} ({},{}) delay={}",
+                event_type,
+                detail,
+                root_x,
+                root_y,
+                delay
+            );
+            // The event happens `delay` milliseconds from now; like Xorg, the
+            // client's later requests wait behind it
+            if delay > 0 {
+                std::thread::sleep(std::time::Duration::from_millis(delay as u64));
+            }
+            server
+                .lock()
+                .unwrap()
+                .fake_input(event_type, detail, root_x, root_y)?;
+            // No reply
         }
-        36 => {
-            // RenderCreateConicalGradient - no reply needed
-            log::debug!("RENDER: CreateConicalGradient");
+        3 => {
+            // XTestGrabControl: impervious(1) + pad(3)
+            if data.is_empty() {
+                return Err(X11Error::bad_length(0, 0).into());
+            }
+            let impervious = match data[0] {
+                0 => false,
+                1 => true,
+                value => return Err(X11Error::bad_value(0, value as u32, 0).into()),
+            };
+            log::debug!("XTEST: GrabControl impervious={}", impervious);
+            server
+                .lock()
+                .unwrap()
+                .set_grab_impervious(client_id, impervious);
+            // No reply
         }
         _ => {
-            log::debug!("RENDER: Unhandled minor opcode {}", minor_opcode);
+            log::debug!("XTEST: Unhandled minor opcode {}", minor_opcode);
         }
     }
     Ok(())
@@ -1374,44 +2173,6 @@ fn handle_damage_request<S: Write>(
     Ok(())
 }
 
-/// Handle XKEYBOARD (XKB) extension requests
-fn handle_xkb_request<S: Write>(
-    stream: &mut S,
-    minor_opcode: u8,
-    sequence: u16,
-    data: &[u8],
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    match minor_opcode {
-        0 => {
-            // XkbUseExtension (query extension)
-            if data.len() >= 4 {
-                let wanted_major = u16::from_le_bytes([data[0], data[1]]);
-                let wanted_minor = u16::from_le_bytes([data[2], data[3]]);
-                log::debug!(
-                    "XKB: UseExtension wanted_major={} wanted_minor={}",
-                    wanted_major,
-                    wanted_minor
-                );
-            }
-            // Return XKB version 1.0 as supported
-            let reply = encode_xkb_use_extension_reply(sequence, true, 1, 0);
-            stream.write_all(&reply)?;
-        }
-        8 => {
-            // XkbGetMap - return an empty map
-            log::debug!("XKB: GetMap");
-            let reply = encode_xkb_get_map_reply(sequence);
-            stream.write_all(&reply)?;
-        }
-        _ => {
-            log::debug!("XKB: Unhandled minor opcode {}", minor_opcode);
-            // For most XKB requests, we don't need to send a reply
-            // The important one is UseExtension (opcode 0)
-        }
-    }
-    Ok(())
-}
-
 // Reply encoders
 
 fn write_u16_le(value: u16) -> [u8; 2] {
@@ -1432,17 +2193,96 @@ fn encode_shape_query_version_reply(sequence: u16) -> Vec<u8> {
     buffer
 }
 
+fn encode_shape_query_extents_reply(sequence: u16, extents: &[ShapeExtents; 2]) -> Vec<u8> {
+    let mut buffer = vec![0u8; 32];
+    buffer[0] = 1; // Reply
+    buffer[2..4].copy_from_slice(&write_u16_le(sequence));
+    buffer[4..8].copy_from_slice(&write_u32_le(0)); // length
+    for (i, shape) in extents.iter().enumerate() {
+        let offset = 12 + i * 8;
+        buffer[8 + i] = shape.shaped as u8; // bounding_shaped, clip_shaped
+        buffer[offset..offset + 2].copy_from_slice(&shape.extents.x.to_le_bytes());
+        buffer[offset + 2..offset + 4].copy_from_slice(&shape.extents.y.to_le_bytes());
+        buffer[offset + 4..offset + 6].copy_from_slice(&write_u16_le(shape.extents.width));
+        buffer[offset + 6..offset + 8].copy_from_slice(&write_u16_le(shape.extents.height));
+    }
+    buffer
+}
+
+fn encode_shape_get_rectangles_reply(sequence: u16, rects: &[Rectangle]) -> Vec<u8> {
+    let mut buffer = vec![0u8; 32 + rects.len() * 8];
+    buffer[0] = 1; // Reply
+    buffer[1] = 3; // ordering: YXBanded
+    buffer[2..4].copy_from_slice(&write_u16_le(sequence));
+    buffer[4..8].copy_from_slice(&write_u32_le(rects.len() as u32 * 2)); // length
+    buffer[8..12].copy_from_slice(&write_u32_le(rects.len() as u32));
+    for (i, rect) in rects.iter().enumerate() {
+        let offset = 32 + i * 8;
+        buffer[offset..offset + 2].copy_from_slice(&rect.x.to_le_bytes());
+        buffer[offset + 2..offset + 4].copy_from_slice(&rect.y.to_le_bytes());
+        buffer[offset + 4..offset + 6].copy_from_slice(&write_u16_le(rect.width));
+        buffer[offset + 6..offset + 8].copy_from_slice(&write_u16_le(rect.height));
+    }
+    buffer
+}
+
+#[cfg(unix)]
 fn encode_shm_query_version_reply(sequence: u16) -> Vec<u8> {
+    // SAFETY: getuid and getgid cannot fail
+    let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
     let mut buffer = vec![0u8; 32];
     buffer[0] = 1; // Reply
-    buffer[1] = 1; // shared pixmaps supported
+    buffer[1] = 0; // no shared pixmaps
     buffer[2..4].copy_from_slice(&write_u16_le(sequence));
     buffer[4..8].copy_from_slice(&write_u32_le(0)); // length
     buffer[8..10].copy_from_slice(&write_u16_le(1)); // major version
     buffer[10..12].copy_from_slice(&write_u16_le(2)); // minor version
-    buffer[12..14].copy_from_slice(&write_u16_le(0)); // uid (not used)
-    buffer[14..16].copy_from_slice(&write_u16_le(0)); // gid (not used)
-    buffer[16] = 0; // pixmap format
+    buffer[12..14].copy_from_slice(&write_u16_le(uid as u16)); // server uid
+    buffer[14..16].copy_from_slice(&write_u16_le(gid as u16)); // server gid
+    buffer[16] = 2; // pixmap format, were there shared pixmaps
+    buffer
+}
+
+#[cfg(unix)]
+fn encode_shm_get_image_reply(sequence: u16, depth: u8, visual: u32, size: u32) -> Vec<u8> {
+    let mut buffer = vec![0u8; 32];
+    buffer[0] = 1; // Reply
+    buffer[1] = depth;
+    buffer[2..4].copy_from_slice(&write_u16_le(sequence));
+    buffer[4..8].copy_from_slice(&write_u32_le(0)); // length
+    buffer[8..12].copy_from_slice(&write_u32_le(visual));
+    buffer[12..16].copy_from_slice(&write_u32_le(size));
+    buffer
+}
+
+/// CreateSegment reply, sent along with the segment's descriptor
+#[cfg(unix)]
+fn encode_shm_create_segment_reply(sequence: u16) -> Vec<u8> {
+    let mut buffer = vec![0u8; 32];
+    buffer[0] = 1; // Reply
+    buffer[1] = 1; // number of descriptors
+    buffer[2..4].copy_from_slice(&write_u16_le(sequence));
+    buffer[4..8].copy_from_slice(&write_u32_le(0)); // length
+    buffer
+}
+
+/// ShmCompletion event, telling the client a PutImage is done with its segment
+#[cfg(unix)]
+fn encode_shm_completion_event(
+    first_event: u8,
+    sequence: u16,
+    drawable: u32,
+    shmseg: u32,
+    offset: u32,
+) -> Vec<u8> {
+    let mut buffer = vec![0u8; 32];
+    buffer[0] = first_event;
+    buffer[2..4].copy_from_slice(&write_u16_le(sequence));
+    buffer[4..8].copy_from_slice(&write_u32_le(drawable));
+    buffer[8..10].copy_from_slice(&write_u16_le(3)); // minor event (ShmPutImage)
+    buffer[10] = 130; // major event (MIT-SHM)
+    buffer[12..16].copy_from_slice(&write_u32_le(shmseg));
+    buffer[16..20].copy_from_slice(&write_u32_le(offset));
     buffer
 }
 
@@ -1562,6 +2402,24 @@ fn encode_xfixes_query_version_reply(sequence: u16) -> Vec<u8> {
     buffer
 }
 
+fn encode_xtest_get_version_reply(sequence: u16) -> Vec<u8> {
+    let mut buffer = vec![0u8; 32];
+    buffer[0] = 1; // Reply
+    buffer[1] = 2; // major version
+    buffer[2..4].copy_from_slice(&write_u16_le(sequence));
+    buffer[4..8].copy_from_slice(&write_u32_le(0)); // length
+    buffer[8..10].copy_from_slice(&write_u16_le(2)); // minor version
+    buffer
+}
+
+fn encode_xtest_compare_cursor_reply(sequence: u16, same: bool) -> Vec<u8> {
+    let mut buffer = vec![0u8; 32];
+    buffer[0] = 1; // Reply
+    buffer[1] = same as u8;
+    buffer[2..4].copy_from_slice(&write_u16_le(sequence));
+    buffer
+}
+
 fn encode_render_query_version_reply(sequence: u16) -> Vec<u8> {
     let mut buffer = vec![0u8; 32];
     buffer[0] = 1; // Reply
@@ -1962,45 +2820,6 @@ fn encode_damage_query_version_reply(sequence: u16) -> Vec<u8> {
     buffer
 }
 
-fn encode_xkb_use_extension_reply(
-    sequence: u16,
-    supported: bool,
-    server_major: u16,
-    server_minor: u16,
-) -> Vec<u8> {
-    let mut buffer = vec![0u8; 32];
-    buffer[0] = 1; // Reply
-    buffer[1] = if supported { 1 } else { 0 }; // supported
-    buffer[2..4].copy_from_slice(&write_u16_le(sequence));
-    buffer[4..8].copy_from_slice(&write_u32_le(0)); // length
-    buffer[8..10].copy_from_slice(&write_u16_le(server_major));
-    buffer[10..12].copy_from_slice(&write_u16_le(server_minor));
-    buffer
-}
-
-fn encode_xkb_get_map_reply(sequence: u16) -> Vec<u8> {
-    // XkbGetMapReply - return a minimal/empty map
-    // The reply has a 40-byte header (8 bytes extra beyond standard 32-byte reply)
-    // Length field counts 4-byte units after the first 32 bytes, so length = 2
-    let mut buffer = vec![0u8; 40];
-    buffer[0] = 1; // Reply
-    buffer[1] = 0; // deviceID (use core keyboard)
-    buffer[2..4].copy_from_slice(&write_u16_le(sequence));
-    buffer[4..8].copy_from_slice(&write_u32_le(2)); // length: 8 extra bytes = 2 words
-                                                    // Bytes 8-9: pad
-    buffer[10] = 8; // minKeyCode (standard)
-    buffer[11] = 255; // maxKeyCode (standard)
-    buffer[12..14].copy_from_slice(&write_u16_le(0)); // present (no components)
-    buffer[14..16].copy_from_slice(&write_u16_le(8)); // firstType
-    buffer[16] = 0; // nTypes
-    buffer[17] = 0; // totalTypes
-    buffer[18] = 8; // firstKeySym
-    buffer[19] = 0; // totalSyms (high byte)
-    buffer[20] = 0; // nKeySyms
-                    // Rest of the header fields are 0 (no key actions, behaviors, etc.)
-    buffer
-}
-
 /// Encode a PICTFORMINFO structure (28 bytes)
 #[allow(clippy::too_many_arguments)]
 fn encode_pictforminfo(
@@ -2260,19 +3079,27 @@ fn encode_xfixes_get_cursor_image_reply(sequence: u16) -> Vec<u8> {
     buffer
 }
 
-/// Encode FetchRegion reply
-/// Returns an empty region (bounding box 0,0,0,0, no rectangles)
-fn encode_xfixes_fetch_region_reply(sequence: u16) -> Vec<u8> {
-    let mut buffer = vec![0u8; 32];
+/// Encode FetchRegion reply: the extents, then the rectangles
+fn encode_xfixes_fetch_region_reply(
+    sequence: u16,
+    extents: Rectangle,
+    rects: &[Rectangle],
+) -> Vec<u8> {
+    let mut buffer = vec![0u8; 32 + rects.len() * 8];
     buffer[0] = 1; // Reply
     buffer[2..4].copy_from_slice(&write_u16_le(sequence));
-    buffer[4..8].copy_from_slice(&write_u32_le(0)); // length (no extra data)
-                                                    // Bounding box: x, y, width, height (all 0)
-    buffer[8..10].copy_from_slice(&write_u16_le(0)); // x
-    buffer[10..12].copy_from_slice(&write_u16_le(0)); // y
-    buffer[12..14].copy_from_slice(&write_u16_le(0)); // width
-    buffer[14..16].copy_from_slice(&write_u16_le(0)); // height
-                                                      // No rectangles follow
+    buffer[4..8].copy_from_slice(&write_u32_le(rects.len() as u32 * 2)); // length
+    buffer[8..10].copy_from_slice(&extents.x.to_le_bytes());
+    buffer[10..12].copy_from_slice(&extents.y.to_le_bytes());
+    buffer[12..14].copy_from_slice(&write_u16_le(extents.width));
+    buffer[14..16].copy_from_slice(&write_u16_le(extents.height));
+    for (i, rect) in rects.iter().enumerate() {
+        let offset = 32 + i * 8;
+        buffer[offset..offset + 2].copy_from_slice(&rect.x.to_le_bytes());
+        buffer[offset + 2..offset + 4].copy_from_slice(&rect.y.to_le_bytes());
+        buffer[offset + 4..offset + 6].copy_from_slice(&write_u16_le(rect.width));
+        buffer[offset + 6..offset + 8].copy_from_slice(&write_u16_le(rect.height));
+    }
     buffer
 }
 
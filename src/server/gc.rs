@@ -0,0 +1,649 @@
+//! Graphics context state
+//!
+//! Parsing of GC value lists and the CreateGC/ChangeGC/CopyGC/
+//! SetClipRectangles logic. The server keeps the complete GC state in a
+//! BackendGC; backends only ever see the resolved values (clip-mask pixmaps,
+//! for instance, arrive as rectangle lists).
+
+use super::Server;
+use crate::backend::{BackendGC, GCPattern};
+use crate::protocol::*;
+use std::error::Error;
+
+/// GC value-mask bits, in value-list order
+pub mod gc_mask {
+    pub const FUNCTION: u32 = 1 << 0;
+    pub const PLANE_MASK: u32 = 1 << 1;
+    pub const FOREGROUND: u32 = 1 << 2;
+    pub const BACKGROUND: u32 = 1 << 3;
+    pub const LINE_WIDTH: u32 = 1 << 4;
+    pub const LINE_STYLE: u32 = 1 << 5;
+    pub const CAP_STYLE: u32 = 1 << 6;
+    pub const JOIN_STYLE: u32 = 1 << 7;
+    pub const FILL_STYLE: u32 = 1 << 8;
+    pub const FILL_RULE: u32 = 1 << 9;
+    pub const TILE: u32 = 1 << 10;
+    pub const STIPPLE: u32 = 1 << 11;
+    pub const TILE_STIPPLE_X_ORIGIN: u32 = 1 << 12;
+    pub const TILE_STIPPLE_Y_ORIGIN: u32 = 1 << 13;
+    pub const FONT: u32 = 1 << 14;
+    pub const SUBWINDOW_MODE: u32 = 1 << 15;
+    pub const GRAPHICS_EXPOSURES: u32 = 1 << 16;
+    pub const CLIP_X_ORIGIN: u32 = 1 << 17;
+    pub const CLIP_Y_ORIGIN: u32 = 1 << 18;
+    pub const CLIP_MASK: u32 = 1 << 19;
+    pub const DASH_OFFSET: u32 = 1 << 20;
+    pub const DASHES: u32 = 1 << 21;
+    pub const ARC_MODE: u32 = 1 << 22;
+}
+
+/// Values from a CreateGC/ChangeGC value list
+#[derive(Debug, Clone, Default)]
+pub struct GCValues {
+    pub function: Option<u32>,
+    pub plane_mask: Option<u32>,
+    pub foreground: Option<u32>,
+    pub background: Option<u32>,
+    pub line_width: Option<u16>,
+    pub line_style: Option<u32>,
+    pub cap_style: Option<u32>,
+    pub join_style: Option<u32>,
+    pub fill_style: Option<u32>,
+    pub fill_rule: Option<u32>,
+    pub tile: Option<u32>,
+    pub stipple: Option<u32>,
+    pub ts_x_origin: Option<i16>,
+    pub ts_y_origin: Option<i16>,
+    pub font: Option<u32>,
+    pub subwindow_mode: Option<u32>,
+    pub graphics_exposures: Option<bool>,
+    pub clip_x_origin: Option<i16>,
+    pub clip_y_origin: Option<i16>,
+    /// Clip-mask pixmap; 0 is None
+    pub clip_mask: Option<u32>,
+    pub dash_offset: Option<u16>,
+    pub dashes: Option<u8>,
+    pub arc_mode: Option<u32>,
+}
+
+impl GCValues {
+    /// Parse a value list; values are 4 bytes each, present in bit order
+    pub fn parse(value_mask: u32, data: &[u8]) -> Self {
+        let mut values = GCValues::default();
+        let mut words = data
+            .chunks_exact(4)
+            .map(|w| u32::from_le_bytes([w[0], w[1], w[2], w[3]]));
+        for bit in 0..23 {
+            if value_mask & (1 << bit) == 0 {
+                continue;
+            }
+            let Some(value) = words.next() else {
+                break;
+            };
+            match 1 << bit {
+                gc_mask::FUNCTION => values.function = Some(value),
+                gc_mask::PLANE_MASK => values.plane_mask = Some(value),
+                gc_mask::FOREGROUND => values.foreground = Some(value),
+                gc_mask::BACKGROUND => values.background = Some(value),
+                gc_mask::LINE_WIDTH => values.line_width = Some(value as u16),
+                gc_mask::LINE_STYLE => values.line_style = Some(value),
+                gc_mask::CAP_STYLE => values.cap_style = Some(value),
+                gc_mask::JOIN_STYLE => values.join_style = Some(value),
+                gc_mask::FILL_STYLE => values.fill_style = Some(value),
+                gc_mask::FILL_RULE => values.fill_rule = Some(value),
+                gc_mask::TILE => values.tile = Some(value),
+                gc_mask::STIPPLE => values.stipple = Some(value),
+                gc_mask::TILE_STIPPLE_X_ORIGIN => values.ts_x_origin = Some(value as i16),
+                gc_mask::TILE_STIPPLE_Y_ORIGIN => values.ts_y_origin = Some(value as i16),
+                gc_mask::FONT => values.font = Some(value),
+                gc_mask::SUBWINDOW_MODE => values.subwindow_mode = Some(value),
+                gc_mask::GRAPHICS_EXPOSURES => values.graphics_exposures = Some(value != 0),
+                gc_mask::CLIP_X_ORIGIN => values.clip_x_origin = Some(value as i16),
+                gc_mask::CLIP_Y_ORIGIN => values.clip_y_origin = Some(value as i16),
+                gc_mask::CLIP_MASK => values.clip_mask = Some(value),
+                gc_mask::DASH_OFFSET => values.dash_offset = Some(value as u16),
+                gc_mask::DASHES => values.dashes = Some(value as u8),
+                _ => values.arc_mode = Some(value),
+            }
+        }
+        values
+    }
+}
+
+impl Server {
+    /// Create a graphics context
+    pub fn create_gc(
+        &mut self,
+        gc: GContext,
+        _drawable: Drawable,
+        values: &GCValues,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let font = match values.font {
+            Some(font_id) => Some(self.font_by_id(font_id)?),
+            None => None,
+        };
+        let mut backend_gc = BackendGC::default();
+        self.apply_gc_values(&mut backend_gc, values)?;
+        self.gcs.insert(gc, backend_gc);
+        if let Some(font) = font {
+            self.gc_fonts.insert(gc, font);
+        }
+        Ok(())
+    }
+
+    /// Change GC attributes
+    pub fn change_gc(
+        &mut self,
+        gc: GContext,
+        values: &GCValues,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let Some(mut backend_gc) = self.gcs.get(&gc).cloned() else {
+            return Err(X11Error::bad_gc(0, gc, 0).into());
+        };
+        let font = match values.font {
+            Some(font_id) => Some(self.font_by_id(font_id)?),
+            None => None,
+        };
+        self.apply_gc_values(&mut backend_gc, values)?;
+        self.gcs.insert(gc, backend_gc);
+        if let Some(font) = font {
+            self.gc_fonts.insert(gc, font);
+        }
+        self.release_unused_pixmaps()
+    }
+
+    /// Validate and apply a value list to a GC
+    ///
+    /// Nothing is changed if any value is invalid.
+    fn apply_gc_values(
+        &mut self,
+        gc: &mut BackendGC,
+        values: &GCValues,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let subwindow_mode = match values.subwindow_mode {
+            None => None,
+            Some(0) => Some(SubwindowMode::ClipByChildren),
+            Some(1) => Some(SubwindowMode::IncludeInferiors),
+            Some(other) => return Err(X11Error::bad_value(0, other, 0).into()),
+        };
+        let function = match values.function {
+            None => None,
+            Some(value) => {
+                Some(GCFunction::from_u32(value).ok_or_else(|| X11Error::bad_value(0, value, 0))?)
+            }
+        };
+        let line_style = match values.line_style {
+            None => None,
+            Some(0) => Some(LineStyle::Solid),
+            Some(1) => Some(LineStyle::OnOffDash),
+            Some(2) => Some(LineStyle::DoubleDash),
+            Some(other) => return Err(X11Error::bad_value(0, other, 0).into()),
+        };
+        let cap_style = match values.cap_style {
+            None => None,
+            Some(0) => Some(CapStyle::NotLast),
+            Some(1) => Some(CapStyle::Butt),
+            Some(2) => Some(CapStyle::Round),
+            Some(3) => Some(CapStyle::Projecting),
+            Some(other) => return Err(X11Error::bad_value(0, other, 0).into()),
+        };
+        let join_style = match values.join_style {
+            None => None,
+            Some(0) => Some(JoinStyle::Miter),
+            Some(1) => Some(JoinStyle::Round),
+            Some(2) => Some(JoinStyle::Bevel),
+            Some(other) => return Err(X11Error::bad_value(0, other, 0).into()),
+        };
+        if values.dashes == Some(0) {
+            return Err(X11Error::bad_value(0, 0, 0).into());
+        }
+        let fill_style = match values.fill_style {
+            None => None,
+            Some(0) => Some(FillStyle::Solid),
+            Some(1) => Some(FillStyle::Tiled),
+            Some(2) => Some(FillStyle::Stippled),
+            Some(3) => Some(FillStyle::OpaqueStippled),
+            Some(other) => return Err(X11Error::bad_value(0, other, 0).into()),
+        };
+        let fill_rule = match values.fill_rule {
+            None => None,
+            Some(0) => Some(FillRule::EvenOdd),
+            Some(1) => Some(FillRule::Winding),
+            Some(other) => return Err(X11Error::bad_value(0, other, 0).into()),
+        };
+        let arc_mode = match values.arc_mode {
+            None => None,
+            Some(0) => Some(ArcMode::Chord),
+            Some(1) => Some(ArcMode::PieSlice),
+            Some(other) => return Err(X11Error::bad_value(0, other, 0).into()),
+        };
+        let tile = match values.tile {
+            None => None,
+            Some(pixmap) => Some(self.gc_pattern(pixmap)?),
+        };
+        let stipple = match values.stipple {
+            None => None,
+            Some(pixmap) => {
+                let pattern = self.gc_pattern(pixmap)?;
+                if pattern.depth != 1 {
+                    return Err(X11Error::bad_match(0, 0).into());
+                }
+                Some(pattern)
+            }
+        };
+        let clip_rectangles = match values.clip_mask {
+            None => None,
+            Some(0) => Some(None),
+            Some(pixmap) => Some(Some(self.clip_mask_rectangles(pixmap)?)),
+        };
+
+        if let Some(function) = function {
+            gc.function = function;
+        }
+        if let Some(mask) = values.plane_mask {
+            gc.plane_mask = mask;
+        }
+        if let Some(fg) = values.foreground {
+            gc.foreground = fg;
+        }
+        if let Some(bg) = values.background {
+            gc.background = bg;
+        }
+        if let Some(width) = values.line_width {
+            gc.line_width = width;
+        }
+        if let Some(style) = line_style {
+            gc.line_style = style;
+        }
+        if let Some(style) = cap_style {
+            gc.cap_style = style;
+        }
+        if let Some(style) = join_style {
+            gc.join_style = style;
+        }
+        if let Some(style) = fill_style {
+            gc.fill_style = style;
+        }
+        if let Some(rule) = fill_rule {
+            gc.fill_rule = rule;
+        }
+        if let Some(mode) = arc_mode {
+            gc.arc_mode = mode;
+        }
+        if let Some(pattern) = tile {
+            gc.tile = Some(pattern);
+        }
+        if let Some(pattern) = stipple {
+            gc.stipple = Some(pattern);
+        }
+        if let Some(x) = values.ts_x_origin {
+            gc.ts_x_origin = x;
+        }
+        if let Some(y) = values.ts_y_origin {
+            gc.ts_y_origin = y;
+        }
+        if let Some(offset) = values.dash_offset {
+            gc.dash_offset = offset;
+        }
+        if let Some(dash) = values.dashes {
+            gc.dashes = vec![dash, dash];
+        }
+        if let Some(mode) = subwindow_mode {
+            gc.subwindow_mode = mode;
+        }
+        if let Some(exposures) = values.graphics_exposures {
+            gc.graphics_exposures = exposures;
+        }
+        if let Some(x) = values.clip_x_origin {
+            gc.clip_x_origin = x;
+        }
+        if let Some(y) = values.clip_y_origin {
+            gc.clip_y_origin = y;
+        }
+        if let Some(rects) = clip_rectangles {
+            gc.clip_rectangles = rects;
+        }
+        Ok(())
+    }
+
+    /// Copy GC attributes from one GC to another
+    pub fn copy_gc(
+        &mut self,
+        src_gc: GContext,
+        dst_gc: GContext,
+        value_mask: u32,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let src_values = self
+            .gcs
+            .get(&src_gc)
+            .cloned()
+            .ok_or_else(|| X11Error::bad_gc(0, src_gc, 0))?;
+
+        // Apply masked values to destination GC
+        let dst = self
+            .gcs
+            .get_mut(&dst_gc)
+            .ok_or_else(|| X11Error::bad_gc(0, dst_gc, 0))?;
+        if value_mask & gc_mask::FUNCTION != 0 {
+            dst.function = src_values.function;
+        }
+        if value_mask & gc_mask::PLANE_MASK != 0 {
+            dst.plane_mask = src_values.plane_mask;
+        }
+        if value_mask & gc_mask::FOREGROUND != 0 {
+            dst.foreground = src_values.foreground;
+        }
+        if value_mask & gc_mask::BACKGROUND != 0 {
+            dst.background = src_values.background;
+        }
+        if value_mask & gc_mask::LINE_WIDTH != 0 {
+            dst.line_width = src_values.line_width;
+        }
+        if value_mask & gc_mask::LINE_STYLE != 0 {
+            dst.line_style = src_values.line_style;
+        }
+        if value_mask & gc_mask::CAP_STYLE != 0 {
+            dst.cap_style = src_values.cap_style;
+        }
+        if value_mask & gc_mask::JOIN_STYLE != 0 {
+            dst.join_style = src_values.join_style;
+        }
+        if value_mask & gc_mask::FILL_STYLE != 0 {
+            dst.fill_style = src_values.fill_style;
+        }
+        if value_mask & gc_mask::FILL_RULE != 0 {
+            dst.fill_rule = src_values.fill_rule;
+        }
+        if value_mask & gc_mask::TILE != 0 {
+            dst.tile = src_values.tile;
+        }
+        if value_mask & gc_mask::STIPPLE != 0 {
+            dst.stipple = src_values.stipple;
+        }
+        if value_mask & gc_mask::TILE_STIPPLE_X_ORIGIN != 0 {
+            dst.ts_x_origin = src_values.ts_x_origin;
+        }
+        if value_mask & gc_mask::TILE_STIPPLE_Y_ORIGIN != 0 {
+            dst.ts_y_origin = src_values.ts_y_origin;
+        }
+        if value_mask & gc_mask::DASH_OFFSET != 0 {
+            dst.dash_offset = src_values.dash_offset;
+        }
+        if value_mask & gc_mask::DASHES != 0 {
+            dst.dashes = src_values.dashes.clone();
+        }
+        if value_mask & gc_mask::SUBWINDOW_MODE != 0 {
+            dst.subwindow_mode = src_values.subwindow_mode;
+        }
+        if value_mask & gc_mask::GRAPHICS_EXPOSURES != 0 {
+            dst.graphics_exposures = src_values.graphics_exposures;
+        }
+        if value_mask & gc_mask::CLIP_X_ORIGIN != 0 {
+            dst.clip_x_origin = src_values.clip_x_origin;
+        }
+        if value_mask & gc_mask::CLIP_Y_ORIGIN != 0 {
+            dst.clip_y_origin = src_values.clip_y_origin;
+        }
+        if value_mask & gc_mask::CLIP_MASK != 0 {
+            dst.clip_rectangles = src_values.clip_rectangles;
+        }
+        if value_mask & gc_mask::ARC_MODE != 0 {
+            dst.arc_mode = src_values.arc_mode;
+        }
+        log::debug!(
+            "CopyGC: copied attributes from 0x{:x} to 0x{:x} (mask=0x{:x})",
+            src_gc.id().get(),
+            dst_gc.id().get(),
+            value_mask
+        );
+        // The font is kept outside BackendGC
+        if value_mask & gc_mask::FONT != 0 {
+            match self.gc_fonts.get(&src_gc).cloned() {
+                Some(font) => self.gc_fonts.insert(dst_gc, font),
+                None => self.gc_fonts.remove(&dst_gc),
+            };
+        }
+        self.release_unused_pixmaps()
+    }
+
+    /// Set the dash offset and dash list of a GC
+    ///
+    /// The list must be non-empty and free of zeros (BadValue otherwise).
+    pub fn set_dashes(
+        &mut self,
+        gc: GContext,
+        dash_offset: u16,
+        dashes: Vec<u8>,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        if dashes.is_empty() || dashes.contains(&0) {
+            return Err(X11Error::bad_value(0, 0, 0).into());
+        }
+        let backend_gc = self
+            .gcs
+            .get_mut(&gc)
+            .ok_or_else(|| X11Error::bad_gc(0, gc, 0))?;
+        backend_gc.dash_offset = dash_offset;
+        backend_gc.dashes = dashes;
+        Ok(())
+    }
+
+    /// Set the clip origin and clip rectangles of a GC
+    ///
+    /// Rectangles claimed to be sorted must really be (BadMatch otherwise).
+    pub fn set_clip_rectangles(
+        &mut self,
+        gc: GContext,
+        ordering: ClipOrdering,
+        clip_x_origin: i16,
+        clip_y_origin: i16,
+        rectangles: Vec<Rectangle>,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        if !ordering.verify(&rectangles) {
+            return Err(X11Error::bad_match(0, 0).into());
+        }
+        let backend_gc = self
+            .gcs
+            .get_mut(&gc)
+            .ok_or_else(|| X11Error::bad_gc(0, gc, 0))?;
+        backend_gc.clip_x_origin = clip_x_origin;
+        backend_gc.clip_y_origin = clip_y_origin;
+        backend_gc.clip_rectangles = Some(rectangles);
+        Ok(())
+    }
+
+    /// Look up a pixmap to use as a tile or stipple
+    pub(super) fn gc_pattern(
+        &self,
+        pixmap: u32,
+    ) -> Result<GCPattern, Box<dyn Error + Send + Sync>> {
+        match (self.pixmaps.get(&pixmap), self.pixmap_info.get(&pixmap)) {
+            (Some(&backend_pixmap), Some(info)) => Ok(GCPattern {
+                pixmap: backend_pixmap,
+                width: info.width,
+                height: info.height,
+                depth: info.depth,
+            }),
+            _ => Err(X11Error::bad_pixmap(0, Pixmap::new(pixmap), 0).into()),
+        }
+    }
+
+    /// The bits of a depth-1 pixmap: its width, height and whether each
+    /// pixel is set, row by row
+    pub(super) fn pixmap_bits(
+        &mut self,
+        pixmap: u32,
+    ) -> Result<(u16, u16, Vec<bool>), Box<dyn Error + Send + Sync>> {
+        let info = self
+            .pixmap_info
+            .get(&pixmap)
+            .cloned()
+            .ok_or_else(|| X11Error::bad_pixmap(0, Pixmap::new(pixmap), 0))?;
+        if info.depth != 1 {
+            return Err(X11Error::bad_match(0, 0).into());
+        }
+        let (width, height) = (info.width as usize, info.height as usize);
+        if width == 0 || height == 0 {
+            return Ok((info.width, info.height, Vec::new()));
+        }
+
+        let drawable = Drawable::Pixmap(Pixmap::new(pixmap));
+        let (depth, _visual, data) =
+            self.get_image(drawable, 0, 0, info.width, info.height, !0, 2)?;
+
+        // Depth-1 images are LSB-first bitmaps padded to 32 bits per row;
+        // backends that keep all pixmaps at full depth return whole pixels
+        let is_set: Box<dyn Fn(usize, usize) -> bool> = if depth == 1 {
+            let stride = width.div_ceil(32) * 4;
+            Box::new(move |x, y| {
+                data.get(y * stride + x / 8)
+                    .is_some_and(|b| b >> (x % 8) & 1 != 0)
+            })
+        } else {
+            let bpp = (data.len() / (width * height)).max(1);
+            Box::new(move |x, y| {
+                let offset = (y * width + x) * bpp;
+                data.get(offset..offset + bpp.min(3))
+                    .is_some_and(|px| px.iter().any(|b| *b != 0))
+            })
+        };
+        let bits = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| is_set(x, y))
+            .collect();
+        Ok((info.width, info.height, bits))
+    }
+
+    /// Turn a depth-1 clip-mask pixmap into YX-banded rectangles covering its
+    /// set bits
+    pub(super) fn clip_mask_rectangles(
+        &mut self,
+        pixmap: u32,
+    ) -> Result<Vec<Rectangle>, Box<dyn Error + Send + Sync>> {
+        let (width, height, bits) = self.pixmap_bits(pixmap)?;
+        let (width, height) = (width as usize, height as usize);
+        let is_set = |x: usize, y: usize| bits[y * width + x];
+
+        // Runs of set pixels per row; identical consecutive rows form a band
+        let mut rects: Vec<Rectangle> = Vec::new();
+        let mut band_start = 0;
+        let mut prev_runs: Vec<(usize, usize)> = Vec::new();
+        for y in 0..=height {
+            let mut runs = Vec::new();
+            if y < height {
+                let mut x = 0;
+                while x < width {
+                    if is_set(x, y) {
+                        let start = x;
+                        while x < width && is_set(x, y) {
+                            x += 1;
+                        }
+                        runs.push((start, x));
+                    } else {
+                        x += 1;
+                    }
+                }
+            }
+            if y == 0 || runs != prev_runs {
+                for &(start, end) in &prev_runs {
+                    rects.push(Rectangle {
+                        x: start as i16,
+                        y: band_start as i16,
+                        width: (end - start) as u16,
+                        height: (y - band_start) as u16,
+                    });
+                }
+                band_start = y;
+                prev_runs = runs;
+            }
+        }
+        Ok(rects)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_value_list() {
+        let mask = gc_mask::FOREGROUND | gc_mask::SUBWINDOW_MODE | gc_mask::CLIP_Y_ORIGIN;
+        let data: Vec<u8> = [0x00ff00u32, 1, (-5i32) as u32]
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        let values = GCValues::parse(mask, &data);
+        assert_eq!(values.foreground, Some(0x00ff00));
+        assert_eq!(values.subwindow_mode, Some(1));
+        assert_eq!(values.clip_y_origin, Some(-5));
+        assert_eq!(values.clip_x_origin, None);
+    }
+
+    #[test]
+    fn test_clip_ordering() {
+        let rect = |x, y, width, height| Rectangle {
+            x,
+            y,
+            width,
+            height,
+        };
+        let banded = [rect(0, 0, 5, 2), rect(10, 0, 5, 2), rect(0, 2, 1, 4)];
+        assert!(ClipOrdering::YXBanded.verify(&banded));
+
+        let ragged = [rect(0, 0, 5, 2), rect(10, 0, 5, 3)];
+        assert!(ClipOrdering::YXSorted.verify(&ragged));
+        assert!(!ClipOrdering::YXBanded.verify(&ragged));
+        assert!(!ClipOrdering::YSorted.verify(&[rect(0, 4, 1, 1), rect(0, 2, 1, 1)]));
+    }
+
+    #[test]
+    fn test_gc_values() {
+        let mut server = Server::new(Box::new(crate::backend::null::NullBackend::new())).unwrap();
+        let drawable = Drawable::Window(server.root_window());
+        let (src, dst) = (GContext::new(0x0040_0001), GContext::new(0x0040_0002));
+        let values = GCValues {
+            cap_style: Some(2),
+            join_style: Some(2),
+            fill_rule: Some(1),
+            arc_mode: Some(0),
+            graphics_exposures: Some(false),
+            ..Default::default()
+        };
+        server.create_gc(src, drawable, &values).unwrap();
+        server
+            .create_gc(dst, drawable, &Default::default())
+            .unwrap();
+        let gc = &server.gcs[&src];
+        assert_eq!(gc.cap_style, CapStyle::Round);
+        assert_eq!(gc.join_style, JoinStyle::Bevel);
+        assert_eq!(gc.fill_rule, FillRule::Winding);
+        assert_eq!(gc.arc_mode, ArcMode::Chord);
+        assert!(!gc.graphics_exposures);
+
+        // An invalid value changes nothing
+        let bad = GCValues {
+            fill_rule: Some(0),
+            arc_mode: Some(2),
+            ..Default::default()
+        };
+        assert!(server.change_gc(src, &bad).is_err());
+        assert_eq!(server.gcs[&src].fill_rule, FillRule::Winding);
+
+        server
+            .copy_gc(src, dst, gc_mask::FILL_RULE | gc_mask::ARC_MODE)
+            .unwrap();
+        let gc = &server.gcs[&dst];
+        assert_eq!(gc.fill_rule, FillRule::Winding);
+        assert_eq!(gc.arc_mode, ArcMode::Chord);
+        assert_eq!(gc.cap_style, CapStyle::Butt);
+        assert!(gc.graphics_exposures);
+
+        // Either GC missing is BadGC
+        let missing = GContext::new(0x0040_0003);
+        for (from, to) in [(missing, dst), (src, missing)] {
+            let err = server.copy_gc(from, to, gc_mask::FUNCTION).unwrap_err();
+            let err = err.downcast_ref::<X11Error>().unwrap();
+            assert_eq!(err.code, ErrorCode::GContext);
+            assert_eq!(err.bad_value, 0x0040_0003);
+        }
+    }
+}
@@ -0,0 +1,456 @@
+//! RENDER glyph sets and CompositeGlyphs
+//!
+//! Glyph images are kept by the server as premultiplied pixels and drawn
+//! through the compositor like any other picture: one Composite per glyph,
+//! or a single Composite through a mask the glyphs are added into when the
+//! client asks for a mask format.
+
+use super::render::pict_format;
+use super::Server;
+use crate::backend::composite::{self, CompositePicture, Operator, PictFormat, PictureSource};
+use crate::protocol::*;
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::Arc;
+
+/// A glyph image with its metrics
+#[derive(Debug, Clone)]
+pub struct Glyph {
+    pub width: u16,
+    pub height: u16,
+    /// Offset from the glyph origin to the image's top-left corner
+    pub x: i16,
+    pub y: i16,
+    /// Advance to the next glyph's origin
+    pub x_off: i16,
+    pub y_off: i16,
+    /// Premultiplied pixels, row by row
+    pub pixels: Arc<Vec<u32>>,
+}
+
+#[derive(Debug)]
+struct GlyphSet {
+    format: PictFormat,
+    glyphs: HashMap<u32, Glyph>,
+    /// IDs naming the set
+    refs: usize,
+}
+
+/// Glyph sets by ID; ReferenceGlyphSet adds IDs naming the same set
+#[derive(Debug, Default)]
+pub struct GlyphSets {
+    /// Sets by the ID that created them
+    sets: HashMap<u32, GlyphSet>,
+    /// Every glyph set ID to the key of its set
+    ids: HashMap<u32, u32>,
+}
+
+impl GlyphSets {
+    /// Every glyph set ID in use
+    pub fn ids(&self) -> impl Iterator<Item = u32> + '_ {
+        self.ids.keys().copied()
+    }
+
+    fn get(&self, id: u32) -> Result<&GlyphSet, Box<dyn Error + Send + Sync>> {
+        self.ids
+            .get(&id)
+            .and_then(|key| self.sets.get(key))
+            .ok_or_else(|| format!("Glyph set 0x{:x} not found", id).into())
+    }
+
+    fn get_mut(&mut self, id: u32) -> Result<&mut GlyphSet, Box<dyn Error + Send + Sync>> {
+        self.ids
+            .get(&id)
+            .and_then(|key| self.sets.get_mut(key))
+            .ok_or_else(|| format!("Glyph set 0x{:x} not found", id).into())
+    }
+}
+
+/// A glyph laid out by CompositeGlyphs
+#[derive(Debug, Clone)]
+struct PlacedGlyph {
+    glyph: Glyph,
+    /// Whether the glyph carries per-channel (subpixel) coverage
+    component_alpha: bool,
+    /// Destination position of the glyph image
+    x: i32,
+    y: i32,
+}
+
+/// Glyphs laid out by CompositeGlyphs
+#[derive(Debug)]
+struct GlyphLayout {
+    glyphs: Vec<PlacedGlyph>,
+    /// Pen position of the first element, which lines up with the source
+    /// origin
+    origin: (i32, i32),
+}
+
+/// One element of a CompositeGlyphs request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GlyphItem {
+    /// Move the pen by (dx, dy), then draw the glyphs
+    Glyphs { dx: i16, dy: i16, glyphs: Vec<u32> },
+    /// Take the following glyphs from another glyph set
+    GlyphSet(u32),
+}
+
+/// Parse the elements of CompositeGlyphs8/16/32, with glyph IDs of
+/// `id_size` bytes
+pub fn parse_glyph_items(data: &[u8], id_size: usize) -> Vec<GlyphItem> {
+    let mut items = Vec::new();
+    let mut pos = 0;
+    while pos + 8 <= data.len() {
+        let count = data[pos] as usize;
+        let dx = i16::from_le_bytes([data[pos + 4], data[pos + 5]]);
+        let dy = i16::from_le_bytes([data[pos + 6], data[pos + 7]]);
+        pos += 8;
+        if count == 0xff {
+            // A glyph set switch carries the set in place of glyphs
+            let Some(id) = data.get(pos..pos + 4) else {
+                break;
+            };
+            items.push(GlyphItem::GlyphSet(u32::from_le_bytes([
+                id[0], id[1], id[2], id[3],
+            ])));
+            pos += 4;
+            continue;
+        }
+        let len = count * id_size;
+        let Some(ids) = data.get(pos..pos + len) else {
+            break;
+        };
+        let glyphs = ids
+            .chunks_exact(id_size)
+            .map(|id| id.iter().rev().fold(0, |glyph, b| glyph << 8 | *b as u32))
+            .collect();
+        items.push(GlyphItem::Glyphs { dx, dy, glyphs });
+        pos += len.div_ceil(4) * 4;
+    }
+    items
+}
+
+/// Add two premultiplied pixels, channel by channel
+fn add_pixels(a: u32, b: u32) -> u32 {
+    let (a, b) = (a.to_le_bytes(), b.to_le_bytes());
+    u32::from_le_bytes(std::array::from_fn(|i| a[i].saturating_add(b[i])))
+}
+
+impl Server {
+    /// Create an empty glyph set of a picture format
+    pub fn create_glyph_set(
+        &mut self,
+        id: u32,
+        format: u32,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        if !(1..=4).contains(&format) {
+            return Err(format!("Glyph set format {} not supported", format).into());
+        }
+        self.glyph_sets.sets.insert(
+            id,
+            GlyphSet {
+                format: pict_format(format, 0),
+                glyphs: HashMap::new(),
+                refs: 1,
+            },
+        );
+        self.glyph_sets.ids.insert(id, id);
+        Ok(())
+    }
+
+    /// Name an existing glyph set with another ID
+    pub fn reference_glyph_set(
+        &mut self,
+        id: u32,
+        existing: u32,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let key = *self
+            .glyph_sets
+            .ids
+            .get(&existing)
+            .ok_or_else(|| format!("Glyph set 0x{:x} not found", existing))?;
+        self.glyph_sets.get_mut(existing)?.refs += 1;
+        self.glyph_sets.ids.insert(id, key);
+        Ok(())
+    }
+
+    /// Drop a glyph set ID; the set goes with its last ID
+    pub fn free_glyph_set(&mut self, id: u32) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let key = self
+            .glyph_sets
+            .ids
+            .remove(&id)
+            .ok_or_else(|| format!("Glyph set 0x{:x} not found", id))?;
+        if let Some(set) = self.glyph_sets.sets.get_mut(&key) {
+            set.refs -= 1;
+            if set.refs == 0 {
+                self.glyph_sets.sets.remove(&key);
+            }
+        }
+        Ok(())
+    }
+
+    /// Add glyphs to a set; `infos` are (width, height, x, y, x_off, y_off)
+    /// and `data` holds their images one after another, each row padded to
+    /// 32 bits
+    pub fn add_glyphs(
+        &mut self,
+        glyph_set: u32,
+        ids: &[u32],
+        infos: &[[i16; 6]],
+        data: &[u8],
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let set = self.glyph_sets.get_mut(glyph_set)?;
+        let format = set.format;
+        let mut pos = 0;
+        for (&id, info) in ids.iter().zip(infos) {
+            let (width, height) = (info[0] as u16 as usize, info[1] as u16 as usize);
+            let stride = match format {
+                PictFormat::A1 => width.div_ceil(32) * 4,
+                PictFormat::A8 => width.div_ceil(4) * 4,
+                PictFormat::Argb32 | PictFormat::Rgb24 => width * 4,
+            };
+            let image = data
+                .get(pos..pos + stride * height)
+                .ok_or_else(|| X11Error::bad_length(0, 0))?;
+            pos += stride * height;
+            let pixels = composite::decode(format, format.depth(), image, width, height);
+            set.glyphs.insert(
+                id,
+                Glyph {
+                    width: width as u16,
+                    height: height as u16,
+                    x: info[2],
+                    y: info[3],
+                    x_off: info[4],
+                    y_off: info[5],
+                    pixels: Arc::new(pixels),
+                },
+            );
+        }
+        Ok(())
+    }
+
+    /// Remove glyphs from a set
+    pub fn free_glyphs(
+        &mut self,
+        glyph_set: u32,
+        ids: &[u32],
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let set = self.glyph_sets.get_mut(glyph_set)?;
+        for id in ids {
+            set.glyphs.remove(id);
+        }
+        Ok(())
+    }
+
+    /// Lay out CompositeGlyphs elements
+    fn layout_glyphs(
+        &self,
+        glyph_set: u32,
+        items: &[GlyphItem],
+    ) -> Result<GlyphLayout, Box<dyn Error + Send + Sync>> {
+        let mut set = self.glyph_sets.get(glyph_set)?;
+        let mut placed = Vec::new();
+        let (mut x, mut y) = (0i32, 0i32);
+        let mut first = None;
+        for item in items {
+            match item {
+                GlyphItem::GlyphSet(id) => set = self.glyph_sets.get(*id)?,
+                GlyphItem::Glyphs { dx, dy, glyphs } => {
+                    x += *dx as i32;
+                    y += *dy as i32;
+                    first.get_or_insert((x, y));
+                    // ARGB glyphs are subpixel coverage
+                    let component_alpha = set.format == PictFormat::Argb32;
+                    for id in glyphs {
+                        // Glyphs missing from the set draw nothing
+                        let Some(glyph) = set.glyphs.get(id) else {
+                            continue;
+                        };
+                        placed.push(PlacedGlyph {
+                            glyph: glyph.clone(),
+                            component_alpha,
+                            x: x - glyph.x as i32,
+                            y: y - glyph.y as i32,
+                        });
+                        x += glyph.x_off as i32;
+                        y += glyph.y_off as i32;
+                    }
+                }
+            }
+        }
+        Ok(GlyphLayout {
+            glyphs: placed,
+            origin: first.unwrap_or((0, 0)),
+        })
+    }
+
+    /// Draw glyphs with `src` onto `dst` (RENDER CompositeGlyphs)
+    ///
+    /// With a mask format the glyphs are first added into a mask of that
+    /// format covering all of them; otherwise each one is composited on its
+    /// own.
+    #[allow(clippy::too_many_arguments)]
+    pub fn composite_glyphs(
+        &mut self,
+        op: u8,
+        src: u32,
+        dst: u32,
+        mask_format: u32,
+        glyph_set: u32,
+        src_origin: (i16, i16),
+        items: &[GlyphItem],
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let op = Operator::from_u8(op).ok_or_else(|| X11Error::bad_value(0, op as u32, 0))?;
+        let src = self.composite_picture(src)?;
+        let dst = self.composite_picture(dst)?;
+        let GlyphLayout {
+            glyphs: placed,
+            origin: (dst_x, dst_y),
+        } = self.layout_glyphs(glyph_set, items)?;
+        let src_at = |x: i32, y: i32| {
+            (
+                (src_origin.0 as i32 + x - dst_x) as i16,
+                (src_origin.1 as i32 + y - dst_y) as i16,
+            )
+        };
+
+        if mask_format == 0 {
+            for PlacedGlyph {
+                glyph,
+                component_alpha,
+                x,
+                y,
+            } in placed
+            {
+                let mut mask = CompositePicture::new(PictureSource::Pixels {
+                    width: glyph.width,
+                    height: glyph.height,
+                    pixels: glyph.pixels,
+                });
+                mask.component_alpha = component_alpha;
+                let area = Rectangle {
+                    x: x as i16,
+                    y: y as i16,
+                    width: glyph.width,
+                    height: glyph.height,
+                };
+                self.backend
+                    .composite(op, &src, Some(&mask), &dst, src_at(x, y), (0, 0), area)?;
+            }
+            return Ok(());
+        }
+
+        let mut extents = (i32::MAX, i32::MAX, i32::MIN, i32::MIN);
+        for PlacedGlyph { glyph, x, y, .. } in &placed {
+            extents.0 = extents.0.min(*x);
+            extents.1 = extents.1.min(*y);
+            extents.2 = extents.2.max(x + glyph.width as i32);
+            extents.3 = extents.3.max(y + glyph.height as i32);
+        }
+        if extents.2 <= extents.0 || extents.3 <= extents.1 {
+            return Ok(());
+        }
+        let width = (extents.2 - extents.0).min(u16::MAX as i32) as usize;
+        let height = (extents.3 - extents.1).min(u16::MAX as i32) as usize;
+        let mut pixels = vec![0u32; width * height];
+        for PlacedGlyph { glyph, x, y, .. } in &placed {
+            let (gx, gy) = ((x - extents.0) as usize, (y - extents.1) as usize);
+            for row in 0..glyph.height as usize {
+                for col in 0..glyph.width as usize {
+                    let (mx, my) = (gx + col, gy + row);
+                    if mx < width && my < height {
+                        let pixel = glyph.pixels[row * glyph.width as usize + col];
+                        let target = &mut pixels[my * width + mx];
+                        *target = add_pixels(*target, pixel);
+                    }
+                }
+            }
+        }
+        // The mask holds what its format can store
+        let format = pict_format(mask_format, 8);
+        for pixel in &mut pixels {
+            *pixel = match format {
+                PictFormat::A8 => *pixel & 0xff00_0000,
+                PictFormat::A1 if *pixel >> 24 >= 0x80 => 0xff00_0000,
+                PictFormat::A1 => 0,
+                PictFormat::Rgb24 => *pixel | 0xff00_0000,
+                PictFormat::Argb32 => *pixel,
+            };
+        }
+        let mut mask = CompositePicture::new(PictureSource::Pixels {
+            width: width as u16,
+            height: height as u16,
+            pixels: Arc::new(pixels),
+        });
+        mask.component_alpha = format == PictFormat::Argb32;
+        let area = Rectangle {
+            x: extents.0 as i16,
+            y: extents.1 as i16,
+            width: width as u16,
+            height: height as u16,
+        };
+        self.backend.composite(
+            op,
+            &src,
+            Some(&mask),
+            &dst,
+            src_at(extents.0, extents.1),
+            (0, 0),
+            area,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::null::NullBackend;
+
+    #[test]
+    fn test_glyph_layout() {
+        let mut server = Server::new(Box::new(NullBackend::new())).unwrap();
+        server.create_glyph_set(0x0020_0001, 3).unwrap();
+        server.create_glyph_set(0x0020_0002, 4).unwrap();
+        // A 2x2 A8 glyph advancing 5, and a 1x1 A1 glyph
+        server
+            .add_glyphs(
+                0x0020_0001,
+                &[65],
+                &[[2, 2, 1, 2, 5, 0]],
+                &[0xff, 0x80, 0, 0, 0x40, 0, 0, 0],
+            )
+            .unwrap();
+        server
+            .add_glyphs(0x0020_0002, &[7], &[[1, 1, 0, 0, 3, 0]], &[1, 0, 0, 0])
+            .unwrap();
+        server
+            .reference_glyph_set(0x0020_0003, 0x0020_0002)
+            .unwrap();
+        server.free_glyph_set(0x0020_0002).unwrap();
+
+        // Two glyphs at (10, 20), a switch to the referenced set, then a
+        // glyph moved by (1, 1)
+        let mut data = vec![2, 0, 0, 0, 10, 0, 20, 0, 65, 65, 0, 0];
+        data.extend_from_slice(&[0xff, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0x20, 0]);
+        data.extend_from_slice(&[1, 0, 0, 0, 1, 0, 1, 0, 7, 0, 0, 0]);
+        let items = parse_glyph_items(&data, 1);
+        assert_eq!(items.len(), 3);
+        assert_eq!(items[1], GlyphItem::GlyphSet(0x0020_0003));
+
+        let layout = server.layout_glyphs(0x0020_0001, &items).unwrap();
+        assert_eq!(layout.origin, (10, 20));
+        let placed = layout.glyphs;
+        let positions: Vec<_> = placed.iter().map(|p| (p.x, p.y)).collect();
+        assert_eq!(positions, vec![(9, 18), (14, 18), (21, 21)]);
+        assert_eq!(
+            placed[0].glyph.pixels[..],
+            [0xff00_0000, 0x8000_0000, 0x4000_0000, 0]
+        );
+        assert_eq!(placed[2].glyph.pixels[..], [0xff00_0000]);
+
+        server.free_glyph_set(0x0020_0003).unwrap();
+        assert!(server.layout_glyphs(0x0020_0003, &items).is_err());
+    }
+}
@@ -0,0 +1,689 @@
+//! Pointer and keyboard grabs
+//!
+//! GrabPointer and GrabKeyboard start active grabs; GrabButton and GrabKey
+//! register passive grabs that activate on a matching press; a button press
+//! nothing grabbed gives its receiver an implicit grab until the buttons are
+//! released. While a device is grabbed its events go only to the grabbing
+//! client. A synchronous grab freezes a device: its input is held until the
+//! client releases it with AllowEvents. GrabServer stops every other client's
+//! requests from being processed, except for clients XTestGrabControl has
+//! made impervious.
+
+use super::device::current_time;
+use super::keyboard::MIN_KEYCODE;
+use super::window_tree::{MODE_GRAB, MODE_UNGRAB};
+use super::Server;
+use crate::backend::BackendEvent;
+use crate::protocol::*;
+use std::collections::HashSet;
+
+/// GrabPointer/GrabKeyboard reply status
+pub const GRAB_SUCCESS: u8 = 0;
+pub const ALREADY_GRABBED: u8 = 1;
+pub const GRAB_INVALID_TIME: u8 = 2;
+pub const GRAB_NOT_VIEWABLE: u8 = 3;
+pub const GRAB_FROZEN: u8 = 4;
+
+/// GrabButton/GrabKey modifiers matching any state
+pub const ANY_MODIFIER: u16 = 0x8000;
+
+/// Modifier bits of the core state field
+const MODIFIER_MASK: u16 = 0x00ff;
+
+/// Event mask of keyboard grabs
+const KEY_EVENTS: u32 = event_mask::KEY_PRESS | event_mask::KEY_RELEASE;
+
+/// How a grab started
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum GrabKind {
+    /// GrabPointer or GrabKeyboard; lasts until ungrabbed
+    Active,
+    /// A GrabButton or GrabKey that matched a press; ends when the buttons
+    /// or the key are released
+    Passive,
+    /// A button press nothing grabbed; ends when the buttons are released
+    Implicit,
+}
+
+/// Where a grab reports events and how it freezes input
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GrabParams {
+    pub window: Window,
+    pub owner_events: bool,
+    /// Pointer events reported relative to the grab window
+    pub event_mask: u32,
+    /// Synchronous pointer mode: freeze the pointer when the grab starts
+    pub pointer_sync: bool,
+    /// Synchronous keyboard mode: freeze the keyboard when the grab starts
+    pub keyboard_sync: bool,
+    /// Window the pointer is confined to, or None
+    pub confine_to: Window,
+}
+
+/// A grab of the pointer or the keyboard
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Grab {
+    pub client: u32,
+    pub params: GrabParams,
+    pub kind: GrabKind,
+    /// The key whose passive grab started this grab
+    pub key: u8,
+    pub time: u32,
+}
+
+/// A GrabButton or GrabKey registration
+#[derive(Debug, Clone, Copy)]
+struct PassiveGrab {
+    client: u32,
+    /// Button or keycode; 0 is AnyButton/AnyKey
+    detail: u8,
+    modifiers: u16,
+    params: GrabParams,
+}
+
+impl PassiveGrab {
+    fn matches(&self, detail: u8, state: u16) -> bool {
+        (self.detail == 0 || self.detail == detail)
+            && (self.modifiers == ANY_MODIFIER || self.modifiers == state & MODIFIER_MASK)
+    }
+
+    /// Whether this and another registration can match the same press
+    fn overlaps(&self, detail: u8, modifiers: u16) -> bool {
+        (self.detail == 0 || detail == 0 || self.detail == detail)
+            && (self.modifiers == ANY_MODIFIER
+                || modifiers == ANY_MODIFIER
+                || self.modifiers == modifiers)
+    }
+}
+
+/// Input devices that can be grabbed and frozen
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Device {
+    Pointer,
+    Keyboard,
+}
+
+/// Per-device freeze state
+#[derive(Debug, Default)]
+struct Freeze {
+    /// Client whose grab froze the device
+    client: Option<u32>,
+    /// The press or release that froze a passive grab, for Replay modes
+    replay: Option<BackendEvent>,
+    /// Set by SyncPointer/SyncKeyboard: freeze again after the next press
+    /// or release reported
+    sync: bool,
+}
+
+/// Active and passive grabs and frozen input
+#[derive(Debug, Default)]
+pub(super) struct GrabState {
+    pub pointer: Option<Grab>,
+    pub keyboard: Option<Grab>,
+    buttons: Vec<PassiveGrab>,
+    keys: Vec<PassiveGrab>,
+    pointer_freeze: Freeze,
+    keyboard_freeze: Freeze,
+    /// Input held while its device is frozen
+    held: Vec<BackendEvent>,
+    /// A replayed press skips passive grabs on this window and its ancestors
+    replaying: Option<Window>,
+    /// Client holding GrabServer
+    server: Option<u32>,
+    /// Clients whose requests are processed despite a server grab
+    impervious: HashSet<u32>,
+}
+
+impl GrabState {
+    /// Whether any grab is registered or active
+    pub fn any(&self) -> bool {
+        self.pointer.is_some()
+            || self.keyboard.is_some()
+            || !self.buttons.is_empty()
+            || !self.keys.is_empty()
+    }
+
+    fn freeze(&mut self, device: Device) -> &mut Freeze {
+        match device {
+            Device::Pointer => &mut self.pointer_freeze,
+            Device::Keyboard => &mut self.keyboard_freeze,
+        }
+    }
+
+    fn grab(&mut self, device: Device) -> &mut Option<Grab> {
+        match device {
+            Device::Pointer => &mut self.pointer,
+            Device::Keyboard => &mut self.keyboard,
+        }
+    }
+}
+
+/// Whether a request's time is earlier than a grab's; CurrentTime (0) is
+/// never earlier
+fn earlier(time: u32, grab_time: u32) -> bool {
+    time != 0 && time < grab_time
+}
+
+/// The device an input event comes from
+fn event_device(event: &BackendEvent) -> Option<Device> {
+    match event {
+        BackendEvent::KeyPress { .. } | BackendEvent::KeyRelease { .. } => Some(Device::Keyboard),
+        BackendEvent::ButtonPress { .. }
+        | BackendEvent::ButtonRelease { .. }
+        | BackendEvent::MotionNotify { .. } => Some(Device::Pointer),
+        _ => None,
+    }
+}
+
+impl Server {
+    /// GrabPointer; returns the reply status
+    pub fn grab_pointer(
+        &mut self,
+        client: u32,
+        params: GrabParams,
+        time: u32,
+    ) -> Result<u8, X11Error> {
+        self.start_active_grab(Device::Pointer, client, params, time)
+    }
+
+    /// GrabKeyboard; returns the reply status
+    pub fn grab_keyboard(
+        &mut self,
+        client: u32,
+        params: GrabParams,
+        time: u32,
+    ) -> Result<u8, X11Error> {
+        let params = GrabParams {
+            event_mask: KEY_EVENTS,
+            confine_to: Window::NONE,
+            ..params
+        };
+        self.start_active_grab(Device::Keyboard, client, params, time)
+    }
+
+    fn start_active_grab(
+        &mut self,
+        device: Device,
+        client: u32,
+        params: GrabParams,
+        time: u32,
+    ) -> Result<u8, X11Error> {
+        for window in [params.window, params.confine_to] {
+            if window != Window::NONE && !self.window_info.contains_key(&window) {
+                return Err(X11Error::bad_window(0, window, 0));
+            }
+        }
+        let current = *self.grabs.grab(device);
+        if current.is_some_and(|grab| grab.client != client) {
+            return Ok(ALREADY_GRABBED);
+        }
+        if current.is_some_and(|grab| earlier(time, grab.time)) {
+            return Ok(GRAB_INVALID_TIME);
+        }
+        let confined = params.confine_to == Window::NONE || self.is_viewable(params.confine_to);
+        if !self.is_viewable(params.window) || !confined {
+            return Ok(GRAB_NOT_VIEWABLE);
+        }
+        if self
+            .grabs
+            .freeze(device)
+            .client
+            .is_some_and(|frozen_by| frozen_by != client)
+        {
+            return Ok(GRAB_FROZEN);
+        }
+
+        let grab = Grab {
+            client,
+            params,
+            kind: GrabKind::Active,
+            key: 0,
+            time,
+        };
+        if device == Device::Pointer {
+            let from = current.map_or(self.last_pointer_window, |grab| grab.params.window);
+            let state = self.pointer_state();
+            let now = if time == 0 { current_time() } else { time };
+            self.send_crossings(from, params.window, MODE_GRAB, state, now);
+        }
+        *self.grabs.grab(device) = Some(grab);
+        self.freeze_for(&grab, None);
+        Ok(GRAB_SUCCESS)
+    }
+
+    /// UngrabPointer
+    pub fn ungrab_pointer(&mut self, client: u32, time: u32) {
+        self.ungrab(Device::Pointer, client, time);
+    }
+
+    /// UngrabKeyboard
+    pub fn ungrab_keyboard(&mut self, client: u32, time: u32) {
+        self.ungrab(Device::Keyboard, client, time);
+    }
+
+    /// GrabServer
+    pub fn grab_server(&mut self, client: u32) {
+        self.grabs.server = Some(client);
+    }
+
+    /// UngrabServer: only the grabbing client releases the grab
+    pub fn ungrab_server(&mut self, client: u32) {
+        if self.grabs.server == Some(client) {
+            self.grabs.server = None;
+        }
+    }
+
+    /// XTestGrabControl: make the client's requests pass server grabs
+    pub fn set_grab_impervious(&mut self, client: u32, impervious: bool) {
+        if impervious {
+            self.grabs.impervious.insert(client);
+        } else {
+            self.grabs.impervious.remove(&client);
+        }
+    }
+
+    /// Whether another client's server grab holds back this client's requests
+    pub fn blocked_by_server_grab(&self, client: u32) -> bool {
+        self.grabs
+            .server
+            .is_some_and(|holder| holder != client && !self.grabs.impervious.contains(&client))
+    }
+
+    fn ungrab(&mut self, device: Device, client: u32, time: u32) {
+        let held = self
+            .grabs
+            .grab(device)
+            .is_some_and(|grab| grab.client == client && !earlier(time, grab.time));
+        if held {
+            self.end_grab(device);
+            self.release_held_input();
+        }
+    }
+
+    /// ChangeActivePointerGrab: update the event mask of the client's grab
+    pub fn change_active_pointer_grab(&mut self, client: u32, event_mask: u32, time: u32) {
+        if let Some(grab) = self.grabs.pointer.as_mut() {
+            if grab.client == client && grab.kind != GrabKind::Implicit && !earlier(time, grab.time)
+            {
+                grab.params.event_mask = event_mask;
+            }
+        }
+    }
+
+    /// GrabButton; `button` 0 is AnyButton
+    pub fn grab_button(
+        &mut self,
+        client: u32,
+        button: u8,
+        modifiers: u16,
+        params: GrabParams,
+    ) -> Result<(), X11Error> {
+        self.add_passive_grab(Device::Pointer, client, button, modifiers, params)
+    }
+
+    /// GrabKey; `key` 0 is AnyKey
+    pub fn grab_key(
+        &mut self,
+        client: u32,
+        key: u8,
+        modifiers: u16,
+        params: GrabParams,
+    ) -> Result<(), X11Error> {
+        if key != 0 && key < MIN_KEYCODE {
+            return Err(X11Error::bad_value(0, key as u32, 0));
+        }
+        let params = GrabParams {
+            event_mask: KEY_EVENTS,
+            confine_to: Window::NONE,
+            ..params
+        };
+        self.add_passive_grab(Device::Keyboard, client, key, modifiers, params)
+    }
+
+    fn add_passive_grab(
+        &mut self,
+        device: Device,
+        client: u32,
+        detail: u8,
+        modifiers: u16,
+        params: GrabParams,
+    ) -> Result<(), X11Error> {
+        for window in [params.window, params.confine_to] {
+            if window != Window::NONE && !self.window_info.contains_key(&window) {
+                return Err(X11Error::bad_window(0, window, 0));
+            }
+        }
+        if modifiers & !(ANY_MODIFIER | MODIFIER_MASK) != 0 {
+            return Err(X11Error::bad_value(0, modifiers as u32, 0));
+        }
+        let grabs = match device {
+            Device::Pointer => &mut self.grabs.buttons,
+            Device::Keyboard => &mut self.grabs.keys,
+        };
+        let taken = grabs.iter().any(|grab| {
+            grab.client != client
+                && grab.params.window == params.window
+                && grab.overlaps(detail, modifiers)
+        });
+        if taken {
+            return Err(X11Error::bad_access(0, 0));
+        }
+        grabs.retain(|grab| {
+            !(grab.client == client
+                && grab.params.window == params.window
+                && grab.detail == detail
+                && grab.modifiers == modifiers)
+        });
+        grabs.push(PassiveGrab {
+            client,
+            detail,
+            modifiers,
+            params,
+        });
+        Ok(())
+    }
+
+    /// UngrabButton
+    pub fn ungrab_button(&mut self, client: u32, button: u8, modifiers: u16, window: Window) {
+        Self::remove_passive_grab(&mut self.grabs.buttons, client, button, modifiers, window);
+    }
+
+    /// UngrabKey
+    pub fn ungrab_key(&mut self, client: u32, key: u8, modifiers: u16, window: Window) {
+        Self::remove_passive_grab(&mut self.grabs.keys, client, key, modifiers, window);
+    }
+
+    fn remove_passive_grab(
+        grabs: &mut Vec<PassiveGrab>,
+        client: u32,
+        detail: u8,
+        modifiers: u16,
+        window: Window,
+    ) {
+        grabs.retain(|grab| {
+            !(grab.client == client
+                && grab.params.window == window
+                && (detail == 0 || grab.detail == detail)
+                && (modifiers == ANY_MODIFIER || grab.modifiers == modifiers))
+        });
+    }
+
+    /// AllowEvents: release input frozen by the client's grabs
+    pub fn allow_events(&mut self, client: u32, mode: u8) -> Result<(), X11Error> {
+        let (devices, action): (&[Device], _) = match mode {
+            0 => (&[Device::Pointer], AllowAction::Async),
+            1 => (&[Device::Pointer], AllowAction::Sync),
+            2 => (&[Device::Pointer], AllowAction::Replay),
+            3 => (&[Device::Keyboard], AllowAction::Async),
+            4 => (&[Device::Keyboard], AllowAction::Sync),
+            5 => (&[Device::Keyboard], AllowAction::Replay),
+            6 => (&[Device::Pointer, Device::Keyboard], AllowAction::Async),
+            7 => (&[Device::Pointer, Device::Keyboard], AllowAction::Sync),
+            _ => return Err(X11Error::bad_value(0, mode as u32, 0)),
+        };
+
+        for &device in devices {
+            if self.grabs.freeze(device).client != Some(client) {
+                continue;
+            }
+            let grabbed = self
+                .grabs
+                .grab(device)
+                .is_some_and(|grab| grab.client == client);
+            match action {
+                AllowAction::Async => self.thaw(device),
+                AllowAction::Sync if grabbed => {
+                    self.thaw(device);
+                    self.grabs.freeze(device).sync = true;
+                }
+                AllowAction::Replay if grabbed => {
+                    let Some(event) = self.grabs.freeze(device).replay.take() else {
+                        continue;
+                    };
+                    let window = self.grabs.grab(device).map(|grab| grab.params.window);
+                    self.end_grab(device);
+                    // The press is processed again as if the grab never
+                    // matched, skipping passive grabs from its window up
+                    self.grabs.replaying = window;
+                    self.route_root_event(event);
+                    self.grabs.replaying = None;
+                }
+                _ => {}
+            }
+        }
+        self.release_held_input();
+        Ok(())
+    }
+
+    /// Whether an input event must wait for its frozen device
+    pub(super) fn hold_frozen_input(&mut self, event: &BackendEvent) -> bool {
+        let frozen =
+            event_device(event).is_some_and(|device| self.grabs.freeze(device).client.is_some());
+        if frozen {
+            self.grabs.held.push(event.clone());
+        }
+        frozen
+    }
+
+    /// Route input held by frozen devices that have since thawed; input of
+    /// a device that freezes again is held again
+    fn release_held_input(&mut self) {
+        for event in std::mem::take(&mut self.grabs.held) {
+            self.route_root_event(event);
+        }
+    }
+
+    /// Freeze the devices a grab's synchronous modes ask for; `trigger` is
+    /// the event that started a passive grab
+    fn freeze_for(&mut self, grab: &Grab, trigger: Option<&BackendEvent>) {
+        for (device, sync) in [
+            (Device::Pointer, grab.params.pointer_sync),
+            (Device::Keyboard, grab.params.keyboard_sync),
+        ] {
+            if sync {
+                let freeze = self.grabs.freeze(device);
+                freeze.client = Some(grab.client);
+                freeze.replay = trigger.cloned();
+                freeze.sync = false;
+            }
+        }
+    }
+
+    /// After SyncPointer/SyncKeyboard, freeze again once a press or release
+    /// was reported to the grabbing client
+    pub(super) fn refreeze_after_sync(&mut self, event: &BackendEvent) {
+        let Some(device) = event_device(event) else {
+            return;
+        };
+        let Some(grab) = *self.grabs.grab(device) else {
+            return;
+        };
+        let freeze = self.grabs.freeze(device);
+        if freeze.sync {
+            freeze.client = Some(grab.client);
+            freeze.replay = Some(event.clone());
+            freeze.sync = false;
+        }
+    }
+
+    fn thaw(&mut self, device: Device) {
+        *self.grabs.freeze(device) = Freeze::default();
+    }
+
+    /// Release a device's grab, thawing what the grabbing client froze
+    fn end_grab(&mut self, device: Device) {
+        let Some(grab) = self.grabs.grab(device).take() else {
+            return;
+        };
+        for device in [Device::Pointer, Device::Keyboard] {
+            if self.grabs.freeze(device).client == Some(grab.client) {
+                self.thaw(device);
+            }
+        }
+        if device == Device::Pointer && grab.kind != GrabKind::Implicit {
+            let pointer_window = self.last_pointer_window;
+            let state = self.pointer_state();
+            let time = current_time();
+            self.send_crossings(grab.params.window, pointer_window, MODE_UNGRAB, state, time);
+        }
+    }
+
+    /// Activate the passive grab, if any, matching a press in `window`
+    ///
+    /// Grabs closer to the root take precedence. Returns whether a grab
+    /// was activated.
+    pub(super) fn activate_passive_grab(
+        &mut self,
+        window: Window,
+        detail: u8,
+        state: u16,
+        time: u32,
+        event: &BackendEvent,
+    ) -> bool {
+        let Some(device) = event_device(event) else {
+            return false;
+        };
+        let mut path = self.ancestry(window);
+        if let Some(skip) = self.grabs.replaying {
+            if let Some(pos) = path.iter().position(|w| *w == skip) {
+                path.truncate(pos);
+            }
+        }
+        let grabs = match device {
+            Device::Pointer => &self.grabs.buttons,
+            Device::Keyboard => &self.grabs.keys,
+        };
+        let found = path.iter().rev().find_map(|w| {
+            grabs
+                .iter()
+                .find(|grab| grab.params.window == *w && grab.matches(detail, state))
+                .copied()
+        });
+        let Some(passive) = found else {
+            return false;
+        };
+
+        let grab = Grab {
+            client: passive.client,
+            params: passive.params,
+            kind: GrabKind::Passive,
+            key: detail,
+            time,
+        };
+        if device == Device::Pointer {
+            let pointer_window = self.last_pointer_window;
+            self.send_crossings(pointer_window, grab.params.window, MODE_GRAB, state, time);
+        }
+        *self.grabs.grab(device) = Some(grab);
+        self.freeze_for(&grab, Some(event));
+        true
+    }
+
+    /// Give the client that received a button press an implicit grab
+    pub(super) fn start_implicit_grab(&mut self, window: Window, time: u32) {
+        let selection = self.event_selections.get(&window).and_then(|selections| {
+            selections
+                .iter()
+                .find(|(_, mask)| *mask & event_mask::BUTTON_PRESS != 0)
+                .map(|(client, mask)| (*client, *mask))
+        });
+        if let Some((client, mask)) = selection {
+            self.grabs.pointer = Some(Grab {
+                client,
+                params: GrabParams {
+                    window,
+                    owner_events: mask & event_mask::OWNER_GRAB_BUTTON != 0,
+                    event_mask: mask,
+                    pointer_sync: false,
+                    keyboard_sync: false,
+                    confine_to: Window::NONE,
+                },
+                kind: GrabKind::Implicit,
+                key: 0,
+                time,
+            });
+        }
+    }
+
+    /// End a passive or implicit pointer grab once all buttons are up
+    pub(super) fn end_button_grab(&mut self) {
+        let ends = self
+            .grabs
+            .pointer
+            .is_some_and(|grab| grab.kind != GrabKind::Active);
+        if ends {
+            self.end_grab(Device::Pointer);
+            self.release_held_input();
+        }
+    }
+
+    /// End a passive keyboard grab when its key is released
+    pub(super) fn end_key_grab(&mut self, keycode: u8) {
+        let ends = self
+            .grabs
+            .keyboard
+            .is_some_and(|grab| grab.kind == GrabKind::Passive && grab.key == keycode);
+        if ends {
+            self.end_grab(Device::Keyboard);
+            self.release_held_input();
+        }
+    }
+
+    /// Release grabs whose windows are no longer viewable, and drop the
+    /// passive grabs of destroyed windows
+    pub(super) fn release_unviewable_grabs(&mut self) {
+        let windows = &self.window_info;
+        self.grabs
+            .buttons
+            .retain(|grab| windows.contains_key(&grab.params.window));
+        self.grabs
+            .keys
+            .retain(|grab| windows.contains_key(&grab.params.window));
+
+        let mut released = false;
+        for device in [Device::Pointer, Device::Keyboard] {
+            let Some(grab) = *self.grabs.grab(device) else {
+                continue;
+            };
+            let confine_to = grab.params.confine_to;
+            if !self.is_viewable(grab.params.window)
+                || (confine_to != Window::NONE && !self.is_viewable(confine_to))
+            {
+                self.end_grab(device);
+                released = true;
+            }
+        }
+        if released {
+            self.release_held_input();
+        }
+    }
+
+    /// Drop all grabs of a disconnecting client
+    pub(super) fn remove_client_grabs(&mut self, client: u32) {
+        self.ungrab_server(client);
+        self.grabs.impervious.remove(&client);
+        self.grabs.buttons.retain(|grab| grab.client != client);
+        self.grabs.keys.retain(|grab| grab.client != client);
+        for device in [Device::Pointer, Device::Keyboard] {
+            if self
+                .grabs
+                .grab(device)
+                .is_some_and(|grab| grab.client == client)
+            {
+                self.end_grab(device);
+            }
+            if self.grabs.freeze(device).client == Some(client) {
+                self.thaw(device);
+            }
+        }
+        self.release_held_input();
+    }
+}
+
+/// What AllowEvents does to a frozen device
+#[derive(Debug, Clone, Copy)]
+enum AllowAction {
+    Async,
+    Sync,
+    Replay,
+}
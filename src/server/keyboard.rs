@@ -0,0 +1,242 @@
+//! Keyboard mapping
+//!
+//! The layout comes from the backend and may be changed by clients with
+//! ChangeKeyboardMapping and SetModifierMapping. Every change, including
+//! the host switching layouts, is announced to all clients with
+//! MappingNotify, and to XKB clients with MapNotify or NewKeyboardNotify.
+
+use super::Server;
+use crate::backend::KeyboardMapping;
+use crate::protocol::*;
+use std::error::Error;
+
+/// Keycode range announced in the connection setup
+pub const MIN_KEYCODE: u8 = 8;
+pub const MAX_KEYCODE: u8 = 255;
+
+/// MappingNotify request values
+const MAPPING_MODIFIER: u8 = 0;
+const MAPPING_KEYBOARD: u8 = 1;
+
+/// SetModifierMapping status
+const MAPPING_BUSY: u8 = 1;
+
+impl Server {
+    /// The current keyboard mapping, fetched from the backend on first use
+    pub fn keyboard_mapping(&mut self) -> &KeyboardMapping {
+        if self.keyboard_mapping.is_none() {
+            self.keyboard_mapping = Some(self.backend_keyboard_mapping());
+        }
+        self.keyboard_mapping.get_or_insert_with(Default::default)
+    }
+
+    /// Keysyms per keycode and the keysyms of `count` keycodes from
+    /// `first_keycode`; BadValue outside the keycode range
+    pub fn get_keyboard_mapping(
+        &mut self,
+        first_keycode: u8,
+        count: u8,
+    ) -> Result<(u8, Vec<u32>), Box<dyn Error + Send + Sync>> {
+        check_keycode_range(first_keycode, count)?;
+        let mapping = self.keyboard_mapping();
+        let first = first_keycode as u16;
+        let keysyms = (first..first + count as u16)
+            .flat_map(|keycode| mapping.keysyms(keycode as u8).iter().copied())
+            .collect();
+        Ok((mapping.keysyms_per_keycode, keysyms))
+    }
+
+    /// Replace the keysyms of `keysyms.len() / keysyms_per_keycode`
+    /// keycodes from `first_keycode`
+    pub fn change_keyboard_mapping(
+        &mut self,
+        first_keycode: u8,
+        keysyms_per_keycode: u8,
+        keysyms: &[u32],
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        if keysyms_per_keycode == 0 {
+            return Err(X11Error::bad_value(0, 0, 0).into());
+        }
+        let rows: Vec<&[u32]> = keysyms.chunks_exact(keysyms_per_keycode as usize).collect();
+        let count = u8::try_from(rows.len()).map_err(|_| X11Error::bad_length(0, 0))?;
+        check_keycode_range(first_keycode, count)?;
+
+        self.keyboard_mapping();
+        let Some(mapping) = self.keyboard_mapping.as_mut() else {
+            return Ok(());
+        };
+        if keysyms_per_keycode > mapping.keysyms_per_keycode {
+            *mapping = widened(mapping, keysyms_per_keycode);
+        }
+        let per = mapping.keysyms_per_keycode as usize;
+        for (i, row) in rows.iter().enumerate() {
+            let start = (first_keycode - mapping.min_keycode) as usize * per + i * per;
+            let slot = &mut mapping.keysyms[start..start + per];
+            slot.fill(0);
+            slot[..row.len()].copy_from_slice(row);
+        }
+        self.queue_mapping_notify(MAPPING_KEYBOARD, first_keycode, count);
+        Ok(())
+    }
+
+    /// Keycodes per modifier and the 8 modifiers' keycodes
+    pub fn modifier_mapping(&mut self) -> (u8, Vec<u8>) {
+        let mapping = self.keyboard_mapping();
+        (mapping.keycodes_per_modifier, mapping.modifiers.clone())
+    }
+
+    /// Replace the modifier map; returns the reply status, Busy if a key
+    /// of a changed modifier is down
+    pub fn set_modifier_mapping(
+        &mut self,
+        keycodes_per_modifier: u8,
+        keycodes: &[u8],
+    ) -> Result<u8, Box<dyn Error + Send + Sync>> {
+        if let Some(&bad) = keycodes.iter().find(|&&k| k != 0 && k < MIN_KEYCODE) {
+            return Err(X11Error::bad_value(0, bad as u32, 0).into());
+        }
+        let (old_per, old) = self.modifier_mapping();
+        let pressed = self.pressed_keys();
+        let is_down = |keycode: u8| pressed[keycode as usize / 8] & (1 << (keycode % 8)) != 0;
+        let old_rows = old.chunks(old_per.max(1) as usize);
+        let new_rows = keycodes.chunks(keycodes_per_modifier.max(1) as usize);
+        let busy = old_rows.zip(new_rows).any(|(old_row, new_row)| {
+            let changed = old_row
+                .iter()
+                .filter(|&&k| k != 0)
+                .ne(new_row.iter().filter(|&&k| k != 0));
+            changed && old_row.iter().chain(new_row).any(|&k| k != 0 && is_down(k))
+        });
+        if busy {
+            return Ok(MAPPING_BUSY);
+        }
+        if let Some(mapping) = self.keyboard_mapping.as_mut() {
+            mapping.keycodes_per_modifier = keycodes_per_modifier;
+            mapping.modifiers = keycodes.to_vec();
+        }
+        self.queue_mapping_notify(MAPPING_MODIFIER, 0, 0);
+        Ok(0)
+    }
+
+    /// Re-read the backend's layout after it reported a change, and tell
+    /// clients what differs
+    pub(super) fn refresh_keyboard_mapping(&mut self) {
+        let new = self.backend_keyboard_mapping();
+        let old = self.keyboard_mapping.replace(new.clone());
+        let Some(old) = old else {
+            return;
+        };
+        log::info!("Host keyboard layout changed");
+        if old.keysyms != new.keysyms || old.keysyms_per_keycode != new.keysyms_per_keycode {
+            let count = MAX_KEYCODE - MIN_KEYCODE + 1;
+            self.queue_mapping_notify(MAPPING_KEYBOARD, MIN_KEYCODE, count);
+        }
+        if old.modifiers != new.modifiers {
+            self.queue_mapping_notify(MAPPING_MODIFIER, 0, 0);
+        }
+        if old != new {
+            self.queue_xkb_new_keyboard_notify();
+        }
+    }
+
+    /// The backend's mapping, covering exactly the announced keycode range
+    fn backend_keyboard_mapping(&mut self) -> KeyboardMapping {
+        let mapping = match self.backend.keyboard_mapping() {
+            Ok(mapping) => mapping,
+            Err(e) => {
+                log::warn!("Cannot get keyboard mapping from backend: {}", e);
+                KeyboardMapping::default()
+            }
+        };
+        let per = mapping.keysyms_per_keycode.max(1);
+        let keycodes_per_modifier = mapping.keycodes_per_modifier.max(1);
+        let mut modifiers = mapping.modifiers.clone();
+        modifiers.resize(8 * keycodes_per_modifier as usize, 0);
+        KeyboardMapping {
+            min_keycode: MIN_KEYCODE,
+            keysyms_per_keycode: per,
+            keysyms: (MIN_KEYCODE..=MAX_KEYCODE)
+                .flat_map(|keycode| {
+                    let mut row = mapping.keysyms(keycode).to_vec();
+                    row.resize(per as usize, 0);
+                    row
+                })
+                .collect(),
+            keycodes_per_modifier,
+            modifiers,
+        }
+    }
+
+    /// Queue a MappingNotify event for every client, and the matching XKB
+    /// MapNotify for clients selecting it
+    fn queue_mapping_notify(&mut self, request: u8, first_keycode: u8, count: u8) {
+        self.queue_xkb_map_notify(request == MAPPING_KEYBOARD, first_keycode, count);
+        let mut event = vec![0u8; 32];
+        event[0] = 34; // MappingNotify
+        event[4] = request;
+        event[5] = first_keycode;
+        event[6] = count;
+        let clients: Vec<u32> = self.resource_tracker.client_ids().collect();
+        for client in clients {
+            self.client_events
+                .entry(client)
+                .or_default()
+                .push(event.clone());
+        }
+    }
+}
+
+/// BadValue unless `count` keycodes from `first` lie in the keycode range
+fn check_keycode_range(first: u8, count: u8) -> Result<(), Box<dyn Error + Send + Sync>> {
+    if first < MIN_KEYCODE || first as u16 + count as u16 > MAX_KEYCODE as u16 + 1 {
+        return Err(X11Error::bad_value(0, first as u32, 0).into());
+    }
+    Ok(())
+}
+
+/// A mapping with more keysyms per keycode, the new ones NoSymbol
+fn widened(mapping: &KeyboardMapping, keysyms_per_keycode: u8) -> KeyboardMapping {
+    let per = mapping.keysyms_per_keycode as usize;
+    let keysyms = mapping
+        .keysyms
+        .chunks(per.max(1))
+        .flat_map(|row| {
+            let mut row = row.to_vec();
+            row.resize(keysyms_per_keycode as usize, 0);
+            row
+        })
+        .collect();
+    KeyboardMapping {
+        keysyms_per_keycode,
+        keysyms,
+        ..mapping.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::null::NullBackend;
+
+    #[test]
+    fn test_change_keyboard_mapping() {
+        let mut server = Server::new(Box::new(NullBackend::new())).unwrap();
+        let client = server.register_client();
+
+        // Widening to 3 keysyms per keycode keeps the other keycodes
+        server
+            .change_keyboard_mapping(38, 3, &[0x61, 0x41, 0xe6, 0x62, 0x42, 0])
+            .unwrap();
+        let (per, keysyms) = server.get_keyboard_mapping(37, 3).unwrap();
+        assert_eq!(per, 3);
+        assert_eq!(keysyms, [0, 0, 0, 0x61, 0x41, 0xe6, 0x62, 0x42, 0]);
+
+        assert!(server.get_keyboard_mapping(7, 1).is_err());
+        assert!(server.get_keyboard_mapping(250, 7).is_err());
+        assert!(server.change_keyboard_mapping(255, 1, &[1, 2]).is_err());
+
+        let events = server.take_client_events(client);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0][..7], [34, 0, 0, 0, MAPPING_KEYBOARD, 38, 2]);
+    }
+}
@@ -3,29 +3,127 @@ use std::error::Error;
 use std::io::{ErrorKind, Read, Write};
 use std::net::{TcpListener, TcpStream};
 #[cfg(unix)]
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+#[cfg(unix)]
 use std::os::unix::net::{UnixListener, UnixStream};
 use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::Duration;
 
-use super::Server;
+use super::gc::GCValues;
+use super::{Server, WindowPaintValues};
 use crate::protocol::setup::{SetupRequest, SetupResponse};
+use crate::protocol::{ClipOrdering, Rectangle, X11Error};
 
-/// Trait for streams that can set read timeouts
-trait TimeoutStream: Read + Write {
+/// A client connection
+pub trait ClientStream: Read + Write {
     fn set_read_timeout(&self, dur: Option<Duration>) -> std::io::Result<()>;
+
+    /// User and group of the process on the other end of a local socket
+    #[cfg(unix)]
+    fn peer_credentials(&self) -> Option<(u32, u32)> {
+        None
+    }
+
+    /// Take the oldest file descriptor received from the client
+    #[cfg(unix)]
+    fn take_fd(&mut self) -> Option<OwnedFd> {
+        None
+    }
+
+    /// Write data along with a file descriptor
+    #[cfg(unix)]
+    fn write_with_fd(&mut self, _data: &[u8], _fd: &OwnedFd) -> std::io::Result<()> {
+        Err(ErrorKind::Unsupported.into())
+    }
 }
 
-impl TimeoutStream for TcpStream {
+impl ClientStream for TcpStream {
     fn set_read_timeout(&self, dur: Option<Duration>) -> std::io::Result<()> {
         TcpStream::set_read_timeout(self, dur)
     }
 }
 
+/// A Unix socket connection, which can carry file descriptors
 #[cfg(unix)]
-impl TimeoutStream for UnixStream {
+struct UnixClientStream {
+    stream: UnixStream,
+    /// Descriptors received and not yet taken, oldest first
+    fds: std::collections::VecDeque<OwnedFd>,
+}
+
+#[cfg(unix)]
+impl Read for UnixClientStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        use nix::sys::socket::{recvmsg, ControlMessageOwned, MsgFlags};
+        let mut iov = [std::io::IoSliceMut::new(buf)];
+        let mut cmsg = nix::cmsg_space!([std::os::fd::RawFd; 8]);
+        let msg = recvmsg::<()>(
+            self.stream.as_raw_fd(),
+            &mut iov,
+            Some(&mut cmsg),
+            MsgFlags::empty(),
+        )?;
+        for message in msg.cmsgs()? {
+            if let ControlMessageOwned::ScmRights(fds) = message {
+                // SAFETY: the kernel just installed these descriptors for us
+                self.fds.extend(
+                    fds.into_iter()
+                        .map(|fd| unsafe { OwnedFd::from_raw_fd(fd) }),
+                );
+            }
+        }
+        Ok(msg.bytes)
+    }
+}
+
+#[cfg(unix)]
+impl Write for UnixClientStream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+#[cfg(unix)]
+impl ClientStream for UnixClientStream {
     fn set_read_timeout(&self, dur: Option<Duration>) -> std::io::Result<()> {
-        UnixStream::set_read_timeout(self, dur)
+        self.stream.set_read_timeout(dur)
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn peer_credentials(&self) -> Option<(u32, u32)> {
+        use nix::sys::socket::{getsockopt, sockopt::PeerCredentials};
+        let credentials = getsockopt(&self.stream, PeerCredentials).ok()?;
+        Some((credentials.uid(), credentials.gid()))
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    fn peer_credentials(&self) -> Option<(u32, u32)> {
+        let (mut uid, mut gid) = (0, 0);
+        // SAFETY: getpeereid only writes the two IDs
+        let result = unsafe { libc::getpeereid(self.stream.as_raw_fd(), &mut uid, &mut gid) };
+        (result == 0).then_some((uid, gid))
+    }
+
+    fn take_fd(&mut self) -> Option<OwnedFd> {
+        self.fds.pop_front()
+    }
+
+    fn write_with_fd(&mut self, data: &[u8], fd: &OwnedFd) -> std::io::Result<()> {
+        use nix::sys::socket::{sendmsg, ControlMessage, MsgFlags};
+        let fds = [fd.as_raw_fd()];
+        let sent = sendmsg::<()>(
+            self.stream.as_raw_fd(),
+            &[std::io::IoSlice::new(data)],
+            &[ControlMessage::ScmRights(&fds)],
+            MsgFlags::empty(),
+            None,
+        )?;
+        self.stream.write_all(&data[sent..])
     }
 }
 
@@ -80,6 +178,10 @@ pub fn start_unix_listener(
         match stream {
             Ok(stream) => {
                 let server = Arc::clone(&server);
+                let stream = UnixClientStream {
+                    stream,
+                    fds: Default::default(),
+                };
                 thread::spawn(move || {
                     if let Err(e) = handle_client(stream, server) {
                         log::error!("Unix client error: {}", e);
@@ -95,7 +197,7 @@ pub fn start_unix_listener(
     Ok(())
 }
 
-fn handle_client<S: TimeoutStream>(
+fn handle_client<S: ClientStream>(
     mut stream: S,
     server: Arc<Mutex<Server>>,
 ) -> Result<(), Box<dyn Error + Send + Sync>> {
@@ -105,20 +207,25 @@ fn handle_client<S: TimeoutStream>(
     let setup_request = SetupRequest::parse(&mut stream)?;
     log::debug!("Setup request: {:?}", setup_request);
 
+    // Register client; its ID determines the resource ID range it is given
+    let client_id = {
+        let mut server = server.lock().unwrap();
+        let client_id = server.register_client();
+        #[cfg(unix)]
+        if let Some((uid, gid)) = stream.peer_credentials() {
+            server.set_peer_credentials(client_id, uid, gid);
+        }
+        client_id
+    };
+
     // Send setup response
     let setup_response = {
         let server = server.lock().unwrap();
-        create_setup_response(&server)
+        create_setup_response(&server, client_id)
     };
 
     send_setup_response(&mut stream, &setup_response)?;
-    log::info!("Client connected successfully");
-
-    // Register client
-    let client_id = {
-        let mut server = server.lock().unwrap();
-        server.register_client()
-    };
+    log::info!("Client {} connected successfully", client_id);
 
     // Sequence number counter - starts at 0, increments with each request
     // Note: The first request after connection has sequence 1
@@ -133,27 +240,31 @@ fn handle_client<S: TimeoutStream>(
     fn send_pending_events<W: Write>(
         stream: &mut W,
         server: &Arc<Mutex<Server>>,
+        client_id: u32,
         client_sequence: u16,
     ) -> std::io::Result<()> {
-        // Poll for new events and send any pending events
-        let events_by_window = {
+        // Poll for new events and send any pending events, both those queued
+        // for this client's selections and those on windows without selections
+        let (client_events, events_by_window) = {
             let mut server = server.lock().unwrap();
             server.poll_and_queue_events();
-            server.take_all_pending_events()
+            (
+                server.take_client_events(client_id),
+                server.take_all_pending_events(),
+            )
         };
 
         // Flatten events from all windows and send them
         // Patch the sequence number in each event (bytes 2-3) to match the client's sequence
-        for (_window, events) in events_by_window {
-            for mut event_data in events {
-                // X11 events are 32 bytes, sequence number is at bytes 2-3 (little-endian)
-                if event_data.len() >= 4 {
-                    let seq_bytes = client_sequence.to_le_bytes();
-                    event_data[2] = seq_bytes[0];
-                    event_data[3] = seq_bytes[1];
-                }
-                stream.write_all(&event_data)?;
+        let window_events = events_by_window.into_values().flatten();
+        for mut event_data in client_events.into_iter().chain(window_events) {
+            // X11 events are 32 bytes, sequence number is at bytes 2-3 (little-endian)
+            if event_data.len() >= 4 {
+                let seq_bytes = client_sequence.to_le_bytes();
+                event_data[2] = seq_bytes[0];
+                event_data[3] = seq_bytes[1];
             }
+            stream.write_all(&event_data)?;
         }
         Ok(())
     }
@@ -161,7 +272,7 @@ fn handle_client<S: TimeoutStream>(
     // Handle requests in a loop
     loop {
         // First, poll and send any pending events to this client
-        if let Err(e) = send_pending_events(&mut stream, &server, sequence_number) {
+        if let Err(e) = send_pending_events(&mut stream, &server, client_id, sequence_number) {
             log::warn!("Client {} event send error: {}", client_id, e);
             break;
         }
@@ -218,11 +329,16 @@ fn handle_client<S: TimeoutStream>(
             }
         }
 
+        // While another client holds a server grab, this client's requests wait
+        while server.lock().unwrap().blocked_by_server_grab(client_id) {
+            std::thread::sleep(Duration::from_millis(5));
+        }
+
         // Handle X11 protocol requests - wrap in closure to handle errors gracefully
         let handle_result: Result<(), Box<dyn Error + Send + Sync>> = (|| {
             match opcode {
                 1 => {
-                    handle_create_window(&mut stream, &header, &request_data, &server)?;
+                    handle_create_window(&mut stream, &header, &request_data, &server, client_id)?;
                     // Track window for cleanup on disconnect
                     if request_data.len() >= 4 {
                         let wid = u32::from_le_bytes([
@@ -236,17 +352,37 @@ fn handle_client<S: TimeoutStream>(
                         server.track_window(client_id, wid);
                     }
                 }
-                2 => handle_change_window_attributes(&mut stream, &header, &request_data, &server)?,
-                3 => handle_get_window_attributes(&mut stream, &header, &request_data, &server)?,
+                2 => handle_change_window_attributes(
+                    &mut stream,
+                    &header,
+                    &request_data,
+                    &server,
+                    client_id,
+                )?,
+                3 => handle_get_window_attributes(
+                    &mut stream,
+                    &header,
+                    &request_data,
+                    &server,
+                    client_id,
+                )?,
                 4 => handle_destroy_window(&mut stream, &header, &request_data, &server)?,
                 5 => handle_destroy_subwindows(&mut stream, &header, &request_data, &server)?,
                 6 => handle_change_save_set(&mut stream, &header, &request_data, &server)?,
                 7 => handle_reparent_window(&mut stream, &header, &request_data, &server)?,
-                8 => handle_map_window(&mut stream, &header, &request_data, &server)?,
-                9 => handle_map_subwindows(&mut stream, &header, &request_data, &server)?,
+                8 => handle_map_window(&mut stream, &header, &request_data, &server, client_id)?,
+                9 => {
+                    handle_map_subwindows(&mut stream, &header, &request_data, &server, client_id)?
+                }
                 10 => handle_unmap_window(&mut stream, &header, &request_data, &server)?,
                 11 => handle_unmap_subwindows(&mut stream, &header, &request_data, &server)?,
-                12 => handle_configure_window(&mut stream, &header, &request_data, &server)?,
+                12 => handle_configure_window(
+                    &mut stream,
+                    &header,
+                    &request_data,
+                    &server,
+                    client_id,
+                )?,
                 13 => handle_circulate_window(&mut stream, &header, &request_data, &server)?,
                 14 => handle_get_geometry(&mut stream, &header, &request_data, &server)?,
                 15 => handle_query_tree(&mut stream, &header, &request_data, &server)?,
@@ -260,20 +396,32 @@ fn handle_client<S: TimeoutStream>(
                 23 => handle_get_selection_owner(&mut stream, &header, &request_data, &server)?,
                 24 => handle_convert_selection(&mut stream, &header, &request_data, &server)?,
                 25 => handle_send_event(&mut stream, &header, &request_data, &server)?,
-                26 => handle_grab_pointer(&mut stream, &header, &request_data, &server)?,
-                27 => handle_ungrab_pointer(&mut stream, &header, &request_data, &server)?,
-                28 => handle_grab_server(&mut stream, &header, &request_data, &server)?,
-                29 => handle_ungrab_server(&mut stream, &header, &request_data, &server)?,
-                30 => {
-                    handle_change_active_pointer_grab(&mut stream, &header, &request_data, &server)?
+                26 => handle_grab_pointer(&mut stream, &header, &request_data, &server, client_id)?,
+                27 => {
+                    handle_ungrab_pointer(&mut stream, &header, &request_data, &server, client_id)?
+                }
+                28 => handle_grab_server(&server, client_id),
+                29 => handle_ungrab_server(&server, client_id),
+                30 => handle_change_active_pointer_grab(
+                    &mut stream,
+                    &header,
+                    &request_data,
+                    &server,
+                    client_id,
+                )?,
+                31 => handle_grab_button(&mut stream, &header, &request_data, &server, client_id)?,
+                32 => {
+                    handle_ungrab_button(&mut stream, &header, &request_data, &server, client_id)?
                 }
-                31 => handle_grab_button(&mut stream, &header, &request_data, &server)?,
-                32 => handle_ungrab_button(&mut stream, &header, &request_data, &server)?,
-                33 => handle_grab_keyboard(&mut stream, &header, &request_data, &server)?,
-                34 => handle_ungrab_keyboard(&mut stream, &header, &request_data, &server)?,
-                35 => handle_allow_events(&mut stream, &header, &request_data, &server)?,
-                36 => handle_grab_key(&mut stream, &header, &request_data, &server)?,
-                37 => handle_ungrab_key(&mut stream, &header, &request_data, &server)?,
+                33 => {
+                    handle_grab_keyboard(&mut stream, &header, &request_data, &server, client_id)?
+                }
+                34 => {
+                    handle_ungrab_keyboard(&mut stream, &header, &request_data, &server, client_id)?
+                }
+                35 => handle_allow_events(&mut stream, &header, &request_data, &server, client_id)?,
+                36 => handle_grab_key(&mut stream, &header, &request_data, &server, client_id)?,
+                37 => handle_ungrab_key(&mut stream, &header, &request_data, &server, client_id)?,
                 38 => handle_query_pointer(&mut stream, &header, &request_data, &server)?,
                 39 => handle_get_motion_events(&mut stream, &header, &request_data, &server)?,
                 40 => handle_translate_coordinates(&mut stream, &header, &request_data, &server)?,
@@ -436,6 +584,7 @@ fn handle_client<S: TimeoutStream>(
                         &request_data,
                         opcode,
                         &server,
+                        client_id,
                     )?;
                 }
                 _ => {
@@ -445,8 +594,23 @@ fn handle_client<S: TimeoutStream>(
             Ok(())
         })();
 
-        // If handler returned an error, log it and disconnect the client
+        // Protocol errors are reported to the client; anything else
+        // disconnects it
         if let Err(e) = handle_result {
+            if let Some(x11_error) = e.downcast_ref::<crate::protocol::X11Error>() {
+                let mut x11_error = x11_error.clone();
+                x11_error.sequence = sequence_number;
+                if x11_error.major_opcode == 0 {
+                    x11_error.major_opcode = opcode;
+                }
+                log::debug!("Client {}: {}", client_id, x11_error);
+                let mut error = [0u8; 32];
+                x11_error.encode(&mut error);
+                if stream.write_all(&error).is_err() {
+                    break;
+                }
+                continue;
+            }
             log::warn!(
                 "Client {} handler error (opcode {}): {}",
                 client_id,
@@ -492,7 +656,9 @@ fn handle_client<S: TimeoutStream>(
                 }
                 crate::resources::CleanupRequest::FreeCursor(cursor) => {
                     log::debug!("Cleanup: freeing cursor 0x{:x}", cursor);
-                    // Cursor cleanup is internal
+                    if let Err(e) = server.free_cursor(cursor) {
+                        log::warn!("Cleanup: failed to free cursor 0x{:x}: {}", cursor, e);
+                    }
                 }
                 crate::resources::CleanupRequest::FreeColormap(colormap) => {
                     log::debug!("Cleanup: freeing colormap 0x{:x}", colormap.id().0);
@@ -505,7 +671,7 @@ fn handle_client<S: TimeoutStream>(
     Ok(())
 }
 
-fn create_setup_response(server: &Server) -> SetupResponse {
+fn create_setup_response(server: &Server, client_id: u32) -> SetupResponse {
     use crate::protocol::*;
 
     // Get screen info from the backend
@@ -515,16 +681,16 @@ fn create_setup_response(server: &Server) -> SetupResponse {
         protocol_major_version: 11,
         protocol_minor_version: 0,
         release_number: 1,
-        resource_id_base: 0x02000000,
-        resource_id_mask: 0x001FFFFF,
+        resource_id_base: super::resource_id_base(client_id),
+        resource_id_mask: super::RESOURCE_ID_MASK,
         motion_buffer_size: 256,
         maximum_request_length: 65535,
         image_byte_order: ByteOrder::LSBFirst,
         bitmap_format_bit_order: ByteOrder::LSBFirst,
         bitmap_format_scanline_unit: 32,
         bitmap_format_scanline_pad: 32,
-        min_keycode: 8,
-        max_keycode: 255,
+        min_keycode: super::keyboard::MIN_KEYCODE,
+        max_keycode: super::keyboard::MAX_KEYCODE,
         vendor: "X11Anywhere".to_string(),
         pixmap_formats: vec![Format {
             depth: 24,
@@ -580,6 +746,7 @@ fn handle_create_window<S: Write>(
     header: &[u8],
     data: &[u8],
     server: &Arc<Mutex<Server>>,
+    client_id: u32,
 ) -> Result<(), Box<dyn Error + Send + Sync>> {
     use crate::protocol::WindowClass;
 
@@ -618,7 +785,8 @@ fn handle_create_window<S: Write>(
     // 4: bit-gravity, 5: win-gravity, 6: backing-store, 7: backing-planes,
     // 8: backing-pixel, 9: override-redirect, 10: save-under, 11: event-mask,
     // 12: do-not-propagate-mask, 13: colormap, 14: cursor
-    let mut background_pixel = None;
+    let mut paint = WindowPaintValues::default();
+    let mut override_redirect = false;
     let mut event_mask = 0u32;
     let mut offset = 28;
 
@@ -636,19 +804,19 @@ fn handle_create_window<S: Write>(
 
     // Bit 0: background-pixmap
     if value_mask & 0x00000001 != 0 {
-        read_u32(&mut offset); // Skip
+        paint.background_pixmap = read_u32(&mut offset);
     }
     // Bit 1: background-pixel
     if value_mask & 0x00000002 != 0 {
-        background_pixel = read_u32(&mut offset);
+        paint.background_pixel = read_u32(&mut offset);
     }
     // Bit 2: border-pixmap
     if value_mask & 0x00000004 != 0 {
-        read_u32(&mut offset); // Skip
+        paint.border_pixmap = read_u32(&mut offset);
     }
     // Bit 3: border-pixel
     if value_mask & 0x00000008 != 0 {
-        read_u32(&mut offset); // Skip
+        paint.border_pixel = read_u32(&mut offset);
     }
     // Bit 4: bit-gravity
     if value_mask & 0x00000010 != 0 {
@@ -672,7 +840,7 @@ fn handle_create_window<S: Write>(
     }
     // Bit 9: override-redirect
     if value_mask & 0x00000200 != 0 {
-        read_u32(&mut offset); // Skip
+        override_redirect = read_u32(&mut offset).unwrap_or(0) != 0;
     }
     // Bit 10: save-under
     if value_mask & 0x00000400 != 0 {
@@ -682,6 +850,22 @@ fn handle_create_window<S: Write>(
     if value_mask & 0x00000800 != 0 {
         event_mask = read_u32(&mut offset).unwrap_or(0);
     }
+    // Bit 12: do-not-propagate-mask
+    let do_not_propagate = if value_mask & 0x00001000 != 0 {
+        read_u32(&mut offset)
+    } else {
+        None
+    };
+    // Bit 13: colormap
+    if value_mask & 0x00002000 != 0 {
+        read_u32(&mut offset); // Skip
+    }
+    // Bit 14: cursor
+    let cursor = if value_mask & 0x00004000 != 0 {
+        read_u32(&mut offset)
+    } else {
+        None
+    };
 
     log::debug!(
         "CreateWindow value_mask=0x{:x}, event_mask=0x{:x}",
@@ -707,18 +891,34 @@ fn handle_create_window<S: Write>(
         border_width,
         window_class,
         crate::protocol::VisualID::new(visual),
-        background_pixel,
+        &paint,
         event_mask,
+        override_redirect,
     )?;
+    server.select_window_events(crate::protocol::Window::new(wid), client_id, event_mask);
+    if let Some(mask) = do_not_propagate {
+        server.set_do_not_propagate_mask(crate::protocol::Window::new(wid), mask as u16);
+    }
+    if cursor.is_some() {
+        server.change_window_attributes(
+            crate::protocol::Window::new(wid),
+            client_id,
+            &WindowPaintValues::default(),
+            None,
+            None,
+            cursor,
+        )?;
+    }
 
     Ok(())
 }
 
 fn handle_map_window<S: Write>(
-    stream: &mut S,
+    _stream: &mut S,
     header: &[u8],
     data: &[u8],
     server: &Arc<Mutex<Server>>,
+    client_id: u32,
 ) -> Result<(), Box<dyn Error + Send + Sync>> {
     // Parse MapWindow request: window(4)
     if data.len() < 4 {
@@ -730,55 +930,19 @@ fn handle_map_window<S: Write>(
     let sequence = u16::from_le_bytes([header[2], header[3]]);
     log::debug!("MapWindow: window=0x{:x}, seq={}", window_id, sequence);
 
-    let window = crate::protocol::Window::new(window_id);
-
-    // Get window info before mapping (need dimensions for Expose event)
-    let (width, height, event_mask) = {
-        let server = server.lock().unwrap();
-        if let Some(info) = server.get_window_info(window) {
-            (info.width, info.height, info.event_mask)
-        } else {
-            // Default to reasonable size if not found
-            (100, 100, 0)
-        }
-    };
-
-    // Map the window
-    {
-        let mut server = server.lock().unwrap();
-        server.map_window(window)?;
-    }
-
-    // Send Expose event if client requested ExposureMask (0x8000 = bit 15)
-    const EXPOSURE_MASK: u32 = 0x8000;
-    if event_mask & EXPOSURE_MASK != 0 {
-        let encoder =
-            crate::protocol::encoder::ProtocolEncoder::new(crate::protocol::ByteOrder::LSBFirst);
-        let expose_event = encoder.encode_expose_event(
-            sequence, // Use current sequence number
-            window, 0,      // x - expose entire window
-            0,      // y
-            width,  // width
-            height, // height
-            0,      // count - no more Expose events following
-        );
-        stream.write_all(&expose_event)?;
-        log::debug!(
-            "Sent Expose event for window 0x{:x} ({}x{})",
-            window_id,
-            width,
-            height
-        );
-    }
+    // The server queues MapNotify/Expose (or MapRequest to the window manager)
+    let mut server = server.lock().unwrap();
+    server.map_window(crate::protocol::Window::new(window_id), client_id)?;
 
     Ok(())
 }
 
 fn handle_map_subwindows<S: Write>(
-    stream: &mut S,
+    _stream: &mut S,
     header: &[u8],
     data: &[u8],
     server: &Arc<Mutex<Server>>,
+    client_id: u32,
 ) -> Result<(), Box<dyn Error + Send + Sync>> {
     // Parse MapSubwindows request: window(4)
     if data.len() < 4 {
@@ -790,51 +954,10 @@ fn handle_map_subwindows<S: Write>(
     let sequence = u16::from_le_bytes([header[2], header[3]]);
     log::debug!("MapSubwindows: parent=0x{:x}, seq={}", parent_id, sequence);
 
-    let parent = crate::protocol::Window::new(parent_id);
-
-    // Get all children and their info
-    let children_info: Vec<(crate::protocol::Window, u16, u16, u32)> = {
-        let server = server.lock().unwrap();
-        server
-            .get_children(parent)
-            .iter()
-            .filter_map(|&child| {
-                server
-                    .get_window_info(child)
-                    .map(|info| (child, info.width, info.height, info.event_mask))
-            })
-            .collect()
-    };
-
-    // Map each child window and send Expose events if needed
-    for (child, width, height, event_mask) in children_info {
-        // Map the child window
-        {
-            let mut server = server.lock().unwrap();
-            server.map_window(child)?;
-        }
-
-        // Send Expose event if child requested ExposureMask (0x8000 = bit 15)
-        const EXPOSURE_MASK: u32 = 0x8000;
-        if event_mask & EXPOSURE_MASK != 0 {
-            let encoder = crate::protocol::encoder::ProtocolEncoder::new(
-                crate::protocol::ByteOrder::LSBFirst,
-            );
-            let expose_event = encoder.encode_expose_event(
-                sequence, child, 0,      // x - expose entire window
-                0,      // y
-                width,  // width
-                height, // height
-                0,      // count - no more Expose events following
-            );
-            stream.write_all(&expose_event)?;
-            log::debug!(
-                "Sent Expose event for child window 0x{:x} ({}x{})",
-                child.id().get(),
-                width,
-                height
-            );
-        }
+    let mut server = server.lock().unwrap();
+    let children = server.get_children(crate::protocol::Window::new(parent_id));
+    for child in children {
+        server.map_window(child, client_id)?;
     }
 
     Ok(())
@@ -1055,6 +1178,7 @@ fn handle_configure_window<S: Write>(
     header: &[u8],
     data: &[u8],
     server: &Arc<Mutex<Server>>,
+    client_id: u32,
 ) -> Result<(), Box<dyn Error + Send + Sync>> {
     // Parse ConfigureWindow request: window(4), value-mask(2), pad(2), values(...)
     if data.len() < 4 {
@@ -1078,36 +1202,40 @@ fn handle_configure_window<S: Write>(
         value_mask
     );
 
-    // Parse values based on mask
-    let mut offset = 8; // Skip window(4), mask(2), pad(2)
-    let mut x: Option<i16> = None;
-    let mut y: Option<i16> = None;
-    let mut width: Option<u16> = None;
-    let mut height: Option<u16> = None;
-
-    // Bit 0: x
-    if value_mask & 0x0001 != 0 && offset + 4 <= data.len() {
-        x = Some(i16::from_le_bytes([data[offset], data[offset + 1]]));
-        offset += 4; // Values are padded to 4 bytes
-    }
-    // Bit 1: y
-    if value_mask & 0x0002 != 0 && offset + 4 <= data.len() {
-        y = Some(i16::from_le_bytes([data[offset], data[offset + 1]]));
-        offset += 4;
-    }
-    // Bit 2: width
-    if value_mask & 0x0004 != 0 && offset + 4 <= data.len() {
-        width = Some(u16::from_le_bytes([data[offset], data[offset + 1]]));
-        offset += 4;
-    }
-    // Bit 3: height
-    if value_mask & 0x0008 != 0 && offset + 4 <= data.len() {
-        height = Some(u16::from_le_bytes([data[offset], data[offset + 1]]));
-        // offset += 4;
+    // Parse values based on mask; each value is padded to 4 bytes
+    // Bits: 0 x, 1 y, 2 width, 3 height, 4 border-width, 5 sibling, 6 stack-mode
+    let mut values = data.get(8..).unwrap_or(&[]).chunks_exact(4);
+    let mut changes = crate::server::WindowChanges::default();
+    for bit in 0..7 {
+        if value_mask & (1 << bit) == 0 {
+            continue;
+        }
+        let Some(value) = values.next() else {
+            break;
+        };
+        let value = u32::from_le_bytes([value[0], value[1], value[2], value[3]]);
+        match bit {
+            0 => changes.x = Some(value as i16),
+            1 => changes.y = Some(value as i16),
+            2 => changes.width = Some(value as u16),
+            3 => changes.height = Some(value as u16),
+            4 => changes.border_width = Some(value as u16),
+            5 => changes.sibling = Some(crate::protocol::Window::new(value)),
+            _ => {
+                use crate::protocol::StackMode;
+                changes.stack_mode = Some(match value {
+                    1 => StackMode::Below,
+                    2 => StackMode::TopIf,
+                    3 => StackMode::BottomIf,
+                    4 => StackMode::Opposite,
+                    _ => StackMode::Above,
+                });
+            }
+        }
     }
 
     let mut server = server.lock().unwrap();
-    server.configure_window(crate::protocol::Window::new(window), x, y, width, height)?;
+    server.configure_window(crate::protocol::Window::new(window), client_id, changes)?;
 
     Ok(())
 }
@@ -1135,41 +1263,7 @@ fn handle_create_gc<S: Write>(
         value_mask
     );
 
-    // Parse value list
-    let mut foreground = None;
-    let mut background = None;
-    let mut offset = 12;
-
-    // Function (bit 0)
-    if value_mask & 0x00000001 != 0 {
-        offset += 4;
-    }
-
-    // Plane mask (bit 1)
-    if value_mask & 0x00000002 != 0 {
-        offset += 4;
-    }
-
-    // Foreground (bit 2)
-    if value_mask & 0x00000004 != 0 && offset + 4 <= data.len() {
-        foreground = Some(u32::from_le_bytes([
-            data[offset],
-            data[offset + 1],
-            data[offset + 2],
-            data[offset + 3],
-        ]));
-        offset += 4;
-    }
-
-    // Background (bit 3)
-    if value_mask & 0x00000008 != 0 && offset + 4 <= data.len() {
-        background = Some(u32::from_le_bytes([
-            data[offset],
-            data[offset + 1],
-            data[offset + 2],
-            data[offset + 3],
-        ]));
-    }
+    let values = GCValues::parse(value_mask, &data[12..]);
 
     let mut server = server.lock().unwrap();
     // Resolve the drawable - could be a window or pixmap
@@ -1177,8 +1271,7 @@ fn handle_create_gc<S: Write>(
     server.create_gc(
         crate::protocol::GContext::new(cid),
         resolved_drawable,
-        foreground,
-        background,
+        &values,
     )?;
 
     Ok(())
@@ -1201,43 +1294,9 @@ fn handle_change_gc<S: Write>(
 
     log::debug!("ChangeGC: gc=0x{:x}, mask=0x{:x}", gc, value_mask);
 
-    // Parse value list
-    let mut foreground = None;
-    let mut background = None;
-    let mut offset = 8;
-
-    // Function (bit 0)
-    if value_mask & 0x00000001 != 0 {
-        offset += 4;
-    }
-
-    // Plane mask (bit 1)
-    if value_mask & 0x00000002 != 0 {
-        offset += 4;
-    }
+    let values = GCValues::parse(value_mask, &data[8..]);
 
-    // Foreground (bit 2)
-    if value_mask & 0x00000004 != 0 && offset + 4 <= data.len() {
-        foreground = Some(u32::from_le_bytes([
-            data[offset],
-            data[offset + 1],
-            data[offset + 2],
-            data[offset + 3],
-        ]));
-        offset += 4;
-    }
-
-    // Background (bit 3)
-    if value_mask & 0x00000008 != 0 && offset + 4 <= data.len() {
-        background = Some(u32::from_le_bytes([
-            data[offset],
-            data[offset + 1],
-            data[offset + 2],
-            data[offset + 3],
-        ]));
-    }
-
-    if let Some(fg) = foreground {
+    if let Some(fg) = values.foreground {
         log::debug!(
             "ChangeGC: setting foreground=0x{:08x} (R={}, G={}, B={})",
             fg,
@@ -1248,7 +1307,7 @@ fn handle_change_gc<S: Write>(
     }
 
     let mut server = server.lock().unwrap();
-    server.change_gc(crate::protocol::GContext::new(gc), foreground, background)?;
+    server.change_gc(crate::protocol::GContext::new(gc), &values)?;
 
     Ok(())
 }
@@ -1896,9 +1955,7 @@ fn handle_open_font<S: Write>(
 
     // Store the font reference in the server
     let mut server = server.lock().unwrap();
-    server.open_font(font_id, &font_name);
-
-    Ok(())
+    server.open_font(font_id, &font_name)
 }
 
 fn handle_close_font<S: Write>(
@@ -1940,22 +1997,21 @@ fn handle_query_font<S: Write>(
     // Get the sequence number from header
     let sequence = u16::from_le_bytes([header[2], header[3]]);
 
-    let server = server.lock().unwrap();
-
-    // Get font info
-    let (ascent, descent, char_width) = if let Some(font_info) = server.query_font(font_id) {
-        (font_info.ascent, font_info.descent, font_info.char_width)
-    } else {
-        // Return default values if font not found
-        log::warn!("QueryFont: font 0x{:x} not found, using defaults", font_id);
-        (12, 4, 8)
+    let mut server = server.lock().unwrap();
+    let Some(font_info) = server.query_font(font_id) else {
+        return Err(X11Error::bad_font(0, font_id, 0).into());
     };
+    let info = server.font_reply_info(&font_info);
+    let char_infos = font_info
+        .font
+        .as_ref()
+        .map(|font| font.char_infos())
+        .unwrap_or_default();
 
     // Encode and send reply
     let encoder =
         crate::protocol::encoder::ProtocolEncoder::new(crate::protocol::ByteOrder::LSBFirst);
-    let reply =
-        encoder.encode_query_font_reply(sequence, ascent, descent, char_width, ascent + descent);
+    let reply = encoder.encode_query_font_reply(sequence, &info, &char_infos);
 
     stream.write_all(&reply)?;
 
@@ -1966,50 +2022,38 @@ fn handle_query_text_extents<S: Write>(
     stream: &mut S,
     header: &[u8],
     data: &[u8],
-    _server: &Arc<Mutex<Server>>,
+    server: &Arc<Mutex<Server>>,
 ) -> Result<(), Box<dyn Error + Send + Sync>> {
-    // Parse QueryTextExtents request: font(4), string(n)
-    // Note: header[1] contains odd_length flag
+    // Parse QueryTextExtents request: odd_length(1 in header), font(4),
+    // string(CHAR2B each)
     if data.len() < 4 {
         log::warn!("QueryTextExtents request too short");
         return Ok(());
     }
 
     let font_or_gc = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
-    let string_bytes = &data[4..];
-    // String is CHAR2B (2 bytes per character)
-    let string_len = string_bytes.len() / 2;
+    let mut codes = char2b_codes(&data[4..]);
+    // With odd_length set, the last character is padding
+    if header[1] != 0 {
+        codes.pop();
+    }
 
     log::debug!(
         "QueryTextExtents: font=0x{:x}, string_len={}",
         font_or_gc,
-        string_len
+        codes.len()
     );
 
     // Get the sequence number from header
     let sequence = u16::from_le_bytes([header[2], header[3]]);
 
-    // Return approximate text metrics (assuming 8-pixel wide monospace font)
-    let char_width = 8i16;
-    let ascent = 10i16;
-    let descent = 3i16;
-    let overall_width = (string_len as i32) * (char_width as i32);
+    let Some(extents) = server.lock().unwrap().text_extents(font_or_gc, &codes) else {
+        return Err(X11Error::bad_font(0, font_or_gc, 0).into());
+    };
 
-    // Reply: reply(1), draw_direction(1), sequence(2), length(4),
-    //        font_ascent(2), font_descent(2), overall_ascent(2), overall_descent(2),
-    //        overall_width(4), overall_left(4), overall_right(4)
-    let mut reply = vec![0u8; 32];
-    reply[0] = 1; // Reply
-    reply[1] = 0; // draw_direction = LeftToRight
-    reply[2..4].copy_from_slice(&sequence.to_le_bytes());
-    reply[4..8].copy_from_slice(&0u32.to_le_bytes()); // length = 0
-    reply[8..10].copy_from_slice(&ascent.to_le_bytes()); // font_ascent
-    reply[10..12].copy_from_slice(&descent.to_le_bytes()); // font_descent
-    reply[12..14].copy_from_slice(&ascent.to_le_bytes()); // overall_ascent
-    reply[14..16].copy_from_slice(&descent.to_le_bytes()); // overall_descent
-    reply[16..20].copy_from_slice(&overall_width.to_le_bytes()); // overall_width
-    reply[20..24].copy_from_slice(&0i32.to_le_bytes()); // overall_left
-    reply[24..28].copy_from_slice(&overall_width.to_le_bytes()); // overall_right
+    let encoder =
+        crate::protocol::encoder::ProtocolEncoder::new(crate::protocol::ByteOrder::LSBFirst);
+    let reply = encoder.encode_query_text_extents_reply(sequence, &extents);
 
     stream.write_all(&reply)?;
 
@@ -2038,7 +2082,7 @@ fn handle_list_fonts<S: Write>(
     // Get the sequence number from header
     let sequence = u16::from_le_bytes([header[2], header[3]]);
 
-    let server = server.lock().unwrap();
+    let mut server = server.lock().unwrap();
     let font_names = server.list_fonts(&pattern, max_names);
 
     log::debug!("ListFonts: returning {} fonts", font_names.len());
@@ -2079,23 +2123,31 @@ fn handle_list_fonts_with_info<S: Write>(
     // Get the sequence number from header
     let sequence = u16::from_le_bytes([header[2], header[3]]);
 
-    // Get matching fonts
-    let font_names = {
-        let server = server.lock().unwrap();
-        server.list_fonts(&pattern, max_names)
+    // Get matching fonts with their metrics
+    let fonts: Vec<_> = {
+        let mut server = server.lock().unwrap();
+        let names = server.list_fonts(&pattern, max_names);
+        names
+            .into_iter()
+            .filter_map(|name| {
+                let font_info = server.font_info_by_name(&name)?;
+                Some((name, server.font_reply_info(&font_info)))
+            })
+            .collect()
     };
 
-    log::debug!("ListFontsWithInfo: found {} fonts", font_names.len());
+    log::debug!("ListFontsWithInfo: found {} fonts", fonts.len());
 
     let encoder =
         crate::protocol::encoder::ProtocolEncoder::new(crate::protocol::ByteOrder::LSBFirst);
 
     // Send a reply for each matching font
-    for (idx, font_name) in font_names.iter().enumerate() {
-        let replies_remaining = font_names.len() - idx - 1;
+    for (idx, (font_name, info)) in fonts.iter().enumerate() {
+        let replies_remaining = fonts.len() - idx - 1;
         let reply = encoder.encode_list_fonts_with_info_reply(
             sequence,
             font_name,
+            info,
             replies_remaining as u32,
         );
         stream.write_all(&reply)?;
@@ -2283,6 +2335,7 @@ fn handle_change_window_attributes<S: Write>(
     _header: &[u8],
     data: &[u8],
     server: &Arc<Mutex<Server>>,
+    client_id: u32,
 ) -> Result<(), Box<dyn Error + Send + Sync>> {
     // Parse ChangeWindowAttributes request
     // Format: window(4), value-mask(4), value-list(...)
@@ -2306,6 +2359,8 @@ fn handle_change_window_attributes<S: Write>(
     // 4: bit-gravity, 5: win-gravity, 6: backing-store, 7: backing-planes,
     // 8: backing-pixel, 9: override-redirect, 10: save-under, 11: event-mask,
     // 12: do-not-propagate-mask, 13: colormap, 14: cursor
+    let mut paint = WindowPaintValues::default();
+    let mut override_redirect = None;
     let mut event_mask = None;
     let mut cursor = None;
     let mut offset = 8;
@@ -2324,19 +2379,19 @@ fn handle_change_window_attributes<S: Write>(
 
     // Bit 0: background-pixmap
     if value_mask & 0x00000001 != 0 {
-        read_u32(data, &mut offset); // Skip
+        paint.background_pixmap = read_u32(data, &mut offset);
     }
     // Bit 1: background-pixel
     if value_mask & 0x00000002 != 0 {
-        read_u32(data, &mut offset); // Skip (could be used for background)
+        paint.background_pixel = read_u32(data, &mut offset);
     }
     // Bit 2: border-pixmap
     if value_mask & 0x00000004 != 0 {
-        read_u32(data, &mut offset); // Skip
+        paint.border_pixmap = read_u32(data, &mut offset);
     }
     // Bit 3: border-pixel
     if value_mask & 0x00000008 != 0 {
-        read_u32(data, &mut offset); // Skip
+        paint.border_pixel = read_u32(data, &mut offset);
     }
     // Bit 4: bit-gravity
     if value_mask & 0x00000010 != 0 {
@@ -2360,7 +2415,7 @@ fn handle_change_window_attributes<S: Write>(
     }
     // Bit 9: override-redirect
     if value_mask & 0x00000200 != 0 {
-        read_u32(data, &mut offset); // Skip
+        override_redirect = read_u32(data, &mut offset).map(|v| v != 0);
     }
     // Bit 10: save-under
     if value_mask & 0x00000400 != 0 {
@@ -2371,9 +2426,11 @@ fn handle_change_window_attributes<S: Write>(
         event_mask = read_u32(data, &mut offset);
     }
     // Bit 12: do-not-propagate-mask
-    if value_mask & 0x00001000 != 0 {
-        read_u32(data, &mut offset); // Skip
-    }
+    let do_not_propagate = if value_mask & 0x00001000 != 0 {
+        read_u32(data, &mut offset)
+    } else {
+        None
+    };
     // Bit 13: colormap
     if value_mask & 0x00002000 != 0 {
         read_u32(data, &mut offset); // Skip
@@ -2385,10 +2442,35 @@ fn handle_change_window_attributes<S: Write>(
 
     let window = crate::protocol::Window::new(window_id);
 
+    let mut server = server.lock().unwrap();
+
+    // Only one client at a time may select SubstructureRedirect (the window manager)
+    if let Some(mask) = event_mask {
+        let redirect = crate::protocol::event_mask::SUBSTRUCTURE_REDIRECT;
+        let holder = server.redirect_client(window);
+        if mask & redirect != 0 && holder.is_some_and(|c| c != client_id) {
+            return Err(crate::protocol::X11Error::new(
+                crate::protocol::ErrorCode::Access,
+                0,
+                window_id,
+                0,
+                2,
+            )
+            .into());
+        }
+    }
+
     // Apply the changes
-    {
-        let mut server = server.lock().unwrap();
-        server.change_window_attributes(window, event_mask, cursor)?;
+    server.change_window_attributes(
+        window,
+        client_id,
+        &paint,
+        event_mask,
+        override_redirect,
+        cursor,
+    )?;
+    if let Some(mask) = do_not_propagate {
+        server.set_do_not_propagate_mask(window, mask as u16);
     }
 
     Ok(())
@@ -2399,6 +2481,7 @@ fn handle_get_window_attributes<S: Write>(
     header: &[u8],
     data: &[u8],
     server: &Arc<Mutex<Server>>,
+    client_id: u32,
 ) -> Result<(), Box<dyn Error + Send + Sync>> {
     // Parse GetWindowAttributes request: window(4)
     if data.len() < 4 {
@@ -2412,9 +2495,27 @@ fn handle_get_window_attributes<S: Write>(
     // Get the sequence number from header
     let sequence = u16::from_le_bytes([header[2], header[3]]);
 
-    let _server = server.lock().unwrap();
+    let server = server.lock().unwrap();
+    let window = crate::protocol::Window::new(window_id);
+
+    let do_not_propagate = server
+        .get_window_info(window)
+        .map_or(0, |info| info.do_not_propagate_mask);
+    let (map_state, override_redirect, all_event_masks) = match server.get_window_info(window) {
+        Some(info) => {
+            let map_state = if !info.mapped {
+                crate::protocol::types::MapState::Unmapped
+            } else if server.is_viewable(window) {
+                crate::protocol::types::MapState::Viewable
+            } else {
+                crate::protocol::types::MapState::Unviewable
+            };
+            (map_state, info.override_redirect, info.event_mask)
+        }
+        None => (crate::protocol::types::MapState::Viewable, false, 0),
+    };
+    let your_event_mask = server.client_event_mask(window, client_id);
 
-    // Return default attributes for the window
     let encoder =
         crate::protocol::encoder::ProtocolEncoder::new(crate::protocol::ByteOrder::LSBFirst);
     let reply = encoder.encode_get_window_attributes_reply(
@@ -2427,12 +2528,12 @@ fn handle_get_window_attributes<S: Write>(
         0,          // backing_pixel
         false,      // save_under
         true,       // map_is_installed
-        crate::protocol::types::MapState::Viewable,
-        false,                                       // override_redirect
+        map_state,
+        override_redirect,
         crate::protocol::types::Colormap::new(0x20), // Default colormap
-        0xFFFFFFFF,                                  // all_event_masks
-        0xFFFFFFFF,                                  // your_event_mask
-        0,                                           // do_not_propagate_mask
+        all_event_masks,
+        your_event_mask,
+        do_not_propagate,
     );
 
     stream.write_all(&reply)?;
@@ -2602,7 +2703,7 @@ fn handle_image_text8<S: Write>(
     let y = i16::from_le_bytes([data[10], data[11]]);
 
     let text_end = (12 + string_len).min(data.len());
-    let text = String::from_utf8_lossy(&data[12..text_end]).to_string();
+    let codes: Vec<u16> = data[12..text_end].iter().map(|&b| b as u16).collect();
 
     log::debug!(
         "ImageText8: drawable=0x{:x}, gc=0x{:x}, ({},{}), text={:?}",
@@ -2610,7 +2711,7 @@ fn handle_image_text8<S: Write>(
         gc,
         x,
         y,
-        text
+        String::from_utf8_lossy(&data[12..text_end])
     );
 
     let mut server = server.lock().unwrap();
@@ -2620,7 +2721,8 @@ fn handle_image_text8<S: Write>(
         crate::protocol::GContext::new(gc),
         x,
         y,
-        &text,
+        &codes,
+        true,
     )?;
 
     Ok(())
@@ -2645,26 +2747,16 @@ fn handle_image_text16<S: Write>(
     let y = i16::from_le_bytes([data[10], data[11]]);
 
     // Parse 16-bit characters (CHAR2B format: byte1=high, byte2=low)
-    let char_data = &data[12..];
-    let mut text = String::new();
-    for i in (0..string_len * 2).step_by(2) {
-        if i + 1 < char_data.len() {
-            let high = char_data[i] as u16;
-            let low = char_data[i + 1] as u16;
-            let codepoint = (high << 8) | low;
-            if let Some(ch) = char::from_u32(codepoint as u32) {
-                text.push(ch);
-            }
-        }
-    }
+    let char_end = (12 + string_len * 2).min(data.len());
+    let codes = char2b_codes(&data[12..char_end]);
 
     log::debug!(
-        "ImageText16: drawable=0x{:x}, gc=0x{:x}, ({},{}), text={:?}",
+        "ImageText16: drawable=0x{:x}, gc=0x{:x}, ({},{}), {} chars",
         drawable,
         gc,
         x,
         y,
-        text
+        codes.len()
     );
 
     let mut server = server.lock().unwrap();
@@ -2674,7 +2766,8 @@ fn handle_image_text16<S: Write>(
         crate::protocol::GContext::new(gc),
         x,
         y,
-        &text,
+        &codes,
+        true,
     )?;
 
     Ok(())
@@ -2691,60 +2784,7 @@ fn handle_poly_text8<S: Write>(
         log::warn!("PolyText8 request too short");
         return Ok(());
     }
-
-    let drawable = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
-    let gc = u32::from_le_bytes([data[4], data[5], data[6], data[7]]);
-    let mut x = i16::from_le_bytes([data[8], data[9]]);
-    let y = i16::from_le_bytes([data[10], data[11]]);
-
-    // Parse text items (each item: len(1), delta(1), string(len))
-    // If len=255, it's a font-shift item (4 bytes total: 255, font(3))
-    let mut offset = 12;
-    let mut server = server.lock().unwrap();
-    let resolved_drawable = server.resolve_drawable(drawable);
-
-    while offset < data.len() {
-        let len = data[offset] as usize;
-        if len == 0 {
-            break; // End of items
-        }
-        if len == 255 {
-            // Font-shift item - skip for now
-            offset += 4;
-            continue;
-        }
-        if offset + 2 + len > data.len() {
-            break;
-        }
-
-        let delta = data[offset + 1] as i16;
-        x += delta;
-
-        let text = String::from_utf8_lossy(&data[offset + 2..offset + 2 + len]).to_string();
-
-        log::debug!(
-            "PolyText8 item: drawable=0x{:x}, gc=0x{:x}, ({},{}), text={:?}",
-            drawable,
-            gc,
-            x,
-            y,
-            text
-        );
-
-        server.draw_text(
-            resolved_drawable,
-            crate::protocol::GContext::new(gc),
-            x,
-            y,
-            &text,
-        )?;
-
-        // Advance x by approximate text width (crude estimate)
-        x += (len * 8) as i16;
-        offset += 2 + len;
-    }
-
-    Ok(())
+    poly_text(data, 1, server)
 }
 
 fn handle_poly_text16<S: Write>(
@@ -2758,73 +2798,89 @@ fn handle_poly_text16<S: Write>(
         log::warn!("PolyText16 request too short");
         return Ok(());
     }
+    poly_text(data, 2, server)
+}
 
+/// Draw the TEXTITEMs of a PolyText8 (`char_size` 1) or PolyText16
+/// (`char_size` 2) request
+///
+/// Each item is either a string, len(1) delta(1) chars(len * char_size),
+/// drawn `delta` pixels after the previous one ends, or a font shift,
+/// 255 followed by a font ID (4 bytes, most significant first) that
+/// becomes the GC's font. Trailing padding is ignored.
+fn poly_text(
+    data: &[u8],
+    char_size: usize,
+    server: &Arc<Mutex<Server>>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
     let drawable = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
-    let gc = u32::from_le_bytes([data[4], data[5], data[6], data[7]]);
+    let gc_id = u32::from_le_bytes([data[4], data[5], data[6], data[7]]);
+    let gc = crate::protocol::GContext::new(gc_id);
     let mut x = i16::from_le_bytes([data[8], data[9]]);
     let y = i16::from_le_bytes([data[10], data[11]]);
 
-    // Parse text items (each item: len(1), delta(1), chars(len*2))
     let mut offset = 12;
     let mut server = server.lock().unwrap();
     let resolved_drawable = server.resolve_drawable(drawable);
 
-    while offset < data.len() {
+    while offset + 2 <= data.len() {
         let len = data[offset] as usize;
-        if len == 0 {
-            break; // End of items
-        }
         if len == 255 {
-            // Font-shift item - skip for now
-            offset += 4;
+            if offset + 5 > data.len() {
+                break;
+            }
+            let font = u32::from_be_bytes([
+                data[offset + 1],
+                data[offset + 2],
+                data[offset + 3],
+                data[offset + 4],
+            ]);
+            log::debug!("PolyText font shift: gc=0x{:x}, font=0x{:x}", gc_id, font);
+            server.set_gc_font(gc, font)?;
+            offset += 5;
             continue;
         }
-        if offset + 2 + len * 2 > data.len() {
+        let end = offset + 2 + len * char_size;
+        if end > data.len() {
             break;
         }
 
-        let delta = data[offset + 1] as i16;
-        x += delta;
-
-        // Parse 16-bit characters
-        let char_data = &data[offset + 2..offset + 2 + len * 2];
-        let mut text = String::new();
-        for i in (0..len * 2).step_by(2) {
-            if i + 1 < char_data.len() {
-                let high = char_data[i] as u16;
-                let low = char_data[i + 1] as u16;
-                let codepoint = (high << 8) | low;
-                if let Some(ch) = char::from_u32(codepoint as u32) {
-                    text.push(ch);
-                }
-            }
-        }
+        let delta = data[offset + 1] as i8 as i16;
+        x = x.wrapping_add(delta);
+
+        let chars = &data[offset + 2..end];
+        let codes: Vec<u16> = if char_size == 2 {
+            char2b_codes(chars)
+        } else {
+            chars.iter().map(|&b| b as u16).collect()
+        };
 
         log::debug!(
-            "PolyText16 item: drawable=0x{:x}, gc=0x{:x}, ({},{}), text={:?}",
+            "PolyText item: drawable=0x{:x}, gc=0x{:x}, ({},{}), {} chars",
             drawable,
-            gc,
+            gc_id,
             x,
             y,
-            text
+            codes.len()
         );
 
-        server.draw_text(
-            resolved_drawable,
-            crate::protocol::GContext::new(gc),
-            x,
-            y,
-            &text,
-        )?;
-
-        // Advance x by approximate text width
-        x += (len * 8) as i16;
-        offset += 2 + len * 2;
+        if !codes.is_empty() {
+            let width = server.draw_text(resolved_drawable, gc, x, y, &codes, false)?;
+            x = x.wrapping_add(width as i16);
+        }
+        offset = end;
     }
 
     Ok(())
 }
 
+/// Character codes of a CHAR2B string (byte1 high, byte2 low)
+fn char2b_codes(data: &[u8]) -> Vec<u16> {
+    data.chunks_exact(2)
+        .map(|c| u16::from_be_bytes([c[0], c[1]]))
+        .collect()
+}
+
 fn handle_intern_atom<S: Write>(
     stream: &mut S,
     header: &[u8],
@@ -3396,28 +3452,16 @@ fn handle_clear_area<S: Write>(
     Ok(())
 }
 
-fn handle_grab_server<S: Write>(
-    _stream: &mut S,
-    _header: &[u8],
-    _data: &[u8],
-    _server: &Arc<Mutex<Server>>,
-) -> Result<(), Box<dyn Error + Send + Sync>> {
-    log::debug!("GrabServer (no-op)");
+fn handle_grab_server(server: &Arc<Mutex<Server>>, client_id: u32) {
+    log::debug!("GrabServer: client {}", client_id);
+    server.lock().unwrap().grab_server(client_id);
     // No reply for GrabServer
-    // In a real X server this would prevent other clients from accessing the server
-    // We just ignore it for now
-    Ok(())
 }
 
-fn handle_ungrab_server<S: Write>(
-    _stream: &mut S,
-    _header: &[u8],
-    _data: &[u8],
-    _server: &Arc<Mutex<Server>>,
-) -> Result<(), Box<dyn Error + Send + Sync>> {
-    log::debug!("UngrabServer (no-op)");
+fn handle_ungrab_server(server: &Arc<Mutex<Server>>, client_id: u32) {
+    log::debug!("UngrabServer: client {}", client_id);
+    server.lock().unwrap().ungrab_server(client_id);
     // No reply for UngrabServer
-    Ok(())
 }
 
 fn handle_query_pointer<S: Write>(
@@ -3438,9 +3482,10 @@ fn handle_query_pointer<S: Write>(
     // Get the sequence number from header
     let sequence = u16::from_le_bytes([header[2], header[3]]);
 
-    let server = server.lock().unwrap();
+    let mut server = server.lock().unwrap();
     let root = server.root_window();
     let (pointer_x, pointer_y, pointer_window) = server.get_pointer_position();
+    let mask = server.pointer_state();
 
     // Compute window-local coordinates
     let win = crate::protocol::Window::new(window);
@@ -3476,7 +3521,7 @@ fn handle_query_pointer<S: Write>(
         pointer_y, // root_y
         win_x,     // win_x
         win_y,     // win_y
-        0,         // mask (no buttons pressed)
+        mask,      // mask
     );
 
     stream.write_all(&reply)?;
@@ -3575,15 +3620,14 @@ fn handle_query_keymap<S: Write>(
     stream: &mut S,
     header: &[u8],
     _data: &[u8],
-    _server: &Arc<Mutex<Server>>,
+    server: &Arc<Mutex<Server>>,
 ) -> Result<(), Box<dyn Error + Send + Sync>> {
     log::debug!("QueryKeymap");
 
     // Get the sequence number from header
     let sequence = u16::from_le_bytes([header[2], header[3]]);
 
-    // Return empty keymap (no keys pressed)
-    let keys = [0u8; 32];
+    let keys = server.lock().unwrap().pressed_keys();
 
     let encoder =
         crate::protocol::encoder::ProtocolEncoder::new(crate::protocol::ByteOrder::LSBFirst);
@@ -3594,13 +3638,25 @@ fn handle_query_keymap<S: Write>(
     Ok(())
 }
 
+/// Synchronous (0) or Asynchronous (1) pointer/keyboard mode of a grab
+fn grab_mode_sync(mode: u8) -> Result<bool, crate::protocol::X11Error> {
+    match mode {
+        0 => Ok(true),
+        1 => Ok(false),
+        _ => Err(crate::protocol::X11Error::bad_value(0, mode as u32, 0)),
+    }
+}
+
 fn handle_grab_pointer<S: Write>(
     stream: &mut S,
     header: &[u8],
     data: &[u8],
-    _server: &Arc<Mutex<Server>>,
+    server: &Arc<Mutex<Server>>,
+    client_id: u32,
 ) -> Result<(), Box<dyn Error + Send + Sync>> {
-    // Parse GrabPointer request
+    // Parse GrabPointer request: owner_events(1 in header), grab_window(4),
+    // event_mask(2), pointer_mode(1), keyboard_mode(1), confine_to(4),
+    // cursor(4), time(4)
     if data.len() < 20 {
         log::warn!("GrabPointer request too short");
         return Ok(());
@@ -3609,21 +3665,38 @@ fn handle_grab_pointer<S: Write>(
     let owner_events = header[1] != 0;
     let grab_window = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
     let event_mask = u16::from_le_bytes([data[4], data[5]]);
+    let confine_to = u32::from_le_bytes([data[8], data[9], data[10], data[11]]);
+    let cursor = u32::from_le_bytes([data[12], data[13], data[14], data[15]]);
+    let time = u32::from_le_bytes([data[16], data[17], data[18], data[19]]);
 
     log::debug!(
-        "GrabPointer: window=0x{:x}, owner_events={}, event_mask=0x{:x}",
+        "GrabPointer: window=0x{:x}, owner_events={}, event_mask=0x{:x}, confine_to=0x{:x}, cursor=0x{:x}",
         grab_window,
         owner_events,
-        event_mask
+        event_mask,
+        confine_to,
+        cursor
     );
 
+    let params = super::grab::GrabParams {
+        window: crate::protocol::Window::new(grab_window),
+        owner_events,
+        event_mask: event_mask as u32,
+        pointer_sync: grab_mode_sync(data[6])?,
+        keyboard_sync: grab_mode_sync(data[7])?,
+        confine_to: crate::protocol::Window::new(confine_to),
+    };
+    let status = server
+        .lock()
+        .unwrap()
+        .grab_pointer(client_id, params, time)?;
+
     // Get the sequence number from header
     let sequence = u16::from_le_bytes([header[2], header[3]]);
 
-    // Return Success (0)
     let encoder =
         crate::protocol::encoder::ProtocolEncoder::new(crate::protocol::ByteOrder::LSBFirst);
-    let reply = encoder.encode_grab_pointer_reply(sequence, 0); // Success
+    let reply = encoder.encode_grab_pointer_reply(sequence, status);
 
     stream.write_all(&reply)?;
 
@@ -3634,7 +3707,8 @@ fn handle_ungrab_pointer<S: Write>(
     _stream: &mut S,
     _header: &[u8],
     data: &[u8],
-    _server: &Arc<Mutex<Server>>,
+    server: &Arc<Mutex<Server>>,
+    client_id: u32,
 ) -> Result<(), Box<dyn Error + Send + Sync>> {
     // Parse UngrabPointer request: time(4)
     let time = if data.len() >= 4 {
@@ -3645,25 +3719,53 @@ fn handle_ungrab_pointer<S: Write>(
 
     log::debug!("UngrabPointer: time={}", time);
 
+    server.lock().unwrap().ungrab_pointer(client_id, time);
+
     // No reply for UngrabPointer
     Ok(())
 }
 
 fn handle_grab_button<S: Write>(
     _stream: &mut S,
-    _header: &[u8],
+    header: &[u8],
     data: &[u8],
-    _server: &Arc<Mutex<Server>>,
+    server: &Arc<Mutex<Server>>,
+    client_id: u32,
 ) -> Result<(), Box<dyn Error + Send + Sync>> {
+    // Parse GrabButton request: owner_events(1 in header), grab_window(4),
+    // event_mask(2), pointer_mode(1), keyboard_mode(1), confine_to(4),
+    // cursor(4), button(1), pad(1), modifiers(2)
     if data.len() < 20 {
         log::warn!("GrabButton request too short");
         return Ok(());
     }
 
+    let owner_events = header[1] != 0;
     let grab_window = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
-    let button = data[10];
+    let event_mask = u16::from_le_bytes([data[4], data[5]]);
+    let confine_to = u32::from_le_bytes([data[8], data[9], data[10], data[11]]);
+    let button = data[16];
+    let modifiers = u16::from_le_bytes([data[18], data[19]]);
+
+    log::debug!(
+        "GrabButton: window=0x{:x}, button={}, modifiers=0x{:x}",
+        grab_window,
+        button,
+        modifiers
+    );
 
-    log::debug!("GrabButton: window=0x{:x}, button={}", grab_window, button);
+    let params = super::grab::GrabParams {
+        window: crate::protocol::Window::new(grab_window),
+        owner_events,
+        event_mask: event_mask as u32,
+        pointer_sync: grab_mode_sync(data[6])?,
+        keyboard_sync: grab_mode_sync(data[7])?,
+        confine_to: crate::protocol::Window::new(confine_to),
+    };
+    server
+        .lock()
+        .unwrap()
+        .grab_button(client_id, button, modifiers, params)?;
 
     // No reply for GrabButton
     Ok(())
@@ -3673,19 +3775,31 @@ fn handle_ungrab_button<S: Write>(
     _stream: &mut S,
     header: &[u8],
     data: &[u8],
-    _server: &Arc<Mutex<Server>>,
+    server: &Arc<Mutex<Server>>,
+    client_id: u32,
 ) -> Result<(), Box<dyn Error + Send + Sync>> {
+    // Parse UngrabButton request: button(1 in header), grab_window(4), modifiers(2)
+    if data.len() < 6 {
+        log::warn!("UngrabButton request too short");
+        return Ok(());
+    }
+
     let button = header[1];
-    let grab_window = if data.len() >= 4 {
-        u32::from_le_bytes([data[0], data[1], data[2], data[3]])
-    } else {
-        0
-    };
+    let grab_window = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+    let modifiers = u16::from_le_bytes([data[4], data[5]]);
 
     log::debug!(
-        "UngrabButton: button={}, window=0x{:x}",
+        "UngrabButton: button={}, window=0x{:x}, modifiers=0x{:x}",
         button,
-        grab_window
+        grab_window,
+        modifiers
+    );
+
+    server.lock().unwrap().ungrab_button(
+        client_id,
+        button,
+        modifiers,
+        crate::protocol::Window::new(grab_window),
     );
 
     // No reply for UngrabButton
@@ -3696,16 +3810,19 @@ fn handle_grab_keyboard<S: Write>(
     stream: &mut S,
     header: &[u8],
     data: &[u8],
-    _server: &Arc<Mutex<Server>>,
+    server: &Arc<Mutex<Server>>,
+    client_id: u32,
 ) -> Result<(), Box<dyn Error + Send + Sync>> {
-    // Parse GrabKeyboard request
-    if data.len() < 12 {
+    // Parse GrabKeyboard request: owner_events(1 in header), grab_window(4),
+    // time(4), pointer_mode(1), keyboard_mode(1)
+    if data.len() < 10 {
         log::warn!("GrabKeyboard request too short");
         return Ok(());
     }
 
     let owner_events = header[1] != 0;
     let grab_window = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+    let time = u32::from_le_bytes([data[4], data[5], data[6], data[7]]);
 
     log::debug!(
         "GrabKeyboard: window=0x{:x}, owner_events={}",
@@ -3713,13 +3830,25 @@ fn handle_grab_keyboard<S: Write>(
         owner_events
     );
 
+    let params = super::grab::GrabParams {
+        window: crate::protocol::Window::new(grab_window),
+        owner_events,
+        event_mask: 0,
+        pointer_sync: grab_mode_sync(data[8])?,
+        keyboard_sync: grab_mode_sync(data[9])?,
+        confine_to: crate::protocol::Window::NONE,
+    };
+    let status = server
+        .lock()
+        .unwrap()
+        .grab_keyboard(client_id, params, time)?;
+
     // Get the sequence number from header
     let sequence = u16::from_le_bytes([header[2], header[3]]);
 
-    // Return Success (0)
     let encoder =
         crate::protocol::encoder::ProtocolEncoder::new(crate::protocol::ByteOrder::LSBFirst);
-    let reply = encoder.encode_grab_keyboard_reply(sequence, 0); // Success
+    let reply = encoder.encode_grab_keyboard_reply(sequence, status);
 
     stream.write_all(&reply)?;
 
@@ -3730,7 +3859,8 @@ fn handle_ungrab_keyboard<S: Write>(
     _stream: &mut S,
     _header: &[u8],
     data: &[u8],
-    _server: &Arc<Mutex<Server>>,
+    server: &Arc<Mutex<Server>>,
+    client_id: u32,
 ) -> Result<(), Box<dyn Error + Send + Sync>> {
     // Parse UngrabKeyboard request: time(4)
     let time = if data.len() >= 4 {
@@ -3741,6 +3871,8 @@ fn handle_ungrab_keyboard<S: Write>(
 
     log::debug!("UngrabKeyboard: time={}", time);
 
+    server.lock().unwrap().ungrab_keyboard(client_id, time);
+
     // No reply for UngrabKeyboard
     Ok(())
 }
@@ -3749,7 +3881,8 @@ fn handle_allow_events<S: Write>(
     _stream: &mut S,
     header: &[u8],
     data: &[u8],
-    _server: &Arc<Mutex<Server>>,
+    server: &Arc<Mutex<Server>>,
+    client_id: u32,
 ) -> Result<(), Box<dyn Error + Send + Sync>> {
     // Parse AllowEvents request: mode(1 in header), time(4)
     let mode = header[1];
@@ -3773,8 +3906,10 @@ fn handle_allow_events<S: Write>(
 
     log::debug!("AllowEvents: mode={} ({}), time={}", mode, mode_str, time);
 
+    // Releases input frozen by the client's synchronous grabs
+    server.lock().unwrap().allow_events(client_id, mode)?;
+
     // No reply for AllowEvents
-    // This releases frozen events from grab operations
     Ok(())
 }
 
@@ -3782,10 +3917,11 @@ fn handle_grab_key<S: Write>(
     _stream: &mut S,
     header: &[u8],
     data: &[u8],
-    _server: &Arc<Mutex<Server>>,
+    server: &Arc<Mutex<Server>>,
+    client_id: u32,
 ) -> Result<(), Box<dyn Error + Send + Sync>> {
     // Parse GrabKey request: owner_events(1 in header), grab_window(4), modifiers(2), key(1), pointer_mode(1), keyboard_mode(1)
-    if data.len() < 8 {
+    if data.len() < 9 {
         log::warn!("GrabKey request too short");
         return Ok(());
     }
@@ -3795,7 +3931,7 @@ fn handle_grab_key<S: Write>(
     let modifiers = u16::from_le_bytes([data[4], data[5]]);
     let key = data[6];
     let pointer_mode = data[7];
-    let keyboard_mode = if data.len() > 8 { data[8] } else { 0 };
+    let keyboard_mode = data[8];
 
     log::debug!(
         "GrabKey: owner_events={}, grab_window=0x{:x}, modifiers=0x{:x}, key={}, pointer_mode={}, keyboard_mode={}",
@@ -3807,16 +3943,29 @@ fn handle_grab_key<S: Write>(
         keyboard_mode
     );
 
+    let params = super::grab::GrabParams {
+        window: crate::protocol::Window::new(grab_window),
+        owner_events,
+        event_mask: 0,
+        pointer_sync: grab_mode_sync(pointer_mode)?,
+        keyboard_sync: grab_mode_sync(keyboard_mode)?,
+        confine_to: crate::protocol::Window::NONE,
+    };
+    server
+        .lock()
+        .unwrap()
+        .grab_key(client_id, key, modifiers, params)?;
+
     // No reply for GrabKey
-    // A full implementation would register this key grab
     Ok(())
 }
 
 fn handle_ungrab_key<S: Write>(
     _stream: &mut S,
-    _header: &[u8],
+    header: &[u8],
     data: &[u8],
-    _server: &Arc<Mutex<Server>>,
+    server: &Arc<Mutex<Server>>,
+    client_id: u32,
 ) -> Result<(), Box<dyn Error + Send + Sync>> {
     // Parse UngrabKey request: key(1 in header), grab_window(4), modifiers(2)
     if data.len() < 6 {
@@ -3824,15 +3973,24 @@ fn handle_ungrab_key<S: Write>(
         return Ok(());
     }
 
+    let key = header[1];
     let grab_window = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
     let modifiers = u16::from_le_bytes([data[4], data[5]]);
 
     log::debug!(
-        "UngrabKey: grab_window=0x{:x}, modifiers=0x{:x}",
+        "UngrabKey: key={}, grab_window=0x{:x}, modifiers=0x{:x}",
+        key,
         grab_window,
         modifiers
     );
 
+    server.lock().unwrap().ungrab_key(
+        client_id,
+        key,
+        modifiers,
+        crate::protocol::Window::new(grab_window),
+    );
+
     // No reply for UngrabKey
     Ok(())
 }
@@ -4007,13 +4165,39 @@ fn handle_query_colors<S: Write>(
     Ok(())
 }
 
+/// Read (red, green, blue) foreground and background colors
+fn cursor_colors(data: &[u8]) -> ([u16; 3], [u16; 3]) {
+    let channel = |i: usize| u16::from_le_bytes([data[i * 2], data[i * 2 + 1]]);
+    (
+        [channel(0), channel(1), channel(2)],
+        [channel(3), channel(4), channel(5)],
+    )
+}
+
+/// Protocol errors go to the client; a cursor the backend can't make only
+/// leaves the cursor undefined
+fn cursor_result(
+    request: &str,
+    result: Result<(), Box<dyn Error + Send + Sync>>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    match result {
+        Err(e) if e.is::<X11Error>() => Err(e),
+        Err(e) => {
+            log::warn!("{}: {}", request, e);
+            Ok(())
+        }
+        Ok(()) => Ok(()),
+    }
+}
+
 fn handle_create_cursor<S: Write>(
     _stream: &mut S,
     _header: &[u8],
     data: &[u8],
     server: &Arc<Mutex<Server>>,
 ) -> Result<(), Box<dyn Error + Send + Sync>> {
-    // Parse CreateCursor request
+    // Parse CreateCursor request: cid(4), source(4), mask(4), fore and back
+    // red/green/blue(2 each), x(2), y(2)
     if data.len() < 28 {
         log::warn!("CreateCursor request too short");
         return Ok(());
@@ -4022,23 +4206,24 @@ fn handle_create_cursor<S: Write>(
     let cid = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
     let source = u32::from_le_bytes([data[4], data[5], data[6], data[7]]);
     let mask = u32::from_le_bytes([data[8], data[9], data[10], data[11]]);
+    let (foreground, background) = cursor_colors(&data[12..24]);
+    let x = u16::from_le_bytes([data[24], data[25]]);
+    let y = u16::from_le_bytes([data[26], data[27]]);
 
     log::debug!(
-        "CreateCursor: cid=0x{:x}, source=0x{:x}, mask=0x{:x}",
+        "CreateCursor: cid=0x{:x}, source=0x{:x}, mask=0x{:x}, hotspot=({},{})",
         cid,
         source,
-        mask
+        mask,
+        x,
+        y
     );
 
-    // Pixmap cursor creation is complex; create a default arrow cursor as a placeholder
     let mut server = server.lock().unwrap();
-    if let Err(e) = server.create_cursor_from_standard(cid, crate::backend::StandardCursor::LeftPtr)
-    {
-        log::warn!("Failed to create cursor 0x{:x}: {:?}", cid, e);
-    }
-
-    // No reply for CreateCursor
-    Ok(())
+    cursor_result(
+        "CreateCursor",
+        server.create_bitmap_cursor(cid, source, mask, foreground, background, x, y),
+    )
 }
 
 fn handle_create_glyph_cursor<S: Write>(
@@ -4047,7 +4232,8 @@ fn handle_create_glyph_cursor<S: Write>(
     data: &[u8],
     server: &Arc<Mutex<Server>>,
 ) -> Result<(), Box<dyn Error + Send + Sync>> {
-    // Parse CreateGlyphCursor request
+    // Parse CreateGlyphCursor request: cid(4), source_font(4), mask_font(4),
+    // source_char(2), mask_char(2), fore and back red/green/blue(2 each)
     if data.len() < 28 {
         log::warn!("CreateGlyphCursor request too short");
         return Ok(());
@@ -4055,26 +4241,33 @@ fn handle_create_glyph_cursor<S: Write>(
 
     let cid = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
     let source_font = u32::from_le_bytes([data[4], data[5], data[6], data[7]]);
+    let mask_font = u32::from_le_bytes([data[8], data[9], data[10], data[11]]);
     let source_char = u16::from_le_bytes([data[12], data[13]]);
+    let mask_char = u16::from_le_bytes([data[14], data[15]]);
+    let (foreground, background) = cursor_colors(&data[16..28]);
 
     log::debug!(
-        "CreateGlyphCursor: cid=0x{:x}, font=0x{:x}, char={}",
+        "CreateGlyphCursor: cid=0x{:x}, font=0x{:x}, char={}, mask_font=0x{:x}, mask_char={}",
         cid,
         source_font,
-        source_char
+        source_char,
+        mask_font,
+        mask_char
     );
 
-    // Map the glyph char to a StandardCursor, defaulting to LeftPtr
-    let cursor_shape = crate::backend::StandardCursor::from_glyph(source_char)
-        .unwrap_or(crate::backend::StandardCursor::LeftPtr);
-
     let mut server = server.lock().unwrap();
-    if let Err(e) = server.create_cursor_from_standard(cid, cursor_shape) {
-        log::warn!("Failed to create glyph cursor 0x{:x}: {:?}", cid, e);
-    }
-
-    // No reply for CreateGlyphCursor
-    Ok(())
+    cursor_result(
+        "CreateGlyphCursor",
+        server.create_glyph_cursor(
+            cid,
+            source_font,
+            mask_font,
+            source_char,
+            mask_char,
+            foreground,
+            background,
+        ),
+    )
 }
 
 fn handle_free_cursor<S: Write>(
@@ -4152,10 +4345,11 @@ fn handle_change_keyboard_mapping<S: Write>(
     _stream: &mut S,
     header: &[u8],
     data: &[u8],
-    _server: &Arc<Mutex<Server>>,
+    server: &Arc<Mutex<Server>>,
 ) -> Result<(), Box<dyn Error + Send + Sync>> {
-    // Parse ChangeKeyboardMapping request: keycode_count(1 in header), first_keycode(1), keysyms_per_keycode(1)
-    if data.len() < 2 {
+    // Parse ChangeKeyboardMapping request: keycode_count(1 in header), first_keycode(1),
+    // keysyms_per_keycode(1), pad(2), keysyms(4 * count * per)
+    if data.len() < 4 {
         log::warn!("ChangeKeyboardMapping request too short");
         return Ok(());
     }
@@ -4171,144 +4365,27 @@ fn handle_change_keyboard_mapping<S: Write>(
         keysyms_per_keycode
     );
 
-    // No reply for ChangeKeyboardMapping
-    // This would normally update the keyboard mapping table
-    Ok(())
-}
-
-/// Map macOS keycode (after +8 offset) to X11 keysym
-/// macOS keycodes are not sequential - this provides the correct mapping
-fn macos_keycode_to_keysym(keycode: u8) -> u32 {
-    // macOS keycode + 8 = our keycode, so subtract 8 to get macOS keycode
-    let mac_keycode = keycode.saturating_sub(8);
-
-    // macOS virtual key codes to X11 keysyms
-    // Reference: Carbon/HIToolbox/Events.h (kVK_* constants)
-    match mac_keycode {
-        // Letters (QWERTY layout)
-        0 => 0x61,  // kVK_ANSI_A -> 'a'
-        1 => 0x73,  // kVK_ANSI_S -> 's'
-        2 => 0x64,  // kVK_ANSI_D -> 'd'
-        3 => 0x66,  // kVK_ANSI_F -> 'f'
-        4 => 0x68,  // kVK_ANSI_H -> 'h'
-        5 => 0x67,  // kVK_ANSI_G -> 'g'
-        6 => 0x7a,  // kVK_ANSI_Z -> 'z'
-        7 => 0x78,  // kVK_ANSI_X -> 'x'
-        8 => 0x63,  // kVK_ANSI_C -> 'c'
-        9 => 0x76,  // kVK_ANSI_V -> 'v'
-        11 => 0x62, // kVK_ANSI_B -> 'b'
-        12 => 0x71, // kVK_ANSI_Q -> 'q'
-        13 => 0x77, // kVK_ANSI_W -> 'w'
-        14 => 0x65, // kVK_ANSI_E -> 'e'
-        15 => 0x72, // kVK_ANSI_R -> 'r'
-        16 => 0x79, // kVK_ANSI_Y -> 'y'
-        17 => 0x74, // kVK_ANSI_T -> 't'
-        18 => 0x31, // kVK_ANSI_1 -> '1'
-        19 => 0x32, // kVK_ANSI_2 -> '2'
-        20 => 0x33, // kVK_ANSI_3 -> '3'
-        21 => 0x34, // kVK_ANSI_4 -> '4'
-        22 => 0x36, // kVK_ANSI_6 -> '6'
-        23 => 0x35, // kVK_ANSI_5 -> '5'
-        24 => 0x3d, // kVK_ANSI_Equal -> '='
-        25 => 0x39, // kVK_ANSI_9 -> '9'
-        26 => 0x37, // kVK_ANSI_7 -> '7'
-        27 => 0x2d, // kVK_ANSI_Minus -> '-'
-        28 => 0x38, // kVK_ANSI_8 -> '8'
-        29 => 0x30, // kVK_ANSI_0 -> '0'
-        30 => 0x5d, // kVK_ANSI_RightBracket -> ']'
-        31 => 0x6f, // kVK_ANSI_O -> 'o'
-        32 => 0x75, // kVK_ANSI_U -> 'u'
-        33 => 0x5b, // kVK_ANSI_LeftBracket -> '['
-        34 => 0x69, // kVK_ANSI_I -> 'i'
-        35 => 0x70, // kVK_ANSI_P -> 'p'
-        37 => 0x6c, // kVK_ANSI_L -> 'l'
-        38 => 0x6a, // kVK_ANSI_J -> 'j'
-        39 => 0x27, // kVK_ANSI_Quote -> '''
-        40 => 0x6b, // kVK_ANSI_K -> 'k'
-        41 => 0x3b, // kVK_ANSI_Semicolon -> ';'
-        42 => 0x5c, // kVK_ANSI_Backslash -> '\'
-        43 => 0x2c, // kVK_ANSI_Comma -> ','
-        44 => 0x2f, // kVK_ANSI_Slash -> '/'
-        45 => 0x6e, // kVK_ANSI_N -> 'n'
-        46 => 0x6d, // kVK_ANSI_M -> 'm'
-        47 => 0x2e, // kVK_ANSI_Period -> '.'
-        50 => 0x60, // kVK_ANSI_Grave -> '`'
-
-        // Special keys
-        36 => 0xff0d, // kVK_Return -> XK_Return
-        48 => 0xff09, // kVK_Tab -> XK_Tab
-        49 => 0x20,   // kVK_Space -> ' '
-        51 => 0xff08, // kVK_Delete (backspace) -> XK_BackSpace
-        53 => 0xff1b, // kVK_Escape -> XK_Escape
-
-        // Arrow keys
-        123 => 0xff51, // kVK_LeftArrow -> XK_Left
-        124 => 0xff53, // kVK_RightArrow -> XK_Right
-        125 => 0xff54, // kVK_DownArrow -> XK_Down
-        126 => 0xff52, // kVK_UpArrow -> XK_Up
-
-        // Function keys
-        122 => 0xffbe, // kVK_F1 -> XK_F1
-        120 => 0xffbf, // kVK_F2 -> XK_F2
-        99 => 0xffc0,  // kVK_F3 -> XK_F3
-        118 => 0xffc1, // kVK_F4 -> XK_F4
-        96 => 0xffc2,  // kVK_F5 -> XK_F5
-        97 => 0xffc3,  // kVK_F6 -> XK_F6
-        98 => 0xffc4,  // kVK_F7 -> XK_F7
-        100 => 0xffc5, // kVK_F8 -> XK_F8
-        101 => 0xffc6, // kVK_F9 -> XK_F9
-        109 => 0xffc7, // kVK_F10 -> XK_F10
-        103 => 0xffc8, // kVK_F11 -> XK_F11
-        111 => 0xffc9, // kVK_F12 -> XK_F12
-
-        // Modifier keys
-        56 => 0xffe1, // kVK_Shift -> XK_Shift_L
-        60 => 0xffe2, // kVK_RightShift -> XK_Shift_R
-        58 => 0xffe9, // kVK_Option -> XK_Alt_L
-        61 => 0xffea, // kVK_RightOption -> XK_Alt_R
-        59 => 0xffe3, // kVK_Control -> XK_Control_L
-        62 => 0xffe4, // kVK_RightControl -> XK_Control_R
-        55 => 0xffeb, // kVK_Command -> XK_Super_L
-        54 => 0xffec, // kVK_RightCommand -> XK_Super_R
-        57 => 0xffe5, // kVK_CapsLock -> XK_Caps_Lock
-
-        // Keypad
-        65 => 0xffae, // kVK_ANSI_KeypadDecimal -> XK_KP_Decimal
-        67 => 0xffaa, // kVK_ANSI_KeypadMultiply -> XK_KP_Multiply
-        69 => 0xffab, // kVK_ANSI_KeypadPlus -> XK_KP_Add
-        71 => 0xff7f, // kVK_ANSI_KeypadClear -> XK_Num_Lock
-        75 => 0xffaf, // kVK_ANSI_KeypadDivide -> XK_KP_Divide
-        76 => 0xff8d, // kVK_ANSI_KeypadEnter -> XK_KP_Enter
-        78 => 0xffad, // kVK_ANSI_KeypadMinus -> XK_KP_Subtract
-        81 => 0xffbd, // kVK_ANSI_KeypadEquals -> XK_KP_Equal
-        82 => 0xffb0, // kVK_ANSI_Keypad0 -> XK_KP_0
-        83 => 0xffb1, // kVK_ANSI_Keypad1 -> XK_KP_1
-        84 => 0xffb2, // kVK_ANSI_Keypad2 -> XK_KP_2
-        85 => 0xffb3, // kVK_ANSI_Keypad3 -> XK_KP_3
-        86 => 0xffb4, // kVK_ANSI_Keypad4 -> XK_KP_4
-        87 => 0xffb5, // kVK_ANSI_Keypad5 -> XK_KP_5
-        88 => 0xffb6, // kVK_ANSI_Keypad6 -> XK_KP_6
-        89 => 0xffb7, // kVK_ANSI_Keypad7 -> XK_KP_7
-        91 => 0xffb8, // kVK_ANSI_Keypad8 -> XK_KP_8
-        92 => 0xffb9, // kVK_ANSI_Keypad9 -> XK_KP_9
-
-        // Navigation keys
-        115 => 0xff50, // kVK_Home -> XK_Home
-        116 => 0xff55, // kVK_PageUp -> XK_Page_Up
-        117 => 0xffff, // kVK_ForwardDelete -> XK_Delete
-        119 => 0xff57, // kVK_End -> XK_End
-        121 => 0xff56, // kVK_PageDown -> XK_Page_Down
-
-        // Unknown key
-        _ => 0, // NoSymbol
+    let n_keysyms = keycode_count as usize * keysyms_per_keycode as usize;
+    if data.len() < 4 + n_keysyms * 4 {
+        return Err(X11Error::bad_length(0, 0).into());
     }
+    let keysyms: Vec<u32> = data[4..4 + n_keysyms * 4]
+        .chunks_exact(4)
+        .map(|k| u32::from_le_bytes([k[0], k[1], k[2], k[3]]))
+        .collect();
+
+    // No reply for ChangeKeyboardMapping
+    server
+        .lock()
+        .unwrap()
+        .change_keyboard_mapping(first_keycode, keysyms_per_keycode, &keysyms)
 }
 
 fn handle_get_keyboard_mapping<S: Write>(
     stream: &mut S,
     header: &[u8],
     data: &[u8],
-    _server: &Arc<Mutex<Server>>,
+    server: &Arc<Mutex<Server>>,
 ) -> Result<(), Box<dyn Error + Send + Sync>> {
     // Parse GetKeyboardMapping request: first_keycode(1), count(1)
     if data.len() < 2 {
@@ -4328,56 +4405,19 @@ fn handle_get_keyboard_mapping<S: Write>(
     // Get the sequence number from header
     let sequence = u16::from_le_bytes([header[2], header[3]]);
 
-    // Return keyboard mapping with 2 keysyms per keycode (normal + shifted)
-    let keysyms_per_keycode = 2u8;
-    let n_keysyms = (count as usize) * (keysyms_per_keycode as usize);
-    let reply_length = n_keysyms; // in 4-byte units (each keysym is 4 bytes)
+    let (keysyms_per_keycode, keysyms) = server
+        .lock()
+        .unwrap()
+        .get_keyboard_mapping(first_keycode, count)?;
 
     // Reply header: reply(1), keysyms_per_keycode(1), sequence(2), length(4)
-    let mut reply = vec![0u8; 32 + n_keysyms * 4];
+    let mut reply = vec![0u8; 32];
     reply[0] = 1; // Reply
     reply[1] = keysyms_per_keycode;
     reply[2..4].copy_from_slice(&sequence.to_le_bytes());
-    reply[4..8].copy_from_slice(&(reply_length as u32).to_le_bytes());
-
-    // Fill in keysyms using macOS keycode mapping
-    for i in 0..count as usize {
-        let keycode = (first_keycode as usize + i) as u8;
-        let keysym = macos_keycode_to_keysym(keycode);
-
-        // Calculate shifted keysym for letters and some symbols
-        let shifted_keysym = match keysym {
-            // Lowercase letters -> uppercase
-            0x61..=0x7a => keysym - 0x20, // 'a'-'z' -> 'A'-'Z'
-            // Number row shifted symbols
-            0x31 => 0x21, // '1' -> '!'
-            0x32 => 0x40, // '2' -> '@'
-            0x33 => 0x23, // '3' -> '#'
-            0x34 => 0x24, // '4' -> '$'
-            0x35 => 0x25, // '5' -> '%'
-            0x36 => 0x5e, // '6' -> '^'
-            0x37 => 0x26, // '7' -> '&'
-            0x38 => 0x2a, // '8' -> '*'
-            0x39 => 0x28, // '9' -> '('
-            0x30 => 0x29, // '0' -> ')'
-            0x2d => 0x5f, // '-' -> '_'
-            0x3d => 0x2b, // '=' -> '+'
-            0x5b => 0x7b, // '[' -> '{'
-            0x5d => 0x7d, // ']' -> '}'
-            0x5c => 0x7c, // '\' -> '|'
-            0x3b => 0x3a, // ';' -> ':'
-            0x27 => 0x22, // ''' -> '"'
-            0x60 => 0x7e, // '`' -> '~'
-            0x2c => 0x3c, // ',' -> '<'
-            0x2e => 0x3e, // '.' -> '>'
-            0x2f => 0x3f, // '/' -> '?'
-            // For other keys, shifted is the same
-            _ => keysym,
-        };
-
-        let offset = 32 + i * 8; // 2 keysyms * 4 bytes each
-        reply[offset..offset + 4].copy_from_slice(&keysym.to_le_bytes());
-        reply[offset + 4..offset + 8].copy_from_slice(&shifted_keysym.to_le_bytes());
+    reply[4..8].copy_from_slice(&(keysyms.len() as u32).to_le_bytes());
+    for keysym in keysyms {
+        reply.extend_from_slice(&keysym.to_le_bytes());
     }
 
     stream.write_all(&reply)?;
@@ -4518,7 +4558,8 @@ fn handle_change_active_pointer_grab<S: Write>(
     _stream: &mut S,
     _header: &[u8],
     data: &[u8],
-    _server: &Arc<Mutex<Server>>,
+    server: &Arc<Mutex<Server>>,
+    client_id: u32,
 ) -> Result<(), Box<dyn Error + Send + Sync>> {
     if data.len() < 10 {
         log::warn!("ChangeActivePointerGrab request too short");
@@ -4533,6 +4574,10 @@ fn handle_change_active_pointer_grab<S: Write>(
         time,
         event_mask
     );
+    server
+        .lock()
+        .unwrap()
+        .change_active_pointer_grab(client_id, event_mask as u32, time);
     Ok(())
 }
 
@@ -4540,29 +4585,46 @@ fn handle_set_font_path<S: Write>(
     _stream: &mut S,
     _header: &[u8],
     data: &[u8],
-    _server: &Arc<Mutex<Server>>,
+    server: &Arc<Mutex<Server>>,
 ) -> Result<(), Box<dyn Error + Send + Sync>> {
+    // Parse SetFontPath request: n_paths(2), pad(2), LISTofSTR
     if data.len() < 4 {
         log::warn!("SetFontPath request too short");
         return Ok(());
     }
-    let n_paths = u16::from_le_bytes([data[0], data[1]]);
-    log::debug!("SetFontPath: n_paths={}", n_paths);
-    Ok(())
+    let n_paths = u16::from_le_bytes([data[0], data[1]]) as usize;
+    let mut path = Vec::with_capacity(n_paths);
+    let mut offset = 4;
+    for _ in 0..n_paths {
+        let Some(&len) = data.get(offset) else {
+            return Err(X11Error::bad_length(0, 0).into());
+        };
+        let end = offset + 1 + len as usize;
+        let Some(element) = data.get(offset + 1..end) else {
+            return Err(X11Error::bad_length(0, 0).into());
+        };
+        path.push(String::from_utf8_lossy(element).into_owned());
+        offset = end;
+    }
+    log::debug!("SetFontPath: {:?}", path);
+
+    let mut server = server.lock().unwrap();
+    server.set_font_path(&path)
 }
 
 fn handle_get_font_path<S: Write>(
     stream: &mut S,
     header: &[u8],
     _data: &[u8],
-    _server: &Arc<Mutex<Server>>,
+    server: &Arc<Mutex<Server>>,
 ) -> Result<(), Box<dyn Error + Send + Sync>> {
     log::debug!("GetFontPath");
     let sequence = u16::from_le_bytes([header[2], header[3]]);
-    let mut reply = vec![0u8; 32];
-    reply[0] = 1;
-    reply[2..4].copy_from_slice(&sequence.to_le_bytes());
-    stream.write_all(&reply)?;
+    let path = server.lock().unwrap().font_path().to_vec();
+
+    let encoder =
+        crate::protocol::encoder::ProtocolEncoder::new(crate::protocol::ByteOrder::LSBFirst);
+    stream.write_all(&encoder.encode_get_font_path_reply(sequence, &path))?;
     Ok(())
 }
 
@@ -4570,7 +4632,7 @@ fn handle_set_dashes<S: Write>(
     _stream: &mut S,
     _header: &[u8],
     data: &[u8],
-    _server: &Arc<Mutex<Server>>,
+    server: &Arc<Mutex<Server>>,
 ) -> Result<(), Box<dyn Error + Send + Sync>> {
     if data.len() < 8 {
         log::warn!("SetDashes request too short");
@@ -4578,40 +4640,60 @@ fn handle_set_dashes<S: Write>(
     }
     let gc = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
     let dash_offset = u16::from_le_bytes([data[4], data[5]]);
-    let n_dashes = u16::from_le_bytes([data[6], data[7]]);
+    let n_dashes = u16::from_le_bytes([data[6], data[7]]) as usize;
+    let dashes = data[8..].iter().take(n_dashes).copied().collect();
     log::debug!(
-        "SetDashes: gc=0x{:x}, dash_offset={}, n_dashes={}",
+        "SetDashes: gc=0x{:x}, dash_offset={}, dashes={:?}",
         gc,
         dash_offset,
-        n_dashes
+        dashes
     );
-    Ok(())
+
+    let mut server = server.lock().unwrap();
+    server.set_dashes(crate::protocol::GContext::new(gc), dash_offset, dashes)
 }
 
 fn handle_set_clip_rectangles<S: Write>(
     _stream: &mut S,
     header: &[u8],
     data: &[u8],
-    _server: &Arc<Mutex<Server>>,
+    server: &Arc<Mutex<Server>>,
 ) -> Result<(), Box<dyn Error + Send + Sync>> {
     if data.len() < 8 {
         log::warn!("SetClipRectangles request too short");
         return Ok(());
     }
-    let ordering = header[1];
+    let ordering = ClipOrdering::from_u8(header[1])
+        .ok_or_else(|| X11Error::bad_value(0, header[1] as u32, 0))?;
     let gc = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
     let clip_x = i16::from_le_bytes([data[4], data[5]]);
     let clip_y = i16::from_le_bytes([data[6], data[7]]);
-    let n_rects = (data.len() - 8) / 8;
+    let rectangles: Vec<Rectangle> = data[8..]
+        .chunks_exact(8)
+        .map(|r| Rectangle {
+            x: i16::from_le_bytes([r[0], r[1]]),
+            y: i16::from_le_bytes([r[2], r[3]]),
+            width: u16::from_le_bytes([r[4], r[5]]),
+            height: u16::from_le_bytes([r[6], r[7]]),
+        })
+        .collect();
     log::debug!(
-        "SetClipRectangles: ordering={}, gc=0x{:x}, origin=({},{}), n_rects={}",
+        "SetClipRectangles: ordering={:?}, gc=0x{:x}, origin=({},{}), n_rects={}",
         ordering,
         gc,
         clip_x,
         clip_y,
-        n_rects
+        rectangles.len()
     );
-    Ok(())
+
+    let mut server = server.lock().unwrap();
+    server.set_clip_rectangles(
+        crate::protocol::GContext::new(gc),
+        ordering,
+        clip_x,
+        clip_y,
+        rectangles,
+    )
 }
 
 fn handle_copy_plane<S: Write>(
@@ -4832,14 +4914,20 @@ fn handle_recolor_cursor<S: Write>(
     _stream: &mut S,
     _header: &[u8],
     data: &[u8],
-    _server: &Arc<Mutex<Server>>,
+    server: &Arc<Mutex<Server>>,
 ) -> Result<(), Box<dyn Error + Send + Sync>> {
+    // Parse RecolorCursor request: cursor(4), fore and back red/green/blue
     if data.len() < 16 {
         return Ok(());
     }
     let cursor = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+    let (foreground, background) = cursor_colors(&data[4..16]);
     log::debug!("RecolorCursor: cursor=0x{:x}", cursor);
-    Ok(())
+    let mut server = server.lock().unwrap();
+    cursor_result(
+        "RecolorCursor",
+        server.recolor_cursor(cursor, foreground, background),
+    )
 }
 
 fn handle_change_keyboard_control<S: Write>(
@@ -5021,18 +5109,29 @@ fn handle_force_screen_saver<S: Write>(
 fn handle_set_modifier_mapping<S: Write>(
     stream: &mut S,
     header: &[u8],
-    _data: &[u8],
-    _server: &Arc<Mutex<Server>>,
+    data: &[u8],
+    server: &Arc<Mutex<Server>>,
 ) -> Result<(), Box<dyn Error + Send + Sync>> {
+    // Parse SetModifierMapping request: keycodes_per_modifier(1 in header),
+    // keycodes(8 * keycodes_per_modifier)
     let keycodes_per_mod = header[1];
     log::debug!(
         "SetModifierMapping: keycodes_per_modifier={}",
         keycodes_per_mod
     );
+    let n_keycodes = 8 * keycodes_per_mod as usize;
+    if data.len() < n_keycodes {
+        return Err(X11Error::bad_length(0, 0).into());
+    }
+    let status = server
+        .lock()
+        .unwrap()
+        .set_modifier_mapping(keycodes_per_mod, &data[..n_keycodes])?;
+
     let sequence = u16::from_le_bytes([header[2], header[3]]);
     let mut reply = vec![0u8; 32];
     reply[0] = 1;
-    reply[1] = 0;
+    reply[1] = status;
     reply[2..4].copy_from_slice(&sequence.to_le_bytes());
     stream.write_all(&reply)?;
     Ok(())
@@ -5042,31 +5141,21 @@ fn handle_get_modifier_mapping<S: Write>(
     stream: &mut S,
     header: &[u8],
     _data: &[u8],
-    _server: &Arc<Mutex<Server>>,
+    server: &Arc<Mutex<Server>>,
 ) -> Result<(), Box<dyn Error + Send + Sync>> {
     log::debug!("GetModifierMapping");
     let sequence = u16::from_le_bytes([header[2], header[3]]);
-    let mut reply = vec![0u8; 48];
+    let (keycodes_per_modifier, keycodes) = server.lock().unwrap().modifier_mapping();
+
+    // Reply: reply(1), keycodes_per_modifier(1), sequence(2), length(4),
+    // unused(24), keycodes(8 * keycodes_per_modifier, padded)
+    let mut reply = vec![0u8; 32];
     reply[0] = 1;
-    reply[1] = 2;
+    reply[1] = keycodes_per_modifier;
     reply[2..4].copy_from_slice(&sequence.to_le_bytes());
-    reply[4..8].copy_from_slice(&4u32.to_le_bytes());
-    reply[32] = 50;
-    reply[33] = 62;
-    reply[34] = 66;
-    reply[35] = 0;
-    reply[36] = 37;
-    reply[37] = 105;
-    reply[38] = 64;
-    reply[39] = 108;
-    reply[40] = 77;
-    reply[41] = 0;
-    reply[42] = 0;
-    reply[43] = 0;
-    reply[44] = 133;
-    reply[45] = 134;
-    reply[46] = 0;
-    reply[47] = 0;
+    reply[4..8].copy_from_slice(&(keycodes_per_modifier as u32 * 2).to_le_bytes());
+    reply.extend_from_slice(&keycodes);
+    reply.resize(32 + 8 * keycodes_per_modifier as usize, 0);
     stream.write_all(&reply)?;
     Ok(())
 }
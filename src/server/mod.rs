@@ -7,16 +7,49 @@
 #![allow(dead_code)]
 
 mod client;
+mod cursor;
+mod device;
 pub mod extensions;
+pub mod gc;
+mod glyphs;
+mod grab;
+mod keyboard;
 pub mod listener;
-
-use crate::backend::{Backend, BackendCursor, BackendGC, BackendWindow, StandardCursor};
+mod region;
+mod render;
+mod shape;
+#[cfg(unix)]
+mod shm;
+mod text;
+mod window_tree;
+mod xfixes;
+mod xkb;
+
+pub use region::Region;
+pub use shape::WindowShape;
+pub use text::FontInfo;
+
+use crate::backend::composite::{Filter, Gradient, Repeat, Transform};
+use crate::backend::{
+    Backend, BackendCursor, BackendDrawable, BackendGC, BackendWindow, CursorFrame, GCPattern,
+    KeyboardMapping, StandardCursor, WindowBorder,
+};
+use crate::font::FontCatalog;
 use crate::protocol::*;
 use crate::resources::ResourceTracker;
 use crate::security::SecurityPolicy;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
 
+/// The bits of a resource ID a client may choose
+pub const RESOURCE_ID_MASK: u32 = 0x001FFFFF;
+
+/// The first resource ID of a client; each client gets its own 2^21 ID range
+/// so window managers and their clients can't collide
+pub fn resource_id_base(client_id: u32) -> u32 {
+    ((client_id - 1) % 255 + 1) << 21
+}
+
 /// Extension information
 #[derive(Debug, Clone)]
 pub struct ExtensionInfo {
@@ -45,23 +78,6 @@ pub struct SelectionInfo {
     pub time: u32,
 }
 
-/// Font information for QueryFont replies
-#[derive(Debug, Clone)]
-pub struct FontInfo {
-    /// Font name as opened
-    pub name: String,
-    /// Font ascent (pixels above baseline)
-    pub ascent: i16,
-    /// Font descent (pixels below baseline)
-    pub descent: i16,
-    /// Average character width
-    pub char_width: i16,
-    /// Minimum character code
-    pub min_char: u16,
-    /// Maximum character code
-    pub max_char: u16,
-}
-
 /// RENDER extension Picture resource
 #[derive(Debug, Clone)]
 pub struct Picture {
@@ -71,6 +87,21 @@ pub struct Picture {
     pub format: u32,
     /// Component alpha flag
     pub component_alpha: bool,
+    /// What the picture shows outside its drawable
+    pub repeat: Repeat,
+    /// Picture supplying the alpha channel (0 for none) and its origin
+    pub alpha_map: u32,
+    pub alpha_x_origin: i16,
+    pub alpha_y_origin: i16,
+    /// Clip origin, added to every clip rectangle
+    pub clip_x_origin: i16,
+    pub clip_y_origin: i16,
+    /// Clip rectangles relative to the clip origin, or None if unclipped
+    pub clip_rectangles: Option<Vec<Rectangle>>,
+    /// Transform from destination to picture coordinates, None for identity
+    pub transform: Option<Transform>,
+    /// Resampling filter
+    pub filter: Filter,
 }
 
 /// RENDER extension Solid Fill picture
@@ -86,8 +117,16 @@ pub struct SolidFill {
     pub alpha: u16,
 }
 
-// Re-export RenderTrapezoid from backend for use by extensions
-pub use crate::backend::RenderTrapezoid;
+/// RENDER extension gradient picture
+#[derive(Debug, Clone)]
+pub struct GradientPicture {
+    /// Geometry and color stops
+    pub gradient: std::sync::Arc<Gradient>,
+    /// What the gradient shows past its ends
+    pub repeat: Repeat,
+    /// Transform from destination to gradient coordinates
+    pub transform: Option<Transform>,
+}
 
 /// Window metadata for event dispatching and geometry queries
 #[derive(Debug, Clone)]
@@ -106,6 +145,56 @@ pub struct WindowInfo {
     pub event_mask: u32,
     /// Parent window
     pub parent: Window,
+    /// Whether the window has been mapped
+    pub mapped: bool,
+    /// Override-redirect windows bypass window manager redirection
+    pub override_redirect: bool,
+    /// What exposed and cleared areas are painted with
+    pub background: WindowBackground,
+    /// What the border is painted with
+    pub border: WindowBorder,
+    /// Cursor attribute; 0 (None) uses the parent's
+    pub cursor: u32,
+    /// Device events that don't propagate from this window to its parent
+    pub do_not_propagate_mask: u16,
+    /// SHAPE bounding, clip and input shapes
+    pub shape: WindowShape,
+}
+
+/// A window's background attribute
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WindowBackground {
+    /// Contents are left as they are
+    None,
+    /// The parent's background, aligned with the parent's origin
+    ParentRelative,
+    Pixel(u32),
+    /// A tile aligned with the window's origin
+    Pixmap(GCPattern),
+}
+
+/// Background and border values from a CreateWindow or
+/// ChangeWindowAttributes value list
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WindowPaintValues {
+    /// 0 is None, 1 is ParentRelative
+    pub background_pixmap: Option<u32>,
+    pub background_pixel: Option<u32>,
+    /// 0 is CopyFromParent
+    pub border_pixmap: Option<u32>,
+    pub border_pixel: Option<u32>,
+}
+
+/// Changes requested by a ConfigureWindow request
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WindowChanges {
+    pub x: Option<i16>,
+    pub y: Option<i16>,
+    pub width: Option<u16>,
+    pub height: Option<u16>,
+    pub border_width: Option<u16>,
+    pub sibling: Option<Window>,
+    pub stack_mode: Option<StackMode>,
 }
 
 /// Pixmap metadata for geometry queries
@@ -119,22 +208,6 @@ pub struct PixmapInfo {
     pub depth: u8,
 }
 
-impl FontInfo {
-    /// Create FontInfo with default metrics for a given font name
-    pub fn new(name: &str) -> Self {
-        // Default metrics for a typical fixed-width font
-        // These are reasonable defaults for most text rendering
-        FontInfo {
-            name: name.to_string(),
-            ascent: 12,
-            descent: 4,
-            char_width: 8,
-            min_char: 0,
-            max_char: 255,
-        }
-    }
-}
-
 /// The main X11 server
 pub struct Server {
     /// The display backend
@@ -158,12 +231,19 @@ pub struct Server {
     /// Pixmap dimensions: X11 Pixmap ID -> PixmapInfo
     pixmap_info: HashMap<u32, PixmapInfo>,
 
+    /// Backend pixmaps freed by their client but still used as a GC tile or
+    /// stipple or as a window background or border
+    retained_pixmaps: Vec<usize>,
+
     /// RENDER Picture mapping: Picture ID -> Picture info
     pictures: HashMap<u32, Picture>,
 
     /// RENDER Solid Fill mapping: Picture ID -> SolidFill info
     solid_fills: HashMap<u32, SolidFill>,
 
+    /// RENDER gradient mapping: Picture ID -> gradient
+    gradients: HashMap<u32, GradientPicture>,
+
     /// Root window
     root_window: Window,
 
@@ -188,6 +268,15 @@ pub struct Server {
     /// Font ID -> font info mapping
     fonts: HashMap<u32, FontInfo>,
 
+    /// Fonts the server can load
+    font_catalog: FontCatalog,
+
+    /// The font set in each GC; GCs without one draw with `default_font`
+    gc_fonts: HashMap<GContext, FontInfo>,
+
+    /// The server's default font ("fixed"), loaded on first use
+    default_font: Option<FontInfo>,
+
     /// Window properties: Window -> (Property Atom -> PropertyValue)
     properties: HashMap<Window, HashMap<Atom, PropertyValue>>,
 
@@ -201,8 +290,19 @@ pub struct Server {
     security_policy: SecurityPolicy,
 
     /// Pending events per window: Window -> Vec<encoded 32-byte events>
+    ///
+    /// Only used for windows without per-client selections; see `client_events`
     pending_events: HashMap<Window, Vec<Vec<u8>>>,
 
+    /// Per-client event selections: Window -> (client ID -> event mask)
+    event_selections: HashMap<Window, HashMap<u32, u32>>,
+
+    /// Events queued for a specific client: client ID -> encoded events
+    client_events: HashMap<u32, Vec<Vec<u8>>>,
+
+    /// All windows in stacking order, bottom to top
+    stacking_order: Vec<Window>,
+
     /// Event sequence number counter
     event_sequence: u16,
 
@@ -215,6 +315,12 @@ pub struct Server {
     /// Cursor mapping: X11 cursor ID -> BackendCursor
     cursors: HashMap<u32, BackendCursor>,
 
+    /// Images of cursors built by the server, kept for animated cursors
+    cursor_frames: HashMap<u32, Vec<CursorFrame>>,
+
+    /// What core cursors were made from, kept for RecolorCursor
+    core_cursors: HashMap<u32, cursor::CoreCursor>,
+
     /// Last known pointer X position
     last_pointer_x: i16,
 
@@ -223,6 +329,31 @@ pub struct Server {
 
     /// Last known window the pointer is in
     last_pointer_window: Window,
+
+    /// Pointer and keyboard grabs
+    grabs: grab::GrabState,
+
+    /// Keyboard mapping, loaded from the backend on first use
+    keyboard_mapping: Option<KeyboardMapping>,
+
+    /// XKEYBOARD state and client selections
+    xkb: xkb::XkbState,
+
+    /// Pressed keys and buttons
+    device_state: device::DeviceState,
+
+    /// RENDER glyph sets
+    glyph_sets: glyphs::GlyphSets,
+
+    /// XFIXES regions: Region ID -> region
+    regions: HashMap<u32, Region>,
+
+    /// Clients selecting ShapeNotify: Window -> client IDs
+    shape_selections: HashMap<Window, HashSet<u32>>,
+
+    /// MIT-SHM segments and shared pixmaps
+    #[cfg(unix)]
+    shm: shm::ShmState,
 }
 
 impl Server {
@@ -242,8 +373,10 @@ impl Server {
             gcs: HashMap::new(),
             pixmaps: HashMap::new(),
             pixmap_info: HashMap::new(),
+            retained_pixmaps: Vec::new(),
             pictures: HashMap::new(),
             solid_fills: HashMap::new(),
+            gradients: HashMap::new(),
             root_window,
             root_backend_window: None,
             next_resource_id: 0x200, // Start after reserved IDs
@@ -252,26 +385,79 @@ impl Server {
             next_atom_id: 69, // Predefined atoms use 1-68
             extensions: HashMap::new(),
             fonts: HashMap::new(),
+            font_catalog: FontCatalog::new(),
+            gc_fonts: HashMap::new(),
+            default_font: None,
             properties: HashMap::new(),
             selections: HashMap::new(),
             resource_tracker: ResourceTracker::new(),
             security_policy: SecurityPolicy::default(),
             pending_events: HashMap::new(),
+            event_selections: HashMap::new(),
+            client_events: HashMap::new(),
+            stacking_order: Vec::new(),
             event_sequence: 0,
             focused_window: root_window,
             focus_revert_to: 1, // PointerRoot
             cursors: HashMap::new(),
+            cursor_frames: HashMap::new(),
+            core_cursors: HashMap::new(),
             last_pointer_x: 0,
             last_pointer_y: 0,
             last_pointer_window: root_window,
+            grabs: grab::GrabState::default(),
+            keyboard_mapping: None,
+            xkb: xkb::XkbState::default(),
+            device_state: device::DeviceState::default(),
+            glyph_sets: glyphs::GlyphSets::default(),
+            regions: HashMap::new(),
+            shape_selections: HashMap::new(),
+            #[cfg(unix)]
+            shm: shm::ShmState::default(),
         };
 
+        // The root window is part of the window tree; rooted backends also
+        // give it a native window that input is reported against
+        let screen = server.get_screen_info();
+        server.window_info.insert(
+            root_window,
+            WindowInfo {
+                width: screen.width,
+                height: screen.height,
+                x: 0,
+                y: 0,
+                border_width: 0,
+                event_mask: 0,
+                parent: Window::NONE,
+                mapped: true,
+                override_redirect: false,
+                background: WindowBackground::None,
+                border: WindowBorder::Pixel(0),
+                cursor: 0,
+                do_not_propagate_mask: 0,
+                shape: WindowShape::default(),
+            },
+        );
+        if let Some(backend_root) = server.backend.root_window() {
+            log::info!(
+                "Backend provides root window {:?} (rooted mode)",
+                backend_root
+            );
+            server.root_backend_window = Some(backend_root);
+            server.windows.insert(root_window, backend_root);
+            server.backend_to_x11.insert(backend_root, root_window);
+        }
+
         // Register predefined atoms
         server.init_predefined_atoms();
 
         // Register common extensions
         server.init_extensions();
 
+        // An empty font path selects the default one, read before any client
+        // connects
+        let _ = server.font_catalog.set_path(&[]);
+
         Ok(server)
     }
 
@@ -560,12 +746,13 @@ impl Server {
         );
 
         // MIT-SHM extension (shared memory)
+        #[cfg(unix)]
         self.extensions.insert(
             "MIT-SHM".to_string(),
             ExtensionInfo {
                 major_opcode: 130,
                 first_event: 65, // ShmCompletion
-                first_error: 128,
+                first_error: ErrorCode::ShmSeg as u8,
             },
         );
 
@@ -639,6 +826,16 @@ impl Server {
             },
         );
 
+        // XTEST extension (input synthesis)
+        self.extensions.insert(
+            "XTEST".to_string(),
+            ExtensionInfo {
+                major_opcode: 132,
+                first_event: 0,
+                first_error: 0,
+            },
+        );
+
         // DAMAGE extension (damage tracking)
         self.extensions.insert(
             "DAMAGE".to_string(),
@@ -784,9 +981,39 @@ impl Server {
         &mut self,
         client_id: u32,
     ) -> Vec<crate::resources::CleanupRequest> {
+        self.remove_client_selections(client_id);
+        self.remove_client_grabs(client_id);
+        self.xkb.remove_client(client_id);
+        for clients in self.shape_selections.values_mut() {
+            clients.remove(&client_id);
+        }
+        #[cfg(unix)]
+        self.shm.remove_client(client_id);
+        self.free_extension_resources(client_id);
         self.unregister_client(client_id)
     }
 
+    /// Free the XFIXES regions and RENDER pictures and glyph sets created
+    /// in a client's ID range
+    fn free_extension_resources(&mut self, client_id: u32) {
+        let base = resource_id_base(client_id);
+        let owned = |id: &u32| id & !RESOURCE_ID_MASK == base;
+        self.regions.retain(|id, _| !owned(id));
+        let pictures: Vec<u32> = (self.pictures.keys())
+            .chain(self.solid_fills.keys())
+            .chain(self.gradients.keys())
+            .copied()
+            .filter(owned)
+            .collect();
+        for picture in pictures {
+            self.free_picture(picture);
+        }
+        let glyph_sets: Vec<u32> = self.glyph_sets.ids().filter(owned).collect();
+        for glyph_set in glyph_sets {
+            let _ = self.free_glyph_set(glyph_set);
+        }
+    }
+
     /// Create a window
     #[allow(clippy::too_many_arguments)]
     pub fn create_window(
@@ -800,9 +1027,26 @@ impl Server {
         border_width: u16,
         class: WindowClass,
         _visual: VisualID,
-        background_pixel: Option<u32>,
+        paint: &WindowPaintValues,
         event_mask: u32,
+        override_redirect: bool,
     ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let background = self
+            .resolve_background(paint)?
+            .unwrap_or(WindowBackground::None);
+        // The border defaults to CopyFromParent
+        let border = WindowPaintValues {
+            border_pixmap: paint.border_pixmap.or(Some(0)),
+            ..*paint
+        };
+        let border = self
+            .resolve_border(parent, &border)?
+            .unwrap_or(WindowBorder::Pixel(0));
+        let background_pixel = match background {
+            WindowBackground::Pixel(pixel) => Some(pixel),
+            _ => None,
+        };
+
         // Get parent backend window (root or existing window)
         let parent_backend = if parent == self.root_window {
             self.root_backend_window
@@ -828,6 +1072,7 @@ impl Server {
             window.id().0,
             backend_window
         );
+        self.backend.set_window_border(backend_window, border)?;
         self.windows.insert(window, backend_window);
         self.backend_to_x11.insert(backend_window, window);
         log::debug!("Windows map now has {} entries", self.windows.len());
@@ -843,23 +1088,95 @@ impl Server {
                 border_width,
                 event_mask,
                 parent,
+                mapped: false,
+                override_redirect,
+                background,
+                border,
+                cursor: 0,
+                do_not_propagate_mask: 0,
+                shape: WindowShape::default(),
             },
         );
+        self.stacking_order.push(window);
 
         // Store root backend window if this is the root
         if window == self.root_window && self.root_backend_window.is_none() {
             self.root_backend_window = Some(backend_window);
         }
 
+        let mut event = vec![0u8; 32];
+        crate::protocol::events::CreateNotifyEvent {
+            sequence: self.next_event_sequence(),
+            parent,
+            window,
+            x,
+            y,
+            width,
+            height,
+            border_width,
+            override_redirect,
+        }
+        .encode(&mut event);
+        self.deliver_event(parent, event_mask::SUBSTRUCTURE_NOTIFY, event);
+
         Ok(())
     }
 
     /// Map a window (make it visible)
-    pub fn map_window(&mut self, window: Window) -> Result<(), Box<dyn Error + Send + Sync>> {
+    ///
+    /// If another client has selected SubstructureRedirect on the parent, the
+    /// request is turned into a MapRequest for it instead and false is returned.
+    pub fn map_window(
+        &mut self,
+        window: Window,
+        client_id: u32,
+    ) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let (parent, override_redirect) = match self.window_info.get(&window) {
+            Some(info) if info.mapped => return Ok(true),
+            Some(info) => (info.parent, info.override_redirect),
+            None => return Ok(false),
+        };
+
+        if !override_redirect {
+            if let Some(wm) = self.redirect_client(parent) {
+                if wm != client_id {
+                    let mut event = vec![0u8; 32];
+                    crate::protocol::events::MapRequestEvent {
+                        sequence: self.next_event_sequence(),
+                        parent,
+                        window,
+                    }
+                    .encode(&mut event);
+                    self.client_events.entry(wm).or_default().push(event);
+                    log::debug!(
+                        "MapWindow 0x{:x} redirected to client {}",
+                        window.id().get(),
+                        wm
+                    );
+                    return Ok(false);
+                }
+            }
+        }
+
         if let Some(&backend_window) = self.windows.get(&window) {
             self.backend.map_window(backend_window)?;
         }
-        Ok(())
+        if let Some(info) = self.window_info.get_mut(&window) {
+            info.mapped = true;
+        }
+
+        let mut event = vec![0u8; 32];
+        crate::protocol::events::MapNotifyEvent {
+            sequence: self.next_event_sequence(),
+            event: window,
+            window,
+            override_redirect,
+        }
+        .encode(&mut event);
+        self.deliver_structure_event(window, parent, event);
+
+        self.expose_subtree(window);
+        Ok(true)
     }
 
     /// Get window info (dimensions, event_mask, etc.)
@@ -871,56 +1188,73 @@ impl Server {
     pub fn change_window_attributes(
         &mut self,
         window: Window,
+        client_id: u32,
+        paint: &WindowPaintValues,
         event_mask: Option<u32>,
+        override_redirect: Option<bool>,
         cursor: Option<u32>,
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        // Update event mask if specified
+        let parent = self
+            .window_info
+            .get(&window)
+            .map_or(Window::NONE, |info| info.parent);
+        let background = self.resolve_background(paint)?;
+        let border = self.resolve_border(parent, paint)?;
+        if let Some(info) = self.window_info.get_mut(&window) {
+            if let Some(background) = background {
+                info.background = background;
+            }
+            if let Some(border) = border {
+                info.border = border;
+            }
+        }
+        if let (Some(border), Some(&backend_window)) = (border, self.windows.get(&window)) {
+            self.backend.set_window_border(backend_window, border)?;
+        }
+        self.release_unused_pixmaps()?;
+
+        // Update this client's event selection if specified
         if let Some(mask) = event_mask {
+            self.select_window_events(window, client_id, mask);
+            log::debug!(
+                "Client {} selected event_mask 0x{:x} on window 0x{:x}",
+                client_id,
+                mask,
+                window.id().get()
+            );
+        }
+
+        if let Some(value) = override_redirect {
             if let Some(info) = self.window_info.get_mut(&window) {
-                info.event_mask = mask;
-                log::debug!(
-                    "Changed window 0x{:x} event_mask to 0x{:x}",
-                    window.id().get(),
-                    mask
-                );
+                info.override_redirect = value;
             }
         }
 
         // Update cursor if specified
         if let Some(cursor_id) = cursor {
-            let backend_cursor = if cursor_id == 0 {
-                BackendCursor::NONE
-            } else if let Some(&bc) = self.cursors.get(&cursor_id) {
-                bc
-            } else {
-                log::debug!(
-                    "Window 0x{:x} cursor 0x{:x} not in cursor map, ignoring",
-                    window.id().get(),
-                    cursor_id
-                );
-                return Ok(());
-            };
-
-            if let Some(&backend_window) = self.windows.get(&window) {
-                self.backend
-                    .set_window_cursor(backend_window, backend_cursor)?;
-                log::debug!(
-                    "Set window 0x{:x} cursor to 0x{:x}",
-                    window.id().get(),
-                    cursor_id
-                );
-            }
+            self.set_window_cursor(window, cursor_id)?;
         }
 
         Ok(())
     }
 
-    /// Get all child windows of a parent window
+    /// Set the device events that don't propagate from a window to its parent
+    pub fn set_do_not_propagate_mask(&mut self, window: Window, mask: u16) {
+        if let Some(info) = self.window_info.get_mut(&window) {
+            info.do_not_propagate_mask = mask;
+        }
+    }
+
+    /// Get all child windows of a parent window, in stacking order (bottom to top)
     pub fn get_children(&self, parent: Window) -> Vec<Window> {
-        self.window_info
+        self.stacking_order
             .iter()
-            .filter(|(_, info)| info.parent == parent)
-            .map(|(window, _)| *window)
+            .filter(|w| {
+                self.window_info
+                    .get(w)
+                    .is_some_and(|info| info.parent == parent)
+            })
+            .copied()
             .collect()
     }
 
@@ -960,7 +1294,9 @@ impl Server {
             } else {
                 (width, height)
             };
-            self.backend.clear_area(backend_window, x, y, w, h)?;
+            if !self.paint_background(window, x, y, w, h)? {
+                self.backend.clear_area(backend_window, x, y, w, h)?;
+            }
             self.backend.flush()?;
         }
         Ok(())
@@ -969,7 +1305,11 @@ impl Server {
     /// Free a graphics context
     pub fn free_gc(&mut self, gc: GContext) {
         if self.gcs.remove(&gc).is_some() {
+            self.gc_fonts.remove(&gc);
             log::debug!("Freed GC 0x{:x}", gc.id().get());
+            if let Err(e) = self.release_unused_pixmaps() {
+                log::warn!("FreeGC: failed to free retained pixmaps: {}", e);
+            }
         } else {
             log::warn!("FreeGC: GC 0x{:x} not found", gc.id().get());
         }
@@ -998,6 +1338,8 @@ impl Server {
 
     /// Free a cursor
     pub fn free_cursor(&mut self, cursor_id: u32) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.cursor_frames.remove(&cursor_id);
+        self.core_cursors.remove(&cursor_id);
         if let Some(backend_cursor) = self.cursors.remove(&cursor_id) {
             self.backend.free_cursor(backend_cursor)?;
             log::debug!("Freed cursor 0x{:x}", cursor_id);
@@ -1022,10 +1364,18 @@ impl Server {
             );
             return Ok(());
         };
+        if let Some(info) = self.window_info.get_mut(&window) {
+            info.cursor = cursor_id;
+        }
 
         if let Some(&backend_window) = self.windows.get(&window) {
             self.backend
                 .set_window_cursor(backend_window, backend_cursor)?;
+            log::debug!(
+                "Set window 0x{:x} cursor to 0x{:x}",
+                window.id().get(),
+                cursor_id
+            );
         }
         Ok(())
     }
@@ -1063,8 +1413,8 @@ impl Server {
 
         while current != self.root_window && current != Window::NONE {
             if let Some(info) = self.window_info.get(&current) {
-                abs_x += info.x;
-                abs_y += info.y;
+                abs_x += info.x + info.border_width as i16;
+                abs_y += info.y + info.border_width as i16;
                 current = info.parent;
             } else {
                 break;
@@ -1074,16 +1424,26 @@ impl Server {
         (abs_x, abs_y)
     }
 
-    /// Find the child of parent_window that contains the point (x, y)
+    /// Find the topmost mapped child of parent_window that contains the point (x, y)
     fn find_child_at(&self, parent: Window, x: i16, y: i16) -> Window {
-        for (win, info) in &self.window_info {
-            if info.parent == parent
-                && x >= info.x
-                && y >= info.y
-                && x < info.x + info.width as i16
-                && y < info.y + info.height as i16
-            {
-                return *win;
+        for win in self.stacking_order.iter().rev() {
+            if let Some(info) = self.window_info.get(win) {
+                let outer_width = info.width as i32 + 2 * info.border_width as i32;
+                let outer_height = info.height as i32 + 2 * info.border_width as i32;
+                let inside = info.border_width as i32;
+                if info.parent == parent
+                    && info.mapped
+                    && x >= info.x
+                    && y >= info.y
+                    && (x as i32) < info.x as i32 + outer_width
+                    && (y as i32) < info.y as i32 + outer_height
+                    && info.shape_contains(
+                        x as i32 - info.x as i32 - inside,
+                        y as i32 - info.y as i32 - inside,
+                    )
+                {
+                    return *win;
+                }
             }
         }
         Window::NONE
@@ -1129,14 +1489,44 @@ impl Server {
 
     /// Unmap a window (hide it)
     pub fn unmap_window(&mut self, window: Window) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let parent = match self.window_info.get(&window) {
+            Some(info) if info.mapped && window != self.root_window => info.parent,
+            _ => return Ok(()),
+        };
+
         if let Some(&backend_window) = self.windows.get(&window) {
             self.backend.unmap_window(backend_window)?;
         }
+        if let Some(info) = self.window_info.get_mut(&window) {
+            info.mapped = false;
+        }
+
+        let mut event = vec![0u8; 32];
+        crate::protocol::events::UnmapNotifyEvent {
+            sequence: self.next_event_sequence(),
+            event: window,
+            window,
+            from_configure: false,
+        }
+        .encode(&mut event);
+        self.deliver_structure_event(window, parent, event);
+        self.release_unviewable_grabs();
+
         Ok(())
     }
 
     /// Destroy a window
     pub fn destroy_window(&mut self, window: Window) -> Result<(), Box<dyn Error + Send + Sync>> {
+        if window == self.root_window {
+            return Ok(());
+        }
+
+        // Unmap first, then destroy inferiors before the window itself
+        self.unmap_window(window)?;
+        for child in self.get_children(window) {
+            self.destroy_window(child)?;
+        }
+
         log::debug!(
             "destroy_window called for window 0x{:08x}, windows map has {} entries",
             window.id().0,
@@ -1155,7 +1545,32 @@ impl Server {
         } else {
             log::warn!("Window 0x{:08x} not found in windows map!", window.id().0);
         }
-        Ok(())
+
+        if let Some(info) = self.window_info.get(&window) {
+            let parent = info.parent;
+            let mut event = vec![0u8; 32];
+            crate::protocol::events::DestroyNotifyEvent {
+                sequence: self.next_event_sequence(),
+                event: window,
+                window,
+            }
+            .encode(&mut event);
+            self.deliver_structure_event(window, parent, event);
+        }
+
+        self.window_info.remove(&window);
+        self.event_selections.remove(&window);
+        self.shape_selections.remove(&window);
+        self.pending_events.remove(&window);
+        self.stacking_order.retain(|w| *w != window);
+        if self.focused_window == window {
+            self.focused_window = self.root_window;
+        }
+        if self.last_pointer_window == window {
+            self.last_pointer_window = self.root_window;
+        }
+        self.release_unviewable_grabs();
+        self.release_unused_pixmaps()
     }
 
     /// Reparent a window to a new parent
@@ -1166,9 +1581,20 @@ impl Server {
         x: i16,
         y: i16,
     ) -> Result<(), Box<dyn Error + Send + Sync>> {
-        // Update the window's parent in window_info
+        let (old_parent, was_mapped, override_redirect) = match self.window_info.get(&window) {
+            Some(info) => (info.parent, info.mapped, info.override_redirect),
+            None => return Ok(()),
+        };
+
+        // A mapped window is unmapped, moved and then mapped again
+        if was_mapped {
+            self.unmap_window(window)?;
+        }
+
         if let Some(info) = self.window_info.get_mut(&window) {
             info.parent = parent;
+            info.x = x;
+            info.y = y;
             log::debug!(
                 "Reparented window 0x{:x} to parent 0x{:x} at ({}, {})",
                 window.id().get(),
@@ -1177,148 +1603,166 @@ impl Server {
                 y
             );
         }
-        // Note: Actual reparenting in the backend would require additional
-        // backend support. For now, we track the logical parent relationship.
-        // This is sufficient for most X11 clients that use reparenting for
-        // window manager integration.
-        Ok(())
-    }
+        // The reparented window goes on top of its new siblings
+        self.stacking_order.retain(|w| *w != window);
+        self.stacking_order.push(window);
 
-    /// Configure a window (resize/move)
-    pub fn configure_window(
-        &mut self,
-        window: Window,
-        x: Option<i16>,
-        y: Option<i16>,
-        width: Option<u16>,
-        height: Option<u16>,
-    ) -> Result<(), Box<dyn Error + Send + Sync>> {
         if let Some(&backend_window) = self.windows.get(&window) {
-            let config = crate::backend::WindowConfig {
-                x,
-                y,
-                width,
-                height,
-                border_width: None,
-                stack_mode: None,
+            let parent_backend = if parent == self.root_window {
+                self.root_backend_window
+            } else {
+                self.windows.get(&parent).copied()
             };
-            self.backend.configure_window(backend_window, config)?;
-
-            // Update window_info with new geometry
-            if let Some(info) = self.window_info.get_mut(&window) {
-                if let Some(new_x) = x {
-                    info.x = new_x;
-                }
-                if let Some(new_y) = y {
-                    info.y = new_y;
-                }
-                if let Some(new_width) = width {
-                    info.width = new_width;
-                }
-                if let Some(new_height) = height {
-                    info.height = new_height;
-                }
-            }
+            self.backend
+                .reparent_window(backend_window, parent_backend, x, y)?;
         }
-        Ok(())
-    }
-
-    /// Create a graphics context
-    pub fn create_gc(
-        &mut self,
-        gc: GContext,
-        _drawable: Drawable,
-        foreground: Option<u32>,
-        background: Option<u32>,
-    ) -> Result<(), Box<dyn Error + Send + Sync>> {
-        let mut backend_gc = crate::backend::BackendGC::default();
 
-        if let Some(fg) = foreground {
-            backend_gc.foreground = fg;
+        let mut event = vec![0u8; 32];
+        crate::protocol::events::ReparentNotifyEvent {
+            sequence: self.next_event_sequence(),
+            event: window,
+            window,
+            parent,
+            x,
+            y,
+            override_redirect,
         }
-        if let Some(bg) = background {
-            backend_gc.background = bg;
+        .encode(&mut event);
+        self.deliver_structure_event(window, old_parent, event.clone());
+        if parent != old_parent {
+            event[4..8].copy_from_slice(&parent.id().get().to_ne_bytes());
+            self.deliver_event(parent, event_mask::SUBSTRUCTURE_NOTIFY, event);
         }
 
-        self.gcs.insert(gc, backend_gc);
-        Ok(())
-    }
-
-    /// Change GC attributes
-    pub fn change_gc(
-        &mut self,
-        gc: GContext,
-        foreground: Option<u32>,
-        background: Option<u32>,
-    ) -> Result<(), Box<dyn Error + Send + Sync>> {
-        if let Some(backend_gc) = self.gcs.get_mut(&gc) {
-            if let Some(fg) = foreground {
-                backend_gc.foreground = fg;
-            }
-            if let Some(bg) = background {
-                backend_gc.background = bg;
-            }
+        if was_mapped {
+            // The automatic remap is done on behalf of the window manager
+            // (if any), so it is never redirected back to it
+            let wm = self.redirect_client(parent).unwrap_or(0);
+            self.map_window(window, wm)?;
         }
         Ok(())
     }
 
-    /// Copy GC attributes from one GC to another
-    pub fn copy_gc(
+    /// Configure a window (resize/move/restack)
+    ///
+    /// If another client has selected SubstructureRedirect on the parent, the
+    /// request is turned into a ConfigureRequest for it instead.
+    pub fn configure_window(
         &mut self,
-        src_gc: GContext,
-        dst_gc: GContext,
-        value_mask: u32,
+        window: Window,
+        client_id: u32,
+        changes: WindowChanges,
     ) -> Result<(), Box<dyn Error + Send + Sync>> {
-        // Get the source GC values
-        let src_values = if let Some(src) = self.gcs.get(&src_gc) {
-            src.clone()
-        } else {
-            log::warn!("CopyGC: source GC 0x{:x} not found", src_gc.id().get());
-            return Ok(());
+        let info = match self.window_info.get(&window) {
+            Some(info) if window != self.root_window => info.clone(),
+            _ => return Ok(()),
         };
 
-        // Apply masked values to destination GC
-        if let Some(dst) = self.gcs.get_mut(&dst_gc) {
-            // Copy attributes based on value_mask
-            // Bit 0: function
-            // Bit 1: plane-mask
-            // Bit 2: foreground
-            if value_mask & (1 << 2) != 0 {
-                dst.foreground = src_values.foreground;
-            }
-            // Bit 3: background
-            if value_mask & (1 << 3) != 0 {
-                dst.background = src_values.background;
+        if !info.override_redirect {
+            if let Some(wm) = self.redirect_client(info.parent) {
+                if wm != client_id {
+                    let mut value_mask = 0u16;
+                    for (bit, present) in [
+                        changes.x.is_some(),
+                        changes.y.is_some(),
+                        changes.width.is_some(),
+                        changes.height.is_some(),
+                        changes.border_width.is_some(),
+                        changes.sibling.is_some(),
+                        changes.stack_mode.is_some(),
+                    ]
+                    .into_iter()
+                    .enumerate()
+                    {
+                        if present {
+                            value_mask |= 1 << bit;
+                        }
+                    }
+                    let mut event = vec![0u8; 32];
+                    crate::protocol::events::ConfigureRequestEvent {
+                        sequence: self.next_event_sequence(),
+                        stack_mode: changes.stack_mode.map_or(0, |m| m as u8),
+                        parent: info.parent,
+                        window,
+                        sibling: changes.sibling.unwrap_or(Window::NONE),
+                        x: changes.x.unwrap_or(info.x),
+                        y: changes.y.unwrap_or(info.y),
+                        width: changes.width.unwrap_or(info.width),
+                        height: changes.height.unwrap_or(info.height),
+                        border_width: changes.border_width.unwrap_or(info.border_width),
+                        value_mask,
+                    }
+                    .encode(&mut event);
+                    self.client_events.entry(wm).or_default().push(event);
+                    log::debug!(
+                        "ConfigureWindow 0x{:x} redirected to client {}",
+                        window.id().get(),
+                        wm
+                    );
+                    return Ok(());
+                }
             }
-            // Bit 4: line-width
-            if value_mask & (1 << 4) != 0 {
-                dst.line_width = src_values.line_width;
+        }
+
+        if let Some(&backend_window) = self.windows.get(&window) {
+            let config = crate::backend::WindowConfig {
+                x: changes.x,
+                y: changes.y,
+                width: changes.width,
+                height: changes.height,
+                border_width: changes.border_width,
+                stack_mode: changes.stack_mode,
+            };
+            self.backend.configure_window(backend_window, config)?;
+        }
+
+        if let Some(mode) = changes.stack_mode {
+            self.restack_window(window, changes.sibling, mode);
+        }
+
+        // Update window_info with new geometry
+        let (x, y, width, height, border_width) = {
+            let info = self.window_info.get_mut(&window).unwrap();
+            if let Some(new_x) = changes.x {
+                info.x = new_x;
             }
-            // Bit 5: line-style
-            if value_mask & (1 << 5) != 0 {
-                dst.line_style = src_values.line_style;
+            if let Some(new_y) = changes.y {
+                info.y = new_y;
             }
-            // Bit 6: cap-style
-            if value_mask & (1 << 6) != 0 {
-                dst.cap_style = src_values.cap_style;
+            if let Some(new_width) = changes.width {
+                info.width = new_width;
             }
-            // Bit 7: join-style
-            if value_mask & (1 << 7) != 0 {
-                dst.join_style = src_values.join_style;
+            if let Some(new_height) = changes.height {
+                info.height = new_height;
             }
-            // Bit 8: fill-style
-            if value_mask & (1 << 8) != 0 {
-                dst.fill_style = src_values.fill_style;
+            if let Some(new_border_width) = changes.border_width {
+                info.border_width = new_border_width;
             }
-            // Note: font (bit 14) is tracked separately in the server, not in BackendGC
-            log::debug!(
-                "CopyGC: copied attributes from 0x{:x} to 0x{:x} (mask=0x{:x})",
-                src_gc.id().get(),
-                dst_gc.id().get(),
-                value_mask
-            );
-        } else {
-            log::warn!("CopyGC: destination GC 0x{:x} not found", dst_gc.id().get());
+            (info.x, info.y, info.width, info.height, info.border_width)
+        };
+
+        let above_sibling = self.sibling_below(window);
+        let mut event = vec![0u8; 32];
+        crate::protocol::events::ConfigureNotifyEvent {
+            sequence: self.next_event_sequence(),
+            event: window,
+            window,
+            above_sibling,
+            x,
+            y,
+            width,
+            height,
+            border_width,
+            override_redirect: info.override_redirect,
+        }
+        .encode(&mut event);
+        self.deliver_structure_event(window, info.parent, event);
+
+        // Growing a mapped window exposes it again
+        let grew = changes.width.is_some_and(|w| w > info.width)
+            || changes.height.is_some_and(|h| h > info.height);
+        if grew {
+            self.expose_subtree(window);
         }
         Ok(())
     }
@@ -1631,139 +2075,10 @@ impl Server {
         format: u8,
     ) -> Result<(u8, u32, Vec<u8>), Box<dyn Error + Send + Sync>> {
         let backend_drawable = self.get_backend_drawable(drawable)?;
-
         self.backend
             .get_image(backend_drawable, x, y, width, height, plane_mask, format)
     }
 
-    /// Draw text
-    pub fn draw_text(
-        &mut self,
-        drawable: Drawable,
-        gc: GContext,
-        x: i16,
-        y: i16,
-        text: &str,
-    ) -> Result<(), Box<dyn Error + Send + Sync>> {
-        let backend_gc = match self.gcs.get(&gc) {
-            Some(gc) => gc,
-            None => return Err("Invalid GC".into()),
-        };
-
-        let backend_drawable = self.get_backend_drawable(drawable)?;
-
-        self.backend
-            .draw_text(backend_drawable, backend_gc, x, y, text)?;
-        self.backend.flush()?;
-        Ok(())
-    }
-
-    /// Open a font
-    pub fn open_font(&mut self, font_id: u32, font_name: &str) {
-        log::debug!("Opening font: id=0x{:x}, name={}", font_id, font_name);
-
-        // Try to get real font metrics from the backend
-        let font_info = if let Ok(Some(metrics)) = self.backend.query_font_metrics(font_name) {
-            log::debug!(
-                "Got font metrics from backend: ascent={}, descent={}, char_width={}",
-                metrics.ascent,
-                metrics.descent,
-                metrics.char_width
-            );
-            FontInfo {
-                name: font_name.to_string(),
-                ascent: metrics.ascent,
-                descent: metrics.descent,
-                char_width: metrics.char_width as i16,
-                min_char: 0,
-                max_char: 255,
-            }
-        } else {
-            // Fall back to default metrics
-            FontInfo::new(font_name)
-        };
-
-        self.fonts.insert(font_id, font_info);
-    }
-
-    /// Close a font
-    pub fn close_font(&mut self, font_id: u32) {
-        log::debug!("Closing font: id=0x{:x}", font_id);
-        self.fonts.remove(&font_id);
-    }
-
-    /// Query font information
-    pub fn query_font(&self, font_id: u32) -> Option<&FontInfo> {
-        self.fonts.get(&font_id)
-    }
-
-    /// List fonts matching a pattern
-    /// Pattern uses X11 font pattern syntax: * matches any sequence, ? matches any char
-    pub fn list_fonts(&self, pattern: &str, max_names: u16) -> Vec<String> {
-        // Built-in fonts that we can report
-        let available_fonts = vec![
-            "fixed",
-            "6x10",
-            "6x12",
-            "6x13",
-            "7x13",
-            "7x14",
-            "8x13",
-            "8x16",
-            "9x15",
-            "9x18",
-            "10x20",
-            "cursor",
-            "-misc-fixed-medium-r-normal--13-120-75-75-c-80-iso8859-1",
-            "-misc-fixed-medium-r-normal--15-140-75-75-c-90-iso8859-1",
-            "-misc-fixed-bold-r-normal--13-120-75-75-c-80-iso8859-1",
-            "-misc-fixed-bold-r-normal--15-140-75-75-c-90-iso8859-1",
-        ];
-
-        // Convert X11 glob pattern to simple matching
-        let pattern_lower = pattern.to_lowercase();
-        let is_wildcard_all = pattern == "*";
-
-        let mut result: Vec<String> = available_fonts
-            .into_iter()
-            .filter(|font| {
-                if is_wildcard_all {
-                    true
-                } else {
-                    // Simple pattern matching: convert * to any sequence
-                    let font_lower = font.to_lowercase();
-                    if pattern_lower.contains('*') {
-                        // Split pattern by * and check each part
-                        let parts: Vec<&str> = pattern_lower.split('*').collect();
-                        let mut pos = 0;
-                        for (i, part) in parts.iter().enumerate() {
-                            if part.is_empty() {
-                                continue;
-                            }
-                            if let Some(found_pos) = font_lower[pos..].find(part) {
-                                // First part must match at start
-                                if i == 0 && found_pos != 0 {
-                                    return false;
-                                }
-                                pos += found_pos + part.len();
-                            } else {
-                                return false;
-                            }
-                        }
-                        true
-                    } else {
-                        font_lower.contains(&pattern_lower)
-                    }
-                }
-            })
-            .map(|s| s.to_string())
-            .collect();
-
-        // Limit to max_names
-        result.truncate(max_names as usize);
-        result
-    }
-
     /// Convert RGB values (0-65535 range) to a pixel value
     /// For TrueColor, this packs the RGB into 0xRRGGBB format
     pub fn alloc_color(&self, red: u16, green: u16, blue: u16) -> u32 {
@@ -1903,12 +2218,148 @@ impl Server {
     pub fn free_pixmap(&mut self, pixmap_id: u32) -> Result<(), Box<dyn Error + Send + Sync>> {
         if let Some(backend_id) = self.pixmaps.remove(&pixmap_id) {
             self.pixmap_info.remove(&pixmap_id);
-            self.backend.free_pixmap(backend_id)?;
+            // Tiles, stipples and window backgrounds outlive the pixmap ID
+            self.retained_pixmaps.push(backend_id);
+            self.release_unused_pixmaps()?;
             log::debug!("Freed pixmap 0x{:x} (backend {})", pixmap_id, backend_id);
         }
         Ok(())
     }
 
+    /// Free retained backend pixmaps that nothing uses any more
+    fn release_unused_pixmaps(&mut self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        if self.retained_pixmaps.is_empty() {
+            return Ok(());
+        }
+        let mut in_use: Vec<usize> = Vec::new();
+        for gc in self.gcs.values() {
+            in_use.extend(gc.tile.iter().chain(&gc.stipple).map(|p| p.pixmap));
+        }
+        for info in self.window_info.values() {
+            if let WindowBackground::Pixmap(pattern) = info.background {
+                in_use.push(pattern.pixmap);
+            }
+            if let WindowBorder::Pixmap(pattern) = info.border {
+                in_use.push(pattern.pixmap);
+            }
+        }
+        let (kept, unused) = std::mem::take(&mut self.retained_pixmaps)
+            .into_iter()
+            .partition(|id| in_use.contains(id));
+        self.retained_pixmaps = kept;
+        for backend_id in unused {
+            self.backend.free_pixmap(backend_id)?;
+        }
+        Ok(())
+    }
+
+    /// The background a window value list sets, if any
+    ///
+    /// A background pixel takes precedence over a background pixmap.
+    fn resolve_background(
+        &self,
+        paint: &WindowPaintValues,
+    ) -> Result<Option<WindowBackground>, Box<dyn Error + Send + Sync>> {
+        Ok(match (paint.background_pixel, paint.background_pixmap) {
+            (Some(pixel), _) => Some(WindowBackground::Pixel(pixel)),
+            (None, Some(0)) => Some(WindowBackground::None),
+            (None, Some(1)) => Some(WindowBackground::ParentRelative),
+            (None, Some(pixmap)) => Some(WindowBackground::Pixmap(self.gc_pattern(pixmap)?)),
+            (None, None) => None,
+        })
+    }
+
+    /// The border a window value list sets, if any
+    ///
+    /// A border pixel takes precedence over a border pixmap; CopyFromParent
+    /// takes the parent's border.
+    fn resolve_border(
+        &self,
+        parent: Window,
+        paint: &WindowPaintValues,
+    ) -> Result<Option<WindowBorder>, Box<dyn Error + Send + Sync>> {
+        Ok(match (paint.border_pixel, paint.border_pixmap) {
+            (Some(pixel), _) => Some(WindowBorder::Pixel(pixel)),
+            (None, Some(0)) => self.window_info.get(&parent).map(|info| info.border),
+            (None, Some(pixmap)) => Some(WindowBorder::Pixmap(self.gc_pattern(pixmap)?)),
+            (None, None) => None,
+        })
+    }
+
+    /// A window's background with ParentRelative resolved, and the origin
+    /// its tile is aligned to, in window coordinates
+    fn effective_background(&self, window: Window) -> (WindowBackground, i16, i16) {
+        let (mut current, mut x, mut y) = (window, 0i16, 0i16);
+        while let Some(info) = self.window_info.get(&current) {
+            if info.background != WindowBackground::ParentRelative {
+                return (info.background, x, y);
+            }
+            let inset = info.border_width as i16;
+            x = x.wrapping_sub(info.x.wrapping_add(inset));
+            y = y.wrapping_sub(info.y.wrapping_add(inset));
+            current = info.parent;
+        }
+        (WindowBackground::None, 0, 0)
+    }
+
+    /// Paint part of a window with its background
+    ///
+    /// Returns false, painting nothing, if the background is None.
+    fn paint_background(
+        &mut self,
+        window: Window,
+        x: i16,
+        y: i16,
+        width: u16,
+        height: u16,
+    ) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let Some(&backend_window) = self.windows.get(&window) else {
+            return Ok(false);
+        };
+        let (background, ts_x_origin, ts_y_origin) = self.effective_background(window);
+        let gc = match background {
+            WindowBackground::None | WindowBackground::ParentRelative => return Ok(false),
+            WindowBackground::Pixel(pixel) => BackendGC {
+                foreground: pixel,
+                ..Default::default()
+            },
+            WindowBackground::Pixmap(tile) => BackendGC {
+                fill_style: FillStyle::Tiled,
+                tile: Some(tile),
+                ts_x_origin,
+                ts_y_origin,
+                ..Default::default()
+            },
+        };
+        self.backend.fill_rectangle(
+            BackendDrawable::Window(backend_window),
+            &gc,
+            x,
+            y,
+            width,
+            height,
+        )?;
+        Ok(true)
+    }
+
+    /// Paint an exposed area of a window with its background if that is a
+    /// tile; backends already restore solid backgrounds themselves
+    fn paint_exposed_tile(&mut self, window: Window, x: i16, y: i16, width: u16, height: u16) {
+        if !matches!(
+            self.effective_background(window).0,
+            WindowBackground::Pixmap(_)
+        ) {
+            return;
+        }
+        if let Err(e) = self.paint_background(window, x, y, width, height) {
+            log::warn!(
+                "Failed to paint background of window 0x{:x}: {}",
+                window.id().get(),
+                e
+            );
+        }
+    }
+
     /// Get pixmap info (dimensions)
     pub fn get_pixmap_info(&self, pixmap_id: u32) -> Option<&PixmapInfo> {
         self.pixmap_info.get(&pixmap_id)
@@ -1962,6 +2413,15 @@ impl Server {
                 drawable,
                 format,
                 component_alpha: false,
+                repeat: Repeat::None,
+                alpha_map: 0,
+                alpha_x_origin: 0,
+                alpha_y_origin: 0,
+                clip_x_origin: 0,
+                clip_y_origin: 0,
+                clip_rectangles: None,
+                transform: None,
+                filter: Filter::Nearest,
             },
         );
     }
@@ -1971,6 +2431,7 @@ impl Server {
         log::debug!("Freeing picture 0x{:x}", picture_id);
         self.pictures.remove(&picture_id);
         self.solid_fills.remove(&picture_id);
+        self.gradients.remove(&picture_id);
     }
 
     /// Get a picture by ID
@@ -2011,62 +2472,6 @@ impl Server {
         self.solid_fills.get(&picture_id)
     }
 
-    /// Render trapezoids using the RENDER extension
-    #[allow(clippy::too_many_arguments)]
-    pub fn render_trapezoids(
-        &mut self,
-        _op: u8,
-        src_picture: u32,
-        dst_picture: u32,
-        _mask_format: u32,
-        src_x: i16,
-        src_y: i16,
-        trapezoids: &[RenderTrapezoid],
-    ) -> Result<(), Box<dyn Error + Send + Sync>> {
-        // Get the source color (from solid fill or picture)
-        let (r, g, b, a) = if let Some(solid) = self.get_solid_fill(src_picture) {
-            (
-                (solid.red >> 8) as u8,
-                (solid.green >> 8) as u8,
-                (solid.blue >> 8) as u8,
-                (solid.alpha >> 8) as u8,
-            )
-        } else {
-            // Default to opaque black if not a solid fill
-            (0, 0, 0, 255)
-        };
-
-        log::debug!(
-            "render_trapezoids: src=0x{:x} dst=0x{:x} color=rgba({},{},{},{}) src_offset=({},{}) {} trapezoids",
-            src_picture,
-            dst_picture,
-            r, g, b, a,
-            src_x, src_y,
-            trapezoids.len()
-        );
-
-        // Get the destination drawable from the picture
-        let dst_drawable = if let Some(picture) = self.pictures.get(&dst_picture) {
-            picture.drawable
-        } else {
-            log::warn!("Destination picture 0x{:x} not found", dst_picture);
-            return Ok(());
-        };
-
-        // Resolve to backend drawable
-        let drawable = self.resolve_drawable(dst_drawable);
-        let backend_drawable = self.get_backend_drawable(drawable)?;
-
-        // Convert RGBA to pixel value
-        let color = ((a as u32) << 24) | ((r as u32) << 16) | ((g as u32) << 8) | (b as u32);
-
-        self.backend
-            .fill_trapezoids(backend_drawable, color, trapezoids)?;
-        self.backend.flush()?;
-
-        Ok(())
-    }
-
     /// Poll backend events and queue them for delivery to clients
     pub fn poll_and_queue_events(&mut self) {
         use crate::backend::BackendEvent;
@@ -2081,7 +2486,9 @@ impl Server {
             }
         };
 
-        for event in events {
+        for mut event in events {
+            self.track_input(&mut event);
+
             // Map backend window to X11 window
             let x11_window = match &event {
                 BackendEvent::KeyPress { window, .. }
@@ -2105,8 +2512,34 @@ impl Server {
                         continue;
                     }
                 }
+                BackendEvent::MappingChanged => {
+                    self.refresh_keyboard_mapping();
+                    continue;
+                }
             };
 
+            // Rooted backends report all input against the root window; it
+            // is routed through the server-side window tree instead
+            if x11_window == self.root_window {
+                self.route_root_event(event);
+                continue;
+            }
+
+            // Grabs are enforced in the window tree, so while any exist
+            // input from other windows is routed there in root coordinates
+            if self.grabs.any() {
+                if let BackendEvent::KeyPress { x, y, .. }
+                | BackendEvent::KeyRelease { x, y, .. }
+                | BackendEvent::ButtonPress { x, y, .. }
+                | BackendEvent::ButtonRelease { x, y, .. }
+                | BackendEvent::MotionNotify { x, y, .. } = &mut event
+                {
+                    (*x, *y) = self.window_to_root_coords(x11_window, *x, *y);
+                    self.route_root_event(event);
+                    continue;
+                }
+            }
+
             // Get window info for event mask checking
             let (event_mask, _width, _height) =
                 if let Some(info) = self.window_info.get(&x11_window) {
@@ -2137,7 +2570,7 @@ impl Server {
             let seq = self.event_sequence;
 
             // Convert backend event to X11 wire format (32 bytes)
-            let encoded = match event {
+            let (target_window, mask, encoded) = match event {
                 BackendEvent::KeyPress {
                     keycode,
                     state,
@@ -2163,20 +2596,24 @@ impl Server {
                         x11_window.id().get(),
                         target_window.id().get()
                     );
-                    Self::encode_key_event(
-                        2, // KeyPress event code
-                        keycode,
-                        seq,
-                        Timestamp::new(time),
-                        self.root_window,
+                    (
                         target_window,
-                        Window::new(0), // child
-                        x,
-                        y,
-                        x,
-                        y,
-                        state,
-                        true,
+                        event_mask::KEY_PRESS,
+                        Self::encode_key_event(
+                            2, // KeyPress event code
+                            keycode,
+                            seq,
+                            Timestamp::new(time),
+                            self.root_window,
+                            target_window,
+                            Window::new(0), // child
+                            x,
+                            y,
+                            x,
+                            y,
+                            state,
+                            true,
+                        ),
                     )
                 }
                 BackendEvent::KeyRelease {
@@ -2198,20 +2635,24 @@ impl Server {
                             );
                             continue;
                         };
-                    Self::encode_key_event(
-                        3, // KeyRelease event code
-                        keycode,
-                        seq,
-                        Timestamp::new(time),
-                        self.root_window,
+                    (
                         target_window,
-                        Window::new(0),
-                        x,
-                        y,
-                        x,
-                        y,
-                        state,
-                        true,
+                        event_mask::KEY_RELEASE,
+                        Self::encode_key_event(
+                            3, // KeyRelease event code
+                            keycode,
+                            seq,
+                            Timestamp::new(time),
+                            self.root_window,
+                            target_window,
+                            Window::new(0),
+                            x,
+                            y,
+                            x,
+                            y,
+                            state,
+                            true,
+                        ),
                     )
                 }
                 BackendEvent::ButtonPress {
@@ -2233,20 +2674,24 @@ impl Server {
                             );
                             continue;
                         };
-                    Self::encode_key_event(
-                        4, // ButtonPress event code
-                        button,
-                        seq,
-                        Timestamp::new(time),
-                        self.root_window,
+                    (
                         target_window,
-                        Window::new(0),
-                        x,
-                        y,
-                        x,
-                        y,
-                        state,
-                        true,
+                        event_mask::BUTTON_PRESS,
+                        Self::encode_key_event(
+                            4, // ButtonPress event code
+                            button,
+                            seq,
+                            Timestamp::new(time),
+                            self.root_window,
+                            target_window,
+                            Window::new(0),
+                            x,
+                            y,
+                            x,
+                            y,
+                            state,
+                            true,
+                        ),
                     )
                 }
                 BackendEvent::ButtonRelease {
@@ -2268,20 +2713,24 @@ impl Server {
                             );
                             continue;
                         };
-                    Self::encode_key_event(
-                        5, // ButtonRelease event code
-                        button,
-                        seq,
-                        Timestamp::new(time),
-                        self.root_window,
+                    (
                         target_window,
-                        Window::new(0),
-                        x,
-                        y,
-                        x,
-                        y,
-                        state,
-                        true,
+                        event_mask::BUTTON_RELEASE,
+                        Self::encode_key_event(
+                            5, // ButtonRelease event code
+                            button,
+                            seq,
+                            Timestamp::new(time),
+                            self.root_window,
+                            target_window,
+                            Window::new(0),
+                            x,
+                            y,
+                            x,
+                            y,
+                            state,
+                            true,
+                        ),
                     )
                 }
                 BackendEvent::MotionNotify {
@@ -2291,20 +2740,24 @@ impl Server {
                     if event_mask & 0x0040 == 0 {
                         continue;
                     }
-                    Self::encode_key_event(
-                        6, // MotionNotify event code
-                        0, // detail (Normal)
-                        seq,
-                        Timestamp::new(time),
-                        self.root_window,
+                    (
                         x11_window,
-                        Window::new(0),
-                        x,
-                        y,
-                        x,
-                        y,
-                        state,
-                        true,
+                        event_mask::POINTER_MOTION,
+                        Self::encode_key_event(
+                            6, // MotionNotify event code
+                            0, // detail (Normal)
+                            seq,
+                            Timestamp::new(time),
+                            self.root_window,
+                            x11_window,
+                            Window::new(0),
+                            x,
+                            y,
+                            x,
+                            y,
+                            state,
+                            true,
+                        ),
                     )
                 }
                 BackendEvent::FocusIn { .. } => {
@@ -2325,7 +2778,11 @@ impl Server {
                         x11_window.id().get(),
                         target_window.id().get()
                     );
-                    Self::encode_focus_event(9, 0, seq, target_window, 0) // Normal detail, Normal mode
+                    (
+                        target_window,
+                        event_mask::FOCUS_CHANGE,
+                        Self::encode_focus_event(9, 0, seq, target_window, 0),
+                    ) // Normal detail, Normal mode
                 }
                 BackendEvent::FocusOut { .. } => {
                     // Find window that wants FocusChange events (bit 21 = 0x200000)
@@ -2345,28 +2802,37 @@ impl Server {
                         x11_window.id().get(),
                         target_window.id().get()
                     );
-                    Self::encode_focus_event(10, 0, seq, target_window, 0)
+                    (
+                        target_window,
+                        event_mask::FOCUS_CHANGE,
+                        Self::encode_focus_event(10, 0, seq, target_window, 0),
+                    )
                 }
                 BackendEvent::EnterNotify { x, y, time, .. } => {
                     // Check if window wants EnterWindow events (bit 4)
                     if event_mask & 0x0010 == 0 {
                         continue;
                     }
-                    Self::encode_enter_leave_event(
-                        7, // EnterNotify
-                        0, // detail (Ancestor)
-                        seq,
-                        Timestamp::new(time),
-                        self.root_window,
+                    let state = self.pointer_state();
+                    (
                         x11_window,
-                        Window::new(0),
-                        x,
-                        y,
-                        x,
-                        y,
-                        0, // state
-                        0, // mode (Normal)
-                        3, // same_screen_focus (same screen = 1, focus = 2)
+                        event_mask::ENTER_WINDOW,
+                        Self::encode_enter_leave_event(
+                            7, // EnterNotify
+                            0, // detail (Ancestor)
+                            seq,
+                            Timestamp::new(time),
+                            self.root_window,
+                            x11_window,
+                            Window::new(0),
+                            x,
+                            y,
+                            x,
+                            y,
+                            state,
+                            0, // mode (Normal)
+                            3, // same_screen_focus (same screen = 1, focus = 2)
+                        ),
                     )
                 }
                 BackendEvent::LeaveNotify { x, y, time, .. } => {
@@ -2374,21 +2840,26 @@ impl Server {
                     if event_mask & 0x0020 == 0 {
                         continue;
                     }
-                    Self::encode_enter_leave_event(
-                        8, // LeaveNotify
-                        0,
-                        seq,
-                        Timestamp::new(time),
-                        self.root_window,
+                    let state = self.pointer_state();
+                    (
                         x11_window,
-                        Window::new(0),
-                        x,
-                        y,
-                        x,
-                        y,
-                        0,
-                        0,
-                        3,
+                        event_mask::LEAVE_WINDOW,
+                        Self::encode_enter_leave_event(
+                            8, // LeaveNotify
+                            0,
+                            seq,
+                            Timestamp::new(time),
+                            self.root_window,
+                            x11_window,
+                            Window::new(0),
+                            x,
+                            y,
+                            x,
+                            y,
+                            state,
+                            0,
+                            3,
+                        ),
                     )
                 }
                 BackendEvent::Expose {
@@ -2398,11 +2869,16 @@ impl Server {
                     height,
                     ..
                 } => {
+                    self.paint_exposed_tile(x11_window, x as i16, y as i16, width, height);
                     // Check if window wants Exposure events (bit 15)
                     if event_mask & 0x8000 == 0 {
                         continue;
                     }
-                    Self::encode_expose_event(seq, x11_window, x, y, width, height, 0)
+                    (
+                        x11_window,
+                        event_mask::EXPOSURE,
+                        Self::encode_expose_event(seq, x11_window, x, y, width, height, 0),
+                    )
                 }
                 BackendEvent::Configure {
                     x,
@@ -2422,22 +2898,27 @@ impl Server {
                     if event_mask & 0x20000 == 0 {
                         continue;
                     }
-                    Self::encode_configure_notify_event(
-                        seq,
-                        x11_window,
+                    (
                         x11_window,
-                        Window::new(0),
-                        x,
-                        y,
-                        width,
-                        height,
-                        0,
-                        false,
+                        event_mask::STRUCTURE_NOTIFY,
+                        Self::encode_configure_notify_event(
+                            seq,
+                            x11_window,
+                            x11_window,
+                            Window::new(0),
+                            x,
+                            y,
+                            width,
+                            height,
+                            0,
+                            false,
+                        ),
                     )
                 }
                 BackendEvent::DestroyNotify { .. }
                 | BackendEvent::MapNotify { .. }
-                | BackendEvent::UnmapNotify { .. } => {
+                | BackendEvent::UnmapNotify { .. }
+                | BackendEvent::MappingChanged => {
                     // These are handled elsewhere or not commonly needed
                     continue;
                 }
@@ -2446,14 +2927,11 @@ impl Server {
             log::debug!(
                 "Queuing event type {} for window 0x{:x}",
                 encoded[0],
-                x11_window.id().get()
+                target_window.id().get()
             );
 
-            // Queue the encoded event for the window
-            self.pending_events
-                .entry(x11_window)
-                .or_default()
-                .push(encoded);
+            // Queue the encoded event for the clients that selected it
+            self.deliver_event(target_window, mask, encoded);
         }
     }
 
@@ -0,0 +1,374 @@
+//! Pixel regions
+//!
+//! Regions are kept as bands of rows, each with the sorted, disjoint spans
+//! of columns it covers; adjacent bands with the same spans are merged, so
+//! equal regions compare equal. SHAPE window shapes and XFIXES regions are
+//! built on them.
+
+use crate::protocol::{Rectangle, X11Error};
+
+/// Rows `y1..y2` covering the columns of `spans`
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Band {
+    y1: i32,
+    y2: i32,
+    spans: Vec<(i32, i32)>,
+}
+
+/// A set of pixels
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Region {
+    /// Bands from top to bottom, not overlapping
+    bands: Vec<Band>,
+}
+
+impl Region {
+    /// A region covering one rectangle
+    pub fn rectangle(rect: Rectangle) -> Self {
+        Self::from_rectangles(&[rect])
+    }
+
+    /// The union of a list of rectangles
+    pub fn from_rectangles(rects: &[Rectangle]) -> Self {
+        // Rectangles as (y1, y2, x1, x2), in order of their tops
+        let mut rects: Vec<(i32, i32, i32, i32)> = rects
+            .iter()
+            .filter(|r| r.width > 0 && r.height > 0)
+            .map(|r| {
+                let (x, y) = (r.x as i32, r.y as i32);
+                (y, y + r.height as i32, x, x + r.width as i32)
+            })
+            .collect();
+        rects.sort_unstable();
+        let mut ys: Vec<i32> = rects.iter().flat_map(|r| [r.0, r.1]).collect();
+        ys.sort_unstable();
+        ys.dedup();
+
+        // Sweep down the band edges, keeping the spans of the rectangles
+        // crossing the band as (x1, x2, bottom), sorted
+        let mut region = Region::default();
+        let mut active: Vec<(i32, i32, i32)> = Vec::new();
+        let mut next = 0;
+        for pair in ys.windows(2) {
+            let (y1, y2) = (pair[0], pair[1]);
+            active.retain(|&(_, _, bottom)| bottom > y1);
+            while let Some(&(top, bottom, x1, x2)) = rects.get(next).filter(|r| r.0 == y1) {
+                let at = active.partition_point(|&span| span < (x1, x2, top));
+                active.insert(at, (x1, x2, bottom));
+                next += 1;
+            }
+            region.push_band(y1, y2, merge_spans(active.iter().map(|s| (s.0, s.1))));
+        }
+        region
+    }
+
+    /// The pixels set in a `width` x `height` bitmap, row by row
+    pub fn from_bitmap(width: u16, height: u16, bits: &[bool]) -> Self {
+        let mut region = Region::default();
+        for (y, row) in bits
+            .chunks_exact(width.max(1) as usize)
+            .take(height as usize)
+            .enumerate()
+        {
+            let mut spans = Vec::new();
+            let mut start = None;
+            for (x, &set) in row.iter().chain([&false]).enumerate() {
+                match (set, start) {
+                    (true, None) => start = Some(x as i32),
+                    (false, Some(x1)) => {
+                        spans.push((x1, x as i32));
+                        start = None;
+                    }
+                    _ => {}
+                }
+            }
+            region.push_band(y as i32, y as i32 + 1, spans);
+        }
+        region
+    }
+
+    /// Append a band below the others, merging it into the last one if it
+    /// continues it
+    fn push_band(&mut self, y1: i32, y2: i32, spans: Vec<(i32, i32)>) {
+        if spans.is_empty() || y1 >= y2 {
+            return;
+        }
+        if let Some(last) = self.bands.last_mut() {
+            if last.y2 == y1 && last.spans == spans {
+                last.y2 = y2;
+                return;
+            }
+        }
+        self.bands.push(Band { y1, y2, spans });
+    }
+
+    /// The spans of the band covering row `y`
+    fn spans_at(&self, y: i32) -> &[(i32, i32)] {
+        let index = self.bands.partition_point(|band| band.y2 <= y);
+        match self.bands.get(index) {
+            Some(band) if band.y1 <= y => &band.spans,
+            _ => &[],
+        }
+    }
+
+    /// Combine two regions pixel by pixel, walking both band lists once;
+    /// `op` must leave pixels in neither region out
+    fn combine(&self, other: &Region, op: fn(bool, bool) -> bool) -> Region {
+        let (a, b) = (&self.bands, &other.bands);
+        let mut region = Region::default();
+        let Some(mut y) = a.iter().chain(b).map(|band| band.y1).min() else {
+            return region;
+        };
+        let (mut i, mut j) = (0, 0);
+        loop {
+            while a.get(i).is_some_and(|band| band.y2 <= y) {
+                i += 1;
+            }
+            while b.get(j).is_some_and(|band| band.y2 <= y) {
+                j += 1;
+            }
+            if i == a.len() && j == b.len() {
+                return region;
+            }
+            let (spans_a, end_a) = band_from(a.get(i), y);
+            let (spans_b, end_b) = band_from(b.get(j), y);
+            let y2 = end_a.min(end_b);
+            region.push_band(y, y2, combine_spans(spans_a, spans_b, op));
+            y = y2;
+        }
+    }
+
+    pub fn union(&self, other: &Region) -> Region {
+        self.combine(other, |a, b| a || b)
+    }
+
+    pub fn intersect(&self, other: &Region) -> Region {
+        self.combine(other, |a, b| a && b)
+    }
+
+    /// The pixels of this region that are not in `other`
+    pub fn subtract(&self, other: &Region) -> Region {
+        self.combine(other, |a, b| a && !b)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bands.is_empty()
+    }
+
+    /// Move the region by (`dx`, `dy`); BadValue, leaving it unchanged, if a
+    /// coordinate would overflow
+    pub fn translate(&mut self, dx: i32, dy: i32) -> Result<(), X11Error> {
+        let moved = |v: i32, d: i32| v.checked_add(d).ok_or(X11Error::bad_value(0, d as u32, 0));
+        let mut bands = Vec::with_capacity(self.bands.len());
+        for band in &self.bands {
+            let spans = band
+                .spans
+                .iter()
+                .map(|&(x1, x2)| Ok((moved(x1, dx)?, moved(x2, dx)?)))
+                .collect::<Result<_, X11Error>>()?;
+            bands.push(Band {
+                y1: moved(band.y1, dy)?,
+                y2: moved(band.y2, dy)?,
+                spans,
+            });
+        }
+        self.bands = bands;
+        Ok(())
+    }
+
+    /// Whether the region contains a pixel
+    pub fn contains(&self, x: i32, y: i32) -> bool {
+        self.spans_at(y).iter().any(|&(x1, x2)| x1 <= x && x < x2)
+    }
+
+    /// The smallest rectangle containing the region; empty for an empty
+    /// region
+    pub fn extents(&self) -> Rectangle {
+        let (Some(first), Some(last)) = (self.bands.first(), self.bands.last()) else {
+            return Rectangle {
+                x: 0,
+                y: 0,
+                width: 0,
+                height: 0,
+            };
+        };
+        let x1 = self.bands.iter().map(|b| b.spans[0].0).min().unwrap_or(0);
+        let x2 = self
+            .bands
+            .iter()
+            .map(|b| b.spans[b.spans.len() - 1].1)
+            .max()
+            .unwrap_or(0);
+        to_rectangle(x1, first.y1, x2, last.y2)
+    }
+
+    /// The region as y-x banded rectangles
+    pub fn rectangles(&self) -> Vec<Rectangle> {
+        self.bands
+            .iter()
+            .flat_map(|band| {
+                band.spans
+                    .iter()
+                    .map(|&(x1, x2)| to_rectangle(x1, band.y1, x2, band.y2))
+            })
+            .collect()
+    }
+}
+
+/// A rectangle from its corners, clamped to protocol coordinates
+fn to_rectangle(x1: i32, y1: i32, x2: i32, y2: i32) -> Rectangle {
+    let clamp = |v: i32| v.clamp(i16::MIN as i32, i16::MAX as i32);
+    Rectangle {
+        x: clamp(x1) as i16,
+        y: clamp(y1) as i16,
+        width: (x2 - clamp(x1)).clamp(0, u16::MAX as i32) as u16,
+        height: (y2 - clamp(y1)).clamp(0, u16::MAX as i32) as u16,
+    }
+}
+
+/// The spans of `band` at row `y`, none if it starts further down, and the
+/// row where that changes
+fn band_from(band: Option<&Band>, y: i32) -> (&[(i32, i32)], i32) {
+    match band {
+        Some(band) if band.y1 <= y => (&band.spans, band.y2),
+        Some(band) => (&[], band.y1),
+        None => (&[], i32::MAX),
+    }
+}
+
+/// Merge sorted, possibly overlapping spans
+fn merge_spans(spans: impl IntoIterator<Item = (i32, i32)>) -> Vec<(i32, i32)> {
+    let mut merged: Vec<(i32, i32)> = Vec::new();
+    for (x1, x2) in spans {
+        match merged.last_mut() {
+            Some(last) if x1 <= last.1 => last.1 = last.1.max(x2),
+            _ => merged.push((x1, x2)),
+        }
+    }
+    merged
+}
+
+/// Combine two sorted, disjoint span lists, walking both once; `op` must
+/// leave columns in neither list out
+fn combine_spans(
+    a: &[(i32, i32)],
+    b: &[(i32, i32)],
+    op: fn(bool, bool) -> bool,
+) -> Vec<(i32, i32)> {
+    // Whether `span` covers column `x`, and the column where that changes
+    let span_from = |span: Option<&(i32, i32)>, x: i32| match span {
+        Some(&(x1, x2)) if x1 <= x => (true, x2),
+        Some(&(x1, _)) => (false, x1),
+        None => (false, i32::MAX),
+    };
+    let mut spans: Vec<(i32, i32)> = Vec::new();
+    let Some(mut x) = a.iter().chain(b).map(|span| span.0).min() else {
+        return spans;
+    };
+    let (mut i, mut j) = (0, 0);
+    loop {
+        while a.get(i).is_some_and(|span| span.1 <= x) {
+            i += 1;
+        }
+        while b.get(j).is_some_and(|span| span.1 <= x) {
+            j += 1;
+        }
+        if i == a.len() && j == b.len() {
+            return spans;
+        }
+        let (in_a, end_a) = span_from(a.get(i), x);
+        let (in_b, end_b) = span_from(b.get(j), x);
+        let x2 = end_a.min(end_b);
+        if op(in_a, in_b) {
+            match spans.last_mut() {
+                Some(last) if last.1 == x => last.1 = x2,
+                _ => spans.push((x, x2)),
+            }
+        }
+        x = x2;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rect(x: i16, y: i16, width: u16, height: u16) -> Rectangle {
+        Rectangle {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    #[test]
+    fn test_region_ops() {
+        let a = Region::rectangle(rect(0, 0, 10, 10));
+        let b = Region::rectangle(rect(5, 5, 10, 10));
+        let union = a.union(&b);
+        assert_eq!(union.extents(), rect(0, 0, 15, 15));
+        assert_eq!(
+            union.rectangles(),
+            [rect(0, 0, 10, 5), rect(0, 5, 15, 5), rect(5, 10, 10, 5)]
+        );
+        assert_eq!(a.intersect(&b).rectangles(), [rect(5, 5, 5, 5)]);
+        let hole = a.subtract(&Region::rectangle(rect(2, 2, 6, 6)));
+        assert!(hole.contains(1, 5) && !hole.contains(5, 5) && hole.contains(8, 5));
+        assert_eq!(hole.rectangles().len(), 4);
+        // Equal regions compare equal however they were built
+        assert_eq!(
+            Region::from_rectangles(&[rect(0, 0, 10, 5), rect(0, 5, 10, 5)]),
+            a
+        );
+        assert!(a.subtract(&a).is_empty());
+
+        let mut moved = b.clone();
+        moved.translate(-5, -5).unwrap();
+        assert_eq!(moved, a);
+        // Coordinates that would overflow are BadValue and leave it as it was
+        assert!(moved.translate(i32::MAX, 0).is_err());
+        assert_eq!(moved, a);
+
+        // Overlapping rectangles agree with a pixel by pixel check
+        let mut seed = 1u32;
+        let mut random = |n: u32| {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (seed >> 16) % n
+        };
+        let mut rects = || -> Vec<Rectangle> {
+            (0..12)
+                .map(|_| {
+                    let (x, y) = (random(30) as i16, random(30) as i16);
+                    rect(x, y, random(12) as u16, random(12) as u16)
+                })
+                .collect()
+        };
+        let (ra, rb) = (rects(), rects());
+        let (a, b) = (Region::from_rectangles(&ra), Region::from_rectangles(&rb));
+        let covers = |rects: &[Rectangle], x: i32, y: i32| {
+            rects.iter().any(|r| {
+                (r.x as i32..r.x as i32 + r.width as i32).contains(&x)
+                    && (r.y as i32..r.y as i32 + r.height as i32).contains(&y)
+            })
+        };
+        let (union, both, only_a) = (a.union(&b), a.intersect(&b), a.subtract(&b));
+        for y in -1..45 {
+            for x in -1..45 {
+                let (in_a, in_b) = (covers(&ra, x, y), covers(&rb, x, y));
+                assert_eq!(a.contains(x, y), in_a);
+                assert_eq!(union.contains(x, y), in_a || in_b);
+                assert_eq!(both.contains(x, y), in_a && in_b);
+                assert_eq!(only_a.contains(x, y), in_a && !in_b);
+            }
+        }
+
+        // A 3x2 bitmap with a diagonal gap
+        let bits = [true, false, true, true, true, false];
+        let bitmap = Region::from_bitmap(3, 2, &bits);
+        assert_eq!(
+            bitmap.rectangles(),
+            [rect(0, 0, 1, 1), rect(2, 0, 1, 1), rect(0, 1, 2, 1)]
+        );
+    }
+}
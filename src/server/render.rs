@@ -0,0 +1,575 @@
+//! RENDER picture attributes and compositing
+//!
+//! Pictures keep their attributes as the client set them; for a Composite
+//! they are resolved into the backend's CompositePicture, with drawables,
+//! sizes, formats and clips looked up here.
+
+use super::{GradientPicture, Region, Server};
+use crate::backend::composite::{
+    CompositePicture, Filter, Gradient, GradientShape, Operator, PictFormat, PictureSource, Repeat,
+    Transform,
+};
+use crate::backend::{trapezoids, RenderTrapezoid};
+use crate::protocol::*;
+use std::error::Error;
+use std::sync::Arc;
+
+/// Picture value-mask bits, in value-list order
+pub mod picture_mask {
+    pub const REPEAT: u32 = 1 << 0;
+    pub const ALPHA_MAP: u32 = 1 << 1;
+    pub const ALPHA_X_ORIGIN: u32 = 1 << 2;
+    pub const ALPHA_Y_ORIGIN: u32 = 1 << 3;
+    pub const CLIP_X_ORIGIN: u32 = 1 << 4;
+    pub const CLIP_Y_ORIGIN: u32 = 1 << 5;
+    pub const CLIP_MASK: u32 = 1 << 6;
+    pub const GRAPHICS_EXPOSURE: u32 = 1 << 7;
+    pub const SUBWINDOW_MODE: u32 = 1 << 8;
+    pub const POLY_EDGE: u32 = 1 << 9;
+    pub const POLY_MODE: u32 = 1 << 10;
+    pub const DITHER: u32 = 1 << 11;
+    pub const COMPONENT_ALPHA: u32 = 1 << 12;
+}
+
+/// The storage format of a picture format ID from QueryPictFormats
+pub(super) fn pict_format(format: u32, depth: u8) -> PictFormat {
+    match format {
+        1 => PictFormat::Argb32,
+        2 => PictFormat::Rgb24,
+        3 => PictFormat::A8,
+        4 => PictFormat::A1,
+        _ => PictFormat::for_depth(depth),
+    }
+}
+
+/// Pair the set bits of a picture value mask with their values
+fn picture_values(
+    value_mask: u32,
+    values: &[u32],
+) -> Result<Vec<(u32, u32)>, Box<dyn Error + Send + Sync>> {
+    let mut values = values.iter().copied();
+    (0..13)
+        .map(|bit| 1 << bit)
+        .filter(|flag| value_mask & flag != 0)
+        .map(|flag| {
+            let value = values.next().ok_or_else(|| X11Error::bad_length(0, 0))?;
+            Ok((flag, value))
+        })
+        .collect()
+}
+
+/// A RENDER color as a premultiplied pixel
+fn solid_color(red: u16, green: u16, blue: u16, alpha: u16) -> u32 {
+    let alpha = (alpha >> 8) as u32;
+    let premultiply = |v: u16| (v >> 8) as u32 * alpha / 255;
+    alpha << 24 | premultiply(red) << 16 | premultiply(green) << 8 | premultiply(blue)
+}
+
+fn parse_repeat(value: u32) -> Result<Repeat, X11Error> {
+    u8::try_from(value)
+        .ok()
+        .and_then(Repeat::from_u8)
+        .ok_or_else(|| X11Error::bad_value(0, value, 0))
+}
+
+/// The area a destination picture can be drawn in
+fn picture_extents(picture: &CompositePicture) -> Rectangle {
+    let (width, height) = match picture.source {
+        PictureSource::Drawable { width, height, .. } => (width, height),
+        _ => (0, 0),
+    };
+    Rectangle {
+        x: 0,
+        y: 0,
+        width,
+        height,
+    }
+}
+
+impl Server {
+    /// Apply a CreatePicture/ChangePicture value list
+    pub fn change_picture(
+        &mut self,
+        picture_id: u32,
+        value_mask: u32,
+        values: &[u32],
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let values = picture_values(value_mask, values)?;
+        if let Some(gradient) = self.gradients.get_mut(&picture_id) {
+            // Of the attributes, only the repeat mode applies to a gradient
+            for (flag, value) in values {
+                if flag == picture_mask::REPEAT {
+                    gradient.repeat = parse_repeat(value)?;
+                }
+            }
+            return Ok(());
+        }
+        if !self.pictures.contains_key(&picture_id) {
+            return Err(X11Error::bad_picture(0, picture_id, 0).into());
+        }
+        for (flag, value) in values {
+            // A clip mask is turned into rectangles before borrowing the picture
+            let clip_mask = if flag == picture_mask::CLIP_MASK && value != 0 {
+                Some(self.clip_mask_rectangles(value)?)
+            } else {
+                None
+            };
+            if flag == picture_mask::ALPHA_MAP && value != 0 && !self.pictures.contains_key(&value)
+            {
+                return Err(X11Error::bad_picture(0, value, 0).into());
+            }
+            let picture = self.pictures.get_mut(&picture_id).unwrap();
+            match flag {
+                picture_mask::REPEAT => picture.repeat = parse_repeat(value)?,
+                picture_mask::ALPHA_MAP => picture.alpha_map = value,
+                picture_mask::ALPHA_X_ORIGIN => picture.alpha_x_origin = value as i16,
+                picture_mask::ALPHA_Y_ORIGIN => picture.alpha_y_origin = value as i16,
+                picture_mask::CLIP_X_ORIGIN => picture.clip_x_origin = value as i16,
+                picture_mask::CLIP_Y_ORIGIN => picture.clip_y_origin = value as i16,
+                picture_mask::CLIP_MASK => picture.clip_rectangles = clip_mask,
+                picture_mask::COMPONENT_ALPHA => picture.component_alpha = value != 0,
+                // Exposures, subwindow mode, edges and dithering don't change
+                // what compositing draws here
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Create a gradient picture from 16.16 fixed-point stop offsets and
+    /// their (red, green, blue, alpha) colors
+    pub fn create_gradient(
+        &mut self,
+        picture_id: u32,
+        shape: GradientShape,
+        offsets: &[i32],
+        colors: &[[u16; 4]],
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        if offsets.is_empty() || offsets.windows(2).any(|pair| pair[0] > pair[1]) {
+            return Err(X11Error::bad_value(0, offsets.len() as u32, 0).into());
+        }
+        let stops = offsets
+            .iter()
+            .zip(colors)
+            .map(|(&offset, color)| {
+                let channel = |v: u16| v as f64 / 65535.0;
+                (
+                    offset as f64 / 65536.0,
+                    [
+                        channel(color[3]),
+                        channel(color[0]),
+                        channel(color[1]),
+                        channel(color[2]),
+                    ],
+                )
+            })
+            .collect();
+        log::debug!("Creating gradient 0x{:x}: {:?}", picture_id, shape);
+        self.gradients.insert(
+            picture_id,
+            GradientPicture {
+                gradient: Arc::new(Gradient { shape, stops }),
+                repeat: Repeat::None,
+                transform: None,
+            },
+        );
+        Ok(())
+    }
+
+    /// Set the clip origin and clip rectangles of a picture
+    pub fn set_picture_clip_rectangles(
+        &mut self,
+        picture_id: u32,
+        clip_x_origin: i16,
+        clip_y_origin: i16,
+        rectangles: Vec<Rectangle>,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let picture = self
+            .pictures
+            .get_mut(&picture_id)
+            .ok_or_else(|| X11Error::bad_picture(0, picture_id, 0))?;
+        picture.clip_x_origin = clip_x_origin;
+        picture.clip_y_origin = clip_y_origin;
+        picture.clip_rectangles = Some(rectangles);
+        Ok(())
+    }
+
+    /// Set a picture's transform from its nine 16.16 fixed-point entries
+    pub fn set_picture_transform(
+        &mut self,
+        picture_id: u32,
+        matrix: [i32; 9],
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        const IDENTITY: [i32; 9] = [0x10000, 0, 0, 0, 0x10000, 0, 0, 0, 0x10000];
+        let transform = (matrix != IDENTITY).then(|| {
+            let entry = |i: usize| matrix[i] as f64 / 65536.0;
+            Transform([
+                [entry(0), entry(1), entry(2)],
+                [entry(3), entry(4), entry(5)],
+                [entry(6), entry(7), entry(8)],
+            ])
+        });
+        if let Some(gradient) = self.gradients.get_mut(&picture_id) {
+            gradient.transform = transform;
+            return Ok(());
+        }
+        let picture = self
+            .pictures
+            .get_mut(&picture_id)
+            .ok_or_else(|| X11Error::bad_picture(0, picture_id, 0))?;
+        picture.transform = transform;
+        Ok(())
+    }
+
+    /// Set a picture's filter by name, with 16.16 fixed-point parameters
+    pub fn set_picture_filter(
+        &mut self,
+        picture_id: u32,
+        name: &str,
+        params: &[i32],
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let filter = match name {
+            "nearest" | "fast" => Filter::Nearest,
+            "bilinear" | "good" | "best" => Filter::Bilinear,
+            "convolution" => {
+                // Width and height come first, then the kernel row by row
+                let size = |i: usize| {
+                    params
+                        .get(i)
+                        .map(|&v| v >> 16)
+                        .filter(|&v| v > 0 && v % 2 == 1)
+                        .ok_or_else(|| X11Error::bad_value(0, params.len() as u32, 0))
+                };
+                let (width, height) = (size(0)? as usize, size(1)? as usize);
+                if params.len() != 2 + width * height {
+                    return Err(X11Error::bad_value(0, params.len() as u32, 0).into());
+                }
+                Filter::Convolution {
+                    width,
+                    height,
+                    kernel: params[2..].iter().map(|&v| v as f64 / 65536.0).collect(),
+                }
+            }
+            _ => return Err(X11Error::bad_name(0, 0).into()),
+        };
+        if self.gradients.contains_key(&picture_id) {
+            // Gradients are computed at each point, so there is nothing to filter
+            return Ok(());
+        }
+        let picture = self
+            .pictures
+            .get_mut(&picture_id)
+            .ok_or_else(|| X11Error::bad_picture(0, picture_id, 0))?;
+        picture.filter = filter;
+        Ok(())
+    }
+
+    /// Composite `src` through `mask` (0 for none) onto `dst` (RENDER
+    /// Composite)
+    #[allow(clippy::too_many_arguments)]
+    pub fn render_composite(
+        &mut self,
+        op: u8,
+        src: u32,
+        mask: u32,
+        dst: u32,
+        src_origin: (i16, i16),
+        mask_origin: (i16, i16),
+        area: Rectangle,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let op = Operator::from_u8(op).ok_or_else(|| X11Error::bad_value(0, op as u32, 0))?;
+        let src = self.composite_picture(src)?;
+        let mask = match mask {
+            0 => None,
+            mask => Some(self.composite_picture(mask)?),
+        };
+        let dst = self.composite_picture(dst)?;
+        self.backend
+            .composite(op, &src, mask.as_ref(), &dst, src_origin, mask_origin, area)
+    }
+
+    /// Composite `src` onto `dst` through the coverage of `shapes` (RENDER
+    /// Trapezoids, Triangles, TriStrip and TriFan)
+    ///
+    /// Each shape is a list of trapezoids. The source is aligned so that
+    /// `src_origin` falls on `reference`, the request's first vertex. With a
+    /// mask format all shapes are added into one mask of that format;
+    /// otherwise each is composited through its own anti-aliased mask.
+    #[allow(clippy::too_many_arguments)]
+    pub fn render_shapes(
+        &mut self,
+        op: u8,
+        src: u32,
+        dst: u32,
+        mask_format: u32,
+        src_origin: (i16, i16),
+        reference: (i32, i32),
+        shapes: &[Vec<RenderTrapezoid>],
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let op = Operator::from_u8(op).ok_or_else(|| X11Error::bad_value(0, op as u32, 0))?;
+        let src = self.composite_picture(src)?;
+        let dst = self.composite_picture(dst)?;
+        let clip = picture_extents(&dst);
+        let (ref_x, ref_y) = (reference.0 >> 16, reference.1 >> 16);
+
+        let mut draw = |trapezoids: &[RenderTrapezoid], format| {
+            let Some((area, pixels)) = trapezoids::rasterize(trapezoids, format, clip) else {
+                return Ok(());
+            };
+            let mask = CompositePicture::new(PictureSource::Pixels {
+                width: area.width,
+                height: area.height,
+                pixels: Arc::new(pixels),
+            });
+            let src_at = (
+                (src_origin.0 as i32 + area.x as i32 - ref_x) as i16,
+                (src_origin.1 as i32 + area.y as i32 - ref_y) as i16,
+            );
+            self.backend
+                .composite(op, &src, Some(&mask), &dst, src_at, (0, 0), area)
+        };
+        if mask_format == 0 {
+            for shape in shapes {
+                draw(shape, PictFormat::A8)?;
+            }
+            return Ok(());
+        }
+        let format = match pict_format(mask_format, 8) {
+            PictFormat::A1 => PictFormat::A1,
+            _ => PictFormat::A8,
+        };
+        draw(&shapes.concat(), format)
+    }
+
+    /// Add the coverage of trapezoids, moved by `offset`, into a picture's
+    /// alpha (RENDER AddTraps)
+    pub fn add_traps(
+        &mut self,
+        picture_id: u32,
+        offset: (i16, i16),
+        trapezoids: &[RenderTrapezoid],
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let dst = self.composite_picture(picture_id)?;
+        let format = match dst.source {
+            PictureSource::Drawable {
+                format: PictFormat::A1,
+                ..
+            } => PictFormat::A1,
+            _ => PictFormat::A8,
+        };
+        let trapezoids: Vec<_> = trapezoids
+            .iter()
+            .map(|t| t.translate(offset.0 as i32, offset.1 as i32))
+            .collect();
+        let Some((area, pixels)) =
+            trapezoids::rasterize(&trapezoids, format, picture_extents(&dst))
+        else {
+            return Ok(());
+        };
+        let mask = CompositePicture::new(PictureSource::Pixels {
+            width: area.width,
+            height: area.height,
+            pixels: Arc::new(pixels),
+        });
+        let white = CompositePicture::new(PictureSource::Solid(0xffff_ffff));
+        self.backend.composite(
+            Operator::Add,
+            &white,
+            Some(&mask),
+            &dst,
+            (0, 0),
+            (0, 0),
+            area,
+        )
+    }
+
+    /// Composite a (red, green, blue, alpha) color onto rectangles of a
+    /// picture (RENDER FillRectangles)
+    pub fn render_fill_rectangles(
+        &mut self,
+        op: u8,
+        dst: u32,
+        color: [u16; 4],
+        rectangles: &[Rectangle],
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let op = Operator::from_u8(op).ok_or_else(|| X11Error::bad_value(0, op as u32, 0))?;
+        let src = CompositePicture::new(PictureSource::Solid(solid_color(
+            color[0], color[1], color[2], color[3],
+        )));
+        let mut dst = self.composite_picture(dst)?;
+        // The rectangles become the destination clip of a single composite
+        let mut region = Region::from_rectangles(rectangles);
+        if let Some(clip) = &dst.clip {
+            region = region.intersect(&Region::from_rectangles(clip));
+        }
+        if region.is_empty() {
+            return Ok(());
+        }
+        let area = region.extents();
+        dst.clip = Some(region.rectangles());
+        self.backend
+            .composite(op, &src, None, &dst, (0, 0), (0, 0), area)
+    }
+
+    /// Resolve a picture or solid fill for the compositor
+    pub(super) fn composite_picture(
+        &self,
+        picture_id: u32,
+    ) -> Result<CompositePicture, Box<dyn Error + Send + Sync>> {
+        self.resolve_picture(picture_id, true)
+    }
+
+    fn resolve_picture(
+        &self,
+        picture_id: u32,
+        with_alpha_map: bool,
+    ) -> Result<CompositePicture, Box<dyn Error + Send + Sync>> {
+        if let Some(solid) = self.solid_fills.get(&picture_id) {
+            let color = solid_color(solid.red, solid.green, solid.blue, solid.alpha);
+            return Ok(CompositePicture::new(PictureSource::Solid(color)));
+        }
+        if let Some(gradient) = self.gradients.get(&picture_id) {
+            let mut resolved =
+                CompositePicture::new(PictureSource::Gradient(gradient.gradient.clone()));
+            resolved.repeat = gradient.repeat;
+            resolved.transform = gradient.transform;
+            return Ok(resolved);
+        }
+        let picture = self
+            .pictures
+            .get(&picture_id)
+            .ok_or_else(|| X11Error::bad_picture(0, picture_id, 0))?;
+        let (width, height, depth) = self
+            .drawable_geometry(picture.drawable)
+            .ok_or_else(|| X11Error::bad_drawable(0, Drawable::from_id(picture.drawable), 0))?;
+        let drawable = self.get_backend_drawable(self.resolve_drawable(picture.drawable))?;
+
+        let mut resolved = CompositePicture::new(PictureSource::Drawable {
+            drawable,
+            format: pict_format(picture.format, depth),
+            width,
+            height,
+        });
+        resolved.repeat = picture.repeat;
+        resolved.component_alpha = picture.component_alpha;
+        resolved.transform = picture.transform;
+        resolved.filter = picture.filter.clone();
+        resolved.clip = picture.clip_rectangles.as_ref().map(|rects| {
+            rects
+                .iter()
+                .map(|r| Rectangle {
+                    x: r.x.saturating_add(picture.clip_x_origin),
+                    y: r.y.saturating_add(picture.clip_y_origin),
+                    width: r.width,
+                    height: r.height,
+                })
+                .collect()
+        });
+        // An alpha map's own alpha map is not used
+        if with_alpha_map && picture.alpha_map != 0 {
+            let map = self.resolve_picture(picture.alpha_map, false)?;
+            resolved.alpha_map = Some((
+                Box::new(map),
+                picture.alpha_x_origin,
+                picture.alpha_y_origin,
+            ));
+        }
+        Ok(resolved)
+    }
+
+    /// Width, height and depth of a window or pixmap
+    pub(super) fn drawable_geometry(&self, drawable: u32) -> Option<(u16, u16, u8)> {
+        if let Some(info) = self.pixmap_info.get(&drawable) {
+            return Some((info.width, info.height, info.depth));
+        }
+        let info = self.window_info.get(&Window::new(drawable))?;
+        Some((info.width, info.height, 24))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::null::NullBackend;
+
+    #[test]
+    fn test_picture_values() {
+        let mut server = Server::new(Box::new(NullBackend::new())).unwrap();
+        server.create_pixmap(0x0020_0010, 20, 20, 24).unwrap();
+        server.create_pixmap(0x0020_0011, 20, 20, 8).unwrap();
+        server.create_picture(0x0020_0001, 0x0020_0010, 2);
+        server.create_picture(0x0020_0002, 0x0020_0011, 3);
+        let mask = picture_mask::REPEAT
+            | picture_mask::ALPHA_MAP
+            | picture_mask::ALPHA_X_ORIGIN
+            | picture_mask::COMPONENT_ALPHA;
+        server
+            .change_picture(0x0020_0001, mask, &[2, 0x0020_0002, 0xfffe, 1])
+            .unwrap();
+        server
+            .set_picture_clip_rectangles(
+                0x0020_0001,
+                5,
+                5,
+                vec![Rectangle {
+                    x: 0,
+                    y: 0,
+                    width: 10,
+                    height: 10,
+                }],
+            )
+            .unwrap();
+
+        let resolved = server.composite_picture(0x0020_0001).unwrap();
+        assert_eq!(resolved.repeat, Repeat::Pad);
+        assert!(resolved.component_alpha);
+        assert_eq!(resolved.clip.unwrap()[0].x, 5);
+        let (map, x, _) = resolved.alpha_map.unwrap();
+        assert_eq!(x, -2);
+        assert!(matches!(
+            map.source,
+            PictureSource::Drawable {
+                format: PictFormat::A8,
+                ..
+            }
+        ));
+
+        // Repeat modes stop at Reflect; a short value list is BadLength
+        assert!(server
+            .change_picture(0x0020_0001, picture_mask::REPEAT, &[4])
+            .is_err());
+        assert!(server
+            .change_picture(0x0020_0001, picture_mask::DITHER, &[])
+            .is_err());
+
+        // Unknown pictures are BadPicture
+        let err = server
+            .change_picture(0x0020_0009, picture_mask::REPEAT, &[1])
+            .unwrap_err();
+        let err = err.downcast_ref::<X11Error>().expect("an X11 error");
+        assert_eq!(err.code, ErrorCode::Picture);
+        assert_eq!(err.bad_value, 0x0020_0009);
+
+        server.create_solid_fill(0x0020_0003, 0xffff, 0, 0, 0x8000);
+        let solid = server.composite_picture(0x0020_0003).unwrap();
+        assert!(matches!(solid.source, PictureSource::Solid(0x8080_0000)));
+
+        // Gradients take a repeat mode; their stops must be in order
+        let shape = GradientShape::Linear {
+            p1: (0.0, 0.0),
+            p2: (1.0, 0.0),
+        };
+        let colors = [[0, 0, 0, 0xffff]; 2];
+        server
+            .create_gradient(0x0020_0004, shape.clone(), &[0, 0x10000], &colors)
+            .unwrap();
+        server
+            .change_picture(0x0020_0004, picture_mask::REPEAT, &[3])
+            .unwrap();
+        let gradient = server.composite_picture(0x0020_0004).unwrap();
+        assert_eq!(gradient.repeat, Repeat::Reflect);
+        assert!(server
+            .create_gradient(0x0020_0005, shape, &[0x10000, 0], &colors)
+            .is_err());
+    }
+}
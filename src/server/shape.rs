@@ -0,0 +1,329 @@
+//! SHAPE extension
+//!
+//! Windows may have a bounding shape (their outline, border included), a
+//! clip shape (where their contents show) and an input shape (where they
+//! receive the pointer), each relative to the window's origin inside the
+//! border. Unshaped windows use their rectangles, and an unshaped input
+//! shape follows the bounding shape.
+
+use super::{Region, Server, WindowInfo};
+use crate::backend::ShapeKind;
+use crate::protocol::*;
+
+/// Shape operations
+const SHAPE_SET: u8 = 0;
+const SHAPE_UNION: u8 = 1;
+const SHAPE_INTERSECT: u8 = 2;
+const SHAPE_SUBTRACT: u8 = 3;
+const SHAPE_INVERT: u8 = 4;
+
+/// A window's shapes; None is unshaped
+#[derive(Debug, Clone, Default)]
+pub struct WindowShape {
+    pub bounding: Option<Region>,
+    pub clip: Option<Region>,
+    pub input: Option<Region>,
+}
+
+impl WindowShape {
+    fn get(&self, kind: ShapeKind) -> Option<&Region> {
+        match kind {
+            ShapeKind::Bounding => self.bounding.as_ref(),
+            ShapeKind::Clip => self.clip.as_ref(),
+            ShapeKind::Input => self.input.as_ref(),
+        }
+    }
+
+    fn get_mut(&mut self, kind: ShapeKind) -> &mut Option<Region> {
+        match kind {
+            ShapeKind::Bounding => &mut self.bounding,
+            ShapeKind::Clip => &mut self.clip,
+            ShapeKind::Input => &mut self.input,
+        }
+    }
+}
+
+/// Extents of one shape, and whether it is set (ShapeQueryExtents)
+pub struct ShapeExtents {
+    pub shaped: bool,
+    pub extents: Rectangle,
+}
+
+/// A window's shape of a kind, or the default of an unshaped one
+fn effective_shape(info: &WindowInfo, kind: ShapeKind) -> Region {
+    let border = info.border_width as i16;
+    let outline = || {
+        Region::rectangle(Rectangle {
+            x: -border,
+            y: -border,
+            width: info.width.saturating_add(2 * info.border_width),
+            height: info.height.saturating_add(2 * info.border_width),
+        })
+    };
+    let shape = &info.shape;
+    match kind {
+        ShapeKind::Bounding => shape.bounding.clone().unwrap_or_else(outline),
+        ShapeKind::Clip => shape.clip.clone().unwrap_or_else(|| {
+            Region::rectangle(Rectangle {
+                x: 0,
+                y: 0,
+                width: info.width,
+                height: info.height,
+            })
+        }),
+        ShapeKind::Input => shape
+            .input
+            .clone()
+            .or_else(|| shape.bounding.clone())
+            .unwrap_or_else(outline),
+    }
+}
+
+impl WindowInfo {
+    /// Whether a point relative to the window's origin is inside its
+    /// bounding and input shapes
+    pub(super) fn shape_contains(&self, x: i32, y: i32) -> bool {
+        [&self.shape.bounding, &self.shape.input]
+            .iter()
+            .all(|shape| shape.as_ref().is_none_or(|region| region.contains(x, y)))
+    }
+}
+
+impl Server {
+    /// A window's shape of a kind, its default if unshaped
+    pub fn window_shape(&self, window: Window, kind: ShapeKind) -> Result<Region, X11Error> {
+        self.window_info
+            .get(&window)
+            .map(|info| effective_shape(info, kind))
+            .ok_or_else(|| X11Error::bad_window(0, window, 0))
+    }
+
+    /// Combine a region into one of a window's shapes (ShapeRectangles,
+    /// ShapeMask and ShapeCombine)
+    ///
+    /// `source` is already offset; None makes the window unshaped.
+    pub fn shape_window(
+        &mut self,
+        window: Window,
+        kind: ShapeKind,
+        operation: u8,
+        source: Option<Region>,
+    ) -> Result<(), X11Error> {
+        if operation > SHAPE_INVERT {
+            return Err(X11Error::bad_value(0, operation as u32, 0));
+        }
+        let current = self.window_shape(window, kind)?;
+        if window == self.root_window {
+            return Ok(());
+        }
+        let shape = source.map(|source| match operation {
+            SHAPE_SET => source,
+            SHAPE_UNION => current.union(&source),
+            SHAPE_INTERSECT => current.intersect(&source),
+            SHAPE_SUBTRACT => current.subtract(&source),
+            _ => source.subtract(&current),
+        });
+        if let Some(info) = self.window_info.get_mut(&window) {
+            *info.shape.get_mut(kind) = shape;
+        }
+        self.shape_changed(window, kind);
+        Ok(())
+    }
+
+    /// Move one of a window's shapes (ShapeOffset)
+    pub fn offset_shape(
+        &mut self,
+        window: Window,
+        kind: ShapeKind,
+        x: i16,
+        y: i16,
+    ) -> Result<(), X11Error> {
+        let info = self
+            .window_info
+            .get_mut(&window)
+            .ok_or_else(|| X11Error::bad_window(0, window, 0))?;
+        let Some(shape) = info.shape.get_mut(kind) else {
+            return Ok(());
+        };
+        shape.translate(x as i32, y as i32)?;
+        self.shape_changed(window, kind);
+        Ok(())
+    }
+
+    /// Extents of a window's bounding and clip shapes (ShapeQueryExtents)
+    pub fn shape_extents(&self, window: Window) -> Result<[ShapeExtents; 2], X11Error> {
+        let info = self
+            .window_info
+            .get(&window)
+            .ok_or_else(|| X11Error::bad_window(0, window, 0))?;
+        Ok(
+            [ShapeKind::Bounding, ShapeKind::Clip].map(|kind| ShapeExtents {
+                shaped: info.shape.get(kind).is_some(),
+                extents: effective_shape(info, kind).extents(),
+            }),
+        )
+    }
+
+    /// Select or deselect ShapeNotify events on a window (ShapeSelectInput)
+    pub fn select_shape_input(
+        &mut self,
+        window: Window,
+        client_id: u32,
+        enable: bool,
+    ) -> Result<(), X11Error> {
+        if !self.window_info.contains_key(&window) {
+            return Err(X11Error::bad_window(0, window, 0));
+        }
+        let clients = self.shape_selections.entry(window).or_default();
+        if enable {
+            clients.insert(client_id);
+        } else {
+            clients.remove(&client_id);
+        }
+        Ok(())
+    }
+
+    /// Whether a client selected ShapeNotify events on a window
+    /// (ShapeInputSelected)
+    pub fn shape_input_selected(&self, window: Window, client_id: u32) -> Result<bool, X11Error> {
+        if !self.window_info.contains_key(&window) {
+            return Err(X11Error::bad_window(0, window, 0));
+        }
+        Ok(self
+            .shape_selections
+            .get(&window)
+            .is_some_and(|clients| clients.contains(&client_id)))
+    }
+
+    /// Forward a changed shape to the backend and send ShapeNotify
+    fn shape_changed(&mut self, window: Window, kind: ShapeKind) {
+        let Some(info) = self.window_info.get(&window) else {
+            return;
+        };
+        let shape = info.shape.get(kind).map(Region::rectangles);
+        let extents = effective_shape(info, kind).extents();
+        if let Some(&backend_window) = self.windows.get(&window) {
+            if let Err(e) = self
+                .backend
+                .set_window_shape(backend_window, kind, shape.as_deref())
+            {
+                log::warn!("Failed to shape window 0x{:x}: {}", window.id().get(), e);
+            }
+        }
+
+        let Some(clients) = self.shape_selections.get(&window) else {
+            return;
+        };
+        let Some(first_event) = self.query_extension("SHAPE").map(|e| e.first_event) else {
+            return;
+        };
+        let mut event = vec![0u8; 32];
+        event[0] = first_event; // ShapeNotify
+        event[1] = kind as u8;
+        event[4..8].copy_from_slice(&window.id().get().to_le_bytes());
+        event[8..10].copy_from_slice(&extents.x.to_le_bytes());
+        event[10..12].copy_from_slice(&extents.y.to_le_bytes());
+        event[12..14].copy_from_slice(&extents.width.to_le_bytes());
+        event[14..16].copy_from_slice(&extents.height.to_le_bytes());
+        event[16..20].copy_from_slice(&super::device::current_time().to_le_bytes());
+        event[20] = shape.is_some() as u8;
+        for client in clients.clone() {
+            self.client_events
+                .entry(client)
+                .or_default()
+                .push(event.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::null::NullBackend;
+    use crate::server::WindowPaintValues;
+
+    fn rect(x: i16, y: i16, width: u16, height: u16) -> Rectangle {
+        Rectangle {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    #[test]
+    fn test_window_shapes() {
+        let mut server = Server::new(Box::new(NullBackend::new())).unwrap();
+        let client = server.register_client();
+        let root = server.root_window();
+        let window = Window::new(0x0020_0001);
+        server
+            .create_window(
+                window,
+                root,
+                10,
+                10,
+                20,
+                20,
+                2,
+                WindowClass::InputOutput,
+                VisualID::new(0x21),
+                &WindowPaintValues::default(),
+                0,
+                false,
+            )
+            .unwrap();
+        server.map_window(window, client).unwrap();
+        server.select_shape_input(window, client, true).unwrap();
+        assert!(server.shape_input_selected(window, client).unwrap());
+        assert!(!server.shape_input_selected(window, client + 1).unwrap());
+
+        // Unshaped windows report their outline and inside
+        let [bounding, clip] = server.shape_extents(window).unwrap();
+        assert!(!bounding.shaped && !clip.shaped);
+        assert_eq!(bounding.extents, rect(-2, -2, 24, 24));
+        assert_eq!(clip.extents, rect(0, 0, 20, 20));
+
+        // Punch a hole through the input shape; the pointer falls through it
+        let hole = Region::rectangle(rect(5, 5, 10, 10));
+        server
+            .shape_window(window, ShapeKind::Input, SHAPE_SUBTRACT, Some(hole))
+            .unwrap();
+        assert_eq!(server.window_at_point(14, 14), window);
+        assert_eq!(server.window_at_point(22, 22), root);
+        let events = server.take_client_events(client);
+        assert_eq!(events.len(), 1);
+        assert_eq!((events[0][0], events[0][1], events[0][20]), (64, 2, 1));
+
+        // Offsets move the shape; unshaping restores the default
+        server
+            .offset_shape(window, ShapeKind::Input, 10, 10)
+            .unwrap();
+        assert_eq!(server.window_at_point(22, 22), window);
+        server
+            .shape_window(window, ShapeKind::Input, SHAPE_SET, None)
+            .unwrap();
+        assert_eq!(
+            server
+                .window_shape(window, ShapeKind::Input)
+                .unwrap()
+                .rectangles(),
+            [rect(-2, -2, 24, 24)]
+        );
+        // Invert leaves what the source has and the shape doesn't
+        let wide = Region::rectangle(rect(-10, 0, 50, 1));
+        server
+            .shape_window(window, ShapeKind::Bounding, SHAPE_INVERT, Some(wide))
+            .unwrap();
+        assert_eq!(
+            server
+                .window_shape(window, ShapeKind::Bounding)
+                .unwrap()
+                .rectangles(),
+            [rect(-10, 0, 8, 1), rect(22, 0, 18, 1)]
+        );
+        assert!(server
+            .shape_window(window, ShapeKind::Clip, 5, None)
+            .is_err());
+    }
+}
@@ -0,0 +1,663 @@
+//! MIT-SHM shared memory segments
+//!
+//! Clients hand the server memory either as a SysV segment ID (Attach) or
+//! as a file descriptor passed over the Unix socket (AttachFd), or have the
+//! server create one and pass it back (CreateSegment). Images move between
+//! segments and drawables without going through the socket.
+//!
+//! Passed files are only mapped once sealed against shrinking: a client
+//! truncating a mapped file would make the server's next access raise
+//! SIGBUS. Unsealed files are copied in and out with pread/pwrite.
+//!
+//! Pixmaps live in the backend, so they can't be shared: QueryVersion
+//! reports no shared pixmaps and CreatePixmap fails with BadImplementation.
+
+use super::Server;
+use crate::protocol::{GContext, X11Error};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::File;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::fs::FileExt;
+use std::sync::Arc;
+
+/// Where a segment's bytes live
+enum Storage {
+    /// Mapped into the server; SysV segments are detached with shmdt,
+    /// others unmapped
+    Mapped { addr: *mut u8, sysv: bool },
+    /// A file the client could still shrink, which would make touching a
+    /// mapping of it raise SIGBUS; it is copied in and out instead
+    File(File),
+}
+
+/// A segment attached to the server
+pub(super) struct Segment {
+    storage: Storage,
+    size: usize,
+    /// Whether the server may write to it
+    writable: bool,
+    /// Client that attached it
+    client: u32,
+}
+
+// SAFETY: the mapping is owned by the segment and only accessed by copying
+// in and out of it while the server is locked
+unsafe impl Send for Segment {}
+unsafe impl Sync for Segment {}
+
+impl Segment {
+    /// Attach a SysV segment the client can access
+    fn attach_sysv(
+        shmid: u32,
+        read_only: bool,
+        credentials: (u32, u32),
+        client: u32,
+    ) -> Result<Self, X11Error> {
+        let shmid = shmid as libc::c_int;
+        // SAFETY: shmid_ds is plain data, filled in by IPC_STAT
+        let mut info: libc::shmid_ds = unsafe { std::mem::zeroed() };
+        if unsafe { libc::shmctl(shmid, libc::IPC_STAT, &mut info) } != 0 {
+            return Err(X11Error::bad_access(0, 0));
+        }
+        if !sysv_access(&info.shm_perm, credentials, read_only) {
+            return Err(X11Error::bad_access(0, 0));
+        }
+        let flags = if read_only { libc::SHM_RDONLY } else { 0 };
+        // SAFETY: the kernel picks the address; failure returns (void *)-1
+        let addr = unsafe { libc::shmat(shmid, std::ptr::null(), flags) };
+        if addr as isize == -1 {
+            return Err(X11Error::bad_access(0, 0));
+        }
+        Ok(Segment {
+            storage: Storage::Mapped {
+                addr: addr as *mut u8,
+                sysv: true,
+            },
+            size: info.shm_segsz as usize,
+            writable: !read_only,
+            client,
+        })
+    }
+
+    /// Attach the whole of a file passed by the client
+    ///
+    /// Only files sealed against shrinking are mapped; others are read and
+    /// written with pread/pwrite.
+    fn map_fd(fd: OwnedFd, read_only: bool, client: u32) -> Result<Self, X11Error> {
+        // SAFETY: stat is plain data, filled in by fstat
+        let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+        if unsafe { libc::fstat(fd.as_raw_fd(), &mut stat) } != 0 || stat.st_size <= 0 {
+            return Err(X11Error::bad_access(0, 0));
+        }
+        let size = stat.st_size as usize;
+        if !sealed_against_shrinking(&fd) {
+            return Ok(Segment {
+                storage: Storage::File(File::from(fd)),
+                size,
+                writable: !read_only,
+                client,
+            });
+        }
+        let protection = if read_only {
+            libc::PROT_READ
+        } else {
+            libc::PROT_READ | libc::PROT_WRITE
+        };
+        // SAFETY: a fresh shared mapping of the file; failure returns MAP_FAILED
+        let addr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                size,
+                protection,
+                libc::MAP_SHARED,
+                fd.as_raw_fd(),
+                0,
+            )
+        };
+        if addr == libc::MAP_FAILED {
+            return Err(X11Error::bad_access(0, 0));
+        }
+        Ok(Segment {
+            storage: Storage::Mapped {
+                addr: addr as *mut u8,
+                sysv: false,
+            },
+            size,
+            writable: !read_only,
+            client,
+        })
+    }
+
+    /// Create an anonymous shared file of `size` bytes, sealed against
+    /// shrinking where the system allows, and attach it
+    fn create(size: u32, read_only: bool, client: u32) -> Result<(Self, OwnedFd), X11Error> {
+        if size == 0 {
+            return Err(X11Error::bad_value(0, size, 0));
+        }
+        let fd = anonymous_file().ok_or_else(|| X11Error::bad_alloc(0, 0))?;
+        if unsafe { libc::ftruncate(fd.as_raw_fd(), size as libc::off_t) } != 0 {
+            return Err(X11Error::bad_alloc(0, 0));
+        }
+        seal_against_shrinking(&fd);
+        let copy = fd.try_clone().map_err(|_| X11Error::bad_alloc(0, 0))?;
+        let segment = Segment::map_fd(copy, read_only, client)?;
+        Ok((segment, fd))
+    }
+
+    /// Check that `len` bytes at `offset` lie within the segment
+    fn range(&self, offset: u32, len: usize) -> Result<usize, X11Error> {
+        let offset_bytes = offset as usize;
+        if !offset.is_multiple_of(4) || offset_bytes > self.size {
+            return Err(X11Error::bad_value(0, offset, 0));
+        }
+        if len > self.size - offset_bytes {
+            return Err(X11Error::bad_access(0, 0));
+        }
+        Ok(offset_bytes)
+    }
+
+    /// Copy `len` bytes out of the segment
+    fn read(&self, offset: u32, len: usize) -> Result<Vec<u8>, X11Error> {
+        let start = self.range(offset, len)?;
+        self.read_at(start, len)
+    }
+
+    /// Copy `len` bytes at any byte `start` out of the segment
+    fn read_at(&self, start: usize, len: usize) -> Result<Vec<u8>, X11Error> {
+        if start.checked_add(len).is_none_or(|end| end > self.size) {
+            return Err(X11Error::bad_access(0, 0));
+        }
+        let mut data = vec![0u8; len];
+        match &self.storage {
+            // SAFETY: the bytes were checked to lie within the mapping
+            Storage::Mapped { addr, .. } => unsafe {
+                std::ptr::copy_nonoverlapping(addr.add(start), data.as_mut_ptr(), len)
+            },
+            // A file shrunk since it was attached reads short
+            Storage::File(file) => file
+                .read_exact_at(&mut data, start as u64)
+                .map_err(|_| X11Error::bad_access(0, 0))?,
+        }
+        Ok(data)
+    }
+
+    /// Copy `data` into the segment
+    fn write(&self, offset: u32, data: &[u8]) -> Result<(), X11Error> {
+        if !self.writable {
+            return Err(X11Error::bad_access(0, 0));
+        }
+        let start = self.range(offset, data.len())?;
+        match &self.storage {
+            // SAFETY: range() checked the bytes lie within the writable mapping
+            Storage::Mapped { addr, .. } => unsafe {
+                std::ptr::copy_nonoverlapping(data.as_ptr(), addr.add(start), data.len())
+            },
+            // Never past the size the segment was attached with
+            Storage::File(file) => file
+                .write_all_at(data, start as u64)
+                .map_err(|_| X11Error::bad_access(0, 0))?,
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Segment {
+    fn drop(&mut self) {
+        let Storage::Mapped { addr, sysv } = self.storage else {
+            return;
+        };
+        // SAFETY: addr and size describe the attachment made at creation
+        unsafe {
+            if sysv {
+                libc::shmdt(addr as *const libc::c_void);
+            } else {
+                libc::munmap(addr as *mut libc::c_void, self.size);
+            }
+        }
+    }
+}
+
+/// Whether a user may attach a SysV segment, following its permission bits
+fn sysv_access(perm: &libc::ipc_perm, (uid, gid): (u32, u32), read_only: bool) -> bool {
+    if uid == 0 {
+        return true;
+    }
+    let mode = perm.mode as u32;
+    let bits = if uid == perm.uid || uid == perm.cuid {
+        mode >> 6
+    } else if gid == perm.gid || gid == perm.cgid {
+        mode >> 3
+    } else {
+        mode
+    };
+    let needed = if read_only { 0o4 } else { 0o6 };
+    bits & needed == needed
+}
+
+/// An unlinked file in shared memory
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn anonymous_file() -> Option<OwnedFd> {
+    let flags = libc::MFD_CLOEXEC | libc::MFD_ALLOW_SEALING;
+    // SAFETY: the name is NUL-terminated; a new descriptor is returned
+    let fd = unsafe { libc::memfd_create(c"x11anywhere-shm".as_ptr(), flags) };
+    (fd >= 0).then(|| unsafe { OwnedFd::from_raw_fd(fd) })
+}
+
+/// Whether a file can no longer be shrunk, so mapping it is safe
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn sealed_against_shrinking(fd: &OwnedFd) -> bool {
+    // SAFETY: F_GET_SEALS only reads the descriptor's seals
+    let seals = unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_GET_SEALS) };
+    seals >= 0 && seals & libc::F_SEAL_SHRINK != 0
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn seal_against_shrinking(fd: &OwnedFd) {
+    // SAFETY: adds a seal to a file the server created
+    unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_ADD_SEALS, libc::F_SEAL_SHRINK) };
+}
+
+/// An unlinked file in shared memory
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn anonymous_file() -> Option<OwnedFd> {
+    use std::sync::atomic::{AtomicU32, Ordering};
+    static NEXT: AtomicU32 = AtomicU32::new(0);
+    let name = format!(
+        "/x11anywhere-{}-{}\0",
+        std::process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    );
+    let name = name.as_ptr() as *const libc::c_char;
+    // SAFETY: the name is NUL-terminated; it is unlinked right away
+    let fd = unsafe { libc::shm_open(name, libc::O_RDWR | libc::O_CREAT | libc::O_EXCL, 0o600) };
+    if fd < 0 {
+        return None;
+    }
+    unsafe { libc::shm_unlink(name) };
+    Some(unsafe { OwnedFd::from_raw_fd(fd) })
+}
+
+/// Files can't be sealed here, so none are mapped
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn sealed_against_shrinking(_fd: &OwnedFd) -> bool {
+    false
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn seal_against_shrinking(_fd: &OwnedFd) {}
+
+/// Bits per pixel of ZPixmap images of a depth
+fn bits_per_pixel(depth: u8) -> usize {
+    match depth {
+        1 => 1,
+        2..=8 => 8,
+        9..=16 => 16,
+        _ => 32,
+    }
+}
+
+/// Bytes in a scanline of `width` pixels, padded to 32 bits
+fn scanline_bytes(width: u16, bits: usize) -> usize {
+    (width as usize * bits).div_ceil(32) * 4
+}
+
+/// Bits per pixel and number of planes of an image format (0 = XYBitmap,
+/// 1 = XYPixmap, 2 = ZPixmap)
+fn image_layout(format: u8, depth: u8) -> Result<(usize, usize), X11Error> {
+    match format {
+        0 if depth == 1 => Ok((1, 1)),
+        0 => Err(X11Error::bad_match(0, 0)),
+        1 => Ok((1, depth as usize)),
+        2 => Ok((bits_per_pixel(depth), 1)),
+        _ => Err(X11Error::bad_value(0, format as u32, 0)),
+    }
+}
+
+/// Read the `src` rectangle (x, y, width, height) of an image of `total`
+/// size at byte `offset` of a segment, as the data of a core PutImage
+///
+/// Only the bytes of the rectangle's rows are read.
+fn sub_image(
+    segment: &Segment,
+    offset: usize,
+    (bits, planes): (usize, usize),
+    total: (u16, u16),
+    src: (u16, u16, u16, u16),
+) -> Result<Vec<u8>, X11Error> {
+    let (src_x, src_y, width, height) = src;
+    if width == 0 || height == 0 {
+        return Ok(Vec::new());
+    }
+    let stride = scanline_bytes(total.0, bits);
+    let out_stride = scanline_bytes(width, bits);
+    let mut out = vec![0u8; out_stride * height as usize * planes];
+    // Bytes of each source row the rectangle covers
+    let first = src_x as usize * bits / 8;
+    let last = ((src_x as usize + width as usize) * bits).div_ceil(8);
+    for plane in 0..planes {
+        for row in 0..height as usize {
+            let line = offset + (plane * total.1 as usize + src_y as usize + row) * stride;
+            let line = segment.read_at(line + first, last - first)?;
+            let out_line = (plane * height as usize + row) * out_stride;
+            let out_line = &mut out[out_line..out_line + out_stride];
+            if bits % 8 == 0 {
+                out_line[..line.len()].copy_from_slice(&line);
+            } else {
+                // Bitmaps are LSBFirst
+                for x in 0..width as usize {
+                    let bit = src_x as usize % 8 + x;
+                    if line[bit / 8] & (1 << (bit % 8)) != 0 {
+                        out_line[x / 8] |= 1 << (x % 8);
+                    }
+                }
+            }
+        }
+    }
+    Ok(out)
+}
+
+/// MIT-SHM segments
+#[derive(Default)]
+pub(super) struct ShmState {
+    /// Attached segments by ID
+    segments: HashMap<u32, Arc<Segment>>,
+    /// User and group of clients on the local socket
+    credentials: HashMap<u32, (u32, u32)>,
+}
+
+impl ShmState {
+    /// Detach a disconnected client's segments
+    pub(super) fn remove_client(&mut self, client_id: u32) {
+        self.segments
+            .retain(|_, segment| segment.client != client_id);
+        self.credentials.remove(&client_id);
+    }
+}
+
+impl Server {
+    /// Record the user and group of a client connected over the Unix socket
+    pub fn set_peer_credentials(&mut self, client_id: u32, uid: u32, gid: u32) {
+        self.shm.credentials.insert(client_id, (uid, gid));
+    }
+
+    /// Add a segment under a new ID
+    fn add_segment(&mut self, shmseg: u32, segment: Segment) -> Result<(), X11Error> {
+        if self.shm.segments.contains_key(&shmseg) {
+            return Err(X11Error::bad_id_choice(0, shmseg, 0));
+        }
+        log::debug!(
+            "MIT-SHM: segment 0x{:x} attached ({} bytes, writable={})",
+            shmseg,
+            segment.size,
+            segment.writable
+        );
+        self.shm.segments.insert(shmseg, Arc::new(segment));
+        Ok(())
+    }
+
+    /// A segment by ID
+    fn segment(&self, shmseg: u32) -> Result<Arc<Segment>, X11Error> {
+        self.shm
+            .segments
+            .get(&shmseg)
+            .cloned()
+            .ok_or_else(|| X11Error::bad_shm_seg(0, shmseg, 0))
+    }
+
+    /// Attach a SysV segment (ShmAttach)
+    ///
+    /// Only local clients whose user may access the segment can attach it.
+    pub fn shm_attach(
+        &mut self,
+        client_id: u32,
+        shmseg: u32,
+        shmid: u32,
+        read_only: bool,
+    ) -> Result<(), X11Error> {
+        if self.shm.segments.contains_key(&shmseg) {
+            return Err(X11Error::bad_id_choice(0, shmseg, 0));
+        }
+        let credentials = self
+            .shm
+            .credentials
+            .get(&client_id)
+            .copied()
+            .ok_or_else(|| X11Error::bad_access(0, 0))?;
+        let segment = Segment::attach_sysv(shmid, read_only, credentials, client_id)?;
+        self.add_segment(shmseg, segment)
+    }
+
+    /// Attach a file passed by the client (ShmAttachFd)
+    pub fn shm_attach_fd(
+        &mut self,
+        client_id: u32,
+        shmseg: u32,
+        fd: OwnedFd,
+        read_only: bool,
+    ) -> Result<(), X11Error> {
+        let segment = Segment::map_fd(fd, read_only, client_id)?;
+        self.add_segment(shmseg, segment)
+    }
+
+    /// Create a segment to pass to the client (ShmCreateSegment)
+    pub fn shm_create_segment(
+        &mut self,
+        client_id: u32,
+        shmseg: u32,
+        size: u32,
+        read_only: bool,
+    ) -> Result<OwnedFd, X11Error> {
+        if !self.shm.credentials.contains_key(&client_id) {
+            // Descriptors only pass over the local socket
+            return Err(X11Error::bad_access(0, 0));
+        }
+        let (segment, fd) = Segment::create(size, read_only, client_id)?;
+        self.add_segment(shmseg, segment)?;
+        Ok(fd)
+    }
+
+    /// Detach a segment (ShmDetach); only the client that attached it may
+    pub fn shm_detach(&mut self, client_id: u32, shmseg: u32) -> Result<(), X11Error> {
+        if self.segment(shmseg)?.client != client_id {
+            return Err(X11Error::bad_access(0, 0));
+        }
+        self.shm.segments.remove(&shmseg);
+        Ok(())
+    }
+
+    /// Draw part of an image held in a segment (ShmPutImage)
+    ///
+    /// `total` is the size of the whole image at `offset`, `src` the
+    /// rectangle (x, y, width, height) of it to draw at `dst`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn shm_put_image(
+        &mut self,
+        drawable: u32,
+        gc: u32,
+        total: (u16, u16),
+        src: (u16, u16, u16, u16),
+        dst: (i16, i16),
+        depth: u8,
+        format: u8,
+        shmseg: u32,
+        offset: u32,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let segment = self.segment(shmseg)?;
+        let Some((_, _, drawable_depth)) = self.drawable_geometry(drawable) else {
+            return Err(X11Error::bad_drawable(0, self.resolve_drawable(drawable), 0).into());
+        };
+        if !self.gcs.contains_key(&GContext::new(gc)) {
+            return Err(X11Error::bad_gc(0, GContext::new(gc), 0).into());
+        }
+        let layout = image_layout(format, depth)?;
+        if format != 0 && depth != drawable_depth {
+            return Err(X11Error::bad_match(0, 0).into());
+        }
+        let (src_x, src_y, width, height) = src;
+        if src_x as u32 + width as u32 > total.0 as u32 {
+            return Err(X11Error::bad_value(0, src_x as u32, 0).into());
+        }
+        if src_y as u32 + height as u32 > total.1 as u32 {
+            return Err(X11Error::bad_value(0, src_y as u32, 0).into());
+        }
+        let len = scanline_bytes(total.0, layout.0) * total.1 as usize * layout.1;
+        let start = segment.range(offset, len)?;
+        if width == 0 || height == 0 {
+            return Ok(());
+        }
+        let data = sub_image(&segment, start, layout, total, src)?;
+        let drawable = self.resolve_drawable(drawable);
+        self.put_image(
+            drawable,
+            GContext::new(gc),
+            width,
+            height,
+            dst.0,
+            dst.1,
+            depth,
+            format,
+            &data,
+        )
+    }
+
+    /// Read an image into a segment (ShmGetImage)
+    ///
+    /// Returns the drawable's depth and visual and the image size in bytes.
+    #[allow(clippy::too_many_arguments)]
+    pub fn shm_get_image(
+        &mut self,
+        drawable: u32,
+        x: i16,
+        y: i16,
+        width: u16,
+        height: u16,
+        plane_mask: u32,
+        format: u8,
+        shmseg: u32,
+        offset: u32,
+    ) -> Result<(u8, u32, u32), Box<dyn Error + Send + Sync>> {
+        let segment = self.segment(shmseg)?;
+        if self.drawable_geometry(drawable).is_none() {
+            return Err(X11Error::bad_drawable(0, self.resolve_drawable(drawable), 0).into());
+        }
+        if format != 1 && format != 2 {
+            return Err(X11Error::bad_value(0, format as u32, 0).into());
+        }
+        let drawable = self.resolve_drawable(drawable);
+        let (depth, visual, data) =
+            self.get_image(drawable, x, y, width, height, plane_mask, format)?;
+        segment.write(offset, &data)?;
+        Ok((depth, visual, data.len() as u32))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::null::NullBackend;
+    use crate::protocol::{Drawable, ErrorCode, Pixmap};
+
+    #[test]
+    fn test_segments() {
+        let mut server = Server::new(Box::new(NullBackend::new())).unwrap();
+        // Remote clients can't be handed segments
+        assert!(server
+            .shm_create_segment(1, 0x0020_0001, 4096, false)
+            .is_err());
+        server.set_peer_credentials(1, 1000, 1000);
+        let fd = server
+            .shm_create_segment(1, 0x0020_0001, 4096, false)
+            .unwrap();
+        assert!(server
+            .shm_create_segment(1, 0x0020_0001, 4096, false)
+            .is_err());
+        // The passed descriptor maps the same memory
+        let other = Segment::map_fd(fd.try_clone().unwrap(), false, 1).unwrap();
+        assert!(matches!(other.storage, Storage::Mapped { .. }));
+        other.write(0, &[1, 2, 3, 4]).unwrap();
+        let segment = server.segment(0x0020_0001).unwrap();
+        assert_eq!(segment.read(0, 4).unwrap(), [1, 2, 3, 4]);
+        assert!(segment.read(2, 4).is_err());
+        assert!(segment.read(4096, 4).is_err());
+
+        // GetImage writes into writable segments only
+        server.create_pixmap(0x0020_0010, 4, 4, 24).unwrap();
+        let (depth, _, size) = server
+            .shm_get_image(0x0020_0010, 0, 0, 4, 4, !0, 2, 0x0020_0001, 0)
+            .unwrap();
+        assert_eq!((depth, size), (24, 64));
+        assert_eq!(segment.read(0, 4).unwrap(), [0; 4]);
+        server.shm_attach_fd(1, 0x0020_0002, fd, true).unwrap();
+        assert!(server
+            .shm_get_image(0x0020_0010, 0, 0, 4, 4, !0, 2, 0x0020_0002, 0)
+            .is_err());
+
+        // PutImage reads the source rectangle, which must lie within the
+        // image, and the image within the segment
+        let gc = GContext::new(0x0020_0020);
+        server
+            .create_gc(
+                gc,
+                Drawable::Pixmap(Pixmap::new(0x0020_0010)),
+                &Default::default(),
+            )
+            .unwrap();
+        let put = |server: &mut Server, total, src| {
+            server.shm_put_image(
+                0x0020_0010,
+                0x0020_0020,
+                total,
+                src,
+                (0, 0),
+                24,
+                2,
+                0x0020_0001,
+                0,
+            )
+        };
+        assert!(put(&mut server, (32, 32), (30, 30, 2, 2)).is_ok());
+        assert!(put(&mut server, (32, 32), (31, 30, 2, 2)).is_err());
+        assert!(put(&mut server, (64, 64), (0, 0, 1, 1)).is_err());
+
+        // Only the attaching client detaches
+        let err = server.shm_detach(2, 0x0020_0001).unwrap_err();
+        assert_eq!(err.code, ErrorCode::Access);
+        server.shm_detach(1, 0x0020_0001).unwrap();
+        let err = server.shm_detach(1, 0x0020_0001).unwrap_err();
+        assert_eq!(err.code, ErrorCode::ShmSeg);
+        server.shm.remove_client(1);
+        assert!(server.shm.segments.is_empty());
+    }
+
+    #[test]
+    fn test_unsealed_segment() {
+        // A file the client can still shrink is copied, not mapped, so
+        // shrinking it makes reads fail instead of raising SIGBUS
+        let fd = anonymous_file().unwrap();
+        assert_eq!(unsafe { libc::ftruncate(fd.as_raw_fd(), 4096) }, 0);
+        let segment = Segment::map_fd(fd.try_clone().unwrap(), false, 1).unwrap();
+        assert!(matches!(segment.storage, Storage::File(_)));
+        segment.write(0, &[1, 2, 3, 4]).unwrap();
+        assert_eq!(segment.read(0, 4).unwrap(), [1, 2, 3, 4]);
+        assert_eq!(unsafe { libc::ftruncate(fd.as_raw_fd(), 0) }, 0);
+        assert!(segment.read(0, 4).is_err());
+    }
+
+    #[test]
+    fn test_sub_image() {
+        // A 2x2 corner of a 3x3 ZPixmap, then of a 9x1 bitmap after it
+        let (segment, _) = Segment::create(4096, false, 1).unwrap();
+        segment.write(0, &(0..36).collect::<Vec<u8>>()).unwrap();
+        segment.write(36, &[0b1010_0000, 0b1, 0, 0]).unwrap();
+        let data = sub_image(&segment, 0, (32, 1), (3, 3), (1, 1, 2, 2)).unwrap();
+        assert_eq!(
+            data,
+            [16, 17, 18, 19, 20, 21, 22, 23, 28, 29, 30, 31, 32, 33, 34, 35]
+        );
+        let data = sub_image(&segment, 36, (1, 1), (9, 1), (5, 0, 4, 1)).unwrap();
+        assert_eq!(data, [0b1101, 0, 0, 0]);
+        assert!(sub_image(&segment, 4092, (32, 1), (3, 3), (0, 0, 1, 1)).is_ok());
+        assert!(sub_image(&segment, 4092, (32, 1), (3, 3), (0, 1, 1, 1)).is_err());
+    }
+}
@@ -0,0 +1,503 @@
+//! Fonts and text drawing
+//!
+//! Fonts from the catalog are measured and rasterized by the server: each
+//! glyph is sent to the backend as rectangles covering its set pixels, so
+//! text looks the same on every backend. Fonts only the backend knows are
+//! drawn by the backend itself.
+
+use super::Server;
+use crate::backend::BackendGC;
+use crate::font::{BitmapFont, PropertyValue};
+use crate::protocol::encoder::{FontReplyInfo, TextExtents};
+use crate::protocol::*;
+use std::error::Error;
+use std::sync::Arc;
+
+/// An open font
+#[derive(Debug, Clone)]
+pub struct FontInfo {
+    /// Font name as opened
+    pub name: String,
+    /// Font ascent (pixels above baseline)
+    pub ascent: i16,
+    /// Font descent (pixels below baseline)
+    pub descent: i16,
+    /// Widest character width
+    pub char_width: i16,
+    /// Minimum character code
+    pub min_char: u16,
+    /// Maximum character code
+    pub max_char: u16,
+    /// The server-side font; None for fonts the backend draws
+    pub font: Option<Arc<BitmapFont>>,
+}
+
+impl FontInfo {
+    /// Metrics of a server-side font
+    fn from_bitmap(name: &str, font: Arc<BitmapFont>) -> Self {
+        let (_, max_bounds) = font.bounds();
+        let (_, _, min_char, max_char) = font.char_range();
+        FontInfo {
+            name: name.to_string(),
+            ascent: font.font_ascent,
+            descent: font.font_descent,
+            char_width: max_bounds.character_width,
+            min_char,
+            max_char,
+            font: Some(font),
+        }
+    }
+
+    /// A font measured and drawn by the backend
+    fn backend(name: &str, ascent: i16, descent: i16, char_width: i16) -> Self {
+        FontInfo {
+            name: name.to_string(),
+            ascent,
+            descent,
+            char_width,
+            min_char: 0,
+            max_char: 255,
+            font: None,
+        }
+    }
+}
+
+impl Server {
+    /// Open a font
+    ///
+    /// Fonts come from the server's catalog, then from the backend. Unknown
+    /// names are a BadName error.
+    pub fn open_font(
+        &mut self,
+        font_id: u32,
+        font_name: &str,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        log::debug!("Opening font: id=0x{:x}, name={}", font_id, font_name);
+
+        // An unreadable font file is the font's fault, not the client's
+        let font = self.font_catalog.open(font_name).map_err(|e| {
+            log::warn!("Cannot load font {}: {}", font_name, e);
+            X11Error::bad_name(0, 0)
+        })?;
+        let font_info = if let Some(font) = font {
+            FontInfo::from_bitmap(font_name, font)
+        } else if let Ok(Some(metrics)) = self.backend.query_font_metrics(font_name) {
+            log::debug!(
+                "Got font metrics from backend: ascent={}, descent={}, char_width={}",
+                metrics.ascent,
+                metrics.descent,
+                metrics.char_width
+            );
+            FontInfo::backend(
+                font_name,
+                metrics.ascent,
+                metrics.descent,
+                metrics.char_width as i16,
+            )
+        } else if font_name == "cursor" {
+            // Only used for glyph cursors, which the backend provides
+            FontInfo::backend(font_name, 16, 0, 16)
+        } else {
+            log::debug!("OpenFont: no font named {:?}", font_name);
+            return Err(X11Error::bad_name(0, 0).into());
+        };
+
+        self.fonts.insert(font_id, font_info);
+        Ok(())
+    }
+
+    /// Close a font
+    ///
+    /// GCs using the font keep it until they are changed or freed.
+    pub fn close_font(&mut self, font_id: u32) {
+        log::debug!("Closing font: id=0x{:x}", font_id);
+        self.fonts.remove(&font_id);
+    }
+
+    /// Query font information; `id` may also name a GC, meaning its font
+    pub fn query_font(&mut self, id: u32) -> Option<FontInfo> {
+        if let Some(font) = self.fonts.get(&id) {
+            return Some(font.clone());
+        }
+        let gc = GContext::new(id);
+        if !self.gcs.contains_key(&gc) {
+            return None;
+        }
+        self.gc_font(gc)
+    }
+
+    /// The open font with this ID, or BadFont
+    pub(super) fn font_by_id(
+        &self,
+        font_id: u32,
+    ) -> Result<FontInfo, Box<dyn Error + Send + Sync>> {
+        self.fonts
+            .get(&font_id)
+            .cloned()
+            .ok_or_else(|| X11Error::bad_font(0, font_id, 0).into())
+    }
+
+    /// Switch a GC's font, as a PolyText font-shift item does
+    pub fn set_gc_font(
+        &mut self,
+        gc: GContext,
+        font_id: u32,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let values = super::gc::GCValues {
+            font: Some(font_id),
+            ..Default::default()
+        };
+        self.change_gc(gc, &values)
+    }
+
+    /// Extents of a string drawn with a font, or with a GC's font; None
+    /// if `id` is neither
+    pub fn text_extents(&mut self, id: u32, codes: &[u16]) -> Option<TextExtents> {
+        let info = self.query_font(id)?;
+        Some(match &info.font {
+            Some(font) => text_extents(font, codes),
+            None => cell_extents(&info, codes.len()),
+        })
+    }
+
+    /// The font a GC draws with
+    fn gc_font(&mut self, gc: GContext) -> Option<FontInfo> {
+        if let Some(font) = self.gc_fonts.get(&gc) {
+            return Some(font.clone());
+        }
+        if self.default_font.is_none() {
+            match self.font_catalog.open("fixed") {
+                Ok(Some(font)) => self.default_font = Some(FontInfo::from_bitmap("fixed", font)),
+                Ok(None) => log::warn!("Default font \"fixed\" is missing"),
+                Err(e) => log::warn!("Cannot load default font: {}", e),
+            }
+        }
+        self.default_font.clone()
+    }
+
+    /// Metrics and properties for QueryFont and ListFontsWithInfo replies
+    ///
+    /// Property names and string values are interned as atoms.
+    pub fn font_reply_info(&mut self, info: &FontInfo) -> FontReplyInfo {
+        let Some(font) = &info.font else {
+            // Backend fonts: a uniform cell
+            return FontReplyInfo {
+                max_bounds: crate::font::CharInfo {
+                    left_side_bearing: 0,
+                    right_side_bearing: info.char_width,
+                    character_width: info.char_width,
+                    ascent: info.ascent,
+                    descent: info.descent,
+                    attributes: 0,
+                },
+                min_char_or_byte2: info.min_char,
+                max_char_or_byte2: info.max_char,
+                all_chars_exist: true,
+                font_ascent: info.ascent,
+                font_descent: info.descent,
+                ..Default::default()
+            };
+        };
+
+        let (min_bounds, max_bounds) = font.bounds();
+        let (min_byte1, max_byte1, min_char_or_byte2, max_char_or_byte2) = font.char_range();
+        let mut properties = Vec::with_capacity(font.properties.len());
+        for (name, value) in &font.properties {
+            let Some(name) = self.intern_atom(name, false) else {
+                continue;
+            };
+            let value = match value {
+                PropertyValue::Int(v) => *v as u32,
+                PropertyValue::Atom(s) => match self.intern_atom(s, false) {
+                    Some(atom) => atom.get(),
+                    None => continue,
+                },
+            };
+            properties.push((name.get(), value));
+        }
+
+        FontReplyInfo {
+            min_bounds,
+            max_bounds,
+            min_char_or_byte2,
+            max_char_or_byte2,
+            default_char: font.default_char,
+            min_byte1,
+            max_byte1,
+            all_chars_exist: font.all_chars_exist(),
+            font_ascent: font.font_ascent,
+            font_descent: font.font_descent,
+            properties,
+        }
+    }
+
+    /// List fonts and aliases matching an XLFD pattern
+    pub fn list_fonts(&mut self, pattern: &str, max_names: u16) -> Vec<String> {
+        self.font_catalog.list(pattern, max_names as usize)
+    }
+
+    /// Set the font path; BadValue if an element is not a font directory
+    pub fn set_font_path(&mut self, path: &[String]) -> Result<(), Box<dyn Error + Send + Sync>> {
+        log::debug!("Font path: {:?}", path);
+        self.font_catalog
+            .set_path(path)
+            .map_err(|bad| X11Error::bad_value(0, bad as u32, 0).into())
+    }
+
+    /// The font path
+    pub fn font_path(&self) -> &[String] {
+        self.font_catalog.path()
+    }
+
+    /// Open a listed font for ListFontsWithInfo, without assigning an ID
+    pub fn font_info_by_name(&mut self, name: &str) -> Option<FontInfo> {
+        match self.font_catalog.open(name) {
+            Ok(font) => font.map(|font| FontInfo::from_bitmap(name, font)),
+            Err(e) => {
+                log::warn!("Cannot load font {}: {}", name, e);
+                None
+            }
+        }
+    }
+
+    /// Draw a string of character codes with the GC's font
+    ///
+    /// Image text first fills the string's background box with the GC
+    /// background. Returns the total character width, by which the next
+    /// string's origin advances.
+    pub fn draw_text(
+        &mut self,
+        drawable: Drawable,
+        gc: GContext,
+        x: i16,
+        y: i16,
+        codes: &[u16],
+        image: bool,
+    ) -> Result<i32, Box<dyn Error + Send + Sync>> {
+        let Some(backend_gc) = self.gcs.get(&gc).cloned() else {
+            return Err(X11Error::bad_gc(0, gc, 0).into());
+        };
+        let backend_drawable = self.get_backend_drawable(drawable)?;
+        let Some(font_info) = self.gc_font(gc) else {
+            log::warn!("No font to draw text with");
+            return Ok(0);
+        };
+
+        let Some(font) = font_info.font.clone() else {
+            // Let the backend draw it with its own font, within the cells
+            // the font is measured with
+            let text: String = codes
+                .iter()
+                .filter_map(|&c| char::from_u32(c as u32))
+                .collect();
+            let extents = cell_extents(&font_info, codes.len());
+            let bounds = Rectangle {
+                x: x.saturating_add(extents.overall_left as i16),
+                y: y.saturating_sub(extents.overall_ascent),
+                width: (extents.overall_right - extents.overall_left).clamp(0, u16::MAX as i32)
+                    as u16,
+                height: (extents.overall_ascent + extents.overall_descent).max(0) as u16,
+            };
+            self.backend
+                .draw_text(backend_drawable, &backend_gc, x, y, &text, bounds)?;
+            self.backend.flush()?;
+            return Ok(extents.overall_width);
+        };
+
+        let (rectangles, width) = text_rectangles(&font, x, y, codes);
+        if image {
+            // Image text ignores the function and fill style
+            let solid = BackendGC {
+                function: GCFunction::Copy,
+                fill_style: FillStyle::Solid,
+                ..backend_gc.clone()
+            };
+            let background = BackendGC {
+                foreground: backend_gc.background,
+                ..solid.clone()
+            };
+            let height = (font.font_ascent + font.font_descent).max(0) as u16;
+            if width > 0 && height > 0 {
+                self.backend.fill_rectangle(
+                    backend_drawable,
+                    &background,
+                    x,
+                    y.saturating_sub(font.font_ascent),
+                    width.min(u16::MAX as i32) as u16,
+                    height,
+                )?;
+            }
+            self.backend
+                .fill_rectangles(backend_drawable, &solid, &rectangles)?;
+        } else {
+            self.backend
+                .fill_rectangles(backend_drawable, &backend_gc, &rectangles)?;
+        }
+        self.backend.flush()?;
+        Ok(width)
+    }
+}
+
+/// Rectangles covering the set pixels of a string drawn with its origin
+/// at (`x`, `y`), one per horizontal run, and the string's total width
+fn text_rectangles(font: &BitmapFont, x: i16, y: i16, codes: &[u16]) -> (Vec<Rectangle>, i32) {
+    let mut rectangles = Vec::new();
+    let mut pen = x as i32;
+    for &code in codes {
+        let Some(glyph) = font.glyph(code) else {
+            continue;
+        };
+        let left = pen + glyph.info.left_side_bearing as i32;
+        let top = y as i32 - glyph.info.ascent as i32;
+        for row in 0..glyph.height() {
+            let mut col = 0;
+            while col < glyph.width() {
+                if !glyph.pixel(col, row) {
+                    col += 1;
+                    continue;
+                }
+                let start = col;
+                while col < glyph.width() && glyph.pixel(col, row) {
+                    col += 1;
+                }
+                rectangles.push(Rectangle {
+                    x: (left + start as i32) as i16,
+                    y: (top + row as i32) as i16,
+                    width: (col - start) as u16,
+                    height: 1,
+                });
+            }
+        }
+        pen += glyph.info.character_width as i32;
+    }
+    (rectangles, pen - x as i32)
+}
+
+/// Extents of a string of `count` characters in a backend font, measured
+/// as uniform cells
+fn cell_extents(info: &FontInfo, count: usize) -> TextExtents {
+    let width = count as i32 * info.char_width as i32;
+    let (ascent, descent) = if count == 0 {
+        (0, 0)
+    } else {
+        (info.ascent, info.descent)
+    };
+    TextExtents {
+        font_ascent: info.ascent,
+        font_descent: info.descent,
+        overall_ascent: ascent,
+        overall_descent: descent,
+        overall_width: width,
+        overall_left: 0,
+        overall_right: width,
+    }
+}
+
+/// Extents of a string from its glyphs' metrics
+///
+/// Characters the font lacks are measured as its default character, or
+/// skipped if that is missing too.
+fn text_extents(font: &BitmapFont, codes: &[u16]) -> TextExtents {
+    let mut extents = TextExtents {
+        font_ascent: font.font_ascent,
+        font_descent: font.font_descent,
+        ..Default::default()
+    };
+    let mut first = true;
+    for glyph in codes.iter().filter_map(|&code| font.glyph(code)) {
+        let info = glyph.info;
+        let pen = extents.overall_width;
+        let left = pen + info.left_side_bearing as i32;
+        let right = pen + info.right_side_bearing as i32;
+        if first {
+            extents.overall_ascent = info.ascent;
+            extents.overall_descent = info.descent;
+            extents.overall_left = left;
+            extents.overall_right = right;
+            first = false;
+        } else {
+            extents.overall_ascent = extents.overall_ascent.max(info.ascent);
+            extents.overall_descent = extents.overall_descent.max(info.descent);
+            extents.overall_left = extents.overall_left.min(left);
+            extents.overall_right = extents.overall_right.max(right);
+        }
+        extents.overall_width += info.character_width as i32;
+    }
+    extents
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::font::{CharInfo, Glyph};
+
+    #[test]
+    fn test_text_rectangles() {
+        let font = crate::font::FontCatalog::new()
+            .open("fixed")
+            .unwrap()
+            .unwrap();
+        let (rects, width) = text_rectangles(&font, 10, 20, b"Hi".map(u16::from).as_slice());
+        assert_eq!(width, 12);
+        assert!(!rects.is_empty());
+        // Everything lies inside the two character cells
+        for r in &rects {
+            assert!(r.x >= 10 && r.x as i32 + r.width as i32 <= 22);
+            assert!(r.y >= 20 - 11 && r.y < 20 + 2);
+        }
+    }
+
+    #[test]
+    fn test_unreadable_font() {
+        let dir = std::env::temp_dir().join(format!("x11anywhere-broken-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("broken.pcf"), b"not a font").unwrap();
+        std::fs::write(dir.join("fonts.dir"), "1\nbroken.pcf broken\n").unwrap();
+
+        let mut server = Server::new(Box::new(crate::backend::null::NullBackend::new())).unwrap();
+        server
+            .set_font_path(&[dir.to_string_lossy().into_owned()])
+            .unwrap();
+        // A corrupt font file is BadName, not a dropped connection
+        let err = server.open_font(0x0020_0001, "broken").unwrap_err();
+        let err = err.downcast_ref::<X11Error>().expect("an X11 error");
+        assert_eq!(err.code, ErrorCode::Name);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_text_extents() {
+        let mut font = BitmapFont::default();
+        (font.font_ascent, font.font_descent) = (10, 3);
+        font.default_char = 0xffff;
+        let glyph = |left_side_bearing, right_side_bearing, ascent, descent| Glyph {
+            info: CharInfo {
+                left_side_bearing,
+                right_side_bearing,
+                character_width: 6,
+                ascent,
+                descent,
+                attributes: 0,
+            },
+            bitmap: Default::default(),
+        };
+        font.glyphs.insert(0x41, glyph(-1, 5, 8, 0));
+        font.glyphs.insert(0x4e2d, glyph(0, 8, 9, 2));
+
+        let extents = text_extents(&font, &[0x41, 0x4e2d, 0x42]);
+        assert_eq!(
+            extents,
+            TextExtents {
+                font_ascent: 10,
+                font_descent: 3,
+                overall_ascent: 9,
+                overall_descent: 2,
+                overall_width: 12,
+                overall_left: -1,
+                overall_right: 14,
+            }
+        );
+        assert_eq!(text_extents(&font, &[]).overall_width, 0);
+    }
+}
@@ -0,0 +1,1124 @@
+//! Server-side window tree
+//!
+//! Stacking order, per-client event selection and delivery, window manager
+//! redirection, and routing of input through the window tree. Rooted backends
+//! report all input against the root window; this module decides which X11
+//! window actually receives it, the same way a real X server does.
+
+use super::grab::Grab;
+use super::Server;
+use crate::backend::BackendEvent;
+use crate::protocol::*;
+
+/// Crossing event details (EnterNotify/LeaveNotify)
+const DETAIL_ANCESTOR: u8 = 0;
+const DETAIL_VIRTUAL: u8 = 1;
+const DETAIL_INFERIOR: u8 = 2;
+const DETAIL_NONLINEAR: u8 = 3;
+const DETAIL_NONLINEAR_VIRTUAL: u8 = 4;
+
+/// Crossing event modes
+pub(super) const MODE_NORMAL: u8 = 0;
+pub(super) const MODE_GRAB: u8 = 1;
+pub(super) const MODE_UNGRAB: u8 = 2;
+
+/// Button bits in the key/button state field
+const BUTTON_STATE_MASK: u16 = 0x1f00;
+
+impl Server {
+    /// Next sequence number for a server-generated event
+    pub(super) fn next_event_sequence(&mut self) -> u16 {
+        self.event_sequence = self.event_sequence.wrapping_add(1);
+        self.event_sequence
+    }
+
+    /// Set the event mask a client has selected on a window
+    ///
+    /// The window's `event_mask` in WindowInfo is kept as the union of all
+    /// clients' selections.
+    pub fn select_window_events(&mut self, window: Window, client_id: u32, mask: u32) {
+        let selections = self.event_selections.entry(window).or_default();
+        selections.insert(client_id, mask);
+        let union = selections.values().fold(0, |acc, m| acc | m);
+        if let Some(info) = self.window_info.get_mut(&window) {
+            info.event_mask = union;
+        }
+    }
+
+    /// The event mask a specific client has selected on a window
+    pub fn client_event_mask(&self, window: Window, client_id: u32) -> u32 {
+        self.event_selections
+            .get(&window)
+            .and_then(|selections| selections.get(&client_id))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// Drop all event selections and queued events of a disconnecting client
+    pub(super) fn remove_client_selections(&mut self, client_id: u32) {
+        for (window, selections) in self.event_selections.iter_mut() {
+            if selections.remove(&client_id).is_some() {
+                let union = selections.values().fold(0, |acc, m| acc | m);
+                if let Some(info) = self.window_info.get_mut(window) {
+                    info.event_mask = union;
+                }
+            }
+        }
+        self.client_events.remove(&client_id);
+    }
+
+    /// The client that selected SubstructureRedirect on a window, if any
+    pub(super) fn redirect_client(&self, window: Window) -> Option<u32> {
+        self.event_selections.get(&window).and_then(|selections| {
+            selections
+                .iter()
+                .find(|(_, mask)| *mask & event_mask::SUBSTRUCTURE_REDIRECT != 0)
+                .map(|(client, _)| *client)
+        })
+    }
+
+    /// Queue an event for every client that selected `mask` on `window`
+    ///
+    /// Windows without per-client selections fall back to the shared
+    /// per-window queue. Returns true if anyone will receive the event.
+    pub(super) fn deliver_event(&mut self, window: Window, mask: u32, event: Vec<u8>) -> bool {
+        if let Some(selections) = self.event_selections.get(&window) {
+            let clients: Vec<u32> = selections
+                .iter()
+                .filter(|(_, m)| *m & mask != 0)
+                .map(|(client, _)| *client)
+                .collect();
+            for client in &clients {
+                self.client_events
+                    .entry(*client)
+                    .or_default()
+                    .push(event.clone());
+            }
+            return !clients.is_empty();
+        }
+
+        match self.window_info.get(&window) {
+            Some(info) if info.event_mask & mask != 0 => {
+                self.pending_events.entry(window).or_default().push(event);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Deliver a structure event to the window itself (StructureNotify) and
+    /// to its parent (SubstructureNotify), patching the event window field
+    pub(super) fn deliver_structure_event(
+        &mut self,
+        window: Window,
+        parent: Window,
+        mut event: Vec<u8>,
+    ) {
+        let mut own = event.clone();
+        own[4..8].copy_from_slice(&window.id().get().to_ne_bytes());
+        self.deliver_event(window, event_mask::STRUCTURE_NOTIFY, own);
+
+        if parent != Window::NONE {
+            event[4..8].copy_from_slice(&parent.id().get().to_ne_bytes());
+            self.deliver_event(parent, event_mask::SUBSTRUCTURE_NOTIFY, event);
+        }
+    }
+
+    /// Take the events queued for a client
+    pub fn take_client_events(&mut self, client_id: u32) -> Vec<Vec<u8>> {
+        self.client_events.remove(&client_id).unwrap_or_default()
+    }
+
+    /// Whether a window and all of its ancestors are mapped
+    pub fn is_viewable(&self, window: Window) -> bool {
+        let mut current = window;
+        while current != self.root_window {
+            match self.window_info.get(&current) {
+                Some(info) if info.mapped => current = info.parent,
+                _ => return false,
+            }
+        }
+        true
+    }
+
+    /// Whether `ancestor` is a strict ancestor of `window`
+    pub fn is_ancestor(&self, ancestor: Window, window: Window) -> bool {
+        let mut current = window;
+        while let Some(info) = self.window_info.get(&current) {
+            if info.parent == ancestor {
+                return true;
+            }
+            current = info.parent;
+        }
+        false
+    }
+
+    /// Send Expose for a window that just became viewable, and for all of its
+    /// mapped inferiors
+    pub(super) fn expose_subtree(&mut self, window: Window) {
+        if !self.is_viewable(window) {
+            return;
+        }
+        let mut stack = vec![window];
+        while let Some(current) = stack.pop() {
+            let (width, height) = match self.window_info.get(&current) {
+                Some(info) if info.mapped => (info.width, info.height),
+                _ => continue,
+            };
+            self.paint_exposed_tile(current, 0, 0, width, height);
+            let seq = self.next_event_sequence();
+            let event = Self::encode_expose_event(seq, current, 0, 0, width, height, 0);
+            self.deliver_event(current, event_mask::EXPOSURE, event);
+            stack.extend(self.get_children(current));
+        }
+    }
+
+    /// Move a window within the stacking order
+    pub(super) fn restack_window(
+        &mut self,
+        window: Window,
+        sibling: Option<Window>,
+        mode: StackMode,
+    ) {
+        let Some(pos) = self.stacking_order.iter().position(|w| *w == window) else {
+            return;
+        };
+        self.stacking_order.remove(pos);
+
+        let below = matches!(mode, StackMode::Below | StackMode::BottomIf);
+        let sibling_pos = sibling.and_then(|s| self.stacking_order.iter().position(|w| *w == s));
+        let index = match (below, sibling_pos) {
+            (true, Some(s)) => s,
+            (false, Some(s)) => s + 1,
+            (true, None) => 0,
+            (false, None) => self.stacking_order.len(),
+        };
+        self.stacking_order.insert(index, window);
+    }
+
+    /// The sibling directly below a window in the stacking order
+    pub(super) fn sibling_below(&self, window: Window) -> Window {
+        let parent = match self.window_info.get(&window) {
+            Some(info) => info.parent,
+            None => return Window::NONE,
+        };
+        self.stacking_order
+            .iter()
+            .take_while(|w| **w != window)
+            .filter(|w| {
+                self.window_info
+                    .get(w)
+                    .is_some_and(|info| info.parent == parent)
+            })
+            .last()
+            .copied()
+            .unwrap_or(Window::NONE)
+    }
+
+    /// Find the deepest viewable window containing a point in root coordinates
+    pub fn window_at_point(&self, x: i16, y: i16) -> Window {
+        let mut window = self.root_window;
+        let (mut local_x, mut local_y) = (x, y);
+        loop {
+            let child = self.find_child_at(window, local_x, local_y);
+            let Some(info) = self.window_info.get(&child) else {
+                return window;
+            };
+            local_x -= info.x + info.border_width as i16;
+            local_y -= info.y + info.border_width as i16;
+            window = child;
+        }
+    }
+
+    /// Route an input event reported against the root backend window
+    pub(super) fn route_root_event(&mut self, event: BackendEvent) {
+        if self.hold_frozen_input(&event) {
+            return;
+        }
+        match event {
+            BackendEvent::MotionNotify {
+                state, time, x, y, ..
+            } => {
+                let window = self.window_at_point(x, y);
+                self.update_pointer_window(window, x, y, state, time);
+
+                // ButtonNMotion bits line up with the Button state bits
+                let mut mask = event_mask::POINTER_MOTION;
+                if state & BUTTON_STATE_MASK != 0 {
+                    mask |= event_mask::BUTTON_MOTION | (state & BUTTON_STATE_MASK) as u32;
+                }
+                self.deliver_pointer_event(6, 0, window, mask, time, x, y, state);
+            }
+            BackendEvent::ButtonPress {
+                button,
+                state,
+                time,
+                x,
+                y,
+                ..
+            } => {
+                let window = self.window_at_point(x, y);
+                self.update_pointer_window(window, x, y, state, time);
+                if self.grabs.pointer.is_none() {
+                    self.activate_passive_grab(window, button, state, time, &event);
+                }
+                let mask = event_mask::BUTTON_PRESS;
+                if self.grabs.pointer.is_some() {
+                    self.deliver_pointer_event(4, button, window, mask, time, x, y, state);
+                    self.refreeze_after_sync(&event);
+                } else if let Some(target) =
+                    self.deliver_device_event(4, button, window, mask, None, time, x, y, state)
+                {
+                    // The receiving client holds an implicit grab until release
+                    self.start_implicit_grab(target, time);
+                }
+            }
+            BackendEvent::ButtonRelease {
+                button,
+                state,
+                time,
+                x,
+                y,
+                ..
+            } => {
+                let window = self.window_at_point(x, y);
+                self.update_pointer_window(window, x, y, state, time);
+                let mask = event_mask::BUTTON_RELEASE;
+                self.deliver_pointer_event(5, button, window, mask, time, x, y, state);
+                self.refreeze_after_sync(&event);
+
+                // The state field still includes the button being released
+                let button_bit = if (1..=5).contains(&button) {
+                    0x80u16 << button
+                } else {
+                    0
+                };
+                if state & BUTTON_STATE_MASK & !button_bit == 0 {
+                    self.end_button_grab();
+                }
+            }
+            BackendEvent::KeyPress {
+                keycode,
+                state,
+                time,
+                x,
+                y,
+                ..
+            } => {
+                let target = self.keyboard_target(x, y);
+                if let (None, Some(window)) = (self.grabs.keyboard, target) {
+                    self.activate_passive_grab(window, keycode, state, time, &event);
+                }
+                let mask = event_mask::KEY_PRESS;
+                self.deliver_key_event(2, keycode, target, mask, time, x, y, state);
+                self.refreeze_after_sync(&event);
+            }
+            BackendEvent::KeyRelease {
+                keycode,
+                state,
+                time,
+                x,
+                y,
+                ..
+            } => {
+                let target = self.keyboard_target(x, y);
+                let mask = event_mask::KEY_RELEASE;
+                self.deliver_key_event(3, keycode, target, mask, time, x, y, state);
+                self.refreeze_after_sync(&event);
+                self.end_key_grab(keycode);
+            }
+            BackendEvent::EnterNotify { x, y, time, .. } => {
+                let window = self.window_at_point(x, y);
+                let state = self.pointer_state();
+                self.update_pointer_window(window, x, y, state, time);
+            }
+            BackendEvent::LeaveNotify { x, y, time, .. } => {
+                let root = self.root_window;
+                let state = self.pointer_state();
+                self.update_pointer_window(root, x, y, state, time);
+            }
+            BackendEvent::Expose {
+                x,
+                y,
+                width,
+                height,
+                ..
+            } => {
+                let seq = self.next_event_sequence();
+                let root = self.root_window;
+                let event = Self::encode_expose_event(seq, root, x, y, width, height, 0);
+                self.deliver_event(root, event_mask::EXPOSURE, event);
+            }
+            _ => {}
+        }
+    }
+
+    /// The window that keyboard input goes to, following the focus rules:
+    /// PointerRoot focus follows the pointer, otherwise input goes to the
+    /// focus window unless the pointer is inside one of its inferiors
+    fn keyboard_target(&self, x: i16, y: i16) -> Option<Window> {
+        let pointer_window = self.window_at_point(x, y);
+        match self.focused_window {
+            Window::NONE => None,
+            focus if focus == self.root_window => Some(pointer_window),
+            focus if self.is_ancestor(focus, pointer_window) => Some(pointer_window),
+            focus => Some(focus),
+        }
+    }
+
+    /// Deliver a button or motion event, following the pointer grab
+    #[allow(clippy::too_many_arguments)]
+    fn deliver_pointer_event(
+        &mut self,
+        code: u8,
+        detail: u8,
+        window: Window,
+        mask: u32,
+        time: u32,
+        root_x: i16,
+        root_y: i16,
+        state: u16,
+    ) {
+        match self.grabs.pointer {
+            Some(grab) => self.deliver_grabbed_event(
+                &grab, code, detail, window, mask, time, root_x, root_y, state,
+            ),
+            None => {
+                self.deliver_device_event(
+                    code, detail, window, mask, None, time, root_x, root_y, state,
+                );
+            }
+        }
+    }
+
+    /// Deliver a key event to the keyboard target, following the keyboard
+    /// grab; input without a focus window is dropped unless grabbed
+    #[allow(clippy::too_many_arguments)]
+    fn deliver_key_event(
+        &mut self,
+        code: u8,
+        keycode: u8,
+        target: Option<Window>,
+        mask: u32,
+        time: u32,
+        root_x: i16,
+        root_y: i16,
+        state: u16,
+    ) {
+        let window = target.unwrap_or(Window::NONE);
+        match self.grabs.keyboard {
+            Some(grab) => self.deliver_grabbed_event(
+                &grab, code, keycode, window, mask, time, root_x, root_y, state,
+            ),
+            None if target.is_some() => {
+                self.deliver_device_event(
+                    code, keycode, window, mask, None, time, root_x, root_y, state,
+                );
+            }
+            None => {}
+        }
+    }
+
+    /// Deliver a device event to the grabbing client: as usual if the grab
+    /// has owner_events and the client selected the event, otherwise
+    /// relative to the grab window if the grab's event mask includes it
+    #[allow(clippy::too_many_arguments)]
+    fn deliver_grabbed_event(
+        &mut self,
+        grab: &Grab,
+        code: u8,
+        detail: u8,
+        window: Window,
+        mask: u32,
+        time: u32,
+        root_x: i16,
+        root_y: i16,
+        state: u16,
+    ) {
+        let client = Some(grab.client);
+        if grab.params.owner_events
+            && self
+                .deliver_device_event(
+                    code, detail, window, mask, client, time, root_x, root_y, state,
+                )
+                .is_some()
+        {
+            return;
+        }
+        if grab.params.event_mask & mask != 0 {
+            let grab_window = grab.params.window;
+            let child = self.child_towards(grab_window, window);
+            let event = self.encode_device_event(
+                code,
+                detail,
+                grab_window,
+                child,
+                time,
+                root_x,
+                root_y,
+                state,
+            );
+            self.client_events
+                .entry(grab.client)
+                .or_default()
+                .push(event);
+        }
+    }
+
+    /// Deliver a key/button/motion event starting at `window` and propagating
+    /// up the tree to the first window that selected `mask`, stopping at
+    /// windows whose do-not-propagate mask includes it
+    ///
+    /// With `client`, only that client's selections count and only it
+    /// receives the event. Returns the window the event was delivered to.
+    #[allow(clippy::too_many_arguments)]
+    fn deliver_device_event(
+        &mut self,
+        code: u8,
+        detail: u8,
+        window: Window,
+        mask: u32,
+        client: Option<u32>,
+        time: u32,
+        root_x: i16,
+        root_y: i16,
+        state: u16,
+    ) -> Option<Window> {
+        let mut current = window;
+        let mut child = Window::NONE;
+        loop {
+            let info = self.window_info.get(&current)?;
+            let (parent, do_not_propagate) = (info.parent, info.do_not_propagate_mask);
+            let selected = match client {
+                Some(client) => self.client_event_mask(current, client) & mask != 0,
+                None => info.event_mask & mask != 0,
+            };
+            if selected {
+                let event = self
+                    .encode_device_event(code, detail, current, child, time, root_x, root_y, state);
+                match client {
+                    Some(client) => self.client_events.entry(client).or_default().push(event),
+                    None => {
+                        self.deliver_event(current, mask, event);
+                    }
+                }
+                return Some(current);
+            }
+            if current == self.root_window || do_not_propagate as u32 & mask != 0 {
+                return None;
+            }
+            child = current;
+            current = parent;
+        }
+    }
+
+    /// Encode a key/button/motion event reported relative to `window`
+    #[allow(clippy::too_many_arguments)]
+    fn encode_device_event(
+        &mut self,
+        code: u8,
+        detail: u8,
+        window: Window,
+        child: Window,
+        time: u32,
+        root_x: i16,
+        root_y: i16,
+        state: u16,
+    ) -> Vec<u8> {
+        let (origin_x, origin_y) = self.window_to_root_coords(window, 0, 0);
+        let seq = self.next_event_sequence();
+        Self::encode_key_event(
+            code,
+            detail,
+            seq,
+            Timestamp::new(time),
+            self.root_window,
+            window,
+            child,
+            root_x,
+            root_y,
+            root_x - origin_x,
+            root_y - origin_y,
+            state,
+            true,
+        )
+    }
+
+    /// A window and its ancestors, up to and including the root
+    pub(super) fn ancestry(&self, window: Window) -> Vec<Window> {
+        let mut path = vec![window];
+        let mut current = window;
+        while let Some(info) = self.window_info.get(&current) {
+            if info.parent == Window::NONE {
+                break;
+            }
+            current = info.parent;
+            path.push(current);
+        }
+        path
+    }
+
+    /// The child of `ancestor` that is or contains `window`, or None if
+    /// `window` is not an inferior of `ancestor`
+    fn child_towards(&self, ancestor: Window, window: Window) -> Window {
+        let path = self.ancestry(window);
+        match path.iter().position(|w| *w == ancestor) {
+            Some(pos) if pos > 0 => path[pos - 1],
+            _ => Window::NONE,
+        }
+    }
+
+    /// Track the window under the pointer, generating LeaveNotify/EnterNotify
+    /// when it changes
+    fn update_pointer_window(&mut self, window: Window, x: i16, y: i16, state: u16, time: u32) {
+        self.last_pointer_x = x;
+        self.last_pointer_y = y;
+
+        let old = self.last_pointer_window;
+        if old == window {
+            return;
+        }
+        self.last_pointer_window = window;
+        self.send_crossings(old, window, MODE_NORMAL, state, time);
+    }
+
+    /// LeaveNotify and EnterNotify for the pointer moving from `from` to
+    /// `to`, including the virtual crossings of the windows in between
+    ///
+    /// Leave events go from `from` up, then Enter events down to `to`. Only
+    /// virtual crossings name a child: the one on the pointer's path.
+    pub(super) fn send_crossings(
+        &mut self,
+        from: Window,
+        to: Window,
+        mode: u8,
+        state: u16,
+        time: u32,
+    ) {
+        if from == to || !self.window_info.contains_key(&from) {
+            return;
+        }
+        let from_path = self.ancestry(from);
+        let to_path = self.ancestry(to);
+        let Some(common) = from_path.iter().find(|w| to_path.contains(w)).copied() else {
+            return;
+        };
+        let up = &from_path[..from_path.iter().position(|w| *w == common).unwrap_or(0)];
+        let down = &to_path[..to_path.iter().position(|w| *w == common).unwrap_or(0)];
+
+        let mut crossings = Vec::new();
+        if common == to {
+            // Out to an ancestor
+            crossings.push((8, DETAIL_ANCESTOR, from, Window::NONE));
+            crossings.extend(up.windows(2).map(|w| (8, DETAIL_VIRTUAL, w[1], w[0])));
+            crossings.push((7, DETAIL_INFERIOR, to, Window::NONE));
+        } else if common == from {
+            // Into an inferior
+            crossings.push((8, DETAIL_INFERIOR, from, Window::NONE));
+            crossings.extend(
+                down.windows(2)
+                    .rev()
+                    .map(|w| (7, DETAIL_VIRTUAL, w[1], w[0])),
+            );
+            crossings.push((7, DETAIL_ANCESTOR, to, Window::NONE));
+        } else {
+            crossings.push((8, DETAIL_NONLINEAR, from, Window::NONE));
+            crossings.extend(
+                up.windows(2)
+                    .map(|w| (8, DETAIL_NONLINEAR_VIRTUAL, w[1], w[0])),
+            );
+            crossings.extend(
+                down.windows(2)
+                    .rev()
+                    .map(|w| (7, DETAIL_NONLINEAR_VIRTUAL, w[1], w[0])),
+            );
+            crossings.push((7, DETAIL_NONLINEAR, to, Window::NONE));
+        }
+
+        let (x, y) = (self.last_pointer_x, self.last_pointer_y);
+        for (code, detail, target, child) in crossings {
+            let mask = if code == 7 {
+                event_mask::ENTER_WINDOW
+            } else {
+                event_mask::LEAVE_WINDOW
+            };
+            let (origin_x, origin_y) = self.window_to_root_coords(target, 0, 0);
+            // same-screen (0x02) | focus (0x01)
+            let same_screen_focus = if target == self.focused_window {
+                0x03
+            } else {
+                0x02
+            };
+            let seq = self.next_event_sequence();
+            let event = Self::encode_enter_leave_event(
+                code,
+                detail,
+                seq,
+                Timestamp::new(time),
+                self.root_window,
+                target,
+                child,
+                x,
+                y,
+                x - origin_x,
+                y - origin_y,
+                state,
+                mode,
+                same_screen_focus,
+            );
+            self.deliver_crossing(target, mask, event);
+        }
+    }
+
+    /// Deliver a crossing event; during a pointer grab only the grabbing
+    /// client receives it, relative to the grab window unless owner_events
+    fn deliver_crossing(&mut self, target: Window, mask: u32, event: Vec<u8>) {
+        match self.grabs.pointer {
+            Some(grab) => {
+                let selected = if grab.params.owner_events {
+                    self.client_event_mask(target, grab.client) & mask != 0
+                } else {
+                    target == grab.params.window && grab.params.event_mask & mask != 0
+                };
+                if selected {
+                    self.client_events
+                        .entry(grab.client)
+                        .or_default()
+                        .push(event);
+                }
+            }
+            None => {
+                self.deliver_event(target, mask, event);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::null::NullBackend;
+    use crate::backend::{BackendWindow, WindowBorder};
+    use crate::server::grab::{GrabParams, ALREADY_GRABBED, ANY_MODIFIER, GRAB_SUCCESS};
+    use crate::server::{WindowChanges, WindowPaintValues};
+
+    const A: Window = Window(XID(0x0020_0001));
+    const B: Window = Window(XID(0x0020_0002));
+    const C: Window = Window(XID(0x0020_0003));
+
+    /// A (10,10 100x100, border 5) with child C (0,0 20x20), and B
+    /// (50,50 100x100) above A; all mapped by `client`
+    fn setup() -> (Server, u32) {
+        let mut server = Server::new(Box::new(NullBackend::new())).unwrap();
+        let client = server.register_client();
+        let root = server.root_window();
+        for (window, parent, x, y, size, border) in [
+            (A, root, 10, 10, 100, 5),
+            (B, root, 50, 50, 100, 0),
+            (C, A, 0, 0, 20, 0),
+        ] {
+            server
+                .create_window(
+                    window,
+                    parent,
+                    x,
+                    y,
+                    size,
+                    size,
+                    border,
+                    WindowClass::InputOutput,
+                    VisualID::new(0x21),
+                    &WindowPaintValues::default(),
+                    0,
+                    false,
+                )
+                .unwrap();
+            server.map_window(window, client).unwrap();
+        }
+        (server, client)
+    }
+
+    fn motion(x: i16, y: i16, state: u16) -> BackendEvent {
+        BackendEvent::MotionNotify {
+            window: BackendWindow(1),
+            state,
+            time: 0,
+            x,
+            y,
+        }
+    }
+
+    fn button(press: bool, x: i16, y: i16, state: u16) -> BackendEvent {
+        let window = BackendWindow(1);
+        if press {
+            BackendEvent::ButtonPress {
+                window,
+                button: 1,
+                state,
+                time: 0,
+                x,
+                y,
+            }
+        } else {
+            BackendEvent::ButtonRelease {
+                window,
+                button: 1,
+                state,
+                time: 0,
+                x,
+                y,
+            }
+        }
+    }
+
+    fn key(press: bool, keycode: u8, x: i16, y: i16) -> BackendEvent {
+        let window = BackendWindow(1);
+        if press {
+            BackendEvent::KeyPress {
+                window,
+                keycode,
+                state: 0,
+                time: 0,
+                x,
+                y,
+            }
+        } else {
+            BackendEvent::KeyRelease {
+                window,
+                keycode,
+                state: 0,
+                time: 0,
+                x,
+                y,
+            }
+        }
+    }
+
+    fn grab_params(window: Window, event_mask: u32, pointer_sync: bool) -> GrabParams {
+        GrabParams {
+            window,
+            owner_events: false,
+            event_mask,
+            pointer_sync,
+            keyboard_sync: false,
+            confine_to: Window::NONE,
+        }
+    }
+
+    /// (code, detail, event window, child) of each event
+    fn summary(events: &[Vec<u8>]) -> Vec<(u8, u8, Window, Window)> {
+        let window = |e: &[u8], at: usize| {
+            Window::new(u32::from_ne_bytes(e[at..at + 4].try_into().unwrap()))
+        };
+        events
+            .iter()
+            .map(|e| (e[0], e[1], window(e, 12), window(e, 16)))
+            .collect()
+    }
+
+    #[test]
+    fn test_hit_testing() {
+        let (mut server, _) = setup();
+        let root = server.root_window();
+        // Borders belong to the window, not to its children
+        assert_eq!(server.window_at_point(12, 12), A);
+        assert_eq!(server.window_at_point(16, 16), C);
+        assert_eq!(server.window_at_point(118, 20), A);
+        assert_eq!(server.window_at_point(121, 20), root);
+        // B was created later, so it is on top where they overlap
+        assert_eq!(server.window_at_point(60, 60), B);
+        server.restack_window(A, None, StackMode::Above);
+        assert_eq!(server.window_at_point(60, 60), A);
+        assert_eq!(server.sibling_below(A), B);
+        assert_eq!(server.window_at_point(130, 130), B);
+    }
+
+    #[test]
+    fn test_redirection() {
+        let (mut server, client) = setup();
+        let root = server.root_window();
+        let wm = server.register_client();
+        server.select_window_events(root, wm, event_mask::SUBSTRUCTURE_REDIRECT);
+        assert_eq!(server.redirect_client(root), Some(wm));
+
+        // Another client's requests become MapRequest/ConfigureRequest
+        server.unmap_window(B).unwrap();
+        assert!(!server.map_window(B, client).unwrap());
+        assert!(!server.window_info[&B].mapped);
+        let changes = WindowChanges {
+            x: Some(0),
+            ..Default::default()
+        };
+        server.configure_window(B, client, changes).unwrap();
+        assert_eq!(server.window_info[&B].x, 50);
+        let events = server.take_client_events(wm);
+        assert_eq!(
+            events.iter().map(|e| e[0]).collect::<Vec<_>>(),
+            [20, 23] // MapRequest, ConfigureRequest
+        );
+
+        // The window manager's own requests go through
+        assert!(server.map_window(B, wm).unwrap());
+        server.configure_window(B, wm, changes).unwrap();
+        assert_eq!(server.window_info[&B].x, 0);
+        // Only the children of the redirected window are redirected
+        server.unmap_window(C).unwrap();
+        assert!(server.map_window(C, client).unwrap());
+    }
+
+    #[test]
+    fn test_implicit_grab() {
+        let (mut server, client) = setup();
+        let buttons =
+            event_mask::BUTTON_PRESS | event_mask::BUTTON_RELEASE | event_mask::POINTER_MOTION;
+        server.select_window_events(A, client, buttons);
+        server.select_window_events(B, client, buttons);
+
+        // Everything goes to B while the button is down, even over A
+        server.route_root_event(button(true, 60, 60, 0));
+        server.route_root_event(motion(20, 20, 0x0100));
+        server.route_root_event(button(false, 20, 20, 0x0100));
+        assert_eq!(
+            summary(&server.take_client_events(client)),
+            [
+                (4, 1, B, Window::NONE),
+                (6, 0, B, Window::NONE),
+                (5, 1, B, Window::NONE),
+            ]
+        );
+
+        // After release, presses in C propagate to A again
+        server.route_root_event(button(true, 20, 20, 0));
+        assert_eq!(summary(&server.take_client_events(client)), [(4, 1, A, C)]);
+    }
+
+    #[test]
+    fn test_crossing_details() {
+        let (mut server, client) = setup();
+        let crossing = event_mask::ENTER_WINDOW | event_mask::LEAVE_WINDOW;
+        for window in [A, B, C] {
+            server.select_window_events(window, client, crossing);
+        }
+
+        let mut crossings = Vec::new();
+        for (x, y) in [(12, 12), (16, 16), (12, 12), (130, 130)] {
+            server.route_root_event(motion(x, y, 0));
+            crossings.push(summary(&server.take_client_events(client)));
+        }
+        let (leave, enter) = (8, 7);
+        assert_eq!(
+            crossings,
+            [
+                // root -> A: the root didn't select crossings
+                vec![(enter, DETAIL_ANCESTOR, A, Window::NONE)],
+                // A -> C, into an inferior
+                vec![
+                    (leave, DETAIL_INFERIOR, A, Window::NONE),
+                    (enter, DETAIL_ANCESTOR, C, Window::NONE),
+                ],
+                // C -> A, back out to the ancestor
+                vec![
+                    (leave, DETAIL_ANCESTOR, C, Window::NONE),
+                    (enter, DETAIL_INFERIOR, A, Window::NONE),
+                ],
+                // A -> B, between siblings
+                vec![
+                    (leave, DETAIL_NONLINEAR, A, Window::NONE),
+                    (enter, DETAIL_NONLINEAR, B, Window::NONE),
+                ],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_keyboard_target() {
+        let (mut server, _) = setup();
+        let root = server.root_window();
+        // PointerRoot follows the pointer
+        server.set_input_focus(root, 1);
+        assert_eq!(server.keyboard_target(130, 130), Some(B));
+        // Focus on A goes to A, or to the inferior of A under the pointer
+        server.set_input_focus(A, 2);
+        assert_eq!(server.keyboard_target(130, 130), Some(A));
+        assert_eq!(server.keyboard_target(16, 16), Some(C));
+        assert_eq!(server.keyboard_target(12, 12), Some(A));
+        // No focus drops keyboard input
+        server.set_input_focus(Window::NONE, 0);
+        assert_eq!(server.keyboard_target(16, 16), None);
+    }
+
+    #[test]
+    fn test_virtual_crossings() {
+        let (mut server, client) = setup();
+        let crossing = event_mask::ENTER_WINDOW | event_mask::LEAVE_WINDOW;
+        for window in [A, B, C] {
+            server.select_window_events(window, client, crossing);
+        }
+        let (leave, enter) = (8, 7);
+
+        // Straight from the root into C passes through A
+        server.route_root_event(motion(16, 16, 0));
+        assert_eq!(
+            summary(&server.take_client_events(client)),
+            [
+                (enter, DETAIL_VIRTUAL, A, C),
+                (enter, DETAIL_ANCESTOR, C, Window::NONE),
+            ]
+        );
+        // From C over to B leaves A on the way
+        server.route_root_event(motion(130, 130, 0));
+        assert_eq!(
+            summary(&server.take_client_events(client)),
+            [
+                (leave, DETAIL_NONLINEAR, C, Window::NONE),
+                (leave, DETAIL_NONLINEAR_VIRTUAL, A, C),
+                (enter, DETAIL_NONLINEAR, B, Window::NONE),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_active_grab() {
+        let (mut server, client) = setup();
+        let wm = server.register_client();
+        let buttons = event_mask::BUTTON_PRESS | event_mask::BUTTON_RELEASE;
+        server.select_window_events(A, client, buttons);
+
+        let params = grab_params(B, buttons, false);
+        assert_eq!(server.grab_pointer(wm, params, 0).unwrap(), GRAB_SUCCESS);
+        let other = grab_params(A, buttons, false);
+        assert_eq!(
+            server.grab_pointer(client, other, 0).unwrap(),
+            ALREADY_GRABBED
+        );
+
+        // A press in C is reported to the grabbing client, relative to B
+        server.route_root_event(button(true, 16, 16, 0));
+        server.route_root_event(button(false, 16, 16, 0x0100));
+        assert!(server.take_client_events(client).is_empty());
+        assert_eq!(
+            summary(&server.take_client_events(wm)),
+            [(4, 1, B, Window::NONE), (5, 1, B, Window::NONE)]
+        );
+
+        // The release didn't end an active grab; ungrabbing does
+        server.route_root_event(button(true, 16, 16, 0));
+        assert!(server.take_client_events(client).is_empty());
+        server.ungrab_pointer(wm, 0);
+        server.route_root_event(button(false, 16, 16, 0x0100));
+        server.route_root_event(button(true, 16, 16, 0));
+        assert_eq!(
+            summary(&server.take_client_events(client)),
+            [(5, 1, A, C), (4, 1, A, C)]
+        );
+    }
+
+    #[test]
+    fn test_passive_grab_replay() {
+        let (mut server, client) = setup();
+        let root = server.root_window();
+        let wm = server.register_client();
+        let buttons = event_mask::BUTTON_PRESS | event_mask::BUTTON_RELEASE;
+        server.select_window_events(A, client, buttons | event_mask::POINTER_MOTION);
+        let params = grab_params(root, buttons, true);
+        server.grab_button(wm, 1, ANY_MODIFIER, params).unwrap();
+        // Another client can't grab the same button on the window
+        assert!(server.grab_button(client, 0, 0, params).is_err());
+
+        // The press activates the grab and freezes the pointer
+        server.route_root_event(button(true, 16, 16, 0));
+        server.route_root_event(motion(20, 20, 0x0100));
+        assert_eq!(summary(&server.take_client_events(wm)), [(4, 1, root, A)]);
+        assert!(server.take_client_events(client).is_empty());
+
+        // Replaying hands the press to the window under the pointer, then
+        // releases the held motion
+        server.allow_events(wm, 2).unwrap();
+        assert!(server.take_client_events(wm).is_empty());
+        assert_eq!(
+            summary(&server.take_client_events(client)),
+            [(4, 1, A, C), (6, 0, A, C)]
+        );
+    }
+
+    #[test]
+    fn test_key_grab_and_propagation() {
+        let (mut server, client) = setup();
+        let root = server.root_window();
+        let wm = server.register_client();
+        server.select_window_events(A, client, event_mask::KEY_PRESS);
+        let params = grab_params(root, 0, false);
+        server.grab_key(wm, 38, 0, params).unwrap();
+
+        // The grabbed key goes to the grabbing client until it is released
+        server.route_root_event(key(true, 38, 16, 16));
+        server.route_root_event(key(false, 38, 16, 16));
+        assert!(server.take_client_events(client).is_empty());
+        assert_eq!(
+            summary(&server.take_client_events(wm)),
+            [(2, 38, root, A), (3, 38, root, A)]
+        );
+        server.route_root_event(key(true, 39, 16, 16));
+        assert_eq!(summary(&server.take_client_events(client)), [(2, 39, A, C)]);
+
+        // Key presses in C no longer propagate to A
+        server.set_do_not_propagate_mask(C, event_mask::KEY_PRESS as u16);
+        server.route_root_event(key(true, 40, 16, 16));
+        assert!(server.take_client_events(client).is_empty());
+    }
+
+    #[test]
+    fn test_server_grab() {
+        let (mut server, client) = setup();
+        let other = server.register_client();
+        server.grab_server(client);
+        assert!(!server.blocked_by_server_grab(client));
+        assert!(server.blocked_by_server_grab(other));
+
+        // XTestGrabControl lets a client through; only the holder ungrabs
+        server.set_grab_impervious(other, true);
+        assert!(!server.blocked_by_server_grab(other));
+        server.set_grab_impervious(other, false);
+        server.ungrab_server(other);
+        assert!(server.blocked_by_server_grab(other));
+
+        // The grab ends with its client
+        server.remove_client_grabs(client);
+        assert!(!server.blocked_by_server_grab(other));
+    }
+
+    #[test]
+    fn test_window_borders() {
+        let (mut server, client) = setup();
+        let border = |server: &Server, window| server.get_window_info(window).unwrap().border;
+        assert_eq!(border(&server, A), WindowBorder::Pixel(0));
+        let paint = WindowPaintValues {
+            border_pixel: Some(0xff0000),
+            ..Default::default()
+        };
+        server
+            .change_window_attributes(A, client, &paint, None, None, None)
+            .unwrap();
+        assert_eq!(border(&server, A), WindowBorder::Pixel(0xff0000));
+
+        // New windows copy their parent's border
+        let d = Window::new(0x0020_0004);
+        server
+            .create_window(
+                d,
+                A,
+                0,
+                0,
+                10,
+                10,
+                1,
+                WindowClass::InputOutput,
+                VisualID::new(0x21),
+                &WindowPaintValues::default(),
+                0,
+                false,
+            )
+            .unwrap();
+        assert_eq!(border(&server, d), WindowBorder::Pixel(0xff0000));
+    }
+}
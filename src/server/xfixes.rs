@@ -0,0 +1,227 @@
+//! XFIXES regions
+//!
+//! Regions are server-side pixel sets named by client-chosen IDs. They can
+//! be built from rectangles, bitmaps, window shapes and GC or picture clips,
+//! and set back as GC clips, picture clips and window shapes. Unknown
+//! regions report the extension's BadRegion error.
+
+use super::{Region, Server};
+use crate::backend::ShapeKind;
+use crate::protocol::*;
+use std::error::Error;
+
+impl Region {
+    /// The pixels of `bounds` that are not in this region
+    pub fn invert(&self, bounds: Rectangle) -> Region {
+        Region::rectangle(bounds).subtract(self)
+    }
+
+    /// The union of every rectangle of the region grown by the given
+    /// amounts on each side (XFixesExpandRegion)
+    pub fn expand(&self, left: u16, right: u16, top: u16, bottom: u16) -> Region {
+        let rects: Vec<Rectangle> = self
+            .rectangles()
+            .into_iter()
+            .map(|r| Rectangle {
+                x: r.x.saturating_sub_unsigned(left),
+                y: r.y.saturating_sub_unsigned(top),
+                width: r.width.saturating_add(left).saturating_add(right),
+                height: r.height.saturating_add(top).saturating_add(bottom),
+            })
+            .collect();
+        Region::from_rectangles(&rects)
+    }
+}
+
+impl Server {
+    /// Create a region (XFixesCreateRegion and friends)
+    pub fn create_region(&mut self, id: u32, region: Region) -> Result<(), X11Error> {
+        if self.regions.contains_key(&id) {
+            return Err(X11Error::bad_id_choice(0, id, 0));
+        }
+        self.regions.insert(id, region);
+        Ok(())
+    }
+
+    /// Look up a region
+    pub fn region(&self, id: u32) -> Result<&Region, X11Error> {
+        self.regions
+            .get(&id)
+            .ok_or_else(|| X11Error::bad_region(0, id, 0))
+    }
+
+    /// Replace the contents of a region
+    pub fn set_region(&mut self, id: u32, region: Region) -> Result<(), X11Error> {
+        let slot = self
+            .regions
+            .get_mut(&id)
+            .ok_or_else(|| X11Error::bad_region(0, id, 0))?;
+        *slot = region;
+        Ok(())
+    }
+
+    pub fn destroy_region(&mut self, id: u32) -> Result<(), X11Error> {
+        self.regions
+            .remove(&id)
+            .map(|_| ())
+            .ok_or_else(|| X11Error::bad_region(0, id, 0))
+    }
+
+    /// A region from a depth-1 pixmap's set pixels
+    /// (XFixesCreateRegionFromBitmap)
+    pub fn bitmap_region(&mut self, pixmap: u32) -> Result<Region, Box<dyn Error + Send + Sync>> {
+        let (width, height, bits) = self.pixmap_bits(pixmap)?;
+        Ok(Region::from_bitmap(width, height, &bits))
+    }
+
+    /// A GC's clip rectangles, relative to its clip origin
+    /// (XFixesCreateRegionFromGC); BadMatch if it has no clip
+    pub fn gc_clip_region(&self, gc: GContext) -> Result<Region, X11Error> {
+        let backend_gc = self
+            .gcs
+            .get(&gc)
+            .ok_or_else(|| X11Error::bad_gc(0, gc, 0))?;
+        backend_gc
+            .clip_rectangles
+            .as_deref()
+            .map(Region::from_rectangles)
+            .ok_or_else(|| X11Error::bad_match(0, 0))
+    }
+
+    /// A picture's clip rectangles, relative to its clip origin
+    /// (XFixesCreateRegionFromPicture); BadMatch if it has no clip
+    pub fn picture_clip_region(&self, picture_id: u32) -> Result<Region, X11Error> {
+        let picture = self
+            .pictures
+            .get(&picture_id)
+            .ok_or_else(|| X11Error::bad_picture(0, picture_id, 0))?;
+        picture
+            .clip_rectangles
+            .as_deref()
+            .map(Region::from_rectangles)
+            .ok_or_else(|| X11Error::bad_match(0, 0))
+    }
+
+    /// Clip a GC to a region, or unclip it for None (XFixesSetGCClipRegion)
+    pub fn set_gc_clip_region(
+        &mut self,
+        gc: GContext,
+        clip_x_origin: i16,
+        clip_y_origin: i16,
+        region: Option<u32>,
+    ) -> Result<(), X11Error> {
+        let rectangles = region
+            .map(|id| self.region(id).map(Region::rectangles))
+            .transpose()?;
+        let backend_gc = self
+            .gcs
+            .get_mut(&gc)
+            .ok_or_else(|| X11Error::bad_gc(0, gc, 0))?;
+        backend_gc.clip_x_origin = clip_x_origin;
+        backend_gc.clip_y_origin = clip_y_origin;
+        backend_gc.clip_rectangles = rectangles;
+        Ok(())
+    }
+
+    /// Clip a picture to a region, or unclip it for None
+    /// (XFixesSetPictureClipRegion)
+    pub fn set_picture_clip_region(
+        &mut self,
+        picture_id: u32,
+        clip_x_origin: i16,
+        clip_y_origin: i16,
+        region: Option<u32>,
+    ) -> Result<(), X11Error> {
+        let rectangles = region
+            .map(|id| self.region(id).map(Region::rectangles))
+            .transpose()?;
+        let picture = self
+            .pictures
+            .get_mut(&picture_id)
+            .ok_or_else(|| X11Error::bad_picture(0, picture_id, 0))?;
+        picture.clip_x_origin = clip_x_origin;
+        picture.clip_y_origin = clip_y_origin;
+        picture.clip_rectangles = rectangles;
+        Ok(())
+    }
+
+    /// Set a window shape to an offset region, or unshape it for None
+    /// (XFixesSetWindowShapeRegion)
+    pub fn set_window_shape_region(
+        &mut self,
+        window: Window,
+        kind: ShapeKind,
+        x_offset: i16,
+        y_offset: i16,
+        region: Option<u32>,
+    ) -> Result<(), X11Error> {
+        let source = region
+            .map(|id| {
+                let mut region = self.region(id)?.clone();
+                region.translate(x_offset as i32, y_offset as i32)?;
+                Ok::<_, X11Error>(region)
+            })
+            .transpose()?;
+        self.shape_window(window, kind, 0, source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::null::NullBackend;
+
+    fn rect(x: i16, y: i16, width: u16, height: u16) -> Rectangle {
+        Rectangle {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    #[test]
+    fn test_regions() {
+        let mut server = Server::new(Box::new(NullBackend::new())).unwrap();
+        let region = Region::from_rectangles(&[rect(0, 0, 4, 4), rect(8, 0, 4, 4)]);
+        server.create_region(0x0040_0001, region.clone()).unwrap();
+        assert!(server
+            .create_region(0x0040_0001, Region::default())
+            .is_err());
+
+        // Inverting within bounds leaves the gap and the margins
+        let inverted = region.invert(rect(0, 0, 12, 6));
+        assert_eq!(inverted.rectangles(), [rect(4, 0, 4, 4), rect(0, 4, 12, 2)]);
+        assert_eq!(region.expand(2, 2, 0, 2).rectangles(), [rect(-2, 0, 16, 6)]);
+
+        // GC clips round-trip through regions
+        let gc = GContext::new(0x0040_0002);
+        let root = server.root_window();
+        server
+            .create_gc(gc, Drawable::Window(root), &Default::default())
+            .unwrap();
+        assert!(server.gc_clip_region(gc).is_err());
+        server
+            .set_gc_clip_region(gc, 3, 3, Some(0x0040_0001))
+            .unwrap();
+        assert_eq!(server.gc_clip_region(gc).unwrap(), region);
+        server.set_gc_clip_region(gc, 0, 0, None).unwrap();
+        assert!(server.gc_clip_region(gc).is_err());
+
+        server.destroy_region(0x0040_0001).unwrap();
+        let err = server.region(0x0040_0001).unwrap_err();
+        assert_eq!(err.code, ErrorCode::Region);
+        assert_eq!(err.bad_value, 0x0040_0001);
+
+        // A disconnecting client's regions go with it
+        server
+            .create_region(0x0040_0003, Region::default())
+            .unwrap();
+        server
+            .create_region(0x0060_0003, Region::default())
+            .unwrap();
+        server.handle_client_disconnect(2);
+        assert!(server.region(0x0040_0003).is_err());
+        assert!(server.region(0x0060_0003).is_ok());
+    }
+}
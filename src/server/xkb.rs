@@ -0,0 +1,1166 @@
+//! XKEYBOARD extension
+//!
+//! The XKB keymap is derived from the core keyboard mapping, so both views
+//! of the keyboard always agree: core keysym columns 1-2 become group 1 and
+//! columns 3-4 group 2, each group gets one of four canonical key types, and
+//! keys in the modifier map get the action that sets or locks their
+//! modifiers. Modifier and group state is tracked from the backend's key
+//! events.
+
+use super::keyboard::{MAX_KEYCODE, MIN_KEYCODE};
+use super::Server;
+use crate::backend::KeyboardMapping;
+use crate::protocol::*;
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+/// Event base and major opcode registered for XKEYBOARD
+const XKB_EVENT: u8 = 85;
+const XKB_MAJOR: u8 = 135;
+
+/// Device ID of the core keyboard, and the XkbUseCoreKbd device spec
+const CORE_KEYBOARD_ID: u8 = 3;
+const USE_CORE_KBD: u16 = 0x100;
+
+/// Event types (the xkbType byte)
+const NEW_KEYBOARD_NOTIFY: usize = 0;
+const MAP_NOTIFY: usize = 1;
+const STATE_NOTIFY: usize = 2;
+
+/// Size of each event type's detail fields in SelectEvents; MapNotify's
+/// are in the fixed part of the request
+const DETAIL_SIZES: [usize; 12] = [2, 0, 2, 4, 4, 4, 2, 1, 1, 1, 2, 2];
+
+/// Modifier masks
+const SHIFT: u8 = 0x01;
+const LOCK: u8 = 0x02;
+const MOD2: u8 = 0x10;
+
+/// Map components
+const KEY_TYPES: u16 = 0x01;
+const KEY_SYMS: u16 = 0x02;
+const MODIFIER_MAP: u16 = 0x04;
+const EXPLICIT_COMPONENTS: u16 = 0x08;
+const KEY_ACTIONS: u16 = 0x10;
+const KEY_BEHAVIORS: u16 = 0x20;
+const VIRTUAL_MOD_MAP: u16 = 0x80;
+
+/// Name components
+const NAME_KEYCODES: u32 = 1 << 0;
+const NAME_GEOMETRY: u32 = 1 << 1;
+const NAME_SYMBOLS: u32 = 1 << 2;
+const NAME_PHYS_SYMBOLS: u32 = 1 << 3;
+const NAME_TYPES: u32 = 1 << 4;
+const NAME_COMPAT: u32 = 1 << 5;
+const NAME_KEY_TYPE_NAMES: u32 = 1 << 6;
+const NAME_KT_LEVEL_NAMES: u32 = 1 << 7;
+const NAME_INDICATOR_NAMES: u32 = 1 << 8;
+const NAME_KEY_NAMES: u32 = 1 << 9;
+const NAME_GROUP_NAMES: u32 = 1 << 12;
+const ALL_NAMES: u32 = 0x3fff;
+
+/// StateNotify changed bits
+const CHANGED_MODS: u16 = 0x1f01; // effective, compat, grab and lookup mods
+const CHANGED_BASE_MODS: u16 = 0x0002;
+const CHANGED_LATCHED_MODS: u16 = 0x0004;
+const CHANGED_LOCKED_MODS: u16 = 0x0008;
+const CHANGED_GROUP: u16 = 0x0110; // effective group and compat state
+const CHANGED_LATCHED_GROUP: u16 = 0x0040;
+const CHANGED_LOCKED_GROUP: u16 = 0x0080;
+
+/// Keysyms that get actions
+const XK_CAPS_LOCK: u32 = 0xffe5;
+const XK_SHIFT_LOCK: u32 = 0xffe6;
+const XK_NUM_LOCK: u32 = 0xff7f;
+const XK_ISO_NEXT_GROUP: u32 = 0xfe08;
+
+/// Supported PerClientFlags: GrabsUseXKBState, AutoResetControls,
+/// LookupStateWhenGrabbed and SendEventUsesXKBState
+const PER_CLIENT_FLAGS: u32 = 0x1e;
+
+const INDICATOR_NAMES: [&str; 3] = ["Caps Lock", "Num Lock", "Scroll Lock"];
+
+/// Name given to the keycodes, geometry and symbols components
+const COMPONENT_NAME: &str = "x11anywhere";
+
+/// Canonical key types, by index
+const ONE_LEVEL: u8 = 0;
+const TWO_LEVEL: u8 = 1;
+const ALPHABETIC: u8 = 2;
+const KEYPAD: u8 = 3;
+
+/// Keyboard state and the clients' XKB settings
+#[derive(Debug, Default)]
+pub struct XkbState {
+    base_mods: u8,
+    latched_mods: u8,
+    locked_mods: u8,
+    latched_group: i16,
+    locked_group: u8,
+    /// Pressed keys that set modifiers, and their modifiers
+    held: HashMap<u8, u8>,
+    /// Selected event details of each client, by xkbType
+    selections: HashMap<u32, [u32; DETAIL_SIZES.len()]>,
+    per_client_flags: HashMap<u32, u32>,
+}
+
+impl XkbState {
+    fn snapshot(&self, num_groups: u8) -> StateSnapshot {
+        let group = (self.locked_group as i16 + self.latched_group).rem_euclid(num_groups as i16);
+        StateSnapshot {
+            base_mods: self.base_mods,
+            latched_mods: self.latched_mods,
+            locked_mods: self.locked_mods,
+            group: group as u8,
+            latched_group: self.latched_group,
+            locked_group: self.locked_group % num_groups,
+        }
+    }
+
+    /// Apply SelectEvents; `data` starts at the device spec
+    fn select_events(
+        &mut self,
+        client_id: u32,
+        data: &[u8],
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        check_length(data, 12)?;
+        let affect_which = read_u16(data, 2);
+        let clear = read_u16(data, 4);
+        let select_all = read_u16(data, 6);
+        let affect_map = read_u16(data, 8) as u32;
+        let map = read_u16(data, 10) as u32;
+
+        let details = self.selections.entry(client_id).or_default();
+        let mut offset = 12;
+        for (index, &size) in DETAIL_SIZES.iter().enumerate() {
+            let bit = 1 << index;
+            if affect_which & bit == 0 {
+                continue;
+            }
+            details[index] = if clear & bit != 0 {
+                0
+            } else if select_all & bit != 0 {
+                u32::MAX
+            } else {
+                let (affect, value) = if index == MAP_NOTIFY {
+                    (affect_map, map)
+                } else {
+                    check_length(data, offset + 2 * size)?;
+                    let fields = (
+                        read_sized(data, offset, size),
+                        read_sized(data, offset + size, size),
+                    );
+                    offset += 2 * size;
+                    fields
+                };
+                (details[index] & !affect) | (value & affect)
+            };
+        }
+        Ok(())
+    }
+
+    /// Forget a disconnected client's selections and flags
+    pub fn remove_client(&mut self, client_id: u32) {
+        self.selections.remove(&client_id);
+        self.per_client_flags.remove(&client_id);
+    }
+}
+
+/// Modifier and group state at one point in time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct StateSnapshot {
+    base_mods: u8,
+    latched_mods: u8,
+    locked_mods: u8,
+    group: u8,
+    latched_group: i16,
+    locked_group: u8,
+}
+
+impl StateSnapshot {
+    fn mods(&self) -> u8 {
+        self.base_mods | self.latched_mods | self.locked_mods
+    }
+
+    /// StateNotify changed bits between two states
+    fn changes(&self, other: &StateSnapshot) -> u16 {
+        let mut changed = 0;
+        let mut flag = |differs: bool, bits: u16| {
+            if differs {
+                changed |= bits;
+            }
+        };
+        flag(self.mods() != other.mods(), CHANGED_MODS);
+        flag(self.base_mods != other.base_mods, CHANGED_BASE_MODS);
+        flag(
+            self.latched_mods != other.latched_mods,
+            CHANGED_LATCHED_MODS,
+        );
+        flag(self.locked_mods != other.locked_mods, CHANGED_LOCKED_MODS);
+        flag(self.group != other.group, CHANGED_GROUP);
+        flag(
+            self.latched_group != other.latched_group,
+            CHANGED_LATCHED_GROUP,
+        );
+        flag(
+            self.locked_group != other.locked_group,
+            CHANGED_LOCKED_GROUP,
+        );
+        changed
+    }
+}
+
+/// How modifiers select a shift level
+struct KeyType {
+    name: &'static str,
+    mods: u8,
+    /// (modifiers, level) map entries
+    entries: Vec<(u8, u8)>,
+    level_names: &'static [&'static str],
+}
+
+impl KeyType {
+    fn encode(&self, buffer: &mut Vec<u8>) {
+        buffer.extend_from_slice(&[self.mods, self.mods, 0, 0]);
+        buffer.extend_from_slice(&[self.level_names.len() as u8, self.entries.len() as u8, 0, 0]);
+        for &(mods, level) in &self.entries {
+            buffer.extend_from_slice(&[1, mods, level, mods, 0, 0, 0, 0]);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Action {
+    SetMods(u8),
+    LockMods(u8),
+    LockGroup(i8),
+}
+
+impl Action {
+    fn encode(&self) -> [u8; 8] {
+        match *self {
+            Action::SetMods(mods) => [1, 0, mods, mods, 0, 0, 0, 0],
+            Action::LockMods(mods) => [3, 0, mods, mods, 0, 0, 0, 0],
+            Action::LockGroup(delta) => [6, 0, delta as u8, 0, 0, 0, 0, 0],
+        }
+    }
+}
+
+/// A key's groups, as (key type, level keysyms), and its action
+struct Key {
+    groups: Vec<(u8, [u32; 2])>,
+    action: Option<Action>,
+}
+
+/// The XKB view of a keyboard mapping
+pub struct XkbKeymap {
+    types: Vec<KeyType>,
+    /// Keys from MIN_KEYCODE on
+    keys: Vec<Key>,
+    modmap: [u8; 256],
+    num_groups: u8,
+    num_lock: u8,
+}
+
+impl XkbKeymap {
+    pub fn new(mapping: &KeyboardMapping) -> Self {
+        let modmap = modifier_map(mapping);
+        let num_lock = (MIN_KEYCODE..=MAX_KEYCODE)
+            .find(|&keycode| mapping.keysyms(keycode).contains(&XK_NUM_LOCK))
+            .map(|keycode| modmap[keycode as usize])
+            .filter(|&mods| mods != 0)
+            .unwrap_or(MOD2);
+        let types = vec![
+            KeyType {
+                name: "ONE_LEVEL",
+                mods: 0,
+                entries: Vec::new(),
+                level_names: &["Any"],
+            },
+            KeyType {
+                name: "TWO_LEVEL",
+                mods: SHIFT,
+                entries: vec![(SHIFT, 1)],
+                level_names: &["Base", "Shift"],
+            },
+            KeyType {
+                name: "ALPHABETIC",
+                mods: SHIFT | LOCK,
+                entries: vec![(SHIFT, 1), (LOCK, 1)],
+                level_names: &["Base", "Caps"],
+            },
+            KeyType {
+                name: "KEYPAD",
+                mods: SHIFT | num_lock,
+                entries: vec![(SHIFT, 1), (num_lock, 1)],
+                level_names: &["Base", "Number"],
+            },
+        ];
+        let keys = (MIN_KEYCODE..=MAX_KEYCODE)
+            .map(|keycode| Key {
+                groups: key_groups(mapping.keysyms(keycode)),
+                action: key_action(mapping, &modmap, keycode),
+            })
+            .collect();
+        XkbKeymap {
+            types,
+            keys,
+            modmap,
+            num_groups: num_groups(mapping),
+            num_lock,
+        }
+    }
+
+    fn key(&self, keycode: u8) -> &Key {
+        &self.keys[(keycode - MIN_KEYCODE) as usize]
+    }
+
+    /// Levels per group of a key: the most of any of its groups' types
+    fn width(&self, key: &Key) -> u8 {
+        key.groups
+            .iter()
+            .map(|&(kt, _)| self.types[kt as usize].level_names.len() as u8)
+            .max()
+            .unwrap_or(0)
+    }
+
+    fn num_syms(&self, key: &Key) -> u8 {
+        key.groups.len() as u8 * self.width(key)
+    }
+
+    /// GetMap reply for the components and ranges asked for
+    fn encode_map(
+        &self,
+        sequence: u16,
+        request: &MapRequest,
+    ) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        let all_keys = (MIN_KEYCODE, MAX_KEYCODE - MIN_KEYCODE + 1);
+        let types = request.range(KEY_TYPES, request.types, (0, self.types.len() as u8))?;
+        let syms = request.range(KEY_SYMS, request.key_syms, all_keys)?;
+        let actions = request.range(KEY_ACTIONS, request.key_actions, all_keys)?;
+        let behaviors = request.range(KEY_BEHAVIORS, request.key_behaviors, all_keys)?;
+        let explicit = request.range(EXPLICIT_COMPONENTS, request.key_explicit, all_keys)?;
+        let modmap = request.range(MODIFIER_MAP, request.mod_map_keys, all_keys)?;
+        let vmodmap = request.range(VIRTUAL_MOD_MAP, request.vmod_map_keys, all_keys)?;
+
+        let mut buffer = vec![0u8; 40];
+        buffer[10] = MIN_KEYCODE;
+        buffer[11] = MAX_KEYCODE;
+        buffer[12..14].copy_from_slice(&(request.components() & 0xff).to_le_bytes());
+
+        if let Some((first, count)) = types {
+            buffer[14] = first;
+            buffer[15] = count;
+            buffer[16] = self.types.len() as u8;
+            for key_type in self.types.iter().skip(first as usize).take(count as usize) {
+                key_type.encode(&mut buffer);
+            }
+        }
+        if let Some((first, count)) = syms {
+            let mut total = 0u16;
+            for keycode in keycodes(first, count) {
+                let key = self.key(keycode);
+                let width = self.width(key);
+                let num_syms = self.num_syms(key);
+                total += num_syms as u16;
+                let mut kt_index = [0u8; 4];
+                for (index, &(kt, _)) in key.groups.iter().enumerate() {
+                    kt_index[index] = kt;
+                }
+                buffer.extend_from_slice(&kt_index);
+                buffer.extend_from_slice(&[key.groups.len() as u8, width]);
+                buffer.extend_from_slice(&(num_syms as u16).to_le_bytes());
+                for (_, levels) in &key.groups {
+                    for &keysym in &levels[..width as usize] {
+                        buffer.extend_from_slice(&keysym.to_le_bytes());
+                    }
+                }
+            }
+            buffer[17] = first;
+            buffer[18..20].copy_from_slice(&total.to_le_bytes());
+            buffer[20] = count;
+        }
+        if let Some((first, count)) = actions {
+            let counts: Vec<u8> = keycodes(first, count)
+                .map(|keycode| {
+                    let key = self.key(keycode);
+                    key.action.map_or(0, |_| self.num_syms(key))
+                })
+                .collect();
+            buffer.extend_from_slice(&counts);
+            buffer.resize(padded_len(buffer.len()), 0);
+            let mut total = 0u16;
+            for (keycode, &n) in keycodes(first, count).zip(&counts) {
+                if let Some(action) = self.key(keycode).action {
+                    for _ in 0..n {
+                        buffer.extend_from_slice(&action.encode());
+                    }
+                    total += n as u16;
+                }
+            }
+            buffer[21] = first;
+            buffer[22..24].copy_from_slice(&total.to_le_bytes());
+            buffer[24] = count;
+        }
+        // No behaviors, explicit components or virtual modifiers: only the
+        // ranges are reported
+        if let Some((first, count)) = behaviors {
+            buffer[25] = first;
+            buffer[26] = count;
+        }
+        if let Some((first, count)) = explicit {
+            buffer[28] = first;
+            buffer[29] = count;
+        }
+        if let Some((first, count)) = modmap {
+            let mut total = 0;
+            for keycode in keycodes(first, count) {
+                let mods = self.modmap[keycode as usize];
+                if mods != 0 {
+                    buffer.extend_from_slice(&[keycode, mods]);
+                    total += 1;
+                }
+            }
+            buffer.resize(padded_len(buffer.len()), 0);
+            buffer[31] = first;
+            buffer[32] = count;
+            buffer[33] = total;
+        }
+        if let Some((first, count)) = vmodmap {
+            buffer[34] = first;
+            buffer[35] = count;
+        }
+        Ok(finish_reply(buffer, sequence))
+    }
+
+    /// GetNames reply for the names in `which`
+    fn encode_names(
+        &self,
+        sequence: u16,
+        which: u32,
+        mut atom: impl FnMut(&str) -> u32,
+    ) -> Vec<u8> {
+        let which = which & ALL_NAMES;
+        let levels: Vec<u8> = self
+            .types
+            .iter()
+            .map(|t| t.level_names.len() as u8)
+            .collect();
+        let total_levels: u16 = levels.iter().map(|&n| n as u16).sum();
+
+        let mut buffer = vec![0u8; 32];
+        buffer[8..12].copy_from_slice(&which.to_le_bytes());
+        buffer[12] = MIN_KEYCODE;
+        buffer[13] = MAX_KEYCODE;
+        buffer[14] = self.types.len() as u8;
+        buffer[15] = (1u8 << self.num_groups) - 1;
+        buffer[18] = MIN_KEYCODE;
+        buffer[19] = MAX_KEYCODE - MIN_KEYCODE + 1;
+        buffer[20..24].copy_from_slice(&((1u32 << INDICATOR_NAMES.len()) - 1).to_le_bytes());
+        buffer[26..28].copy_from_slice(&total_levels.to_le_bytes());
+
+        let mut put_atom = |buffer: &mut Vec<u8>, name: &str| {
+            buffer.extend_from_slice(&atom(name).to_le_bytes());
+        };
+        for (bit, name) in [
+            (NAME_KEYCODES, COMPONENT_NAME),
+            (NAME_GEOMETRY, COMPONENT_NAME),
+            (NAME_SYMBOLS, COMPONENT_NAME),
+            (NAME_PHYS_SYMBOLS, COMPONENT_NAME),
+            (NAME_TYPES, "complete"),
+            (NAME_COMPAT, "complete"),
+        ] {
+            if which & bit != 0 {
+                put_atom(&mut buffer, name);
+            }
+        }
+        if which & NAME_KEY_TYPE_NAMES != 0 {
+            for key_type in &self.types {
+                put_atom(&mut buffer, key_type.name);
+            }
+        }
+        if which & NAME_KT_LEVEL_NAMES != 0 {
+            buffer.extend_from_slice(&levels);
+            buffer.resize(padded_len(buffer.len()), 0);
+            for name in self.types.iter().flat_map(|t| t.level_names) {
+                put_atom(&mut buffer, name);
+            }
+        }
+        if which & NAME_INDICATOR_NAMES != 0 {
+            for name in INDICATOR_NAMES {
+                put_atom(&mut buffer, name);
+            }
+        }
+        if which & NAME_GROUP_NAMES != 0 {
+            for group in 1..=self.num_groups {
+                put_atom(&mut buffer, &format!("Group {}", group));
+            }
+        }
+        if which & NAME_KEY_NAMES != 0 {
+            for keycode in MIN_KEYCODE..=MAX_KEYCODE {
+                buffer.extend_from_slice(format!("K{:03}", keycode).as_bytes());
+            }
+        }
+        finish_reply(buffer, sequence)
+    }
+
+    /// GetIndicatorMap reply: Caps Lock and Num Lock follow the locked
+    /// modifiers, Scroll Lock has no map
+    fn encode_indicator_map(&self, sequence: u16, which: u32) -> Vec<u8> {
+        let mut buffer = vec![0u8; 32];
+        buffer[8..12].copy_from_slice(&which.to_le_bytes());
+        buffer[12..16].copy_from_slice(&((1u32 << INDICATOR_NAMES.len()) - 1).to_le_bytes());
+        buffer[16] = INDICATOR_NAMES.len() as u8;
+        for index in (0..32).filter(|i| which & (1 << i) != 0) {
+            let mods = match index {
+                0 => LOCK,
+                1 => self.num_lock,
+                _ => 0,
+            };
+            // whichMods = UseLocked
+            let which_mods = if mods != 0 { 0x04 } else { 0 };
+            buffer.extend_from_slice(&[0, 0, 0, which_mods, mods, mods, 0, 0, 0, 0, 0, 0]);
+        }
+        finish_reply(buffer, sequence)
+    }
+
+    /// Indicators lit by the locked modifiers
+    fn indicator_state(&self, state: &StateSnapshot) -> u32 {
+        let mut lit = 0;
+        if state.locked_mods & LOCK != 0 {
+            lit |= 1;
+        }
+        if state.locked_mods & self.num_lock != 0 {
+            lit |= 2;
+        }
+        lit
+    }
+}
+
+/// Which parts of the map GetMap asks for
+struct MapRequest {
+    full: u16,
+    partial: u16,
+    types: (u8, u8),
+    key_syms: (u8, u8),
+    key_actions: (u8, u8),
+    key_behaviors: (u8, u8),
+    key_explicit: (u8, u8),
+    mod_map_keys: (u8, u8),
+    vmod_map_keys: (u8, u8),
+}
+
+impl MapRequest {
+    /// Parse a GetMap request; `data` starts at the device spec
+    fn parse(data: &[u8]) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        check_length(data, 24)?;
+        let range = |offset: usize| (data[offset], data[offset + 1]);
+        Ok(MapRequest {
+            full: read_u16(data, 2),
+            partial: read_u16(data, 4),
+            types: range(6),
+            key_syms: range(8),
+            key_actions: range(10),
+            key_behaviors: range(12),
+            key_explicit: range(16),
+            mod_map_keys: range(18),
+            vmod_map_keys: range(20),
+        })
+    }
+
+    fn components(&self) -> u16 {
+        self.full | self.partial
+    }
+
+    /// The (first, count) range returned for a component: everything if
+    /// it is in `full`, the requested range if in `partial`
+    fn range(
+        &self,
+        component: u16,
+        requested: (u8, u8),
+        all: (u8, u8),
+    ) -> Result<Option<(u8, u8)>, Box<dyn Error + Send + Sync>> {
+        if self.full & component != 0 {
+            return Ok(Some(all));
+        }
+        if self.partial & component == 0 {
+            return Ok(None);
+        }
+        let (first, count) = requested;
+        if first < all.0 || first as u16 + count as u16 > all.0 as u16 + all.1 as u16 {
+            return Err(X11Error::bad_value(0, first as u32, 0).into());
+        }
+        Ok(Some(requested))
+    }
+}
+
+impl Server {
+    /// The XKB view of the current keyboard mapping
+    pub fn xkb_keymap(&mut self) -> XkbKeymap {
+        XkbKeymap::new(self.keyboard_mapping())
+    }
+
+    fn xkb_snapshot(&mut self) -> StateSnapshot {
+        let num_groups = num_groups(self.keyboard_mapping());
+        self.xkb.snapshot(num_groups)
+    }
+
+    /// Effective modifiers and group in core state bits
+    pub(super) fn xkb_core_state(&mut self) -> u16 {
+        let state = self.xkb_snapshot();
+        state.mods() as u16 | (state.group as u16) << 13
+    }
+
+    /// Update the modifier and group state from a key event
+    pub(super) fn xkb_key_event(&mut self, keycode: u8, press: bool, time: u32) {
+        if keycode < MIN_KEYCODE {
+            return;
+        }
+        let mapping = self.keyboard_mapping();
+        let action = key_action(mapping, &modifier_map(mapping), keycode);
+        let num_groups = num_groups(mapping);
+        let before = self.xkb.snapshot(num_groups);
+
+        let xkb = &mut self.xkb;
+        match action {
+            Some(Action::SetMods(mods)) => {
+                if press {
+                    xkb.held.insert(keycode, mods);
+                } else {
+                    xkb.held.remove(&keycode);
+                }
+                xkb.base_mods = xkb.held.values().fold(0, |all, mods| all | mods);
+            }
+            Some(Action::LockMods(mods)) if press => xkb.locked_mods ^= mods,
+            Some(Action::LockGroup(delta)) if press => {
+                let group = (xkb.locked_group as i16 + delta as i16).rem_euclid(num_groups as i16);
+                xkb.locked_group = group as u8;
+            }
+            None if press => {
+                // Latches last until the next key
+                xkb.latched_mods = 0;
+                xkb.latched_group = 0;
+            }
+            _ => {}
+        }
+
+        let event_type = if press { 2 } else { 3 };
+        self.queue_state_notify(&before, time, keycode, event_type, 0);
+    }
+
+    /// LatchLockState; `data` starts at the device spec
+    fn xkb_latch_lock_state(&mut self, data: &[u8]) -> Result<(), Box<dyn Error + Send + Sync>> {
+        check_length(data, 12)?;
+        let before = self.xkb_snapshot();
+        let num_groups = num_groups(self.keyboard_mapping()) as i16;
+        let xkb = &mut self.xkb;
+        let (affect_locks, locks) = (data[2], data[3]);
+        xkb.locked_mods = (xkb.locked_mods & !affect_locks) | (locks & affect_locks);
+        if data[4] != 0 {
+            xkb.locked_group = (data[5] as i16).rem_euclid(num_groups) as u8;
+        }
+        let (affect_latches, latches) = (data[6], data[7]);
+        xkb.latched_mods = (xkb.latched_mods & !affect_latches) | (latches & affect_latches);
+        if data[9] != 0 {
+            xkb.latched_group = read_u16(data, 10) as i16;
+        }
+        self.queue_state_notify(&before, 0, 0, 0, 5);
+        Ok(())
+    }
+
+    /// Queue StateNotify for what changed since `before`
+    fn queue_state_notify(
+        &mut self,
+        before: &StateSnapshot,
+        time: u32,
+        keycode: u8,
+        event_type: u8,
+        request_minor: u8,
+    ) {
+        let after = self.xkb_snapshot();
+        let changed = before.changes(&after);
+        if changed == 0 {
+            return;
+        }
+        let mut event = xkb_event(STATE_NOTIFY, time);
+        event[9] = after.mods();
+        event[10] = after.base_mods;
+        event[11] = after.latched_mods;
+        event[12] = after.locked_mods;
+        event[13] = after.group;
+        event[16..18].copy_from_slice(&after.latched_group.to_le_bytes());
+        event[18] = after.locked_group;
+        event[19..24].fill(after.mods());
+        event[24..26].copy_from_slice(&self.button_state().to_le_bytes());
+        event[26..28].copy_from_slice(&changed.to_le_bytes());
+        event[28] = keycode;
+        event[29] = event_type;
+        if request_minor != 0 {
+            event[30] = XKB_MAJOR;
+            event[31] = request_minor;
+        }
+        self.queue_xkb_event(STATE_NOTIFY, changed as u32, event);
+    }
+
+    /// Queue MapNotify after the core keyboard (`keys`) or modifier mapping
+    /// changed
+    pub(super) fn queue_xkb_map_notify(&mut self, keys: bool, first_keycode: u8, count: u8) {
+        let mut event = xkb_event(MAP_NOTIFY, 0);
+        event[12] = MIN_KEYCODE;
+        event[13] = MAX_KEYCODE;
+        let changed = if keys {
+            event[15] = self.xkb_keymap().types.len() as u8;
+            event[16] = first_keycode;
+            event[17] = count;
+            event[18] = first_keycode;
+            event[19] = count;
+            KEY_TYPES | KEY_SYMS | KEY_ACTIONS
+        } else {
+            // Actions follow the modifier map
+            event[18] = MIN_KEYCODE;
+            event[19] = MAX_KEYCODE - MIN_KEYCODE + 1;
+            event[24] = MIN_KEYCODE;
+            event[25] = MAX_KEYCODE - MIN_KEYCODE + 1;
+            MODIFIER_MAP | KEY_ACTIONS
+        };
+        event[10..12].copy_from_slice(&changed.to_le_bytes());
+        self.queue_xkb_event(MAP_NOTIFY, changed as u32, event);
+    }
+
+    /// Queue NewKeyboardNotify after the host replaced its layout
+    pub(super) fn queue_xkb_new_keyboard_notify(&mut self) {
+        let mut event = xkb_event(NEW_KEYBOARD_NOTIFY, 0);
+        event[9] = CORE_KEYBOARD_ID;
+        event[10] = MIN_KEYCODE;
+        event[11] = MAX_KEYCODE;
+        event[12] = MIN_KEYCODE;
+        event[13] = MAX_KEYCODE;
+        // changed = Keycodes
+        event[16] = 1;
+        self.queue_xkb_event(NEW_KEYBOARD_NOTIFY, 1, event);
+    }
+
+    /// Queue an XKB event for the clients selecting any of `details`
+    fn queue_xkb_event(&mut self, xkb_type: usize, details: u32, event: Vec<u8>) {
+        let clients: Vec<u32> = self
+            .xkb
+            .selections
+            .iter()
+            .filter(|(_, selected)| selected[xkb_type] & details != 0)
+            .map(|(&client, _)| client)
+            .collect();
+        for client in clients {
+            self.client_events
+                .entry(client)
+                .or_default()
+                .push(event.clone());
+        }
+    }
+}
+
+/// Handle XKEYBOARD extension requests
+pub fn handle_xkb_request<S: Write>(
+    stream: &mut S,
+    minor_opcode: u8,
+    sequence: u16,
+    data: &[u8],
+    server: &Arc<Mutex<Server>>,
+    client_id: u32,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    if minor_opcode == 0 {
+        // UseExtension
+        check_length(data, 4)?;
+        log::debug!(
+            "XKB: UseExtension wanted {}.{}",
+            read_u16(data, 0),
+            read_u16(data, 2)
+        );
+        let mut reply = vec![0u8; 32];
+        reply[0] = 1;
+        reply[1] = 1; // supported
+        reply[2..4].copy_from_slice(&sequence.to_le_bytes());
+        reply[8..10].copy_from_slice(&1u16.to_le_bytes());
+        stream.write_all(&reply)?;
+        return Ok(());
+    }
+
+    // Every other request starts with a device spec; the protocol's
+    // BadKeyboard is an extension error, so an unknown device is BadValue
+    check_length(data, 4)?;
+    let device = read_u16(data, 0);
+    if device != USE_CORE_KBD && device != CORE_KEYBOARD_ID as u16 {
+        return Err(X11Error::bad_value(0, device as u32, 0).into());
+    }
+
+    let mut server = server.lock().unwrap();
+    let reply = match minor_opcode {
+        1 => {
+            log::debug!("XKB: SelectEvents");
+            server.xkb.select_events(client_id, data)?;
+            None
+        }
+        3 => {
+            log::debug!("XKB: Bell");
+            None
+        }
+        4 => {
+            let state = server.xkb_snapshot();
+            let mut buffer = vec![0u8; 32];
+            buffer[8] = state.mods();
+            buffer[9] = state.base_mods;
+            buffer[10] = state.latched_mods;
+            buffer[11] = state.locked_mods;
+            buffer[12] = state.group;
+            buffer[13] = state.locked_group;
+            buffer[16..18].copy_from_slice(&state.latched_group.to_le_bytes());
+            buffer[18..23].fill(state.mods());
+            buffer[24..26].copy_from_slice(&server.button_state().to_le_bytes());
+            Some(finish_reply(buffer, sequence))
+        }
+        5 => {
+            log::debug!("XKB: LatchLockState");
+            server.xkb_latch_lock_state(data)?;
+            None
+        }
+        6 => Some(encode_controls(sequence, server.xkb_keymap().num_groups)),
+        8 => {
+            let request = MapRequest::parse(data)?;
+            log::debug!(
+                "XKB: GetMap full=0x{:x} partial=0x{:x}",
+                request.full,
+                request.partial
+            );
+            Some(server.xkb_keymap().encode_map(sequence, &request)?)
+        }
+        10 => {
+            check_length(data, 8)?;
+            let groups = data[2] & 0x0f;
+            let mut buffer = vec![0u8; 32];
+            buffer[8] = groups;
+            // No symbol interpretations: actions are bound to the keys;
+            // groups do not map to modifiers
+            buffer.resize(32 + 4 * groups.count_ones() as usize, 0);
+            Some(finish_reply(buffer, sequence))
+        }
+        12 => {
+            let keymap = server.xkb_keymap();
+            let state = server.xkb_snapshot();
+            let mut buffer = vec![0u8; 32];
+            buffer[8..12].copy_from_slice(&keymap.indicator_state(&state).to_le_bytes());
+            Some(finish_reply(buffer, sequence))
+        }
+        13 => {
+            check_length(data, 8)?;
+            let which = read_u32(data, 4);
+            Some(server.xkb_keymap().encode_indicator_map(sequence, which))
+        }
+        17 => {
+            check_length(data, 8)?;
+            let which = read_u32(data, 4);
+            log::debug!("XKB: GetNames which=0x{:x}", which);
+            let keymap = server.xkb_keymap();
+            Some(keymap.encode_names(sequence, which, |name| {
+                server.intern_atom(name, false).map_or(0, |atom| atom.0)
+            }))
+        }
+        21 => {
+            check_length(data, 24)?;
+            let (change, value) = (read_u32(data, 4), read_u32(data, 8));
+            let flags = server.xkb.per_client_flags.entry(client_id).or_default();
+            *flags = ((*flags & !change) | (value & change)) & PER_CLIENT_FLAGS;
+            let mut buffer = vec![0u8; 32];
+            buffer[8..12].copy_from_slice(&PER_CLIENT_FLAGS.to_le_bytes());
+            buffer[12..16].copy_from_slice(&flags.to_le_bytes());
+            Some(finish_reply(buffer, sequence))
+        }
+        24 => {
+            check_length(data, 12)?;
+            let dev_type = server.intern_atom("KEYBOARD", false).map_or(0, |a| a.0);
+            Some(encode_device_info(sequence, read_u16(data, 2), dev_type))
+        }
+        _ => {
+            log::debug!("XKB: Unhandled minor opcode {}", minor_opcode);
+            None
+        }
+    };
+    drop(server);
+    if let Some(reply) = reply {
+        stream.write_all(&reply)?;
+    }
+    Ok(())
+}
+
+/// GetControls reply: key repeat on with the usual delay and rate
+fn encode_controls(sequence: u16, num_groups: u8) -> Vec<u8> {
+    let mut buffer = vec![0u8; 92];
+    buffer[9] = num_groups;
+    for (offset, value) in [
+        (20, 660), // repeatDelay
+        (22, 40),  // repeatInterval
+        (28, 160), // mouseKeysDelay
+        (30, 40),  // mouseKeysInterval
+        (32, 30),  // mouseKeysTimeToMax
+        (34, 30),  // mouseKeysMaxSpeed
+        (36, 500), // mouseKeysCurve
+    ] {
+        buffer[offset..offset + 2].copy_from_slice(&(value as u16).to_le_bytes());
+    }
+    // enabledControls = RepeatKeys, and every key repeats
+    buffer[56..60].copy_from_slice(&1u32.to_le_bytes());
+    buffer[60..92].fill(0xff);
+    finish_reply(buffer, sequence)
+}
+
+/// GetDeviceInfo reply for the core keyboard, without button actions or
+/// extra indicator feedbacks
+fn encode_device_info(sequence: u16, wanted: u16, dev_type: u32) -> Vec<u8> {
+    let name = b"Virtual core keyboard";
+    let mut buffer = vec![0u8; 32];
+    buffer[12..14].copy_from_slice(&wanted.to_le_bytes()); // unsupported
+    buffer[21] = 1; // hasOwnState
+    buffer[28..32].copy_from_slice(&dev_type.to_le_bytes());
+    buffer.extend_from_slice(&(name.len() as u16).to_le_bytes());
+    buffer.extend_from_slice(name);
+    finish_reply(buffer, sequence)
+}
+
+/// A 32-byte XKB event of the given xkbType for the core keyboard
+fn xkb_event(xkb_type: usize, time: u32) -> Vec<u8> {
+    let mut event = vec![0u8; 32];
+    event[0] = XKB_EVENT;
+    event[1] = xkb_type as u8;
+    event[4..8].copy_from_slice(&time.to_le_bytes());
+    event[8] = CORE_KEYBOARD_ID;
+    event
+}
+
+/// Pad a reply and fill in its header
+fn finish_reply(mut buffer: Vec<u8>, sequence: u16) -> Vec<u8> {
+    buffer.resize(padded_len(buffer.len()), 0);
+    buffer[0] = 1;
+    buffer[1] = CORE_KEYBOARD_ID;
+    buffer[2..4].copy_from_slice(&sequence.to_le_bytes());
+    let length = (buffer.len() - 32) as u32 / 4;
+    buffer[4..8].copy_from_slice(&length.to_le_bytes());
+    buffer
+}
+
+fn check_length(data: &[u8], length: usize) -> Result<(), Box<dyn Error + Send + Sync>> {
+    if data.len() < length {
+        return Err(X11Error::bad_length(0, 0).into());
+    }
+    Ok(())
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        data[offset],
+        data[offset + 1],
+        data[offset + 2],
+        data[offset + 3],
+    ])
+}
+
+fn read_sized(data: &[u8], offset: usize, size: usize) -> u32 {
+    match size {
+        1 => data[offset] as u32,
+        2 => read_u16(data, offset) as u32,
+        _ => read_u32(data, offset),
+    }
+}
+
+fn keycodes(first: u8, count: u8) -> impl Iterator<Item = u8> {
+    (first as u16..first as u16 + count as u16).map(|keycode| keycode as u8)
+}
+
+/// The modifiers of each keycode, from the core modifier mapping
+fn modifier_map(mapping: &KeyboardMapping) -> [u8; 256] {
+    let mut modmap = [0u8; 256];
+    let per = mapping.keycodes_per_modifier.max(1) as usize;
+    for (index, &keycode) in mapping.modifiers.iter().enumerate() {
+        if keycode != 0 && index / per < 8 {
+            modmap[keycode as usize] |= 1 << (index / per);
+        }
+    }
+    modmap
+}
+
+/// Groups in the keymap: two if any key has keysyms in columns 3-4
+fn num_groups(mapping: &KeyboardMapping) -> u8 {
+    let per = mapping.keysyms_per_keycode as usize;
+    let second_group = per > 2
+        && mapping
+            .keysyms
+            .chunks(per)
+            .any(|row| row[2..per.min(4)].iter().any(|&keysym| keysym != 0));
+    if second_group {
+        2
+    } else {
+        1
+    }
+}
+
+/// A key's groups from its core keysyms
+fn key_groups(keysyms: &[u32]) -> Vec<(u8, [u32; 2])> {
+    let column = |index: usize| keysyms.get(index).copied().unwrap_or(0);
+    let mut groups = vec![group(column(0), column(1))];
+    if column(2) != 0 || column(3) != 0 {
+        groups.push(group(column(2), column(3)));
+    }
+    if groups.iter().all(|(_, levels)| levels[0] == 0) && groups.len() == 1 {
+        groups.clear();
+    }
+    groups
+}
+
+/// The key type and levels of a group; a lone lowercase letter gets its
+/// uppercase as the shifted level, as in the core protocol
+fn group(base: u32, shifted: u32) -> (u8, [u32; 2]) {
+    let upper = upper_case(base);
+    let shifted = if shifted == 0 {
+        upper.unwrap_or(0)
+    } else {
+        shifted
+    };
+    if shifted == 0 || shifted == base {
+        return (ONE_LEVEL, [base, 0]);
+    }
+    let key_type = if is_keypad(base) || is_keypad(shifted) {
+        KEYPAD
+    } else if upper == Some(shifted) {
+        ALPHABETIC
+    } else {
+        TWO_LEVEL
+    };
+    (key_type, [base, shifted])
+}
+
+fn is_keypad(keysym: u32) -> bool {
+    (0xff80..=0xffbd).contains(&keysym)
+}
+
+/// The uppercase keysym of a lowercase Latin-1 or Unicode keysym
+fn upper_case(keysym: u32) -> Option<u32> {
+    let c = match keysym {
+        0x20..=0x7e | 0xa0..=0xff => char::from_u32(keysym)?,
+        0x0100_0000..=0x0110_ffff => char::from_u32(keysym & 0x00ff_ffff)?,
+        _ => return None,
+    };
+    if !c.is_lowercase() {
+        return None;
+    }
+    let mut upper = c.to_uppercase();
+    let u = upper.next()?;
+    if upper.next().is_some() || u == c {
+        return None;
+    }
+    Some(match u as u32 {
+        code @ (0x20..=0x7e | 0xa0..=0xff) => code,
+        code => 0x0100_0000 | code,
+    })
+}
+
+/// The action of a key: modifier keys set their modifiers, or lock them
+/// for the lock keysyms; ISO_Next_Group locks the next group
+fn key_action(mapping: &KeyboardMapping, modmap: &[u8; 256], keycode: u8) -> Option<Action> {
+    let keysyms = mapping.keysyms(keycode);
+    let mods = modmap[keycode as usize];
+    if mods != 0 {
+        let lock = keysyms
+            .iter()
+            .any(|&k| matches!(k, XK_CAPS_LOCK | XK_SHIFT_LOCK | XK_NUM_LOCK));
+        return Some(if lock {
+            Action::LockMods(mods)
+        } else {
+            Action::SetMods(mods)
+        });
+    }
+    keysyms
+        .contains(&XK_ISO_NEXT_GROUP)
+        .then_some(Action::LockGroup(1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::null::NullBackend;
+
+    #[test]
+    fn test_keymap_and_state() {
+        let mut server = Server::new(Box::new(NullBackend::new())).unwrap();
+        let client = server.register_client();
+        // a/A, Shift_L, Caps_Lock, KP_Home/KP_7, 1/exclam with a second group
+        server
+            .change_keyboard_mapping(38, 4, &[0x61, 0, 0, 0])
+            .unwrap();
+        server
+            .change_keyboard_mapping(50, 4, &[0xffe1, 0, 0, 0])
+            .unwrap();
+        server
+            .change_keyboard_mapping(66, 4, &[0xffe5, 0, 0, 0])
+            .unwrap();
+        server
+            .change_keyboard_mapping(79, 4, &[0xff95, 0xffb7, 0, 0])
+            .unwrap();
+        server
+            .change_keyboard_mapping(10, 4, &[0x31, 0x21, 0x6c6, 0x6e6])
+            .unwrap();
+        let mut modifiers = vec![0u8; 8];
+        modifiers[0] = 50;
+        modifiers[1] = 66;
+        server.set_modifier_mapping(1, &modifiers).unwrap();
+
+        let keymap = server.xkb_keymap();
+        assert_eq!(keymap.key(38).groups, [(ALPHABETIC, [0x61, 0x41])]);
+        assert_eq!(keymap.key(79).groups, [(KEYPAD, [0xff95, 0xffb7])]);
+        assert_eq!(keymap.key(10).groups.len(), 2);
+        assert_eq!(keymap.key(66).action, Some(Action::LockMods(LOCK)));
+        assert!(keymap.key(9).groups.is_empty());
+        assert_eq!(keymap.num_groups, 2);
+
+        let request = MapRequest::parse(&[0; 24]).unwrap();
+        let reply = keymap
+            .encode_map(
+                1,
+                &MapRequest {
+                    full: 0xff,
+                    ..request
+                },
+            )
+            .unwrap();
+        let length = u32::from_le_bytes(reply[4..8].try_into().unwrap());
+        assert_eq!(reply.len(), 32 + 4 * length as usize);
+        assert_eq!((reply[15], reply[17], reply[20]), (4, 8, 248));
+
+        // SelectEvents for all StateNotify details
+        let mut select = vec![0u8; 16];
+        select[0..2].copy_from_slice(&USE_CORE_KBD.to_le_bytes());
+        select[2] = 1 << STATE_NOTIFY;
+        select[12..14].copy_from_slice(&0xffffu16.to_le_bytes());
+        select[14..16].copy_from_slice(&0xffffu16.to_le_bytes());
+        server.xkb.select_events(client, &select).unwrap();
+        server.take_client_events(client);
+
+        server.xkb_key_event(66, true, 0);
+        server.xkb_key_event(66, false, 0);
+        server.xkb_key_event(50, true, 0);
+        let state = server.xkb_snapshot();
+        assert_eq!((state.locked_mods, state.mods()), (LOCK, LOCK | SHIFT));
+
+        let events = server.take_client_events(client);
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0][..2], [XKB_EVENT, STATE_NOTIFY as u8]);
+        assert_eq!((events[0][12], events[0][28]), (LOCK, 66));
+        assert_eq!(events[1][9], LOCK | SHIFT);
+
+        server.xkb.remove_client(client);
+        server.xkb_key_event(50, false, 0);
+        assert!(server.take_client_events(client).is_empty());
+    }
+}
@@ -476,6 +476,18 @@ class X11ContentView: NSView {
 
 // MARK: - Backend Class
 
+/// An animated cursor: its frames with their delays, and the timer showing
+/// the next one
+class CursorAnimation {
+    let frames: [(cursor: NSCursor, delay: TimeInterval)]
+    var current = 0
+    var timer: Timer?
+
+    init(frames: [(cursor: NSCursor, delay: TimeInterval)]) {
+        self.frames = frames
+    }
+}
+
 class MacOSBackendImpl {
     var windows: [Int: NSWindow] = [:]
     var windowContentViews: [Int: X11ContentView] = [:]
@@ -484,6 +496,7 @@ class MacOSBackendImpl {
     var pixmapSizes: [Int: (Int, Int)] = [:]  // Pixmap dimensions for coordinate conversion
     var cursors: [Int: NSCursor] = [:]
     var windowCursors: [Int: NSCursor] = [:]  // Per-window cursor
+    var cursorAnimations: [Int: CursorAnimation] = [:]
     var nextId: Int = 1
     var screenWidth: Int = 1920  // Default fallback
     var screenHeight: Int = 1080 // Default fallback
@@ -920,6 +933,7 @@ public func macos_backend_clear_area(_ handle: BackendHandle, windowId: Int32,
     guard let context = backend.getWindowContext(id: Int(windowId)) else { return BackendResult.error.rawValue }
 
     let rect = CGRect(x: CGFloat(x), y: CGFloat(y), width: CGFloat(width), height: CGFloat(height))
+    context.resetClip()
     context.setFillColor(CGColor(red: 1, green: 1, blue: 1, alpha: 1))
     context.fill(rect)
 
@@ -927,6 +941,40 @@ public func macos_backend_clear_area(_ handle: BackendHandle, windowId: Int32,
     return BackendResult.success.rawValue
 }
 
+@_cdecl("macos_backend_set_clip")
+public func macos_backend_set_clip(_ handle: BackendHandle, isWindow: Int32, drawableId: Int32,
+                                   rects: UnsafePointer<Int32>?, count: Int32) -> Int32 {
+    let backend = Unmanaged<MacOSBackendImpl>.fromOpaque(handle).takeUnretainedValue()
+
+    let context: CGContext?
+    if isWindow != 0 {
+        context = backend.getWindowContext(id: Int(drawableId))
+    } else {
+        context = backend.getPixmapContext(id: Int(drawableId))
+    }
+
+    guard let ctx = context else { return BackendResult.error.rawValue }
+
+    // A negative count removes the clip; otherwise rects holds x, y, width, height
+    // for each rectangle, and an empty list clips everything away
+    ctx.resetClip()
+    if count >= 0 {
+        var clipRects: [CGRect] = []
+        if let rects = rects {
+            for i in 0..<Int(count) {
+                clipRects.append(CGRect(x: CGFloat(rects[i * 4]), y: CGFloat(rects[i * 4 + 1]),
+                                        width: CGFloat(rects[i * 4 + 2]), height: CGFloat(rects[i * 4 + 3])))
+            }
+        }
+        if clipRects.isEmpty {
+            ctx.clip(to: CGRect.zero)
+        } else {
+            ctx.clip(to: clipRects)
+        }
+    }
+    return BackendResult.success.rawValue
+}
+
 @_cdecl("macos_backend_draw_rectangle")
 public func macos_backend_draw_rectangle(_ handle: BackendHandle, isWindow: Int32, drawableId: Int32,
                                         x: Int32, y: Int32, width: Int32, height: Int32,
@@ -992,17 +1040,23 @@ public func macos_backend_fill_rectangle(_ handle: BackendHandle, isWindow: Int3
             let fillG = UInt8(min(max(g * 255.0, 0), 255))
             let fillB = UInt8(min(max(b * 255.0, 0), 255))
 
-            // Clamp rectangle to buffer bounds
-            let startX = max(0, Int(x))
-            let startY = max(0, Int(y))
-            let endX = min(bufWidth, Int(x) + Int(width))
-            let endY = min(bufHeight, Int(y) + Int(height))
+            // Clamp rectangle to buffer bounds and the clip's bounding box
+            // (pixel access bypasses the context's clip path)
+            var clip = ctx.boundingBoxOfClipPath.intersection(
+                CGRect(x: 0, y: 0, width: bufWidth, height: bufHeight))
+            if clip.isNull {
+                clip = CGRect.zero
+            }
+            let startX = max(Int(x), Int(clip.minX.rounded(.down)))
+            let startY = max(Int(y), Int(clip.minY.rounded(.down)))
+            let endX = min(Int(x) + Int(width), Int(clip.maxX.rounded(.up)))
+            let endY = min(Int(y) + Int(height), Int(clip.maxY.rounded(.up)))
 
             // XOR each pixel in the rectangle
             // Memory layout with premultipliedLast + byteOrder32Big:
             // RGBA format: R at offset 0, G at 1, B at 2, A at 3
-            for py in startY..<endY {
-                for px in startX..<endX {
+            for py in startY..<max(startY, endY) {
+                for px in startX..<max(startX, endX) {
                     let offset = py * bytesPerRow + px * 4
                     ptr[offset + 0] ^= fillR  // R
                     ptr[offset + 1] ^= fillG  // G
@@ -1827,10 +1881,95 @@ public func macos_backend_create_cursor(_ handle: BackendHandle, cursorType: Int
     return Int32(id)
 }
 
+/// Create a cursor from premultiplied ARGB pixels (32-bit, little-endian);
+/// returns 0 on failure
+@_cdecl("macos_backend_create_image_cursor")
+public func macos_backend_create_image_cursor(_ handle: BackendHandle, width: Int32, height: Int32,
+                                              xHot: Int32, yHot: Int32,
+                                              data: UnsafePointer<UInt8>) -> Int32 {
+    let backend = Unmanaged<MacOSBackendImpl>.fromOpaque(handle).takeUnretainedValue()
+
+    let bytesPerRow = Int(width) * 4
+    let pixels = Data(bytes: data, count: bytesPerRow * Int(height))
+    let bitmapInfo = CGBitmapInfo(rawValue: CGImageAlphaInfo.premultipliedFirst.rawValue
+                                      | CGBitmapInfo.byteOrder32Little.rawValue)
+    guard let provider = CGDataProvider(data: pixels as CFData),
+          let image = CGImage(width: Int(width), height: Int(height),
+                              bitsPerComponent: 8, bitsPerPixel: 32, bytesPerRow: bytesPerRow,
+                              space: CGColorSpaceCreateDeviceRGB(), bitmapInfo: bitmapInfo,
+                              provider: provider, decode: nil, shouldInterpolate: false,
+                              intent: .defaultIntent) else {
+        NSLog("create_image_cursor: FAILED - CGImage creation failed")
+        return 0
+    }
+
+    let id = backend.nextId
+    backend.nextId += 1
+
+    let nsImage = NSImage(cgImage: image, size: NSSize(width: Int(width), height: Int(height)))
+    backend.cursors[id] = NSCursor(image: nsImage, hotSpot: NSPoint(x: Int(xHot), y: Int(yHot)))
+
+    return Int32(id)
+}
+
+/// Show the next frame of an animated cursor, in windows showing the
+/// current one, and schedule the frame after it
+private func stepCursorAnimation(_ backend: MacOSBackendImpl, id: Int) {
+    guard let animation = backend.cursorAnimations[id] else { return }
+    let previous = animation.frames[animation.current].cursor
+    animation.current = (animation.current + 1) % animation.frames.count
+    let next = animation.frames[animation.current]
+    backend.cursors[id] = next.cursor
+    for (windowId, cursor) in backend.windowCursors where cursor === previous {
+        backend.windowCursors[windowId] = next.cursor
+        if let window = backend.windows[windowId], window.isKeyWindow {
+            next.cursor.set()
+        }
+    }
+    animation.timer = Timer.scheduledTimer(withTimeInterval: next.delay, repeats: false) { _ in
+        stepCursorAnimation(backend, id: id)
+    }
+}
+
+/// Create a cursor cycling through existing cursors, each shown for its
+/// delay in milliseconds
+@_cdecl("macos_backend_create_animated_cursor")
+public func macos_backend_create_animated_cursor(_ handle: BackendHandle, frameIds: UnsafePointer<Int32>,
+                                                 delays: UnsafePointer<UInt32>, count: Int32) -> Int32 {
+    let backend = Unmanaged<MacOSBackendImpl>.fromOpaque(handle).takeUnretainedValue()
+    var frames: [(cursor: NSCursor, delay: TimeInterval)] = []
+    for i in 0..<Int(count) {
+        guard let cursor = backend.cursors[Int(frameIds[i])] else {
+            return 0
+        }
+        frames.append((cursor, TimeInterval(max(delays[i], 1)) / 1000))
+    }
+    guard let first = frames.first else { return 0 }
+
+    let id = backend.nextId
+    backend.nextId += 1
+    let animation = CursorAnimation(frames: frames)
+    backend.cursors[id] = first.cursor
+    backend.cursorAnimations[id] = animation
+
+    // Timers fire on the main run loop
+    DispatchQueue.main.async {
+        animation.timer = Timer.scheduledTimer(withTimeInterval: first.delay, repeats: false) { _ in
+            stepCursorAnimation(backend, id: id)
+        }
+    }
+    return Int32(id)
+}
+
 @_cdecl("macos_backend_free_cursor")
 public func macos_backend_free_cursor(_ handle: BackendHandle, cursorId: Int32) -> Int32 {
     let backend = Unmanaged<MacOSBackendImpl>.fromOpaque(handle).takeUnretainedValue()
     backend.cursors.removeValue(forKey: Int(cursorId))
+    if let animation = backend.cursorAnimations.removeValue(forKey: Int(cursorId)) {
+        DispatchQueue.main.async {
+            animation.timer?.invalidate()
+        }
+    }
     return BackendResult.success.rawValue
 }
 
@@ -1859,6 +1998,15 @@ public func macos_backend_set_window_cursor(_ handle: BackendHandle, windowId: I
     return BackendResult.success.rawValue
 }
 
+/// Move the pointer; global display coordinates have a top-left origin like X11
+@_cdecl("macos_backend_warp_pointer")
+public func macos_backend_warp_pointer(_ handle: BackendHandle, x: Int32, y: Int32) -> Int32 {
+    let result = CGWarpMouseCursorPosition(CGPoint(x: CGFloat(x), y: CGFloat(y)))
+    // Warping suppresses mouse input briefly; reattach it so real motion continues
+    CGAssociateMouseAndMouseCursorPosition(1)
+    return result == .success ? BackendResult.success.rawValue : BackendResult.error.rawValue
+}
+
 // MARK: - Run Loop
 
 /// Run the NSApplication event loop. This function never returns.